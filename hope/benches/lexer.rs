@@ -0,0 +1,25 @@
+use std::hint::black_box;
+use std::time::Instant;
+use logos::Logos;
+use hope::syntax::token::Token;
+
+const ROUNDS: usize = 20;
+
+fn main() {
+    // HOPE_BENCH_FILE swaps the repeated prelude for a real corpus file
+    let source = match std::env::var("HOPE_BENCH_FILE") {
+        Ok(path) => std::fs::read_to_string(path).expect("Should be able to read file"),
+        Err(_) => include_str!("../../lib/Standard.hop").repeat(1 << 16),
+    };
+
+    let mut best = f64::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let count = Token::lexer(&source).count();
+        black_box(count);
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+
+    let mb = source.len() as f64 / (1024.0 * 1024.0);
+    println!("lexer: {:.2} MB in {:.4}s, {:.2} MB/s", mb, best, mb / best);
+}
@@ -0,0 +1,62 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use hope::parser::Parser;
+
+const ROUNDS: usize = 10;
+
+// Counts allocations, to see how hard parsing works the allocator
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn main() {
+    // HOPE_BENCH_FILE swaps the repeated prelude for a real corpus file
+    let source = match std::env::var("HOPE_BENCH_FILE") {
+        Ok(path) => std::fs::read_to_string(path).expect("Should be able to read file"),
+        Err(_) => include_str!("../../lib/Standard.hop").repeat(1 << 12),
+    };
+
+    let mut best = f64::MAX;
+    let mut allocations = 0;
+    let mut bytes = 0;
+    for _ in 0..ROUNDS {
+        let (before, before_bytes) = (ALLOCATIONS.load(Ordering::Relaxed), BYTES.load(Ordering::Relaxed));
+        let start = Instant::now();
+        let program = Parser::new(&source).and_then(|mut parser| parser.parse_program()).expect("the corpus parses");
+        black_box(&program);
+        best = best.min(start.elapsed().as_secs_f64());
+        allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        bytes = BYTES.load(Ordering::Relaxed) - before_bytes;
+    }
+
+    let mb = source.len() as f64 / (1024.0 * 1024.0);
+    let kb = source.len() as f64 / 1024.0;
+    println!(
+        "parser: {:.2} MB in {:.4}s, {:.2} MB/s, {:.0} allocations and {:.0} KB allocated per KB",
+        mb, best, mb / best, allocations as f64 / kb, bytes as f64 / 1024.0 / kb,
+    );
+}
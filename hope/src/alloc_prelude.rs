@@ -0,0 +1,7 @@
+// What the std prelude has that the modules built without std need from alloc
+pub use alloc::borrow::ToOwned;
+pub use alloc::boxed::Box;
+pub use alloc::format;
+pub use alloc::string::{String, ToString};
+pub use alloc::vec;
+pub use alloc::vec::Vec;
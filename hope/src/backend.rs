@@ -0,0 +1,145 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::desugar::{self, Def};
+use crate::driver::Diagnostic;
+use crate::export::{self, Target};
+use crate::types::TypedProgram;
+
+// Turns a checked program into files for something else to run or read, the way the
+// backends here write core, SMT-LIB and TPTP. A crate can add its own with
+// Driver::with_backend, and Driver::generate runs one by name
+pub trait Backend {
+    fn name(&self) -> &str;
+
+    // The files of the program, each after the modules it uses. Problems go in
+    // diagnostics, and any error among them fails the generation
+    fn generate(&self, units: &[Unit<'_>], diagnostics: &mut Vec<Diagnostic>) -> Vec<Artifact>;
+}
+
+impl fmt::Debug for dyn Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Backend({:?})", self.name())
+    }
+}
+
+// One file of the program, both as declarations with their types and taken down to
+// core by desugar::program
+#[derive(Debug)]
+pub struct Unit<'a> {
+    pub path: &'a Path,
+    pub typed: &'a TypedProgram,
+    pub core: Vec<Def>,
+}
+
+// A file a backend made, named relative to wherever they are all written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    pub name: PathBuf,
+    pub contents: String,
+}
+
+pub fn units(typed: &[(PathBuf, TypedProgram)]) -> Vec<Unit<'_>> {
+    typed.iter().map(|(path, typed)| Unit { path, typed, core: desugar::program(&typed.decls) }).collect()
+}
+
+// Named after the file, with the extension
+fn named(path: &Path, extension: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or(path.as_os_str()).to_string_lossy();
+    PathBuf::from(format!("{}.{}", stem, extension))
+}
+
+pub const BUILT_IN: [&dyn Backend; 3] = [&Core, &Prover(Target::Smt2), &Prover(Target::Tptp)];
+
+// Each file's core, as `hope check --emit core` writes it
+pub struct Core;
+
+impl Backend for Core {
+    fn name(&self) -> &str {
+        "core"
+    }
+
+    fn generate(&self, units: &[Unit<'_>], _: &mut Vec<Diagnostic>) -> Vec<Artifact> {
+        units.iter().map(|unit| Artifact {
+            name: named(unit.path, "core"),
+            contents: unit.core.iter().map(|def| format!("{}\n", def)).collect(),
+        }).collect()
+    }
+}
+
+// The whole program as one theory for a prover, see export, named after its last file
+pub struct Prover(pub Target);
+
+impl Backend for Prover {
+    fn name(&self) -> &str {
+        match self.0 {
+            Target::Smt2 => "smt2",
+            Target::Tptp => "tptp",
+        }
+    }
+
+    fn generate(&self, units: &[Unit<'_>], _: &mut Vec<Diagnostic>) -> Vec<Artifact> {
+        let decls: Vec<_> = units.iter().flat_map(|unit| unit.typed.decls.iter().map(|typed| typed.decl.clone())).collect();
+        let extension = match self.0 {
+            Target::Smt2 => "smt2",
+            Target::Tptp => "p",
+        };
+        let name = units.last().map_or_else(|| PathBuf::from(format!("theory.{}", extension)), |unit| named(unit.path, extension));
+        vec![Artifact { name, contents: export::export(&decls, self.0) }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::desugar::Def;
+    use crate::driver::{Driver, Severity, Stage, Status};
+
+    // Writes the names each file defines, and fails on one that defines none
+    struct Names;
+
+    impl Backend for Names {
+        fn name(&self) -> &str {
+            "names"
+        }
+
+        fn generate(&self, units: &[Unit<'_>], diagnostics: &mut Vec<Diagnostic>) -> Vec<Artifact> {
+            let mut artifacts = Vec::new();
+            for unit in units {
+                let names: Vec<&str> = unit.core.iter().filter_map(|def| match def {
+                    Def::Value(name, _) => Some(name.as_str()),
+                    _ => None,
+                }).collect();
+                if names.is_empty() {
+                    let message = "nothing to name".to_owned();
+                    diagnostics.push(Diagnostic { severity: Severity::Error, path: Some(unit.path.to_path_buf()), pos: None, code: None, message });
+                }
+                artifacts.push(Artifact { name: named(unit.path, "names"), contents: names.join(" ") });
+            }
+            artifacts
+        }
+    }
+
+    #[test]
+    fn should_generate_through_a_registered_backend() {
+        let (path, empty) = (Path::new("<backend>/sq.hop"), Path::new("<backend>/empty.hop"));
+        let driver = Driver::new()
+            .with_backend(Names)
+            .with_source(path, "dec sq : num -> num;\n--- sq x <= x * x;\ndec one : num;\n--- one <= 1;".to_owned())
+            .with_source(empty, "sq 2;".to_owned());
+        assert_eq!(driver.backends(), ["names", "core", "smt2", "tptp"]);
+
+        let (outcome, artifacts) = driver.generate(&[path.to_path_buf()], "names");
+        assert!(outcome.succeeded());
+        assert_eq!(artifacts, [Artifact { name: PathBuf::from("sq.names"), contents: "sq one".to_owned() }]);
+        let (outcome, _) = driver.generate(&[path.to_path_buf(), empty.to_path_buf()], "names");
+        assert_eq!((outcome.status, outcome.diagnostics.len()), (Status::Failed(Stage::Generate), 1));
+
+        let (_, artifacts) = driver.generate(&[path.to_path_buf()], "smt2");
+        assert!(artifacts[0].contents.contains("(declare-fun sq (Int) Int)"), "{}", artifacts[0].contents);
+        let (_, artifacts) = driver.generate(&[path.to_path_buf()], "core");
+        assert_eq!(artifacts[0].name, Path::new("sq.core"));
+        let (outcome, artifacts) = driver.generate(&[path.to_path_buf()], "jvm");
+        assert!(artifacts.is_empty() && !outcome.succeeded());
+        assert_eq!(outcome.diagnostics[0].message, "there is no backend `jvm`, only names, core, smt2 and tptp");
+    }
+}
@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+use crate::library;
+use crate::source;
+use crate::modules::Module;
+
+// Where `hope build` writes the commands, as compile_commands.json does for C
+pub const COMMANDS_FILE: &str = "hope-commands.json";
+
+// How to build one file of a program: check it with the modules it uses and write its
+// core, as `hope build` does for them all at once. Library modules are built in, so
+// they are neither built nor used here
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub path: PathBuf,
+    pub uses: Vec<PathBuf>,
+    pub output: PathBuf,
+    pub arguments: Vec<String>,
+}
+
+// The file's core, as `hope check --emit core` names it
+pub fn output(path: &Path, dir: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or(path.as_os_str()).to_string_lossy();
+    dir.join(format!("{}.core", stem))
+}
+
+// program is how hope is run and flags what the files are read with, both given to
+// each command as they are
+pub fn commands(modules: &[Module], program: &str, flags: &[String], dir: &Path) -> Vec<Command> {
+    let built = |path: &Path| library::source(path).is_none();
+    modules.iter().filter(|module| built(&module.path)).map(|module| {
+        let mut arguments = vec![program.to_owned(), "check".to_owned()];
+        arguments.extend(flags.iter().cloned());
+        arguments.extend(["--emit".to_owned(), "core".to_owned(), "--emit-dir".to_owned()]);
+        arguments.extend([dir, &module.path].map(|path| path.to_string_lossy().into_owned()));
+        Command {
+            path: module.path.clone(),
+            uses: module.uses.iter().filter(|path| built(path)).cloned().collect(),
+            output: output(&module.path, dir),
+            arguments,
+        }
+    }).collect()
+}
+
+// The kinds of build file `hope build --emit-build-graph` writes, each building every
+// file's output after those of the modules it uses, so the build tool can run the
+// commands in parallel and skip those whose inputs haven't changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Graph {
+    Ninja,
+    Make,
+}
+
+impl Graph {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Graph::Ninja => "build.ninja",
+            Graph::Make => "Makefile",
+        }
+    }
+
+    // The build file, to be run in directory
+    pub fn write(self, commands: &[Command], directory: &Path) -> String {
+        let mut out = format!("# Written by hope build, to be run in {}\n", source::display(directory));
+        let (escape, rule): (fn(&str) -> String, _) = match self {
+            Graph::Ninja => (ninja_path, "\nrule hope\n  command = $command\n  description = hope check $in\n".to_owned()),
+            Graph::Make => {
+                let all: String = commands.iter().map(|command| format!(" {}", make_path(&source::display(&command.output)))).collect();
+                (make_path, format!("\n.PHONY: all\nall:{}\n", all))
+            }
+        };
+        out.push_str(&rule);
+        for command in commands {
+            // What the modules it uses build, which it has to come after
+            let after: String = commands.iter()
+                .filter(|used| command.uses.contains(&used.path))
+                .map(|used| format!(" {}", escape(&source::display(&used.output))))
+                .collect();
+            let (output, path) = (escape(&source::display(&command.output)), escape(&source::display(&command.path)));
+            let run = shell(&command.arguments).replace('$', "$$");
+            out.push_str(&match self {
+                Graph::Ninja if after.is_empty() => format!("\nbuild {}: hope {}\n  command = {}\n", output, path, run),
+                Graph::Ninja => format!("\nbuild {}: hope {} |{}\n  command = {}\n", output, path, after, run),
+                Graph::Make => format!("\n{}: {}{}\n\t{}\n", output, path, after, run),
+            });
+        }
+        out
+    }
+}
+
+// Quoted for sh where they need it
+fn shell(arguments: &[String]) -> String {
+    let quoted = arguments.iter().map(|argument| {
+        let plain = !argument.is_empty() && argument.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+        if plain { argument.clone() } else { format!("'{}'", argument.replace('\'', "'\\''")) }
+    });
+    quoted.collect::<Vec<_>>().join(" ")
+}
+
+fn ninja_path(path: &str) -> String {
+    path.replace('$', "$$").replace(' ', "$ ").replace(':', "$:")
+}
+
+// Make has no way to write a space in a target, so those are left to fail there
+fn make_path(path: &str) -> String {
+    path.replace('$', "$$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_write_build_files_that_build_modules_first() {
+        let modules = [
+            Module { path: PathBuf::from("Lists.hop"), uses: Vec::new() },
+            Module { path: PathBuf::from("my main.hop"), uses: vec![PathBuf::from("Lists.hop")] },
+        ];
+        let commands = commands(&modules, "hope", &[], Path::new("out"));
+        let ninja = Graph::Ninja.write(&commands, Path::new("/src"));
+        assert!(ninja.starts_with("# Written by hope build, to be run in /src\n\nrule hope\n  command = $command\n"), "{}", ninja);
+        assert!(ninja.contains("\nbuild out/Lists.core: hope Lists.hop\n  command = hope check --emit core --emit-dir out Lists.hop\n"), "{}", ninja);
+        assert!(ninja.contains("\nbuild out/my$ main.core: hope my$ main.hop | out/Lists.core\n  command = hope check --emit core --emit-dir out 'my main.hop'\n"), "{}", ninja);
+        let make = Graph::Make.write(&commands, Path::new("/src"));
+        assert!(make.contains("\n.PHONY: all\nall: out/Lists.core out/my main.core\n"), "{}", make);
+        assert!(make.contains("\nout/my main.core: my main.hop out/Lists.core\n\thope check --emit core --emit-dir out 'my main.hop'\n"), "{}", make);
+        assert_eq!(shell(&["it's".to_owned(), String::new()]), "'it'\\''s' ''");
+    }
+
+    #[test]
+    fn should_build_each_file_but_the_library() {
+        let modules = [
+            Module { path: PathBuf::from("<library>/Char.hop"), uses: Vec::new() },
+            Module { path: PathBuf::from("src/Lists.hop"), uses: vec![PathBuf::from("<library>/Char.hop")] },
+            Module { path: PathBuf::from("src/main.hop"), uses: vec![PathBuf::from("src/Lists.hop")] },
+        ];
+        let commands = commands(&modules, "hope", &["--macros".to_owned()], Path::new("out"));
+        assert_eq!(commands.len(), 2);
+        assert_eq!((commands[0].uses.len(), &commands[1].uses), (0, &vec![PathBuf::from("src/Lists.hop")]));
+        assert_eq!(commands[1].output, Path::new("out").join("main.core"));
+        assert_eq!(commands[1].arguments, ["hope", "check", "--macros", "--emit", "core", "--emit-dir", "out", "src/main.hop"]);
+    }
+}
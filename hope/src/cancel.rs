@@ -0,0 +1,25 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// A flag raised from elsewhere, usually another thread, to give up on work no longer
+// wanted, as when the text being checked has changed since. Lexing looks at it every
+// EVERY tokens, parsing and checking before each declaration, and each stops with an
+// error saying it was cancelled. Clones share the flag
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+pub const EVERY: usize = 256;
+
+impl Cancel {
+    pub fn new() -> Self {
+        Cancel::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
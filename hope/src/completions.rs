@@ -0,0 +1,339 @@
+use std::fmt::Write;
+use clap::builder::ValueHint;
+use clap::{ArgAction, Command};
+use crate::source;
+
+// Shell scripts that complete the command line of a clap command: its subcommands,
+// each one's options and the values they take. Options and arguments with a set of
+// values complete to those, ones hinted as a directory or file to those, and the
+// other arguments, and options hinted as any path, to Hope sources and directories
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+}
+
+pub fn generate(shell: Shell, command: &Command) -> String {
+    let spec = Spec::of(command);
+    match shell {
+        Shell::Bash => bash(&spec),
+        Shell::Zsh => zsh(&spec),
+        Shell::Fish => fish(&spec),
+        Shell::PowerShell => powershell(&spec),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Choices(Vec<String>),
+    Dirs,
+    Files,
+    Sources,
+    // Anything, which there is nothing to complete to
+    Text,
+}
+
+#[derive(Debug)]
+struct Opt {
+    long: String,
+    short: Option<char>,
+    help: String,
+    value: Option<Value>,
+    repeats: bool,
+}
+
+#[derive(Debug)]
+struct Sub {
+    name: String,
+    // Every other name it goes by
+    aliases: Vec<String>,
+    about: String,
+    options: Vec<Opt>,
+    // What its arguments are, if it takes any
+    positional: Option<(Value, bool)>,
+}
+
+#[derive(Debug)]
+struct Spec {
+    name: String,
+    subcommands: Vec<Sub>,
+}
+
+impl Spec {
+    fn of(command: &Command) -> Self {
+        let help = || Opt { long: "help".to_owned(), short: Some('h'), help: "Print help".to_owned(), value: None, repeats: false };
+        let subcommands = command.get_subcommands().filter(|sub| !sub.is_hide_set()).map(|sub| {
+            let mut options = Vec::new();
+            let mut positional = None;
+            for arg in sub.get_arguments().filter(|arg| !arg.is_hide_set()) {
+                let repeats = matches!(arg.get_action(), ArgAction::Append | ArgAction::Count);
+                let value = arg.get_action().takes_values().then(|| value(arg));
+                if arg.is_positional() {
+                    positional = value.map(|value| (value, repeats));
+                    continue;
+                }
+                let Some(long) = arg.get_long() else { continue };
+                let help = arg.get_help().map_or_else(String::new, |help| first_line(&help.to_string()));
+                options.push(Opt { long: long.to_owned(), short: arg.get_short(), help, value, repeats });
+            }
+            options.push(help());
+            // Only the first word after it is completed, the name of one of its own
+            // subcommands
+            if sub.has_subcommands() {
+                positional = Some((Value::Choices(sub.get_subcommands().map(|sub| sub.get_name().to_owned()).collect()), false));
+            }
+            Sub {
+                name: sub.get_name().to_owned(),
+                aliases: sub.get_all_aliases().map(str::to_owned).collect(),
+                about: sub.get_about().map_or_else(String::new, |about| first_line(&about.to_string())),
+                options,
+                positional,
+            }
+        }).collect();
+        Spec { name: command.get_name().to_owned(), subcommands }
+    }
+
+    fn names(&self) -> Vec<&str> {
+        self.subcommands.iter().map(|sub| sub.name.as_str()).collect()
+    }
+}
+
+fn value(arg: &clap::Arg) -> Value {
+    let choices: Vec<String> = arg.get_possible_values().iter().filter(|v| !v.is_hide_set()).map(|v| v.get_name().to_owned()).collect();
+    match arg.get_value_hint() {
+        _ if !choices.is_empty() => Value::Choices(choices),
+        ValueHint::DirPath => Value::Dirs,
+        ValueHint::FilePath => Value::Files,
+        ValueHint::AnyPath => Value::Sources,
+        ValueHint::Other => Value::Text,
+        _ if arg.is_positional() => Value::Sources,
+        _ => Value::Text,
+    }
+}
+
+// Help that was written over several lines, joined into one
+fn first_line(help: &str) -> String {
+    help.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn bash(spec: &Spec) -> String {
+    let name = &spec.name;
+    let mut out = format!("_{name}_sources() {{\n    COMPREPLY+=($(compgen -d -- \"$cur\"))\n");
+    for ext in source::EXTENSIONS {
+        writeln!(out, "    COMPREPLY+=($(compgen -f -X '!*.{}' -- \"$cur\"))", ext).unwrap();
+    }
+    out.push_str("}\n\n");
+    writeln!(out, "_{name}() {{").unwrap();
+    out.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\" command=\"\"\n");
+    out.push_str("    COMPREPLY=()\n");
+    out.push_str("    for word in \"${COMP_WORDS[@]:1:COMP_CWORD-1}\"; do\n");
+    out.push_str("        case \"$word\" in -*) ;; *) command=\"$word\"; break ;; esac\n    done\n");
+    out.push_str("    case \"$command\" in\n");
+    writeln!(out, "        \"\") COMPREPLY=($(compgen -W \"{} --help --version\" -- \"$cur\")) ;;", spec.names().join(" ")).unwrap();
+    let complete = |value: &Value| match value {
+        Value::Choices(choices) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", choices.join(" ")),
+        Value::Dirs => "COMPREPLY=($(compgen -d -- \"$cur\"))".to_owned(),
+        Value::Files => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_owned(),
+        Value::Sources => format!("_{}_sources", name),
+        Value::Text => "COMPREPLY=()".to_owned(),
+    };
+    for sub in &spec.subcommands {
+        let names: Vec<&str> = std::iter::once(sub.name.as_str()).chain(sub.aliases.iter().map(String::as_str)).collect();
+        writeln!(out, "        {})", names.join("|")).unwrap();
+        let valued: Vec<&Opt> = sub.options.iter().filter(|opt| opt.value.is_some()).collect();
+        if !valued.is_empty() {
+            out.push_str("            case \"$prev\" in\n");
+            for opt in valued {
+                let flags = flags(opt).join("|");
+                writeln!(out, "                {}) {}; return ;;", flags, complete(opt.value.as_ref().unwrap())).unwrap();
+            }
+            out.push_str("            esac\n");
+        }
+        let flags: Vec<String> = sub.options.iter().flat_map(flags).collect();
+        writeln!(out, "            if [[ \"$cur\" == -* ]]; then COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return; fi", flags.join(" ")).unwrap();
+        if let Some((value, _)) = &sub.positional {
+            writeln!(out, "            {}", complete(value)).unwrap();
+        }
+        out.push_str("            ;;\n");
+    }
+    out.push_str("    esac\n}\n\n");
+    writeln!(out, "complete -o filenames -F _{name} {name}").unwrap();
+    out
+}
+
+fn flags(opt: &Opt) -> Vec<String> {
+    opt.short.map(|short| format!("-{}", short)).into_iter().chain([format!("--{}", opt.long)]).collect()
+}
+
+fn zsh(spec: &Spec) -> String {
+    let name = &spec.name;
+    let escape = |text: &str| text.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:");
+    let sources = format!("_files -g \"*.({})\"", source::EXTENSIONS.join("|"));
+    let action = |value: &Value| match value {
+        Value::Choices(choices) => format!("({})", choices.join(" ")),
+        Value::Dirs => "_files -/".to_owned(),
+        Value::Files => "_files".to_owned(),
+        Value::Sources => sources.clone(),
+        Value::Text => " ".to_owned(),
+    };
+    let mut out = format!("#compdef {name}\n\n_{name}() {{\n    local line state\n    _arguments -C \\\n");
+    out.push_str("        '(-h --help)'{-h,--help}'[Print help]' \\\n");
+    out.push_str("        '(-V --version)'{-V,--version}'[Print version]' \\\n");
+    out.push_str("        '1:command:->command' \\\n        '*::arg:->args'\n");
+    out.push_str("    case $state in\n        command)\n            local -a commands\n            commands=(\n");
+    for sub in &spec.subcommands {
+        writeln!(out, "                '{}:{}'", sub.name, escape(&sub.about)).unwrap();
+    }
+    out.push_str("            )\n            _describe command commands ;;\n        args)\n            case $line[1] in\n");
+    for sub in &spec.subcommands {
+        let names: Vec<&str> = std::iter::once(sub.name.as_str()).chain(sub.aliases.iter().map(String::as_str)).collect();
+        writeln!(out, "                {})\n                    _arguments \\", names.join("|")).unwrap();
+        let mut specs = Vec::new();
+        for opt in &sub.options {
+            let repeats = if opt.repeats { "*" } else { "" };
+            let takes = match &opt.value {
+                Some(value) => format!("=[{}]:{}:{}", escape(&opt.help), opt.long, action(value)),
+                None => format!("[{}]", escape(&opt.help)),
+            };
+            for flag in flags(opt) {
+                specs.push(format!("'{}{}{}'", repeats, flag, takes));
+            }
+        }
+        if let Some((value, repeats)) = &sub.positional {
+            specs.push(format!("'{}:argument:{}'", if *repeats { "*" } else { "" }, action(value)));
+        }
+        let specs: Vec<String> = specs.iter().map(|spec| format!("                        {}", spec)).collect();
+        writeln!(out, "{} ;;", specs.join(" \\\n")).unwrap();
+    }
+    out.push_str("            esac ;;\n    esac\n}\n\n");
+    writeln!(out, "_{name} \"$@\"").unwrap();
+    out
+}
+
+fn fish(spec: &Spec) -> String {
+    let name = &spec.name;
+    let quote = |text: &str| format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"));
+    let sources: Vec<String> = source::EXTENSIONS.iter().map(|ext| format!(".{}", ext)).collect();
+    let mut out = format!("complete -c {name} -f\n");
+    writeln!(out, "complete -c {name} -n __fish_use_subcommand -s h -l help -d 'Print help'").unwrap();
+    writeln!(out, "complete -c {name} -n __fish_use_subcommand -s V -l version -d 'Print version'").unwrap();
+    for sub in &spec.subcommands {
+        writeln!(out, "complete -c {name} -n __fish_use_subcommand -a {} -d {}", sub.name, quote(&sub.about)).unwrap();
+    }
+    let values = |value: &Value| match value {
+        Value::Choices(choices) => format!(" -x -a {}", quote(&choices.join(" "))),
+        Value::Dirs => " -x -a '(__fish_complete_directories)'".to_owned(),
+        Value::Files => " -r -F".to_owned(),
+        Value::Sources => format!(" -r -k -a '(__fish_complete_suffix {})'", sources.join(" ")),
+        Value::Text => " -x".to_owned(),
+    };
+    for sub in &spec.subcommands {
+        let names: Vec<&str> = std::iter::once(sub.name.as_str()).chain(sub.aliases.iter().map(String::as_str)).collect();
+        let condition = format!("-n '__fish_seen_subcommand_from {}'", names.join(" "));
+        for opt in &sub.options {
+            let short = opt.short.map_or_else(String::new, |short| format!(" -s {}", short));
+            let takes = opt.value.as_ref().map_or_else(String::new, values);
+            let help = if opt.help.is_empty() { String::new() } else { format!(" -d {}", quote(&opt.help)) };
+            writeln!(out, "complete -c {name} {condition}{short} -l {}{takes}{help}", opt.long).unwrap();
+        }
+        if let Some((value, _)) = &sub.positional {
+            writeln!(out, "complete -c {name} {condition}{}", values(value)).unwrap();
+        }
+    }
+    out
+}
+
+fn powershell(spec: &Spec) -> String {
+    let name = &spec.name;
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    let list = |items: &[String]| format!("@({})", items.iter().map(|item| quote(item)).collect::<Vec<_>>().join(", "));
+    let patterns: Vec<String> = source::EXTENSIONS.iter().map(|ext| format!("*.{}", ext)).collect();
+    let mut out = format!("Register-ArgumentCompleter -Native -CommandName {name} -ScriptBlock {{\n");
+    out.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+    out.push_str("    $words = @($commandAst.CommandElements | Select-Object -Skip 1 | ForEach-Object { $_.ToString() })\n");
+    out.push_str("    if ($wordToComplete) { $words = @($words | Select-Object -SkipLast 1) }\n");
+    out.push_str("    $command = $words | Where-Object { -not $_.StartsWith('-') } | Select-Object -First 1\n");
+    out.push_str("    $previous = if ($words.Count -gt 0) { $words[-1] } else { '' }\n");
+    out.push_str("    $candidates = @()\n    $paths = $null\n");
+    let complete = |value: &Value| match value {
+        Value::Choices(choices) => format!("$candidates = {}", list(choices)),
+        Value::Dirs => "$paths = 'dirs'".to_owned(),
+        Value::Files => "$paths = 'files'".to_owned(),
+        Value::Sources => "$paths = 'sources'".to_owned(),
+        Value::Text => "$candidates = @()".to_owned(),
+    };
+    out.push_str("    switch ($command) {\n");
+    for sub in &spec.subcommands {
+        let names: Vec<String> = std::iter::once(&sub.name).chain(&sub.aliases).map(|name| quote(name)).collect();
+        writeln!(out, "        {{ $_ -in @({}) }} {{", names.join(", ")).unwrap();
+        out.push_str("            $taken = $true\n            switch ($previous) {\n");
+        for opt in sub.options.iter().filter(|opt| opt.value.is_some()) {
+            for flag in flags(opt) {
+                writeln!(out, "                {} {{ {} }}", quote(&flag), complete(opt.value.as_ref().unwrap())).unwrap();
+            }
+        }
+        out.push_str("                default { $taken = $false }\n            }\n");
+        let flags: Vec<String> = sub.options.iter().flat_map(flags).collect();
+        writeln!(out, "            if (-not $taken -and $wordToComplete.StartsWith('-')) {{ $candidates = {} }}", list(&flags)).unwrap();
+        if let Some((value, _)) = &sub.positional {
+            writeln!(out, "            elseif (-not $taken) {{ {} }}", complete(value)).unwrap();
+        }
+        out.push_str("            break\n        }\n");
+    }
+    let mut top: Vec<String> = spec.names().iter().map(|name| name.to_string()).collect();
+    top.extend(["--help".to_owned(), "--version".to_owned()]);
+    writeln!(out, "        default {{ $candidates = {} }}\n    }}", list(&top)).unwrap();
+    writeln!(out, "    $sources = {}", list(&patterns)).unwrap();
+    out.push_str("    if ($paths) {\n        $parent = Split-Path -Path $wordToComplete -Parent\n");
+    out.push_str("        $candidates = Get-ChildItem -Path \"$wordToComplete*\" -ErrorAction SilentlyContinue | Where-Object {\n");
+    out.push_str("            $item = $_\n");
+    out.push_str("            $item.PSIsContainer -or $paths -eq 'files' -or ($paths -eq 'sources' -and ($sources | Where-Object { $item.Name -like $_ }))\n");
+    out.push_str("        } | ForEach-Object { if ($parent) { Join-Path $parent $_.Name } else { $_.Name } }\n    }\n");
+    out.push_str("    $candidates | Where-Object { $_ -like \"$wordToComplete*\" } | ForEach-Object {\n");
+    out.push_str("        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n    }\n}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn command() -> Command {
+        Command::new("hope").subcommand(
+            Command::new("run").about("Run the files")
+                .alias("go")
+                .arg(Arg::new("dialect").long("dialect").help("Which keywords").value_parser(["classic", "modern"]))
+                .arg(Arg::new("module-path").long("module-path").value_hint(ValueHint::DirPath).action(ArgAction::Append))
+                .arg(Arg::new("strict").long("strict").action(ArgAction::SetTrue))
+                .arg(Arg::new("paths").action(ArgAction::Append)),
+        )
+        .subcommand(Command::new("examples").subcommand(Command::new("list")).subcommand(Command::new("show")))
+    }
+
+    #[test]
+    fn should_complete_options_and_their_values_from_the_command() {
+        let bash = generate(Shell::Bash, &command());
+        assert!(bash.contains("        run|go)\n"), "{}", bash);
+        assert!(bash.contains("--dialect) COMPREPLY=($(compgen -W \"classic modern\" -- \"$cur\")); return ;;"));
+        assert!(bash.contains("--module-path) COMPREPLY=($(compgen -d -- \"$cur\")); return ;;"));
+        assert!(bash.contains("compgen -W \"--dialect --module-path --strict -h --help\""));
+        assert!(bash.contains("compgen -f -X '!*.lhop'") && bash.ends_with("complete -o filenames -F _hope hope\n"));
+        assert!(bash.contains("COMPREPLY=($(compgen -W \"list show\" -- \"$cur\"))"));
+
+        let zsh = generate(Shell::Zsh, &command());
+        assert!(zsh.contains("'--dialect=[Which keywords]:dialect:(classic modern)'"), "{}", zsh);
+        assert!(zsh.contains("'*--module-path=[]:module-path:_files -/'"));
+        assert!(zsh.contains("'*:argument:_files -g \"*.(hop|lhop)\"'"));
+
+        let fish = generate(Shell::Fish, &command());
+        assert!(fish.contains("complete -c hope -n '__fish_seen_subcommand_from run go' -l dialect -x -a 'classic modern' -d 'Which keywords'"), "{}", fish);
+        assert!(fish.contains("complete -c hope -n '__fish_seen_subcommand_from run go' -r -k -a '(__fish_complete_suffix .hop .lhop)'"));
+
+        let powershell = generate(Shell::PowerShell, &command());
+        assert!(powershell.contains("{ $_ -in @('run', 'go') } {"), "{}", powershell);
+        assert!(powershell.contains("'--dialect' { $candidates = @('classic', 'modern') }"));
+    }
+}
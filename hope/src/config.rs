@@ -0,0 +1,74 @@
+use std::fmt;
+use std::path::Path;
+use crate::driver::{Diagnostic, Driver};
+use crate::eval::{ConvertError, FromValue};
+use crate::sandbox;
+
+// Hope as a configuration language. A configuration is a file whose last expression
+// is its value, checked and evaluated like any program in the pure sandbox, then
+// converted to what the host asked for
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    // Why it didn't parse, check or evaluate
+    Program(Vec<Diagnostic>),
+    NoValue,
+    Convert(ConvertError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Program(diagnostics) => {
+                let lines: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            ConfigError::NoValue => write!(f, "the configuration has no expression to give its value"),
+            ConfigError::Convert(e) => write!(f, "the configuration's value isn't what was asked for: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub fn load<T: FromValue>(path: impl AsRef<Path>) -> Result<T, ConfigError> {
+    let pure = sandbox::sandbox("pure").expect("there is a pure sandbox");
+    let outcome = Driver::new().with_sandbox(pure).run(&[path.as_ref().to_path_buf()]);
+    if !outcome.succeeded() {
+        return Err(ConfigError::Program(outcome.diagnostics));
+    }
+    let value = outcome.value.ok_or(ConfigError::NoValue)?;
+    T::from_value(&value).map_err(ConfigError::Convert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_source<T: FromValue>(name: &str, source: &str) -> Result<T, ConfigError> {
+        let path = std::env::temp_dir().join(format!("hope-config-{}-{}.hop", std::process::id(), name));
+        std::fs::write(&path, source).unwrap();
+        let loaded = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn should_give_the_last_expression_as_a_rust_value() {
+        let source = "dec port : num;\n--- port <= 8000 + 80;\n[(\"web\", port, true), (\"admin\", port + 1, false)];";
+        let servers: Vec<(String, i64, bool)> = load_source("servers", source).unwrap();
+        assert_eq!(servers, [("web".to_owned(), 8080, true), ("admin".to_owned(), 8081, false)]);
+        let e = load_source::<Vec<String>>("wrong", "[1, 2];").unwrap_err();
+        assert_eq!(e.to_string(), "the configuration's value isn't what was asked for: expected text, found 1");
+        assert_eq!(load_source::<i64>("none", "dec x : num;\n--- x <= 1;").unwrap_err(), ConfigError::NoValue);
+    }
+
+    #[test]
+    fn should_refuse_writes_and_stop_what_runs_too_long() {
+        let e = load_source::<i64>("write", "write 1;\n2;").unwrap_err();
+        assert!(e.to_string().ends_with(":1:1: this sandbox doesn't allow `write`"), "{}", e);
+        let source = "dec spin : num -> num;\n--- spin n <= spin (n + 1);\nspin 0;";
+        let ConfigError::Program(diagnostics) = load_source::<i64>("spin", source).unwrap_err() else { panic!() };
+        assert!(diagnostics[0].code.is_some_and(|code| code.starts_with("E04")));
+    }
+}
@@ -0,0 +1,484 @@
+use core::fmt;
+use alloc::collections::{BTreeMap, BTreeSet};
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+
+// A rough estimate of how many steps a function takes, from the shape of its
+// recursion. Each equation gives a recurrence in the size n of the argument its
+// recursive calls shrink, which is solved for the usual shapes: one call on a smaller
+// part is linear, two or more are exponential, and calls on a fraction of it follow
+// the master theorem. Functions it calls cost what their own estimate says, and
+// anything passed in as an argument is taken to run in constant time.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    // n^degree (log n)^logs
+    Power { degree: f64, logs: u32 },
+    Exponential(u32),
+    Unknown,
+}
+
+impl Order {
+    pub const CONSTANT: Order = Order::Power { degree: 0.0, logs: 0 };
+
+    fn rank(self) -> (u8, f64, u32) {
+        match self {
+            Order::Power { degree, logs } => (0, degree, logs),
+            Order::Exponential(base) => (1, f64::from(base), 0),
+            Order::Unknown => (2, 0.0, 0),
+        }
+    }
+
+    pub fn max(self, other: Order) -> Order {
+        if other.rank().partial_cmp(&self.rank()) == Some(core::cmp::Ordering::Greater) { other } else { self }
+    }
+
+    fn times_n(self) -> Order {
+        match self {
+            Order::Power { degree, logs } => Order::Power { degree: degree + 1.0, logs },
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Order::Power { degree, logs } => {
+                let n = if degree == 0.0 {
+                    String::new()
+                } else if degree == 1.0 {
+                    "n".to_string()
+                } else if degree == degree as i64 as f64 {
+                    format!("n^{}", degree)
+                } else {
+                    format!("n^{:.2}", degree)
+                };
+                let log = match logs {
+                    0 => String::new(),
+                    1 => "log n".to_string(),
+                    k => format!("log^{} n", k),
+                };
+                match (n.is_empty(), log.is_empty()) {
+                    (true, true) => write!(f, "1"),
+                    (false, false) => write!(f, "{} {}", n, log),
+                    _ => write!(f, "{}{}", n, log),
+                }
+            }
+            Order::Exponential(base) => write!(f, "{}^n", base),
+            Order::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+// How a recursive call makes its argument smaller
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shrink {
+    Minus(i64),
+    Divide(i64),
+    // A variable the argument's pattern binds inside it, such as the tail of a list
+    Part(String),
+    // The call doesn't visibly shrink any argument
+    Unknown,
+}
+
+impl fmt::Display for Shrink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Shrink::Minus(k) => write!(f, "T(n - {})", k),
+            Shrink::Divide(k) => write!(f, "T(n / {})", k),
+            Shrink::Part(v) => write!(f, "T(|{}|)", v),
+            Shrink::Unknown => write!(f, "T(?)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub name: String,
+    // The argument the recursion shrinks, counting the parts of a tuple as arguments
+    pub size: Option<usize>,
+    // The recursive calls and the work around them of the costliest equation
+    pub calls: Vec<Shrink>,
+    pub work: Order,
+    pub order: Order,
+    // Names taken to run in constant time, why the estimate isn't exact
+    pub assumed: Vec<String>,
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.name)?;
+        if self.calls.is_empty() {
+            write!(f, "no recursion, O({})", self.order)?;
+        } else {
+            write!(f, "T(n) = ")?;
+            for call in &self.calls {
+                write!(f, "{} + ", call)?;
+            }
+            write!(f, "O({}), about O({})", self.work, self.order)?;
+        }
+        if let Some(size) = self.size {
+            write!(f, "\n  where n is the size of argument {}", size + 1)?;
+        }
+        for name in &self.assumed {
+            write!(f, "\n  assuming `{}` takes constant time", name)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn estimate(decls: &[Decl], name: &str) -> Option<Estimate> {
+    let mut analysis = Analysis { equations: BTreeMap::new(), done: BTreeMap::new(), active: Vec::new() };
+    for decl in decls {
+        if let DeclKind::Equation(eq) = &decl.kind {
+            analysis.equations.entry(eq.name.name.as_str()).or_default().push(eq);
+        }
+    }
+    if !analysis.equations.contains_key(name) {
+        return None;
+    }
+    Some(analysis.function(name))
+}
+
+struct Analysis<'a> {
+    equations: BTreeMap<&'a str, Vec<&'a Equation>>,
+    done: BTreeMap<String, Estimate>,
+    active: Vec<String>,
+}
+
+// What an expression calls, where `recursive` holds the arguments of each call to the
+// function being estimated
+#[derive(Default)]
+struct Calls<'a> {
+    recursive: Vec<Vec<&'a Expr>>,
+    others: Vec<&'a str>,
+    assumed: Vec<&'a str>,
+    // The function is passed somewhere as a value, so it may be called any number of times
+    escapes: bool,
+}
+
+impl<'a> Calls<'a> {
+    fn extend(&mut self, other: Calls<'a>) {
+        self.recursive.extend(other.recursive);
+        self.others.extend(other.others);
+        self.assumed.extend(other.assumed);
+        self.escapes |= other.escapes;
+    }
+}
+
+impl<'a> Analysis<'a> {
+    fn function(&mut self, name: &str) -> Estimate {
+        if let Some(done) = self.done.get(name) {
+            return done.clone();
+        }
+        let unknown = Estimate { name: name.to_string(), size: None, calls: Vec::new(), work: Order::Unknown, order: Order::Unknown, assumed: Vec::new() };
+        if self.active.iter().any(|active| active == name) {
+            return unknown;
+        }
+        self.active.push(name.to_string());
+        let equations = self.equations[name].clone();
+        let mut best: Option<Estimate> = None;
+        for eq in equations {
+            let estimate = self.equation(name, eq);
+            best = match best {
+                Some(best) if best.order.max(estimate.order) == best.order => Some(best),
+                _ => Some(estimate),
+            };
+        }
+        self.active.pop();
+        let estimate = best.unwrap_or(unknown);
+        self.done.insert(name.to_string(), estimate.clone());
+        estimate
+    }
+
+    fn equation(&mut self, name: &str, eq: &'a Equation) -> Estimate {
+        let params = params(&eq.args);
+        let mut bound = BTreeSet::new();
+        for param in &params {
+            bind(param, &mut bound);
+        }
+        let mut calls = Calls::default();
+        if let Some(guard) = &eq.guard {
+            calls.extend(self.calls(name, guard, &bound));
+        }
+        calls.extend(self.calls(name, &eq.body, &bound));
+
+        let mut work = Order::CONSTANT;
+        let mut assumed: Vec<String> = Vec::new();
+        let mut assume = |name: String| {
+            if !assumed.contains(&name) {
+                assumed.push(name);
+            }
+        };
+        calls.assumed.iter().for_each(|name| assume(name.to_string()));
+        for other in &calls.others {
+            let callee = self.function(other);
+            work = work.max(callee.order);
+            callee.assumed.into_iter().for_each(&mut assume);
+        }
+
+        let mut size = None;
+        let mut shrinks = Vec::new();
+        for args in &calls.recursive {
+            let args = arguments(args);
+            let found = args.iter().zip(&params).enumerate().find_map(|(i, (arg, param))| shrink(arg, param).map(|s| (i, s)));
+            match found {
+                Some((i, s)) => {
+                    size.get_or_insert(i);
+                    shrinks.push(s);
+                }
+                None => shrinks.push(Shrink::Unknown),
+            }
+        }
+        let order = if calls.escapes { Order::Unknown } else { solve(&shrinks, work) };
+        Estimate { name: name.to_string(), size, calls: shrinks, work, order, assumed }
+    }
+
+    fn calls(&self, name: &str, expr: &'a Expr, bound: &BTreeSet<String>) -> Calls<'a> {
+        let mut calls = Calls::default();
+        match &expr.kind {
+            ExprKind::Var(v) => {
+                if v == name {
+                    calls.escapes = true;
+                } else if self.equations.contains_key(v.as_str()) && !bound.contains(v) {
+                    calls.others.push(v);
+                }
+            }
+            ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) => {}
+            ExprKind::Tuple(items) | ExprKind::List(items) => {
+                for item in items {
+                    calls.extend(self.calls(name, item, bound));
+                }
+            }
+            ExprKind::Apply(..) => {
+                let mut head = expr;
+                let mut args = Vec::new();
+                while let ExprKind::Apply(f, arg) = &head.kind {
+                    args.push(&**arg);
+                    head = f;
+                }
+                args.reverse();
+                match &head.kind {
+                    ExprKind::Var(v) if v == name && !bound.contains(v) => calls.recursive.push(args.clone()),
+                    ExprKind::Var(v) if bound.contains(v) => calls.assumed.push(v),
+                    _ => calls.extend(self.calls(name, head, bound)),
+                }
+                for arg in args {
+                    calls.extend(self.calls(name, arg, bound));
+                }
+            }
+            ExprKind::BinOp(op, l, r) => {
+                if op.name == name {
+                    calls.recursive.push(vec![&**l, &**r]);
+                } else if self.equations.contains_key(op.name.as_str()) {
+                    calls.others.push(&op.name);
+                }
+                calls.extend(self.calls(name, l, bound));
+                calls.extend(self.calls(name, r, bound));
+            }
+            // Only one branch runs, so count the one with more recursive calls
+            ExprKind::If(c, t, e) => {
+                calls.extend(self.calls(name, c, bound));
+                let mut t = self.calls(name, t, bound);
+                let mut e = self.calls(name, e, bound);
+                if e.recursive.len() > t.recursive.len() {
+                    core::mem::swap(&mut t, &mut e);
+                }
+                calls.others.append(&mut e.others);
+                calls.assumed.append(&mut e.assumed);
+                calls.escapes |= e.escapes;
+                calls.extend(t);
+            }
+            ExprKind::Lambda(rules) => {
+                for rule in rules {
+                    let mut bound = bound.clone();
+                    bind(&rule.pattern, &mut bound);
+                    calls.extend(self.calls(name, &rule.body, &bound));
+                }
+            }
+            ExprKind::Let(l) => {
+                let mut inner = bound.clone();
+                bind(&l.pattern, &mut inner);
+                calls.extend(self.calls(name, &l.value, if l.kind.is_rec() { &inner } else { bound }));
+                calls.extend(self.calls(name, &l.body, &inner));
+            }
+        }
+        calls
+    }
+}
+
+// The arguments of an equation or a call with any tuples spread out, so that
+// `f (x :: l, n)` and `f (l, n - 1)` line up
+fn params(args: &[Pattern]) -> Vec<&Pattern> {
+    let mut flat = Vec::new();
+    for arg in args {
+        match &arg.kind {
+            PatternKind::Tuple(items) => flat.extend(items),
+            _ => flat.push(arg),
+        }
+    }
+    flat
+}
+
+fn arguments<'a>(args: &[&'a Expr]) -> Vec<&'a Expr> {
+    let mut flat = Vec::new();
+    for arg in args {
+        match &arg.kind {
+            ExprKind::Tuple(items) => flat.extend(items),
+            _ => flat.push(*arg),
+        }
+    }
+    flat
+}
+
+fn bind(pattern: &Pattern, bound: &mut BTreeSet<String>) {
+    match &pattern.kind {
+        PatternKind::Var(v) => {
+            bound.insert(v.clone());
+        }
+        PatternKind::As(name, inner) => {
+            bound.insert(name.name.clone());
+            bind(inner, bound);
+        }
+        PatternKind::Tuple(items) | PatternKind::List(items) | PatternKind::Construct(_, items) => {
+            for item in items {
+                bind(item, bound);
+            }
+        }
+        PatternKind::BinOp(_, l, r) => {
+            bind(l, bound);
+            bind(r, bound);
+        }
+        PatternKind::Wildcard | PatternKind::Int(_) | PatternKind::BigInt(_) | PatternKind::Num(_) | PatternKind::Str(_) => {}
+    }
+}
+
+fn binds(pattern: &Pattern, name: &str) -> bool {
+    let mut bound = BTreeSet::new();
+    bind(pattern, &mut bound);
+    bound.contains(name)
+}
+
+fn shrink(arg: &Expr, param: &Pattern) -> Option<Shrink> {
+    match &arg.kind {
+        ExprKind::Var(v) => match &param.kind {
+            PatternKind::Var(_) | PatternKind::Wildcard => None,
+            PatternKind::As(whole, inner) if whole.name == *v => shrink(arg, inner),
+            // `--- f (n + 1) <= ... f n`
+            PatternKind::BinOp(op, l, r) if op.name == "+" && matches!(&l.kind, PatternKind::Var(x) if x == v) => match r.kind {
+                PatternKind::Int(k) if k > 0 => Some(Shrink::Minus(k)),
+                _ => None,
+            },
+            _ if binds(param, v) => Some(Shrink::Part(v.clone())),
+            _ => None,
+        },
+        ExprKind::BinOp(op, l, r) => {
+            let ExprKind::Var(v) = &l.kind else { return None };
+            let ExprKind::Int(k) = r.kind else { return None };
+            if !binds(param, v) {
+                return None;
+            }
+            match op.name.as_str() {
+                "-" if k > 0 => Some(Shrink::Minus(k)),
+                "div" | "/" if k > 1 => Some(Shrink::Divide(k)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn solve(calls: &[Shrink], work: Order) -> Order {
+    if calls.is_empty() {
+        return work;
+    }
+    if calls.iter().all(|call| matches!(call, Shrink::Divide(_))) {
+        return master(calls, work);
+    }
+    if calls.iter().any(|call| !matches!(call, Shrink::Minus(_) | Shrink::Part(_))) {
+        return Order::Unknown;
+    }
+    // Calls on different parts of a structure, like both branches of a tree, share
+    // its size between them, so they visit it once between them
+    let mut parts = BTreeSet::new();
+    let disjoint = calls.iter().all(|call| matches!(call, Shrink::Part(v) if parts.insert(v.clone())));
+    if calls.len() == 1 || disjoint {
+        work.times_n()
+    } else {
+        Order::Exponential(calls.len() as u32)
+    }
+}
+
+// T(n) = a T(n / b) + f(n)
+fn master(calls: &[Shrink], work: Order) -> Order {
+    let Order::Power { degree, logs } = work else { return work };
+    let Shrink::Divide(b) = calls[0] else { return Order::Unknown };
+    if calls.iter().any(|call| *call != calls[0]) {
+        return Order::Unknown;
+    }
+    let critical = ln(calls.len() as f64) / ln(b as f64);
+    if (degree - critical).abs() < 1e-9 {
+        Order::Power { degree, logs: logs + 1 }
+    } else if degree < critical {
+        Order::Power { degree: critical, logs: 0 }
+    } else {
+        work
+    }
+}
+
+// The natural log, which core doesn't have without std
+fn ln(x: f64) -> f64 {
+    // ln x = 2 atanh((x - 1) / (x + 1)), summed until the terms vanish
+    let y = (x - 1.0) / (x + 1.0);
+    let (mut term, mut sum, mut k) = (y, 0.0, 1.0);
+    while term.abs() > 1e-17 {
+        sum += term / k;
+        term *= y * y;
+        k += 2.0;
+    }
+    2.0 * sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn order(source: &str, name: &str) -> String {
+        let mut program = crate::prelude::program();
+        program.decls.extend(parser::parse_program(source).unwrap().decls);
+        estimate(&program.decls, name).unwrap().order.to_string()
+    }
+
+    #[test]
+    fn should_estimate_the_standard_functions() {
+        assert_eq!(order("", "length"), "n");
+        assert_eq!(order("", "<>"), "n");
+        assert_eq!(order("", "reverse"), "n");
+        let estimate = estimate(&crate::prelude::program().decls, "map").unwrap();
+        assert_eq!(estimate.to_string(), "map: T(n) = T(|l|) + O(1), about O(n)\n  where n is the size of argument 2\n  assuming `f` takes constant time");
+    }
+
+    #[test]
+    fn should_solve_the_usual_recurrences() {
+        let source = "dec slow : list num -> list num;\n--- slow [] <= [];\n--- slow (x :: l) <= slow l <> [x];\n\
+            dec fib : num -> num;\n--- fib n <= if n < 2 then n else fib (n - 1) + fib (n - 2);\n\
+            dec halve : num -> num;\n--- halve n <= if n < 1 then 0 else 1 + halve (n div 2);\n\
+            data tree == leaf ++ node (tree # num # tree);\n\
+            dec size : tree -> num;\n--- size leaf <= 0;\n--- size (node (l, _, r)) <= size l + 1 + size r;\n\
+            dec split : list num -> list num;\n--- split l <= if length l < 2 then l else split (take 1 l) <> split (drop 1 l);";
+        assert_eq!(order(source, "slow"), "n^2");
+        assert_eq!(order(source, "fib"), "2^n");
+        assert_eq!(order(source, "halve"), "log n");
+        assert_eq!(order(source, "size"), "n");
+        assert_eq!(order(source, "split"), "unknown");
+    }
+
+    #[test]
+    fn should_follow_the_master_theorem() {
+        assert_eq!(master(&[Shrink::Divide(2), Shrink::Divide(2)], Order::Power { degree: 1.0, logs: 0 }).to_string(), "n log n");
+        assert_eq!(master(&[Shrink::Divide(2), Shrink::Divide(2), Shrink::Divide(2)], Order::CONSTANT).to_string(), "n^1.58");
+        assert_eq!(master(&[Shrink::Divide(2)], Order::Power { degree: 2.0, logs: 0 }).to_string(), "n^2");
+    }
+}
@@ -0,0 +1,389 @@
+use core::fmt;
+use alloc::collections::BTreeMap;
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+use crate::types::TypedDecl;
+
+// A program taken down to a small core: names, numbers, characters, constructors
+// applied to all their arguments, application of one argument, lambdas of one
+// variable, `let` and `letrec` of one name, and `case`, which tries its alternatives in
+// order and fails if none matches. Each function is a lambda for each argument and a
+// case over them with an alternative for each equation, and `if`, lambda rules,
+// where, lists, strings, tuples and infix operators all become these. What it is
+// written in:
+//
+//   program ::= decl*
+//   decl    ::= data name param* = con | ... ;  |  f : type ;  |  f = term ;
+//             | eval term ;  |  write term ;
+//   con     ::= name arity
+//   term    ::= x | (op) | n | 'c' | C[term, ...] | term term | \x. term
+//             | let x = term in term | letrec x = term in term
+//             | case term, ... of alt | ... end
+//   alt     ::= pat, ... (if term)? -> term
+//   pat     ::= x | _ | n | 'c' | C[pat, ...] | x @ pat
+//
+// Tuples are `#[a, #[b, c]]`, lists `::[#[x, nil[]]]` and characters in strings `'c'`,
+// and names made up along the way start with `$`, so they can't be any in the program
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Var(String),
+    Int(i64),
+    Big(String),
+    Num(f64),
+    Char(char),
+    Con(String, Vec<Term>),
+    App(Box<Term>, Box<Term>),
+    Lambda(String, Box<Term>),
+    Let { rec: bool, name: String, value: Box<Term>, body: Box<Term> },
+    Case(Vec<Term>, Vec<Alt>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alt {
+    pub patterns: Vec<Pat>,
+    pub guard: Option<Term>,
+    pub body: Term,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pat {
+    Var(String),
+    Wildcard,
+    Int(i64),
+    Big(String),
+    Num(f64),
+    Char(char),
+    Con(String, Vec<Pat>),
+    As(String, Box<Pat>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Def {
+    Data { name: String, params: Vec<String>, constructors: Vec<(String, usize)> },
+    // The type, as the checker has it
+    Dec(String, String),
+    Value(String, Term),
+    Eval(Term),
+    Write(Term),
+}
+
+pub fn program(decls: &[TypedDecl]) -> Vec<Def> {
+    let mut arities: BTreeMap<String, usize> = [("true", 0), ("false", 0), ("nil", 0), ("::", 1)].into_iter()
+        .map(|(name, arity)| (name.to_owned(), arity))
+        .collect();
+    for typed in decls {
+        if let DeclKind::Data { constructors, .. } = &typed.decl.kind {
+            arities.extend(constructors.iter().map(|c| (c.name.name.clone(), c.args.len())));
+        }
+    }
+    let mut elaborate = Elaborate { arities, fresh: 0 };
+    let mut defs = Vec::new();
+    let mut done: Vec<&str> = Vec::new();
+    for typed in decls {
+        match &typed.decl.kind {
+            DeclKind::Data { head, constructors } => defs.push(Def::Data {
+                name: head.name.name.clone(),
+                params: head.params.iter().map(|p| p.name.clone()).collect(),
+                constructors: constructors.iter().map(|c| (c.name.name.clone(), c.args.len())).collect(),
+            }),
+            DeclKind::Dec { names, .. } => {
+                let ty = typed.ty.as_ref().map_or_else(String::new, |ty| ty.to_string());
+                defs.extend(names.iter().map(|name| Def::Dec(name.name.clone(), ty.clone())));
+            }
+            // All of a function's equations at its first
+            DeclKind::Equation(eq) if !done.contains(&eq.name.name.as_str()) => {
+                done.push(&eq.name.name);
+                let equations: Vec<&Equation> = decls.iter()
+                    .filter_map(|typed| match &typed.decl.kind {
+                        DeclKind::Equation(other) if other.name.name == eq.name.name => Some(other),
+                        _ => None,
+                    })
+                    .collect();
+                elaborate.fresh = 0;
+                defs.push(Def::Value(eq.name.name.clone(), elaborate.function(&equations)));
+            }
+            DeclKind::Expr(expr) => defs.push(Def::Eval(elaborate.expr(expr))),
+            DeclKind::Write(expr) => defs.push(Def::Write(elaborate.expr(expr))),
+            _ => {}
+        }
+    }
+    defs
+}
+
+struct Elaborate {
+    // How many arguments each constructor takes
+    arities: BTreeMap<String, usize>,
+    fresh: usize,
+}
+
+impl Elaborate {
+    fn fresh(&mut self) -> String {
+        self.fresh += 1;
+        format!("${}", self.fresh)
+    }
+
+    fn function(&mut self, equations: &[&Equation]) -> Term {
+        let arity = equations[0].args.len();
+        // A single equation of variables needs no case
+        if let [eq] = equations {
+            let names: Option<Vec<&str>> = eq.args.iter()
+                .map(|p| match &p.kind {
+                    PatternKind::Var(name) if !self.arities.contains_key(name) => Some(name.as_str()),
+                    _ => None,
+                })
+                .collect();
+            if let (Some(names), None) = (names, &eq.guard) {
+                let body = self.expr(&eq.body);
+                return names.into_iter().rev().fold(body, |body, name| Term::Lambda(name.to_owned(), Box::new(body)));
+            }
+        }
+        let params: Vec<String> = (0..arity).map(|_| self.fresh()).collect();
+        let alts = equations.iter()
+            .map(|eq| Alt {
+                patterns: eq.args.iter().map(|p| self.pattern(p)).collect(),
+                guard: eq.guard.as_ref().map(|guard| self.expr(guard)),
+                body: self.expr(&eq.body),
+            })
+            .collect();
+        let case = Term::Case(params.iter().map(|param| Term::Var(param.clone())).collect(), alts);
+        params.into_iter().rev().fold(case, |body, param| Term::Lambda(param, Box::new(body)))
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Term {
+        match &expr.kind {
+            ExprKind::Var(name) => self.apply(name, Vec::new()),
+            ExprKind::Int(n) => Term::Int(*n),
+            ExprKind::BigInt(digits) => Term::Big(digits.clone()),
+            ExprKind::Num(x) => Term::Num(*x),
+            ExprKind::Str(s) => list(s.chars().map(Term::Char).collect()),
+            ExprKind::Tuple(items) => tuple(items.iter().map(|item| self.expr(item)).collect()),
+            ExprKind::List(items) => list(items.iter().map(|item| self.expr(item)).collect()),
+            ExprKind::Apply(..) => {
+                let mut head = expr;
+                let mut args = Vec::new();
+                while let ExprKind::Apply(f, arg) = &head.kind {
+                    args.push(self.expr(arg));
+                    head = f;
+                }
+                args.reverse();
+                match &head.kind {
+                    ExprKind::Var(name) => self.apply(name, args),
+                    _ => {
+                        let f = self.expr(head);
+                        args.into_iter().fold(f, |f, arg| Term::App(Box::new(f), Box::new(arg)))
+                    }
+                }
+            }
+            ExprKind::BinOp(op, l, r) => {
+                let pair = tuple(vec![self.expr(l), self.expr(r)]);
+                self.apply(&op.name, vec![pair])
+            }
+            ExprKind::If(c, t, e) => Term::Case(vec![self.expr(c)], vec![
+                Alt { patterns: vec![Pat::Con("true".to_owned(), Vec::new())], guard: None, body: self.expr(t) },
+                Alt { patterns: vec![Pat::Con("false".to_owned(), Vec::new())], guard: None, body: self.expr(e) },
+            ]),
+            ExprKind::Lambda(rules) => {
+                match &rules[..] {
+                    [Rule { pattern: Pattern { kind: PatternKind::Var(name), .. }, body, .. }] if !self.arities.contains_key(name) => {
+                        return Term::Lambda(name.clone(), Box::new(self.expr(body)));
+                    }
+                    _ => {}
+                }
+                let param = self.fresh();
+                let alts = rules.iter()
+                    .map(|rule| Alt { patterns: vec![self.pattern(&rule.pattern)], guard: None, body: self.expr(&rule.body) })
+                    .collect();
+                Term::Lambda(param.clone(), Box::new(Term::Case(vec![Term::Var(param)], alts)))
+            }
+            ExprKind::Let(binding) => {
+                let rec = binding.kind.is_rec();
+                let value = self.expr(&binding.value);
+                let body = self.expr(&binding.body);
+                match self.pattern(&binding.pattern) {
+                    Pat::Var(name) => Term::Let { rec, name, value: Box::new(value), body: Box::new(body) },
+                    pattern => {
+                        // What the pattern takes apart is named, and each use of a part is
+                        // a case over it, which a letrec's value may be too
+                        let name = self.fresh();
+                        let open = |term: Term| Term::Case(vec![Term::Var(name.clone())], vec![
+                            Alt { patterns: vec![pattern.clone()], guard: None, body: term },
+                        ]);
+                        let value = if rec { open(value) } else { value };
+                        Term::Let { rec, name: name.clone(), value: Box::new(value), body: Box::new(open(body)) }
+                    }
+                }
+            }
+        }
+    }
+
+    // A name applied to arguments, a constructor given all of its, with lambdas for
+    // those it isn't
+    fn apply(&mut self, name: &str, mut args: Vec<Term>) -> Term {
+        let Some(&arity) = self.arities.get(name) else {
+            return args.into_iter().fold(Term::Var(name.to_owned()), |f, arg| Term::App(Box::new(f), Box::new(arg)));
+        };
+        let missing: Vec<String> = (args.len()..arity).map(|_| self.fresh()).collect();
+        args.extend(missing.iter().map(|param| Term::Var(param.clone())));
+        let rest = args.split_off(arity);
+        let con = Term::Con(name.to_owned(), args);
+        let applied = rest.into_iter().fold(con, |f, arg| Term::App(Box::new(f), Box::new(arg)));
+        missing.into_iter().rev().fold(applied, |body, param| Term::Lambda(param, Box::new(body)))
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> Pat {
+        match &pattern.kind {
+            PatternKind::Var(name) if self.arities.contains_key(name) => Pat::Con(name.clone(), Vec::new()),
+            PatternKind::Var(name) => Pat::Var(name.clone()),
+            PatternKind::Wildcard => Pat::Wildcard,
+            PatternKind::As(name, inner) => Pat::As(name.name.clone(), Box::new(self.pattern(inner))),
+            PatternKind::Int(n) => Pat::Int(*n),
+            PatternKind::BigInt(digits) => Pat::Big(digits.clone()),
+            PatternKind::Num(x) => Pat::Num(*x),
+            PatternKind::Str(s) => list_pattern(s.chars().map(Pat::Char).collect()),
+            PatternKind::Tuple(items) => tuple_pattern(items.iter().map(|item| self.pattern(item)).collect()),
+            PatternKind::List(items) => list_pattern(items.iter().map(|item| self.pattern(item)).collect()),
+            PatternKind::Construct(c, args) => Pat::Con(c.name.clone(), args.iter().map(|arg| self.pattern(arg)).collect()),
+            PatternKind::BinOp(op, l, r) => Pat::Con(op.name.clone(), vec![tuple_pattern(vec![self.pattern(l), self.pattern(r)])]),
+        }
+    }
+}
+
+fn tuple(mut items: Vec<Term>) -> Term {
+    let last = items.pop().unwrap();
+    items.into_iter().rev().fold(last, |rest, item| Term::Con("#".to_owned(), vec![item, rest]))
+}
+
+fn list(items: Vec<Term>) -> Term {
+    items.into_iter().rev().fold(Term::Con("nil".to_owned(), Vec::new()), |rest, item| {
+        Term::Con("::".to_owned(), vec![Term::Con("#".to_owned(), vec![item, rest])])
+    })
+}
+
+fn tuple_pattern(mut items: Vec<Pat>) -> Pat {
+    let last = items.pop().unwrap();
+    items.into_iter().rev().fold(last, |rest, item| Pat::Con("#".to_owned(), vec![item, rest]))
+}
+
+fn list_pattern(items: Vec<Pat>) -> Pat {
+    items.into_iter().rev().fold(Pat::Con("nil".to_owned(), Vec::new()), |rest, item| {
+        Pat::Con("::".to_owned(), vec![Pat::Con("#".to_owned(), vec![item, rest])])
+    })
+}
+
+// Operators are written in brackets where they are used as names
+fn name(name: &str) -> String {
+    if name.starts_with(|c: char| c.is_alphanumeric() || c == '_' || c == '$') { name.to_owned() } else { format!("({})", name) }
+}
+
+impl fmt::Display for Def {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Def::Data { name: data, params, constructors } => {
+                let constructors: Vec<String> = constructors.iter().map(|(c, arity)| format!("{} {}", name(c), arity)).collect();
+                let params: String = params.iter().map(|p| format!(" {}", p)).collect();
+                write!(f, "data {}{} = {};", data, params, constructors.join(" | "))
+            }
+            Def::Dec(value, ty) => write!(f, "{} : {};", name(value), ty),
+            Def::Value(value, term) => write!(f, "{} = {};", name(value), Layout(term, 0)),
+            Def::Eval(term) => write!(f, "eval {};", Layout(term, 0)),
+            Def::Write(term) => write!(f, "write {};", Layout(term, 0)),
+        }
+    }
+}
+
+// A term at some depth of case alternatives, each of which goes on a line of its own
+struct Layout<'a>(&'a Term, usize);
+
+impl fmt::Display for Layout<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Layout(term, depth) = *self;
+        // Where a term goes in an application, which takes only what is atomic there
+        let atom = |term: &Term| match term {
+            Term::App(..) | Term::Lambda(..) | Term::Let { .. } | Term::Case(..) => format!("({})", Layout(term, depth)),
+            Term::Int(n) if *n < 0 => format!("({})", n),
+            Term::Num(x) if *x < 0.0 => format!("({})", x),
+            _ => Layout(term, depth).to_string(),
+        };
+        match term {
+            Term::Var(var) => write!(f, "{}", name(var)),
+            Term::Int(n) => write!(f, "{}", n),
+            Term::Big(digits) => write!(f, "{}", digits),
+            Term::Num(x) => write!(f, "{:?}", x),
+            Term::Char(c) => write!(f, "'{}'", c.escape_default()),
+            Term::Con(c, args) => {
+                let args: Vec<String> = args.iter().map(|arg| Layout(arg, depth).to_string()).collect();
+                write!(f, "{}[{}]", c, args.join(", "))
+            }
+            Term::App(g, arg) => match **g {
+                Term::App(..) => write!(f, "{} {}", Layout(g, depth), atom(arg)),
+                _ => write!(f, "{} {}", atom(g), atom(arg)),
+            },
+            Term::Lambda(param, body) => write!(f, "\\{}. {}", param, Layout(body, depth)),
+            Term::Let { rec, name: bound, value, body } => {
+                let keyword = if *rec { "letrec" } else { "let" };
+                write!(f, "{} {} = {} in {}", keyword, name(bound), Layout(value, depth), Layout(body, depth))
+            }
+            Term::Case(scrutinees, alts) => {
+                let scrutinees: Vec<String> = scrutinees.iter().map(|s| Layout(s, depth).to_string()).collect();
+                write!(f, "case {} of", scrutinees.join(", "))?;
+                let indent = "  ".repeat(depth + 1);
+                for (i, alt) in alts.iter().enumerate() {
+                    let patterns: Vec<String> = alt.patterns.iter().map(ToString::to_string).collect();
+                    write!(f, "\n{}{} {}", indent, if i == 0 { " " } else { "|" }, patterns.join(", "))?;
+                    if let Some(guard) = &alt.guard {
+                        write!(f, " if {}", Layout(guard, depth + 1))?;
+                    }
+                    write!(f, " -> {}", Layout(&alt.body, depth + 1))?;
+                }
+                write!(f, "\n{}end", "  ".repeat(depth))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pat::Var(var) => write!(f, "{}", name(var)),
+            Pat::Wildcard => write!(f, "_"),
+            Pat::Int(n) => write!(f, "{}", n),
+            Pat::Big(digits) => write!(f, "{}", digits),
+            Pat::Num(x) => write!(f, "{:?}", x),
+            Pat::Char(c) => write!(f, "'{}'", c.escape_default()),
+            Pat::Con(c, args) => {
+                let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+                write!(f, "{}[{}]", c, args.join(", "))
+            }
+            Pat::As(var, inner) => write!(f, "{} @ {}", name(var), inner),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::types::Checker;
+
+    fn core(source: &str) -> String {
+        let mut checker = Checker::new();
+        checker.check(crate::prelude::program()).unwrap();
+        let typed = checker.check(parser::parse_program(source).unwrap()).unwrap();
+        program(&typed.decls).iter().map(|def| format!("{}\n", def)).collect()
+    }
+
+    #[test]
+    fn should_make_equations_into_a_case_over_the_arguments() {
+        let source = "data shape == dot ++ box (num # num);\n\
+            dec area : shape -> num;\n--- area dot <= 0;\n--- area (box (w, h)) if w > 0 <= w * h;\n--- area _ <= 0;\n\
+            dec boxes : list num -> list shape;\n--- boxes l <= map (lambda n => box (n, n)) l;\n\
+            area (if true then dot else box (2, 3));";
+        assert_eq!(core(source), "data shape = dot 0 | box 1;\n\
+            area : shape -> num;\n\
+            area = \\$1. case $1 of\n    dot[] -> 0\n  | box[#[w, h]] if (>) #[w, 0] -> (*) #[w, h]\n  | _ -> 0\nend;\n\
+            boxes : list num -> list shape;\n\
+            boxes = \\l. map (\\n. box[#[n, n]]) l;\n\
+            eval area (case true[] of\n    true[] -> dot[]\n  | false[] -> box[#[2, 3]]\nend);\n");
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use crate::driver::{Diagnostic, Severity};
+use crate::source;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BLUE: &str = "\x1b[1;34m";
+
+// Renders diagnostics with the line they point at and a marker under their span:
+//
+//     error[E0201]: expected `;`, found TripleDash
+//      --> prog.hop:2:1
+//       |
+//     2 | --- x <= 1;
+//       | ^^^
+//
+// Sources are read the first time a diagnostic points into them
+#[derive(Debug, Default)]
+pub struct Renderer {
+    color: bool,
+    // None when the file couldn't be read, its diagnostics are then rendered without snippets
+    sources: HashMap<PathBuf, Option<String>>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Renderer::default()
+    }
+
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    // Use these contents for the file instead of reading it
+    pub fn with_source(mut self, path: impl Into<PathBuf>, contents: impl Into<String>) -> Self {
+        self.sources.insert(path.into(), Some(contents.into()));
+        self
+    }
+
+    pub fn render(&mut self, diagnostic: &Diagnostic) -> String {
+        let color = self.color;
+        let paint = |style: &str, text: &str| if color { format!("{}{}{}", style, text, RESET) } else { text.to_owned() };
+        let (label, style, marker) = match diagnostic.severity {
+            Severity::Error => ("error", "\x1b[1;31m", '^'),
+            Severity::Warning => ("warning", "\x1b[1;33m", '^'),
+            Severity::Note => ("note", "\x1b[1;32m", '-'),
+            Severity::Help => ("help", "\x1b[1;36m", '-'),
+        };
+
+        let label = match diagnostic.code {
+            Some(code) => format!("{}[{}]", label, code),
+            None => label.to_owned(),
+        };
+        let mut out = format!("{}{}\n", paint(style, &label), paint(BOLD, &format!(": {}", diagnostic.message)));
+        let Some(path) = &diagnostic.path else { return out };
+        let Some(pos) = &diagnostic.pos else {
+            writeln!(out, " {} {}", paint(BLUE, "-->"), source::display(path)).unwrap();
+            return out;
+        };
+
+        let number = pos.line.to_string();
+        let gutter = " ".repeat(number.len());
+        writeln!(out, "{}{} {}:{}:{}", gutter, paint(BLUE, "-->"), source::display(path), pos.line, pos.column).unwrap();
+        let Some(contents) = self.source(path) else { return out };
+        // A span past the end, like that of an unexpected end of input, marks the last character
+        let start = pos.range.start.min(contents.len());
+        let line_start = contents[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = contents[start..].find('\n').map_or(contents.len(), |i| start + i);
+        let line = contents[line_start..line_end].trim_end_matches('\r');
+
+        // Tabs are kept so the marker lines up however wide they are shown
+        let indent: String = contents[line_start..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let width = contents[start..pos.range.end.clamp(start, line_start + line.len())].chars().count().max(1);
+        let bar = paint(BLUE, "|");
+        writeln!(out, "{} {}", gutter, bar).unwrap();
+        writeln!(out, "{} {} {}", paint(BLUE, &number), bar, line).unwrap();
+        writeln!(out, "{} {} {}{}", gutter, bar, indent, paint(style, &marker.to_string().repeat(width))).unwrap();
+        out
+    }
+
+    // Renders each in turn, lining up the first words of the notes that follow a
+    // diagnostic on their own, like the expected and found of a failed assertion
+    pub fn render_all(&mut self, diagnostics: &[Diagnostic]) -> String {
+        let loose = |d: &Diagnostic| d.severity == Severity::Note && d.path.is_none() && d.pos.is_none();
+        let mut out = String::new();
+        let mut i = 0;
+        while i < diagnostics.len() {
+            let run = diagnostics[i..].iter().take_while(|d| loose(d)).count();
+            if run < 2 {
+                out.push_str(&self.render(&diagnostics[i]));
+                i += 1;
+                continue;
+            }
+            let notes = &diagnostics[i..i + run];
+            let first_word = |d: &Diagnostic| d.message.split(' ').next().unwrap_or_default().chars().count();
+            let width = notes.iter().map(first_word).max().unwrap_or(0);
+            for note in notes {
+                let message = format!("{}{}", " ".repeat(width - first_word(note)), note.message);
+                out.push_str(&self.render(&Diagnostic { message, ..note.clone() }));
+            }
+            i += run;
+        }
+        out
+    }
+
+    fn source(&mut self, path: &Path) -> Option<&str> {
+        self.sources.entry(path.to_path_buf()).or_insert_with(|| source::read(path).ok()).as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::token::Pos;
+
+    #[test]
+    fn should_mark_the_span_under_its_line() {
+        let mut renderer = Renderer::new().with_source("prog.hop", "dec x : num;\n--- x <= true;\n");
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            path: Some(PathBuf::from("prog.hop")),
+            pos: Some(Pos { line: 2, column: 10, range: 22..26 }),
+            code: Some("E0301"),
+            message: "expected num, found truval".to_owned(),
+        };
+        assert_eq!(renderer.render(&diagnostic), concat!(
+            "error[E0301]: expected num, found truval\n",
+            " --> prog.hop:2:10\n",
+            "  |\n",
+            "2 | --- x <= true;\n",
+            "  |          ^^^^\n",
+        ));
+
+        let eof = Diagnostic { severity: Severity::Note, code: None, pos: Some(Pos { line: 3, column: 1, range: 28..28 }), ..diagnostic };
+        assert!(renderer.render(&eof).ends_with("3 | \n  | -\n"));
+    }
+
+    #[test]
+    fn should_line_up_the_notes_after_a_diagnostic() {
+        let note = |message: &str| Diagnostic { severity: Severity::Note, path: None, pos: None, code: None, message: message.to_owned() };
+        let failed = Diagnostic { severity: Severity::Error, code: Some("E0414"), ..note("assertion failed") };
+        let rendered = Renderer::new().render_all(&[failed, note("expected `1`"), note("found `2`")]);
+        assert_eq!(rendered, "error[E0414]: assertion failed\nnote: expected `1`\nnote:    found `2`\n");
+    }
+}
@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+use crate::driver::{Driver, RunOutcome, Severity};
+
+// The ways of running a program that ought to make no difference to what it does,
+// each compared with an ordinary run by `hope test --differential`. They may take
+// more or fewer steps, sharing most of all, so steps are only held to a bound if
+// one is given
+pub const WAYS: [Way; 3] = [
+    ("without sharing", |driver| driver.with_sharing(false)),
+    ("without the native prelude", |driver| driver.with_native_prelude(false)),
+    ("optimized", |driver| driver.with_optimization(true, None)),
+];
+
+pub type Way = (&'static str, fn(Driver) -> Driver);
+
+// What a run did, as far as any way of running it has to agree: what it wrote, its
+// value, and the codes of its errors in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Behaviour {
+    pub stdout: String,
+    pub value: Option<String>,
+    pub errors: Vec<&'static str>,
+}
+
+impl Behaviour {
+    pub fn of(outcome: &RunOutcome) -> Self {
+        let errors = outcome.diagnostics.iter().filter(|d| d.severity == Severity::Error);
+        Behaviour {
+            stdout: outcome.stdout.clone(),
+            value: outcome.value.as_ref().map(|value| value.to_string()),
+            errors: errors.map(|d| d.code.unwrap_or("-")).collect(),
+        }
+    }
+}
+
+// Each way the files run differently from the driver as it is, and how. With a bound,
+// a way taking more than that many times the steps of the ordinary run differs too
+pub fn compare(driver: &Driver, paths: &[PathBuf], bound: Option<u64>) -> Vec<String> {
+    let expected = driver.run(paths);
+    let behaviour = Behaviour::of(&expected);
+    let mut differences = Vec::new();
+    for (way, configure) in WAYS {
+        let found = configure(driver.clone()).run(paths);
+        let other = Behaviour::of(&found);
+        if other.errors != behaviour.errors {
+            differences.push(format!("{} it fails with [{}] instead of [{}]", way, other.errors.join(", "), behaviour.errors.join(", ")));
+        } else if other.stdout != behaviour.stdout {
+            differences.push(format!("{} it writes {:?} instead of {:?}", way, other.stdout, behaviour.stdout));
+        } else if other.value != behaviour.value {
+            let shown = |value: &Option<String>| value.clone().unwrap_or_else(|| "nothing".to_owned());
+            differences.push(format!("{} its value is {} instead of {}", way, shown(&other.value), shown(&behaviour.value)));
+        }
+        let most = bound.map(|bound| bound.saturating_mul(expected.stats.steps.max(1)));
+        if let Some(most) = most.filter(|most| found.stats.steps > *most) {
+            differences.push(format!("{} it takes {} steps, more than {}", way, found.stats.steps, most));
+        }
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::*;
+
+    // The examples and the golden outputs, some of which fail on purpose
+    #[test]
+    fn should_run_the_corpus_alike_every_way() {
+        let corpus = crate::source::discover(&["../examples/*.hop", "tests/golden/*.hop"]).unwrap();
+        assert!(corpus.len() > 5);
+        let driver = Driver::new().with_prelude(true);
+        for path in &corpus {
+            assert_eq!(compare(&driver, std::slice::from_ref(path), Some(20)), Vec::<String>::new(), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn should_say_how_a_way_differs() {
+        let path = Path::new("<differential>/slow.hop");
+        let source = "dec count : num -> num;\n--- count 0 <= 0;\n--- count n <= 1 + count (n - 1);\n\
+                      dec big : num;\n--- big <= count 1000;\nbig + big + big + big;";
+        let driver = Driver::new().with_prelude(true).with_source(path, source.to_owned());
+        assert_eq!(compare(&driver, &[path.to_path_buf()], None), Vec::<String>::new());
+        let differences = compare(&driver, &[path.to_path_buf()], Some(2));
+        assert_eq!(differences.len(), 1);
+        assert!(differences[0].starts_with("without sharing it takes "), "{}", differences[0]);
+    }
+}
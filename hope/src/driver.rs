@@ -0,0 +1,931 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use crate::backend::{self, Artifact, Backend};
+use crate::cancel::Cancel;
+use crate::eval::{Builtins, FileCoverage, Interpreter, Limits, Meter, Profile, Snapshot, Trace, Value, DEFAULT_MEMO_CAPACITY};
+use crate::modules::{Exports, Loader, ModuleError};
+use crate::parser::{self, ParseError};
+use crate::prelude;
+use crate::sandbox::{Sandbox, Violation};
+use crate::source;
+use crate::trace;
+use crate::syntax::ast::{Decl, DeclKind, Program};
+use crate::syntax::token::{Dialect, Pos};
+use crate::types::{Checker, TypeError, TypedProgram};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // Missing for problems that aren't in any one file, like an unknown entry point
+    pub path: Option<PathBuf>,
+    pub pos: Option<Pos>,
+    // Which problem it is, like E0301 for a type mismatch. Notes and help have none
+    pub code: Option<&'static str>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, path: &Path, pos: Option<&Pos>, message: impl fmt::Display) -> Self {
+        Diagnostic { severity, path: Some(path.to_path_buf()), pos: pos.cloned(), code: None, message: message.to_string() }
+    }
+
+    fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+}
+
+// `path:line:column: message`, with the severity before the message unless it is an error
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.path, &self.pos) {
+            (Some(path), Some(pos)) => write!(f, "{}:{}:{}: ", source::display(path), pos.line, pos.column)?,
+            (Some(path), None) => write!(f, "{}: ", source::display(path))?,
+            (None, _) => {}
+        }
+        match self.severity {
+            Severity::Error => {}
+            Severity::Warning => write!(f, "warning: ")?,
+            Severity::Note => write!(f, "note: ")?,
+            Severity::Help => write!(f, "help: ")?,
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+// The stage a program stopped at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    // Including finding the modules they use
+    Read,
+    Parse,
+    Check,
+    Eval,
+    // By a backend, see Driver::generate
+    Generate,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    #[default]
+    Success,
+    Failed(Stage),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunStats {
+    pub files: usize,
+    pub decls: usize,
+    // Evaluation steps, as counted against Limits::steps
+    pub steps: u64,
+    pub elapsed: Duration,
+}
+
+// Everything a run produced. stdout is what `hope run` prints: each `write`, then the
+// final value
+#[derive(Debug, Clone, Default)]
+pub struct RunOutcome {
+    pub stdout: String,
+    pub written: Vec<Value>,
+    pub value: Option<Value>,
+    pub diagnostics: Vec<Diagnostic>,
+    // Each file that checked, with its declarations' types
+    pub typed: Vec<(PathBuf, TypedProgram)>,
+    pub stats: RunStats,
+    pub status: Status,
+    // With coverage on, what ran of each file with equations, but the prelude
+    pub coverage: Vec<(PathBuf, FileCoverage)>,
+    // What the files tried that the sandbox doesn't allow, each also a diagnostic
+    pub violations: Vec<Violation>,
+    // With profiling on, the calls made at each site
+    pub profile: Option<Profile>,
+    // With recording on, every call and what it returned
+    pub trace: Option<Trace>,
+    // With a step to take one at, what the heap held then
+    pub heap: Option<Snapshot>,
+}
+
+impl RunOutcome {
+    pub fn succeeded(&self) -> bool {
+        self.status == Status::Success
+    }
+
+    fn fail(&mut self, stage: Stage) {
+        self.status = Status::Failed(stage);
+    }
+
+    // What a run of other files gave, after what this one did. The first failure is
+    // the one that counts
+    pub fn merge(&mut self, other: RunOutcome) {
+        self.stdout.push_str(&other.stdout);
+        self.written.extend(other.written);
+        self.value = other.value.or(self.value.take());
+        self.diagnostics.extend(other.diagnostics);
+        self.typed.extend(other.typed);
+        self.stats.files += other.stats.files;
+        self.stats.decls += other.stats.decls;
+        self.stats.steps += other.stats.steps;
+        self.stats.elapsed += other.stats.elapsed;
+        if self.succeeded() {
+            self.status = other.status;
+        }
+        self.coverage.extend(other.coverage);
+        self.violations.extend(other.violations);
+        match (&mut self.profile, other.profile) {
+            (Some(mine), Some(theirs)) => mine.merge(&theirs),
+            (mine, theirs) => *mine = theirs.or(mine.take()),
+        }
+        // A trace or a snapshot is of one run, the latest
+        self.trace = other.trace.or(self.trace.take());
+        self.heap = other.heap.or(self.heap.take());
+    }
+}
+
+// What the files are written in. Miranda-lite scripts, see parser::miranda, are read
+// into the same declarations, and the prelude and the modules they use are still Hope
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Frontend {
+    #[default]
+    Hope,
+    MirandaLite,
+}
+
+// Parses, checks and runs files as one program, the way the command line does
+#[derive(Debug, Clone, Default)]
+pub struct Driver {
+    lenient_semicolons: bool,
+    comprehensions: bool,
+    macros: bool,
+    strict_numerics: bool,
+    no_sharing: bool,
+    no_native_prelude: bool,
+    memo_capacity: Option<usize>,
+    coverage: bool,
+    profiling: bool,
+    recording: bool,
+    heap_snapshot: Option<u64>,
+    optimize: bool,
+    guide: Option<Profile>,
+    dialect: Dialect,
+    frontend: Frontend,
+    entry: Option<String>,
+    limits: Limits,
+    meter: Option<Meter>,
+    modules: Loader,
+    prelude: bool,
+    builtins: Builtins,
+    sandbox: Option<Sandbox>,
+    cancel: Option<Cancel>,
+    // Besides backend::BUILT_IN, and before them
+    backends: Vec<Rc<dyn Backend>>,
+    // Contents to use instead of reading these files, as source::read gives them
+    sources: Vec<(PathBuf, String)>,
+}
+
+impl Driver {
+    pub fn new() -> Self {
+        Driver::default()
+    }
+
+    pub fn with_lenient_semicolons(mut self, lenient: bool) -> Self {
+        self.lenient_semicolons = lenient;
+        self
+    }
+
+    pub fn with_comprehensions(mut self, comprehensions: bool) -> Self {
+        self.comprehensions = comprehensions;
+        self
+    }
+
+    pub fn with_macros(mut self, macros: bool) -> Self {
+        self.macros = macros;
+        self
+    }
+
+    pub fn with_strict_numerics(mut self, strict: bool) -> Self {
+        self.strict_numerics = strict;
+        self
+    }
+
+    // See Interpreter::with_sharing
+    pub fn with_sharing(mut self, sharing: bool) -> Self {
+        self.no_sharing = !sharing;
+        self
+    }
+
+    // See Interpreter::with_native_prelude
+    pub fn with_native_prelude(mut self, native: bool) -> Self {
+        self.no_native_prelude = !native;
+        self
+    }
+
+    // See Interpreter::with_memo_capacity, None for the default
+    pub fn with_memo_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.memo_capacity = capacity;
+        self
+    }
+
+    // Whether runs count how often each equation and `if` ran, see RunOutcome::coverage
+    pub fn with_coverage(mut self, coverage: bool) -> Self {
+        self.coverage = coverage;
+        self
+    }
+
+    // Whether runs count the calls at each site, see RunOutcome::profile
+    pub fn with_profiling(mut self, profiling: bool) -> Self {
+        self.profiling = profiling;
+        self
+    }
+
+    // Whether runs record every call, see RunOutcome::trace
+    pub fn with_recording(mut self, recording: bool) -> Self {
+        self.recording = recording;
+        self
+    }
+
+    // The step to take a snapshot of the heap at, see RunOutcome::heap
+    pub fn with_heap_snapshot(mut self, at: Option<u64>) -> Self {
+        self.heap_snapshot = at;
+        self
+    }
+
+    // See Interpreter::with_optimization and with_guide
+    pub fn with_optimization(mut self, optimize: bool, guide: Option<Profile>) -> Self {
+        self.optimize = optimize;
+        self.guide = guide;
+        self
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn with_frontend(mut self, frontend: Frontend) -> Self {
+        self.frontend = frontend;
+        self
+    }
+
+    // Runs to the value of this definition instead of the last top level expression
+    pub fn with_entry(mut self, entry: Option<String>) -> Self {
+        self.entry = entry;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // See Interpreter::with_meter
+    pub fn with_meter(mut self, meter: Meter) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    // Gives up lexing, parsing and checking once cancel is, failing the stage it was in
+    pub fn with_cancel(mut self, cancel: Cancel) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    // Where to find the modules the files use
+    pub fn with_modules(mut self, modules: Loader) -> Self {
+        self.modules = modules;
+        self
+    }
+
+    // Whether the programs start from the standard prelude, as if every one used it
+    pub fn with_prelude(mut self, prelude: bool) -> Self {
+        self.prelude = prelude;
+        self
+    }
+
+    // Host functions the programs can call
+    pub fn with_builtins(mut self, builtins: Builtins) -> Self {
+        self.builtins = builtins;
+        self
+    }
+
+    // Runs within the sandbox's limits, which a later with_limits replaces, and fail
+    // before evaluating anything if the files do what it doesn't allow
+    pub fn with_sandbox(mut self, sandbox: &Sandbox) -> Self {
+        self.limits = sandbox.limits;
+        self.sandbox = Some(*sandbox);
+        self
+    }
+
+    // The host functions the programs are given, none if the sandbox doesn't allow them
+    fn host(&self) -> Builtins {
+        match self.sandbox {
+            Some(sandbox) if !sandbox.host => Builtins::new(),
+            _ => self.builtins.clone(),
+        }
+    }
+
+    // Runs use these contents for the file instead of what is on disk, wherever it is
+    // found from
+    pub fn with_source(mut self, path: &Path, contents: String) -> Self {
+        let path = source::canonical(path);
+        self.sources.retain(|(seen, _)| *seen != path);
+        self.sources.push((path, contents));
+        self
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<String> {
+        if !self.sources.is_empty() {
+            let path = source::canonical(path);
+            if let Some((_, contents)) = self.sources.iter().find(|(seen, _)| *seen == path) {
+                return Ok(contents.clone());
+            }
+        }
+        source::read(path)
+    }
+
+    // Adds any problems with the file itself to diagnostics
+    pub fn parse_file(&self, path: &Path, diagnostics: &mut Vec<Diagnostic>) -> Result<Program, Stage> {
+        self.parse_file_with(path, &[], diagnostics)
+    }
+
+    // With the operators and syntax the files before it declared
+    fn parse_file_with(&self, path: &Path, notation: &[Decl], diagnostics: &mut Vec<Diagnostic>) -> Result<Program, Stage> {
+        let _span = trace::span("hope::parser", "parse", "file", source::display(path));
+        let contents = self.read(path).map_err(|e| {
+            diagnostics.push(Diagnostic::new(Severity::Error, path, None, source::describe(&e)));
+            Stage::Read
+        })?;
+        if self.frontend == Frontend::MirandaLite {
+            return parser::miranda::parse_script(&contents).map_err(|e| parse_error(path, &e, diagnostics));
+        }
+
+        let parser = match &self.cancel {
+            Some(cancel) => parser::Parser::cancellable(&contents, cancel),
+            None => parser::Parser::new(&contents),
+        };
+        let mut parser = match parser {
+            Ok(parser) => parser
+                .with_lenient_semicolons(self.lenient_semicolons)
+                .with_comprehensions(self.comprehensions)
+                .with_macros(self.macros)
+                .with_dialect(self.dialect)
+                .with_notation(notation),
+            Err(e) => return Err(parse_error(path, &e, diagnostics)),
+        };
+        let parsed = parser.parse_program();
+        for warning in parser.warnings() {
+            diagnostics.push(Diagnostic::new(Severity::Warning, path, Some(warning.pos()), warning).with_code(warning.code()));
+            let fix = format!("insert `{}` here", warning.fix());
+            diagnostics.push(Diagnostic::new(Severity::Help, path, Some(warning.pos()), fix));
+        }
+        parsed.map_err(|e| parse_error(path, &e, diagnostics))
+    }
+
+    // Each file can use what the ones before it declared. The modules each one uses come
+    // before it, and are only checked once
+    pub fn check(&self, paths: &[PathBuf]) -> RunOutcome {
+        let start = Instant::now();
+        let mut outcome = RunOutcome::default();
+        self.check_into(paths, &mut outcome);
+        outcome.stats.elapsed = start.elapsed();
+        outcome
+    }
+
+    // Adds a backend that generate can use, which hides any of the same name
+    pub fn with_backend(mut self, backend: impl Backend + 'static) -> Self {
+        self.backends.insert(0, Rc::new(backend));
+        self
+    }
+
+    // The names generate takes
+    pub fn backends(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for name in self.backends.iter().map(|backend| backend.name()).chain(backend::BUILT_IN.map(|backend| backend.name())) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    // Checks the files, then gives them to the backend with that name
+    pub fn generate(&self, paths: &[PathBuf], name: &str) -> (RunOutcome, Vec<Artifact>) {
+        let start = Instant::now();
+        let mut outcome = RunOutcome::default();
+        let registered = self.backends.iter().map(|backend| &**backend);
+        let Some(chosen) = registered.chain(backend::BUILT_IN).find(|backend| backend.name() == name) else {
+            let names = self.backends();
+            let (last, rest) = names.split_last().expect("there are built in backends");
+            let message = format!("there is no backend `{}`, only {} and {}", name, rest.join(", "), last);
+            outcome.diagnostics.push(Diagnostic { severity: Severity::Error, path: None, pos: None, code: None, message });
+            outcome.fail(Stage::Generate);
+            return (outcome, Vec::new());
+        };
+        let mut artifacts = Vec::new();
+        if self.check_into(paths, &mut outcome).is_some() {
+            let _span = trace::span("hope::backend", "generate", "backend", name);
+            let units = backend::units(&outcome.typed);
+            let mut diagnostics = Vec::new();
+            artifacts = chosen.generate(&units, &mut diagnostics);
+            if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+                outcome.fail(Stage::Generate);
+            }
+            outcome.diagnostics.extend(diagnostics);
+        }
+        outcome.stats.elapsed = start.elapsed();
+        (outcome, artifacts)
+    }
+
+    // Evaluates the programs, collecting each `write` and then the value of the entry
+    // point, or of the last top level expression if there isn't one
+    pub fn run(&self, paths: &[PathBuf]) -> RunOutcome {
+        let start = Instant::now();
+        let mut outcome = RunOutcome::default();
+        if let Some(programs) = self.check_into(paths, &mut outcome) {
+            self.eval(&programs, &mut outcome);
+        }
+        outcome.stats.elapsed = start.elapsed();
+        outcome
+    }
+
+    fn check_into(&self, paths: &[PathBuf], outcome: &mut RunOutcome) -> Option<Vec<(PathBuf, Program)>> {
+        let mut modules = if self.prelude { self.modules.clone().with_provided(prelude::MODULE) } else { self.modules.clone() };
+        if let Some(sandbox) = self.sandbox {
+            modules = modules.with_access(sandbox.modules);
+        }
+        let span = trace::span("hope::modules", "order", "files", paths.len());
+        let paths = match modules.order(paths) {
+            Ok(paths) => paths,
+            Err(e) => {
+                let diagnostic = Diagnostic::new(Severity::Error, e.path(), Some(e.pos()), &e);
+                outcome.diagnostics.push(diagnostic.with_code(e.code()));
+                outcome.violations.extend(Violation::from_module(&e));
+                outcome.fail(Stage::Read);
+                return None;
+            }
+        };
+        drop(span);
+        let host = self.host();
+        let mut checker = Checker::new().with_builtins(&host);
+        let mut exports = Exports::new();
+        let mut programs = Vec::new();
+        let mut notation = Vec::new();
+        // The prelude isn't counted or reported on, it is part of the language
+        if self.prelude {
+            let program = prelude::program();
+            checker.check(program.clone()).expect("the prelude checks");
+            notation.extend(parser::notation(&program.decls));
+            programs.push((PathBuf::from(prelude::FILE), program));
+        }
+        if let Some(cancel) = &self.cancel {
+            checker = checker.with_cancel(cancel.clone());
+        }
+        for path in &paths {
+            let program = self.parse_file_with(path, &notation, &mut outcome.diagnostics)
+                .map_err(|stage| outcome.fail(stage))
+                .ok()?;
+            notation.extend(parser::notation(&program.decls));
+            outcome.stats.files += 1;
+            outcome.stats.decls += program.decls.len();
+            if self.sandbox.is_some_and(|sandbox| !sandbox.write) {
+                let writes = program.decls.iter().filter(|decl| matches!(decl.kind, DeclKind::Write(_)));
+                let writes: Vec<_> = writes.map(|decl| Violation::Write { path: path.clone(), pos: decl.pos.clone() }).collect();
+                if !writes.is_empty() {
+                    self.violate(writes, outcome);
+                    return None;
+                }
+            }
+            if let Err(e) = exports.add(&modules, path, &program) {
+                ambiguous(&e, &mut outcome.diagnostics);
+                outcome.fail(Stage::Check);
+                return None;
+            }
+            let span = trace::span("hope::types", "check", "file", source::display(path));
+            let checked = checker.check(program.clone());
+            drop(span);
+            match checked {
+                Ok(typed) => {
+                    for warning in &typed.warnings {
+                        let diagnostic = Diagnostic::new(Severity::Warning, path, Some(warning.pos()), warning);
+                        outcome.diagnostics.push(diagnostic.with_code(warning.code()));
+                    }
+                    outcome.typed.push((path.clone(), typed));
+                }
+                Err(errors) => {
+                    // Names that are only unknown because the sandbox left out the host's functions
+                    let hidden: Vec<_> = errors.iter().filter_map(|e| match e {
+                        TypeError::UnknownVariable(name, pos) if self.builtins.iter().any(|f| f.name == *name) =>
+                            Some(Violation::Host { path: path.clone(), name: name.clone(), pos: pos.clone() }),
+                        _ => None,
+                    }).collect();
+                    if !hidden.is_empty() {
+                        self.violate(hidden, outcome);
+                        return None;
+                    }
+                    for e in &errors {
+                        outcome.diagnostics.push(Diagnostic::new(Severity::Error, path, Some(e.pos()), e).with_code(e.code()));
+                        if let Some(first) = e.first_declared() {
+                            outcome.diagnostics.push(Diagnostic::new(Severity::Note, path, Some(first), "first declared here"));
+                            let rule = "a name can be declared once in each module, only a later module can declare it again and hide it";
+                            outcome.diagnostics.push(Diagnostic { severity: Severity::Note, path: None, pos: None, code: None, message: rule.to_owned() });
+                        }
+                    }
+                    outcome.fail(Stage::Check);
+                    return None;
+                }
+            }
+            programs.push((path.clone(), program));
+        }
+        Some(programs)
+    }
+
+    fn violate(&self, violations: Vec<Violation>, outcome: &mut RunOutcome) {
+        for violation in &violations {
+            let diagnostic = Diagnostic::new(Severity::Error, violation.path(), Some(violation.pos()), violation);
+            outcome.diagnostics.push(diagnostic.with_code(violation.code()));
+        }
+        outcome.violations.extend(violations);
+        outcome.fail(Stage::Check);
+    }
+
+    fn eval(&self, programs: &[(PathBuf, Program)], outcome: &mut RunOutcome) {
+        let mut interp = Interpreter::new()
+            .with_limits(self.limits)
+            .with_meter(self.meter.clone())
+            .with_strict_numerics(self.strict_numerics)
+            .with_sharing(!self.no_sharing)
+            .with_native_prelude(!self.no_native_prelude)
+            .with_memo_capacity(self.memo_capacity.unwrap_or(DEFAULT_MEMO_CAPACITY))
+            .with_builtins(&self.host())
+            .with_coverage(self.coverage)
+            .with_profiling(self.profiling)
+            .with_recording(self.recording)
+            .with_heap_snapshot(self.heap_snapshot)
+            .with_optimization(self.optimize)
+            .with_guide(self.guide.clone());
+        self.eval_with(&mut interp, programs, outcome);
+        outcome.stats.steps = interp.steps();
+        outcome.profile = interp.profile();
+        outcome.trace = interp.trace();
+        outcome.heap = interp.heap_snapshot();
+        if let Some(coverage) = interp.coverage() {
+            let files = programs.iter().filter(|(path, _)| path != Path::new(prelude::FILE));
+            let files = files.map(|(path, program)| (path.clone(), coverage.of(program)));
+            outcome.coverage = files.filter(|(_, runs)| !runs.functions.is_empty()).collect();
+        }
+    }
+
+    fn eval_with(&self, interp: &mut Interpreter, programs: &[(PathBuf, Program)], outcome: &mut RunOutcome) {
+        interp.start_clock();
+        let mut last = None;
+        for (path, program) in programs {
+            let _span = trace::span("hope::eval", "eval", "file", source::display(path));
+            let load = trace::span("hope::eval", "load", "file", source::display(path));
+            interp.load(program);
+            drop(load);
+            for decl in &program.decls {
+                let result = match &decl.kind {
+                    DeclKind::Write(expr) => interp.eval(expr).map(|value| {
+                        outcome.stdout.push_str(&format!("{}\n", value));
+                        outcome.written.push(value);
+                    }),
+                    DeclKind::Expr(expr) if self.entry.is_none() => interp.eval(expr).map(|value| last = Some(value)),
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    outcome.diagnostics.push(Diagnostic::new(Severity::Error, path, e.pos(), &e).with_code(e.code()));
+                    for message in e.notes() {
+                        outcome.diagnostics.push(Diagnostic { severity: Severity::Note, path: None, pos: None, code: None, message });
+                    }
+                    return outcome.fail(Stage::Eval);
+                }
+            }
+        }
+
+        if let Some(name) = &self.entry {
+            match interp.entry(name) {
+                Ok(value) => last = Some(value),
+                Err(e) => {
+                    let (code, message) = (Some(e.code()), e.to_string());
+                    outcome.diagnostics.push(Diagnostic { severity: Severity::Error, path: None, pos: None, code, message });
+                    return outcome.fail(Stage::Eval);
+                }
+            }
+        }
+        if let Some(value) = &last {
+            outcome.stdout.push_str(&format!("{}\n", value));
+        }
+        outcome.value = last;
+    }
+}
+
+fn parse_error(path: &Path, e: &ParseError, diagnostics: &mut Vec<Diagnostic>) -> Stage {
+    diagnostics.push(Diagnostic::new(Severity::Error, path, Some(e.pos()), e).with_code(e.code()));
+    if let Some((opener, open)) = e.opened_at() {
+        diagnostics.push(Diagnostic::new(Severity::Note, path, Some(open), format!("{} opened here", opener)));
+    }
+    if let Some(help) = e.help() {
+        diagnostics.push(Diagnostic::new(Severity::Help, path, Some(e.pos()), help));
+    }
+    Stage::Parse
+}
+
+fn ambiguous(e: &ModuleError, diagnostics: &mut Vec<Diagnostic>) {
+    diagnostics.push(Diagnostic::new(Severity::Error, e.path(), Some(e.pos()), e).with_code(e.code()));
+    if let ModuleError::Ambiguous { name, declared, .. } = e {
+        for (module, path, pos) in declared.iter() {
+            diagnostics.push(Diagnostic::new(Severity::Note, path, Some(pos), format!("`{}` declared in `{}` here", name, module)));
+        }
+        let rule = format!("a module used later hides what one used earlier declares, so `{}` would be the one in `{}`. \
+            Declaring it in this file hides both", name, declared[1].0);
+        diagnostics.push(Diagnostic { severity: Severity::Note, path: None, pos: None, code: None, message: rule });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_file<T>(name: &str, contents: &str, f: impl FnOnce(&[PathBuf]) -> T) -> T {
+        let path = std::env::temp_dir().join(format!("hope-driver-{}-{}.hop", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        let result = f(std::slice::from_ref(&path));
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn should_collect_what_a_run_produced() {
+        let source = "dec x : num;\n--- x <= 41;\nwrite x + 1;\nwrite [x];\nx * 2;";
+        let outcome = with_file("run", source, |paths| Driver::new().run(paths));
+        assert!(outcome.succeeded());
+        assert_eq!(outcome.written.iter().map(Value::to_string).collect::<Vec<_>>(), ["42", "[41]"]);
+        assert_eq!(outcome.value.as_ref().map(Value::to_string).as_deref(), Some("82"));
+        assert_eq!(outcome.stdout, "42\n[41]\n82\n");
+        assert_eq!((outcome.stats.files, outcome.stats.decls), (1, 5));
+        assert!(outcome.stats.steps > 0);
+
+        let outcome = with_file("entry", source, |paths| Driver::new().with_entry(Some("y".to_owned())).run(paths));
+        assert_eq!(outcome.status, Status::Failed(Stage::Eval));
+        assert_eq!(outcome.stdout, "42\n[41]\n");
+    }
+
+    #[test]
+    fn should_run_miranda_lite_scripts_with_the_prelude() {
+        let path = Path::new("<driver>/course.m");
+        let script = "tree * ::= Leaf | Node (tree *) * (tree *)\n\
+                      \n\
+                      insert :: num -> tree num -> tree num\n\
+                      insert x Leaf = Node Leaf x Leaf\n\
+                      insert x (Node l y r) = Node (insert x l) y r, if x < y\n\
+                      \x20                     = Node l y (insert x r), otherwise\n\
+                      \n\
+                      flatten :: tree * -> [*]\n\
+                      flatten Leaf = []\n\
+                      flatten (Node l x r) = flatten l ++ [x] ++ flatten r\n\
+                      \n\
+                      squares :: [num] -> num\n\
+                      squares xs = total [x * x | x <- xs; x ~= 2]\n\
+                      \x20            where total [] = 0\n\
+                      \x20                  total (y:ys) = y + total ys\n\
+                      main = (flatten (insert 1 (insert 3 (insert 2 Leaf))), squares [1, 3], #\"four\")\n";
+        let driver = Driver::new().with_prelude(true).with_frontend(Frontend::MirandaLite).with_source(path, script.to_owned());
+        let outcome = driver.run(&[path.to_path_buf()]);
+        assert_eq!(outcome.value.as_ref().map(Value::to_string).as_deref(), Some("([1, 2, 3], 10, 4)"), "{:?}", outcome.diagnostics);
+    }
+
+    #[test]
+    fn should_give_up_lexing_and_parsing_once_cancelled() {
+        let path = Path::new("<driver>/cancel.hop");
+        let cancel = Cancel::new();
+        let source: String = (0..100).map(|i| format!("dec x{} : num;\n--- x{} <= {};\n", i, i, i)).collect();
+        let driver = Driver::new().with_cancel(cancel.clone()).with_source(path, source);
+        assert!(driver.check(&[path.to_path_buf()]).succeeded());
+        cancel.cancel();
+        let outcome = driver.check(&[path.to_path_buf()]);
+        assert_eq!(outcome.status, Status::Failed(Stage::Parse));
+        assert_eq!(outcome.diagnostics[0].code, Some("E0209"));
+        assert_eq!(outcome.diagnostics[0].message, "parsing was cancelled");
+
+        assert!(matches!(parser::Parser::cancellable("dec x : num;", &cancel), Err(ParseError::Cancelled(_))));
+    }
+
+    #[test]
+    fn should_work_out_constants_once_unless_asked_not_to() {
+        let source = "dec count : num -> num;\n--- count 0 <= 0;\n--- count n <= 1 + count (n - 1);\n\
+                      dec big : num;\n--- big <= count 1000;\nwrite big;\nwrite big + 1;\nbig * 2;";
+        let (shared, unshared) = with_file("sharing", source, |paths| {
+            (Driver::new().run(paths), Driver::new().with_sharing(false).run(paths))
+        });
+        assert_eq!(shared.stdout, "1000\n1001\n2000\n");
+        assert_eq!(unshared.stdout, shared.stdout);
+        assert!(shared.stats.steps * 2 < unshared.stats.steps, "{} {}", shared.stats.steps, unshared.stats.steps);
+    }
+
+    #[test]
+    fn should_report_the_failing_stage() {
+        let outcome = with_file("check", "dec x : num;\n--- x <= true;", |paths| Driver::new().check(paths));
+        assert_eq!(outcome.status, Status::Failed(Stage::Check));
+        assert_eq!(outcome.diagnostics[0].pos.as_ref().map(|pos| (pos.line, pos.column)), Some((2, 10)));
+        assert_eq!(outcome.diagnostics[0].code, Some("E0301"));
+
+        let outcome = with_file("parse", "dec x : num\n--- x <= 1;", |paths| Driver::new().run(paths));
+        assert_eq!(outcome.status, Status::Failed(Stage::Parse));
+        let outcome = Driver::new().run(&[PathBuf::from("/nonexistent/file.hop")]);
+        assert_eq!(outcome.status, Status::Failed(Stage::Read));
+    }
+
+    #[test]
+    fn should_run_list_comprehensions_when_enabled() {
+        let source = "[(x, y) | x <- [1, 2, 3], x > 1, y <- [x, 10]];";
+        let outcome = with_file("comprehension", source, |paths| Driver::new().with_comprehensions(true).run(paths));
+        assert_eq!(outcome.value.as_ref().map(Value::to_string).as_deref(), Some("[(2, 2), (2, 10), (3, 3), (3, 10)]"));
+
+        // A constructor in a generator's pattern skips the elements it doesn't match
+        let source = "data shape == circle ++ square;\n[1 | true <- [true, false, true]];\n[0 | square <- [circle, square]];";
+        let outcome = with_file("comprehension-constructors", source, |paths| Driver::new().with_comprehensions(true).run(paths));
+        assert_eq!(outcome.value.as_ref().map(Value::to_string).as_deref(), Some("[0]"));
+        let source = "[1 | true <- [true, false, true]];";
+        let outcome = with_file("comprehension-truths", source, |paths| Driver::new().with_comprehensions(true).run(paths));
+        assert_eq!(outcome.value.as_ref().map(Value::to_string).as_deref(), Some("[1, 1]"));
+
+        // Type errors point at the part of the comprehension that disagrees, here the list
+        // the guard makes out to be of truth values
+        let source = "[x | x <- [1, 2], x];";
+        let outcome = with_file("comprehension-check", source, |paths| Driver::new().with_comprehensions(true).check(paths));
+        assert_eq!(outcome.status, Status::Failed(Stage::Check));
+        assert_eq!(outcome.diagnostics[0].pos.as_ref().map(|pos| pos.column), Some(11));
+    }
+
+    #[test]
+    fn should_resolve_free_names_in_syntax_where_they_were_defined() {
+        let source = "dec x : num;\n--- x <= 5;\nsyntax twice e <= let y == e in y + x;\n\
+                      dec f : num -> num;\n--- f x <= twice x;\n\
+                      (let x == 100 in twice 1, twice 1 where x == 100, f 100);";
+        let outcome = with_file("syntax-hygiene", source, |paths| Driver::new().with_macros(true).run(paths));
+        assert_eq!(outcome.value.as_ref().map(Value::to_string).as_deref(), Some("(6, 6, 105)"), "{:?}", outcome.diagnostics);
+    }
+
+    #[test]
+    fn should_check_used_modules_first_and_once() {
+        let dir = std::env::temp_dir().join(format!("hope-driver-{}-modules", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/Pairs.hop"), "infix <+> : 5;\ndec <+> : num # num -> num;\n--- a <+> b <= a + b;").unwrap();
+        std::fs::write(dir.join("Twice.hop"), "uses Pairs;\ndec twice : num -> num;\n--- twice n <= n <+> n;").unwrap();
+        std::fs::write(dir.join("main.hop"), "uses Twice, Pairs;\ntwice 2 <+> 1;").unwrap();
+        let run = |modules| Driver::new().with_modules(modules).run(&[dir.join("main.hop"), dir.join("Twice.hop")]);
+        let found = run(Loader::new().with_search_path([dir.join("lib")]));
+        let missing = run(Loader::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(found.succeeded(), "{:?}", found.diagnostics);
+        assert_eq!(found.value.as_ref().map(Value::to_string).as_deref(), Some("5"));
+        assert_eq!(found.stats.files, 3);
+        assert_eq!(missing.status, Status::Failed(Stage::Read));
+        assert_eq!(missing.diagnostics[0].code, Some("E0501"));
+        assert_eq!(missing.diagnostics[0].path.as_deref(), Some(dir.join("Twice.hop").as_path()));
+    }
+
+    #[test]
+    fn should_point_at_both_declarations_of_a_name() {
+        let outcome = with_file("duplicate", "dec x : num;\n--- x <= 1;\ndec x : num;", |paths| Driver::new().check(paths));
+        assert_eq!(outcome.status, Status::Failed(Stage::Check));
+        let found: Vec<_> = outcome.diagnostics.iter().map(|d| (d.severity, d.pos.as_ref().map(|pos| pos.line))).collect();
+        assert_eq!(found, [(Severity::Error, Some(3)), (Severity::Note, Some(1)), (Severity::Note, None)]);
+
+        // Unless one of the modules uses the other, which one a name means is up to the
+        // order of the uses
+        let dir = std::env::temp_dir().join(format!("hope-driver-{}-ambiguous", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("A.hop"), "dec size : num;\n--- size <= 1;").unwrap();
+        std::fs::write(dir.join("B.hop"), "dec size : num;\n--- size <= 2;").unwrap();
+        std::fs::write(dir.join("C.hop"), "uses A;\ndec size : num;\n--- size <= 3;").unwrap();
+        let run = |main: &str| {
+            std::fs::write(dir.join("main.hop"), main).unwrap();
+            Driver::new().run(&[dir.join("main.hop")])
+        };
+        let (ambiguous, shadowed, declared) = (run("uses A, B;\nsize;"), run("uses A, C;\nsize;"), run("uses A, B;\ndec size : num;\n--- size <= 4;\nsize;"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(ambiguous.status, Status::Failed(Stage::Check));
+        assert_eq!(ambiguous.diagnostics[0].to_string(), format!("{}:1:9: `size` is declared by both `A` and `B`, which this file uses", source::display(dir.join("main.hop"))));
+        assert_eq!(ambiguous.diagnostics[0].code, Some("E0503"));
+        let notes: Vec<_> = ambiguous.diagnostics[1..3].iter().map(|d| d.path.as_ref().and_then(|p| p.file_name()).unwrap().to_owned()).collect();
+        assert_eq!(notes, ["A.hop", "B.hop"]);
+        assert_eq!(shadowed.value.as_ref().map(Value::to_string).as_deref(), Some("3"));
+        assert_eq!(declared.value.as_ref().map(Value::to_string).as_deref(), Some("4"));
+    }
+
+    #[test]
+    fn should_run_long_chains_of_right_associative_operators() {
+        let items: Vec<_> = (0..1000).map(|n| n.to_string()).collect();
+        let source = format!("dec xs : list num;\n--- xs <= {} :: [];\nlength xs;", items.join(" :: "));
+        let outcome = with_file("chain", &source, |paths| Driver::new().with_prelude(true).run(paths));
+        assert!(outcome.succeeded(), "{:?}", outcome.diagnostics);
+        assert_eq!(outcome.value.as_ref().map(Value::to_string).as_deref(), Some("1000"));
+    }
+
+    #[test]
+    fn should_start_from_the_prelude_when_asked() {
+        let source = "uses Standard;\ndec xs : list num;\n--- xs <= map (\\x => x * 2) [1, 2, 3];\nlength xs :: [0] <> [1];";
+        let (with, without) = with_file("prelude", source, |paths| {
+            (Driver::new().with_prelude(true).run(paths), Driver::new().run(paths))
+        });
+        assert!(with.succeeded(), "{:?}", with.diagnostics);
+        assert_eq!(with.value.as_ref().map(Value::to_string).as_deref(), Some("[3, 0, 1]"));
+        assert_eq!((with.stats.files, with.typed.len()), (1, 1));
+        assert_eq!(without.status, Status::Failed(Stage::Read));
+    }
+
+    #[test]
+    fn should_find_the_library_modules_from_anywhere() {
+        let source = "uses Text;\nwrite split (chr 44) \"a,b\";\nwrite map toupper (trim \" hi \");\n\
+                      to_num (join \"\" [\"1\", \"2\"]) + to_num \"0.5\";";
+        let outcome = with_file("library", source, |paths| Driver::new().with_prelude(true).run(paths));
+        assert!(outcome.succeeded(), "{:?}", outcome.diagnostics);
+        assert_eq!(outcome.stdout, "[\"a\", \"b\"]\n\"HI\"\n12.5\n");
+        assert_eq!((outcome.stats.files, outcome.typed.len()), (3, 3));
+    }
+
+    #[test]
+    fn should_count_what_ran_with_coverage() {
+        let source = "dec sign : num -> num;\n--- sign 0 <= 0;\n--- sign n <= if n < 0 then 0 - 1 else 1;\nsign 3 + length [1];";
+        let outcome = with_file("coverage", source, |paths| Driver::new().with_prelude(true).with_coverage(true).run(paths));
+        assert!(outcome.succeeded(), "{:?}", outcome.diagnostics);
+        let [(_, runs)] = &outcome.coverage[..] else { panic!("only the file is covered, not the prelude") };
+        let sign = &runs.functions[0];
+        assert_eq!(sign.equations.iter().map(|(pos, runs)| (pos.line, *runs)).collect::<Vec<_>>(), [(2, 0), (3, 1)]);
+        assert_eq!((sign.branches[0].1, sign.branches_taken()), ([0, 1], 1));
+        assert!(runs.lcov("f.hop").contains("FNDA:1,sign\nFNF:1\nFNH:1\nBRDA:3,0,0,0\nBRDA:3,0,1,1\n"));
+        assert!(Driver::new().run(&[]).coverage.is_empty());
+    }
+
+    #[test]
+    fn should_read_files_given_a_source_from_it() {
+        let outcome = with_file("source", "1 + 1;", |paths| Driver::new().with_source(&paths[0], "2 + 2;".to_owned()).run(paths));
+        assert_eq!(outcome.stdout, "4\n");
+    }
+
+    #[test]
+    fn should_stop_what_the_sandbox_doesnt_allow_before_running_it() {
+        let pure = crate::sandbox::sandbox("pure").unwrap();
+        let mut builtins = Builtins::new();
+        builtins.register("clock", 1, "num -> num", |_| Ok(Value::Int(0))).unwrap();
+        let driver = Driver::new().with_builtins(builtins).with_sandbox(pure);
+        let outcome = with_file("sandbox-write", "write 1;
+2;
+write 3;", |paths| driver.run(paths));
+        assert_eq!(outcome.status, Status::Failed(Stage::Check));
+        assert_eq!(outcome.violations.iter().map(|v| (v.code(), v.pos().line)).collect::<Vec<_>>(), [("E0601", 1), ("E0601", 3)]);
+        assert!(outcome.written.is_empty());
+
+        let outcome = with_file("sandbox-host", "clock 0 + 1;", |paths| driver.run(paths));
+        let [Violation::Host { name, .. }] = &outcome.violations[..] else { panic!("{:?}", outcome.diagnostics) };
+        assert_eq!(name, "clock");
+        assert_eq!(outcome.diagnostics[0].message, "`clock` is a host function, which this sandbox doesn't allow");
+
+        let io = driver.with_sandbox(crate::sandbox::sandbox("io").unwrap());
+        let outcome = with_file("sandbox-io", "write clock 0;
+2;", |paths| io.run(paths));
+        assert!(outcome.succeeded() && outcome.violations.is_empty(), "{:?}", outcome.diagnostics);
+        assert_eq!(outcome.stdout, "0\n2\n");
+    }
+
+    // Each program in tests/golden, given by a relative path as on a command line, has
+    // what it prints and its rendered diagnostics in the .out next to it, which every
+    // system has to give byte for byte. HOPE_UPDATE_GOLDEN=1 writes them instead
+    #[test]
+    fn should_give_the_golden_output_on_every_system() {
+        let dir = Path::new("tests").join("golden");
+        let mut programs: Vec<PathBuf> = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "hop"))
+            .map(|path| dir.join(path.file_name().unwrap()))
+            .collect();
+        programs.sort();
+        assert!(programs.len() >= 6);
+        let update = std::env::var_os("HOPE_UPDATE_GOLDEN").is_some();
+        for program in &programs {
+            let outcome = Driver::new().with_prelude(true).with_modules(Loader::new()).run(std::slice::from_ref(program));
+            let mut renderer = crate::diagnostics::Renderer::new();
+            let rendered = renderer.render_all(&outcome.diagnostics);
+            let found = format!("{}{}", outcome.stdout, rendered);
+            let golden = program.with_extension("out");
+            if update {
+                std::fs::write(&golden, &found).unwrap();
+                continue;
+            }
+            let expected = std::fs::read_to_string(&golden).unwrap_or_default();
+            assert!(found == expected, "{} differs from {}:\n{}", source::display(program), source::display(&golden), found);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+// An integer of any size, for results past the range of i64. A sign and magnitude,
+// least significant 32 bits first with no high zero digits, so each number has one
+// representation and zero is never negative
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(negative: bool, mut digits: Vec<u32>) -> Self {
+        trim(&mut digits);
+        BigInt { negative: negative && !digits.is_empty(), digits }
+    }
+
+    // What it takes in memory, with its digits
+    pub(crate) fn size(&self) -> usize {
+        std::mem::size_of::<Self>() + std::mem::size_of_val(&self.digits[..])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let magnitude = self.digits.iter().rev().fold(0u64, |acc, &d| (acc << 32) | u64::from(d));
+        if self.negative {
+            // i64::MIN has no positive counterpart, but negates to itself
+            (magnitude <= 1 << 63).then(|| (magnitude as i64).wrapping_neg())
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    // From decimal digits alone, nine at a time
+    pub fn parse(digits: &str) -> Option<BigInt> {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let billion = BigInt::from(1_000_000_000);
+        let first = match digits.len() % 9 {
+            0 => 9,
+            n => n,
+        };
+        let mut n = BigInt::from(digits[..first].parse::<i64>().ok()?);
+        for start in (first..digits.len()).step_by(9) {
+            n = &(&n * &billion) + &BigInt::from(digits[start..start + 9].parse::<i64>().ok()?);
+        }
+        Some(n)
+    }
+
+    // The nearest double, by way of the decimal digits since those round correctly
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        let magnitude = n.unsigned_abs();
+        BigInt::new(n < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+fn trim(digits: &mut Vec<u32>) {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+}
+
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let digit = u64::from(a.get(i).copied().unwrap_or(0)) + u64::from(b.get(i).copied().unwrap_or(0)) + carry;
+        sum.push(digit as u32);
+        carry = digit >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+// a - b, where b is no larger than a
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, &digit) in a.iter().enumerate() {
+        let (digit, under) = digit.overflowing_sub(b.get(i).copied().unwrap_or(0));
+        let (digit, under_again) = digit.overflowing_sub(borrow);
+        difference.push(digit);
+        borrow = u32::from(under || under_again);
+    }
+    trim(&mut difference);
+    difference
+}
+
+fn mul_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut product = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, &y) in b.iter().enumerate() {
+            let digit = u64::from(x) * u64::from(y) + u64::from(product[i + j]) + carry;
+            product[i + j] = digit as u32;
+            carry = digit >> 32;
+        }
+        product[i + b.len()] = carry as u32;
+    }
+    product
+}
+
+// Long division a bit at a time, which is slow for huge numbers but simple
+fn divrem_digits(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    assert!(!b.is_empty(), "division by zero");
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = Vec::new();
+    for bit in (0..a.len() * 32).rev() {
+        let mut carry = (a[bit / 32] >> (bit % 32)) & 1;
+        for digit in &mut remainder {
+            let high = *digit >> 31;
+            *digit = (*digit << 1) | carry;
+            carry = high;
+        }
+        if carry != 0 {
+            remainder.push(carry);
+        }
+        if cmp_digits(&remainder, b) != Ordering::Less {
+            remainder = sub_digits(&remainder, b);
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    (quotient, remainder)
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.digits.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_digits(&self.digits, &other.digits));
+        }
+        match cmp_digits(&self.digits, &other.digits) {
+            Ordering::Less => BigInt::new(other.negative, sub_digits(&other.digits, &self.digits)),
+            _ => BigInt::new(self.negative, sub_digits(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::new(self.negative != other.negative, mul_digits(&self.digits, &other.digits))
+    }
+}
+
+// Rounds toward zero, as i64 division does, and panics on division by zero
+impl Div for &BigInt {
+    type Output = BigInt;
+
+    fn div(self, other: &BigInt) -> BigInt {
+        let (quotient, _) = divrem_digits(&self.digits, &other.digits);
+        BigInt::new(self.negative != other.negative, quotient)
+    }
+}
+
+// With the sign of the dividend
+impl Rem for &BigInt {
+    type Output = BigInt;
+
+    fn rem(self, other: &BigInt) -> BigInt {
+        let (_, remainder) = divrem_digits(&self.digits, &other.digits);
+        BigInt::new(self.negative, remainder)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_digits(&self.digits, &other.digits),
+            (true, true) => cmp_digits(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const CHUNK: u64 = 1_000_000_000;
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Nine decimal digits at a time, least significant first
+        let mut digits = self.digits.clone();
+        let mut chunks = Vec::new();
+        while !digits.is_empty() {
+            let mut remainder = 0;
+            for digit in digits.iter_mut().rev() {
+                let current = (remainder << 32) | u64::from(*digit);
+                *digit = (current / CHUNK) as u32;
+                remainder = current % CHUNK;
+            }
+            trim(&mut digits);
+            chunks.push(remainder);
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        chunks.try_for_each(|chunk| write!(f, "{:09}", chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Products of two i64s always fit an i128, which checks the arithmetic
+    #[test]
+    fn should_agree_with_i128_arithmetic() {
+        let values = [0, 1, -1, 7, -7, 1 << 31, u32::MAX as i64, 1 << 32, 1_000_000_007, i64::MAX, i64::MIN, i64::MIN + 1];
+        for &a in &values {
+            for &b in &values {
+                for &c in &values {
+                    let product = &BigInt::from(a) * &BigInt::from(b);
+                    let exact = i128::from(a) * i128::from(b);
+                    assert_eq!(product.to_string(), exact.to_string());
+                    assert_eq!((&product + &BigInt::from(c)).to_string(), (exact + i128::from(c)).to_string());
+                    assert_eq!((&product - &BigInt::from(c)).to_string(), (exact - i128::from(c)).to_string());
+                    assert_eq!(product.cmp(&BigInt::from(c)), exact.cmp(&i128::from(c)));
+                    if c != 0 {
+                        assert_eq!((&product / &BigInt::from(c)).to_string(), (exact / i128::from(c)).to_string(), "{} / {}", exact, c);
+                        assert_eq!((&product % &BigInt::from(c)).to_string(), (exact % i128::from(c)).to_string(), "{} % {}", exact, c);
+                    }
+                    assert_eq!(product.to_i64(), i64::try_from(exact).ok());
+                }
+            }
+        }
+        assert_eq!((&BigInt::from(i64::MAX) * &BigInt::from(4)).to_f64(), 4.0 * i64::MAX as f64);
+        for digits in ["0", "7", "123456789", "1234567890", "170141183460469231731687303715884105727"] {
+            assert_eq!(BigInt::parse(digits).unwrap().to_string(), digits);
+        }
+        assert_eq!(BigInt::parse("-1").or(BigInt::parse("")), None);
+    }
+}
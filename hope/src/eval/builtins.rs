@@ -0,0 +1,328 @@
+// Implementations of the functions in types::builtins. Binary operators take their
+// operands as a pair.
+//
+// There is one type of number. A number is whole if it is finite with no fractional
+// part and fractional otherwise, but the two mix freely: `1 = 1.0` and `1 < 1.5`
+// compare values. Whole literals are integers, and `+`, `-`, `*`, `div` and `mod` on
+// two integers are exact, up to the build's Overflow. Otherwise numbers are doubles
+// and the operators are the IEEE ones, as `/` always is.
+//
+// `a div b` is a / b rounded toward zero and `a mod b` is a - b * (a div b), so the
+// remainder takes the sign of a: `(0 - 7) div 2` is -3 and `(0 - 7) mod 2` is -1.
+// Either is a division by zero error when b is 0. Equality and ordering follow IEEE
+// too, so NaN, which only arises from infinities such as `1e308 * 10 - 1e308 * 10`,
+// is neither equal to, less than nor greater than anything, itself included, and
+// `0 = 0 - 0`.
+//
+// With strict numerics, the operators that combine or compare two numbers fail when
+// one is whole and the other fractional, and `div` and `mod` only take whole numbers
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use crate::printf::{self, Conversion, FormatError, Piece};
+use crate::syntax::token::Pos;
+use crate::eval::{diff, BigInt, Builtin, EvalError, Function, MemoTable, Value};
+
+// What integer arithmetic does with a result outside the range of i64
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    // Fails with EvalError::Overflow
+    Checked,
+    // Two's complement, as i64::wrapping_add and the rest
+    Wrapping,
+    // Carries on with a BigInt, shrinking back to an i64 when a result fits again
+    Promote,
+}
+
+impl Overflow {
+    // Promote, unless the crate was built with the overflow-checked or
+    // overflow-wrapping feature. Checked wins if both are on
+    pub const BUILD: Overflow = if cfg!(feature = "overflow-checked") {
+        Overflow::Checked
+    } else if cfg!(feature = "overflow-wrapping") {
+        Overflow::Wrapping
+    } else {
+        Overflow::Promote
+    };
+}
+
+// An arithmetic operator on each representation of numbers
+struct Arithmetic {
+    checked: fn(i64, i64) -> Option<i64>,
+    wrapping: fn(i64, i64) -> i64,
+    big: fn(&BigInt, &BigInt) -> BigInt,
+    float: fn(f64, f64) -> f64,
+}
+
+const ADD: Arithmetic = Arithmetic { checked: i64::checked_add, wrapping: i64::wrapping_add, big: |a, b| a + b, float: |a, b| a + b };
+const SUB: Arithmetic = Arithmetic { checked: i64::checked_sub, wrapping: i64::wrapping_sub, big: |a, b| a - b, float: |a, b| a - b };
+const MUL: Arithmetic = Arithmetic { checked: i64::checked_mul, wrapping: i64::wrapping_mul, big: |a, b| a * b, float: |a, b| a * b };
+const DIV: Arithmetic = Arithmetic { checked: i64::checked_div, wrapping: i64::wrapping_div, big: |a, b| a / b, float: |a, b| (a / b).trunc() };
+const MOD: Arithmetic = Arithmetic { checked: i64::checked_rem, wrapping: i64::wrapping_rem, big: |a, b| a % b, float: |a, b| a % b };
+
+pub const FUNCTIONS: &[(&str, Builtin)] = &[
+    ("+", |v, pos| arithmetic("+", v, pos, &ADD, Overflow::BUILD)),
+    ("-", |v, pos| arithmetic("-", v, pos, &SUB, Overflow::BUILD)),
+    ("*", |v, pos| arithmetic("*", v, pos, &MUL, Overflow::BUILD)),
+    ("/", divide),
+    ("div", |v, pos| integer_division("div", v, pos, &DIV, Overflow::BUILD)),
+    ("mod", |v, pos| integer_division("mod", v, pos, &MOD, Overflow::BUILD)),
+    ("<", |v, pos| compare("<", v, pos, |o| o == Ordering::Less)),
+    ("=<", |v, pos| compare("=<", v, pos, |o| o != Ordering::Greater)),
+    (">", |v, pos| compare(">", v, pos, |o| o == Ordering::Greater)),
+    (">=", |v, pos| compare(">=", v, pos, |o| o != Ordering::Less)),
+    ("=", |v, pos| pair("=", v, pos).map(|(a, b)| Value::bool(a.equals(b)))),
+    ("/=", |v, pos| pair("/=", v, pos).map(|(a, b)| Value::bool(!a.equals(b)))),
+    ("and", |v, pos| logic("and", v, pos, |a, b| a && b)),
+    ("or", |v, pos| logic("or", v, pos, |a, b| a || b)),
+    ("not", |v, pos| v.as_bool().map(|b| Value::bool(!b)).ok_or(EvalError::BadArgument("not", pos.clone()))),
+    // Applied by the interpreter, see Interpreter::with_memo_capacity
+    ("memo", |v, _| Ok(Value::Function(Rc::new(Function::Memo(v.clone(), MemoTable::default()))))),
+    ("ord", |v, pos| match v {
+        Value::Char(c) => Ok(Value::Int(i64::from(u32::from(*c)))),
+        _ => Err(EvalError::BadArgument("ord", pos.clone())),
+    }),
+    ("chr", |v, pos| {
+        let code = v.as_f64().filter(|n| n.fract() == 0.0 && *n >= 0.0 && *n <= f64::from(u32::MAX));
+        code.and_then(|n| char::from_u32(n as u32)).map(Value::Char).ok_or(EvalError::BadArgument("chr", pos.clone()))
+    }),
+    ("read_num", read_num),
+    ("show_num", |v, pos| match v.as_f64() {
+        Some(_) => Ok(Value::string(&v.to_string())),
+        None => Err(EvalError::BadArgument("show_num", pos.clone())),
+    }),
+    ("format", format_text),
+    ("assert_eq", |v, pos| {
+        let (expected, actual) = pair("assert_eq", v, pos)?;
+        match diff(expected, actual) {
+            None => Ok(Value::bool(true)),
+            Some(difference) => Err(EvalError::AssertionFailed(Box::new(difference), pos.clone())),
+        }
+    }),
+];
+
+// Replace their namesakes in FUNCTIONS under strict numerics
+pub const STRICT_NUMERICS: &[(&str, Builtin)] = &[
+    ("+", |v, pos| strict("+", v, pos).and_then(|_| arithmetic("+", v, pos, &ADD, Overflow::BUILD))),
+    ("-", |v, pos| strict("-", v, pos).and_then(|_| arithmetic("-", v, pos, &SUB, Overflow::BUILD))),
+    ("*", |v, pos| strict("*", v, pos).and_then(|_| arithmetic("*", v, pos, &MUL, Overflow::BUILD))),
+    ("/", |v, pos| strict("/", v, pos).and_then(|_| divide(v, pos))),
+    ("div", |v, pos| whole("div", v, pos).and_then(|_| integer_division("div", v, pos, &DIV, Overflow::BUILD))),
+    ("mod", |v, pos| whole("mod", v, pos).and_then(|_| integer_division("mod", v, pos, &MOD, Overflow::BUILD))),
+    ("<", |v, pos| strict("<", v, pos).and_then(|_| compare("<", v, pos, |o| o == Ordering::Less))),
+    ("=<", |v, pos| strict("=<", v, pos).and_then(|_| compare("=<", v, pos, |o| o != Ordering::Greater))),
+    (">", |v, pos| strict(">", v, pos).and_then(|_| compare(">", v, pos, |o| o == Ordering::Greater))),
+    (">=", |v, pos| strict(">=", v, pos).and_then(|_| compare(">=", v, pos, |o| o != Ordering::Less))),
+    ("=", |v, pos| strict_equality("=", v, pos).map(|(a, b)| Value::bool(a.equals(b)))),
+    ("/=", |v, pos| strict_equality("/=", v, pos).map(|(a, b)| Value::bool(!a.equals(b)))),
+];
+
+fn pair<'a>(name: &'static str, value: &'a Value, pos: &Pos) -> Result<(&'a Value, &'a Value), EvalError> {
+    match value {
+        Value::Pair(cell) => Ok((&cell.0, &cell.1)),
+        _ => Err(EvalError::BadArgument(name, pos.clone())),
+    }
+}
+
+fn numbers<'a>(name: &'static str, value: &'a Value, pos: &Pos) -> Result<(&'a Value, &'a Value), EvalError> {
+    match pair(name, value, pos)? {
+        (a, b) if a.as_f64().is_some() && b.as_f64().is_some() => Ok((a, b)),
+        _ => Err(EvalError::BadArgument(name, pos.clone())),
+    }
+}
+
+fn big(value: &Value) -> Option<Cow<'_, BigInt>> {
+    match value {
+        Value::Int(n) => Some(Cow::Owned(BigInt::from(*n))),
+        Value::Big(n) => Some(Cow::Borrowed(n)),
+        _ => None,
+    }
+}
+
+fn arithmetic(name: &'static str, value: &Value, pos: &Pos, op: &Arithmetic, overflow: Overflow) -> Result<Value, EvalError> {
+    let (a, b) = numbers(name, value, pos)?;
+    if let (Value::Int(x), Value::Int(y)) = (a, b) {
+        return match ((op.checked)(*x, *y), overflow) {
+            (Some(n), _) => Ok(Value::Int(n)),
+            (None, Overflow::Checked) => Err(EvalError::Overflow(name, pos.clone())),
+            (None, Overflow::Wrapping) => Ok(Value::Int((op.wrapping)(*x, *y))),
+            (None, Overflow::Promote) => Ok(Value::big((op.big)(&BigInt::from(*x), &BigInt::from(*y)))),
+        };
+    }
+    match (big(a), big(b)) {
+        (Some(x), Some(y)) => Ok(Value::big((op.big)(&x, &y))),
+        _ => Ok(Value::Num((op.float)(a.as_f64().unwrap(), b.as_f64().unwrap()))),
+    }
+}
+
+fn integer_division(name: &'static str, value: &Value, pos: &Pos, op: &Arithmetic, overflow: Overflow) -> Result<Value, EvalError> {
+    match numbers(name, value, pos)? {
+        (_, b) if b.as_f64() == Some(0.0) => Err(EvalError::DivisionByZero(pos.clone())),
+        _ => arithmetic(name, value, pos, op, overflow),
+    }
+}
+
+fn divide(value: &Value, pos: &Pos) -> Result<Value, EvalError> {
+    let (a, b) = numbers("/", value, pos)?;
+    match (a.as_f64().unwrap(), b.as_f64().unwrap()) {
+        (_, 0.0) => Err(EvalError::DivisionByZero(pos.clone())),
+        (a, b) => Ok(Value::Num(a / b)),
+    }
+}
+
+// The number the text is, written as a literal is or as show_num writes numbers, with
+// a `-` in front of a negative one. Whole numbers too large for an i64 are read exactly
+fn read_num(value: &Value, pos: &Pos) -> Result<Value, EvalError> {
+    let bad = || EvalError::BadArgument("read_num", pos.clone());
+    let text = value.as_string().ok_or_else(bad)?;
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.as_str()),
+    };
+    // Rust reads `inf` and `NaN` too, which Hope doesn't write
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(bad());
+    }
+    if digits.bytes().all(|b| b.is_ascii_digit()) {
+        let ten = BigInt::from(10);
+        let n = digits.bytes().fold(BigInt::from(0), |n, digit| &(&n * &ten) + &BigInt::from(i64::from(digit - b'0')));
+        return Ok(Value::big(if negative { &BigInt::from(0) - &n } else { n }));
+    }
+    text.parse::<f64>().ok().filter(|n| n.is_finite()).map(Value::Num).ok_or_else(bad)
+}
+
+// The format string with each directive replaced by its argument written out, see
+// printf. Whole numbers are written in full however large they are
+fn format_text(value: &Value, pos: &Pos) -> Result<Value, EvalError> {
+    let bad_argument = || EvalError::BadArgument("format", pos.clone());
+    let bad_format = |e| EvalError::BadFormat(e, pos.clone());
+    let (text, args) = pair("format", value, pos)?;
+    let text = text.as_string().ok_or_else(bad_argument)?;
+    let args = args.as_list().ok_or_else(bad_argument)?.into_iter()
+        .map(|arg| match arg {
+            Value::Data(d) if d.args.len() == 1 => Ok((d.name.as_str(), &d.args[0])),
+            _ => Err(bad_argument()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let pieces = printf::parse(&text).map_err(bad_format)?;
+    let constructors: Vec<_> = args.iter().map(|&(constructor, _)| Some(constructor)).collect();
+    printf::check(&pieces, &constructors).map_err(bad_format)?;
+
+    let mut out = String::new();
+    let mut args = args.into_iter().enumerate();
+    for piece in &pieces {
+        let directive = match piece {
+            Piece::Text(text) => {
+                out.push_str(text);
+                continue;
+            }
+            Piece::Directive(directive) => directive,
+        };
+        let (index, (_, arg)) = args.next().expect("there is an argument for each directive");
+        let written = match (directive.conversion, arg) {
+            (Conversion::Text, _) => arg.as_string().ok_or_else(bad_argument)?,
+            (Conversion::Whole, Value::Num(n)) if is_whole(arg) => format!("{:.0}", n),
+            (Conversion::Whole, Value::Int(_) | Value::Big(_)) => arg.to_string(),
+            (Conversion::Whole, _) => {
+                let e = FormatError::Argument { index, directive: directive.text.clone(), needs: "a whole number" };
+                return Err(bad_format(e));
+            }
+            (Conversion::Fixed, _) => format!("{:.*}", directive.precision.unwrap_or(6), arg.as_f64().ok_or_else(bad_argument)?),
+        };
+        out.push_str(&directive.pad(&written));
+    }
+    Ok(Value::string(&out))
+}
+
+// NaN is unordered, so every comparison with it is false
+fn compare(name: &'static str, value: &Value, pos: &Pos, op: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    let (a, b) = numbers(name, value, pos)?;
+    Ok(Value::bool(a.compare_numbers(b).is_some_and(op)))
+}
+
+fn logic(name: &'static str, value: &Value, pos: &Pos, op: fn(bool, bool) -> bool) -> Result<Value, EvalError> {
+    let (a, b) = pair(name, value, pos)?;
+    match (a.as_bool(), b.as_bool()) {
+        (Some(a), Some(b)) => Ok(Value::bool(op(a, b))),
+        _ => Err(EvalError::BadArgument(name, pos.clone())),
+    }
+}
+
+fn is_whole(n: &Value) -> bool {
+    match n {
+        Value::Num(n) => n.is_finite() && n.fract() == 0.0,
+        _ => true,
+    }
+}
+
+fn strict(name: &'static str, value: &Value, pos: &Pos) -> Result<(), EvalError> {
+    match numbers(name, value, pos)? {
+        (a, b) if is_whole(a) != is_whole(b) => Err(EvalError::MixedNumbers(name, pos.clone())),
+        _ => Ok(()),
+    }
+}
+
+fn whole(name: &'static str, value: &Value, pos: &Pos) -> Result<(), EvalError> {
+    match numbers(name, value, pos)? {
+        (a, b) if is_whole(a) && is_whole(b) => Ok(()),
+        _ => Err(EvalError::NotWhole(name, pos.clone())),
+    }
+}
+
+// Only numbers compared directly are held to their kind, not those inside other values
+fn strict_equality<'a>(name: &'static str, value: &'a Value, pos: &Pos) -> Result<(&'a Value, &'a Value), EvalError> {
+    let (a, b) = pair(name, value, pos)?;
+    if a.as_f64().is_some() && b.as_f64().is_some() {
+        strict(name, value, pos)?;
+    }
+    Ok((a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(op: &Arithmetic, a: i64, b: i64, overflow: Overflow) -> Result<String, EvalError> {
+        let pos = Pos { line: 1, column: 1, range: 0..0 };
+        let value = Value::pair(Value::Int(a), Value::Int(b));
+        integer_division("op", &value, &pos, op, overflow).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn should_overflow_as_the_mode_says() {
+        let cases = [
+            (&ADD, i64::MAX, 1, "9223372036854775808", "-9223372036854775808"),
+            (&SUB, i64::MIN, 1, "-9223372036854775809", "9223372036854775807"),
+            (&MUL, i64::MIN, -1, "9223372036854775808", "-9223372036854775808"),
+            (&MUL, 1 << 32, 1 << 32, "18446744073709551616", "0"),
+            (&DIV, i64::MIN, -1, "9223372036854775808", "-9223372036854775808"),
+            (&MOD, i64::MIN, -1, "0", "0"),
+        ];
+        for (op, a, b, promoted, wrapped) in cases {
+            assert_eq!(apply(op, a, b, Overflow::Promote).unwrap(), promoted);
+            assert_eq!(apply(op, a, b, Overflow::Wrapping).unwrap(), wrapped);
+            assert!(matches!(apply(op, a, b, Overflow::Checked), Err(EvalError::Overflow(..))));
+        }
+
+        // Right at the boundaries nothing overflows, whatever the mode
+        for overflow in [Overflow::Checked, Overflow::Wrapping, Overflow::Promote] {
+            assert_eq!(apply(&ADD, i64::MAX - 1, 1, overflow).unwrap(), i64::MAX.to_string());
+            assert_eq!(apply(&SUB, i64::MIN + 1, 1, overflow).unwrap(), i64::MIN.to_string());
+            assert_eq!(apply(&MUL, -(1 << 31), 1 << 32, overflow).unwrap(), i64::MIN.to_string());
+            assert_eq!(apply(&DIV, i64::MIN, 1, overflow).unwrap(), i64::MIN.to_string());
+            assert_eq!(apply(&MOD, i64::MIN, 2, overflow).unwrap(), "0");
+        }
+    }
+
+    #[test]
+    fn should_shrink_promoted_results_that_fit_again() {
+        let pos = Pos { line: 1, column: 1, range: 0..0 };
+        let run = |op, a: Value, b: Value| arithmetic("op", &Value::pair(a, b), &pos, op, Overflow::Promote).unwrap();
+        let big = run(&ADD, Value::Int(i64::MAX), Value::Int(1));
+        assert!(matches!(big, Value::Big(_)));
+        assert!(matches!(run(&SUB, big.clone(), Value::Int(1)), Value::Int(i64::MAX)));
+        assert_eq!(run(&MUL, big.clone(), big.clone()).to_string(), "85070591730234615865843651857942052864");
+        assert!(matches!(run(&ADD, big.clone(), Value::Num(0.5)), Value::Num(n) if n == 2f64.powi(63)));
+        assert!(big.equals(&Value::Num(2f64.powi(63))) && big.compare_numbers(&Value::Int(i64::MAX)) == Some(Ordering::Greater));
+    }
+}
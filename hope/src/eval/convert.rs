@@ -0,0 +1,102 @@
+use std::fmt;
+use crate::eval::Value;
+
+// Rust values from the values programs give, for hosts that read what a program
+// computed, see config
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, ConvertError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertError {
+    // What the Rust type takes, like "a whole number"
+    pub expected: &'static str,
+    // The value, as programs write it
+    pub found: String,
+}
+
+impl ConvertError {
+    fn new(expected: &'static str, found: &Value) -> Self {
+        ConvertError { expected, found: found.to_string() }
+    }
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, ConvertError> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, ConvertError> {
+        value.as_bool().ok_or_else(|| ConvertError::new("true or false", value))
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Int(n) => Ok(*n),
+            Value::Num(x) if *x as i64 as f64 == *x => Ok(*x as i64),
+            _ => Err(ConvertError::new("a whole number that fits in 64 bits", value)),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, ConvertError> {
+        value.as_f64().ok_or_else(|| ConvertError::new("a number", value))
+    }
+}
+
+impl FromValue for char {
+    fn from_value(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Char(c) => Ok(*c),
+            _ => Err(ConvertError::new("a character", value)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, ConvertError> {
+        value.as_string().ok_or_else(|| ConvertError::new("text", value))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, ConvertError> {
+        let items = value.as_list().ok_or_else(|| ConvertError::new("a list", value))?;
+        items.into_iter().map(T::from_value).collect()
+    }
+}
+
+impl<A: FromValue, B: FromValue> FromValue for (A, B) {
+    fn from_value(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Pair(cell) => Ok((A::from_value(&cell.0)?, B::from_value(&cell.1)?)),
+            _ => Err(ConvertError::new("a pair", value)),
+        }
+    }
+}
+
+// Tuples nest to the right, so (a, b, c) is (a, (b, c))
+impl<A: FromValue, B: FromValue, C: FromValue> FromValue for (A, B, C) {
+    fn from_value(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Pair(cell) => {
+                let (b, c) = <(B, C)>::from_value(&cell.1)?;
+                Ok((A::from_value(&cell.0)?, b, c))
+            }
+            _ => Err(ConvertError::new("a triple", value)),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::syntax::ast::*;
+use crate::syntax::token::Pos;
+
+// How often each equation and each way of each `if` ran, by where they start. A
+// position doesn't say which file it is in, so an equation is counted by its function
+// too, and an `if` only where its equation ran
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    equations: HashMap<(String, usize), u64>,
+    branches: HashMap<usize, [u64; 2]>,
+}
+
+impl Coverage {
+    pub(crate) fn equation(&mut self, name: &str, pos: &Pos) {
+        *self.equations.entry((name.to_owned(), pos.range.start)).or_default() += 1;
+    }
+
+    pub(crate) fn branch(&mut self, pos: &Pos, taken: bool) {
+        self.branches.entry(pos.range.start).or_default()[usize::from(!taken)] += 1;
+    }
+
+    // Every equation and `if` of the program, with how often each ran
+    pub fn of(&self, program: &Program) -> FileCoverage {
+        let mut functions: Vec<FunctionCoverage> = Vec::new();
+        for decl in &program.decls {
+            let DeclKind::Equation(eq) = &decl.kind else { continue };
+            let runs = self.equations.get(&(eq.name.name.clone(), eq.name.pos.range.start)).copied().unwrap_or(0);
+            let mut ifs = Vec::new();
+            eq.guard.iter().chain([&eq.body]).for_each(|expr| collect_ifs(expr, &mut ifs));
+            let branches = ifs.into_iter().map(|pos| {
+                let taken = if runs > 0 { self.branches.get(&pos.range.start).copied().unwrap_or_default() } else { [0, 0] };
+                (pos.clone(), taken)
+            });
+            let function = match functions.iter_mut().find(|f| f.name == eq.name.name) {
+                Some(function) => function,
+                None => {
+                    functions.push(FunctionCoverage { name: eq.name.name.clone(), equations: Vec::new(), branches: Vec::new() });
+                    functions.last_mut().expect("just pushed")
+                }
+            };
+            function.equations.push((eq.name.pos.clone(), runs));
+            function.branches.extend(branches);
+        }
+        FileCoverage { functions }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileCoverage {
+    pub functions: Vec<FunctionCoverage>,
+}
+
+// Runs of each equation, and of the `then` and `else` of each `if`, in the order written
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCoverage {
+    pub name: String,
+    pub equations: Vec<(Pos, u64)>,
+    pub branches: Vec<(Pos, [u64; 2])>,
+}
+
+impl FunctionCoverage {
+    pub fn equations_run(&self) -> usize {
+        self.equations.iter().filter(|(_, runs)| *runs > 0).count()
+    }
+
+    pub fn branches_taken(&self) -> usize {
+        self.branches.iter().map(|(_, taken)| taken.iter().filter(|&&runs| runs > 0).count()).sum()
+    }
+}
+
+impl FileCoverage {
+    // Adds the counts of another run of the same file
+    pub fn merge(&mut self, other: &FileCoverage) {
+        for (mine, theirs) in self.functions.iter_mut().zip(&other.functions) {
+            for (mine, theirs) in mine.equations.iter_mut().zip(&theirs.equations) {
+                mine.1 += theirs.1;
+            }
+            for (mine, theirs) in mine.branches.iter_mut().zip(&theirs.branches) {
+                mine.1[0] += theirs.1[0];
+                mine.1[1] += theirs.1[1];
+            }
+        }
+    }
+
+    // The file's record in the LCOV tracefile format, with each function at its first
+    // equation, each equation as a line and each `if` as a pair of branches
+    pub fn lcov(&self, path: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", path);
+        for f in &self.functions {
+            let _ = writeln!(out, "FN:{},{}", f.equations[0].0.line, f.name);
+        }
+        for f in &self.functions {
+            let _ = writeln!(out, "FNDA:{},{}", f.equations.iter().map(|(_, runs)| runs).sum::<u64>(), f.name);
+        }
+        let hit = self.functions.iter().filter(|f| f.equations_run() > 0).count();
+        let _ = write!(out, "FNF:{}\nFNH:{}\n", self.functions.len(), hit);
+        let mut found = 0;
+        let mut taken = 0;
+        for f in &self.functions {
+            for (i, (pos, runs)) in f.branches.iter().enumerate() {
+                for (way, runs) in runs.iter().enumerate() {
+                    let _ = writeln!(out, "BRDA:{},{},{},{}", pos.line, i, way, runs);
+                }
+                found += 2;
+                taken += runs.iter().filter(|&&runs| runs > 0).count();
+            }
+        }
+        let _ = write!(out, "BRF:{}\nBRH:{}\n", found, taken);
+        let lines: Vec<_> = self.functions.iter().flat_map(|f| &f.equations).collect();
+        for (pos, runs) in &lines {
+            let _ = writeln!(out, "DA:{},{}", pos.line, runs);
+        }
+        let run = lines.iter().filter(|(_, runs)| *runs > 0).count();
+        let _ = write!(out, "LF:{}\nLH:{}\nend_of_record\n", lines.len(), run);
+        out
+    }
+}
+
+fn collect_ifs<'a>(expr: &'a Expr, ifs: &mut Vec<&'a Pos>) {
+    match &expr.kind {
+        ExprKind::Var(_) | ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) => {}
+        ExprKind::Tuple(items) | ExprKind::List(items) => items.iter().for_each(|item| collect_ifs(item, ifs)),
+        ExprKind::Apply(f, arg) => {
+            collect_ifs(f, ifs);
+            collect_ifs(arg, ifs);
+        }
+        ExprKind::BinOp(_, l, r) => {
+            collect_ifs(l, ifs);
+            collect_ifs(r, ifs);
+        }
+        ExprKind::If(c, t, e) => {
+            ifs.push(&expr.pos);
+            collect_ifs(c, ifs);
+            collect_ifs(t, ifs);
+            collect_ifs(e, ifs);
+        }
+        ExprKind::Lambda(rules) => rules.iter().for_each(|rule| collect_ifs(&rule.body, ifs)),
+        ExprKind::Let(binding) => {
+            collect_ifs(&binding.value, ifs);
+            collect_ifs(&binding.body, ifs);
+        }
+    }
+}
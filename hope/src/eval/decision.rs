@@ -0,0 +1,353 @@
+use std::fmt;
+use crate::pp::{self, Doc};
+use crate::syntax::ast::*;
+use crate::eval::resolve;
+
+// Where a value being matched is: which argument, then which field at each step down
+// through constructors and pairs
+pub type Occurrence = Vec<usize>;
+
+// What a switch compares the value at its occurrence with
+#[derive(Debug, Clone, PartialEq)]
+pub enum Test {
+    Constructor(String),
+    // Whole numbers are always tested as integers, so no value passes two tests
+    Int(i64),
+    Big(String),
+    Num(f64),
+    Char(char),
+}
+
+// Equations compiled so that each part of the arguments is tested at most once on the
+// way to an equation, however deeply the patterns nest or however many equations test
+// the same part
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tree {
+    Fail,
+    Leaf {
+        equation: usize,
+        // In the order of the equation's frame, see resolve::binders
+        bindings: Vec<(String, Occurrence)>,
+        // Where to go when the equation's guard is false
+        otherwise: Option<Box<Tree>>,
+    },
+    Switch {
+        occurrence: Occurrence,
+        cases: Vec<(Test, Tree)>,
+        default: Option<Box<Tree>>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Pat {
+    Any,
+    Bind(String, Box<Pat>),
+    Pair(Box<Pat>, Box<Pat>),
+    Test(Test, Vec<Pat>),
+}
+
+#[derive(Clone)]
+struct Row {
+    columns: Vec<(Occurrence, Pat)>,
+    equation: usize,
+    guarded: bool,
+    bindings: Vec<(String, Occurrence)>,
+}
+
+// is_constructor tells a nullary constructor in a pattern from a variable. The function
+// takes as many arguments as its first equation has patterns, any others take part in
+// matching only as far as that
+pub fn compile(equations: &[&Equation], is_constructor: &dyn Fn(&str) -> bool) -> Tree {
+    let arity = equations.first().map_or(0, |eq| eq.args.len());
+    let rows = equations.iter().enumerate()
+        .map(|(i, eq)| Row {
+            columns: (0..arity)
+                .map(|arg| (vec![arg], eq.args.get(arg).map_or(Pat::Any, |p| pattern(p, is_constructor))))
+                .collect(),
+            equation: i,
+            guarded: eq.guard.is_some(),
+            bindings: Vec::new(),
+        })
+        .collect();
+    let mut tree = tree(rows);
+    let frames: Vec<Vec<String>> = equations.iter()
+        .map(|eq| {
+            let mut names = Vec::new();
+            eq.args.iter().take(arity).for_each(|arg| resolve::binders(arg, is_constructor, &mut names));
+            names
+        })
+        .collect();
+    in_frame_order(&mut tree, &frames);
+    tree
+}
+
+fn in_frame_order(tree: &mut Tree, frames: &[Vec<String>]) {
+    match tree {
+        Tree::Fail => {}
+        Tree::Leaf { equation, bindings, otherwise } => {
+            let mut left = std::mem::take(bindings);
+            for bound in &frames[*equation] {
+                if let Some(i) = left.iter().position(|(name, _)| name == bound) {
+                    bindings.push(left.remove(i));
+                }
+            }
+            bindings.extend(left);
+            if let Some(otherwise) = otherwise {
+                in_frame_order(otherwise, frames);
+            }
+        }
+        Tree::Switch { cases, default, .. } => {
+            cases.iter_mut().for_each(|(_, case)| in_frame_order(case, frames));
+            if let Some(default) = default {
+                in_frame_order(default, frames);
+            }
+        }
+    }
+}
+
+fn pattern(pattern: &Pattern, is_constructor: &dyn Fn(&str) -> bool) -> Pat {
+    let sub = |p| self::pattern(p, is_constructor);
+    match &pattern.kind {
+        PatternKind::Var(name) if is_constructor(name) => Pat::Test(Test::Constructor(name.clone()), Vec::new()),
+        PatternKind::Var(name) => Pat::Bind(name.clone(), Box::new(Pat::Any)),
+        PatternKind::Wildcard => Pat::Any,
+        PatternKind::As(name, p) => Pat::Bind(name.name.clone(), Box::new(sub(p))),
+        PatternKind::Int(n) => Pat::Test(Test::Int(*n), Vec::new()),
+        PatternKind::BigInt(digits) => Pat::Test(Test::Big(digits.clone()), Vec::new()),
+        PatternKind::Num(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Pat::Test(Test::Int(*n as i64), Vec::new()),
+        PatternKind::Num(n) => Pat::Test(Test::Num(*n), Vec::new()),
+        PatternKind::Str(s) => s.chars().rev().fold(nil(), |tail, c| cons(Pat::Test(Test::Char(c), Vec::new()), tail)),
+        PatternKind::Tuple(items) => {
+            let mut items = items.iter().rev().map(sub);
+            let last = items.next().expect("tuples have at least two items");
+            items.fold(last, |rest, item| Pat::Pair(Box::new(item), Box::new(rest)))
+        }
+        PatternKind::List(items) => items.iter().rev().fold(nil(), |tail, item| cons(sub(item), tail)),
+        PatternKind::Construct(name, args) => Pat::Test(Test::Constructor(name.name.clone()), args.iter().map(sub).collect()),
+        PatternKind::BinOp(op, l, r) => {
+            let pair = Pat::Pair(Box::new(sub(l)), Box::new(sub(r)));
+            Pat::Test(Test::Constructor(op.name.clone()), vec![pair])
+        }
+    }
+}
+
+fn nil() -> Pat {
+    Pat::Test(Test::Constructor("nil".to_owned()), Vec::new())
+}
+
+fn cons(head: Pat, tail: Pat) -> Pat {
+    Pat::Test(Test::Constructor("::".to_owned()), vec![Pat::Pair(Box::new(head), Box::new(tail))])
+}
+
+fn tree(mut rows: Vec<Row>) -> Tree {
+    rows.iter_mut().for_each(bind);
+    let Some(first) = rows.first() else { return Tree::Fail };
+
+    // Pairs always match, so they are taken apart without a test
+    let column = first.columns.iter().position(|(_, p)| !matches!(p, Pat::Any));
+    let Some(column) = column else {
+        let first = rows.remove(0);
+        let otherwise = first.guarded.then(|| Box::new(tree(rows)));
+        return Tree::Leaf { equation: first.equation, bindings: first.bindings, otherwise };
+    };
+    if rows.iter().any(|row| matches!(row.columns[column].1, Pat::Pair(..))) {
+        for row in &mut rows {
+            let (occurrence, p) = row.columns.remove(column);
+            let (l, r) = match p {
+                Pat::Pair(l, r) => (*l, *r),
+                _ => (Pat::Any, Pat::Any),
+            };
+            row.columns.insert(column, (field(&occurrence, 1), r));
+            row.columns.insert(column, (field(&occurrence, 0), l));
+        }
+        return tree(rows);
+    }
+
+    let occurrence = first.columns[column].0.clone();
+    let mut tests: Vec<(Test, usize)> = Vec::new();
+    for row in &rows {
+        match &row.columns[column].1 {
+            Pat::Test(test, args) if !tests.iter().any(|(t, _)| t == test) => tests.push((test.clone(), args.len())),
+            _ => {}
+        }
+    }
+    let cases = tests.into_iter()
+        .map(|(test, arity)| {
+            let rows = rows.iter()
+                .filter_map(|row| {
+                    let args = match &row.columns[column].1 {
+                        Pat::Test(t, args) if *t == test => args.clone(),
+                        Pat::Test(..) => return None,
+                        _ => vec![Pat::Any; arity],
+                    };
+                    let mut row = row.clone();
+                    row.columns.remove(column);
+                    for (i, arg) in args.into_iter().enumerate().rev() {
+                        row.columns.insert(column, (field(&occurrence, i), arg));
+                    }
+                    Some(row)
+                })
+                .collect();
+            (test, tree(rows))
+        })
+        .collect();
+    let rest: Vec<_> = rows.into_iter()
+        .filter(|row| matches!(row.columns[column].1, Pat::Any))
+        .map(|mut row| {
+            row.columns.remove(column);
+            row
+        })
+        .collect();
+    let default = (!rest.is_empty()).then(|| Box::new(tree(rest)));
+    Tree::Switch { occurrence, cases, default }
+}
+
+// Moves the names the row's patterns bind at the top into its bindings
+fn bind(row: &mut Row) {
+    for (occurrence, p) in &mut row.columns {
+        while let Pat::Bind(name, inner) = p {
+            row.bindings.push((std::mem::take(name), occurrence.clone()));
+            *p = std::mem::replace(&mut **inner, Pat::Any);
+        }
+    }
+}
+
+fn field(occurrence: &Occurrence, i: usize) -> Occurrence {
+    occurrence.iter().copied().chain([i]).collect()
+}
+
+// Arguments and fields counted from 1, as in `$1.2` for the second field of the first
+// argument
+fn show(occurrence: &Occurrence) -> String {
+    let steps: Vec<_> = occurrence.iter().map(|i| (i + 1).to_string()).collect();
+    format!("${}", steps.join("."))
+}
+
+impl fmt::Display for Test {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Test::Constructor(name) => write!(f, "{}", name),
+            Test::Int(n) => write!(f, "{}", n),
+            Test::Big(digits) => write!(f, "{}", digits),
+            Test::Num(n) => write!(f, "{}", n),
+            Test::Char(c) => write!(f, "{:?}", c),
+        }
+    }
+}
+
+impl Tree {
+    // One line per test or equation, each case nested under its switch. Bindings that
+    // don't fit on the equation's line go one to a line below it
+    fn doc(&self) -> Doc {
+        match self {
+            Tree::Fail => pp::text("no match"),
+            Tree::Leaf { equation, bindings, otherwise } => {
+                let mut doc = pp::text(format!("equation {}", equation + 1));
+                if !bindings.is_empty() {
+                    let bindings = bindings.iter().map(|(name, o)| pp::text(format!("{} = {}", name, show(o))));
+                    doc = doc.append(pp::text(" with ")).append(pp::join(bindings, pp::text(",").append(pp::line())).nest(4).group());
+                }
+                if let Some(otherwise) = otherwise {
+                    doc = doc.append(pp::hardline()).append(pp::text("if the guard fails:"));
+                    doc = doc.append(pp::hardline().append(otherwise.doc()).nest(2));
+                }
+                doc
+            }
+            Tree::Switch { occurrence, cases, default } => {
+                let cases = cases.iter()
+                    .map(|(test, tree)| (test.to_string(), tree))
+                    .chain(default.as_deref().map(|tree| ("_".to_owned(), tree)))
+                    .map(|(test, tree)| {
+                        let case = pp::text(format!("{} =>", test)).append(pp::hardline().append(tree.doc()).nest(2));
+                        pp::hardline().append(case).nest(2)
+                    });
+                pp::text(format!("match {}", show(occurrence))).append(pp::concat(cases))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.doc().render(f.width().unwrap_or(80)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn compile_source(source: &str) -> Tree {
+        let program = parser::parse_program(source).unwrap();
+        let equations: Vec<_> = program.decls.iter()
+            .filter_map(|decl| match &decl.kind {
+                DeclKind::Equation(eq) => Some(eq),
+                _ => None,
+            })
+            .collect();
+        compile(&equations, &|name| ["leaf", "nil", "true", "false"].contains(&name))
+    }
+
+    // Every occurrence tested on the way from the root to each leaf
+    fn paths(tree: &Tree, tested: &mut Vec<Occurrence>, out: &mut Vec<Vec<Occurrence>>) {
+        match tree {
+            Tree::Fail => out.push(tested.clone()),
+            Tree::Leaf { otherwise, .. } => {
+                out.push(tested.clone());
+                if let Some(otherwise) = otherwise {
+                    paths(otherwise, tested, out);
+                }
+            }
+            Tree::Switch { occurrence, cases, default } => {
+                tested.push(occurrence.clone());
+                cases.iter().map(|(_, tree)| tree).chain(default.as_deref()).for_each(|tree| paths(tree, tested, out));
+                tested.pop();
+            }
+        }
+    }
+
+    #[test]
+    fn should_test_each_part_of_nested_patterns_once() {
+        let tree = compile_source("
+            --- depth (node (node (node (a, x, b), y, c), z, d)) <= 3;
+            --- depth (node (node (leaf, y, c), z, d)) <= 2;
+            --- depth (node (leaf, z, d)) <= 1;
+            --- depth leaf <= 0;
+        ");
+        let Tree::Switch { occurrence, cases, default: None } = &tree else { panic!("{}", tree) };
+        assert_eq!((occurrence, cases.len()), (&vec![0], 2));
+
+        let mut all = Vec::new();
+        paths(&tree, &mut Vec::new(), &mut all);
+        for path in &all {
+            let mut unique = path.clone();
+            unique.dedup();
+            assert_eq!(&unique, path, "{}", tree);
+        }
+        assert_eq!(all.iter().map(Vec::len).max(), Some(3));
+    }
+
+    #[test]
+    fn should_print_occurrences_bindings_and_guards() {
+        let tree = compile_source("--- f (x :: _) if x > 0 <= x;\n--- f l <= 0;");
+        assert_eq!(tree.to_string(), concat!(
+            "match $1\n",
+            "  :: =>\n",
+            "    equation 1 with x = $1.1.1\n",
+            "    if the guard fails:\n",
+            "      equation 2 with l = $1\n",
+            "  _ =>\n",
+            "    equation 2 with l = $1\n",
+        ));
+
+        // Bindings that don't fit in the width go one to a line
+        let tree = compile_source("--- g (first, second, third, fourth) <= first;");
+        assert_eq!(format!("{:40}", tree), concat!(
+            "equation 1 with first = $1.1,\n",
+            "    second = $1.2.1,\n",
+            "    third = $1.2.2.1,\n",
+            "    fourth = $1.2.2.2\n",
+        ));
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use super::value::{is_symbolic, Value};
+
+// Values longer than this are shown cut short in a difference
+const SHOWN_CHARS: usize = 60;
+
+// Where two values first differ, as the way down to it from the outside, and what
+// each has there, quoted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub path: Vec<String>,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "the values differ")
+        } else {
+            write!(f, "the values differ at {}", self.path.join(", then "))
+        }
+    }
+}
+
+// How `expected` and `actual` first differ, going into lists, tuples and constructors
+// they agree on, or None if they are equal
+pub fn diff(expected: &Value, actual: &Value) -> Option<Difference> {
+    let mut path = Vec::new();
+    let (mut expected, mut actual) = (expected, actual);
+    loop {
+        if expected.equals(actual) {
+            return None;
+        }
+        let step = match (parts(expected), parts(actual)) {
+            (Some((what, l)), Some((other, r))) if what == other => {
+                match l.iter().zip(&r).position(|(l, r)| !l.equals(r)) {
+                    Some(i) => {
+                        path.push(what.step(i));
+                        (l[i], r[i])
+                    }
+                    // Only a list can have more parts, one ran out before the other did
+                    None => {
+                        let i = l.len().min(r.len());
+                        path.push(what.step(i));
+                        let show = |items: &[&Value]| items.get(i).map_or("the end of the list".to_owned(), |item| quote(item));
+                        return Some(Difference { path, expected: show(&l), actual: show(&r) });
+                    }
+                }
+            }
+            _ => return Some(Difference { path, expected: quote(expected), actual: quote(actual) }),
+        };
+        (expected, actual) = step;
+    }
+}
+
+#[derive(PartialEq)]
+enum Parts<'a> {
+    List,
+    Tuple(usize),
+    Constructor(&'a str, usize),
+}
+
+impl Parts<'_> {
+    fn step(&self, i: usize) -> String {
+        match self {
+            Parts::List => format!("item {} of the list", i + 1),
+            Parts::Tuple(_) => format!("component {} of the tuple", i + 1),
+            Parts::Constructor(name, _) => format!("argument {} of `{}`", i + 1, name),
+        }
+    }
+}
+
+// What a value is made of, the way it prints: the items of a list, the components of
+// a tuple, or the arguments of a constructor given a tuple of them
+fn parts(value: &Value) -> Option<(Parts<'_>, Vec<&Value>)> {
+    if let Some(items) = value.as_list() {
+        return Some((Parts::List, items));
+    }
+    match value {
+        Value::Pair(_) => {
+            let components = components(value);
+            Some((Parts::Tuple(components.len()), components))
+        }
+        Value::Data(d) if !d.args.is_empty() => {
+            let args = match &d.args[..] {
+                [arg @ Value::Pair(_)] if !is_symbolic(&d.name) => components(arg),
+                [Value::Pair(cell)] => vec![&cell.0, &cell.1],
+                args => args.iter().collect(),
+            };
+            Some((Parts::Constructor(&d.name, args.len()), args))
+        }
+        _ => None,
+    }
+}
+
+fn components(value: &Value) -> Vec<&Value> {
+    let mut components = Vec::new();
+    let mut rest = value;
+    while let Value::Pair(cell) = rest {
+        components.push(&cell.0);
+        rest = &cell.1;
+    }
+    components.push(rest);
+    components
+}
+
+fn quote(value: &Value) -> String {
+    let shown = value.to_string();
+    if shown.chars().count() <= SHOWN_CHARS {
+        return format!("`{}`", shown);
+    }
+    format!("`{} ...`", shown.chars().take(SHOWN_CHARS).collect::<String>())
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+use crate::syntax::token::Pos;
+use crate::printf::FormatError;
+use super::diff::Difference;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    // No equation of the function, or rule of the lambda, matched its argument
+    NoMatch(String, Pos),
+    UnboundVariable(String, Pos),
+    NotAFunction(Pos),
+    // A builtin was given an argument of the wrong shape, which a checked program
+    // can't do
+    BadArgument(&'static str, Pos),
+    DivisionByZero(Pos),
+    // Under strict numerics, an operator given a whole and a fractional number, or
+    // `div` or `mod` given a fractional one
+    MixedNumbers(&'static str, Pos),
+    NotWhole(&'static str, Pos),
+    // An integer result outside the range of i64, when the build checks for that
+    Overflow(&'static str, Pos),
+    // One of the interpreter's limits ran out, at the expression it would have
+    // evaluated next
+    StepLimit(Pos),
+    DepthLimit(Pos),
+    Timeout(Pos),
+    // The host's Meter said to stop
+    Aborted(Pos),
+    UnknownEntryPoint(String),
+    // A host function returned an error, with its message
+    Host(String, String, Pos),
+    AssertionFailed(Box<Difference>, Pos),
+    // A call of `format` whose arguments don't suit its format string
+    BadFormat(FormatError, Pos),
+}
+
+impl EvalError {
+    pub fn pos(&self) -> Option<&Pos> {
+        match self {
+            EvalError::NoMatch(_, pos)
+            | EvalError::UnboundVariable(_, pos)
+            | EvalError::NotAFunction(pos)
+            | EvalError::BadArgument(_, pos)
+            | EvalError::DivisionByZero(pos)
+            | EvalError::MixedNumbers(_, pos)
+            | EvalError::NotWhole(_, pos)
+            | EvalError::Overflow(_, pos)
+            | EvalError::StepLimit(pos)
+            | EvalError::DepthLimit(pos)
+            | EvalError::Timeout(pos)
+            | EvalError::Aborted(pos)
+            | EvalError::Host(_, _, pos)
+            | EvalError::AssertionFailed(_, pos)
+            | EvalError::BadFormat(_, pos) => Some(pos),
+            EvalError::UnknownEntryPoint(_) => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvalError::NoMatch(..) => "E0401",
+            EvalError::UnboundVariable(..) => "E0402",
+            EvalError::NotAFunction(_) => "E0403",
+            EvalError::BadArgument(..) => "E0404",
+            EvalError::DivisionByZero(_) => "E0405",
+            EvalError::StepLimit(_) => "E0406",
+            EvalError::DepthLimit(_) => "E0407",
+            EvalError::Timeout(_) => "E0408",
+            EvalError::UnknownEntryPoint(_) => "E0409",
+            EvalError::MixedNumbers(..) => "E0410",
+            EvalError::NotWhole(..) => "E0411",
+            EvalError::Overflow(..) => "E0412",
+            EvalError::Host(..) => "E0413",
+            EvalError::AssertionFailed(..) => "E0414",
+            EvalError::BadFormat(..) => "E0415",
+            EvalError::Aborted(_) => "E0416",
+        }
+    }
+
+    // What else there is to say, one note per line
+    pub fn notes(&self) -> Vec<String> {
+        match self {
+            EvalError::AssertionFailed(difference, _) => {
+                vec![format!("expected {}", difference.expected), format!("found {}", difference.actual)]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::NoMatch(name, _) => write!(f, "no equation of `{}` matches its argument", name),
+            EvalError::UnboundVariable(name, _) => write!(f, "unbound identifier `{}`", name),
+            EvalError::NotAFunction(_) => write!(f, "applied a value that is not a function"),
+            EvalError::BadArgument(name, _) => write!(f, "bad argument to `{}`", name),
+            EvalError::DivisionByZero(_) => write!(f, "division by zero"),
+            EvalError::MixedNumbers(name, _) => write!(f, "`{}` given a whole and a fractional number", name),
+            EvalError::NotWhole(name, _) => write!(f, "`{}` takes whole numbers", name),
+            EvalError::Overflow(name, _) => write!(f, "`{}` overflowed 64 bit integers", name),
+            EvalError::StepLimit(_) => write!(f, "evaluation took too many steps"),
+            EvalError::DepthLimit(_) => write!(f, "evaluation nested too deeply"),
+            EvalError::Timeout(_) => write!(f, "evaluation took too long"),
+            EvalError::Aborted(_) => write!(f, "evaluation was stopped by the host"),
+            EvalError::UnknownEntryPoint(name) => write!(f, "no definition of `{}` to run", name),
+            EvalError::Host(name, message, _) => write!(f, "`{}` failed: {}", name, message),
+            EvalError::AssertionFailed(difference, _) => write!(f, "assertion failed, {}", difference),
+            EvalError::BadFormat(e, _) => write!(f, "bad format: {}", e),
+        }
+    }
+}
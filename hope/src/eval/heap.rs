@@ -0,0 +1,160 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem::size_of;
+use std::rc::Rc;
+use crate::eval::{Env, Frame, Function, Value};
+
+// The cells a run holds on to at one step, found from what outlives any one call: the
+// shared values of constants, with the tables of any `memo` among them, and the local
+// variables of the expression being evaluated. Each cell is counted once, under the
+// first of these that reaches it, constants in order of name and then the locals.
+// What a call is still working on, its arguments and results on the way back, is
+// only found if a constant or the locals reach it too
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub step: u64,
+    // By bytes, most first
+    pub groups: Vec<Group>,
+}
+
+// The cells made by one constructor that one definition holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub retainer: String,
+    pub constructor: String,
+    pub cells: u64,
+    // Of the cells and what they point to directly, as laid out in memory, without
+    // what the allocator adds
+    pub bytes: u64,
+}
+
+#[derive(Default)]
+struct Walk {
+    seen: HashSet<usize>,
+    groups: HashMap<(String, String), (u64, u64)>,
+}
+
+impl Walk {
+    // Whether the cell at this address is new, counting it if it is
+    fn count<T>(&mut self, retainer: &str, cell: &Rc<T>, constructor: &str, bytes: usize) -> bool {
+        if !self.seen.insert(Rc::as_ptr(cell) as *const () as usize) {
+            return false;
+        }
+        let group = self.groups.entry((retainer.to_owned(), constructor.to_owned())).or_default();
+        group.0 += 1;
+        group.1 += bytes as u64;
+        true
+    }
+
+    // Without recursing, since a long list is a long chain of cells
+    fn walk(&mut self, retainer: &str, roots: Vec<Value>, frames: Vec<Rc<Frame>>) {
+        let (mut values, mut frames) = (roots, frames);
+        loop {
+            if let Some(frame) = frames.pop() {
+                let bytes = size_of::<Frame>() + size_of_val(&*frame.slots);
+                if self.count(retainer, &frame, "frame", bytes) {
+                    values.extend(frame.slots.iter().filter_map(|slot| slot.get().cloned()));
+                    frames.extend(frame.rest.innermost());
+                }
+                continue;
+            }
+            let Some(value) = values.pop() else { break };
+            match &value {
+                Value::Num(_) | Value::Int(_) | Value::Char(_) => {}
+                Value::Big(big) => {
+                    self.count(retainer, big, "big integer", big.size());
+                }
+                Value::Pair(cell) => {
+                    if self.count(retainer, cell, "#", size_of_val(&**cell)) {
+                        values.extend([cell.0.clone(), cell.1.clone()]);
+                    }
+                }
+                Value::Data(d) => {
+                    if self.count(retainer, d, &d.name, size_of_val(&**d) + size_of_val(&d.args[..])) {
+                        values.extend(d.args.iter().cloned());
+                    }
+                }
+                Value::Function(fun) => {
+                    let (kind, args): (&str, &[Value]) = match &**fun {
+                        Function::Closure(..) => ("closure", &[]),
+                        Function::Equations(_, args) | Function::Constructor(_, _, args) | Function::Host(_, args) => ("partial application", args),
+                        Function::Builtin(..) => ("builtin", &[]),
+                        Function::Memo(..) => ("memo", &[]),
+                    };
+                    if !self.count(retainer, fun, kind, size_of_val(&**fun) + size_of_val(args)) {
+                        continue;
+                    }
+                    values.extend(args.iter().cloned());
+                    match &**fun {
+                        Function::Closure(_, env) => frames.extend(env.innermost()),
+                        Function::Memo(fun, table) => {
+                            values.push(fun.clone());
+                            values.extend(table.entries().into_iter().flat_map(|(arg, result)| [arg, result]));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Snapshot {
+    // The constants by name, and the description of the locals with where they are
+    pub(crate) fn take<'a>(step: u64, constants: impl IntoIterator<Item = (&'a str, Value)>, locals: Option<(String, &Env)>) -> Self {
+        let mut walk = Walk::default();
+        let mut constants: Vec<_> = constants.into_iter().collect();
+        constants.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in constants {
+            walk.walk(name, vec![value], Vec::new());
+        }
+        if let Some((name, env)) = locals {
+            walk.walk(&name, Vec::new(), env.innermost().into_iter().collect());
+        }
+        let mut groups: Vec<Group> = walk.groups.into_iter()
+            .map(|((retainer, constructor), (cells, bytes))| Group { retainer, constructor, cells, bytes })
+            .collect();
+        groups.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| (&a.retainer, &a.constructor).cmp(&(&b.retainer, &b.constructor))));
+        Snapshot { step, groups }
+    }
+
+    pub fn cells(&self) -> u64 {
+        self.groups.iter().map(|group| group.cells).sum()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.groups.iter().map(|group| group.bytes).sum()
+    }
+
+    // As a graph for Graphviz, each definition a box with an edge to each constructor
+    // it holds cells of
+    pub fn dot(&self) -> String {
+        let mut out = format!("digraph heap {{\n  label = \"step {}\";\n  rankdir = LR;\n", self.step);
+        let mut nodes: Vec<(&str, &str)> = self.groups.iter()
+            .flat_map(|group| [("box", group.retainer.as_str()), ("ellipse", group.constructor.as_str())])
+            .collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        // A constructor may have the name of a definition, so the two are told apart
+        for (shape, name) in nodes {
+            out.push_str(&format!("  {:?} [shape = {}, label = {:?}];\n", format!("{} {}", shape, name), shape, name));
+        }
+        for group in &self.groups {
+            let (from, to) = (format!("box {}", group.retainer), format!("ellipse {}", group.constructor));
+            out.push_str(&format!("  {:?} -> {:?} [label = \"{} cells, {} bytes\"];\n", from, to, group.cells, group.bytes));
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "heap at step {}: {} cells, {} bytes", self.step, self.cells(), self.bytes())?;
+        write!(f, "{:>12} {:>12}  constructor, held by", "cells", "bytes")?;
+        for group in &self.groups {
+            write!(f, "\n{:>12} {:>12}  {}, {}", group.cells, group.bytes, group.constructor, group.retainer)?;
+        }
+        Ok(())
+    }
+}
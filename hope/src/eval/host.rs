@@ -0,0 +1,73 @@
+use std::fmt;
+use std::rc::Rc;
+use crate::eval::Value;
+use crate::syntax::token::Pos;
+use crate::types::{Checker, Scheme, Type, TypeError};
+
+// What a host function does with all of its arguments, or why it couldn't
+pub type HostFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+// A function written in Rust that programs call by name like any other. It takes
+// its arguments curried, one application each, and runs once it has them all
+#[derive(Clone)]
+pub struct Native {
+    pub name: String,
+    pub arity: usize,
+    pub scheme: Scheme,
+    pub run: Rc<HostFn>,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Native({:?}, {}, {})", self.name, self.arity, self.scheme)
+    }
+}
+
+// Host functions for the checker and interpreter to add to the builtins, see
+// Checker::with_builtins and Interpreter::with_builtins. Programs can still define
+// their own function of the same name, which hides the host's
+#[derive(Debug, Clone, Default)]
+pub struct Builtins {
+    functions: Vec<Native>,
+}
+
+impl Builtins {
+    pub fn new() -> Self {
+        Builtins::default()
+    }
+
+    // The signature is a Hope type over the builtin types, with alpha, beta and gamma
+    // as type variables, and must take at least arity arguments. Registering a name
+    // again replaces it. A signature that isn't one leaves the registry as it was
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: usize,
+        signature: &str,
+        run: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) -> Result<&mut Self, TypeError> {
+        let scheme = Checker::host_scheme(name, signature)?;
+        if arguments(&scheme.ty) < arity {
+            let message = format!("takes {} arguments but its type is {}", arity, scheme);
+            let pos = Pos { line: 1, column: 1, range: 0..signature.len() };
+            return Err(TypeError::HostSignature { name: name.to_owned(), message, pos });
+        }
+        let native = Native { name: name.to_owned(), arity, scheme, run: Rc::new(run) };
+        match self.functions.iter_mut().find(|f| f.name == name) {
+            Some(existing) => *existing = native,
+            None => self.functions.push(native),
+        }
+        Ok(self)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Native> {
+        self.functions.iter()
+    }
+}
+
+fn arguments(ty: &Type) -> usize {
+    match ty {
+        Type::Con(arrow, items) if arrow == "->" => 1 + arguments(&items[1]),
+        _ => 0,
+    }
+}
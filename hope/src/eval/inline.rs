@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use crate::syntax::ast::*;
+use crate::eval::Profile;
+use crate::eval::resolve::{Code, CodeKind, Var};
+
+// What -O does to resolved code. A call of a small function that has one equation,
+// no guard and patterns that can't fail becomes a `let` of the arguments around the
+// body, which only uses the frame of those patterns, so it means the same wherever it
+// goes. A call of any other function with all its arguments passes them at once
+// instead of one at a time. Given a profile, the calls it saw most are inlined first,
+// and those it never saw not at all
+
+// Inlining grows each program loaded by at most this many nodes
+const BUDGET: usize = 20_000;
+// Functions with larger bodies are always called
+const LARGEST: usize = 40;
+
+const STACK_RED_ZONE: usize = 64 * 1024;
+const STACK_GROWTH: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Callee {
+    // The patterns of the arguments, a tuple of them for more than one
+    pattern: Pattern,
+    body: Code,
+}
+
+// The function as inlining takes it, if it can be
+pub fn callee(name: &str, args: &[Pattern], guard: Option<&Code>, body: &Code, is_constructor: &dyn Fn(&str) -> bool) -> Option<Callee> {
+    let irrefutable = args.iter().all(|arg| irrefutable(arg, is_constructor));
+    if guard.is_some() || !irrefutable || size(body) > LARGEST || mentions(body, name) {
+        return None;
+    }
+    let pattern = match args {
+        [arg] => arg.clone(),
+        _ => Pattern { kind: PatternKind::Tuple(args.to_vec()), pos: args.first()?.pos.clone() },
+    };
+    Some(Callee { pattern, body: body.clone() })
+}
+
+fn irrefutable(pattern: &Pattern, is_constructor: &dyn Fn(&str) -> bool) -> bool {
+    match &pattern.kind {
+        PatternKind::Var(name) => !is_constructor(name),
+        PatternKind::Wildcard => true,
+        PatternKind::As(_, inner) => irrefutable(inner, is_constructor),
+        PatternKind::Tuple(items) => items.iter().all(|item| irrefutable(item, is_constructor)),
+        _ => false,
+    }
+}
+
+pub struct Inliner {
+    // How many arguments each function takes
+    arities: HashMap<String, usize>,
+    callees: HashMap<String, Callee>,
+    // The sites chosen to inline, by function and where they start
+    chosen: HashSet<(String, usize)>,
+    // What is left of the budget, and the functions being inlined, which aren't again
+    // inside themselves
+    left: usize,
+    within: Vec<String>,
+}
+
+impl Inliner {
+    pub fn new(arities: HashMap<String, usize>, callees: HashMap<String, Callee>) -> Self {
+        Inliner { arities, callees, chosen: HashSet::new(), left: BUDGET, within: Vec::new() }
+    }
+
+    // Chooses the calls to inline in all of the code and the bodies it may inline,
+    // within the budget
+    pub fn plan(&mut self, code: &[&Code], profile: Option<&Profile>) {
+        let mut sites = Vec::new();
+        for code in code.iter().copied().chain(self.callees.values().map(|callee| &callee.body)) {
+            self.sites(code, &mut sites);
+        }
+        if let Some(profile) = profile {
+            sites.retain(|(name, offset, _)| profile.calls(name, *offset) > 0);
+            sites.sort_by_key(|(name, offset, _)| std::cmp::Reverse(profile.calls(name, *offset)));
+        }
+        let mut left = BUDGET;
+        for (name, offset, size) in sites {
+            if size <= left {
+                left -= size;
+                self.chosen.insert((name, offset));
+            }
+        }
+    }
+
+    // The functions chosen to be inlined somewhere
+    pub fn inlined(&self) -> impl Iterator<Item = &str> {
+        self.chosen.iter().map(|(name, _)| name.as_str())
+    }
+
+    fn site(&self, code: &Code) -> Option<(String, usize)> {
+        let (name, count) = match &code.kind {
+            CodeKind::BinOp(Var::Global(name), pos, ..) => return (self.arities.get(name) == Some(&1)).then(|| (name.clone(), pos.range.start)),
+            CodeKind::Apply(..) => spine(code)?,
+            _ => return None,
+        };
+        (self.arities.get(name) == Some(&count)).then(|| (name.to_owned(), code.pos.range.start))
+    }
+
+    fn sites(&self, code: &Code, out: &mut Vec<(String, usize, usize)>) {
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || self.sites_in(code, out));
+    }
+
+    fn sites_in(&self, code: &Code, out: &mut Vec<(String, usize, usize)>) {
+        let site = self.site(code).and_then(|(name, offset)| Some((size(&self.callees.get(&name)?.body), name, offset)));
+        out.extend(site.map(|(size, name, offset)| (name, offset, size)));
+        children(code).into_iter().for_each(|child| self.sites(child, out));
+    }
+
+    // Inlines the calls chosen and passes every other call of a function of more than
+    // one argument all of them at once
+    pub fn rewrite(&mut self, code: &mut Code) {
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || self.rewrite_in(code));
+    }
+
+    fn rewrite_in(&mut self, code: &mut Code) {
+        children_mut(code).into_iter().for_each(|child| self.rewrite(child));
+        let Some((name, offset)) = self.site(code) else { return };
+        let chosen = self.chosen.contains(&(name.clone(), offset)) && !self.within.contains(&name);
+        let callee = self.callees.get(&name).filter(|callee| chosen && size(&callee.body) <= self.left).cloned();
+        let uncurry = matches!(&code.kind, CodeKind::Apply(fun, _) if matches!(fun.kind, CodeKind::Apply(..)));
+        if callee.is_none() && !uncurry {
+            return;
+        }
+        let pos = code.pos.clone();
+        let mut args = match std::mem::replace(&mut code.kind, CodeKind::Int(0)) {
+            CodeKind::BinOp(_, _, l, r) => vec![Code { kind: CodeKind::Tuple(vec![*l, *r]), pos: pos.clone() }],
+            kind => unwind(Code { kind, pos: pos.clone() }),
+        };
+        code.kind = match callee {
+            Some(callee) => {
+                let value = match args.len() {
+                    1 => args.pop().expect("one argument"),
+                    _ => Code { kind: CodeKind::Tuple(args), pos },
+                };
+                // The calls in the body are inlined as chosen in it
+                let mut body = callee.body;
+                self.left -= size(&body);
+                self.within.push(name);
+                self.rewrite(&mut body);
+                self.within.pop();
+                CodeKind::Let { rec: false, pattern: callee.pattern, value: Box::new(value), body: Box::new(body) }
+            }
+            None => CodeKind::Call(name, args),
+        };
+    }
+}
+
+// The global at the head of an application and how many arguments it is given
+fn spine(code: &Code) -> Option<(&str, usize)> {
+    let mut count = 0;
+    let mut head = code;
+    while let CodeKind::Apply(fun, _) = &head.kind {
+        count += 1;
+        head = fun;
+    }
+    match &head.kind {
+        CodeKind::Var(Var::Global(name)) => Some((name, count)),
+        _ => None,
+    }
+}
+
+fn unwind(code: Code) -> Vec<Code> {
+    let mut args = Vec::new();
+    let mut head = code;
+    while let CodeKind::Apply(fun, arg) = head.kind {
+        args.push(*arg);
+        head = *fun;
+    }
+    args.reverse();
+    args
+}
+
+fn children(code: &Code) -> Vec<&Code> {
+    match &code.kind {
+        CodeKind::Var(_) | CodeKind::Int(_) | CodeKind::BigInt(_) | CodeKind::Num(_) | CodeKind::Str(_) => Vec::new(),
+        CodeKind::Tuple(items) | CodeKind::List(items) | CodeKind::Call(_, items) => items.iter().collect(),
+        CodeKind::Apply(fun, arg) => vec![fun, arg],
+        CodeKind::BinOp(_, _, l, r) => vec![l, r],
+        CodeKind::If(cond, then, other) => vec![cond, then, other],
+        CodeKind::Lambda(rules) => rules.iter().map(|rule| &rule.body).collect(),
+        CodeKind::Let { value, body, .. } => vec![value, body],
+    }
+}
+
+fn children_mut(code: &mut Code) -> Vec<&mut Code> {
+    match &mut code.kind {
+        CodeKind::Var(_) | CodeKind::Int(_) | CodeKind::BigInt(_) | CodeKind::Num(_) | CodeKind::Str(_) => Vec::new(),
+        CodeKind::Tuple(items) | CodeKind::List(items) | CodeKind::Call(_, items) => items.iter_mut().collect(),
+        CodeKind::Apply(fun, arg) => vec![fun, arg],
+        CodeKind::BinOp(_, _, l, r) => vec![l, r],
+        CodeKind::If(cond, then, other) => vec![cond, then, other],
+        CodeKind::Lambda(rules) => Rc::make_mut(rules).iter_mut().map(|rule| &mut rule.body).collect(),
+        CodeKind::Let { value, body, .. } => vec![value, body],
+    }
+}
+
+// Both walk the code with a stack of their own, as bodies can nest deeply
+fn size(code: &Code) -> usize {
+    let (mut size, mut left) = (0, vec![code]);
+    while let Some(code) = left.pop() {
+        size += 1;
+        left.extend(children(code));
+    }
+    size
+}
+
+fn mentions(code: &Code, name: &str) -> bool {
+    let mut left = vec![code];
+    while let Some(code) = left.pop() {
+        match &code.kind {
+            CodeKind::Var(Var::Global(global)) | CodeKind::BinOp(Var::Global(global), ..) | CodeKind::Call(global, _) if global == name => return true,
+            _ => left.extend(children(code)),
+        }
+    }
+    false
+}
@@ -0,0 +1,851 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use crate::prelude;
+use crate::syntax::ast::*;
+use crate::syntax::token::Pos;
+use crate::trace;
+use crate::eval::{builtins, BigInt, Builtin, Builtins, Coverage, Env, EvalError, Function, Native, Profile, Snapshot, Trace, Value};
+use crate::eval::inline::{self, Inliner};
+use crate::eval::decision::{self, Occurrence, Test, Tree};
+use crate::eval::resolve::{self, Body, Code, CodeKind, Var};
+
+type EResult<T> = Result<T, EvalError>;
+
+// Results each function made with `memo` keeps, unless told otherwise
+pub const DEFAULT_MEMO_CAPACITY: usize = 100_000;
+
+// Deeply recursive programs run on a stack that grows on demand, as in the parser
+const STACK_RED_ZONE: usize = 64 * 1024;
+const STACK_GROWTH: usize = 1024 * 1024;
+
+// Prelude functions that run natively, with the prelude functions each one's equations
+// use, while all of those are the prelude's own. See Interpreter::native
+const NATIVE: &[(&str, &[&str])] = &[
+    ("length", &["length"]),
+    ("<>", &["<>"]),
+    ("map", &["map"]),
+    ("reverse", &["reverse", "foldl"]),
+];
+
+// What evaluation may use before it stops with an error. Steps are counted across
+// everything the interpreter evaluates, time from each call to start_clock, and depth
+// bounds how much stack a single evaluation can take
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub steps: Option<u64>,
+    pub depth: Option<usize>,
+    pub time: Option<Duration>,
+}
+
+// What evaluation has used so far, as a Meter is shown it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub steps: u64,
+    // Steps left before the limit, with any that were granted, None without one
+    pub remaining: Option<u64>,
+    // How deep evaluation is nested where it was, which is what it holds on the stack
+    pub depth: usize,
+    // Since start_clock
+    pub elapsed: Duration,
+}
+
+// What a Meter says to do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metering {
+    Continue,
+    // Raise the step limit by this many steps
+    Grant(u64),
+    Abort,
+}
+
+pub type MeterFn = dyn Fn(&Usage) -> Metering;
+
+// A host's callback, shown the usage every so many steps, for charging for evaluation
+// or keeping a UI responsive
+#[derive(Clone)]
+pub struct Meter {
+    pub every: u64,
+    pub meter: Rc<MeterFn>,
+}
+
+impl Meter {
+    pub fn new(every: u64, meter: impl Fn(&Usage) -> Metering + 'static) -> Self {
+        assert!(every > 0, "a meter is shown the usage every at least one step");
+        Meter { every, meter: Rc::new(meter) }
+    }
+}
+
+impl fmt::Debug for Meter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Meter(every {} steps)", self.every)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Budget {
+    limits: Limits,
+    steps: Cell<u64>,
+    // Steps a meter added to the limit
+    granted: Cell<u64>,
+    depth: Cell<usize>,
+    started: Cell<Option<Instant>>,
+    deadline: Cell<Option<Instant>>,
+    meter: Option<Meter>,
+}
+
+impl Budget {
+    fn step_limit(&self) -> Option<u64> {
+        self.limits.steps.map(|limit| limit.saturating_add(self.granted.get()))
+    }
+}
+
+// Clones share one budget, so an environment and everything derived from it draw on
+// the same limits
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    // Constructor arities
+    constructors: HashMap<String, usize>,
+    functions: HashMap<String, Vec<Rc<Equation>>>,
+    // Each function's equations compiled into one decision tree
+    trees: HashMap<String, Rc<Tree>>,
+    // And each equation with its variables resolved, see eval::resolve
+    bodies: HashMap<String, Vec<Rc<Body>>>,
+    builtins: HashMap<&'static str, Builtin>,
+    hosts: HashMap<String, Rc<Native>>,
+    // The values of the definitions without arguments evaluated so far, each worked out
+    // once, unless sharing is off. Loading anything forgets them
+    constants: RefCell<HashMap<String, Value>>,
+    sharing: bool,
+    memo_capacity: usize,
+    budget: Rc<Budget>,
+    // Shared by clones, like the budget
+    coverage: Option<Rc<RefCell<Coverage>>>,
+    profile: Option<Rc<RefCell<Profile>>>,
+    trace: Option<Rc<RefCell<Trace>>>,
+    // The step to take a snapshot of the heap at, and the one taken
+    heap: Option<(u64, Rc<RefCell<Option<Snapshot>>>)>,
+    optimize: bool,
+    guide: Option<Rc<Profile>>,
+    // The functions inlined somewhere, which can't change without everything that
+    // calls them being resolved again
+    inlined: HashSet<String>,
+    native_prelude: bool,
+    // The functions of NATIVE whose equations are the prelude's as loaded
+    natives: HashSet<&'static str>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let constructors = [("true", 0), ("false", 0), ("nil", 0), ("::", 1), ("fnum", 1), ("fstr", 1)].iter()
+            .map(|&(name, arity)| (name.to_owned(), arity))
+            .collect();
+
+        Interpreter {
+            constructors,
+            functions: HashMap::new(),
+            trees: HashMap::new(),
+            bodies: HashMap::new(),
+            builtins: builtins::FUNCTIONS.iter().copied().collect(),
+            hosts: HashMap::new(),
+            constants: RefCell::default(),
+            sharing: true,
+            memo_capacity: DEFAULT_MEMO_CAPACITY,
+            budget: Rc::default(),
+            coverage: None,
+            profile: None,
+            trace: None,
+            heap: None,
+            optimize: false,
+            guide: None,
+            inlined: HashSet::new(),
+            native_prelude: true,
+            natives: HashSet::new(),
+        }
+    }
+
+    // Starts a fresh budget, not shared with any earlier clone
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.budget = Rc::new(Budget { limits, meter: self.budget.meter.clone(), ..Budget::default() });
+        self
+    }
+
+    // Starts a fresh budget under the same limits, with the meter shown the usage after
+    // every so many steps
+    pub fn with_meter(mut self, meter: Option<Meter>) -> Self {
+        self.budget = Rc::new(Budget { limits: self.budget.limits, meter, ..Budget::default() });
+        self
+    }
+
+    // Makes arithmetic and comparison fail on a whole and a fractional number rather
+    // than combining them, see eval::builtins
+    pub fn with_strict_numerics(mut self, strict: bool) -> Self {
+        if strict {
+            self.builtins.extend(builtins::STRICT_NUMERICS.iter().copied());
+        }
+        self
+    }
+
+    // Whether definitions without arguments are evaluated once and their value shared
+    // by every use, or evaluated again at each one
+    pub fn with_sharing(mut self, sharing: bool) -> Self {
+        self.sharing = sharing;
+        self
+    }
+
+    // How many results each function made with `memo` keeps. The table of one that is
+    // full is emptied to make room. A `memo` shared as a constant keeps its results
+    // for the whole run, so recursion through it is only worked out once per argument
+    pub fn with_memo_capacity(mut self, capacity: usize) -> Self {
+        self.memo_capacity = capacity;
+        self
+    }
+
+    // Whether to count the runs of each equation and `if`, see coverage
+    pub fn with_coverage(mut self, coverage: bool) -> Self {
+        self.coverage = coverage.then(Rc::default);
+        self
+    }
+
+    // Whether the prelude's length, <>, map and reverse run natively rather than through
+    // their equations, as they do unless a program defines them or foldl anew
+    pub fn with_native_prelude(mut self, native: bool) -> Self {
+        self.native_prelude = native;
+        self
+    }
+
+    // Whether to count the calls at each site, see profile
+    pub fn with_profiling(mut self, profiling: bool) -> Self {
+        self.profile = profiling.then(Rc::default);
+        self
+    }
+
+    // Whether to record each call and what it returned, see record
+    pub fn with_recording(mut self, recording: bool) -> Self {
+        self.trace = recording.then(Rc::default);
+        self
+    }
+
+    pub fn trace(&self) -> Option<Trace> {
+        self.trace.as_ref().map(|trace| trace.borrow().clone())
+    }
+
+    // The step to take a snapshot of the heap at, see heap
+    pub fn with_heap_snapshot(mut self, at: Option<u64>) -> Self {
+        self.heap = at.map(|at| (at, Rc::default()));
+        self
+    }
+
+    // The snapshot taken at the step asked for, or one taken now if the run stopped
+    // before it
+    pub fn heap_snapshot(&self) -> Option<Snapshot> {
+        let (_, taken) = self.heap.as_ref()?;
+        let snapshot = taken.borrow().clone();
+        Some(snapshot.unwrap_or_else(|| self.snapshot(None)))
+    }
+
+    fn snapshot(&self, locals: Option<(String, &Env)>) -> Snapshot {
+        let constants = self.constants.borrow();
+        Snapshot::take(self.steps(), constants.iter().map(|(name, value)| (name.as_str(), value.clone())), locals)
+    }
+
+    // Whether calls are inlined and uncurried where they can be, see eval::inline. Not
+    // while counting coverage, which needs the equations to run
+    pub fn with_optimization(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    // The profile of an earlier run, whose hottest calls are inlined first
+    pub fn with_guide(mut self, guide: Option<Profile>) -> Self {
+        self.guide = guide.map(Rc::new);
+        self
+    }
+
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile.as_ref().map(|profile| profile.borrow().clone())
+    }
+
+    pub fn coverage(&self) -> Option<Coverage> {
+        self.coverage.as_ref().map(|coverage| coverage.borrow().clone())
+    }
+
+    // The host functions, which take the place of any builtin of the same name
+    pub fn with_builtins(mut self, builtins: &Builtins) -> Self {
+        for native in builtins.iter() {
+            self.builtins.remove(native.name.as_str());
+            self.hosts.insert(native.name.clone(), Rc::new(native.clone()));
+        }
+        self
+    }
+
+    // Steps taken so far under the current budget
+    pub fn steps(&self) -> u64 {
+        self.budget.steps.get()
+    }
+
+    pub fn start_clock(&self) {
+        let now = Instant::now();
+        self.budget.started.set(Some(now));
+        if let Some(time) = self.budget.limits.time {
+            self.budget.deadline.set(Some(now + time));
+        }
+    }
+
+    // Adds the program's constructors and equations. Expressions are left to the
+    // caller to evaluate with eval
+    pub fn load(&mut self, program: &Program) {
+        // A constant can be worked out from anything the program redefines
+        self.constants.get_mut().clear();
+        let touched = |name: &Ident| self.inlined.contains(&name.name);
+        let stale = program.decls.iter().any(|decl| match &decl.kind {
+            DeclKind::Dec { names, .. } => names.iter().any(touched),
+            DeclKind::Equation(eq) => touched(&eq.name),
+            _ => false,
+        });
+        // A new `dec` starts the function afresh, rather than adding to its old equations
+        for decl in &program.decls {
+            if let DeclKind::Dec { names, .. } = &decl.kind {
+                for name in names {
+                    self.functions.remove(&name.name);
+                    self.trees.remove(&name.name);
+                    self.bodies.remove(&name.name);
+                }
+            }
+        }
+
+        for decl in &program.decls {
+            match &decl.kind {
+                DeclKind::Data { constructors, .. } => {
+                    for constructor in constructors {
+                        self.constructors.insert(constructor.name.name.clone(), constructor.args.len());
+                    }
+                }
+                DeclKind::Equation(eq) => {
+                    self.functions.entry(eq.name.name.clone()).or_default().push(Rc::new(eq.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        for decl in &program.decls {
+            if let DeclKind::Equation(eq) = &decl.kind {
+                let equations: Vec<_> = self.functions[&eq.name.name].iter().map(|eq| &**eq).collect();
+                let is_constructor = |name: &str| self.constructors.contains_key(name);
+                let tree = decision::compile(&equations, &is_constructor);
+                let arity = equations[0].args.len();
+                let bodies = equations.iter().map(|eq| Rc::new(resolve::equation(eq, arity, &is_constructor))).collect();
+                self.trees.insert(eq.name.name.clone(), Rc::new(tree));
+                self.bodies.insert(eq.name.name.clone(), bodies);
+            }
+        }
+
+        let is_prelude = |name: &str| {
+            let (Some(loaded), Some(shipped)) = (self.functions.get(name), prelude_functions().get(name)) else { return false };
+            loaded.iter().map(|eq| &**eq).eq(shipped)
+        };
+        self.natives = NATIVE.iter()
+            .filter(|(_, uses)| self.native_prelude && uses.iter().all(|name| is_prelude(name)))
+            .map(|&(name, _)| name)
+            .collect();
+
+        if self.optimize && self.coverage.is_none() {
+            let mut names: Vec<String> = Vec::new();
+            for decl in &program.decls {
+                match &decl.kind {
+                    DeclKind::Equation(eq) if !names.contains(&eq.name.name) => names.push(eq.name.name.clone()),
+                    _ => {}
+                }
+            }
+            if stale {
+                let is_constructor = |name: &str| self.constructors.contains_key(name);
+                let resolved = self.functions.iter().map(|(name, equations)| {
+                    let arity = equations[0].args.len();
+                    (name.clone(), equations.iter().map(|eq| Rc::new(resolve::equation(eq, arity, &is_constructor))).collect())
+                });
+                self.bodies = resolved.collect();
+                self.inlined.clear();
+                names = self.functions.keys().cloned().collect();
+            }
+            self.inline(&names);
+        }
+    }
+
+    // The inliner, with what it can inline of the functions loaded
+    fn inliner(&self) -> Inliner {
+        let is_constructor = |name: &str| self.constructors.contains_key(name);
+        let callees = self.functions.iter()
+            .filter(|(name, equations)| equations.len() == 1 && !self.natives.contains(name.as_str()))
+            .filter_map(|(name, equations)| {
+                let body = &self.bodies[name][0];
+                Some((name.clone(), inline::callee(name, &equations[0].args, body.guard.as_ref(), &body.body, &is_constructor)?))
+            })
+            .collect();
+        let arities = self.functions.iter().map(|(name, equations)| (name.clone(), equations[0].args.len())).collect();
+        Inliner::new(arities, callees)
+    }
+
+    fn inline(&mut self, names: &[String]) {
+        let _span = trace::span(module_path!(), "optimize", "functions", names.len());
+        let mut inliner = self.inliner();
+        let code: Vec<&Code> = names.iter()
+            .flat_map(|name| &self.bodies[name])
+            .flat_map(|body| body.guard.iter().chain([&body.body]))
+            .collect();
+        inliner.plan(&code, self.guide.as_deref());
+
+        for name in names {
+            for body in self.bodies.get_mut(name).expect("the function was loaded") {
+                let body = Rc::make_mut(body);
+                if let Some(guard) = &mut body.guard {
+                    inliner.rewrite(guard);
+                }
+                inliner.rewrite(&mut body.body);
+            }
+        }
+        self.inlined.extend(inliner.inlined().map(ToOwned::to_owned));
+    }
+
+    // How the function's equations are chosen between, for inspection
+    pub fn match_tree(&self, name: &str) -> Option<&Tree> {
+        self.trees.get(name).map(|tree| &**tree)
+    }
+
+    // Each equation of the function with its variables resolved, for inspection
+    pub fn resolved(&self, name: &str) -> Option<&[Rc<Body>]> {
+        self.bodies.get(name).map(Vec::as_slice)
+    }
+
+    pub fn eval(&self, expr: &Expr) -> EResult<Value> {
+        let mut code = resolve::expr(expr, &|name| self.constructors.contains_key(name));
+        if self.optimize && self.coverage.is_none() {
+            let mut inliner = self.inliner();
+            inliner.plan(&[&code], self.guide.as_deref());
+            inliner.rewrite(&mut code);
+        }
+        self.eval_in(&code, &Env::default())
+    }
+
+    // The value of a top level definition
+    pub fn entry(&self, name: &str) -> EResult<Value> {
+        if !self.functions.contains_key(name) {
+            return Err(EvalError::UnknownEntryPoint(name.to_owned()));
+        }
+        let pos = self.functions[name][0].name.pos.clone();
+        self.lookup(name, &pos)
+    }
+
+    fn eval_in(&self, expr: &Code, env: &Env) -> EResult<Value> {
+        let depth = &self.budget.depth;
+        if self.budget.limits.depth.is_some_and(|limit| depth.get() >= limit) {
+            return Err(EvalError::DepthLimit(expr.pos.clone()));
+        }
+        depth.set(depth.get() + 1);
+        let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || self.eval_kind(expr, env));
+        depth.set(depth.get() - 1);
+        result
+    }
+
+    fn eval_kind(&self, expr: &Code, env: &Env) -> EResult<Value> {
+        let budget = &self.budget;
+        let due = |meter: &&Meter| budget.steps.get() > 0 && budget.steps.get().is_multiple_of(meter.every);
+        if let Some(meter) = budget.meter.as_ref().filter(due) {
+            let usage = Usage {
+                steps: budget.steps.get(),
+                remaining: budget.step_limit().map(|limit| limit.saturating_sub(budget.steps.get())),
+                depth: budget.depth.get(),
+                elapsed: budget.started.get().map_or(Duration::ZERO, |started| started.elapsed()),
+            };
+            match (meter.meter)(&usage) {
+                Metering::Continue => {}
+                Metering::Grant(steps) => budget.granted.set(budget.granted.get().saturating_add(steps)),
+                Metering::Abort => return Err(EvalError::Aborted(expr.pos.clone())),
+            }
+        }
+        if budget.step_limit().is_some_and(|limit| budget.steps.get() >= limit) {
+            return Err(EvalError::StepLimit(expr.pos.clone()));
+        }
+        budget.steps.set(budget.steps.get() + 1);
+        if let Some((_, taken)) = self.heap.as_ref().filter(|(at, _)| *at == budget.steps.get()) {
+            let locals = format!("the locals at {}:{}", expr.pos.line, expr.pos.column);
+            *taken.borrow_mut() = Some(self.snapshot(Some((locals, env))));
+        }
+        if budget.deadline.get().is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(EvalError::Timeout(expr.pos.clone()));
+        }
+
+        match &expr.kind {
+            CodeKind::Var(var) => self.var(var, env, &expr.pos),
+            // Every number is a num for now, which is a float
+            CodeKind::Int(n) => Ok(Value::Int(*n)),
+            CodeKind::BigInt(digits) => Ok(big(digits)),
+            CodeKind::Num(n) => Ok(Value::Num(*n)),
+            CodeKind::Str(s) => Ok(string(s)),
+            CodeKind::Tuple(items) => {
+                let items = items.iter().map(|item| self.eval_in(item, env)).collect::<EResult<Vec<_>>>()?;
+                Ok(tuple(items))
+            }
+            CodeKind::List(items) => {
+                let items = items.iter().map(|item| self.eval_in(item, env)).collect::<EResult<Vec<_>>>()?;
+                Ok(Value::list(items.into_iter()))
+            }
+            CodeKind::Apply(fun, arg) => {
+                let fun = self.eval_in(fun, env)?;
+                let arg = self.eval_in(arg, env)?;
+                self.apply(fun, arg, &expr.pos)
+            }
+            CodeKind::BinOp(op, pos, l, r) => {
+                let fun = self.var(op, env, pos)?;
+                let arg = Value::pair(self.eval_in(l, env)?, self.eval_in(r, env)?);
+                self.apply(fun, arg, pos)
+            }
+            CodeKind::If(cond, then, other) => {
+                let taken = self.eval_in(cond, env)?.as_bool();
+                if let (Some(coverage), Some(taken)) = (&self.coverage, taken) {
+                    coverage.borrow_mut().branch(&expr.pos, taken);
+                }
+                match taken {
+                    Some(true) => self.eval_in(then, env),
+                    Some(false) => self.eval_in(other, env),
+                    None => Err(EvalError::BadArgument("if", cond.pos.clone())),
+                }
+            }
+            CodeKind::Lambda(rules) => Ok(Value::Function(Rc::new(Function::Closure(rules.clone(), env.clone())))),
+            CodeKind::Call(name, args) => {
+                let args = args.iter().map(|arg| self.eval_in(arg, env)).collect::<EResult<Vec<_>>>()?;
+                match self.functions.get(name) {
+                    Some(equations) if equations[0].args.len() == args.len() => {
+                        if let Some(profile) = &self.profile {
+                            profile.borrow_mut().call(name, &expr.pos);
+                        }
+                        self.dispatch(name, &args, &expr.pos)
+                    }
+                    // Defined anew since
+                    _ => args.into_iter().try_fold(self.lookup(name, &expr.pos)?, |fun, arg| self.apply(fun, arg, &expr.pos)),
+                }
+            }
+            CodeKind::Let { rec: true, pattern, value, body } => {
+                // The value is evaluated in the frame it binds into, so closures in it
+                // see the bindings once they are set
+                let mut slots = Vec::new();
+                resolve::binders(pattern, &|name| self.constructors.contains_key(name), &mut slots);
+                let scope = env.declare(slots.len());
+                let value = self.eval_in(value, &scope)?;
+                let mut vars = Vec::new();
+                if !self.matches(pattern, &value, &mut vars) {
+                    return Err(EvalError::NoMatch("letrec".to_owned(), pattern.pos.clone()));
+                }
+                let frame = scope.frame(0).expect("the frame just declared");
+                for (cell, value) in frame.slots.iter().zip(vars) {
+                    let _ = cell.set(value);
+                }
+                self.eval_in(body, &scope)
+            }
+            CodeKind::Let { pattern, value, body, .. } => {
+                let value = self.eval_in(value, env)?;
+                let mut vars = Vec::new();
+                if !self.matches(pattern, &value, &mut vars) {
+                    return Err(EvalError::NoMatch("let".to_owned(), pattern.pos.clone()));
+                }
+                self.eval_in(body, &env.push(vars))
+            }
+        }
+    }
+
+    fn var(&self, var: &Var, env: &Env, pos: &Pos) -> EResult<Value> {
+        match var {
+            Var::Local { depth, slot, name } => {
+                env.get(*depth, *slot).cloned().ok_or_else(|| EvalError::UnboundVariable(name.clone(), pos.clone()))
+            }
+            Var::Global(name) => self.lookup(name, pos),
+        }
+    }
+
+    // A name no pattern around it binds
+    fn lookup(&self, name: &str, pos: &Pos) -> EResult<Value> {
+        if let Some(equations) = self.functions.get(name) {
+            return match equations[0].args.len() {
+                0 => self.constant(name, pos),
+                _ => Ok(Value::Function(Rc::new(Function::Equations(name.to_owned(), Vec::new())))),
+            };
+        }
+        if let Some(&arity) = self.constructors.get(name) {
+            return match arity {
+                0 => Ok(Value::data(name, Vec::new())),
+                _ => Ok(Value::Function(Rc::new(Function::Constructor(name.to_owned(), arity, Vec::new())))),
+            };
+        }
+        if let Some((&name, &builtin)) = self.builtins.get_key_value(name) {
+            return Ok(Value::Function(Rc::new(Function::Builtin(name, builtin))));
+        }
+        if let Some(native) = self.hosts.get(name) {
+            return match native.arity {
+                0 => self.call_host(native, &[], pos),
+                _ => Ok(Value::Function(Rc::new(Function::Host(native.clone(), Vec::new())))),
+            };
+        }
+        Err(EvalError::UnboundVariable(name.to_owned(), pos.clone()))
+    }
+
+    fn constant(&self, name: &str, pos: &Pos) -> EResult<Value> {
+        if let Some(value) = self.constants.borrow().get(name) {
+            return Ok(value.clone());
+        }
+        let value = self.dispatch(name, &[], pos)?;
+        if self.sharing {
+            self.constants.borrow_mut().insert(name.to_owned(), value.clone());
+        }
+        Ok(value)
+    }
+
+    fn apply(&self, fun: Value, arg: Value, pos: &Pos) -> EResult<Value> {
+        let Value::Function(fun) = fun else {
+            return Err(EvalError::NotAFunction(pos.clone()));
+        };
+
+        match &*fun {
+            Function::Closure(rules, env) => {
+                for rule in rules.iter() {
+                    let mut vars = Vec::new();
+                    if self.matches(&rule.pattern, &arg, &mut vars) {
+                        return self.eval_in(&rule.body, &env.push(vars));
+                    }
+                }
+                Err(EvalError::NoMatch("lambda".to_owned(), pos.clone()))
+            }
+            Function::Equations(name, args) => {
+                let args: Vec<_> = args.iter().cloned().chain([arg]).collect();
+                if args.len() == self.functions[name][0].args.len() {
+                    if let Some(profile) = &self.profile {
+                        profile.borrow_mut().call(name, pos);
+                    }
+                    self.dispatch(name, &args, pos)
+                } else {
+                    Ok(Value::Function(Rc::new(Function::Equations(name.clone(), args))))
+                }
+            }
+            Function::Constructor(name, arity, args) => {
+                let args: Vec<_> = args.iter().cloned().chain([arg]).collect();
+                if args.len() == *arity {
+                    Ok(Value::data(name, args))
+                } else {
+                    Ok(Value::Function(Rc::new(Function::Constructor(name.clone(), *arity, args))))
+                }
+            }
+            Function::Builtin(_, builtin) => builtin(&arg, pos),
+            Function::Memo(fun, table) => {
+                let hash = arg.structural_hash();
+                if let Some(result) = table.get(hash, &arg) {
+                    return Ok(result);
+                }
+                let result = self.apply(fun.clone(), arg.clone(), pos)?;
+                table.insert(hash, arg, result.clone(), self.memo_capacity);
+                Ok(result)
+            }
+            Function::Host(native, args) => {
+                let args: Vec<_> = args.iter().cloned().chain([arg]).collect();
+                if args.len() == native.arity {
+                    self.call_host(native, &args, pos)
+                } else {
+                    Ok(Value::Function(Rc::new(Function::Host(native.clone(), args))))
+                }
+            }
+        }
+    }
+
+    fn call_host(&self, native: &Native, args: &[Value], pos: &Pos) -> EResult<Value> {
+        (native.run)(args).map_err(|message| EvalError::Host(native.name.clone(), message, pos.clone()))
+    }
+
+    fn dispatch(&self, name: &str, args: &[Value], pos: &Pos) -> EResult<Value> {
+        let Some(trace) = &self.trace else { return self.run_equations(name, args, pos) };
+        trace.borrow_mut().call(self.steps(), name, args, pos);
+        let result = self.run_equations(name, args, pos);
+        trace.borrow_mut().returned(&result);
+        result
+    }
+
+    // The first equation written that matches and whose guard holds, found by walking
+    // the function's decision tree
+    fn run_equations(&self, name: &str, args: &[Value], pos: &Pos) -> EResult<Value> {
+        if let Some(value) = self.native(name, args, pos) {
+            return Ok(value);
+        }
+        let no_match = || EvalError::NoMatch(name.to_owned(), pos.clone());
+        let mut tree = &*self.trees[name];
+        loop {
+            match tree {
+                Tree::Fail => return Err(no_match()),
+                Tree::Switch { occurrence, cases, default } => {
+                    let value = value_at(args, occurrence);
+                    let case = value.and_then(|value| cases.iter().find(|(test, _)| passes(test, value)));
+                    tree = match (case, default) {
+                        (Some((_, case)), _) => case,
+                        (None, Some(default)) => default,
+                        (None, None) => return Err(no_match()),
+                    };
+                }
+                Tree::Leaf { equation, bindings, otherwise } => {
+                    // The bindings are in the order of the slots
+                    let vars = bindings.iter()
+                        .map(|(_, occurrence)| value_at(args, occurrence).cloned())
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(no_match)?;
+                    let scope = Env::default().push(vars);
+                    let (eq, code) = (&self.functions[name][*equation], &self.bodies[name][*equation]);
+                    if let Some(guard) = &code.guard {
+                        match (self.eval_in(guard, &scope)?.as_bool(), otherwise) {
+                            (Some(true), _) => {}
+                            (Some(false), Some(otherwise)) => {
+                                tree = otherwise;
+                                continue;
+                            }
+                            (Some(false), None) => return Err(no_match()),
+                            (None, _) => return Err(EvalError::BadArgument("if", guard.pos.clone())),
+                        }
+                    }
+                    if let Some(coverage) = &self.coverage {
+                        coverage.borrow_mut().equation(name, &eq.name.pos);
+                    }
+                    return self.eval_in(&code.body, &scope);
+                }
+            }
+        }
+    }
+
+    // What the prelude function's equations give for the arguments, worked out without
+    // them, or None to leave them to the equations. That includes anything going wrong,
+    // so that errors come from where the equations would make them
+    fn native(&self, name: &str, args: &[Value], pos: &Pos) -> Option<Value> {
+        // Counting runs needs the equations to run
+        if self.coverage.is_some() || !self.natives.contains(name) {
+            return None;
+        }
+        match (name, args) {
+            ("length", [list]) => Some(Value::Int(list.as_list()?.len() as i64)),
+            ("<>", [Value::Pair(cell)]) => {
+                let (l, m) = (cell.0.as_list()?, cell.1.as_list()?);
+                Some(Value::list(l.into_iter().chain(m).cloned()))
+            }
+            ("map", [f, list]) => {
+                let items = list.as_list()?.into_iter().map(|item| self.apply(f.clone(), item.clone(), pos).ok());
+                Some(Value::list(items.collect::<Option<Vec<_>>>()?.into_iter()))
+            }
+            ("reverse", [list]) => Some(Value::list(list.as_list()?.into_iter().rev().cloned())),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, pattern: &Pattern, value: &Value, vars: &mut Vec<Value>) -> bool {
+        match (&pattern.kind, value) {
+            (PatternKind::Var(name), _) if self.constructors.contains_key(name) => {
+                matches!(value, Value::Data(d) if d.name == *name && d.args.is_empty())
+            }
+            (PatternKind::Var(_), _) => {
+                vars.push(value.clone());
+                true
+            }
+            (PatternKind::Wildcard, _) => true,
+            (PatternKind::As(_, pattern), _) => {
+                vars.push(value.clone());
+                self.matches(pattern, value, vars)
+            }
+            (PatternKind::Int(n), _) => Value::Int(*n).equals(value),
+            (PatternKind::BigInt(digits), _) => big(digits).equals(value),
+            (PatternKind::Num(n), _) => Value::Num(*n).equals(value),
+            (PatternKind::Str(s), _) => string(s).equals(value),
+            (PatternKind::Tuple(items), _) => {
+                let (last, init) = items.split_last().expect("tuples have at least two items");
+                let mut value = value;
+                for item in init {
+                    let Value::Pair(cell) = value else { return false };
+                    if !self.matches(item, &cell.0, vars) {
+                        return false;
+                    }
+                    value = &cell.1;
+                }
+                self.matches(last, value, vars)
+            }
+            (PatternKind::List(items), _) => match value.as_list() {
+                Some(values) if values.len() == items.len() => {
+                    items.iter().zip(values).all(|(p, v)| self.matches(p, v, vars))
+                }
+                _ => false,
+            },
+            (PatternKind::Construct(name, args), Value::Data(d)) => {
+                d.name == name.name && d.args.len() == args.len()
+                    && args.iter().zip(&d.args).all(|(p, v)| self.matches(p, v, vars))
+            }
+            (PatternKind::BinOp(op, l, r), Value::Data(d)) if d.name == op.name => match &d.args[..] {
+                [Value::Pair(cell)] => self.matches(l, &cell.0, vars) && self.matches(r, &cell.1, vars),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+}
+
+// The equations of each prelude function, as loaded from prelude::program
+fn prelude_functions() -> &'static HashMap<String, Vec<Equation>> {
+    static FUNCTIONS: OnceLock<HashMap<String, Vec<Equation>>> = OnceLock::new();
+    FUNCTIONS.get_or_init(|| {
+        let mut functions: HashMap<String, Vec<Equation>> = HashMap::new();
+        for decl in prelude::program().decls {
+            if let DeclKind::Equation(eq) = decl.kind {
+                functions.entry(eq.name.name.clone()).or_default().push(eq);
+            }
+        }
+        functions
+    })
+}
+
+// The part of the arguments an occurrence picks out, if they have that shape
+fn value_at<'v>(args: &'v [Value], occurrence: &Occurrence) -> Option<&'v Value> {
+    let (arg, fields) = occurrence.split_first()?;
+    let mut value = args.get(*arg)?;
+    for &i in fields {
+        value = match value {
+            Value::Data(d) => d.args.get(i)?,
+            Value::Pair(cell) if i == 0 => &cell.0,
+            Value::Pair(cell) => &cell.1,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+fn passes(test: &Test, value: &Value) -> bool {
+    match (test, value) {
+        (Test::Constructor(name), Value::Data(d)) => d.name == *name,
+        (Test::Int(n), _) => Value::Int(*n).equals(value),
+        (Test::Big(digits), _) => big(digits).equals(value),
+        (Test::Num(n), _) => Value::Num(*n).equals(value),
+        (Test::Char(c), Value::Char(d)) => c == d,
+        _ => false,
+    }
+}
+
+// The lexer only gives digits to big literals
+fn big(digits: &str) -> Value {
+    Value::big(BigInt::parse(digits).expect("a big literal is digits"))
+}
+
+fn string(s: &str) -> Value {
+    let chars: Vec<_> = s.chars().map(Value::Char).collect();
+    Value::list(chars.into_iter())
+}
+
+fn tuple(mut items: Vec<Value>) -> Value {
+    let last = items.pop().expect("tuples have at least two items");
+    items.into_iter().rev().fold(last, |r, l| Value::pair(l, r))
+}
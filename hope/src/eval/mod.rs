@@ -0,0 +1,502 @@
+mod bignum;
+mod builtins;
+mod convert;
+mod coverage;
+pub mod decision;
+mod diff;
+mod error;
+mod heap;
+mod host;
+mod inline;
+mod interp;
+mod profile;
+mod record;
+pub mod resolve;
+mod value;
+
+pub use bignum::BigInt;
+pub use coverage::{Coverage, FileCoverage, FunctionCoverage};
+pub use builtins::Overflow;
+pub use convert::{ConvertError, FromValue};
+pub use diff::{diff, Difference};
+pub use error::EvalError;
+pub use heap::{Group, Snapshot};
+pub use host::{Builtins, HostFn, Native};
+pub use interp::{Interpreter, Limits, Meter, MeterFn, Metering, Usage, DEFAULT_MEMO_CAPACITY};
+pub use profile::{Profile, Site};
+pub use record::{Event, Replay, Trace};
+pub use value::{Builtin, Data, Env, Frame, Function, MemoTable, Value};
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::*;
+    use crate::parser;
+    use crate::syntax::ast::ExprKind;
+    use crate::types::TypeError;
+
+    fn run(source: &str, expr: &str) -> Result<Value, EvalError> {
+        let mut interp = Interpreter::new();
+        interp.load(&parser::parse_program(source).unwrap());
+        interp.eval(&parser::parse_expr(expr).unwrap())
+    }
+
+    fn show(source: &str, expr: &str) -> String {
+        run(source, expr).unwrap().to_string()
+    }
+
+    #[test]
+    fn should_dispatch_equations_by_pattern() {
+        let source = "dec fact : num -> num;\n\
+            --- fact 0 <= 1;\n\
+            --- fact n <= n * fact (n - 1);\n\
+            dec <> : list alpha # list alpha -> list alpha;\n\
+            --- nil <> ys <= ys;\n\
+            --- (x :: xs) <> ys <= x :: (xs <> ys);";
+        assert_eq!(show(source, "fact 10"), "3628800");
+        assert_eq!(show(source, "[1, 2] <> [3]"), "[1, 2, 3]");
+        assert_eq!(show(source, "\"ab\" <> \"c\""), "\"abc\"");
+    }
+
+    #[test]
+    fn should_fall_through_guards_that_fail() {
+        let source = "dec sign : num -> num;\n\
+            --- sign n if n < 0 <= 0 - 1;\n\
+            --- sign 0 <= 0;\n\
+            --- sign n <= 1;";
+        assert_eq!(show(source, "[sign (0 - 5), sign 0, sign 7]"), "[-1, 0, 1]");
+        assert!(matches!(run("dec f : num -> num;\n--- f n if n > 0 <= n;", "f 0"), Err(EvalError::NoMatch(..))));
+    }
+
+    #[test]
+    fn should_bind_as_patterns_and_skip_wildcards() {
+        let source = "dec dup : list num -> list num;\n--- dup (l & (x :: _)) <= x :: l;\n--- dup _ <= [];";
+        assert_eq!(show(source, "(dup [1, 2], dup [])"), "([1, 1, 2], [])");
+    }
+
+    #[test]
+    fn should_evaluate_lambdas_lets_and_conditionals() {
+        assert_eq!(show("", "(lambda 0 => false | _ => true) 3"), "true");
+        assert_eq!(show("", "let (a, b) == (1, 2) in if a < b then (b, a) else (a, b)"), "(2, 1)");
+        assert_eq!(show("", "letrec f == lambda 0 => 0 | n => n + f (n - 1) in f 100"), "5050");
+        assert_eq!(show("", "x + y where x == 1 where y == 2"), "3");
+    }
+
+    #[test]
+    fn should_share_scopes_with_the_closures_made_in_them() {
+        let outer = Env::default().push(vec![Value::Int(1)]);
+        let inner = outer.push(vec![Value::Int(2), Value::Int(3)]);
+        assert_eq!(inner.get(0, 1).map(|x| x.to_string()).as_deref(), Some("3"));
+        assert_eq!(inner.get(1, 0).map(|x| x.to_string()).as_deref(), Some("1"));
+        assert!(core::ptr::eq(inner.frame(1).unwrap(), outer.frame(0).unwrap()));
+        assert!(inner.get(2, 0).is_none() && inner.get(0, 2).is_none());
+
+        let evens = "letrec (even, odd) == (lambda 0 => true | n => odd (n - 1), lambda 0 => false | n => even (n - 1)) in even 10";
+        assert_eq!(show("", evens), "true");
+    }
+
+    #[test]
+    fn should_build_and_match_constructors() {
+        let source = "data tree alpha == leaf ++ node (tree alpha # alpha # tree alpha);\n\
+            dec sum : tree num -> num;\n\
+            --- sum leaf <= 0;\n\
+            --- sum (node (l, x, r)) <= sum l + x + sum r;";
+        assert_eq!(show(source, "sum (node (node (leaf, 1, leaf), 2, leaf))"), "3");
+        assert_eq!(show(source, "node (leaf, 1, leaf)"), "node (leaf, 1, leaf)");
+
+        // Constructors of more than one argument are curried like equations
+        let source = "typevar alpha, beta;\ndata pair alpha beta == mkpair alpha beta;\n\
+            dec second : pair alpha beta -> beta;\n--- second (mkpair a b) <= b;";
+        assert_eq!(show(source, "second ((mkpair 1) \"b\")"), "\"b\"");
+        assert_eq!(show(source, "mkpair [1] true"), "mkpair [1] true");
+    }
+
+    #[test]
+    fn should_report_failed_matches() {
+        let source = "dec f : num -> num;\n--- f 0 <= 1;";
+        assert!(matches!(run(source, "f 1"), Err(EvalError::NoMatch(name, _)) if name == "f"));
+        assert!(matches!(run("", "1 div 0"), Err(EvalError::DivisionByZero(_))));
+    }
+
+    // The numeric semantics documented in eval::builtins
+    #[test]
+    fn should_divide_and_compare_numbers_as_specified() {
+        let cases = [
+            ("(7 div 2, (0 - 7) div 2, 7 div (0 - 2), (0 - 7) div (0 - 2))", "(3, -3, -3, 3)"),
+            ("(7 mod 2, (0 - 7) mod 2, 7 mod (0 - 2), (0 - 7) mod (0 - 2))", "(1, -1, 1, -1)"),
+            ("(7.5 div 2, 7.5 mod 2, (0 - 7.5) mod 2)", "(3.0, 1.5, -1.5)"),
+            ("(1 = 1.0, 1 < 1.5, 2 >= 1.5, 0 = 0 - 0)", "(true, true, true, true)"),
+            ("let n == 1e308 * 10 - 1e308 * 10 in (n = n, n /= n, n < n, n >= n, [n] = [n])", "(false, true, false, false, false)"),
+            ("let inf == 1e308 * 10 in (inf > 1e308, 0 - inf < 0 - 1e308, 1 / inf)", "(true, true, 0.0)"),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(show("", expr), expected, "{}", expr);
+        }
+        for expr in ["1 div 0", "1 mod 0", "1.5 div 0", "1 / 0"] {
+            assert!(matches!(run("", expr), Err(EvalError::DivisionByZero(_))), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn should_keep_whole_numbers_exact() {
+        let expr = "(9007199254740993 + 0, 9007199254740993 = 9007199254740992, 2 * 0.5, 7 / 2)";
+        assert_eq!(show("", expr), "(9007199254740993, false, 1.0, 3.5)");
+        let source = "dec fact : num -> num;\n--- fact 0 <= 1;\n--- fact n <= n * fact (n - 1);";
+        match Overflow::BUILD {
+            Overflow::Promote => assert_eq!(show(source, "fact 25 div fact 23"), "600"),
+            Overflow::Wrapping => assert_eq!(show(source, "fact 25"), "7034535277573963776"),
+            Overflow::Checked => assert!(matches!(run(source, "fact 25"), Err(EvalError::Overflow("*", _)))),
+        }
+        if Overflow::BUILD == Overflow::Promote {
+            let source = "dec f : num -> num;\n--- f 100000000000000000000000 <= 1;\n--- f _ <= 0;";
+            assert_eq!(show(source, "(99999999999999999999999 + 1, f (99999999999999999999999 + 1))"), "(100000000000000000000000, 1)");
+        }
+    }
+
+    #[test]
+    fn should_print_fractional_numbers_that_read_back_the_same() {
+        let printed = [1.0, 0.1, 1e19, 1e20, 1.5e300, 1e-7, 2.5e-300].map(|n| Value::Num(n).to_string());
+        assert_eq!(printed, ["1.0", "0.1", "10000000000000000000.0", "1e20", "1.5e300", "0.0000001", "2.5e-300"]);
+
+        for n in [1.0, 0.1, 2.0 / 3.0, 123456789.125, 1e19, 1e20, 1.5e300, f64::MAX, 1e-7, 3e-8, f64::MIN_POSITIVE, 5e-324] {
+            let printed = Value::Num(n).to_string();
+            let read = parser::parse_expr(&printed).unwrap();
+            assert!(matches!(read.kind, ExprKind::Num(m) if m == n), "{} printed as {}", n, printed);
+            assert!(show("", &printed).parse::<f64>().is_ok_and(|m| m == n), "{}", printed);
+        }
+    }
+
+    #[test]
+    fn should_refuse_to_mix_whole_and_fractional_numbers_when_strict() {
+        let strict = |expr| Interpreter::new().with_strict_numerics(true).eval(&parser::parse_expr(expr).unwrap());
+        assert_eq!(strict("(7 div 2, 1.5 + 2.5, 1 < 2, 0.5 = 0.5, [1] = [1.0])").unwrap().to_string(), "(3, 4.0, true, true, true)");
+        assert!(matches!(strict("1 + 0.5"), Err(EvalError::MixedNumbers("+", _))));
+        assert!(matches!(strict("1 < 1.5"), Err(EvalError::MixedNumbers("<", _))));
+        assert!(strict("1 = 1.0").is_ok());
+        assert!(matches!(strict("1 = 1.5"), Err(EvalError::MixedNumbers("=", _))));
+        assert!(matches!(strict("7.5 div 2"), Err(EvalError::NotWhole("div", _))));
+        assert!(matches!(strict("7 mod 0.5"), Err(EvalError::NotWhole("mod", _))));
+    }
+
+    #[test]
+    fn should_forget_constants_when_what_they_use_changes() {
+        let mut interp = Interpreter::new();
+        interp.load(&parser::parse_program("dec f : num;\n--- f <= 1;\ndec x : num;\n--- x <= f + 1;").unwrap());
+        let x = parser::parse_expr("x").unwrap();
+        assert_eq!(interp.eval(&x).unwrap().to_string(), "2");
+        interp.load(&parser::parse_program("dec f : num;\n--- f <= 10;").unwrap());
+        assert_eq!(interp.eval(&x).unwrap().to_string(), "11");
+    }
+
+    #[test]
+    fn should_remember_what_memo_functions_returned() {
+        let source = parser::parse_program("dec fib : num -> num;\n\
+            --- fib n <= if n < 2 then n else fast (n - 1) + fast (n - 2);\n\
+            dec fast : num -> num;\n--- fast <= memo fib;").unwrap();
+        let steps = |capacity, n: u32| {
+            let mut interp = Interpreter::new().with_memo_capacity(capacity).with_limits(Limits { steps: Some(10_000_000), ..Limits::default() });
+            interp.load(&source);
+            let value = interp.eval(&parser::parse_expr(&format!("fast {}", n)).unwrap()).unwrap();
+            (value.to_string(), interp.steps())
+        };
+        let (fib80, remembered) = steps(DEFAULT_MEMO_CAPACITY, 80);
+        assert_eq!(fib80, "23416728348467685");
+        assert!(remembered < 10_000, "{}", remembered);
+        let (fib20, forgotten) = steps(0, 20);
+        assert_eq!(fib20, "6765");
+        assert!(forgotten > 100_000, "{}", forgotten);
+    }
+
+    #[test]
+    fn should_recurse_deeply() {
+        let source = "dec count : num -> num;\n\
+            --- count 0 <= 0;\n\
+            --- count n <= 1 + count (n - 1);";
+        assert_eq!(show(source, "count 20000"), "20000");
+    }
+
+    #[test]
+    fn should_compare_and_drop_long_lists() {
+        let long = || Value::list((0..1_000_000).map(Value::Int));
+        let (a, b) = (long(), long());
+        assert!(a.equals(&b));
+        assert!(!a.equals(&Value::list((0..1_000_000).map(|n| Value::Int(n.min(999_998))))));
+        drop((a, b));
+
+        let mut interp = Interpreter::new();
+        interp.load(&crate::prelude::program());
+        let built = interp.eval(&parser::parse_expr("let l == iterate 30000 (lambda x => x) 0 in 1").unwrap());
+        assert_eq!(built.unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn should_hash_values_the_way_they_compare() {
+        let hash = |v: &Value| v.structural_hash();
+        assert_eq!(hash(&Value::Int(1)), hash(&Value::Num(1.0)));
+        assert_eq!(hash(&Value::Num(0.0)), hash(&Value::Num(-0.0)));
+        let long = || Value::list((0..1_000_000).map(Value::Int));
+        let (a, b) = (long(), long());
+        assert_eq!(hash(&a), hash(&b));
+        assert_ne!(hash(&a), hash(&Value::list((0..1_000_000).map(|n| Value::Int(n.min(999_998))))));
+        assert_ne!(hash(&Value::pair(Value::Int(1), Value::Int(2))), hash(&Value::pair(Value::Int(2), Value::Int(1))));
+
+        // A value shared between both sides is equal without comparing it, unless it
+        // can't even equal itself
+        assert!(a.equals(&a.clone()));
+        let nan = Value::list([Value::Num(f64::NAN)].into_iter());
+        assert!(!nan.equals(&nan.clone()));
+        let function = run("", "[lambda x => x]").unwrap();
+        assert!(!function.equals(&function.clone()));
+    }
+
+    #[test]
+    fn should_run_each_prelude_function() {
+        let mut interp = Interpreter::new();
+        interp.load(&crate::prelude::program());
+        let cases = [
+            ("(length [], length [1, 2, 3])", "(0, 3)"),
+            ("([1] <> [], [] <> [2], [1, 2] <> [3])", "([1], [2], [1, 2, 3])"),
+            ("map (lambda x => x * 2) [1, 2, 3]", "[2, 4, 6]"),
+            ("filter (lambda x => x > 1) [3, 1, 2]", "[3, 2]"),
+            ("foldl (lambda (s, x) => s - x) 10 [1, 2, 3]", "4"),
+            ("foldr (lambda (x, s) => x - s) 0 [1, 2, 3]", "2"),
+            ("(zip ([1, 2, 3], [4, 5]), zip ([1], []))", "([(1, 4), (2, 5)], [])"),
+            ("unzip (zip (\"ab\", [1, 2]))", "(\"ab\", [1, 2])"),
+            ("(take 2 [1, 2, 3], take 5 [1], take 0 [1], take (0 - 1) [1, 2])", "([1, 2], [1], [], [])"),
+            ("(drop 2 [1, 2, 3], drop 5 [1], drop 0 [1], drop (0 - 1) [1, 2])", "([3], [], [1], [1, 2])"),
+            ("reverse [1, 2, 3]", "[3, 2, 1]"),
+            ("insertby (lambda (x, y) => x < y) 2 [1, 3]", "[1, 2, 3]"),
+            ("sort (lambda (x, y) => x < y) [3, 1, 2, 1]", "[1, 1, 2, 3]"),
+            ("concatmap (lambda x => [x, x]) [1, 2]", "[1, 1, 2, 2]"),
+            ("(iterate 3 (lambda x => x * 2) 1, iterate 0 (lambda x => x) 1, iterate 1.5 (lambda x => x) 1)", "([1, 2, 4], [], [1, 1])"),
+            ("iterate (0 - 1) (lambda x => x) 1", "[]"),
+        ];
+        for (expr, expected) in cases {
+            let value = interp.eval(&parser::parse_expr(expr).unwrap());
+            assert_eq!(value.map(|value| value.to_string()).as_deref(), Ok(expected), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn should_run_the_preludes_own_functions_natively() {
+        let prelude = crate::prelude::program();
+        let run = |native: bool, program: &str, expr: &str| {
+            let mut interp = Interpreter::new().with_native_prelude(native);
+            interp.load(&prelude);
+            interp.load(&parser::parse_program(program).unwrap());
+            let value = interp.eval(&parser::parse_expr(expr).unwrap()).map(|value| value.to_string());
+            (value, interp.steps())
+        };
+        let expr = "(length (reverse [1, 2, 3] <> [4]), map (lambda x => x * 2) [1, 2])";
+        let (native, steps) = run(true, "", expr);
+        let (equations, more_steps) = run(false, "", expr);
+        assert_eq!(native.as_deref(), Ok("(4, [2, 4])"));
+        assert_eq!(native, equations);
+        assert!(steps * 2 < more_steps, "{} {}", steps, more_steps);
+
+        // Errors come from the equations, as they would have without native functions
+        let (native, _) = run(true, "", "map (lambda 1 => 1) [1, 2]");
+        assert_eq!(native, run(false, "", "map (lambda 1 => 1) [1, 2]").0);
+
+        // reverse is written with foldl, so a new foldl is used by it
+        let foldl = "dec foldl : (beta # alpha -> beta) -> beta -> list alpha -> beta;\n--- foldl f b l <= b;";
+        assert_eq!(run(true, foldl, "reverse [1, 2]").0.as_deref(), Ok("[]"));
+    }
+
+    #[test]
+    fn should_count_calls_by_site_and_inline_the_small_ones() {
+        let source = "dec sq : num -> num;\n--- sq x <= x * x;\n\
+                      dec add : num -> num -> num;\n--- add x y <= x + y;\n\
+                      dec go : num # num -> num;\n\
+                      --- go (0, acc) <= acc;\n\
+                      --- go (n, acc) <= go (n - 1, add acc (sq n));";
+        let run = |interp: Interpreter| {
+            let mut interp = interp;
+            interp.load(&parser::parse_program(source).unwrap());
+            let value = interp.eval(&parser::parse_expr("go (10, 0)").unwrap()).unwrap().to_string();
+            (value, interp.steps(), interp.profile())
+        };
+        let (value, steps, profile) = run(Interpreter::new().with_profiling(true));
+        let profile = profile.unwrap();
+        let sites = profile.sites();
+        assert_eq!(value, "385");
+        assert_eq!(sites[0].function, "go");
+        assert_eq!((sites[0].calls, sites[0].line), (10, 7));
+        let sq = sites.iter().find(|site| site.function == "sq").unwrap();
+        assert_eq!((sq.calls, profile.calls("sq", sq.offset)), (10, 10));
+
+        let (optimized, fewer, _) = run(Interpreter::new().with_optimization(true));
+        assert_eq!(optimized, value);
+        assert!(fewer < steps, "{} {}", fewer, steps);
+        let inlined = |name: &str, interp: &Interpreter| interp.resolved(name).unwrap()[1].to_string().contains("let");
+        let mut interp = Interpreter::new().with_optimization(true);
+        interp.load(&parser::parse_program(source).unwrap());
+        assert!(inlined("go", &interp));
+
+        // Calls a profile never saw are left alone
+        let mut interp = Interpreter::new().with_optimization(true).with_guide(Some(Profile::default()));
+        interp.load(&parser::parse_program(source).unwrap());
+        assert!(!inlined("go", &interp));
+        assert_eq!(run(Interpreter::new().with_optimization(true).with_guide(Some(profile))).0, value);
+    }
+
+    #[test]
+    fn should_record_calls_to_step_through_both_ways() {
+        let source = "dec fact : num -> num;\n--- fact 0 <= 1;\n--- fact n <= n * fact (n - 1);\n\
+                      dec len : list num -> num;\n--- len nil <= 0;\n--- len (_ :: l) <= 1 + len l;\n\
+                      dec bad : num -> num;\n--- bad n <= n div 0;";
+        let mut interp = Interpreter::new().with_recording(true);
+        interp.load(&parser::parse_program(source).unwrap());
+        assert_eq!(interp.eval(&parser::parse_expr("len [fact 2, bad 1]").unwrap()).unwrap_err().code(), "E0405");
+        let trace = interp.trace().unwrap();
+        assert_eq!(Trace::decode(&trace.encode()), Ok(trace.clone()));
+        assert!(Trace::decode(b"HOPETRACE1\x05").is_err());
+
+        let mut replay = Replay::new(trace);
+        assert_eq!(replay.to_string(), "[1/8] fact 2 at 1:6, step 6");
+        assert!(replay.forward() && replay.forward());
+        assert_eq!(replay.to_string(), "[3/8]     fact 0 at 3:19, step 20");
+        assert!(replay.up());
+        assert_eq!(replay.to_string(), "[2/8]   fact 1 at 3:19, step 13");
+        assert!(replay.over());
+        assert_eq!(replay.to_string(), "[5/8]   fact 1 = 1");
+        assert!(replay.go(7));
+        assert_eq!(replay.to_string(), "[8/8] bad 1 failed with E0405");
+        assert!(!replay.forward() && replay.go(0) && !replay.back());
+        assert!(!replay.find("len") && replay.find("fact"));
+        assert_eq!(replay.at(), 1);
+
+        let mut out = Vec::new();
+        replay.run(&b"g 6\nb\nu\nz\nq\nn\n"[..], &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[..4], ["[2/8]   fact 1 at 3:19, step 13", "[6/8] fact 2 = 2", "[5/8]   fact 1 = 1", "[2/8]   fact 1 at 3:19, step 13"]);
+        assert!(lines[4].starts_with("n or return") && lines.len() == 5, "{}", out);
+    }
+
+    #[test]
+    fn should_snapshot_the_cells_definitions_hold() {
+        let source = "dec base : list num;\n--- base <= [1, 2, 3];\n\
+                      dec both : list num # list num;\n--- both <= (base, 4 :: base);\n\
+                      dec f : num -> num;\n--- f n <= n;";
+        let run = |at| {
+            let mut interp = Interpreter::new().with_heap_snapshot(at);
+            interp.load(&parser::parse_program(source).unwrap());
+            interp.eval(&parser::parse_expr("(\\ys => f 1) both").unwrap()).unwrap();
+            interp.heap_snapshot()
+        };
+        assert_eq!(run(None), None);
+        let end = run(Some(u64::MAX)).unwrap();
+        let held: Vec<(&str, &str, u64)> = end.groups.iter().map(|g| (g.retainer.as_str(), g.constructor.as_str(), g.cells)).collect();
+        // What both shares with base is counted under base, which comes first
+        assert_eq!(held, [("base", "::", 3), ("base", "#", 3), ("both", "::", 1), ("base", "nil", 1), ("both", "#", 2)]);
+        assert_eq!(end.cells(), 10);
+        assert!(end.to_string().starts_with(&format!("heap at step {}: 10 cells, {} bytes\n", end.step, end.bytes())));
+        assert!(end.dot().contains("\"box both\" -> \"ellipse ::\" [label = \"1 cells, "));
+
+        // In f, the lambda's argument is still held
+        let within = run(Some(end.step - 1)).unwrap();
+        assert!(within.groups.iter().any(|g| g.constructor == "frame" && g.retainer.starts_with("the locals at 1:")), "{}", within);
+    }
+
+    #[test]
+    fn should_stop_at_its_limits() {
+        let source = "dec loop : num -> num;\n--- loop n <= loop (n + 1);";
+        let run = |limits| {
+            let mut interp = Interpreter::new().with_limits(limits);
+            interp.load(&parser::parse_program(source).unwrap());
+            interp.start_clock();
+            interp.eval(&parser::parse_expr("loop 0").unwrap())
+        };
+        let steps = Limits { steps: Some(1000), ..Limits::default() };
+        assert!(matches!(run(steps), Err(EvalError::StepLimit(_))));
+        let depth = Limits { depth: Some(100), ..Limits::default() };
+        assert!(matches!(run(depth), Err(EvalError::DepthLimit(_))));
+        let time = Limits { time: Some(std::time::Duration::ZERO), ..Limits::default() };
+        assert!(matches!(run(time), Err(EvalError::Timeout(_))));
+    }
+
+    #[test]
+    fn should_ask_the_meter_whether_to_go_on() {
+        let source = "dec loop : num -> num;\n--- loop n <= loop (n + 1);";
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let record = seen.clone();
+        let meter = Meter::new(500, move |usage| {
+            record.borrow_mut().push((usage.steps, usage.remaining));
+            match usage.steps {
+                1000 | 2000 => Metering::Grant(1000),
+                steps if steps < 3000 => Metering::Continue,
+                _ => Metering::Abort,
+            }
+        });
+        let limits = Limits { steps: Some(1000), ..Limits::default() };
+        let mut interp = Interpreter::new().with_meter(Some(meter)).with_limits(limits);
+        interp.load(&parser::parse_program(source).unwrap());
+        interp.start_clock();
+        let e = interp.eval(&parser::parse_expr("loop 0").unwrap()).unwrap_err();
+        assert_eq!((e.code(), e.to_string()), ("E0416", "evaluation was stopped by the host".to_owned()));
+        let seen = seen.borrow();
+        assert_eq!(seen[..], [(500, Some(500)), (1000, Some(0)), (1500, Some(500)), (2000, Some(0)), (2500, Some(500)), (3000, Some(0))]);
+        assert_eq!(interp.steps(), 3000);
+    }
+
+    #[test]
+    fn should_say_where_values_differ() {
+        let source = "data tree == leaf ++ node (tree # num # tree);";
+        assert_eq!(show(source, "assert_eq ((1, [2]), (1, [2]))"), "true");
+        let failed = run(source, "assert_eq ([node (leaf, 1, leaf)], [node (leaf, 2, leaf)])").unwrap_err();
+        assert_eq!(failed.to_string(), "assertion failed, the values differ at item 1 of the list, then argument 2 of `node`");
+        assert_eq!(failed.notes(), ["expected `1`", "found `2`"]);
+        let shorter = run(source, "assert_eq ((0, [1, 2]), (0, [1]))").unwrap_err();
+        assert_eq!(shorter.to_string(), "assertion failed, the values differ at component 2 of the tuple, then item 2 of the list");
+        assert_eq!(shorter.notes(), ["expected `2`", "found the end of the list"]);
+        let top = run(source, "assert_eq (leaf, node (leaf, 1, leaf))").unwrap_err();
+        assert_eq!(top.to_string(), "assertion failed, the values differ");
+    }
+
+    #[test]
+    fn should_format_text_the_way_directives_say() {
+        let source = "dec row : list char # num -> list char;\n--- row (name, n) <= format (\"%-5s|%4d|%6.2f\", [fstr name, fnum n, fnum (n / 3)]);";
+        assert_eq!(show(source, "(row (\"ab\", 7), row (\"abcdef\", 10))"), "(\"ab   |   7|  2.33\", \"abcdef|  10|  3.33\")");
+        assert_eq!(show("", "format (\"100%% %.3s\", [fstr \"done!\"])"), "\"100% don\"");
+        let fractional = run("", "format (\"%d\", [fnum (1 / 2)])").unwrap_err();
+        assert_eq!(fractional.to_string(), "bad format: argument 1 is for `%d`, which needs a whole number");
+    }
+
+    #[test]
+    fn should_call_host_functions_like_any_other() {
+        let mut builtins = Builtins::new();
+        builtins
+            .register("sqrt", 1, "num -> num", |args| match args[0].as_f64() {
+                Some(n) if n >= 0.0 => Ok(Value::Num(n.sqrt())),
+                _ => Err("needs a number that isn't negative".to_owned()),
+            })
+            .and_then(|b| b.register("repeat", 2, "num -> list char -> list char", |args| {
+                let times = args[0].as_f64().unwrap_or(0.0) as usize;
+                Ok(Value::string(&args[1].as_string().unwrap_or_default().repeat(times)))
+            }))
+            .and_then(|b| b.register("not", 1, "bool -> bool", |_| Ok(Value::bool(true))))
+            .unwrap();
+        let bad = builtins.register("half", 1, "num ->", |args| Ok(args[0].clone())).unwrap_err();
+        assert_eq!(bad.code(), "E0313");
+        let bad = builtins.register("pair", 2, "num -> num", |args| Ok(args[0].clone())).unwrap_err();
+        assert_eq!(bad.to_string(), "host function `pair`: takes 2 arguments but its type is num -> num");
+        assert!(matches!(builtins.register("odd", 1, "thing -> bool", |_| Ok(Value::bool(true))), Err(TypeError::UnknownType(..))));
+        assert_eq!(builtins.iter().count(), 3);
+
+        let program = parser::parse_program("dec twice : list char -> list char;\n--- twice <= repeat 2;").unwrap();
+        let typed = crate::types::Checker::new().with_builtins(&builtins).check(program.clone()).unwrap();
+        assert!(typed.warnings.is_empty());
+        let mut interp = Interpreter::new().with_builtins(&builtins);
+        interp.load(&program);
+        let eval = |expr| interp.eval(&parser::parse_expr(expr).unwrap());
+        assert_eq!(eval("(sqrt 16, twice \"ab\", not true)").unwrap().to_string(), "(4.0, \"abab\", true)");
+        let failed = eval("sqrt (0 - 1)").unwrap_err();
+        assert_eq!(failed.to_string(), "`sqrt` failed: needs a number that isn't negative");
+        assert_eq!(failed.code(), "E0413");
+
+        let unknown = parser::parse_program("sqrt 2;").unwrap();
+        assert!(crate::types::Checker::new().check(unknown).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use crate::syntax::token::Pos;
+
+// How often each function was called with all its arguments at each place a call was
+// written. A position doesn't say which file it is in, so a site is the function
+// called and where in its file the call starts, as coverage counts equations
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    sites: HashMap<usize, Vec<Site>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Site {
+    pub function: String,
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+    pub calls: u64,
+}
+
+impl Profile {
+    pub(crate) fn call(&mut self, function: &str, pos: &Pos) {
+        let sites = self.sites.entry(pos.range.start).or_default();
+        match sites.iter_mut().find(|site| site.function == function) {
+            Some(site) => site.calls += 1,
+            None => sites.push(Site { function: function.to_owned(), line: pos.line, column: pos.column, offset: pos.range.start, calls: 1 }),
+        }
+    }
+
+    pub fn calls(&self, function: &str, offset: usize) -> u64 {
+        let sites = self.sites.get(&offset).into_iter().flatten();
+        sites.filter(|site| site.function == function).map(|site| site.calls).sum()
+    }
+
+    // The hottest first, those called as often in the order they are written
+    pub fn sites(&self) -> Vec<&Site> {
+        let mut sites: Vec<&Site> = self.sites.values().flatten().collect();
+        sites.sort_by(|a, b| b.calls.cmp(&a.calls).then(a.offset.cmp(&b.offset)).then(a.function.cmp(&b.function)));
+        sites
+    }
+
+    // Adds the counts of another run
+    pub fn merge(&mut self, other: &Profile) {
+        for site in other.sites.values().flatten() {
+            let sites = self.sites.entry(site.offset).or_default();
+            match sites.iter_mut().find(|mine| mine.function == site.function) {
+                Some(mine) => mine.calls += site.calls,
+                None => sites.push(site.clone()),
+            }
+        }
+    }
+
+    pub fn from_sites(sites: impl IntoIterator<Item = Site>) -> Profile {
+        let mut profile = Profile::default();
+        for site in sites {
+            profile.sites.entry(site.offset).or_default().push(site);
+        }
+        profile
+    }
+}
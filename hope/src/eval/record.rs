@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::io::{self, BufRead};
+use crate::eval::{EvalError, Value};
+use crate::eval::value::is_symbolic;
+use crate::syntax::token::Pos;
+
+// What `hope run --record` keeps of a run: each call of a function with all its
+// arguments, and what it returned, in the order they happened. Nothing a program
+// does depends on when it is evaluated, so this is enough to step through the run
+// backwards as well as forwards. Arguments and results are kept as they print, cut
+// to SHOWN characters, and each text only once
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    texts: Vec<String>,
+    indices: HashMap<String, u32>,
+    events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    // The step it was made at, the texts of the function's name and arguments, and
+    // where the call was written
+    Call { step: u64, function: u32, args: u32, line: u32, column: u32 },
+    Return { value: u32 },
+    // The code of the error it failed with
+    Fail { code: u32 },
+}
+
+const SHOWN: usize = 60;
+// Calls deeper than this are shown no further in
+const MAX_INDENT: usize = 20;
+const MAGIC: &[u8] = b"HOPETRACE1";
+
+impl Trace {
+    fn text(&mut self, text: String) -> u32 {
+        if let Some(&index) = self.indices.get(&text) {
+            return index;
+        }
+        let index = self.texts.len() as u32;
+        self.indices.insert(text.clone(), index);
+        self.texts.push(text);
+        index
+    }
+
+    pub(crate) fn call(&mut self, step: u64, function: &str, args: &[Value], pos: &Pos) {
+        let mut shown = String::new();
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                shown.push(' ');
+            }
+            shown.push_str(&show(arg, true));
+        }
+        let (function, args) = (self.text(function.to_owned()), self.text(shown));
+        let (line, column) = (pos.line as u32, pos.column as u32);
+        self.events.push(Event::Call { step, function, args, line, column });
+    }
+
+    pub(crate) fn returned(&mut self, result: &Result<Value, EvalError>) {
+        let event = match result {
+            Ok(value) => Event::Return { value: self.text(show(value, false)) },
+            Err(e) => Event::Fail { code: self.text(e.code().to_owned()) },
+        };
+        self.events.push(event);
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn get(&self, text: u32) -> &str {
+        &self.texts[text as usize]
+    }
+
+    // The texts as they are first used, each a varint length and its bytes, then the
+    // events, each a tag byte and its fields as varints
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        varint(&mut out, self.texts.len() as u64);
+        for text in &self.texts {
+            varint(&mut out, text.len() as u64);
+            out.extend_from_slice(text.as_bytes());
+        }
+        varint(&mut out, self.events.len() as u64);
+        for event in &self.events {
+            match *event {
+                Event::Call { step, function, args, line, column } => {
+                    out.push(0);
+                    for field in [step, function.into(), args.into(), line.into(), column.into()] {
+                        varint(&mut out, field);
+                    }
+                }
+                Event::Return { value } => {
+                    out.push(1);
+                    varint(&mut out, value.into());
+                }
+                Event::Fail { code } => {
+                    out.push(2);
+                    varint(&mut out, code.into());
+                }
+            }
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Trace, String> {
+        let rest = bytes.strip_prefix(MAGIC).ok_or("not a trace written by hope run --record")?;
+        let mut reader = Reader { bytes: rest };
+        let mut trace = Trace::default();
+        for _ in 0..reader.varint()? {
+            let len = reader.varint()? as usize;
+            let text = reader.take(len)?;
+            let text = String::from_utf8(text.to_vec()).map_err(|_| "the trace has text that isn't UTF-8")?;
+            trace.text(text);
+        }
+        let index = |reader: &mut Reader, texts: usize| match reader.varint()? {
+            n if (n as usize) < texts => Ok(n as u32),
+            _ => Err("the trace refers to text it doesn't have".to_owned()),
+        };
+        let texts = trace.texts.len();
+        for _ in 0..reader.varint()? {
+            let event = match reader.take(1)?[0] {
+                0 => Event::Call {
+                    step: reader.varint()?,
+                    function: index(&mut reader, texts)?,
+                    args: index(&mut reader, texts)?,
+                    line: reader.varint()? as u32,
+                    column: reader.varint()? as u32,
+                },
+                1 => Event::Return { value: index(&mut reader, texts)? },
+                2 => Event::Fail { code: index(&mut reader, texts)? },
+                tag => return Err(format!("the trace has an event of unknown kind {}", tag)),
+            };
+            trace.events.push(event);
+        }
+        Ok(trace)
+    }
+}
+
+fn varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err("the trace ends early".to_owned());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Ok(n);
+            }
+        }
+        Err("the trace has a number that is too long".to_owned())
+    }
+}
+
+// The value as it prints, cut to about SHOWN characters without walking any more of
+// it than that, since a call down a long list is recorded once per cell. An argument
+// that isn't atomic is bracketed, as it is written after a function
+fn show(value: &Value, atomic: bool) -> String {
+    let mut shown = String::new();
+    if !preview(value, atomic, &mut shown) {
+        shown.push_str("...");
+    }
+    shown
+}
+
+// Whether all of it fit
+fn preview(value: &Value, atomic: bool, out: &mut String) -> bool {
+    if out.len() > SHOWN {
+        return false;
+    }
+    match value {
+        Value::Data(d) if d.name == "::" || (d.name == "nil" && d.args.is_empty()) => {
+            let mut items = core::iter::successors(cons(value), |(_, tail)| cons(tail)).map(|(head, _)| head).peekable();
+            if let Some(Value::Char(_)) = items.peek() {
+                let text: String = items.by_ref().take(SHOWN).map_while(|item| match item {
+                    Value::Char(c) => Some(*c),
+                    _ => None,
+                }).collect();
+                let _ = write!(out, "{:?}", text);
+                return items.next().is_none() && out.len() <= SHOWN;
+            }
+            out.push('[');
+            for (i, item) in items.enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                if !preview(item, false, out) {
+                    return false;
+                }
+            }
+            out.push(']');
+        }
+        Value::Pair(_) => {
+            out.push('(');
+            let mut rest = value;
+            while let Value::Pair(cell) = rest {
+                if !preview(&cell.0, false, out) {
+                    return false;
+                }
+                out.push_str(", ");
+                rest = &cell.1;
+            }
+            if !preview(rest, false, out) {
+                return false;
+            }
+            out.push(')');
+        }
+        Value::Data(d) if !d.args.is_empty() => {
+            if atomic {
+                out.push('(');
+            }
+            match &d.args[..] {
+                [Value::Pair(cell)] if is_symbolic(&d.name) => {
+                    if !preview(&cell.0, true, out) {
+                        return false;
+                    }
+                    let _ = write!(out, " {} ", d.name);
+                    if !preview(&cell.1, true, out) {
+                        return false;
+                    }
+                }
+                args => {
+                    out.push_str(&d.name);
+                    for arg in args {
+                        out.push(' ');
+                        if !preview(arg, true, out) {
+                            return false;
+                        }
+                    }
+                }
+            }
+            if atomic {
+                out.push(')');
+            }
+        }
+        // Numbers, characters, functions and constructors on their own are short
+        _ => {
+            let _ = write!(out, "{}", value);
+        }
+    }
+    out.len() <= SHOWN
+}
+
+// The head and tail of a list that isn't empty
+fn cons(value: &Value) -> Option<(&Value, &Value)> {
+    match value {
+        Value::Data(d) if d.name == "::" => match &d.args[..] {
+            [Value::Pair(cell)] => Some((&cell.0, &cell.1)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Where `hope replay` is in a trace, and the ways it moves
+#[derive(Debug)]
+pub struct Replay {
+    trace: Trace,
+    at: usize,
+    // For each event, the call it is made in or returns from, None at the top
+    calls: Vec<Option<usize>>,
+    // For each call, the event it returns or fails at, None if the run stopped first
+    ends: Vec<Option<usize>>,
+    // How many calls each event is inside
+    depths: Vec<usize>,
+}
+
+impl Replay {
+    pub fn new(trace: Trace) -> Self {
+        let events = trace.events.len();
+        let (mut calls, mut ends, mut depths, mut open) = (Vec::with_capacity(events), vec![None; events], Vec::with_capacity(events), Vec::new());
+        for (i, event) in trace.events.iter().enumerate() {
+            match event {
+                Event::Call { .. } => {
+                    calls.push(open.last().copied());
+                    depths.push(open.len());
+                    open.push(i);
+                }
+                Event::Return { .. } | Event::Fail { .. } => {
+                    let call = open.pop();
+                    if let Some(call) = call {
+                        ends[call] = Some(i);
+                    }
+                    calls.push(call);
+                    depths.push(open.len());
+                }
+            }
+        }
+        Replay { trace, at: 0, calls, ends, depths }
+    }
+
+    pub fn len(&self) -> usize {
+        self.trace.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trace.events.is_empty()
+    }
+
+    pub fn at(&self) -> usize {
+        self.at
+    }
+
+    // Each returns whether there was anywhere to go
+    pub fn forward(&mut self) -> bool {
+        self.go(self.at + 1)
+    }
+
+    pub fn back(&mut self) -> bool {
+        match self.at.checked_sub(1) {
+            Some(at) => self.go(at),
+            None => false,
+        }
+    }
+
+    pub fn go(&mut self, at: usize) -> bool {
+        let there = at < self.len();
+        if there {
+            self.at = at;
+        }
+        there
+    }
+
+    // Past the whole of the current call, to where it returns
+    pub fn over(&mut self) -> bool {
+        match self.ends.get(self.at).copied().flatten() {
+            Some(end) => self.go(end),
+            None => self.forward(),
+        }
+    }
+
+    // To the call the current event is made in, or the call a return is from
+    pub fn up(&mut self) -> bool {
+        let call = match self.trace.events.get(self.at) {
+            Some(Event::Call { .. }) => self.calls[self.at],
+            _ => self.calls.get(self.at).copied().flatten(),
+        };
+        call.is_some_and(|call| self.go(call))
+    }
+
+    // The next call of the function after the current event
+    pub fn find(&mut self, function: &str) -> bool {
+        let found = self.trace.events.iter().enumerate().skip(self.at + 1).find(|(_, event)| {
+            matches!(event, Event::Call { function: name, .. } if self.trace.get(*name) == function)
+        });
+        let found = found.map(|(at, _)| at);
+        found.is_some_and(|at| self.go(at))
+    }
+}
+
+const HELP: &str = "n or return for the next event, b the one before, o over the call, u up to \
+                    the call it is in, g N to go to the Nth, f NAME to find the next call of \
+                    NAME, q to quit";
+
+impl Replay {
+    // Reads commands a line at a time, printing where each leaves it, until q or the
+    // end of the input
+    pub fn run(&mut self, input: impl BufRead, mut out: impl io::Write) -> io::Result<()> {
+        writeln!(out, "{}", self)?;
+        for line in input.lines() {
+            let line = line?;
+            let (command, arg) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            let moved = match (command, arg.trim()) {
+                ("" | "n", "") => self.forward(),
+                ("b", "") => self.back(),
+                ("o", "") => self.over(),
+                ("u", "") => self.up(),
+                ("g", n) => match n.parse::<usize>() {
+                    Ok(n) if n > 0 => self.go(n - 1),
+                    _ => {
+                        writeln!(out, "g takes the number of an event, from 1 to {}", self.len())?;
+                        continue;
+                    }
+                },
+                ("f", name) if !name.is_empty() => self.find(name),
+                ("q", "") => return Ok(()),
+                _ => {
+                    writeln!(out, "{}", HELP)?;
+                    continue;
+                }
+            };
+            if !moved {
+                writeln!(out, "(no further that way)")?;
+            }
+            writeln!(out, "{}", self)?;
+        }
+        Ok(())
+    }
+}
+
+// The event, indented by how many calls it is inside
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(event) = self.trace.events.get(self.at) else { return write!(f, "the trace is empty") };
+        let indent = "  ".repeat(self.depths[self.at].min(MAX_INDENT));
+        write!(f, "[{}/{}] {}", self.at + 1, self.len(), indent)?;
+        let call = |at: Option<usize>| match at.map(|at| self.trace.events[at]) {
+            Some(Event::Call { function, args, .. }) => match self.trace.get(args) {
+                "" => self.trace.get(function).to_owned(),
+                args => format!("{} {}", self.trace.get(function), args),
+            },
+            _ => "the run".to_owned(),
+        };
+        match *event {
+            Event::Call { step, line, column, .. } => write!(f, "{} at {}:{}, step {}", call(Some(self.at)), line, column, step),
+            Event::Return { value } => write!(f, "{} = {}", call(self.calls[self.at]), self.trace.get(value)),
+            Event::Fail { code } => write!(f, "{} failed with {}", call(self.calls[self.at]), self.trace.get(code)),
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::rc::Rc;
+use crate::syntax::ast::*;
+use crate::syntax::token::Pos;
+
+// Deeply nested expressions are resolved on a stack that grows, as they are evaluated
+const STACK_RED_ZONE: usize = 64 * 1024;
+const STACK_GROWTH: usize = 1024 * 1024;
+
+// Expressions with each variable worked out before they are evaluated. A local is found
+// by the frame it is in, counting out from the innermost, and its slot there, so the
+// interpreter indexes rather than looks for names. A frame holds what one pattern binds,
+// in the order binders gives them. Anything else is left to be looked up by name
+#[derive(Debug, Clone, PartialEq)]
+pub enum Var {
+    Local { depth: usize, slot: usize, name: String },
+    Global(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Code {
+    pub kind: CodeKind,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodeKind {
+    Var(Var),
+    Int(i64),
+    BigInt(String),
+    Num(f64),
+    Str(String),
+    Tuple(Vec<Code>),
+    List(Vec<Code>),
+    Apply(Box<Code>, Box<Code>),
+    // The operator, where it was written, and its operands
+    BinOp(Var, Pos, Box<Code>, Box<Code>),
+    If(Box<Code>, Box<Code>, Box<Code>),
+    // Shared with the closures made from it
+    Lambda(Rc<[Rule]>),
+    // The frame of the pattern is the value's scope too with rec
+    Let { rec: bool, pattern: Pattern, value: Box<Code>, body: Box<Code> },
+    // A function given all its arguments at once, as -O leaves calls, see eval::inline
+    Call(String, Vec<Code>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub pattern: Pattern,
+    pub body: Code,
+}
+
+// An equation's guard and body, in the frame of what its patterns bind
+#[derive(Debug, Clone, PartialEq)]
+pub struct Body {
+    pub slots: Vec<String>,
+    pub guard: Option<Code>,
+    pub body: Code,
+}
+
+// The names the pattern binds, in the order of the slots in its frame
+pub fn binders(pattern: &Pattern, is_constructor: &dyn Fn(&str) -> bool, names: &mut Vec<String>) {
+    match &pattern.kind {
+        PatternKind::Var(name) if !is_constructor(name) => names.push(name.clone()),
+        PatternKind::As(name, pattern) => {
+            names.push(name.name.clone());
+            binders(pattern, is_constructor, names);
+        }
+        PatternKind::Tuple(items) | PatternKind::List(items) | PatternKind::Construct(_, items) => {
+            items.iter().for_each(|item| binders(item, is_constructor, names));
+        }
+        PatternKind::BinOp(_, l, r) => {
+            binders(l, is_constructor, names);
+            binders(r, is_constructor, names);
+        }
+        _ => {}
+    }
+}
+
+// An expression outside any frame, as at the top level
+pub fn expr(expr: &Expr, is_constructor: &dyn Fn(&str) -> bool) -> Code {
+    Resolver { frames: Vec::new(), is_constructor }.expr(expr)
+}
+
+// The equation of a function of this many arguments, whose frame is what its first
+// arity patterns bind
+pub fn equation(eq: &Equation, arity: usize, is_constructor: &dyn Fn(&str) -> bool) -> Body {
+    let mut slots = Vec::new();
+    for arg in eq.args.iter().take(arity) {
+        binders(arg, is_constructor, &mut slots);
+    }
+    let mut resolver = Resolver { frames: vec![slots], is_constructor };
+    let guard = eq.guard.as_ref().map(|guard| resolver.expr(guard));
+    let body = resolver.expr(&eq.body);
+    Body { slots: resolver.frames.pop().expect("the equation's frame"), guard, body }
+}
+
+struct Resolver<'c> {
+    // The names in each frame, the innermost last
+    frames: Vec<Vec<String>>,
+    is_constructor: &'c dyn Fn(&str) -> bool,
+}
+
+impl Resolver<'_> {
+    // A name bound twice in one pattern is the later binding, as matching leaves it
+    fn var(&self, name: &str) -> Var {
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            if let Some(slot) = frame.iter().rposition(|bound| bound == name) {
+                return Var::Local { depth, slot, name: name.to_owned() };
+            }
+        }
+        Var::Global(name.to_owned())
+    }
+
+    fn within(&mut self, pattern: &Pattern, expr: &Expr) -> Code {
+        let mut names = Vec::new();
+        binders(pattern, self.is_constructor, &mut names);
+        self.frames.push(names);
+        let code = self.expr(expr);
+        self.frames.pop();
+        code
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Code {
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || self.kind(expr))
+    }
+
+    fn kind(&mut self, expr: &Expr) -> Code {
+        let kind = match &expr.kind {
+            ExprKind::Var(name) => CodeKind::Var(self.var(name)),
+            ExprKind::Int(n) => CodeKind::Int(*n),
+            ExprKind::BigInt(digits) => CodeKind::BigInt(digits.clone()),
+            ExprKind::Num(x) => CodeKind::Num(*x),
+            ExprKind::Str(s) => CodeKind::Str(s.clone()),
+            ExprKind::Tuple(items) => CodeKind::Tuple(items.iter().map(|item| self.expr(item)).collect()),
+            ExprKind::List(items) => CodeKind::List(items.iter().map(|item| self.expr(item)).collect()),
+            ExprKind::Apply(fun, arg) => CodeKind::Apply(self.boxed(fun), self.boxed(arg)),
+            ExprKind::BinOp(op, l, r) => {
+                let (l, r) = (self.boxed(l), self.boxed(r));
+                CodeKind::BinOp(self.var(&op.name), op.pos.clone(), l, r)
+            }
+            ExprKind::If(cond, then, other) => CodeKind::If(self.boxed(cond), self.boxed(then), self.boxed(other)),
+            ExprKind::Lambda(rules) => {
+                let rules = rules.iter().map(|rule| Rule { pattern: rule.pattern.clone(), body: self.within(&rule.pattern, &rule.body) });
+                CodeKind::Lambda(rules.collect())
+            }
+            ExprKind::Let(binding) => {
+                let rec = binding.kind.is_rec();
+                let value = if rec { self.within(&binding.pattern, &binding.value) } else { self.expr(&binding.value) };
+                let body = self.within(&binding.pattern, &binding.body);
+                CodeKind::Let { rec, pattern: binding.pattern.clone(), value: Box::new(value), body: Box::new(body) }
+            }
+        };
+        Code { kind, pos: expr.pos.clone() }
+    }
+
+    fn boxed(&mut self, expr: &Expr) -> Box<Code> {
+        Box::new(self.expr(expr))
+    }
+}
+
+// Operators are written in brackets where they are used as names, and locals with
+// their frame and slot, as `x@0.1`
+fn name(var: &Var) -> String {
+    let (Var::Local { name, .. } | Var::Global(name)) = var;
+    let shown = if name.starts_with(|c: char| c.is_alphanumeric() || c == '_') { name.clone() } else { format!("(nonop {})", name) };
+    match var {
+        Var::Local { depth, slot, .. } => format!("{}@{}.{}", shown, depth, slot),
+        Var::Global(_) => shown,
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Where code goes in an application or next to an operator, which takes only
+        // what is atomic there
+        let atom = |code: &Code| match &code.kind {
+            CodeKind::Apply(..) | CodeKind::Call(..) | CodeKind::BinOp(..) | CodeKind::If(..) | CodeKind::Lambda(..) | CodeKind::Let { .. } => format!("({})", code),
+            CodeKind::Int(n) if *n < 0 => format!("({})", n),
+            CodeKind::Num(x) if *x < 0.0 => format!("({})", x),
+            _ => code.to_string(),
+        };
+        let list = |items: &[Code]| items.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        match &self.kind {
+            CodeKind::Var(var) => write!(f, "{}", name(var)),
+            CodeKind::Int(n) => write!(f, "{}", n),
+            CodeKind::BigInt(digits) => write!(f, "{}", digits),
+            CodeKind::Num(x) => write!(f, "{:?}", x),
+            CodeKind::Str(s) => write!(f, "{:?}", s),
+            CodeKind::Tuple(items) => write!(f, "({})", list(items)),
+            CodeKind::List(items) => write!(f, "[{}]", list(items)),
+            CodeKind::Apply(fun, arg) => match fun.kind {
+                CodeKind::Apply(..) => write!(f, "{} {}", fun, atom(arg)),
+                _ => write!(f, "{} {}", atom(fun), atom(arg)),
+            },
+            CodeKind::BinOp(op, _, l, r) => {
+                let (Var::Local { name: op, .. } | Var::Global(op)) = op;
+                write!(f, "{} {} {}", atom(l), op, atom(r))
+            }
+            CodeKind::If(cond, then, other) => write!(f, "if {} then {} else {}", cond, then, other),
+            CodeKind::Lambda(rules) => {
+                let rules: Vec<String> = rules.iter().map(|rule| format!("{} => {}", pattern(&rule.pattern), rule.body)).collect();
+                write!(f, "lambda {}", rules.join(" | "))
+            }
+            CodeKind::Call(fun, args) => {
+                let args: String = args.iter().map(|arg| format!(" {}", atom(arg))).collect();
+                write!(f, "{}{}", name(&Var::Global(fun.clone())), args)
+            }
+            CodeKind::Let { rec, pattern: bound, value, body } => {
+                let keyword = if *rec { "letrec" } else { "let" };
+                write!(f, "{} {} == {} in {}", keyword, pattern(bound), value, body)
+            }
+        }
+    }
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    let atom = |p: &Pattern| match &p.kind {
+        PatternKind::Construct(_, args) if !args.is_empty() => format!("({})", self::pattern(p)),
+        PatternKind::BinOp(..) | PatternKind::As(..) => format!("({})", self::pattern(p)),
+        _ => self::pattern(p),
+    };
+    let list = |items: &[Pattern]| items.iter().map(self::pattern).collect::<Vec<_>>().join(", ");
+    match &pattern.kind {
+        PatternKind::Var(name) => name.clone(),
+        PatternKind::Wildcard => "_".to_owned(),
+        PatternKind::As(name, inner) => format!("{} & {}", name.name, atom(inner)),
+        PatternKind::Int(n) => n.to_string(),
+        PatternKind::BigInt(digits) => digits.clone(),
+        PatternKind::Num(x) => format!("{:?}", x),
+        PatternKind::Str(s) => format!("{:?}", s),
+        PatternKind::Tuple(items) => format!("({})", list(items)),
+        PatternKind::List(items) => format!("[{}]", list(items)),
+        PatternKind::Construct(name, args) => {
+            let args: String = args.iter().map(|arg| format!(" {}", atom(arg))).collect();
+            format!("{}{}", name.name, args)
+        }
+        PatternKind::BinOp(op, l, r) => format!("{} {} {}", atom(l), op.name, atom(r)),
+    }
+}
+
+impl fmt::Display for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slots: Vec<String> = self.slots.iter().enumerate().map(|(slot, name)| format!("{}@0.{}", name, slot)).collect();
+        write!(f, "[{}]", slots.join(", "))?;
+        if let Some(guard) = &self.guard {
+            write!(f, " if {}", guard)?;
+        }
+        write!(f, " <= {}", self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn resolved(source: &str) -> Vec<String> {
+        let program = parser::parse_program(source).unwrap();
+        let is_constructor = |name: &str| ["nil", "true", "false"].contains(&name);
+        program.decls.iter()
+            .filter_map(|decl| match &decl.kind {
+                DeclKind::Equation(eq) => Some(equation(eq, eq.args.len(), &is_constructor).to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_number_each_local_by_its_frame_and_slot() {
+        let source = "--- f (x, l & (y :: nil)) <= let z == x + y in lambda w => [w, z, x, g l];\n\
+            --- g n if n > 0 <= letrec (a, b) == (b, 1) in a;";
+        assert_eq!(resolved(source), [
+            "[x@0.0, l@0.1, y@0.2] <= let z == x@0.0 + y@0.2 in lambda w => [w@0.0, z@1.0, x@2.0, g l@2.1]",
+            "[n@0.0] if n@0.0 > 0 <= letrec (a, b) == (b@0.1, 1) in a@0.0",
+        ]);
+    }
+}
@@ -0,0 +1,436 @@
+use std::cell::{Cell, OnceCell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use crate::syntax::token::Pos;
+use crate::eval::{BigInt, EvalError, Native};
+use crate::eval::resolve::Rule;
+
+// Numbers are one type to programs, but whole numbers are kept exactly: as an i64
+// while they fit, and past that as a BigInt if the build promotes on overflow, see
+// eval::builtins. Everything else is a double
+#[derive(Debug, Clone)]
+pub enum Value {
+    Num(f64),
+    Int(i64),
+    // Always outside the range of i64
+    Big(Rc<BigInt>),
+    Char(char),
+    Pair(Rc<(Value, Value)>),
+    Data(Rc<Data>),
+    Function(Rc<Function>),
+}
+
+// A constructor applied to all of its arguments
+#[derive(Debug)]
+pub struct Data {
+    pub name: String,
+    pub args: Vec<Value>,
+    // The structural hash, and whether the value equals itself, once something has
+    // needed them. Either never changes, since values are immutable
+    hashed: OnceCell<(u64, bool)>,
+}
+
+// Dropping a long list would otherwise recurse once per cell, so whatever only this
+// value holds is taken apart in a loop instead
+impl Drop for Data {
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(&mut self.args);
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Data(data) => {
+                    if let Ok(mut data) = Rc::try_unwrap(data) {
+                        pending.append(&mut data.args);
+                    }
+                }
+                Value::Pair(cell) => {
+                    if let Ok((l, r)) = Rc::try_unwrap(cell) {
+                        pending.push(l);
+                        pending.push(r);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+pub type Builtin = fn(&Value, &Pos) -> Result<Value, EvalError>;
+
+pub enum Function {
+    Closure(Rc<[Rule]>, Env),
+    // A function defined by equations, with the arguments it has been given so far
+    Equations(String, Vec<Value>),
+    Constructor(String, usize, Vec<Value>),
+    Builtin(&'static str, Builtin),
+    // A host function with the arguments it has been given so far
+    Host(Rc<Native>, Vec<Value>),
+    // `memo f`, with what f has returned so far
+    Memo(Value, MemoTable),
+}
+
+// Results by the structural hash of their argument. Past its capacity the table starts
+// again empty, rather than keeping track of which results are used
+#[derive(Debug, Default)]
+pub struct MemoTable {
+    results: RefCell<HashMap<u64, Vec<(Value, Value)>>>,
+    len: Cell<usize>,
+}
+
+impl MemoTable {
+    pub fn get(&self, hash: u64, arg: &Value) -> Option<Value> {
+        let results = self.results.borrow();
+        results.get(&hash)?.iter().find(|(key, _)| key.equals(arg)).map(|(_, result)| result.clone())
+    }
+
+    pub fn insert(&self, hash: u64, arg: Value, result: Value, capacity: usize) {
+        let mut results = self.results.borrow_mut();
+        if self.len.get() >= capacity {
+            results.clear();
+            self.len.set(0);
+        }
+        if capacity > 0 {
+            results.entry(hash).or_default().push((arg, result));
+            self.len.set(self.len.get() + 1);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    // Each argument with its result
+    pub(crate) fn entries(&self) -> Vec<(Value, Value)> {
+        self.results.borrow().values().flatten().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Function::Closure(..) => write!(f, "Closure"),
+            Function::Equations(name, args) => write!(f, "Equations({:?}, {:?})", name, args),
+            Function::Constructor(name, _, args) => write!(f, "Constructor({:?}, {:?})", name, args),
+            Function::Builtin(name, _) => write!(f, "Builtin({:?})", name),
+            Function::Host(native, args) => write!(f, "Host({:?}, {:?})", native.name, args),
+            Function::Memo(fun, table) => write!(f, "Memo({:?}, {})", fun, table.len()),
+        }
+    }
+}
+
+// Local variables, a persistent list of frames with the innermost first, each what one
+// pattern bound, see eval::resolve. A scope shares the one it extends, so a closure
+// captures its scope by cloning an Rc and nothing is copied as scopes nest
+#[derive(Debug, Clone, Default)]
+pub struct Env(Option<Rc<Frame>>);
+
+#[derive(Debug)]
+pub struct Frame {
+    // Set after the frame is made by letrec, and till then not found
+    pub slots: Box<[OnceCell<Value>]>,
+    pub rest: Env,
+}
+
+impl Env {
+    pub fn push(&self, values: Vec<Value>) -> Env {
+        Env(Some(Rc::new(Frame { slots: values.into_iter().map(OnceCell::from).collect(), rest: self.clone() })))
+    }
+
+    // A frame of this many slots, all yet to be set
+    pub fn declare(&self, slots: usize) -> Env {
+        Env(Some(Rc::new(Frame { slots: (0..slots).map(|_| OnceCell::new()).collect(), rest: self.clone() })))
+    }
+
+    pub(crate) fn innermost(&self) -> Option<Rc<Frame>> {
+        self.0.clone()
+    }
+
+    pub fn frame(&self, depth: usize) -> Option<&Frame> {
+        let mut frame = self.0.as_deref()?;
+        for _ in 0..depth {
+            frame = frame.rest.0.as_deref()?;
+        }
+        Some(frame)
+    }
+
+    pub fn get(&self, depth: usize, slot: usize) -> Option<&Value> {
+        self.frame(depth)?.slots.get(slot)?.get()
+    }
+}
+
+impl Value {
+    pub fn data(name: &str, args: Vec<Value>) -> Value {
+        Value::Data(Rc::new(Data { name: name.to_owned(), args, hashed: OnceCell::new() }))
+    }
+
+    // The smallest representation of the integer
+    pub fn big(n: BigInt) -> Value {
+        match n.to_i64() {
+            Some(n) => Value::Int(n),
+            None => Value::Big(Rc::new(n)),
+        }
+    }
+
+    pub fn pair(l: Value, r: Value) -> Value {
+        Value::Pair(Rc::new((l, r)))
+    }
+
+    pub fn bool(b: bool) -> Value {
+        Value::data(if b { "true" } else { "false" }, Vec::new())
+    }
+
+    pub fn list(items: impl DoubleEndedIterator<Item = Value>) -> Value {
+        items.rev().fold(Value::data("nil", Vec::new()), |tail, head| {
+            Value::data("::", vec![Value::pair(head, tail)])
+        })
+    }
+
+    pub fn string(s: &str) -> Value {
+        Value::list(s.chars().map(Value::Char))
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Data(d) if d.args.is_empty() && d.name == "true" => Some(true),
+            Value::Data(d) if d.args.is_empty() && d.name == "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    // The items of a list, if this is one
+    pub fn as_list(&self) -> Option<Vec<&Value>> {
+        let mut items = Vec::new();
+        let mut value = self;
+        loop {
+            let Value::Data(d) = value else { return None };
+            match (d.name.as_str(), &d.args[..]) {
+                ("nil", []) => return Some(items),
+                ("::", [Value::Pair(cell)]) => {
+                    items.push(&cell.0);
+                    value = &cell.1;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    pub fn as_string(&self) -> Option<String> {
+        self.as_list()?.into_iter()
+            .map(|item| match item {
+                Value::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            Value::Int(n) => Some(*n as f64),
+            Value::Big(n) => Some(n.to_f64()),
+            _ => None,
+        }
+    }
+
+    // Exactly between integers, and as doubles otherwise. None if either isn't a
+    // number or either is NaN
+    pub fn compare_numbers(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Big(a), Value::Big(b)) => Some(a.cmp(b)),
+            (Value::Big(a), Value::Int(b)) => Some(a.as_ref().cmp(&BigInt::from(*b))),
+            (Value::Int(a), Value::Big(b)) => Some(BigInt::from(*a).cmp(b)),
+            _ => self.as_f64()?.partial_cmp(&other.as_f64()?),
+        }
+    }
+
+    // Structural equality, functions are never equal. The last field of each pair and
+    // constructor is compared in a loop rather than recursively, so that long lists
+    // don't run out of stack. Constructors that are shared, or whose hashes are known
+    // to differ, aren't compared field by field
+    pub fn equals(&self, other: &Value) -> bool {
+        let (mut a, mut b) = (self, other);
+        loop {
+            match (a, b) {
+                (Value::Num(_) | Value::Int(_) | Value::Big(_), _) => return a.compare_numbers(b) == Some(Ordering::Equal),
+                (Value::Char(x), Value::Char(y)) => return x == y,
+                (Value::Pair(x), Value::Pair(y)) => {
+                    if !x.0.equals(&y.0) {
+                        return false;
+                    }
+                    (a, b) = (&x.1, &y.1);
+                }
+                (Value::Data(x), Value::Data(y)) => {
+                    if Rc::ptr_eq(x, y) {
+                        return a.hashed().1;
+                    }
+                    if matches!((x.hashed.get(), y.hashed.get()), (Some(h), Some(k)) if h.0 != k.0) {
+                        return false;
+                    }
+                    if x.name != y.name || x.args.len() != y.args.len() {
+                        return false;
+                    }
+                    let Some((last, rest)) = x.args.split_last() else { return true };
+                    if !rest.iter().zip(&y.args).all(|(x, y)| x.equals(y)) {
+                        return false;
+                    }
+                    (a, b) = (last, &y.args[rest.len()]);
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    // Agrees with equals: values that are equal hash the same, so `1` and `1.0` do
+    pub fn structural_hash(&self) -> u64 {
+        self.hashed().0
+    }
+
+    // The hash, and whether the value equals itself, which it doesn't if there is a
+    // function or NaN in it. Computed down the last fields in a loop as equals compares
+    // them, and kept on each constructor on the way
+    fn hashed(&self) -> (u64, bool) {
+        // What is known of each pair and constructor above the value being looked at,
+        // everything but its last field
+        let mut above: Vec<((u64, bool), Option<&Data>)> = Vec::new();
+        let mut value = self;
+        let mut hashed = loop {
+            match value {
+                Value::Num(_) | Value::Int(_) | Value::Big(_) => {
+                    let n = value.as_f64().unwrap_or(0.0);
+                    // 0 and -0 are equal
+                    let bits = if n == 0.0 { 0 } else { n.to_bits() };
+                    break (mix(1, bits), !n.is_nan());
+                }
+                Value::Char(c) => break (mix(2, *c as u64), true),
+                Value::Function(_) => break (3, false),
+                Value::Pair(cell) => {
+                    let (h, reflexive) = cell.0.hashed();
+                    above.push(((mix(4, h), reflexive), None));
+                    value = &cell.1;
+                }
+                Value::Data(d) => {
+                    if let Some(&hashed) = d.hashed.get() {
+                        break hashed;
+                    }
+                    let mut prefix = (d.name.bytes().fold(5, |h, b| mix(h, b as u64)), true);
+                    let Some((last, rest)) = d.args.split_last() else {
+                        d.hashed.set(prefix).ok();
+                        break prefix;
+                    };
+                    for arg in rest {
+                        let (h, reflexive) = arg.hashed();
+                        prefix = (mix(prefix.0, h), prefix.1 && reflexive);
+                    }
+                    above.push((prefix, Some(d)));
+                    value = last;
+                }
+            }
+        };
+        while let Some(((h, reflexive), data)) = above.pop() {
+            hashed = (mix(h, hashed.0), reflexive && hashed.1);
+            if let Some(d) = data {
+                d.hashed.set(hashed).ok();
+            }
+        }
+        hashed
+    }
+
+    fn fmt_at(&self, f: &mut fmt::Formatter<'_>, atomic: bool) -> fmt::Result {
+        if let Some(items) = self.as_list() {
+            if !items.is_empty() && items.iter().all(|item| matches!(item, Value::Char(_))) {
+                let s: String = items.iter().map(|item| match item {
+                    Value::Char(c) => *c,
+                    _ => unreachable!(),
+                }).collect();
+                return write!(f, "{:?}", s);
+            }
+
+            write!(f, "[")?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                item.fmt_at(f, false)?;
+            }
+            return write!(f, "]");
+        }
+
+        match self {
+            Value::Num(n) => write_num(f, *n),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Big(n) => write!(f, "{}", n),
+            Value::Char(c) => write!(f, "{:?}", c),
+            Value::Pair(cell) => {
+                write!(f, "(")?;
+                cell.0.fmt_at(f, false)?;
+                let mut rest = &cell.1;
+                while let Value::Pair(cell) = rest {
+                    write!(f, ", ")?;
+                    cell.0.fmt_at(f, false)?;
+                    rest = &cell.1;
+                }
+                write!(f, ", ")?;
+                rest.fmt_at(f, false)?;
+                write!(f, ")")
+            }
+            Value::Data(d) if d.args.is_empty() => write!(f, "{}", d.name),
+            Value::Data(d) => {
+                if atomic {
+                    write!(f, "(")?;
+                }
+                match &d.args[..] {
+                    [Value::Pair(cell)] if is_symbolic(&d.name) => {
+                        cell.0.fmt_at(f, true)?;
+                        write!(f, " {} ", d.name)?;
+                        cell.1.fmt_at(f, true)?;
+                    }
+                    args => {
+                        write!(f, "{}", d.name)?;
+                        for arg in args {
+                            write!(f, " ")?;
+                            arg.fmt_at(f, true)?;
+                        }
+                    }
+                }
+                if atomic {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            Value::Function(_) => write!(f, "<function>"),
+        }
+    }
+}
+
+// So that it reads back as the same num: a whole num keeps its `.0`, and a very large
+// or very small one is written with an exponent rather than every digit
+fn write_num(f: &mut fmt::Formatter<'_>, n: f64) -> fmt::Result {
+    let size = n.abs();
+    if n.is_finite() && size != 0.0 && !(1e-7..1e20).contains(&size) {
+        write!(f, "{:e}", n)
+    } else if n.is_finite() && n.fract() == 0.0 {
+        write!(f, "{:.1}", n)
+    } else {
+        write!(f, "{}", n)
+    }
+}
+
+fn mix(h: u64, x: u64) -> u64 {
+    (h.rotate_left(5) ^ x).wrapping_mul(0x517c_c1b7_2722_0a95)
+}
+
+pub(super) fn is_symbolic(name: &str) -> bool {
+    !name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_at(f, false)
+    }
+}
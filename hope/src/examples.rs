@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+use crate::driver::{Driver, RunOutcome};
+
+// The programs of examples/, built in so that `hope examples` can show and run them
+// anywhere. Each comes with what it writes, which the tests hold it to
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Example {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub source: &'static str,
+    pub output: &'static str,
+}
+
+pub const EXAMPLES: [Example; 3] = [
+    Example {
+        name: "queens",
+        summary: "Every way to put eight queens on a chessboard with none attacking another",
+        source: include_str!("../../examples/queens.hop"),
+        output: include_str!("../../examples/queens.out"),
+    },
+    Example {
+        name: "deriv",
+        summary: "Symbolic differentiation, simplifying and writing out the derivative",
+        source: include_str!("../../examples/deriv.hop"),
+        output: include_str!("../../examples/deriv.out"),
+    },
+    Example {
+        name: "interp",
+        summary: "An interpreter, in Hope, of a small language with functions and recursion",
+        source: include_str!("../../examples/interp.hop"),
+        output: include_str!("../../examples/interp.out"),
+    },
+];
+
+// Where they seem to be, as the library modules seem to be in <library>
+pub const DIR: &str = "<examples>";
+
+pub fn example(name: &str) -> Option<&'static Example> {
+    EXAMPLES.iter().find(|example| example.name == name)
+}
+
+impl Example {
+    pub fn path(&self) -> PathBuf {
+        Path::new(DIR).join(format!("{}.hop", self.name))
+    }
+
+    // Runs it with the prelude, as `hope run` would the file
+    pub fn run(&self, driver: Driver) -> RunOutcome {
+        let path = self.path();
+        driver.with_prelude(true).with_source(&path, self.source.to_owned()).run(&[path])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_write_what_each_example_is_said_to() {
+        for example in &EXAMPLES {
+            let outcome = example.run(Driver::new());
+            assert!(outcome.succeeded() && outcome.diagnostics.is_empty(), "{}: {:?}", example.name, outcome.diagnostics);
+            assert_eq!(outcome.stdout, example.output, "{}", example.name);
+        }
+        assert_eq!(example("queens").unwrap().path(), Path::new("<examples>").join("queens.hop"));
+    }
+}
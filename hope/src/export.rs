@@ -0,0 +1,1063 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+
+// First-order functions and the data types they use, written out for a prover. Numbers
+// are the prover's integers and characters their codes. A polymorphic data type is
+// declared once for each type it is used at, `list num` as `list_num`. Each equation
+// is an axiom over its variables, which applies when its guard holds and no earlier
+// equation matches, so the first that matches is the one that counts, as it is when
+// the program runs. Functions the prover can't say, because they are polymorphic, take
+// or give functions, or use lambdas, fractions or functions that aren't exported
+// themselves, are left out with a comment saying why
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Smt2,
+    Tptp,
+}
+
+pub fn export(decls: &[Decl], target: Target) -> String {
+    let mut theory = Theory::new(decls);
+    let (functions, skipped) = theory.functions();
+    let mut out = String::new();
+    let comment = match target {
+        Target::Smt2 => ";",
+        Target::Tptp => "%",
+    };
+    out.push_str(&format!("{} From Hope, where numbers are integers and characters their codes\n", comment));
+    match target {
+        Target::Smt2 => theory.smt2(&functions, &mut out),
+        Target::Tptp => theory.tptp(&functions, &mut out),
+    }
+    for (name, reason) in skipped {
+        out.push_str(&format!("{} `{}` isn't exported: {}\n", comment, name, reason));
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Sort {
+    Int,
+    Bool,
+    // Lists are `list`, tuples `#` of two
+    Data(String, Vec<Sort>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    // Rounding toward zero, as Hope does
+    Div,
+    Mod,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    // Equality of truth values, which some provers write differently
+    Iff,
+    Ne,
+    And,
+    Or,
+    Not,
+    Implies,
+    Ite,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Var(String),
+    Int(i64),
+    // Past the range of i64, as its digits
+    Big(String),
+    Bool(bool),
+    // A function or constructor by the symbol it is written out as
+    App(String, Vec<Term>),
+    Op(Op, Vec<Term>),
+    Forall(Vec<(String, Sort)>, Box<Term>),
+    Exists(Vec<(String, Sort)>, Box<Term>),
+}
+
+#[derive(Debug, Clone)]
+struct Signature {
+    params: Vec<Sort>,
+    result: Sort,
+    // Takes a single tuple, whose parts are passed separately
+    tupled: bool,
+}
+
+// What a variable stands for: a value a pattern matched, or what a `let` gave it,
+// which is written out where it is used
+#[derive(Debug, Clone)]
+enum Bound<'d> {
+    Term(Term, Sort),
+    Expr(&'d Expr, Env<'d>),
+}
+
+type Env<'d> = BTreeMap<String, Bound<'d>>;
+
+// Each function exported, with an axiom for each of its equations
+type Axioms<'d> = Vec<(&'d str, Vec<Term>)>;
+
+// The arguments of a function or constructor, applied or written between them
+enum Args<'a, T> {
+    Curried(Vec<&'a T>),
+    Pair(&'a T, &'a T),
+}
+
+struct Matched<'d> {
+    args: Vec<Term>,
+    vars: Vec<(String, Sort)>,
+    guard: Option<Term>,
+    env: Env<'d>,
+    // Told apart from those of the equation an earlier one is matched in
+    suffix: String,
+    wildcards: usize,
+}
+
+impl<'d> Matched<'d> {
+    fn bind(&mut self, name: &str, sort: &Sort) -> Term {
+        let var = format!("{}{}", name, self.suffix);
+        self.vars.push((var.clone(), sort.clone()));
+        self.env.insert(name.to_owned(), Bound::Term(Term::Var(var.clone()), sort.clone()));
+        Term::Var(var)
+    }
+
+    fn fresh(&mut self, sort: &Sort) -> Term {
+        self.wildcards += 1;
+        let var = format!("_{}{}", self.wildcards, self.suffix);
+        self.vars.push((var.clone(), sort.clone()));
+        Term::Var(var)
+    }
+}
+
+struct Theory<'d> {
+    data: BTreeMap<&'d str, (Vec<&'d str>, &'d [Constructor])>,
+    synonyms: BTreeMap<&'d str, (Vec<&'d str>, &'d TypeExpr)>,
+    abstract_types: BTreeSet<&'d str>,
+    // Each constructor's data type
+    constructors: BTreeMap<&'d str, &'d str>,
+    decs: BTreeMap<&'d str, &'d TypeExpr>,
+    equations: Vec<(&'d str, Vec<&'d Equation>)>,
+    signatures: BTreeMap<&'d str, Signature>,
+    // The data types at each type they are used at, in the order they came up
+    sorts: Vec<Sort>,
+}
+
+impl<'d> Theory<'d> {
+    fn new(decls: &'d [Decl]) -> Self {
+        let mut theory = Theory {
+            data: BTreeMap::new(),
+            synonyms: BTreeMap::new(),
+            abstract_types: BTreeSet::new(),
+            constructors: BTreeMap::new(),
+            decs: BTreeMap::new(),
+            equations: Vec::new(),
+            signatures: BTreeMap::new(),
+            sorts: Vec::new(),
+        };
+        for decl in decls {
+            match &decl.kind {
+                DeclKind::Data { head, constructors } => {
+                    let params = head.params.iter().map(|p| p.name.as_str()).collect();
+                    theory.data.insert(&head.name.name, (params, constructors));
+                    for c in constructors {
+                        theory.constructors.insert(&c.name.name, &head.name.name);
+                    }
+                }
+                DeclKind::Type { head, body } => {
+                    let params = head.params.iter().map(|p| p.name.as_str()).collect();
+                    theory.synonyms.insert(&head.name.name, (params, body));
+                }
+                DeclKind::AbsType(head) => {
+                    theory.abstract_types.insert(&head.name.name);
+                }
+                DeclKind::Dec { names, ty } => {
+                    for name in names {
+                        theory.decs.insert(&name.name, ty);
+                    }
+                }
+                DeclKind::Equation(eq) => match theory.equations.iter_mut().find(|(name, _)| *name == eq.name.name) {
+                    Some((_, equations)) => equations.push(eq),
+                    None => theory.equations.push((&eq.name.name, vec![eq])),
+                },
+                _ => {}
+            }
+        }
+        theory
+    }
+
+    // The axioms of each function that can be exported, and why each other one isn't.
+    // Leaving one out can leave out those that use it, so this goes round until
+    // nothing more is
+    fn functions(&mut self) -> (Axioms<'d>, Vec<(&'d str, String)>) {
+        let mut skipped = Vec::new();
+        for (name, equations) in self.equations.clone() {
+            match self.signature(name, &equations) {
+                Ok(signature) => {
+                    self.signatures.insert(name, signature);
+                }
+                Err(reason) => skipped.push((name, reason)),
+            }
+        }
+        let mut gathered = false;
+        loop {
+            let mut functions = Vec::new();
+            let mut failed = false;
+            for (name, equations) in self.equations.clone() {
+                if !self.signatures.contains_key(name) {
+                    continue;
+                }
+                match self.axioms(name, &equations) {
+                    Ok(axioms) => functions.push((name, axioms)),
+                    Err(reason) => {
+                        self.signatures.remove(name);
+                        skipped.push((name, reason));
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                continue;
+            }
+            // Once nothing more is left out, once more to declare only the sorts of what
+            // is exported
+            if !gathered {
+                gathered = true;
+                self.sorts.clear();
+                let sorts: Vec<Sort> = self.signatures.values().flat_map(|s| s.params.iter().chain([&s.result])).cloned().collect();
+                sorts.iter().for_each(|sort| self.register(sort).unwrap_or_default());
+                continue;
+            }
+            let order: Vec<&str> = self.equations.iter().map(|(name, _)| *name).collect();
+            skipped.sort_by_key(|(name, _)| order.iter().position(|other| other == name));
+            return (functions, skipped);
+        }
+    }
+
+    fn signature(&mut self, name: &str, equations: &[&Equation]) -> Result<Signature, String> {
+        let Some(&ty) = self.decs.get(name) else { return Err("it has no `dec`".to_owned()) };
+        let mut params = Vec::new();
+        let mut result = ty;
+        for _ in 0..equations[0].args.len() {
+            if result.name.name != "->" || result.args.len() != 2 {
+                return Err("its `dec` doesn't give it as many arguments as its equations".to_owned());
+            }
+            params.push(&result.args[0]);
+            result = &result.args[1];
+        }
+        let result = self.sort(result, &BTreeMap::new())?;
+        let mut sorts = params.iter().map(|param| self.sort(param, &BTreeMap::new())).collect::<Result<Vec<_>, _>>()?;
+        let tupled = matches!(&params[..], [param] if param.name.name == "#");
+        if tupled {
+            sorts = spread(&sorts[0]);
+        }
+        for sort in sorts.iter().chain([&result]) {
+            self.register(sort)?;
+        }
+        Ok(Signature { params: sorts, result, tupled })
+    }
+
+    fn sort(&self, ty: &TypeExpr, params: &BTreeMap<&str, Sort>) -> Result<Sort, String> {
+        let name = ty.name.name.as_str();
+        if let Some(sort) = params.get(name) {
+            return Ok(sort.clone());
+        }
+        let args = || ty.args.iter().map(|arg| self.sort(arg, params)).collect::<Result<Vec<_>, _>>();
+        match name {
+            "num" | "char" => Ok(Sort::Int),
+            "bool" => Ok(Sort::Bool),
+            "->" => Err("it takes or gives functions".to_owned()),
+            "list" | "#" => Ok(Sort::Data(name.to_owned(), args()?)),
+            _ if self.data.contains_key(name) => Ok(Sort::Data(name.to_owned(), args()?)),
+            _ if self.abstract_types.contains(name) => Err(format!("`{}` is an abstract type", name)),
+            _ => match self.synonyms.get(name) {
+                Some((names, body)) => {
+                    let inner = names.iter().copied().zip(args()?).collect();
+                    self.sort(body, &inner)
+                }
+                None if name == "formatarg" => Err("`formatarg` has nothing to stand for it".to_owned()),
+                // Anything else in a checked program is a type variable
+                None => Err("its type is polymorphic".to_owned()),
+            },
+        }
+    }
+
+    fn constructor_names(&self, sort: &Sort) -> Vec<&'d str> {
+        match sort {
+            Sort::Data(name, _) if name == "list" => vec!["nil", "::"],
+            Sort::Data(name, _) if name == "#" => vec!["#"],
+            Sort::Data(name, _) => self.data[name.as_str()].1.iter().map(|c| c.name.name.as_str()).collect(),
+            Sort::Int | Sort::Bool => Vec::new(),
+        }
+    }
+
+    // The sorts of the constructor's arguments, with those of a constructor that takes
+    // a tuple taken apart, and whether it was
+    fn fields(&self, sort: &Sort, constructor: &str) -> Result<(Vec<Sort>, bool), String> {
+        let mismatch = || format!("`{}` is used at a type it doesn't make", constructor);
+        let Sort::Data(name, args) = sort else { return Err(mismatch()) };
+        match (name.as_str(), constructor) {
+            ("list", "nil") => return Ok((Vec::new(), false)),
+            ("list", "::") => return Ok((vec![args[0].clone(), sort.clone()], true)),
+            ("#", "#") => return Ok((args.clone(), false)),
+            ("list" | "#", _) => return Err(mismatch()),
+            _ => {}
+        }
+        let (names, constructors) = &self.data[name.as_str()];
+        let Some(c) = constructors.iter().find(|c| c.name.name == constructor) else { return Err(mismatch()) };
+        let params = names.iter().copied().zip(args.iter().cloned()).collect();
+        let fields = c.args.iter().map(|arg| self.sort(arg, &params)).collect::<Result<Vec<_>, _>>()?;
+        match &c.args[..] {
+            [arg] if arg.name.name == "#" => Ok((spread(&fields[0]), true)),
+            _ => Ok((fields, false)),
+        }
+    }
+
+    fn is_constructor(&self, name: &str) -> bool {
+        matches!(name, "nil" | "::" | "true" | "false") || self.constructors.contains_key(name)
+    }
+
+    // Adds the data type at this sort, and those its constructors take, to what is
+    // declared, or none of them if any can't be
+    fn register(&mut self, sort: &Sort) -> Result<(), String> {
+        let mut sorts = self.sorts.clone();
+        self.reach(sort, &mut sorts)?;
+        self.sorts = sorts;
+        Ok(())
+    }
+
+    fn reach(&self, sort: &Sort, sorts: &mut Vec<Sort>) -> Result<(), String> {
+        if !matches!(sort, Sort::Data(..)) || sorts.contains(sort) {
+            return Ok(());
+        }
+        sorts.push(sort.clone());
+        for c in self.constructor_names(sort) {
+            for field in self.fields(sort, c)?.0 {
+                self.reach(&field, sorts)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn construct(&mut self, constructor: &str, sort: &Sort, args: Vec<Term>) -> Result<Term, String> {
+        self.register(sort)?;
+        Ok(Term::App(constructor_symbol(constructor, sort), args))
+    }
+
+    fn tuple(&mut self, mut terms: Vec<Term>, sorts: &[Sort]) -> Result<Term, String> {
+        let mut whole = terms.pop().unwrap();
+        for (i, term) in terms.into_iter().enumerate().rev() {
+            let sort = tuple_sort(&sorts[i..]);
+            whole = self.construct("#", &sort, vec![term, whole])?;
+        }
+        Ok(whole)
+    }
+
+    fn list(&mut self, items: Vec<Term>, sort: &Sort) -> Result<Term, String> {
+        let mut list = self.construct("nil", sort, Vec::new())?;
+        for item in items.into_iter().rev() {
+            list = self.construct("::", sort, vec![item, list])?;
+        }
+        Ok(list)
+    }
+
+    fn axioms(&mut self, name: &'d str, equations: &[&'d Equation]) -> Result<Vec<Term>, String> {
+        let signature = self.signatures[name].clone();
+        let mut axioms = Vec::new();
+        for (i, eq) in equations.iter().enumerate() {
+            let matched = self.matched(eq, &signature, String::new())?;
+            let body = self.expr(&eq.body, &signature.result, &matched.env)?;
+            let mut premises: Vec<Term> = matched.guard.into_iter().collect();
+            for (j, earlier) in equations[..i].iter().enumerate() {
+                let other = self.matched(earlier, &signature, format!("_{}", j + 1))?;
+                if matched.args.iter().zip(&other.args).any(|(a, b)| disjoint(a, b)) {
+                    continue;
+                }
+                let mut same: Vec<Term> = matched.args.iter().zip(&other.args).zip(&signature.params)
+                    .map(|((a, b), sort)| equal(a.clone(), b.clone(), sort))
+                    .collect();
+                same.extend(other.guard);
+                premises.push(Term::Op(Op::Not, vec![quantify(false, other.vars, and(same))]));
+            }
+            let conclusion = equal(Term::App(symbol(name), matched.args), body, &signature.result);
+            let axiom = match premises.is_empty() {
+                true => conclusion,
+                false => Term::Op(Op::Implies, vec![and(premises), conclusion]),
+            };
+            axioms.push(quantify(true, matched.vars, axiom));
+        }
+        Ok(axioms)
+    }
+
+    fn matched(&mut self, eq: &'d Equation, signature: &Signature, suffix: String) -> Result<Matched<'d>, String> {
+        let mut matched = Matched { args: Vec::new(), vars: Vec::new(), guard: None, env: Env::new(), suffix, wildcards: 0 };
+        let args: Vec<&Pattern> = eq.args.iter().collect();
+        matched.args = self.pattern_args(&eq.name.name, Args::Curried(args), &signature.params, signature.tupled, &mut matched)?;
+        if let Some(guard) = &eq.guard {
+            matched.guard = Some(self.expr(guard, &Sort::Bool, &matched.env.clone())?);
+        }
+        Ok(matched)
+    }
+
+    fn pattern_args(&mut self, name: &str, args: Args<'_, Pattern>, sorts: &[Sort], tupled: bool, matched: &mut Matched<'d>) -> Result<Vec<Term>, String> {
+        let items: Vec<&Pattern> = match (args, tupled) {
+            (Args::Pair(l, r), true) => vec![l, r],
+            (Args::Curried(args), true) => match &args[..] {
+                [Pattern { kind: PatternKind::Tuple(items), .. }] => items.iter().collect(),
+                // The whole tuple, made from its parts
+                [Pattern { kind: PatternKind::Var(var), .. }] if !self.is_constructor(var) => {
+                    let terms: Vec<Term> = sorts.iter().map(|sort| matched.fresh(sort)).collect();
+                    let whole = self.tuple(terms.clone(), sorts)?;
+                    matched.env.insert(var.clone(), Bound::Term(whole, tuple_sort(sorts)));
+                    return Ok(terms);
+                }
+                [Pattern { kind: PatternKind::Wildcard, .. }] => return Ok(sorts.iter().map(|sort| matched.fresh(sort)).collect()),
+                _ => return Err(shape(name)),
+            },
+            (Args::Curried(args), false) => args,
+            (Args::Pair(..), false) => return Err(shape(name)),
+        };
+        if items.len() != sorts.len() {
+            return Err(shape(name));
+        }
+        items.into_iter().zip(sorts).map(|(p, sort)| self.pattern(p, sort, matched)).collect()
+    }
+
+    fn pattern(&mut self, p: &Pattern, sort: &Sort, matched: &mut Matched<'d>) -> Result<Term, String> {
+        match &p.kind {
+            PatternKind::Var(name) if *sort == Sort::Bool && matches!(name.as_str(), "true" | "false") => Ok(Term::Bool(name == "true")),
+            PatternKind::Var(name) if self.is_constructor(name) => self.construct(name, sort, Vec::new()),
+            PatternKind::Var(name) => Ok(matched.bind(name, sort)),
+            PatternKind::Wildcard => Ok(matched.fresh(sort)),
+            PatternKind::As(name, inner) => {
+                let term = self.pattern(inner, sort, matched)?;
+                matched.env.insert(name.name.clone(), Bound::Term(term.clone(), sort.clone()));
+                Ok(term)
+            }
+            PatternKind::Int(n) => Ok(Term::Int(*n)),
+            PatternKind::BigInt(digits) => Ok(Term::Big(digits.clone())),
+            PatternKind::Num(x) => whole(*x).map(Term::Int),
+            PatternKind::Str(s) => self.list(s.chars().map(|c| Term::Int(c as i64)).collect(), sort),
+            PatternKind::Tuple(items) => {
+                let sorts = parts(sort, items.len())?;
+                let terms = items.iter().zip(&sorts).map(|(p, sort)| self.pattern(p, sort, matched)).collect::<Result<Vec<_>, _>>()?;
+                self.tuple(terms, &sorts)
+            }
+            PatternKind::List(items) => {
+                let element = element(sort)?;
+                let terms = items.iter().map(|p| self.pattern(p, &element, matched)).collect::<Result<Vec<_>, _>>()?;
+                self.list(terms, sort)
+            }
+            PatternKind::Construct(c, args) => {
+                let (fields, tupled) = self.fields(sort, &c.name)?;
+                let terms = self.pattern_args(&c.name, Args::Curried(args.iter().collect()), &fields, tupled, matched)?;
+                self.construct(&c.name, sort, terms)
+            }
+            PatternKind::BinOp(op, l, r) => {
+                let (fields, tupled) = self.fields(sort, &op.name)?;
+                let terms = self.pattern_args(&op.name, Args::Pair(l, r), &fields, tupled, matched)?;
+                self.construct(&op.name, sort, terms)
+            }
+        }
+    }
+
+    fn expr(&mut self, e: &'d Expr, sort: &Sort, env: &Env<'d>) -> Result<Term, String> {
+        match &e.kind {
+            ExprKind::Int(n) => Ok(Term::Int(*n)),
+            ExprKind::BigInt(digits) => Ok(Term::Big(digits.clone())),
+            ExprKind::Num(x) => whole(*x).map(Term::Int),
+            ExprKind::Str(s) => self.list(s.chars().map(|c| Term::Int(c as i64)).collect(), sort),
+            ExprKind::Tuple(items) => {
+                let sorts = parts(sort, items.len())?;
+                let terms = items.iter().zip(&sorts).map(|(e, sort)| self.expr(e, sort, env)).collect::<Result<Vec<_>, _>>()?;
+                self.tuple(terms, &sorts)
+            }
+            ExprKind::List(items) => {
+                let element = element(sort)?;
+                let terms = items.iter().map(|e| self.expr(e, &element, env)).collect::<Result<Vec<_>, _>>()?;
+                self.list(terms, sort)
+            }
+            ExprKind::Var(name) => self.call(name, Args::Curried(Vec::new()), sort, env),
+            ExprKind::Apply(..) => {
+                let (head, args) = unwind(e);
+                match &head.kind {
+                    ExprKind::Var(name) => self.call(name, Args::Curried(args), sort, env),
+                    _ => Err("it applies a function an expression gives".to_owned()),
+                }
+            }
+            ExprKind::BinOp(op, l, r) => self.call(&op.name, Args::Pair(l, r), sort, env),
+            ExprKind::If(c, t, f) => {
+                let args = vec![self.expr(c, &Sort::Bool, env)?, self.expr(t, sort, env)?, self.expr(f, sort, env)?];
+                Ok(Term::Op(Op::Ite, args))
+            }
+            ExprKind::Lambda(_) => Err("it has lambdas".to_owned()),
+            ExprKind::Let(binding) => match &binding.pattern.kind {
+                PatternKind::Var(name) if !binding.kind.is_rec() => {
+                    let mut inner = env.clone();
+                    inner.insert(name.clone(), Bound::Expr(&binding.value, env.clone()));
+                    self.expr(&binding.body, sort, &inner)
+                }
+                _ => Err("it takes a value apart in a `let` or `where`, or defines one recursively".to_owned()),
+            },
+        }
+    }
+
+    fn call(&mut self, name: &str, args: Args<'d, Expr>, sort: &Sort, env: &Env<'d>) -> Result<Term, String> {
+        if let Some(bound) = env.get(name) {
+            return match (bound, args) {
+                (Bound::Term(term, _), Args::Curried(args)) if args.is_empty() => Ok(term.clone()),
+                (Bound::Expr(value, inner), Args::Curried(args)) if args.is_empty() => {
+                    let (value, inner) = (*value, inner.clone());
+                    self.expr(value, sort, &inner)
+                }
+                _ => Err("it applies a function it is given".to_owned()),
+            };
+        }
+        if BINARY.contains(&name) {
+            return match args {
+                Args::Pair(l, r) => self.builtin(name, l, r, env),
+                Args::Curried(args) => match &args[..] {
+                    [Expr { kind: ExprKind::Tuple(items), .. }] if items.len() == 2 => self.builtin(name, &items[0], &items[1], env),
+                    _ => Err(shape(name)),
+                },
+            };
+        }
+        match (name, &args) {
+            ("true" | "false", Args::Curried(args)) if args.is_empty() => return Ok(Term::Bool(name == "true")),
+            ("not", Args::Curried(args)) if args.len() == 1 => return Ok(Term::Op(Op::Not, vec![self.expr(args[0], &Sort::Bool, env)?])),
+            // Characters are their codes already
+            ("ord" | "chr", Args::Curried(args)) if args.len() == 1 => return self.expr(args[0], &Sort::Int, env),
+            _ => {}
+        }
+        if self.is_constructor(name) {
+            let (fields, tupled) = self.fields(sort, name)?;
+            let terms = self.args(name, args, &fields, tupled, env)?;
+            return self.construct(name, sort, terms);
+        }
+        match self.signatures.get(name).cloned() {
+            Some(signature) => {
+                let terms = self.args(name, args, &signature.params, signature.tupled, env)?;
+                Ok(Term::App(symbol(name), terms))
+            }
+            None => Err(format!("it uses `{}`, which isn't exported", name)),
+        }
+    }
+
+    fn args(&mut self, name: &str, args: Args<'d, Expr>, sorts: &[Sort], tupled: bool, env: &Env<'d>) -> Result<Vec<Term>, String> {
+        let items: Vec<&'d Expr> = match (args, tupled) {
+            (Args::Pair(l, r), true) => vec![l, r],
+            (Args::Curried(args), true) => match &args[..] {
+                [arg] => match &arg.kind {
+                    ExprKind::Tuple(items) => items.iter().collect(),
+                    _ => return Err(shape(name)),
+                },
+                _ => return Err(shape(name)),
+            },
+            (Args::Curried(args), false) => args,
+            (Args::Pair(..), false) => return Err(shape(name)),
+        };
+        if items.len() != sorts.len() {
+            return Err(shape(name));
+        }
+        items.into_iter().zip(sorts).map(|(e, sort)| self.expr(e, sort, env)).collect()
+    }
+
+    fn builtin(&mut self, name: &str, l: &'d Expr, r: &'d Expr, env: &Env<'d>) -> Result<Term, String> {
+        let (op, sort) = match name {
+            "+" => (Op::Add, Sort::Int),
+            "-" => (Op::Sub, Sort::Int),
+            "*" => (Op::Mul, Sort::Int),
+            "div" => (Op::Div, Sort::Int),
+            "mod" => (Op::Mod, Sort::Int),
+            "<" => (Op::Lt, Sort::Int),
+            "=<" => (Op::Le, Sort::Int),
+            ">" => (Op::Gt, Sort::Int),
+            ">=" => (Op::Ge, Sort::Int),
+            "and" => (Op::And, Sort::Bool),
+            "or" => (Op::Or, Sort::Bool),
+            "=" | "/=" => {
+                let sort = self.synth(l, env).or_else(|_| self.synth(r, env))?;
+                let same = equal(self.expr(l, &sort, env)?, self.expr(r, &sort, env)?, &sort);
+                return Ok(if name == "=" { same } else { Term::Op(Op::Not, vec![same]) });
+            }
+            _ => return Err("it divides with `/`, which can give fractions".to_owned()),
+        };
+        Ok(Term::Op(op, vec![self.expr(l, &sort, env)?, self.expr(r, &sort, env)?]))
+    }
+
+    // The sort of what an expression gives, for the sides of `=` and `/=`, as far as
+    // it can be told by looking
+    fn synth(&self, e: &Expr, env: &Env<'d>) -> Result<Sort, String> {
+        let unknown = || Err("it compares values whose type can't be told from what they are".to_owned());
+        let list = |element| Sort::Data("list".to_owned(), vec![element]);
+        match &e.kind {
+            ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) => Ok(Sort::Int),
+            ExprKind::Str(_) => Ok(list(Sort::Int)),
+            ExprKind::Tuple(items) => Ok(tuple_sort(&items.iter().map(|e| self.synth(e, env)).collect::<Result<Vec<_>, _>>()?)),
+            ExprKind::List(items) => match items.first() {
+                Some(first) => self.synth(first, env).map(list),
+                None => unknown(),
+            },
+            ExprKind::Var(_) | ExprKind::Apply(..) => {
+                let (head, args) = unwind(e);
+                let ExprKind::Var(name) = &head.kind else { return unknown() };
+                match env.get(name.as_str()) {
+                    Some(Bound::Term(_, sort)) if args.is_empty() => return Ok(sort.clone()),
+                    Some(Bound::Expr(value, inner)) if args.is_empty() => return self.synth(value, inner),
+                    Some(_) => return unknown(),
+                    None => {}
+                }
+                match name.as_str() {
+                    "true" | "false" | "not" => Ok(Sort::Bool),
+                    "ord" | "chr" => Ok(Sort::Int),
+                    name => self.named(name).map_or_else(unknown, Ok),
+                }
+            }
+            ExprKind::BinOp(op, l, r) => match op.name.as_str() {
+                "+" | "-" | "*" | "div" | "mod" => Ok(Sort::Int),
+                "<" | "=<" | ">" | ">=" | "and" | "or" | "=" | "/=" => Ok(Sort::Bool),
+                "::" => self.synth(r, env).or_else(|_| self.synth(l, env).map(list)),
+                name => self.named(name).map_or_else(unknown, Ok),
+            },
+            ExprKind::If(_, t, f) => self.synth(t, env).or_else(|_| self.synth(f, env)),
+            ExprKind::Let(binding) => match &binding.pattern.kind {
+                PatternKind::Var(name) => {
+                    let mut inner = env.clone();
+                    inner.insert(name.clone(), Bound::Expr(&binding.value, env.clone()));
+                    self.synth(&binding.body, &inner)
+                }
+                _ => unknown(),
+            },
+            ExprKind::Lambda(_) => unknown(),
+        }
+    }
+
+    // What a function gives, or the data type a constructor of one with no parameters
+    // makes
+    fn named(&self, name: &str) -> Option<Sort> {
+        if let Some(signature) = self.signatures.get(name) {
+            return Some(signature.result.clone());
+        }
+        let data = self.constructors.get(name)?;
+        self.data[data].0.is_empty().then(|| Sort::Data((*data).to_owned(), Vec::new()))
+    }
+
+    fn smt2(&self, functions: &Axioms<'_>, out: &mut String) {
+        if functions.iter().flat_map(|(_, axioms)| axioms).any(divides) {
+            out.push_str("(define-fun hope_div ((a Int) (b Int)) Int (ite (>= a 0) (div a b) (- (div (- a) b))))\n");
+            out.push_str("(define-fun hope_mod ((a Int) (b Int)) Int (ite (>= a 0) (mod a b) (- (mod (- a) b))))\n");
+        }
+        if !self.sorts.is_empty() {
+            let names: Vec<String> = self.sorts.iter().map(|sort| format!("({} 0)", sort_name(sort))).collect();
+            let bodies: Vec<String> = self.sorts.iter().map(|sort| {
+                let constructors: Vec<String> = self.made(sort).into_iter().map(|(c, fields)| {
+                    let fields: Vec<String> = fields.iter().enumerate()
+                        .map(|(i, field)| format!(" ({}_{} {})", c, i + 1, smt_sort(field)))
+                        .collect();
+                    format!("({}{})", c, fields.concat())
+                }).collect();
+                format!("({})", constructors.join(" "))
+            }).collect();
+            out.push_str(&format!("(declare-datatypes ({})\n  ({}))\n", names.join(" "), bodies.join("\n   ")));
+        }
+        for (name, _) in functions {
+            let signature = &self.signatures[name];
+            let params: Vec<String> = signature.params.iter().map(smt_sort).collect();
+            out.push_str(&format!("(declare-fun {} ({}) {})\n", symbol(name), params.join(" "), smt_sort(&signature.result)));
+        }
+        for (_, axioms) in functions {
+            axioms.iter().for_each(|axiom| out.push_str(&format!("(assert {})\n", smt(axiom))));
+        }
+    }
+
+    fn tptp(&self, functions: &Axioms<'_>, out: &mut String) {
+        for sort in &self.sorts {
+            out.push_str(&format!("tff({0}_type, type, {0}: $tType).\n", sort_name(sort)));
+        }
+        for sort in &self.sorts {
+            for (c, fields) in self.made(sort) {
+                out.push_str(&format!("tff({0}_type, type, {0}: {1}).\n", c, tptp_type(&fields, sort)));
+            }
+        }
+        for sort in &self.sorts {
+            for (name, axiom) in self.datatype_axioms(sort) {
+                out.push_str(&format!("tff({}, axiom, {}).\n", name, tptp(&axiom)));
+            }
+        }
+        for (name, _) in functions {
+            let signature = &self.signatures[name];
+            out.push_str(&format!("tff({0}_type, type, {0}: {1}).\n", symbol(name), tptp_type(&signature.params, &signature.result)));
+        }
+        for (name, axioms) in functions {
+            for (i, axiom) in axioms.iter().enumerate() {
+                out.push_str(&format!("tff({}_{}, axiom, {}).\n", symbol(name), i + 1, tptp(axiom)));
+            }
+        }
+    }
+
+    // The symbol of each constructor of the sort, and the sorts it takes
+    fn made(&self, sort: &Sort) -> Vec<(String, Vec<Sort>)> {
+        self.constructor_names(sort).into_iter()
+            .map(|c| (constructor_symbol(c, sort), self.fields(sort, c).map(|(fields, _)| fields).unwrap_or_default()))
+            .collect()
+    }
+
+    // What SMT solvers know of their datatypes without being told: that different
+    // constructors make different values, that the same one makes the same value only
+    // from the same arguments, and that nothing else makes them
+    fn datatype_axioms(&self, sort: &Sort) -> Vec<(String, Term)> {
+        let name = sort_name(sort);
+        let made = self.made(sort);
+        let vars = |prefix: &str, fields: &[Sort]| -> Vec<(String, Sort)> {
+            fields.iter().enumerate().map(|(i, field)| (format!("{}{}", prefix, i + 1), field.clone())).collect()
+        };
+        let apply = |c: &str, vars: &[(String, Sort)]| Term::App(c.to_owned(), vars.iter().map(|(var, _)| Term::Var(var.clone())).collect());
+        let mut axioms = Vec::new();
+        for (i, (a, a_fields)) in made.iter().enumerate() {
+            for (b, b_fields) in &made[i + 1..] {
+                let (xs, ys) = (vars("x", a_fields), vars("y", b_fields));
+                let distinct = Term::Op(Op::Ne, vec![apply(a, &xs), apply(b, &ys)]);
+                axioms.push((format!("{}_distinct_{}", name, axioms.len() + 1), quantify(true, [xs, ys].concat(), distinct)));
+            }
+        }
+        for (c, fields) in made.iter().filter(|(_, fields)| !fields.is_empty()) {
+            let (xs, ys) = (vars("x", fields), vars("y", fields));
+            let same = xs.iter().zip(&ys).map(|((x, sort), (y, _))| equal(Term::Var(x.clone()), Term::Var(y.clone()), sort)).collect();
+            let injective = Term::Op(Op::Implies, vec![Term::Op(Op::Eq, vec![apply(c, &xs), apply(c, &ys)]), and(same)]);
+            axioms.push((format!("{}_injective", c), quantify(true, [xs, ys].concat(), injective)));
+        }
+        let cases = made.iter().map(|(c, fields)| {
+            let ys = vars("y", fields);
+            let case = Term::Op(Op::Eq, vec![Term::Var("x".to_owned()), apply(c, &ys)]);
+            quantify(false, ys, case)
+        }).collect();
+        axioms.push((format!("{}_exhaustive", name), quantify(true, vec![("x".to_owned(), sort.clone())], or(cases))));
+        axioms
+    }
+}
+
+const BINARY: [&str; 14] = ["+", "-", "*", "/", "div", "mod", "<", "=<", ">", ">=", "=", "/=", "and", "or"];
+
+// Names SMT-LIB gives its own functions
+const RESERVED: [&str; 12] = ["abs", "distinct", "ite", "xor", "let", "forall", "exists", "assert", "is_int", "to_int", "to_real", "match"];
+
+fn shape(name: &str) -> String {
+    format!("it uses `{}` other than with all its arguments written out", name)
+}
+
+fn whole(x: f64) -> Result<i64, String> {
+    if x as i64 as f64 == x { Ok(x as i64) } else { Err("it has fractional numbers".to_owned()) }
+}
+
+fn element(sort: &Sort) -> Result<Sort, String> {
+    match sort {
+        Sort::Data(name, args) if name == "list" => Ok(args[0].clone()),
+        _ => Err("it uses a list where the prover expects something else".to_owned()),
+    }
+}
+
+// The sorts of the first n parts of a tuple, the last of them the rest of it
+fn parts(sort: &Sort, n: usize) -> Result<Vec<Sort>, String> {
+    let mut parts = Vec::new();
+    let mut rest = sort;
+    for _ in 1..n {
+        match rest {
+            Sort::Data(name, args) if name == "#" => {
+                parts.push(args[0].clone());
+                rest = &args[1];
+            }
+            _ => return Err("it uses a tuple where the prover expects something else".to_owned()),
+        }
+    }
+    parts.push(rest.clone());
+    Ok(parts)
+}
+
+fn unwind(mut e: &Expr) -> (&Expr, Vec<&Expr>) {
+    let mut args = Vec::new();
+    while let ExprKind::Apply(f, arg) = &e.kind {
+        args.push(&**arg);
+        e = f;
+    }
+    args.reverse();
+    (e, args)
+}
+
+fn equal(a: Term, b: Term, sort: &Sort) -> Term {
+    Term::Op(if *sort == Sort::Bool { Op::Iff } else { Op::Eq }, vec![a, b])
+}
+
+fn and(mut terms: Vec<Term>) -> Term {
+    match terms.len() {
+        0 => Term::Bool(true),
+        1 => terms.pop().unwrap(),
+        _ => Term::Op(Op::And, terms),
+    }
+}
+
+fn or(mut terms: Vec<Term>) -> Term {
+    match terms.len() {
+        0 => Term::Bool(false),
+        1 => terms.pop().unwrap(),
+        _ => Term::Op(Op::Or, terms),
+    }
+}
+
+fn quantify(universal: bool, vars: Vec<(String, Sort)>, body: Term) -> Term {
+    match (vars.is_empty(), universal) {
+        (true, _) => body,
+        (false, true) => Term::Forall(vars, Box::new(body)),
+        (false, false) => Term::Exists(vars, Box::new(body)),
+    }
+}
+
+// Whether two patterns' terms can't be the same value, as constructors and numbers
+// that differ can't
+fn disjoint(a: &Term, b: &Term) -> bool {
+    match (a, b) {
+        (Term::App(c, xs), Term::App(d, ys)) => c != d || xs.iter().zip(ys).any(|(x, y)| disjoint(x, y)),
+        (Term::Int(x), Term::Int(y)) => x != y,
+        (Term::Big(x), Term::Big(y)) => x != y,
+        (Term::Int(_), Term::Big(_)) | (Term::Big(_), Term::Int(_)) => true,
+        (Term::Bool(x), Term::Bool(y)) => x != y,
+        _ => false,
+    }
+}
+
+fn divides(term: &Term) -> bool {
+    match term {
+        Term::Op(Op::Div | Op::Mod, _) => true,
+        Term::App(_, args) | Term::Op(_, args) => args.iter().any(divides),
+        Term::Forall(_, body) | Term::Exists(_, body) => divides(body),
+        Term::Var(_) | Term::Int(_) | Term::Big(_) | Term::Bool(_) => false,
+    }
+}
+
+// A name as something both kinds of prover take, `<>` as `lt_gt`
+fn symbol(name: &str) -> String {
+    match name {
+        "::" => return "cons".to_owned(),
+        "#" => return "tuple".to_owned(),
+        _ => {}
+    }
+    let mut out = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            out.push(c);
+            continue;
+        }
+        let word = match c {
+            '<' => "lt",
+            '>' => "gt",
+            '=' => "eq",
+            '+' => "plus",
+            '-' => "minus",
+            '*' => "times",
+            '/' => "slash",
+            ':' => "colon",
+            '&' => "amp",
+            '|' => "bar",
+            '!' => "bang",
+            '.' => "dot",
+            '^' => "caret",
+            '@' => "at",
+            '\'' => "prime",
+            '~' => "tilde",
+            '?' => "query",
+            _ => "sym",
+        };
+        if !out.is_empty() && !out.ends_with('_') {
+            out.push('_');
+        }
+        out.push_str(word);
+    }
+    if !out.starts_with(|c: char| c.is_ascii_lowercase()) || RESERVED.contains(&out.as_str()) {
+        out.insert_str(0, "h_");
+    }
+    out
+}
+
+fn sort_name(sort: &Sort) -> String {
+    match sort {
+        Sort::Int => "num".to_owned(),
+        Sort::Bool => "bool".to_owned(),
+        Sort::Data(name, args) => core::iter::once(symbol(name)).chain(args.iter().map(sort_name)).collect::<Vec<_>>().join("_"),
+    }
+}
+
+fn constructor_symbol(constructor: &str, sort: &Sort) -> String {
+    match sort {
+        Sort::Data(name, _) if name == "#" => format!("mk_{}", sort_name(sort)),
+        Sort::Data(_, args) if !args.is_empty() => format!("{}_{}", symbol(constructor), sort_name(sort)),
+        _ => symbol(constructor),
+    }
+}
+
+fn var_name(var: &str) -> String {
+    var.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+fn smt_sort(sort: &Sort) -> String {
+    match sort {
+        Sort::Int => "Int".to_owned(),
+        Sort::Bool => "Bool".to_owned(),
+        Sort::Data(..) => sort_name(sort),
+    }
+}
+
+fn smt(term: &Term) -> String {
+    let list = |terms: &[Term]| terms.iter().map(smt).collect::<Vec<_>>().join(" ");
+    let vars = |vars: &[(String, Sort)]| vars.iter().map(|(var, sort)| format!("({} {})", var_name(var), smt_sort(sort))).collect::<Vec<_>>().join(" ");
+    match term {
+        Term::Var(var) => var_name(var),
+        Term::Int(n) if *n < 0 => format!("(- {})", n.unsigned_abs()),
+        Term::Int(n) => n.to_string(),
+        Term::Big(digits) => digits.clone(),
+        Term::Bool(b) => b.to_string(),
+        Term::App(f, args) if args.is_empty() => f.clone(),
+        Term::App(f, args) => format!("({} {})", f, list(args)),
+        Term::Op(op, args) => {
+            let op = match op {
+                Op::Add => "+",
+                Op::Sub => "-",
+                Op::Mul => "*",
+                Op::Div => "hope_div",
+                Op::Mod => "hope_mod",
+                Op::Lt => "<",
+                Op::Le => "<=",
+                Op::Gt => ">",
+                Op::Ge => ">=",
+                Op::Eq | Op::Iff => "=",
+                Op::Ne => "distinct",
+                Op::And => "and",
+                Op::Or => "or",
+                Op::Not => "not",
+                Op::Implies => "=>",
+                Op::Ite => "ite",
+            };
+            format!("({} {})", op, list(args))
+        }
+        Term::Forall(bound, body) => format!("(forall ({}) {})", vars(bound), smt(body)),
+        Term::Exists(bound, body) => format!("(exists ({}) {})", vars(bound), smt(body)),
+    }
+}
+
+fn tptp_sort(sort: &Sort) -> String {
+    match sort {
+        Sort::Int => "$int".to_owned(),
+        Sort::Bool => "$o".to_owned(),
+        Sort::Data(..) => sort_name(sort),
+    }
+}
+
+fn tptp_type(params: &[Sort], result: &Sort) -> String {
+    match params {
+        [] => tptp_sort(result),
+        [param] => format!("{} > {}", tptp_sort(param), tptp_sort(result)),
+        params => format!("({}) > {}", params.iter().map(tptp_sort).collect::<Vec<_>>().join(" * "), tptp_sort(result)),
+    }
+}
+
+fn tptp(term: &Term) -> String {
+    let list = |terms: &[Term], between: &str| terms.iter().map(tptp).collect::<Vec<_>>().join(between);
+    let vars = |vars: &[(String, Sort)]| vars.iter().map(|(var, sort)| format!("V{}: {}", var_name(var), tptp_sort(sort))).collect::<Vec<_>>().join(", ");
+    match term {
+        Term::Var(var) => format!("V{}", var_name(var)),
+        Term::Int(n) => n.to_string(),
+        Term::Big(digits) => digits.clone(),
+        Term::Bool(b) => format!("${}", b),
+        Term::App(f, args) if args.is_empty() => f.clone(),
+        Term::App(f, args) => format!("{}({})", f, list(args, ", ")),
+        Term::Op(op, args) => match op {
+            Op::Add => format!("$sum({})", list(args, ", ")),
+            Op::Sub => format!("$difference({})", list(args, ", ")),
+            Op::Mul => format!("$product({})", list(args, ", ")),
+            Op::Div => format!("$quotient_t({})", list(args, ", ")),
+            Op::Mod => format!("$remainder_t({})", list(args, ", ")),
+            Op::Lt => format!("$less({})", list(args, ", ")),
+            Op::Le => format!("$lesseq({})", list(args, ", ")),
+            Op::Gt => format!("$greater({})", list(args, ", ")),
+            Op::Ge => format!("$greatereq({})", list(args, ", ")),
+            Op::Ite => format!("$ite({})", list(args, ", ")),
+            Op::Eq => format!("({})", list(args, " = ")),
+            Op::Iff => format!("({})", list(args, " <=> ")),
+            Op::Ne => format!("({})", list(args, " != ")),
+            Op::And => format!("({})", list(args, " & ")),
+            Op::Or => format!("({})", list(args, " | ")),
+            Op::Implies => format!("({})", list(args, " => ")),
+            Op::Not => format!("~{}", list(args, "")),
+        },
+        Term::Forall(bound, body) => format!("(! [{}] : {})", vars(bound), tptp(body)),
+        Term::Exists(bound, body) => format!("(? [{}] : {})", vars(bound), tptp(body)),
+    }
+}
+
+// The sorts of the parts of a tuple, as many as there are
+fn spread(sort: &Sort) -> Vec<Sort> {
+    let mut parts = Vec::new();
+    let mut rest = sort;
+    while let Sort::Data(name, args) = rest {
+        if name != "#" {
+            break;
+        }
+        parts.push(args[0].clone());
+        rest = &args[1];
+    }
+    parts.push(rest.clone());
+    parts
+}
+
+fn tuple_sort(sorts: &[Sort]) -> Sort {
+    match sorts {
+        [sort] => sort.clone(),
+        [first, rest @ ..] => Sort::Data("#".to_owned(), vec![first.clone(), tuple_sort(rest)]),
+        [] => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn export(source: &str, target: Target) -> String {
+        super::export(&parser::parse_program(source).unwrap().decls, target)
+    }
+
+    #[test]
+    fn should_say_each_equation_applies_only_when_no_earlier_one_does() {
+        let source = "dec len : list num -> num;\n--- len nil <= 0;\n--- len (x :: l) <= 1 + len l;\n\
+            dec sign : num -> num;\n--- sign n if n < 0 <= 0 - 1;\n--- sign n <= n div n;";
+        let smt = export(source, Target::Smt2);
+        assert!(smt.contains("(declare-datatypes ((list_num 0))\n  (((nil_list_num) (cons_list_num (cons_list_num_1 Int) (cons_list_num_2 list_num)))))\n"));
+        assert!(smt.contains("(declare-fun len (list_num) Int)\n"));
+        assert!(smt.contains("(assert (forall ((x Int) (l list_num)) (= (len (cons_list_num x l)) (+ 1 (len l)))))\n"));
+        assert!(smt.contains(
+            "(assert (forall ((n Int)) (=> (not (exists ((n_1 Int)) (and (= n n_1) (< n_1 0)))) (= (sign n) (hope_div n n)))))\n"
+        ));
+        let tptp = export(source, Target::Tptp);
+        assert!(tptp.contains("tff(cons_list_num_type, type, cons_list_num: ($int * list_num) > list_num).\n"));
+        assert!(tptp.contains("tff(list_num_distinct_1, axiom, (! [Vy1: $int, Vy2: list_num] : (nil_list_num != cons_list_num(Vy1, Vy2)))).\n"));
+        assert!(tptp.contains("tff(sign_1, axiom, (! [Vn: $int] : ($less(Vn, 0) => (sign(Vn) = $difference(0, 1))))).\n"));
+    }
+
+    #[test]
+    fn should_leave_out_what_the_prover_cant_say() {
+        let source = "dec twice : (num -> num) -> num -> num;\n--- twice f x <= f (f x);\n\
+            dec id : alpha -> alpha;\n--- id x <= x;\n\
+            dec one : num -> num;\n--- one n <= id 1;\n\
+            dec pairs : num # bool -> bool;\n--- pairs (n, b) <= b = (n > 0);";
+        let smt = export(source, Target::Smt2);
+        assert!(smt.ends_with(
+            "; `twice` isn't exported: it takes or gives functions\n\
+            ; `id` isn't exported: its type is polymorphic\n\
+            ; `one` isn't exported: it uses `id`, which isn't exported\n"
+        ));
+        assert!(smt.contains("(assert (forall ((n Int) (b Bool)) (= (pairs n b) (= b (> n 0)))))\n"));
+        assert!(export(source, Target::Tptp).contains("(pairs(Vn, Vb) <=> (Vb <=> $greater(Vn, 0)))"));
+    }
+}
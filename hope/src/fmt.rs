@@ -0,0 +1,253 @@
+use crate::alloc_prelude::*;
+use crate::syntax::cst::{self, CstToken, Element, Node, SyntaxKind};
+use crate::syntax::token::TokenKind;
+
+// Continuation lines of a declaration are indented this far, `where` and `whererec`
+// lines half as far, and each bracket still open at the start of a line adds a step
+const CONTINUATION: usize = 8;
+const WHERE: usize = 4;
+const BRACKET: usize = 4;
+
+// A formatted declaration, or a comment or blank line between them
+enum Item {
+    Decl {
+        lines: Vec<String>,
+        // Where the `<=` of an equation is on its first line, for aligning clauses
+        arrow: Option<usize>,
+    },
+    Comment(String),
+    Blank,
+}
+
+// The source with one space between tokens on a line, the line breaks it already had
+// and consistent indentation. Comments are kept, and the `<=` of consecutive
+// equations are lined up. Lines end the way the first line of the source does, `\r\n`
+// or `\n`. The source must lex, tokens that didn't are copied as they were
+pub fn format(source: &str) -> String {
+    let newline = match source.find('\n') {
+        Some(end) if source[..end].ends_with('\r') => "\r\n",
+        _ => "\n",
+    };
+    let program = cst::parse(source);
+    let mut items = Vec::new();
+    // Whether anything has been written on the current line at the top level
+    let mut on_line = false;
+    for child in &program.children {
+        match child {
+            Element::Token(token) if token.kind == SyntaxKind::Whitespace => {
+                let newlines = token.text.matches('\n').count();
+                if newlines >= 2 && !items.is_empty() {
+                    items.push(Item::Blank);
+                }
+                on_line &= newlines == 0;
+            }
+            Element::Token(token) => match items.last_mut() {
+                Some(Item::Decl { lines, .. }) if on_line => lines.last_mut().unwrap().push_str(&format!(" {}", token.text)),
+                _ => {
+                    items.push(Item::Comment(token.text.to_owned()));
+                    on_line = true;
+                }
+            },
+            Element::Node(decl) => {
+                let (lines, arrow) = format_decl(decl);
+                items.push(Item::Decl { lines, arrow });
+                on_line = true;
+            }
+        }
+    }
+    while matches!(items.last(), Some(Item::Blank)) {
+        items.pop();
+    }
+
+    align(&mut items);
+    let mut out = String::new();
+    for item in &items {
+        match item {
+            Item::Decl { lines, .. } => lines.iter().for_each(|line| {
+                out.push_str(line.trim_end());
+                out.push_str(newline);
+            }),
+            Item::Comment(text) => {
+                out.push_str(text.trim_end());
+                out.push_str(newline);
+            }
+            Item::Blank => out.push_str(newline),
+        }
+    }
+    out
+}
+
+fn format_decl(decl: &Node) -> (Vec<String>, Option<usize>) {
+    let mut lines = vec![String::new()];
+    let mut arrow = None;
+    let mut depth = 0usize;
+    let mut newlines = 0;
+    let mut after_comment = false;
+    let mut prev: Option<&CstToken> = None;
+    for token in decl.tokens() {
+        match token.kind {
+            SyntaxKind::Whitespace => {
+                newlines += token.text.matches('\n').count();
+                continue;
+            }
+            // A comment runs to the end of its line, so whatever follows starts a new one,
+            // and the line break after it is the comment's own rather than a blank line
+            SyntaxKind::Comment if newlines == 0 => {
+                lines.last_mut().unwrap().push_str(&format!(" {}", token.text));
+                after_comment = true;
+                continue;
+            }
+            _ => {}
+        }
+        if core::mem::take(&mut after_comment) {
+            newlines = newlines.max(1);
+        }
+
+        let kind = match token.kind {
+            SyntaxKind::Token(kind) => Some(kind),
+            _ => None,
+        };
+        let closes = matches!(kind, Some(TokenKind::RParen | TokenKind::RSquare));
+        if newlines > 0 {
+            if newlines > 1 {
+                lines.push(String::new());
+            }
+            let base = match kind {
+                Some(TokenKind::Where | TokenKind::WhereRec) => WHERE,
+                _ => CONTINUATION,
+            };
+            let open = if closes { depth.saturating_sub(1) } else { depth };
+            lines.push(" ".repeat(base + BRACKET * open));
+        } else if prev.is_some_and(|prev| spaced(prev, token)) {
+            lines.last_mut().unwrap().push(' ');
+        }
+        newlines = 0;
+
+        if kind == Some(TokenKind::LeftArrowFat) && lines.len() == 1 && depth == 0 && arrow.is_none() {
+            arrow = Some(lines[0].len());
+        }
+        lines.last_mut().unwrap().push_str(token.text);
+        match kind {
+            Some(TokenKind::LParen | TokenKind::LSquare) => depth += 1,
+            _ if closes => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        prev = Some(token);
+    }
+    (lines, arrow)
+}
+
+fn spaced(prev: &CstToken, next: &CstToken) -> bool {
+    let opens = matches!(prev.kind, SyntaxKind::Token(TokenKind::LParen | TokenKind::LSquare));
+    let lambda = prev.text == "\\";
+    let tight = matches!(
+        next.kind,
+        SyntaxKind::Token(TokenKind::RParen | TokenKind::RSquare | TokenKind::Comma | TokenKind::SemiColon)
+    );
+    !(opens || lambda || tight)
+}
+
+// Pads the `<=` of each run of equations with no blank line or comment between them
+// out to the same column
+fn align(items: &mut [Item]) {
+    let mut start = 0;
+    while start < items.len() {
+        let mut end = start;
+        while matches!(&items[end], Item::Decl { arrow: Some(_), lines } if lines[0].starts_with("---")) {
+            end += 1;
+            if end == items.len() {
+                break;
+            }
+        }
+        let column = items[start..end].iter()
+            .filter_map(|item| match item {
+                Item::Decl { arrow, .. } => *arrow,
+                _ => None,
+            })
+            .max();
+        if let Some(column) = column {
+            for item in &mut items[start..end] {
+                if let Item::Decl { lines, arrow: Some(arrow) } = item {
+                    lines[0].insert_str(*arrow, &" ".repeat(column - *arrow));
+                }
+            }
+        }
+        start = end.max(start + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "serde")]
+    use std::path::Path;
+    #[cfg(feature = "serde")]
+    use serde_json::Value as Json;
+    use super::*;
+    #[cfg(feature = "serde")]
+    use crate::{json, parser};
+
+    #[test]
+    fn should_normalize_spacing_and_align_equations() {
+        let source = "dec f:num->num;! doubles\n---   f 0<=0 ;\n--- f( n )  <= n+n\n  where m==[1 ,2];\n\n\n\nf 2;\n";
+        assert_eq!(format(source), concat!(
+            "dec f : num -> num; ! doubles\n",
+            "--- f 0   <= 0;\n",
+            "--- f (n) <= n + n\n",
+            "    where m == [1, 2];\n",
+            "\n",
+            "f 2;\n",
+        ));
+
+        let source = "--- g n if n>0 <= n;\n--- g (l&(x::_))<=0;\n";
+        assert_eq!(format(source), "--- g n if n > 0     <= n;\n--- g (l & (x :: _)) <= 0;\n");
+    }
+
+    #[test]
+    fn should_keep_comments_inside_a_decl_stable() {
+        let source = "--- f n <= n ! the same\n  + 1;\n--- g n <= n;! trailing\n";
+        let formatted = format(source);
+        assert_eq!(formatted, "--- f n <= n ! the same\n        + 1;\n--- g n <= n; ! trailing\n");
+        assert_eq!(format(&formatted), formatted);
+
+        let source = "dec f : num ! in\n\n  -> num;\n";
+        assert_eq!(format(source), "dec f : num ! in\n\n        -> num;\n");
+        assert_eq!(format(&format(source)), format(source));
+    }
+
+    #[test]
+    fn should_keep_the_line_endings_the_source_has() {
+        let source = "dec f:num->num;! doubles\r\n--- f n<=n+n\r\n  where m==1;\r\n\r\n\r\nf 2;\r\n";
+        let formatted = format(source);
+        assert_eq!(formatted, "dec f : num -> num; ! doubles\r\n--- f n <= n + n\r\n    where m == 1;\r\n\r\nf 2;\r\n");
+        assert_eq!(format(&formatted), formatted);
+        assert_eq!(format(&formatted.replace("\r\n", "\n")), formatted.replace("\r\n", "\n"));
+    }
+
+    // Compared as JSON, which needs serde
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_keep_the_prelude_parsing_the_same() {
+        let prelude = include_str!("../../lib/Standard.hop");
+        let formatted = format(prelude);
+        assert_eq!(format(&formatted), formatted);
+        assert_eq!(shape(&formatted), shape(prelude));
+    }
+
+    // The syntax tree without its positions
+    #[cfg(feature = "serde")]
+    fn shape(source: &str) -> Json {
+        fn strip(json: &mut Json) {
+            match json {
+                Json::Object(fields) => {
+                    fields.remove("pos");
+                    fields.values_mut().for_each(strip);
+                }
+                Json::Array(items) => items.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        let mut tree = json::ast_file(Path::new("p"), &parser::parse_program(source).unwrap());
+        strip(&mut tree);
+        tree
+    }
+}
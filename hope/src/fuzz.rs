@@ -0,0 +1,252 @@
+use core::fmt;
+use crate::alloc_prelude::*;
+
+// Random programs that check, for `hope fuzz-gen`. Each is a few functions, of random
+// types over numbers, truth values, lists and pairs, whose bodies are written from the
+// type they have to be, then a `write` of each function applied to some argument. A
+// function only calls those before it, and itself only on the tail of a list it was
+// given, so every program finishes. The same seed always gives the same program
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Num,
+    Bool,
+    List(Box<Ty>),
+    Pair(Box<Ty>, Box<Ty>),
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Num => write!(f, "num"),
+            Ty::Bool => write!(f, "bool"),
+            Ty::List(elem) if matches!(**elem, Ty::List(_) | Ty::Pair(..)) => write!(f, "list ({})", elem),
+            Ty::List(elem) => write!(f, "list {}", elem),
+            Ty::Pair(a, b) => write!(f, "({} # {})", a, b),
+        }
+    }
+}
+
+// SplitMix64, small and the same everywhere
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, one_in: usize) -> bool {
+        self.below(one_in) == 0
+    }
+}
+
+struct Function {
+    name: String,
+    arg: Ty,
+    result: Ty,
+}
+
+// What is in scope in a body, each written as it is used, with its type
+type Scope = Vec<(String, Ty)>;
+
+struct Generator {
+    rng: Rng,
+    functions: Vec<Function>,
+    fresh: usize,
+}
+
+// How deeply the body of a function nests
+const DEPTH: usize = 3;
+
+pub fn generate(seed: u64) -> String {
+    let mut generator = Generator { rng: Rng(seed), functions: Vec::new(), fresh: 0 };
+    let mut out = format!("! Generated by hope fuzz-gen --seed {}\n", seed);
+    for i in 0..3 + generator.rng.below(4) {
+        let function = Function { name: format!("f{}", i), arg: generator.ty(2), result: generator.ty(2) };
+        out.push_str(&format!("\ndec {} : {} -> {};\n", function.name, function.arg, function.result));
+        for (pattern, scope) in generator.equations(&function) {
+            let body = generator.expr(&function.result, DEPTH, &scope);
+            out.push_str(&format!("--- {} {} <= {};\n", function.name, pattern, body));
+        }
+        generator.functions.push(function);
+    }
+    out.push('\n');
+    let count = generator.functions.len();
+    for i in 0..count {
+        let arg = generator.functions[i].arg.clone();
+        let written = generator.expr(&arg, 2, &Vec::new());
+        let kind = if i + 1 == count { "" } else { "write " };
+        out.push_str(&format!("{}{} {};\n", kind, generator.functions[i].name, written));
+    }
+    out
+}
+
+impl Generator {
+    fn name(&mut self) -> String {
+        self.fresh += 1;
+        format!("v{}", self.fresh)
+    }
+
+    fn ty(&mut self, depth: usize) -> Ty {
+        match self.rng.below(if depth == 0 { 2 } else { 5 }) {
+            0 => Ty::Num,
+            1 => Ty::Bool,
+            2 | 3 => Ty::List(Box::new(self.ty(depth - 1))),
+            _ => Ty::Pair(Box::new(self.ty(depth - 1)), Box::new(self.ty(depth - 1))),
+        }
+    }
+
+    // The patterns of the function's equations, with what each puts in scope
+    fn equations(&mut self, function: &Function) -> Vec<(String, Scope)> {
+        match &function.arg {
+            Ty::Num if self.rng.chance(2) => {
+                let n = self.name();
+                vec![("0".to_owned(), Vec::new()), (n.clone(), vec![(n, Ty::Num)])]
+            }
+            Ty::Bool => vec![("true".to_owned(), Vec::new()), ("false".to_owned(), Vec::new())],
+            Ty::List(elem) => {
+                let (h, t) = (self.name(), self.name());
+                let recursive = format!("({} {})", function.name, t);
+                let scope = vec![(h.clone(), (**elem).clone()), (t.clone(), function.arg.clone()), (recursive, function.result.clone())];
+                vec![("[]".to_owned(), Vec::new()), (format!("({} :: {})", h, t), scope)]
+            }
+            Ty::Pair(a, b) => {
+                let (x, y) = (self.name(), self.name());
+                vec![(format!("({}, {})", x, y), vec![(x, (**a).clone()), (y, (**b).clone())])]
+            }
+            ty => {
+                let x = self.name();
+                vec![(x.clone(), vec![(x, ty.clone())])]
+            }
+        }
+    }
+
+    // Something in scope of the type, or a literal one
+    fn leaf(&mut self, ty: &Ty, scope: &Scope) -> String {
+        let vars: Vec<&String> = scope.iter().filter(|(_, t)| t == ty).map(|(e, _)| e).collect();
+        if !vars.is_empty() && !self.rng.chance(3) {
+            return vars[self.rng.below(vars.len())].clone();
+        }
+        match ty {
+            Ty::Num => self.rng.below(10).to_string(),
+            Ty::Bool if self.rng.chance(2) => "true".to_owned(),
+            Ty::Bool => "false".to_owned(),
+            Ty::List(_) if self.rng.chance(2) => "[]".to_owned(),
+            Ty::List(elem) => format!("[{}]", self.leaf(elem, scope)),
+            Ty::Pair(a, b) => format!("({}, {})", self.leaf(a, scope), self.leaf(b, scope)),
+        }
+    }
+
+    fn expr(&mut self, ty: &Ty, depth: usize, scope: &Scope) -> String {
+        if depth == 0 || self.rng.chance(4) {
+            return self.leaf(ty, scope);
+        }
+        let d = depth - 1;
+        let callable: Vec<usize> = (0..self.functions.len()).filter(|&i| self.functions[i].result == *ty).collect();
+        if !callable.is_empty() && self.rng.chance(4) {
+            let i = callable[self.rng.below(callable.len())];
+            let (name, arg) = (self.functions[i].name.clone(), self.functions[i].arg.clone());
+            return format!("({} {})", name, self.expr(&arg, d, scope));
+        }
+        if self.rng.chance(6) {
+            return format!("(if {} then {} else {})", self.expr(&Ty::Bool, d, scope), self.expr(ty, d, scope), self.expr(ty, d, scope));
+        }
+        if self.rng.chance(6) {
+            let other = self.ty(1);
+            let (x, y) = (self.name(), self.name());
+            let pair = Ty::Pair(Box::new(ty.clone()), Box::new(other));
+            let value = self.expr(&pair, d, scope);
+            let mut inner = scope.clone();
+            inner.push((x.clone(), ty.clone()));
+            return format!("(let ({}, {}) == {} in {})", x, y, value, self.expr(ty, d, &inner));
+        }
+        match ty {
+            Ty::Num => match self.rng.below(5) {
+                0 => format!("({} + {})", self.expr(ty, d, scope), self.expr(ty, d, scope)),
+                1 => format!("({} - {})", self.expr(ty, d, scope), self.expr(ty, d, scope)),
+                2 => format!("({} * {})", self.expr(ty, d, scope), self.expr(ty, d, scope)),
+                3 => {
+                    let list = Ty::List(Box::new(self.ty(1)));
+                    format!("(length {})", self.expr(&list, d, scope))
+                }
+                _ => format!("(foldr (nonop +) 0 {})", self.expr(&Ty::List(Box::new(Ty::Num)), d, scope)),
+            },
+            Ty::Bool => match self.rng.below(5) {
+                0 => format!("({} < {})", self.expr(&Ty::Num, d, scope), self.expr(&Ty::Num, d, scope)),
+                1 => {
+                    let other = self.ty(1);
+                    format!("({} = {})", self.expr(&other, d, scope), self.expr(&other, d, scope))
+                }
+                2 => format!("(not {})", self.expr(ty, d, scope)),
+                3 => format!("({} and {})", self.expr(ty, d, scope), self.expr(ty, d, scope)),
+                _ => format!("({} or {})", self.expr(ty, d, scope), self.expr(ty, d, scope)),
+            },
+            Ty::List(elem) => match self.rng.below(6) {
+                0 => format!("[{}, {}]", self.expr(elem, d, scope), self.expr(elem, d, scope)),
+                1 => format!("({} :: {})", self.expr(elem, d, scope), self.expr(ty, d, scope)),
+                2 => format!("({} <> {})", self.expr(ty, d, scope), self.expr(ty, d, scope)),
+                3 => format!("(reverse {})", self.expr(ty, d, scope)),
+                4 => {
+                    let (from, v) = (self.ty(1), self.name());
+                    let mut inner = scope.clone();
+                    inner.push((v.clone(), from.clone()));
+                    let body = self.expr(elem, d, &inner);
+                    format!("(map (lambda {} => {}) {})", v, body, self.expr(&Ty::List(Box::new(from)), d, scope))
+                }
+                _ => {
+                    let v = self.name();
+                    let mut inner = scope.clone();
+                    inner.push((v.clone(), (**elem).clone()));
+                    let keep = self.expr(&Ty::Bool, d, &inner);
+                    format!("(filter (lambda {} => {}) {})", v, keep, self.expr(ty, d, scope))
+                }
+            },
+            Ty::Pair(a, b) => format!("({}, {})", self.expr(a, d, scope), self.expr(b, d, scope)),
+        }
+    }
+}
+
+// Runs the program of the seed the ways that ought to make no difference, see
+// differential::WAYS, saying how it failed or the runs differ
+#[cfg(feature = "std")]
+pub fn differential(seed: u64) -> Result<(), String> {
+    use std::path::Path;
+    use crate::driver::Driver;
+
+    let source = generate(seed);
+    let path = Path::new("<fuzz>").join(format!("{}.hop", seed));
+    let driver = Driver::new().with_prelude(true).with_source(&path, source);
+    let paths = [path];
+    let expected = driver.run(&paths);
+    if !expected.succeeded() || !expected.diagnostics.is_empty() {
+        let messages: Vec<String> = expected.diagnostics.iter().map(|d| d.message.clone()).collect();
+        return Err(format!("seed {} gives a program that fails: {}", seed, messages.join("; ")));
+    }
+    match crate::differential::compare(&driver, &paths, None).first() {
+        Some(difference) => Err(format!("seed {} gives a program that runs differently {}", seed, difference)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_generate_the_same_programs_that_check_and_run_alike_every_way() {
+        assert_eq!(generate(7), generate(7));
+        assert_ne!(generate(7), generate(8));
+        #[cfg(feature = "std")]
+        for seed in 0..40 {
+            assert_eq!(differential(seed), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use core::fmt;
+use crate::alloc_prelude::*;
+
+// Long-form help on the language and tools. `hope help <topic>`, the REPL's `:help`
+// and the man page `hope help --man` are all written from these, so they say the same
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Topic {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub sections: &'static [Section],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Section {
+    pub heading: &'static str,
+    pub paragraphs: &'static [&'static str],
+    // Source to show as it is, one line per line
+    pub example: &'static str,
+    // Terms with what each one is, like a command with what it does
+    pub terms: &'static [(&'static str, &'static str)],
+}
+
+const fn section(heading: &'static str, paragraphs: &'static [&'static str], example: &'static str) -> Section {
+    Section { heading, paragraphs, example, terms: &[] }
+}
+
+// What the REPL takes besides declarations and expressions
+pub const REPL_COMMANDS: &[(&str, &str)] = &[
+    (":help [topic]", "List the help topics, or show one"),
+    (":quit, :q", "Leave, as `exit` and end of input do"),
+    (":paste", "Take lines until `:end` and submit them as one, so declarations can refer to each other"),
+    (":script <file>", "Submit the file's declarations and expressions as if typed"),
+    (":save <file>", "Write what has been defined to the file"),
+    (":undo [count]", "Forget the last count submissions, one if not given"),
+    (":bench <expr>[; <expr>...]", "Time each expression, comparing them if there are several"),
+    (":browse [prefix]", "List the values whose names start with the prefix, with their types"),
+    (":kind <type>", "Show how many arguments the type takes"),
+    (":fixity <operator>", "Show the operator's precedence and associativity"),
+    (":set [page on|off | lines <count>|off]", "Show or change how long results are cut or paged"),
+    (":workspace [new <name> | switch <name> | load <name> <file>]", "List, start or switch between sessions kept side by side"),
+];
+
+pub const TOPICS: &[Topic] = &[
+    Topic {
+        name: "syntax",
+        summary: "Declarations, expressions and patterns",
+        sections: &[
+            section(
+                "DECLARATIONS",
+                &[
+                    "A program is declarations and expressions, each ending in `;`. `dec` gives a function its type and \
+                     each `---` equation says what it gives for the arguments that match its patterns, trying them in order. \
+                     An expression on its own is evaluated, the last one is the program's value, and `write` prints one.",
+                    "`!` starts a comment to the end of the line.",
+                ],
+                "dec area : shape -> num;\n--- area (circle r) <= 3 * r * r;\n--- area (rect (w, h)) <= w * h;\nwrite area (rect (2, 3));",
+            ),
+            section(
+                "EXPRESSIONS",
+                &[
+                    "Application is by juxtaposition, `f x`, binding tighter than any operator. Tuples are `(a, b)`, lists \
+                     `[1, 2]` or `1 :: [2]`, and text in double quotes is a list of characters.",
+                    "`if c then a else b` chooses, `let p == e in body` and `body where p == e` name the parts of a value, \
+                     with `letrec` and `whererec` for recursive ones, and `lambda p => e | q => f` is a function of its own \
+                     equations, `\\` for short.",
+                ],
+                "write let (q, r) == (7 div 2, 7 mod 2) in q + r;\nwrite (\\0 => 1 | n => n * 2) 5;",
+            ),
+            section(
+                "OPERATORS",
+                &[
+                    "`infix` and `infixr` declare operators with a precedence, higher binding tighter, and the operator is \
+                     then declared and defined like any function of a pair. `nonop` uses an operator as an ordinary name.",
+                ],
+                "infix <+> : 5;\ndec <+> : num # num -> num;\n--- a <+> b <= a + b;\nwrite foldr (nonop <+>) 0 [1, 2, 3];",
+            ),
+            section(
+                "EXTENSIONS",
+                &[
+                    "With --list-comprehensions, `[e | x <- xs, cond]` builds a list. With --macros, `syntax` defines a form \
+                     that is expanded where it is used, so its arguments are only evaluated as the expansion does. With \
+                     --lenient-semicolons a newline can end a declaration before another one starts.",
+                ],
+                "syntax unless c a b <= if c then b else a;",
+            ),
+        ],
+    },
+    Topic {
+        name: "types",
+        summary: "Data types, synonyms, type variables and checking",
+        sections: &[
+            section(
+                "TYPES",
+                &[
+                    "The built in types are `num` for whole and fractional numbers, `bool`, `char`, `list alpha`, tuples \
+                     `alpha # beta` and functions `alpha -> beta`. Every function has a `dec` that the checker holds its \
+                     equations to, and the types of expressions are inferred.",
+                ],
+                "dec swap : alpha # beta -> beta # alpha;\n--- swap (x, y) <= (y, x);",
+            ),
+            section(
+                "DEFINING TYPES",
+                &[
+                    "`data` defines a type by its constructors, separated by `++`, each taking the types after it. `type` \
+                     names another type, and `abstype` declares one without saying what it is made of.",
+                ],
+                "data tree alpha == leaf ++ node (tree alpha # alpha # tree alpha);\ntype pairs alpha == list (alpha # alpha);",
+            ),
+            section(
+                "TYPE VARIABLES",
+                &[
+                    "`alpha`, `beta` and `gamma` stand for any type. `typevar` declares more, and a function whose type has \
+                     them works on values of every type they could be.",
+                ],
+                "typevar element;\ndec twice : (element -> element) -> element -> element;",
+            ),
+        ],
+    },
+    Topic {
+        name: "modules",
+        summary: "How files use each other",
+        sections: &[
+            section(
+                "USES",
+                &[
+                    "`uses Name;` makes what the module Name declares visible in the file. Its file is Name.hop or Name.lhop \
+                     next to the file doing the using, or in a directory given with --module-path or in HOPE_PATH, or else \
+                     the library module of that name, `Char` or `Text`. Each module is checked once, before the files that \
+                     use it, and modules can't use each other in a cycle.",
+                    "What comes after `private;` in a module is only seen inside it. When two modules a file uses both declare \
+                     a name, it has to declare the name itself to say which it means.",
+                ],
+                "uses Text;\nwrite split (chr 44) \"a,b\";",
+            ),
+            section(
+                "CLASSIC MODULES",
+                &[
+                    "In the classic dialect, `module name; ... end;` groups declarations, and only those named by its \
+                     `pubconst`, `pubfun` and `pubtype` declarations are seen outside it.",
+                ],
+                "module stack;\npubtype stack;\npubfun push, empty;\ndata stack alpha == empty ++ push (alpha # stack alpha);\nend;",
+            ),
+        ],
+    },
+    Topic {
+        name: "repl-commands",
+        summary: "What the REPL takes besides declarations and expressions",
+        sections: &[
+            Section {
+                heading: "COMMANDS",
+                paragraphs: &[
+                    "`hope repl` takes declarations and expressions, evaluating each as soon as it is complete, and these \
+                     commands.",
+                ],
+                example: "",
+                terms: REPL_COMMANDS,
+            },
+        ],
+    },
+    Topic {
+        name: "dialects",
+        summary: "The keywords each dialect has",
+        sections: &[
+            section(
+                "DIALECTS",
+                &[
+                    "Sources are read in the classic dialect unless --dialect says otherwise. The classic dialect has the \
+                     keywords of classic modules, `module`, `end`, `pubconst`, `pubfun` and `pubtype`, and `nonop`. In the \
+                     modern dialect those are ordinary names, so programs written since can use them, and modules are \
+                     only files.",
+                ],
+                "! Fine in the modern dialect, not in the classic one\ndec pubfun : num;\n--- pubfun <= 1;",
+            ),
+        ],
+    },
+];
+
+pub fn topic(name: &str) -> Option<&'static Topic> {
+    TOPICS.iter().find(|topic| topic.name == name)
+}
+
+const WIDTH: usize = 80;
+const INDENT: &str = "    ";
+
+// The words of the text over lines no wider than WIDTH with the indent
+pub(crate) fn wrap(text: &str, indent: &str, out: &mut String) {
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && indent.len() + line.len() + 1 + word.len() > WIDTH {
+            out.push_str(&format!("{}{}\n", indent, line));
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        out.push_str(&format!("{}{}\n", indent, line));
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = format!("{}: {}\n", self.name, self.summary);
+        for section in self.sections {
+            out.push_str(&format!("\n{}\n", section.heading));
+            for paragraph in section.paragraphs {
+                wrap(paragraph, INDENT, &mut out);
+                out.push('\n');
+            }
+            for line in section.example.lines() {
+                out.push_str(&format!("{}{}{}\n", INDENT, INDENT, line));
+            }
+            for (term, meaning) in section.terms {
+                out.push_str(&format!("{}{}\n", INDENT, term));
+                wrap(meaning, &INDENT.repeat(3), &mut out);
+            }
+        }
+        write!(f, "{}", out.trim_end())
+    }
+}
+
+// The list `hope help` and `:help` give
+pub fn index() -> String {
+    let width = TOPICS.iter().map(|topic| topic.name.len()).max().unwrap_or(0);
+    let mut out = String::from("Help topics, see `hope help <topic>`:\n");
+    for topic in TOPICS {
+        out.push_str(&format!("{}{:width$}  {}\n", INDENT, topic.name, topic.summary, width = width));
+    }
+    out
+}
+
+// A man page for the command, with each of its subcommands and what it does, then the
+// topics
+pub fn man(name: &str, about: &str, version: &str, commands: &[(String, String)]) -> String {
+    let mut out = format!(".TH {} 1 \"\" \"{} {}\"\n", name.to_uppercase(), name, version);
+    out.push_str(&format!(".SH NAME\n{} \\- {}\n", name, roff(about)));
+    out.push_str(&format!(".SH SYNOPSIS\n.B {}\n\\fIcommand\\fR [\\fIoptions\\fR] [\\fIfiles\\fR]\n", name));
+    out.push_str(&format!(".SH COMMANDS\nSee \\fB{} <command> \\-\\-help\\fR for the options of each.\n", name));
+    for (command, about) in commands {
+        out.push_str(&format!(".TP\n.B {}\n{}\n", command, roff(about)));
+    }
+    for topic in TOPICS {
+        out.push_str(&format!(".SH {}\n{}.\n", topic.name.to_uppercase(), roff(topic.summary)));
+        for section in topic.sections {
+            out.push_str(&format!(".SS {}\n", section.heading));
+            for paragraph in section.paragraphs {
+                out.push_str(&format!(".PP\n{}\n", roff(&paragraph.split_whitespace().collect::<Vec<_>>().join(" "))));
+            }
+            if !section.example.is_empty() {
+                out.push_str(&format!(".PP\n.nf\n.RS\n{}\n.RE\n.fi\n", roff(section.example)));
+            }
+            for (term, meaning) in section.terms {
+                out.push_str(&format!(".TP\n.B {}\n{}\n", roff(term), roff(meaning)));
+            }
+        }
+    }
+    out
+}
+
+// Text as roff reads it literally
+fn roff(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    let lines: Vec<String> = escaped.lines()
+        .map(|line| if line.starts_with(['.', '\'']) { format!("\\&{}", line) } else { line.to_owned() })
+        .collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn should_write_topics_for_the_terminal_and_for_man() {
+        let repl = topic("repl-commands").unwrap().to_string();
+        assert!(repl.starts_with("repl-commands: What the REPL takes besides declarations and expressions\n\nCOMMANDS\n"));
+        assert!(repl.contains("\n    :undo [count]\n            Forget the last count submissions, one if not given\n"), "{}", repl);
+        assert!(repl.lines().all(|line| line.len() <= WIDTH));
+        assert!(index().contains("\n    dialects       The keywords each dialect has\n"), "{}", index());
+
+        let page = man("hope", "Tools for Hope", "0.1.0", &[("run".to_owned(), "Run the files".to_owned())]);
+        assert!(page.starts_with(".TH HOPE 1 \"\" \"hope 0.1.0\"\n.SH NAME\nhope \\- Tools for Hope\n"));
+        assert!(page.contains(".TP\n.B run\nRun the files\n") && page.contains(".SS TYPE VARIABLES\n"));
+        assert!(page.contains("\\-\\-\\- area (circle r) <= 3 * r * r;"));
+    }
+
+    #[test]
+    fn should_only_give_examples_that_parse() {
+        for section in TOPICS.iter().flat_map(|topic| topic.sections).filter(|section| !section.example.is_empty()) {
+            let parsed = match section.heading {
+                "EXTENSIONS" => parser::Parser::new(section.example).unwrap().with_macros(true).parse_program(),
+                "DIALECTS" => parser::Parser::new(section.example).unwrap().with_dialect(crate::syntax::token::Dialect::Modern).parse_program(),
+                _ => parser::parse_program(section.example),
+            };
+            assert!(parsed.is_ok(), "{}: {:?}", section.heading, parsed);
+        }
+    }
+}
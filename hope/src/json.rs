@@ -0,0 +1,671 @@
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value as Json};
+use crate::build::Command;
+use crate::driver::{Diagnostic, RunOutcome, Severity, Stage, Status};
+use crate::eval::{FileCoverage, Profile, Site};
+use crate::source;
+use crate::syntax::ast::*;
+use crate::syntax::token::{Pos, Token, Whole};
+use crate::types::TypedProgram;
+
+// Bumped whenever a field of any artifact is renamed, removed or changes meaning. New
+// fields can be added without a bump. Object keys are always written in sorted order
+// and arrays in source order, so the same input gives byte-identical output
+pub const SCHEMA_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artifact {
+    Tokens,
+    Ast,
+    Diagnostics,
+    Types,
+    Run,
+    Test,
+    Profile,
+    Commands,
+}
+
+impl Artifact {
+    pub const ALL: [Artifact; 8] = [
+        Artifact::Tokens, Artifact::Ast, Artifact::Diagnostics, Artifact::Types, Artifact::Run, Artifact::Test, Artifact::Profile,
+        Artifact::Commands,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Artifact::Tokens => "tokens",
+            Artifact::Ast => "ast",
+            Artifact::Diagnostics => "diagnostics",
+            Artifact::Types => "types",
+            Artifact::Run => "run",
+            Artifact::Test => "test",
+            Artifact::Profile => "profile",
+            Artifact::Commands => "commands",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Artifact> {
+        Artifact::ALL.into_iter().find(|artifact| artifact.name() == name)
+    }
+}
+
+// Every document says what it is and which version of the schema it follows
+fn document(artifact: Artifact, key: &str, body: Json) -> Json {
+    json!({ "schema": artifact.name(), "version": SCHEMA_VERSION, key: body })
+}
+
+pub fn tokens_document(files: Vec<Json>) -> Json {
+    document(Artifact::Tokens, "files", Json::Array(files))
+}
+
+pub fn ast_document(files: Vec<Json>) -> Json {
+    document(Artifact::Ast, "files", Json::Array(files))
+}
+
+pub fn types_document(files: Vec<Json>) -> Json {
+    document(Artifact::Types, "files", Json::Array(files))
+}
+
+// What `hope run` printed and ended with. Files and diagnostics are left to the types
+// and diagnostics documents, and the time taken out so runs compare equal
+pub fn run_document(outcome: &RunOutcome) -> Json {
+    let stage = match outcome.status {
+        Status::Success => None,
+        Status::Failed(Stage::Read) => Some("read"),
+        Status::Failed(Stage::Parse) => Some("parse"),
+        Status::Failed(Stage::Check) => Some("check"),
+        Status::Failed(Stage::Eval) => Some("eval"),
+        Status::Failed(Stage::Generate) => Some("generate"),
+    };
+    let stats = &outcome.stats;
+    document(Artifact::Run, "run", json!({
+        "stdout": outcome.stdout,
+        "written": outcome.written.iter().map(|value| value.to_string()).collect::<Vec<_>>(),
+        "value": outcome.value.as_ref().map(|value| value.to_string()),
+        "failed": stage,
+        "stats": { "files": stats.files, "decls": stats.decls, "steps": stats.steps },
+    }))
+}
+
+// Each test file with why it failed, if it did, and what the tests ran of each file
+// with --coverage
+pub fn test_document(results: &[(&Path, Option<String>)], covered: &[(PathBuf, FileCoverage)]) -> Json {
+    let tests: Vec<_> = results.iter()
+        .map(|(path, failure)| json!({ "path": source::display(path), "passed": failure.is_none(), "failure": failure }))
+        .collect();
+    let coverage: Vec<_> = covered.iter()
+        .map(|(path, runs)| {
+            let functions = &runs.functions;
+            json!({
+                "path": source::display(path),
+                "equations": functions.iter().map(|f| f.equations.len()).sum::<usize>(),
+                "equations_run": functions.iter().map(|f| f.equations_run()).sum::<usize>(),
+                "branches": functions.iter().map(|f| 2 * f.branches.len()).sum::<usize>(),
+                "branches_taken": functions.iter().map(|f| f.branches_taken()).sum::<usize>(),
+            })
+        })
+        .collect();
+    let failed = results.iter().filter(|(_, failure)| failure.is_some()).count();
+    document(Artifact::Test, "results", json!({
+        "tests": tests,
+        "passed": results.len() - failed,
+        "failed": failed,
+        "coverage": coverage,
+    }))
+}
+
+// The calls at each site, as `hope run --emit-profile` writes them, the hottest first
+pub fn profile_document(profile: &Profile) -> Json {
+    let sites: Vec<_> = profile.sites().into_iter()
+        .map(|site| json!({ "function": site.function, "line": site.line, "column": site.column, "offset": site.offset, "calls": site.calls }))
+        .collect();
+    document(Artifact::Profile, "sites", Json::Array(sites))
+}
+
+// How to build each file, as `hope build` writes it to build::COMMANDS_FILE. The
+// commands are run in directory, which paths are relative to unless they are absolute
+pub fn commands_document(commands: &[Command], directory: &Path) -> Json {
+    let modules: Vec<_> = commands.iter().map(|command| json!({
+        "file": source::display(&command.path),
+        "directory": source::display(directory),
+        "uses": command.uses.iter().map(source::display).collect::<Vec<_>>(),
+        "output": source::display(&command.output),
+        "arguments": command.arguments,
+    })).collect();
+    document(Artifact::Commands, "modules", Json::Array(modules))
+}
+
+// A profile as profile_document wrote it
+pub fn profile(document: &Json) -> Result<Profile, String> {
+    if document["schema"] != Artifact::Profile.name() {
+        return Err("not a profile written by hope run --emit-profile".to_owned());
+    }
+    if document["version"] != SCHEMA_VERSION {
+        return Err(format!("a profile of schema version {}, not {}", document["version"], SCHEMA_VERSION));
+    }
+    let sites = document["sites"].as_array().ok_or("a profile without sites")?;
+    let sites = sites.iter().map(|site| {
+        let count = |key: &str| site[key].as_u64().ok_or_else(|| format!("a site without a count of `{}`", key));
+        Ok(Site {
+            function: site["function"].as_str().ok_or("a site without a function")?.to_owned(),
+            line: count("line")? as usize,
+            column: count("column")? as usize,
+            offset: count("offset")? as usize,
+            calls: count("calls")?,
+        })
+    });
+    Ok(Profile::from_sites(sites.collect::<Result<Vec<_>, String>>()?))
+}
+
+// Notes and help go in the `notes` of the error or warning they follow, only those
+// with nothing to follow are written on their own
+pub fn diagnostics_document(diagnostics: &[Diagnostic]) -> Json {
+    let mut entries: Vec<Json> = Vec::new();
+    for d in diagnostics {
+        let parent = entries.last_mut().filter(|last| last["notes"].is_array());
+        match (d.severity, parent) {
+            (Severity::Note | Severity::Help, Some(parent)) => {
+                parent["notes"].as_array_mut().expect("checked above").push(diagnostic(d));
+            }
+            _ => entries.push(diagnostic(d)),
+        }
+    }
+    document(Artifact::Diagnostics, "diagnostics", Json::Array(entries))
+}
+
+pub fn pos(pos: &Pos) -> Json {
+    json!({ "line": pos.line, "column": pos.column, "start": pos.range.start, "end": pos.range.end })
+}
+
+pub fn token(token: &Token) -> Json {
+    let value = match token {
+        Token::Identifier((name, _)) => json!(name),
+        Token::String((s, _)) => json!(s),
+        Token::Int((Whole::Int(n), _)) => json!(n),
+        Token::Int((Whole::Big(digits), _)) => json!(digits),
+        Token::Num((n, _)) => json!(n),
+        _ => Json::Null,
+    };
+    json!({ "kind": token.name(), "value": value, "pos": pos(token.pos()) })
+}
+
+// A file's tokens and the errors between them
+pub fn token_file(path: &Path, tokens: &[Json], errors: &[(String, Pos)]) -> Json {
+    let errors: Vec<_> = errors.iter().map(|(message, p)| json!({ "message": message, "pos": pos(p) })).collect();
+    json!({ "path": source::display(path), "tokens": tokens, "errors": errors })
+}
+
+pub fn diagnostic(d: &Diagnostic) -> Json {
+    let severity = match d.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+        Severity::Help => "help",
+    };
+    let mut entry = json!({
+        "severity": severity,
+        "path": d.path.as_ref().map(source::display),
+        "pos": d.pos.as_ref().map(pos),
+        "code": d.code,
+        "message": d.message,
+    });
+    if matches!(d.severity, Severity::Error | Severity::Warning) {
+        entry["notes"] = json!([]);
+    }
+    entry
+}
+
+pub fn ast_file(path: &Path, program: &Program) -> Json {
+    json!({ "path": source::display(path), "decls": program.decls.iter().map(decl).collect::<Vec<_>>() })
+}
+
+// The declared or inferred type of each declaration that has one
+pub fn types_file(path: &Path, program: &TypedProgram) -> Json {
+    let decls: Vec<_> = program.decls.iter()
+        .filter_map(|typed| {
+            let ty = typed.ty.as_ref()?;
+            let (kind, names) = match &typed.decl.kind {
+                DeclKind::Dec { names, .. } => ("dec", names.iter().map(|n| n.name.clone()).collect()),
+                DeclKind::Equation(eq) => ("equation", vec![eq.name.name.clone()]),
+                DeclKind::Write(_) => ("write", Vec::new()),
+                _ => ("expr", Vec::new()),
+            };
+            Some(json!({ "kind": kind, "names": names, "type": ty.to_string(), "pos": pos(&typed.decl.pos) }))
+        })
+        .collect();
+    json!({ "path": source::display(path), "decls": decls })
+}
+
+fn ident(ident: &Ident) -> Json {
+    json!({ "name": ident.name, "pos": pos(&ident.pos) })
+}
+
+fn idents(idents: &[Ident]) -> Json {
+    idents.iter().map(ident).collect()
+}
+
+fn head(head: &TypeHead) -> Json {
+    json!({ "name": ident(&head.name), "params": idents(&head.params), "infix": head.infix })
+}
+
+fn type_expr(ty: &TypeExpr) -> Json {
+    json!({ "name": ident(&ty.name), "args": ty.args.iter().map(type_expr).collect::<Vec<_>>(), "pos": pos(&ty.pos) })
+}
+
+// Tagged objects have a `kind` and a `pos` alongside their own fields
+fn tagged(kind: &str, at: &Pos, fields: Json) -> Json {
+    let mut object = fields;
+    object["kind"] = json!(kind);
+    object["pos"] = pos(at);
+    object
+}
+
+fn decl(decl: &Decl) -> Json {
+    let (kind, fields) = match &decl.kind {
+        DeclKind::TypeVar(names) => ("typevar", json!({ "names": idents(names) })),
+        DeclKind::Infix { assoc, ops, prec } => {
+            let assoc = match assoc {
+                Assoc::Left => "left",
+                Assoc::Right => "right",
+            };
+            ("infix", json!({ "assoc": assoc, "ops": idents(ops), "precedence": prec }))
+        }
+        DeclKind::AbsType(h) => ("abstype", json!({ "head": head(h) })),
+        DeclKind::Data { head: h, constructors } => {
+            let constructors: Vec<_> = constructors.iter()
+                .map(|c| json!({
+                    "name": ident(&c.name),
+                    "args": c.args.iter().map(type_expr).collect::<Vec<_>>(),
+                    "infix": c.infix,
+                    "pos": pos(&c.pos),
+                }))
+                .collect();
+            ("data", json!({ "head": head(h), "constructors": constructors }))
+        }
+        DeclKind::Type { head: h, body } => ("type", json!({ "head": head(h), "body": type_expr(body) })),
+        DeclKind::Dec { names, ty } => ("dec", json!({ "names": idents(names), "type": type_expr(ty) })),
+        DeclKind::Equation(eq) => ("equation", json!({
+            "name": ident(&eq.name),
+            "args": eq.args.iter().map(pattern).collect::<Vec<_>>(),
+            "guard": eq.guard.as_ref().map(expr),
+            "body": expr(&eq.body),
+            "infix": eq.infix,
+        })),
+        DeclKind::Syntax(def) => ("syntax", json!({
+            "name": ident(&def.name),
+            "params": idents(&def.params),
+            "body": expr(&def.body),
+        })),
+        DeclKind::Uses(modules) => ("uses", json!({ "modules": idents(modules) })),
+        DeclKind::Private => ("private", json!({})),
+        DeclKind::Module(name) => ("module", json!({ "name": ident(name) })),
+        DeclKind::End => ("end", json!({})),
+        DeclKind::Public(kind, names) => {
+            let kind = match kind {
+                PublicKind::Const => "const",
+                PublicKind::Fun => "fun",
+                PublicKind::Type => "type",
+            };
+            ("public", json!({ "kind": kind, "names": idents(names) }))
+        }
+        DeclKind::Write(e) => ("write", json!({ "expr": expr(e) })),
+        DeclKind::Expr(e) => ("expr", json!({ "expr": expr(e) })),
+    };
+    tagged(kind, &decl.pos, fields)
+}
+
+fn exprs(items: &[Expr]) -> Json {
+    items.iter().map(expr).collect()
+}
+
+fn expr(e: &Expr) -> Json {
+    let (kind, fields) = match &e.kind {
+        ExprKind::Var(name) => ("var", json!({ "name": name })),
+        ExprKind::Int(n) => ("int", json!({ "value": n })),
+        ExprKind::BigInt(digits) => ("bigint", json!({ "digits": digits })),
+        ExprKind::Num(n) => ("num", json!({ "value": n })),
+        ExprKind::Str(s) => ("str", json!({ "value": s })),
+        ExprKind::Tuple(items) => ("tuple", json!({ "items": exprs(items) })),
+        ExprKind::List(items) => ("list", json!({ "items": exprs(items) })),
+        ExprKind::Apply(fun, arg) => ("apply", json!({ "fun": expr(fun), "arg": expr(arg) })),
+        ExprKind::BinOp(op, l, r) => ("binop", json!({ "op": ident(op), "left": expr(l), "right": expr(r) })),
+        ExprKind::If(c, t, other) => ("if", json!({ "cond": expr(c), "then": expr(t), "else": expr(other) })),
+        ExprKind::Lambda(rules) => {
+            let rules: Vec<_> = rules.iter()
+                .map(|rule| json!({ "pattern": pattern(&rule.pattern), "body": expr(&rule.body), "pos": pos(&rule.pos) }))
+                .collect();
+            ("lambda", json!({ "rules": rules }))
+        }
+        ExprKind::Let(binding) => {
+            let kind = match binding.kind {
+                LetKind::Let => "let",
+                LetKind::LetRec => "letrec",
+                LetKind::Where => "where",
+                LetKind::WhereRec => "whererec",
+            };
+            (kind, json!({
+                "pattern": pattern(&binding.pattern),
+                "value": expr(&binding.value),
+                "body": expr(&binding.body),
+            }))
+        }
+    };
+    tagged(kind, &e.pos, fields)
+}
+
+fn patterns(items: &[Pattern]) -> Json {
+    items.iter().map(pattern).collect()
+}
+
+fn pattern(p: &Pattern) -> Json {
+    let (kind, fields) = match &p.kind {
+        PatternKind::Var(name) => ("var", json!({ "name": name })),
+        PatternKind::Wildcard => ("wildcard", json!({})),
+        PatternKind::As(name, p) => ("as", json!({ "name": ident(name), "pattern": pattern(p) })),
+        PatternKind::Int(n) => ("int", json!({ "value": n })),
+        PatternKind::BigInt(digits) => ("bigint", json!({ "digits": digits })),
+        PatternKind::Num(n) => ("num", json!({ "value": n })),
+        PatternKind::Str(s) => ("str", json!({ "value": s })),
+        PatternKind::Tuple(items) => ("tuple", json!({ "items": patterns(items) })),
+        PatternKind::List(items) => ("list", json!({ "items": patterns(items) })),
+        PatternKind::Construct(name, args) => ("construct", json!({ "name": ident(name), "args": patterns(args) })),
+        PatternKind::BinOp(op, l, r) => ("binop", json!({ "op": ident(op), "left": pattern(l), "right": pattern(r) })),
+    };
+    tagged(kind, &p.pos, fields)
+}
+
+// JSON Schema for each artifact
+
+fn object(properties: Json) -> Json {
+    let required: Vec<_> = properties.as_object().expect("properties are an object").keys().cloned().collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+fn array(items: Json) -> Json {
+    json!({ "type": "array", "items": items })
+}
+
+fn reference(name: &str) -> Json {
+    json!({ "$ref": format!("#/$defs/{}", name) })
+}
+
+// One variant of a tagged object
+fn variant(kind: &str, mut properties: Json) -> Json {
+    properties["kind"] = json!({ "const": kind });
+    properties["pos"] = reference("pos");
+    object(properties)
+}
+
+fn pos_schema() -> Json {
+    let count = json!({ "type": "integer", "minimum": 0 });
+    object(json!({ "line": count, "column": count, "start": count, "end": count }))
+}
+
+fn ident_schema() -> Json {
+    object(json!({ "name": { "type": "string" }, "pos": reference("pos") }))
+}
+
+pub fn schema(artifact: Artifact) -> Json {
+    let string = json!({ "type": "string" });
+    let boolean = json!({ "type": "boolean" });
+    let number = json!({ "type": "number" });
+    let integer = json!({ "type": "integer" });
+    let path = json!(["string", "null"]);
+    let mut defs = json!({ "pos": pos_schema() });
+
+    let body = match artifact {
+        Artifact::Tokens => {
+            let value = json!({ "type": ["string", "number", "null"] });
+            defs["token"] = object(json!({ "kind": string, "value": value, "pos": reference("pos") }));
+            defs["error"] = object(json!({ "message": string, "pos": reference("pos") }));
+            let file = object(json!({
+                "path": string,
+                "tokens": array(reference("token")),
+                "errors": array(reference("error")),
+            }));
+            ("files", array(file))
+        }
+        Artifact::Diagnostics => {
+            let severity = json!({ "enum": ["error", "warning", "note", "help"] });
+            let pos = json!({ "oneOf": [reference("pos"), { "type": "null" }] });
+            let code = json!({ "type": ["string", "null"] });
+            let fields = json!({
+                "severity": severity,
+                "path": { "type": path },
+                "pos": pos,
+                "code": code,
+                "message": string,
+            });
+            defs["note"] = object(fields.clone());
+            let mut diagnostic = object(fields);
+            diagnostic["properties"]["notes"] = array(reference("note"));
+            ("diagnostics", array(diagnostic))
+        }
+        Artifact::Run => {
+            let count = json!({ "type": "integer", "minimum": 0 });
+            let failed = json!({ "enum": ["read", "parse", "check", "eval", "generate", null] });
+            let stats = object(json!({ "files": count, "decls": count, "steps": count }));
+            let run = object(json!({
+                "stdout": string,
+                "written": array(string.clone()),
+                "value": { "type": ["string", "null"] },
+                "failed": failed,
+                "stats": stats,
+            }));
+            ("run", run)
+        }
+        Artifact::Test => {
+            let count = json!({ "type": "integer", "minimum": 0 });
+            let test = object(json!({ "path": string, "passed": boolean, "failure": { "type": ["string", "null"] } }));
+            let coverage = object(json!({
+                "path": string,
+                "equations": count,
+                "equations_run": count,
+                "branches": count,
+                "branches_taken": count,
+            }));
+            let results = object(json!({
+                "tests": array(test),
+                "passed": count,
+                "failed": count,
+                "coverage": array(coverage),
+            }));
+            ("results", results)
+        }
+        Artifact::Profile => {
+            let count = json!({ "type": "integer", "minimum": 0 });
+            let site = object(json!({ "function": string, "line": count, "column": count, "offset": count, "calls": count }));
+            ("sites", array(site))
+        }
+        Artifact::Commands => {
+            let module = object(json!({
+                "file": string,
+                "directory": string,
+                "uses": array(string.clone()),
+                "output": string,
+                "arguments": array(string.clone()),
+            }));
+            ("modules", array(module))
+        }
+        Artifact::Types => {
+            let kind = json!({ "enum": ["dec", "equation", "write", "expr"] });
+            let decl = object(json!({ "kind": kind, "names": array(string.clone()), "type": string, "pos": reference("pos") }));
+            ("files", array(object(json!({ "path": string, "decls": array(decl) }))))
+        }
+        Artifact::Ast => {
+            defs["ident"] = ident_schema();
+            defs["idents"] = array(reference("ident"));
+            defs["head"] = object(json!({ "name": reference("ident"), "params": reference("idents"), "infix": boolean }));
+            defs["type"] = object(json!({
+                "name": reference("ident"),
+                "args": array(reference("type")),
+                "pos": reference("pos"),
+            }));
+            let constructor = object(json!({
+                "name": reference("ident"),
+                "args": array(reference("type")),
+                "infix": boolean,
+                "pos": reference("pos"),
+            }));
+            defs["decl"] = json!({ "oneOf": [
+                variant("typevar", json!({ "names": reference("idents") })),
+                variant("infix", json!({ "assoc": { "enum": ["left", "right"] }, "ops": reference("idents"), "precedence": integer })),
+                variant("abstype", json!({ "head": reference("head") })),
+                variant("data", json!({ "head": reference("head"), "constructors": array(constructor) })),
+                variant("type", json!({ "head": reference("head"), "body": reference("type") })),
+                variant("dec", json!({ "names": reference("idents"), "type": reference("type") })),
+                variant("equation", json!({
+                    "name": reference("ident"),
+                    "args": array(reference("pattern")),
+                    "guard": { "oneOf": [reference("expr"), { "type": "null" }] },
+                    "body": reference("expr"),
+                    "infix": boolean,
+                })),
+                variant("syntax", json!({ "name": reference("ident"), "params": reference("idents"), "body": reference("expr") })),
+                variant("uses", json!({ "modules": reference("idents") })),
+                variant("private", json!({})),
+                variant("module", json!({ "name": reference("ident") })),
+                variant("end", json!({})),
+                variant("public", json!({ "kind": { "enum": ["const", "fun", "type"] }, "names": reference("idents") })),
+                variant("write", json!({ "expr": reference("expr") })),
+                variant("expr", json!({ "expr": reference("expr") })),
+            ]});
+
+            let rule = object(json!({ "pattern": reference("pattern"), "body": reference("expr"), "pos": reference("pos") }));
+            let mut exprs = vec![
+                variant("var", json!({ "name": string })),
+                variant("int", json!({ "value": integer })),
+                variant("bigint", json!({ "digits": string })),
+                variant("num", json!({ "value": number })),
+                variant("str", json!({ "value": string })),
+                variant("tuple", json!({ "items": array(reference("expr")) })),
+                variant("list", json!({ "items": array(reference("expr")) })),
+                variant("apply", json!({ "fun": reference("expr"), "arg": reference("expr") })),
+                variant("binop", json!({ "op": reference("ident"), "left": reference("expr"), "right": reference("expr") })),
+                variant("if", json!({ "cond": reference("expr"), "then": reference("expr"), "else": reference("expr") })),
+                variant("lambda", json!({ "rules": array(rule) })),
+            ];
+            for kind in ["let", "letrec", "where", "whererec"] {
+                exprs.push(variant(kind, json!({
+                    "pattern": reference("pattern"),
+                    "value": reference("expr"),
+                    "body": reference("expr"),
+                })));
+            }
+            defs["expr"] = json!({ "oneOf": exprs });
+            defs["pattern"] = json!({ "oneOf": [
+                variant("var", json!({ "name": string })),
+                variant("wildcard", json!({})),
+                variant("as", json!({ "name": reference("ident"), "pattern": reference("pattern") })),
+                variant("int", json!({ "value": integer })),
+                variant("bigint", json!({ "digits": string })),
+                variant("num", json!({ "value": number })),
+                variant("str", json!({ "value": string })),
+                variant("tuple", json!({ "items": array(reference("pattern")) })),
+                variant("list", json!({ "items": array(reference("pattern")) })),
+                variant("construct", json!({ "name": reference("ident"), "args": array(reference("pattern")) })),
+                variant("binop", json!({ "op": reference("ident"), "left": reference("pattern"), "right": reference("pattern") })),
+            ]});
+            ("files", array(object(json!({ "path": string, "decls": array(reference("decl")) }))))
+        }
+    };
+
+    let (key, body) = body;
+    let mut schema = object(json!({
+        "schema": { "const": artifact.name() },
+        "version": { "const": SCHEMA_VERSION },
+        key: body,
+    }));
+    schema["$schema"] = json!("https://json-schema.org/draft/2020-12/schema");
+    schema["$id"] = json!(format!("hope-{}-v{}", artifact.name(), SCHEMA_VERSION));
+    schema["$defs"] = defs;
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn should_write_tagged_syntax_trees() {
+        let program = parser::parse_program("dec f : num -> num;\n--- f n <= n + 1;").unwrap();
+        let doc = ast_document(vec![ast_file(Path::new("f.hop"), &program)]);
+        assert_eq!((&doc["schema"], &doc["version"]), (&json!("ast"), &json!(SCHEMA_VERSION)));
+
+        let equation = &doc["files"][0]["decls"][1];
+        assert_eq!(equation["kind"], "equation");
+        assert_eq!(equation["body"]["kind"], "binop");
+        assert_eq!(equation["body"]["op"]["name"], "+");
+        assert_eq!(equation["body"]["right"], json!({
+            "kind": "int",
+            "value": 1,
+            "pos": { "line": 2, "column": 16, "start": 35, "end": 36 },
+        }));
+
+        // Keys are sorted, so the text is the same from run to run
+        let text = doc.to_string();
+        assert!(text.starts_with(r#"{"files":[{"decls":[{"kind":"dec","names":"#));
+    }
+
+    #[test]
+    fn should_give_every_artifact_a_schema() {
+        for artifact in Artifact::ALL {
+            let schema = schema(artifact);
+            assert_eq!(Artifact::from_name(artifact.name()), Some(artifact));
+            assert_eq!(schema["properties"]["schema"]["const"], artifact.name());
+            assert!(schema["required"].as_array().unwrap().contains(&json!("version")));
+        }
+        assert_eq!(Artifact::from_name("nope"), None);
+    }
+
+    #[test]
+    fn should_write_what_a_run_printed_and_how_tests_went() {
+        let path = Path::new("<json>/run.hop");
+        let outcome = crate::driver::Driver::new()
+            .with_source(path, "write 1;\n[2, 3];".to_owned())
+            .run(&[path.to_path_buf()]);
+        let doc = run_document(&outcome);
+        assert_eq!(doc["run"]["written"], json!(["1"]));
+        assert_eq!(doc["run"]["value"], "[2, 3]");
+        assert_eq!(doc["run"]["failed"], Json::Null);
+
+        let failure = Some("line 1 differs".to_owned());
+        let doc = test_document(&[(path, None), (Path::new("b.hop"), failure)], &[]);
+        assert_eq!((&doc["schema"], &doc["results"]["passed"], &doc["results"]["failed"]), (&json!("test"), &json!(1), &json!(1)));
+        assert_eq!(doc["results"]["tests"][1]["failure"], "line 1 differs");
+    }
+
+    #[test]
+    fn should_read_back_the_profiles_it_writes() {
+        let path = Path::new("<json>/profile.hop");
+        let outcome = crate::driver::Driver::new()
+            .with_profiling(true)
+            .with_source(path, "dec f : num -> num;\n--- f n <= n + 1;\nf (f 1);".to_owned())
+            .run(&[path.to_path_buf()]);
+        let written = outcome.profile.expect("a profile");
+        let doc = profile_document(&written);
+        assert_eq!(doc["sites"].as_array().map(Vec::len), Some(2));
+        assert_eq!(profile(&doc), Ok(written));
+        assert!(profile(&json!({ "schema": "run" })).is_err());
+    }
+
+    #[test]
+    fn should_nest_notes_under_the_diagnostic_they_follow() {
+        let entry = |severity, message: &str| Diagnostic { severity, path: None, pos: None, code: None, message: message.to_owned() };
+        let doc = diagnostics_document(&[
+            entry(Severity::Note, "on its own"),
+            entry(Severity::Error, "assertion failed"),
+            entry(Severity::Note, "expected 1"),
+            entry(Severity::Help, "try 2"),
+            entry(Severity::Warning, "unused"),
+        ]);
+        let messages = |entries: &Json| entries.as_array().unwrap().iter().map(|e| e["message"].clone()).collect::<Vec<_>>();
+        let diagnostics = &doc["diagnostics"];
+        assert_eq!(messages(diagnostics), ["on its own", "assertion failed", "unused"]);
+        assert_eq!(diagnostics[0].get("notes"), None);
+        assert_eq!(messages(&diagnostics[1]["notes"]), ["expected 1", "try 2"]);
+        assert_eq!(diagnostics[1]["notes"][1]["severity"], "help");
+        assert_eq!(diagnostics[2]["notes"], json!([]));
+    }
+}
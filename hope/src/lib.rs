@@ -1 +1,105 @@
-pub mod syntax;
\ No newline at end of file
+#![forbid(unsafe_code)]
+// Tests always have std, whatever the features
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+mod alloc_prelude;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "cli")]
+pub mod completions;
+#[cfg(feature = "std")]
+pub mod backend;
+#[cfg(feature = "std")]
+pub mod build;
+pub mod cancel;
+pub mod cost;
+pub mod desugar;
+#[cfg(feature = "std")]
+pub mod diagnostics;
+#[cfg(feature = "std")]
+pub mod differential;
+#[cfg(feature = "std")]
+pub mod driver;
+#[cfg(feature = "std")]
+pub mod eval;
+#[cfg(feature = "std")]
+pub mod examples;
+pub mod export;
+pub mod fmt;
+pub mod fuzz;
+pub mod help;
+#[cfg(feature = "serde")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod library;
+#[cfg(feature = "std")]
+pub mod modules;
+pub mod mutate;
+#[cfg(feature = "std")]
+pub mod output;
+pub mod parser;
+pub mod pp;
+pub mod prelude;
+pub mod printf;
+pub mod reduce;
+#[cfg(feature = "std")]
+pub mod repl;
+#[cfg(feature = "std")]
+pub mod sandbox;
+#[cfg(feature = "serde")]
+pub mod serve;
+#[cfg(feature = "std")]
+pub mod source;
+pub mod syntax;
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod tutor;
+pub mod types;
+
+// What each feature brings in, see Cargo.toml. Lexing, parsing and checking need none
+// of them, not even std
+#[cfg(test)]
+mod tests {
+    use crate::{parser, prelude, types};
+
+    #[test]
+    fn should_check_programs_without_any_feature() {
+        let mut program = prelude::program();
+        program.decls.extend(parser::parse_program("dec xs : list num;\n--- xs <= map (lambda x => x * x) [1, 2, 3];\nlength xs;").unwrap().decls);
+        let typed = types::check_program(program).unwrap();
+        assert_eq!(typed.decls.last().unwrap().ty.as_ref().unwrap().to_string(), "num");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn should_run_programs_with_std() {
+        let mut session = crate::repl::Session::new().with_prelude();
+        let outputs = session.submit("dec xs : list num;\n--- xs <= map (lambda x => x * x) [1, 2, 3];\nlength xs;").unwrap();
+        assert_eq!(outputs[0].to_string(), "3 : num");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_write_json_with_serde() {
+        let program = crate::parser::parse_program("dec x : num;").unwrap();
+        let ast = crate::json::ast_file(std::path::Path::new("x.hop"), &program);
+        assert!(ast.is_object());
+    }
+
+    #[cfg(feature = "repl")]
+    #[test]
+    fn should_have_the_prompt_with_repl() {
+        // Reading from a terminal can't be tested here, it only has to be there
+        let _run: fn(&[std::path::PathBuf], bool, crate::modules::Loader, crate::repl::Paging) -> rustyline::Result<()> = crate::repl::run;
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn should_have_everything_with_cli() {
+        const { assert!(cfg!(feature = "repl") && cfg!(feature = "serde")) };
+        let found = crate::source::discover(&["../lib/*.hop"]).unwrap();
+        assert_eq!(found, ["../lib/Char.hop", "../lib/Standard.hop", "../lib/Text.hop"].map(std::path::PathBuf::from));
+    }
+}
@@ -0,0 +1,23 @@
+use std::path::{Path, PathBuf};
+
+// The modules of lib/ besides the prelude, built in so that `uses Char;` finds one
+// wherever the program is, when no directory searched has a file for it
+pub const MODULES: &[(&str, &str)] = &[
+    ("Char", include_str!("../../lib/Char.hop")),
+    ("Text", include_str!("../../lib/Text.hop")),
+];
+
+// Where they seem to be, as the prelude seems to be Standard.hop. No directory can
+// have this name on every system, so nothing on disk is read for them
+pub const DIR: &str = "<library>";
+
+// Where the module seems to be, if it is built in
+pub fn path(name: &str) -> Option<PathBuf> {
+    MODULES.iter().any(|(module, _)| *module == name).then(|| Path::new(DIR).join(format!("{}.hop", name)))
+}
+
+// The contents of a built in module, from where it seems to be
+pub fn source(path: &Path) -> Option<&'static str> {
+    let name = path.strip_prefix(DIR).ok()?.to_str()?.strip_suffix(".hop")?;
+    MODULES.iter().find(|(module, _)| *module == name).map(|(_, source)| *source)
+}
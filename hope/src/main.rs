@@ -1,18 +1,1433 @@
-use std::fs;
+use std::io::IsTerminal;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use clap::builder::ValueHint;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use logos::Logos;
-use hope::syntax::token::Token;
+use hope::backend::Backend;
+use hope::diagnostics::Renderer;
+use hope::driver::{Diagnostic, Driver, RunOutcome, Severity};
+use hope::eval::{FileCoverage, Interpreter, Limits, Profile, Replay, Trace};
+use hope::json::{self, Artifact};
+use hope::modules::Loader;
+use hope::{backend, completions, examples, export, fmt, fuzz, library, mutate, output, prelude, repl, sandbox, serve, source, trace, tutor};
+use hope::syntax::ast::{DeclKind, Program};
+use hope::syntax::stats::CorpusStats;
+use hope::syntax::token::{self, Extras, IdentifierPolicy, Token};
 
-fn main() {
-    let file_path = "./lib/Standard.hop";
-    let contents = fs::read_to_string(file_path)
-        .expect("Should be able to read file");
+// Past this many errors in one file the rest are counted but not printed, a binary
+// or badly broken file would otherwise report an error for nearly every byte
+const MAX_REPORTED_ERRORS: usize = 20;
 
-    let lex = Token::lexer_with_extras(&contents, 1);
+#[derive(Parser)]
+#[command(name = "hope", version, about = "Tools for the Hope programming language")]
+#[command(arg_required_else_help = true, disable_help_subcommand = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Log the work of each phase to stderr, as `debug` or `hope::types=debug,hope::eval=trace`
+    #[arg(long, global = true, value_name = "FILTER")]
+    log: Option<trace::Filter>,
+    /// Log one JSON object a line, of debug messages unless --log says otherwise
+    #[arg(long, global = true)]
+    log_json: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the tokens of each file
+    #[command(alias = "tokens")]
+    Lex {
+        /// Print corpus statistics instead of tokens
+        #[arg(long)]
+        stats: bool,
+        /// Only accept operators made of the standard operator characters
+        #[arg(long)]
+        strict: bool,
+        #[arg(long, value_enum, default_value_t = Dialect::Classic)]
+        dialect: Dialect,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+        /// Files, directories or glob patterns
+        #[arg(default_value = ".")]
+        paths: Vec<String>,
+    },
+    /// Print the syntax tree of each file
+    Parse(Files),
+    /// Type check the files, in order, as one program
+    Check {
+        /// The same as --emit match
+        #[arg(long)]
+        dump_match: bool,
+        /// The same as --emit resolved
+        #[arg(long)]
+        dump_resolved: bool,
+        /// Write these artifacts of each file, to `<name>.tokens.json` and so on
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "ARTIFACTS")]
+        emit: Vec<Emit>,
+        /// Where --emit writes, instead of next to each file
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        emit_dir: Option<PathBuf>,
+        /// Check each file as a program of its own rather than all as one, this many at
+        /// once. Diagnostics still come in the order of the files
+        #[arg(long, short, value_name = "N")]
+        jobs: Option<NonZeroUsize>,
+        #[command(flatten)]
+        files: Files,
+    },
+    /// Check the files with every module they use and write the core of each to a
+    /// directory, with the command that builds each one in hope-commands.json there
+    Build {
+        /// Where to write, made if it isn't there
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath, default_value = "build")]
+        out_dir: PathBuf,
+        /// Also write a build file there that runs the command of each file after those
+        /// of the modules it uses
+        #[arg(long, value_enum, value_name = "KIND")]
+        emit_build_graph: Option<BuildGraph>,
+        #[command(flatten)]
+        files: Files,
+    },
+    /// Run each file as a program of its own, reporting those that fail, as when an
+    /// `assert_eq` doesn't hold
+    Test {
+        /// Compare what each file writes with `<file>.out` too, failing those that differ
+        #[arg(long)]
+        snap: bool,
+        /// Write what each file writes to `<file>.out`, instead of comparing with it
+        #[arg(long)]
+        update_snapshots: bool,
+        /// Report which equations and branches of `if` the tests never ran
+        #[arg(long)]
+        coverage: bool,
+        /// Write the coverage to this file as an LCOV tracefile
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        lcov: Option<PathBuf>,
+        /// Run each file without sharing, without the native prelude and optimized too,
+        /// failing those that write, give or fail with anything different
+        #[arg(long)]
+        differential: bool,
+        /// Fail a differential run that takes more than this many times the steps
+        #[arg(long, value_name = "FACTOR", requires = "differential")]
+        step_bound: Option<u64>,
+        #[command(flatten)]
+        files: Files,
+    },
+    /// Make small changes to the files and run the tests against each, reporting the
+    /// changes no test fails on
+    Mutate {
+        /// The tests, each run as a program of its own as by `hope test`
+        #[arg(long = "test", value_name = "PATH", value_hint = ValueHint::AnyPath, required = true)]
+        tests: Vec<String>,
+        #[command(flatten)]
+        files: Files,
+    },
+    /// Cut a file down to as little as still passes a check, printing what is left
+    Reduce {
+        /// A shell command that succeeds while the file shows what is being reduced to,
+        /// given the path of each try as $1
+        #[arg(long, value_name = "COMMAND")]
+        check: String,
+        #[command(flatten)]
+        language: Language,
+        /// Parse without the standard prelude's operators
+        #[arg(long)]
+        no_prelude: bool,
+        file: PathBuf,
+    },
+    /// Check and evaluate the files, in order, as one program
+    Run {
+        /// Print the value of this definition instead of the last expression
+        #[arg(long)]
+        entry: Option<String>,
+        /// Evaluate definitions without arguments at each use, instead of once
+        #[arg(long)]
+        no_share: bool,
+        /// Evaluate the prelude's length, <>, map and reverse by their equations, instead
+        /// of natively
+        #[arg(long)]
+        no_native_prelude: bool,
+        /// How many results each function made with `memo` keeps
+        #[arg(long, value_name = "N")]
+        memo_capacity: Option<usize>,
+        /// Cut what the program prints down to this many terminal lines
+        #[arg(long, value_name = "N")]
+        max_output_lines: Option<usize>,
+        /// Run in this sandbox: pure, with no `write` and only library modules, io, with
+        /// modules next to the files, or full. Each has its own limits
+        #[arg(long, value_name = "PROFILE", value_parser = sandbox::SANDBOXES.map(|sandbox| sandbox.name))]
+        sandbox: Option<String>,
+        /// Count the calls made at each site, printing the most made after the run
+        #[arg(long)]
+        profile: bool,
+        /// Write the calls made at each site to this file, see `hope schema profile`
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        emit_profile: Option<PathBuf>,
+        /// Inline calls of small functions, and pass functions all their arguments at once
+        #[arg(short = 'O', long)]
+        optimize: bool,
+        /// Inline the calls this profile saw most first, and those it never saw not at all
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, requires = "optimize")]
+        use_profile: Option<PathBuf>,
+        /// Write every call and what it returned to this file, to step through with `hope replay`
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        record: Option<PathBuf>,
+        /// Print what the heap holds at this step, or at the end, by constructor and the
+        /// definition holding it
+        #[arg(long, value_name = "STEP", value_parser = step_or_end)]
+        heap_snapshot_at: Option<u64>,
+        /// Write the heap snapshot to this file as a Graphviz graph
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, requires = "heap_snapshot_at")]
+        emit_heap_graph: Option<PathBuf>,
+        #[command(flatten)]
+        files: Files,
+    },
+    /// Step forwards and backwards through the calls of a run written by `hope run --record`,
+    /// reading commands from stdin
+    Replay {
+        #[arg(value_hint = ValueHint::FilePath)]
+        trace: PathBuf,
+    },
+    /// Check the files and estimate how the running time of functions grows
+    Analyze {
+        /// Estimate the cost of this function from the shape of its recursion
+        #[arg(long, value_name = "FN", required = true)]
+        cost: Vec<String>,
+        #[command(flatten)]
+        files: Files,
+    },
+    /// Check the files and write out their first-order functions and data types for a
+    /// prover
+    Export {
+        /// Which prover's language to write
+        #[arg(long = "format", value_enum, default_value_t = Prover::Smt2)]
+        prover: Prover,
+        #[command(flatten)]
+        language: Language,
+        /// Look for the modules `uses` names in this directory too, before those in HOPE_PATH
+        #[arg(long = "module-path", value_name = "DIR", value_hint = ValueHint::DirPath)]
+        module_path: Vec<PathBuf>,
+        /// Don't load the standard prelude first
+        #[arg(long)]
+        no_prelude: bool,
+        /// Files, directories or glob patterns
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Rewrite files in the standard layout
+    Fmt {
+        /// Only report the files that aren't formatted, exiting with failure if any
+        #[arg(long)]
+        check: bool,
+        /// Format what is read from stdin to stdout instead of files, writing it out as
+        /// it was if it doesn't parse
+        #[arg(long, conflicts_with = "paths")]
+        stdin: bool,
+        /// The file stdin has the contents of, for diagnostics
+        #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, requires = "stdin", default_value = "<stdin>")]
+        stdin_filename: PathBuf,
+        /// How many files to format at once, by default as many as there are processors
+        #[arg(long, short, value_name = "N")]
+        jobs: Option<NonZeroUsize>,
+        #[command(flatten)]
+        language: Language,
+        /// Files, directories or glob patterns
+        #[arg(default_value = ".")]
+        paths: Vec<String>,
+    },
+    /// Start an interactive session, after loading any files given
+    Repl {
+        /// Don't load the standard prelude first
+        #[arg(long)]
+        no_prelude: bool,
+        /// Cut results down to this many terminal lines, 0 for no limit
+        #[arg(long, value_name = "N", default_value_t = repl::DEFAULT_MAX_LINES)]
+        max_output_lines: usize,
+        /// Show results that are too long through $PAGER instead of cutting them
+        #[arg(long)]
+        page: bool,
+        /// Look for the modules `uses` names in this directory too, before those in HOPE_PATH
+        #[arg(long = "module-path", value_name = "DIR", value_hint = ValueHint::DirPath)]
+        module_path: Vec<PathBuf>,
+        paths: Vec<String>,
+    },
+    /// Work through exercises at a prompt, each definition checked by tests, carrying
+    /// on from the last one done
+    Tutor {
+        /// Where to keep which exercises are done, instead of ~/.hope_tutor
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        progress: Option<PathBuf>,
+    },
+    /// Answer JSON-RPC requests to run programs, one per line on stdin
+    Serve {
+        /// The sandbox profile for requests that don't name one
+        #[arg(long, default_value = "playground", value_parser = serve::PROFILES.map(|profile| profile.name()))]
+        profile: String,
+        /// Look for the modules `uses` names in this directory too, before those in
+        /// HOPE_PATH, for profiles that can read files
+        #[arg(long = "module-path", value_name = "DIR", value_hint = ValueHint::DirPath)]
+        module_path: Vec<PathBuf>,
+    },
+    /// Print a random program that checks, the same one for the same seed
+    FuzzGen {
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Instead of printing them, run this many programs from the seed on, each every
+        /// way that ought to give the same result, reporting those that don't
+        #[arg(long, value_name = "N")]
+        differential: Option<u64>,
+    },
+    /// Print the JSON Schema of an artifact written with --format=json
+    Schema {
+        #[arg(value_parser = Artifact::ALL.map(Artifact::name))]
+        artifact: String,
+    },
+    /// Print a script that completes hope's command line in the shell
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// List the example programs that come with hope, or show or run one
+    Examples {
+        #[command(subcommand)]
+        action: Option<Examples>,
+    },
+    /// Explain a part of the language (syntax, types, modules, repl-commands or
+    /// dialects) or a command, listing them if none is given
+    Help {
+        #[arg(value_hint = ValueHint::Other)]
+        topic: Option<String>,
+        /// Print all of it as a man page instead
+        #[arg(long, conflicts_with = "topic")]
+        man: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum Examples {
+    /// List them with what each does
+    List,
+    /// Print the source of one
+    Show {
+        #[arg(value_parser = examples::EXAMPLES.map(|example| example.name))]
+        name: String,
+    },
+    /// Run one as `hope run` would its file
+    Run {
+        #[arg(value_parser = examples::EXAMPLES.map(|example| example.name))]
+        name: String,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Emit {
+    Tokens,
+    Ast,
+    Types,
+    /// The decision tree each function's equations compile to
+    Match,
+    /// The program taken down to lambdas, constructors and case
+    Core,
+    /// Each equation with its variables numbered by the frame and slot they are in
+    Resolved,
+}
+
+impl Emit {
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::Tokens => "tokens.json",
+            Emit::Ast => "ast.json",
+            Emit::Types => "types.json",
+            Emit::Match => "match.txt",
+            Emit::Core => "core",
+            Emit::Resolved => "resolved.txt",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Text,
+    /// One versioned JSON document, see `hope schema`
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Prover {
+    /// SMT-LIB 2, for Z3, cvc5 and the like
+    Smt2,
+    /// TPTP's typed first-order form, for Vampire, E and the like
+    Tptp,
+}
+
+impl From<Prover> for export::Target {
+    fn from(prover: Prover) -> Self {
+        match prover {
+            Prover::Smt2 => export::Target::Smt2,
+            Prover::Tptp => export::Target::Tptp,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl From<Shell> for completions::Shell {
+    fn from(shell: Shell) -> Self {
+        match shell {
+            Shell::Bash => completions::Shell::Bash,
+            Shell::Zsh => completions::Shell::Zsh,
+            Shell::Fish => completions::Shell::Fish,
+            Shell::Powershell => completions::Shell::PowerShell,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Dialect {
+    /// With `module ... end`, the `pub` declarations and `nonop`
+    Classic,
+    /// Where those keywords are ordinary names
+    Modern,
+}
+
+impl From<Dialect> for token::Dialect {
+    fn from(dialect: Dialect) -> Self {
+        match dialect {
+            Dialect::Classic => token::Dialect::Classic,
+            Dialect::Modern => token::Dialect::Modern,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Frontend {
+    Hope,
+    /// A subset of Miranda scripts, run with the Hope prelude
+    MirandaLite,
+}
+
+impl From<Frontend> for hope::driver::Frontend {
+    fn from(frontend: Frontend) -> Self {
+        match frontend {
+            Frontend::Hope => hope::driver::Frontend::Hope,
+            Frontend::MirandaLite => hope::driver::Frontend::MirandaLite,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BuildGraph {
+    /// build.ninja, for ninja -f
+    Ninja,
+    /// A Makefile, for make -f
+    Make,
+}
+
+impl From<BuildGraph> for hope::build::Graph {
+    fn from(graph: BuildGraph) -> Self {
+        match graph {
+            BuildGraph::Ninja => hope::build::Graph::Ninja,
+            BuildGraph::Make => hope::build::Graph::Make,
+        }
+    }
+}
+
+// What a file has to be parsed with, shared by everything that reads programs
+#[derive(Args)]
+struct Language {
+    /// Let a newline end a declaration that is followed by a declaration keyword
+    #[arg(long)]
+    lenient_semicolons: bool,
+    /// Allow list comprehensions, `[e | x <- xs, cond]`
+    #[arg(long)]
+    list_comprehensions: bool,
+    /// Allow `syntax` definitions, `syntax unless c a b <= if c then b else a`
+    #[arg(long)]
+    macros: bool,
+    /// Which keywords the language has
+    #[arg(long, value_enum, default_value_t = Dialect::Classic)]
+    dialect: Dialect,
+    /// What the files are written in
+    #[arg(long = "from", value_enum, default_value_t = Frontend::Hope)]
+    from: Frontend,
+}
+
+impl Language {
+    // The flags that give this language again
+    fn flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        for (set, flag) in [(self.lenient_semicolons, "--lenient-semicolons"), (self.list_comprehensions, "--list-comprehensions"), (self.macros, "--macros")] {
+            if set {
+                flags.push(flag.to_owned());
+            }
+        }
+        let named = |value: Option<clap::builder::PossibleValue>| value.expect("no value is skipped").get_name().to_owned();
+        if self.dialect != Dialect::Classic {
+            flags.push(format!("--dialect={}", named(self.dialect.to_possible_value())));
+        }
+        if self.from != Frontend::Hope {
+            flags.push(format!("--from={}", named(self.from.to_possible_value())));
+        }
+        flags
+    }
+
+    fn driver(&self) -> Driver {
+        Driver::new()
+            .with_lenient_semicolons(self.lenient_semicolons)
+            .with_comprehensions(self.list_comprehensions)
+            .with_macros(self.macros)
+            .with_dialect(self.dialect.into())
+            .with_frontend(self.from.into())
+    }
+}
+
+#[derive(Args)]
+struct Files {
+    #[command(flatten)]
+    language: Language,
+    /// Fail on arithmetic or comparison between a whole and a fractional number
+    #[arg(long)]
+    strict_numerics: bool,
+    /// Look for the modules `uses` names in this directory too, before those in HOPE_PATH
+    #[arg(long = "module-path", value_name = "DIR", value_hint = ValueHint::DirPath)]
+    module_path: Vec<PathBuf>,
+    /// Don't load the standard prelude first
+    #[arg(long)]
+    no_prelude: bool,
+    /// How to print results and diagnostics
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    /// How to print diagnostics, if not the same way as results
+    #[arg(long, value_enum)]
+    error_format: Option<Format>,
+    /// Files, directories or glob patterns
+    #[arg(required = true)]
+    paths: Vec<String>,
+}
+
+// Returns the number of lexing errors in the file
+fn print_tokens(file_path: &Path, extras: Extras) -> usize {
+    let contents = match source::read(file_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("{}: {}", source::display(file_path), source::describe(&e));
+            return 1;
+        }
+    };
+
+    let mut errors = 0;
+    let mut renderer = renderer();
+    let mut lex = Token::lexer_with_extras(&contents, extras);
 
-    for tok in lex {
+    while let Some(tok) = lex.next() {
         match tok {
-            Ok(token) => println!("{:?}", token),
-            Err(e) => eprintln!("{:#?}", e)
+            Ok(token) => println!("{:?}", token.in_dialect(lex.extras.dialect)),
+            Err(e) => {
+                errors += 1;
+                if errors <= MAX_REPORTED_ERRORS {
+                    let diagnostic = Diagnostic {
+                        severity: Severity::Error,
+                        path: Some(file_path.to_path_buf()),
+                        pos: Some(lex.extras.pos(lex.span())),
+                        code: Some(e.code()),
+                        message: e.to_string(),
+                    };
+                    eprint!("{}", renderer.render(&diagnostic));
+                }
+            }
+        }
+    }
+
+    if errors > MAX_REPORTED_ERRORS {
+        eprintln!("{}: {} further errors not shown", source::display(file_path), errors - MAX_REPORTED_ERRORS);
+    }
+    errors
+}
+
+// The file's tokens as JSON, with the number of lexing errors in it
+fn tokens_json(file_path: &Path, extras: Extras) -> (serde_json::Value, usize) {
+    let contents = match source::read(file_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("{}: {}", source::display(file_path), source::describe(&e));
+            return (json::token_file(file_path, &[], &[]), 1);
+        }
+    };
+
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut lex = Token::lexer_with_extras(&contents, extras);
+    while let Some(tok) = lex.next() {
+        match tok {
+            Ok(token) => tokens.push(json::token(&token.in_dialect(lex.extras.dialect))),
+            Err(e) => errors.push((e.to_string(), lex.extras.pos(lex.span()))),
+        }
+    }
+    let count = errors.len();
+    (json::token_file(file_path, &tokens, &errors), count)
+}
+
+fn print_stats(files: &[PathBuf], policy: IdentifierPolicy) -> ExitCode {
+    let mut stats = CorpusStats { policy, ..CorpusStats::default() };
+    // Only the lexing is timed, reading the files would otherwise count against it
+    for file in files {
+        match source::read(file) {
+            Ok(contents) => {
+                let start = Instant::now();
+                stats.add_source(file, &contents);
+                stats.elapsed += start.elapsed();
+            }
+            Err(e) => eprintln!("{}: {}", source::display(file), source::describe(&e))
+        }
+    }
+
+    println!("{}", stats);
+    ExitCode::SUCCESS
+}
+
+fn lex(paths: &[String], stats: bool, strict: bool, dialect: Dialect, format: Format) -> ExitCode {
+    let policy = if strict { IdentifierPolicy::Strict } else { IdentifierPolicy::Permissive };
+    let extras = Extras::with_policy(policy).with_dialect(dialect.into());
+    let Some(files) = discover(paths) else { return ExitCode::FAILURE };
+    if stats {
+        return print_stats(&files, policy);
+    }
+
+    let mut failed = 0;
+    let mut errors = 0;
+    let mut documents = Vec::new();
+    for file in &files {
+        let count = match format {
+            Format::Text => print_tokens(file, extras.clone()),
+            Format::Json => {
+                let (document, count) = tokens_json(file, extras.clone());
+                documents.push(document);
+                count
+            }
+        };
+        errors += count;
+        failed += usize::from(count > 0);
+    }
+    if format == Format::Json {
+        println!("{}", json::tokens_document(documents));
+    }
+
+    if errors > 0 {
+        eprintln!("{} errors in {} of {} files", errors, failed, files.len());
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn discover(paths: &[String]) -> Option<Vec<PathBuf>> {
+    match source::discover(paths) {
+        Ok(files) => Some(files),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+impl Files {
+    fn error_format(&self) -> Format {
+        self.error_format.unwrap_or(self.format)
+    }
+
+    // The flags the files are read and checked with
+    fn flags(&self) -> Vec<String> {
+        let mut flags = self.language.flags();
+        if self.strict_numerics {
+            flags.push("--strict-numerics".to_owned());
+        }
+        flags.extend(self.module_path.iter().map(|dir| format!("--module-path={}", dir.display())));
+        if self.no_prelude {
+            flags.push("--no-prelude".to_owned());
+        }
+        flags
+    }
+}
+
+fn loader(files: &Files) -> Loader {
+    Loader::new().with_search_path(files.module_path.clone()).with_env()
+}
+
+fn driver(files: &Files) -> Driver {
+    files.language.driver()
+        .with_strict_numerics(files.strict_numerics)
+        .with_modules(loader(files))
+        .with_prelude(!files.no_prelude)
+}
+
+fn parse(files: &Files) -> ExitCode {
+    let Some(paths) = discover(&files.paths) else { return ExitCode::FAILURE };
+
+    let driver = driver(files);
+    let mut failed = 0;
+    let mut diagnostics = Vec::new();
+    let mut documents = Vec::new();
+    let mut renderer = renderer();
+    for path in &paths {
+        let parsed = driver.parse_file(path, &mut diagnostics);
+        if files.error_format() == Format::Text {
+            eprint!("{}", renderer.render_all(&diagnostics));
+            diagnostics.clear();
+        }
+        match parsed {
+            Ok(program) if files.format == Format::Json => documents.push(json::ast_file(path, &program)),
+            Ok(program) => println!("{:#?}", program),
+            Err(_) => failed += 1,
+        }
+    }
+    if files.format == Format::Json {
+        println!("{}", json::ast_document(documents));
+    }
+    if files.error_format() == Format::Json {
+        print_diagnostics(&diagnostics, Format::Json);
+    }
+
+    if failed > 0 {
+        eprintln!("{} of {} files failed to parse", failed, paths.len());
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+// Colored unless stderr isn't a terminal or NO_COLOR is set. A Windows console only
+// understands the escape codes once it is asked to, and one that can't be stays plain
+fn renderer() -> Renderer {
+    let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    Renderer::new().with_color(color && anstyle_query::windows::enable_ansi_colors().unwrap_or(true))
+}
+
+// Diagnostics go to stderr either way, as one document when they are JSON
+fn print_diagnostics(diagnostics: &[Diagnostic], format: Format) {
+    match format {
+        Format::Text => {
+            eprint!("{}", renderer().render_all(diagnostics));
+        }
+        Format::Json if diagnostics.is_empty() => {}
+        Format::Json => eprintln!("{}", json::diagnostics_document(diagnostics)),
+    }
+}
+
+// Editors format on save through this, so the contents always come back out, as they
+// were if they can't be formatted
+fn format_stdin(path: &Path, check: bool, language: &Language) -> ExitCode {
+    let mut contents = String::new();
+    if let Err(e) = std::io::Read::read_to_string(&mut std::io::stdin(), &mut contents) {
+        eprintln!("stdin: {}", e);
+        return ExitCode::FAILURE;
+    }
+    let mut diagnostics = Vec::new();
+    let parsed = language.driver().with_source(path, contents.clone()).parse_file(path, &mut diagnostics);
+    eprint!("{}", renderer().with_source(path, &contents).render_all(&diagnostics));
+    if parsed.is_err() {
+        if !check {
+            print!("{}", contents);
+        }
+        return ExitCode::FAILURE;
+    }
+    let formatted = fmt::format(&contents);
+    if check {
+        if formatted == contents {
+            return ExitCode::SUCCESS;
+        }
+        println!("{}", source::display(path));
+        return ExitCode::FAILURE;
+    }
+    print!("{}", formatted);
+    ExitCode::SUCCESS
+}
+
+// What formatting a file came to, to be reported in the order the files were given
+#[derive(Default)]
+struct Formatted {
+    diagnostics: Vec<Diagnostic>,
+    error: Option<String>,
+    failed: bool,
+    unformatted: bool,
+}
+
+// Files that don't parse are left alone, so formatting never has to guess
+fn format_files(paths: &[String], check: bool, jobs: Option<NonZeroUsize>, language: &Language) -> ExitCode {
+    let Some(files) = discover(paths) else { return ExitCode::FAILURE };
+    let jobs = jobs.or_else(|| std::thread::available_parallelism().ok()).map_or(1, NonZeroUsize::get);
+    let results = in_parallel(&files, jobs, |file| {
+        let mut result = Formatted::default();
+        if file.extension().is_some_and(|ext| ext == "lhop") {
+            result.error = Some(format!("{}: literate files are not formatted", source::display(file)));
+            return result;
+        }
+        let parsed = language.driver().parse_file(file, &mut result.diagnostics);
+        let Some(contents) = parsed.ok().and_then(|_| std::fs::read_to_string(file).ok()) else {
+            result.failed = true;
+            return result;
+        };
+        let formatted = fmt::format(&contents);
+        result.unformatted = formatted != contents;
+        let written = if result.unformatted && !check { std::fs::write(file, formatted) } else { Ok(()) };
+        if let Err(e) = written {
+            result.error = Some(format!("{}: {}", source::display(file), source::describe(&e)));
+            result.failed = true;
+        }
+        result
+    });
+
+    let mut renderer = renderer();
+    for (file, result) in files.iter().zip(&results) {
+        eprint!("{}", renderer.render_all(&result.diagnostics));
+        if let Some(error) = &result.error {
+            eprintln!("{}", error);
+        }
+        if check && result.unformatted {
+            println!("{}", source::display(file));
+        }
+    }
+    let failed = results.iter().filter(|result| result.failed).count();
+    let unformatted = results.iter().filter(|result| result.unformatted).count();
+    if failed > 0 {
+        eprintln!("{} of {} files could not be formatted", failed, files.len());
+    }
+    if check && unformatted > 0 {
+        eprintln!("{} of {} files are not formatted", unformatted, files.len());
+    }
+    if failed > 0 || (check && unformatted > 0) { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
+
+// The work on each item, on up to `jobs` threads at once, with the results in the
+// order of the items whichever finished first
+fn in_parallel<T: Sync, R: Send>(items: &[T], jobs: usize, work: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = std::iter::repeat_with(|| None).take(items.len()).collect();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.clamp(1, items.len().max(1)))
+            .map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else { break };
+                    done.push((i, work(item)));
+                }
+                done
+            }))
+            .collect();
+        for worker in workers {
+            for (i, result) in worker.join().expect("workers don't panic") {
+                results[i] = Some(result);
+            }
+        }
+    });
+    results.into_iter().map(|result| result.expect("every item is worked on")).collect()
+}
+
+fn read_profile(path: &Path) -> Result<Profile, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", source::display(path), source::describe(&e)))?;
+    let document = serde_json::from_str(&text).map_err(|e| format!("{}: {}", source::display(path), e))?;
+    json::profile(&document).map_err(|message| format!("{}: {}", source::display(path), message))
+}
+
+// The sites with the most calls, to stderr so the program's output is left as it is
+// A step of the run, or `end` for after it
+fn step_or_end(arg: &str) -> Result<u64, String> {
+    match arg {
+        "end" => Ok(u64::MAX),
+        _ => arg.parse().map_err(|_| format!("`{}` is neither a step nor `end`", arg)),
+    }
+}
+
+fn print_profile(profile: &Profile) {
+    const SHOWN: usize = 10;
+    let sites = profile.sites();
+    eprintln!("{:>12}  site", "calls");
+    for site in sites.iter().take(SHOWN) {
+        eprintln!("{:>12}  {} at {}:{}", site.calls, site.function, site.line, site.column);
+    }
+    if sites.len() > SHOWN {
+        eprintln!("{:>12}  and {} more sites", "", sites.len() - SHOWN);
+    }
+}
+
+// What show gives for each function of each file, in the order its first equation
+// appears, loading the files in order so later ones can use earlier ones
+fn functions(outcome: &RunOutcome, show: impl Fn(&Interpreter, &str) -> String) -> Vec<String> {
+    let mut interp = Interpreter::new();
+    let mut shown = Vec::new();
+    for (path, typed) in &outcome.typed {
+        let program = Program { decls: typed.decls.iter().map(|typed| typed.decl.clone()).collect() };
+        interp.load(&program);
+        let mut names: Vec<&str> = Vec::new();
+        for decl in &program.decls {
+            match &decl.kind {
+                DeclKind::Equation(eq) if !names.contains(&eq.name.name.as_str()) => names.push(&eq.name.name),
+                _ => {}
+            }
+        }
+        let mut text = String::new();
+        for name in names {
+            text.push_str(&format!("{}: {}\n{}", source::display(path), name, show(&interp, name)));
+        }
+        shown.push(text);
+    }
+    shown
+}
+
+// The decision tree of each function
+fn match_trees(outcome: &RunOutcome) -> Vec<String> {
+    functions(outcome, |interp, name| interp.match_tree(name).expect("loaded functions have a tree").to_string())
+}
+
+// Each equation of each function, numbered from 1 as in the decision trees
+fn resolved(outcome: &RunOutcome) -> Vec<String> {
+    functions(outcome, |interp, name| {
+        let bodies = interp.resolved(name).expect("loaded functions are resolved");
+        bodies.iter().enumerate().map(|(i, body)| format!("equation {}: {}\n", i + 1, body)).collect()
+    })
+}
+
+// Writes each artifact of each checked file as `<name>.<artifact>`, returning whether
+// all of them could be written
+fn emit(outcome: &RunOutcome, artifacts: &[Emit], dir: Option<&Path>, files: &Files) -> bool {
+    let extras = Extras::default().with_dialect(files.language.dialect.into());
+    let trees = if artifacts.contains(&Emit::Match) { match_trees(outcome) } else { Vec::new() };
+    let bodies = if artifacts.contains(&Emit::Resolved) { resolved(outcome) } else { Vec::new() };
+    let cores = if artifacts.contains(&Emit::Core) { backend::Core.generate(&backend::units(&outcome.typed), &mut Vec::new()) } else { Vec::new() };
+    let mut written = true;
+    for (i, (path, typed)) in outcome.typed.iter().enumerate() {
+        // The library's modules are built in, with nowhere next to them to write
+        if library::source(path).is_some() {
+            continue;
+        }
+        let stem = path.file_stem().unwrap_or(path.as_os_str()).to_string_lossy();
+        let dir = dir.or(path.parent()).unwrap_or(Path::new("."));
+        for &artifact in artifacts {
+            let contents = match artifact {
+                Emit::Tokens => json::tokens_document(vec![tokens_json(path, extras.clone()).0]).to_string(),
+                Emit::Ast => {
+                    let program = Program { decls: typed.decls.iter().map(|typed| typed.decl.clone()).collect() };
+                    json::ast_document(vec![json::ast_file(path, &program)]).to_string()
+                }
+                Emit::Types => json::types_document(vec![json::types_file(path, typed)]).to_string(),
+                Emit::Match => trees[i].clone(),
+                Emit::Resolved => bodies[i].clone(),
+                Emit::Core => cores[i].contents.clone(),
+            };
+            let target = dir.join(format!("{}.{}", stem, artifact.extension()));
+            if let Err(e) = std::fs::write(&target, contents) {
+                eprintln!("{}: {}", source::display(&target), source::describe(&e));
+                written = false;
+            }
+        }
+    }
+    written
+}
+
+fn build(files: &Files, dir: &Path, graph: Option<hope::build::Graph>) -> ExitCode {
+    let Some(paths) = discover(&files.paths) else { return ExitCode::FAILURE };
+    let outcome = driver(files).check(&paths);
+    if !outcome.succeeded() {
+        return report(&outcome, files);
+    }
+    let modules = match loader(files).graph(&paths) {
+        Ok(modules) => modules,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        eprintln!("{}: {}", source::display(dir), source::describe(&e));
+        return ExitCode::FAILURE;
+    }
+    if !emit(&outcome, &[Emit::Core], Some(dir), files) {
+        return ExitCode::FAILURE;
+    }
+    let program = std::env::args().next().unwrap_or_else(|| "hope".to_owned());
+    let commands = hope::build::commands(&modules, &program, &files.flags(), dir);
+    let directory = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let mut written = vec![(dir.join(hope::build::COMMANDS_FILE), format!("{:#}\n", json::commands_document(&commands, &directory)))];
+    if let Some(graph) = graph {
+        written.push((dir.join(graph.file_name()), graph.write(&commands, &directory)));
+    }
+    for (target, contents) in written {
+        if let Err(e) = std::fs::write(&target, contents) {
+            eprintln!("{}: {}", source::display(&target), source::describe(&e));
+            return ExitCode::FAILURE;
+        }
+    }
+    report(&outcome, files)
+}
+
+// With differential, the bound on steps if there is one
+fn test(files: &Files, snap: bool, update: bool, coverage: bool, lcov: Option<&Path>, differential: Option<Option<u64>>) -> ExitCode {
+    let Some(paths) = discover(&files.paths) else { return ExitCode::FAILURE };
+    let driver = driver(files).with_coverage(coverage || lcov.is_some());
+    let text = files.format == Format::Text;
+    let mut results: Vec<(&Path, Option<String>)> = Vec::new();
+    // Files that more than one test uses are counted across all of them
+    let mut covered: Vec<(PathBuf, FileCoverage)> = Vec::new();
+    for path in &paths {
+        let outcome = driver.run(std::slice::from_ref(path));
+        for (file, runs) in &outcome.coverage {
+            match covered.iter_mut().find(|(seen, _)| seen == file) {
+                Some((_, total)) => total.merge(runs),
+                None => covered.push((file.clone(), runs.clone())),
+            }
+        }
+        if !outcome.succeeded() {
+            if text {
+                println!("{} ... FAILED", source::display(path));
+            }
+            print_diagnostics(&outcome.diagnostics, files.error_format());
+            let first = outcome.diagnostics.iter().find(|d| d.severity == Severity::Error);
+            results.push((path, Some(first.map_or_else(|| "failed".to_owned(), |d| d.message.clone()))));
+            continue;
+        }
+        let mut snapshot = path.clone().into_os_string();
+        snapshot.push(".out");
+        let snapshot = PathBuf::from(snapshot);
+        let result = if update {
+            std::fs::write(&snapshot, &outcome.stdout).map_err(|e| format!("{}: {}", source::display(&snapshot), source::describe(&e)))
+        } else if snap {
+            compare_snapshot(&snapshot, &outcome.stdout)
+        } else {
+            Ok(())
+        };
+        let result = result.and_then(|()| match differential {
+            Some(bound) => {
+                let differences = hope::differential::compare(&driver, std::slice::from_ref(path), bound);
+                if differences.is_empty() { Ok(()) } else { Err(differences.join("\n  ")) }
+            }
+            None => Ok(()),
+        });
+        match &result {
+            Ok(()) if text => println!("{} ... ok", source::display(path)),
+            Err(message) if text => println!("{} ... FAILED\n  {}", source::display(path), message),
+            _ => {}
+        }
+        results.push((path, result.err()));
+    }
+    let failed = results.iter().filter(|(_, failure)| failure.is_some()).count();
+    if text {
+        println!("{} passed, {} failed", paths.len() - failed, failed);
+        if coverage {
+            print_coverage(&covered);
+        }
+    } else {
+        println!("{}", json::test_document(&results, &covered));
+    }
+    if let Some(lcov) = lcov {
+        let tracefile: String = covered.iter().map(|(path, runs)| runs.lcov(&source::display(path))).collect();
+        if let Err(e) = std::fs::write(lcov, tracefile) {
+            eprintln!("{}: {}", source::display(lcov), source::describe(&e));
+            return ExitCode::FAILURE;
+        }
+    }
+    if failed == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+// The tests of a mutant get this many times the steps the slowest test took on the
+// original, since a change can easily make a program loop forever
+const MUTANT_STEPS: u64 = 10;
+
+fn mutate(files: &Files, tests: &[String]) -> ExitCode {
+    let Some(paths) = discover(&files.paths) else { return ExitCode::FAILURE };
+    let Some(tests) = discover(tests) else { return ExitCode::FAILURE };
+    let driver = driver(files);
+    let mut steps = 0;
+    for test in &tests {
+        let outcome = driver.run(std::slice::from_ref(test));
+        if !outcome.succeeded() {
+            eprintln!("{}: fails before any change", source::display(test));
+            print_diagnostics(&outcome.diagnostics, files.error_format());
+            return ExitCode::FAILURE;
+        }
+        steps = steps.max(outcome.stats.steps);
+    }
+    let limits = Limits { steps: Some(steps.saturating_mul(MUTANT_STEPS).max(100_000)), ..Limits::default() };
+
+    let (mut total, mut survived) = (0, 0);
+    for path in &paths {
+        let outcome = driver.check(std::slice::from_ref(path));
+        let (Ok(contents), Some((_, typed))) = (source::read(path), outcome.typed.iter().find(|(checked, _)| checked == path)) else {
+            print_diagnostics(&outcome.diagnostics, files.error_format());
+            return ExitCode::FAILURE;
+        };
+        let program = Program { decls: typed.decls.iter().map(|typed| typed.decl.clone()).collect() };
+        for mutant in mutate::mutants(&contents, &program) {
+            total += 1;
+            let driver = driver.clone().with_source(path, mutant.source).with_limits(limits);
+            if tests.iter().all(|test| driver.run(std::slice::from_ref(test)).succeeded()) {
+                println!("{}:{}:{}: {} survived", source::display(path), mutant.pos.line, mutant.pos.column, mutant.description);
+                survived += 1;
+            }
+        }
+    }
+    println!("{} of {} mutants caught", total - survived, total);
+    if survived == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+fn reduce(file: &Path, check: &str, language: &Language, no_prelude: bool) -> ExitCode {
+    let contents = match source::read(file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("{}: {}", source::display(file), source::describe(&e));
+            return ExitCode::FAILURE;
+        }
+    };
+    // Each try is written where it can have the file's name, so that checks that look
+    // at it see much the same thing
+    let dir = std::env::temp_dir().join(format!("hope-reduce-{}", std::process::id()));
+    let name = file.file_stem().map(|stem| Path::new(stem).with_extension("hop")).unwrap_or_else(|| PathBuf::from("reduced.hop"));
+    let target = dir.join(name);
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("{}: {}", source::display(&dir), source::describe(&e));
+        return ExitCode::FAILURE;
+    }
+    let holds = |candidate: &str| {
+        std::fs::write(&target, candidate).is_ok()
+            && std::process::Command::new("sh").arg("-c").arg(check).arg("sh").arg(&target)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+    };
+    let prelude = if no_prelude { Vec::new() } else { prelude::program().decls };
+    let parse = |candidate: &str| {
+        let mut parser = hope::parser::Parser::new(candidate).ok()?
+            .with_lenient_semicolons(language.lenient_semicolons)
+            .with_comprehensions(language.list_comprehensions)
+            .with_macros(language.macros)
+            .with_dialect(language.dialect.into())
+            .with_notation(&prelude);
+        parser.parse_program().ok()
+    };
+
+    let reduced = if holds(&contents) { Some(hope::reduce::reduce(&contents, parse, holds)) } else { None };
+    let _ = std::fs::remove_dir_all(&dir);
+    match reduced {
+        Some(reduced) => {
+            print!("{}", reduced);
+            eprintln!("reduced {} bytes to {}", contents.len(), reduced.len());
+            ExitCode::SUCCESS
+        }
+        None => {
+            eprintln!("{}: the check fails on the file as it is", source::display(file));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// A line per file, then whatever in it never ran
+fn print_coverage(covered: &[(PathBuf, FileCoverage)]) {
+    for (path, runs) in covered {
+        let equations: usize = runs.functions.iter().map(|f| f.equations.len()).sum();
+        let run: usize = runs.functions.iter().map(|f| f.equations_run()).sum();
+        let branches: usize = runs.functions.iter().map(|f| 2 * f.branches.len()).sum();
+        let taken: usize = runs.functions.iter().map(|f| f.branches_taken()).sum();
+        println!("{}: {} of {} equations, {} of {} branches", source::display(path), run, equations, taken, branches);
+        for f in &runs.functions {
+            for (pos, _) in f.equations.iter().filter(|(_, runs)| *runs == 0) {
+                println!("  {}:{}:{}: equation of `{}` never ran", source::display(path), pos.line, pos.column, f.name);
+            }
+            for (pos, taken) in &f.branches {
+                let ways: Vec<_> = ["then", "else"].iter().zip(taken).filter(|(_, runs)| **runs == 0).map(|(way, _)| *way).collect();
+                if !ways.is_empty() {
+                    println!("  {}:{}:{}: `{}` of this `if` never ran", source::display(path), pos.line, pos.column, ways.join("` or `"));
+                }
+            }
+        }
+    }
+}
+
+fn compare_snapshot(snapshot: &Path, written: &str) -> Result<(), String> {
+    let expected = std::fs::read_to_string(snapshot).map_err(|e| format!("{}: {}, see --update-snapshots", source::display(snapshot), source::describe(&e)))?;
+    let Some((line, expected, found)) = output::first_different_line(&expected, written) else { return Ok(()) };
+    let show = |line: Option<&str>| line.map_or("the end of the output".to_owned(), |line| format!("`{}`", line));
+    Err(format!("line {} differs from {}: expected {}, found {}", line, source::display(snapshot), show(expected), show(found)))
+}
+
+fn report(outcome: &RunOutcome, files: &Files) -> ExitCode {
+    print!("{}", outcome.stdout);
+    print_diagnostics(&outcome.diagnostics, files.error_format());
+    if outcome.succeeded() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+fn help(topic: Option<&str>, man: bool) -> ExitCode {
+    let mut cli = Cli::command();
+    cli.build();
+    if man {
+        let commands: Vec<_> = cli.get_subcommands()
+            .map(|command| (command.get_name().to_owned(), command.get_about().map(|about| about.to_string()).unwrap_or_default()))
+            .collect();
+        let about = cli.get_about().map(|about| about.to_string()).unwrap_or_default();
+        print!("{}", hope::help::man(cli.get_name(), &about, cli.get_version().unwrap_or_default(), &commands));
+        return ExitCode::SUCCESS;
+    }
+    let Some(name) = topic else {
+        print!("{}\n{}", cli.render_help(), hope::help::index());
+        return ExitCode::SUCCESS;
+    };
+    if let Some(topic) = hope::help::topic(name) {
+        println!("{}", topic);
+        return ExitCode::SUCCESS;
+    }
+    match cli.find_subcommand_mut(name) {
+        Some(command) => {
+            print!("{}", command.render_long_help());
+            ExitCode::SUCCESS
+        }
+        None => {
+            eprint!("no help topic or command `{}`\n\n{}", name, hope::help::index());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if cli.log.is_some() || cli.log_json {
+        let filter = cli.log.unwrap_or_else(|| "debug".parse().expect("debug is a level"));
+        trace::init(filter, cli.log_json).expect("no other logger is set");
+    }
+    match cli.command {
+        Command::Lex { stats, strict, dialect, format, paths } => lex(&paths, stats, strict, dialect, format),
+        Command::Parse(files) => parse(&files),
+        Command::Check { dump_match, dump_resolved, emit: mut artifacts, emit_dir, jobs, files } => {
+            let Some(paths) = discover(&files.paths) else { return ExitCode::FAILURE };
+            let outcome = match jobs {
+                // Checking gives no values, which are all that can't go between threads
+                Some(jobs) => {
+                    let outcomes = in_parallel(&paths, jobs.get(), |path| {
+                        let RunOutcome { diagnostics, typed, stats, status, violations, .. } = driver(&files).check(std::slice::from_ref(path));
+                        (diagnostics, typed, stats, status, violations)
+                    });
+                    outcomes.into_iter().fold(RunOutcome::default(), |mut all, (diagnostics, typed, stats, status, violations)| {
+                        all.merge(RunOutcome { diagnostics, typed, stats, status, violations, ..RunOutcome::default() });
+                        all
+                    })
+                }
+                None => driver(&files).check(&paths),
+            };
+            for (dump, artifact) in [(dump_match, Emit::Match), (dump_resolved, Emit::Resolved)] {
+                if dump && !artifacts.contains(&artifact) {
+                    artifacts.push(artifact);
+                }
+            }
+            if outcome.succeeded() && !emit(&outcome, &artifacts, emit_dir.as_deref(), &files) {
+                return ExitCode::FAILURE;
+            }
+            if files.format == Format::Json && outcome.succeeded() {
+                let documents = outcome.typed.iter().map(|(path, typed)| json::types_file(path, typed)).collect();
+                println!("{}", json::types_document(documents));
+            }
+            report(&outcome, &files)
+        }
+        Command::Build { out_dir, emit_build_graph, files } => build(&files, &out_dir, emit_build_graph.map(Into::into)),
+        Command::Mutate { tests, files } => mutate(&files, &tests),
+        Command::Reduce { check, language, no_prelude, file } => reduce(&file, &check, &language, no_prelude),
+        Command::Test { snap, update_snapshots, coverage, lcov, differential, step_bound, files } => {
+            let differential = differential.then_some(step_bound);
+            test(&files, snap, update_snapshots, coverage, lcov.as_deref(), differential)
+        }
+        Command::Run { entry, no_share, no_native_prelude, memo_capacity, max_output_lines, sandbox, profile, emit_profile, optimize, use_profile, record, heap_snapshot_at, emit_heap_graph, files } => {
+            let Some(paths) = discover(&files.paths) else { return ExitCode::FAILURE };
+            let guide = match use_profile.as_deref().map(read_profile).transpose() {
+                Ok(guide) => guide,
+                Err(message) => {
+                    eprintln!("{}", message);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let mut driver = driver(&files);
+            if let Some(name) = sandbox {
+                driver = driver.with_sandbox(sandbox::sandbox(&name).expect("clap only accepts sandbox names"));
+            }
+            let driver = driver
+                .with_entry(entry)
+                .with_sharing(!no_share)
+                .with_native_prelude(!no_native_prelude)
+                .with_memo_capacity(memo_capacity)
+                .with_profiling(profile || emit_profile.is_some())
+                .with_recording(record.is_some())
+                .with_heap_snapshot(heap_snapshot_at)
+                .with_optimization(optimize, guide);
+            let mut outcome = driver.run(&paths);
+            let emitted = match (&emit_profile, &outcome.profile) {
+                (Some(path), Some(counted)) => std::fs::write(path, format!("{:#}\n", json::profile_document(counted))).map_err(|e| (path, e)),
+                _ => Ok(()),
+            };
+            let emitted = emitted.and(match (&record, &outcome.trace) {
+                (Some(path), Some(trace)) => std::fs::write(path, trace.encode()).map_err(|e| (path, e)),
+                _ => Ok(()),
+            });
+            let emitted = emitted.and(match (&emit_heap_graph, &outcome.heap) {
+                (Some(path), Some(heap)) => std::fs::write(path, heap.dot()).map_err(|e| (path, e)),
+                _ => Ok(()),
+            });
+            if let Err((path, e)) = emitted {
+                eprintln!("{}: {}", source::display(path), source::describe(&e));
+                return ExitCode::FAILURE;
+            }
+            if let Some(counted) = outcome.profile.as_ref().filter(|_| profile) {
+                print_profile(counted);
+            }
+            if let Some(heap) = &outcome.heap {
+                eprintln!("{}", heap);
+            }
+            if let Some(cut) = max_output_lines.and_then(|rows| output::truncate(&outcome.stdout, rows, output::columns())) {
+                outcome.stdout = cut;
+            }
+            if files.format == Format::Json {
+                println!("{}", json::run_document(&outcome));
+                print_diagnostics(&outcome.diagnostics, files.error_format());
+                return if outcome.succeeded() { ExitCode::SUCCESS } else { ExitCode::FAILURE };
+            }
+            report(&outcome, &files)
+        }
+        Command::Analyze { cost, files } => {
+            let Some(paths) = discover(&files.paths) else { return ExitCode::FAILURE };
+            let outcome = driver(&files).check(&paths);
+            if !outcome.succeeded() {
+                return report(&outcome, &files);
+            }
+            let mut decls = if files.no_prelude { Vec::new() } else { prelude::program().decls };
+            decls.extend(outcome.typed.iter().flat_map(|(_, typed)| typed.decls.iter().map(|typed| typed.decl.clone())));
+            let mut missing = false;
+            for name in &cost {
+                match hope::cost::estimate(&decls, name) {
+                    Some(estimate) => println!("{}", estimate),
+                    None => {
+                        eprintln!("no function `{}` has equations to analyze", name);
+                        missing = true;
+                    }
+                }
+            }
+            if missing { ExitCode::FAILURE } else { report(&outcome, &files) }
+        }
+        Command::Export { prover, language, module_path, no_prelude, paths } => {
+            let Some(paths) = discover(&paths) else { return ExitCode::FAILURE };
+            let driver = language.driver()
+                .with_modules(Loader::new().with_search_path(module_path).with_env())
+                .with_prelude(!no_prelude);
+            let (outcome, artifacts) = driver.generate(&paths, backend::Prover(prover.into()).name());
+            print_diagnostics(&outcome.diagnostics, Format::Text);
+            if !outcome.succeeded() {
+                return ExitCode::FAILURE;
+            }
+            artifacts.iter().for_each(|artifact| print!("{}", artifact.contents));
+            ExitCode::SUCCESS
+        }
+        Command::Fmt { stdin: true, check, stdin_filename, language, .. } => format_stdin(&stdin_filename, check, &language),
+        Command::Fmt { check, jobs, language, paths, .. } => format_files(&paths, check, jobs, &language),
+        Command::Repl { no_prelude, max_output_lines, page, module_path, paths } => {
+            let Some(files) = discover(&paths) else { return ExitCode::FAILURE };
+            let paging = repl::Paging { max_lines: Some(max_output_lines).filter(|&lines| lines > 0), page };
+            let modules = Loader::new().with_search_path(module_path).with_env();
+            match repl::run(&files, !no_prelude, modules, paging) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::Tutor { progress } => match tutor::run(progress.or_else(tutor::progress_file).as_deref()) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{}", e);
+                ExitCode::FAILURE
+            }
+        },
+        Command::FuzzGen { seed, differential: None } => {
+            print!("{}", fuzz::generate(seed));
+            ExitCode::SUCCESS
+        }
+        Command::FuzzGen { seed, differential: Some(count) } => {
+            let failures: Vec<String> = (seed..seed.saturating_add(count)).filter_map(|seed| fuzz::differential(seed).err()).collect();
+            failures.iter().for_each(|failure| eprintln!("{}", failure));
+            println!("{} of {} programs ran alike every way", count - failures.len() as u64, count);
+            if failures.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+        }
+        Command::Schema { artifact } => {
+            let artifact = Artifact::from_name(&artifact).expect("clap only accepts artifact names");
+            println!("{}", serde_json::to_string_pretty(&json::schema(artifact)).expect("schemas are valid JSON"));
+            ExitCode::SUCCESS
+        }
+        Command::Completions { shell } => {
+            print!("{}", completions::generate(shell.into(), &Cli::command()));
+            ExitCode::SUCCESS
+        }
+        Command::Examples { action: None | Some(Examples::List) } => {
+            let width = examples::EXAMPLES.iter().map(|example| example.name.len()).max().unwrap_or(0);
+            for example in &examples::EXAMPLES {
+                println!("{:width$}  {}", example.name, example.summary, width = width);
+            }
+            ExitCode::SUCCESS
+        }
+        Command::Examples { action: Some(Examples::Show { name }) } => {
+            print!("{}", examples::example(&name).expect("clap only accepts example names").source);
+            ExitCode::SUCCESS
+        }
+        Command::Examples { action: Some(Examples::Run { name }) } => {
+            let outcome = examples::example(&name).expect("clap only accepts example names").run(Driver::new());
+            print!("{}", outcome.stdout);
+            print_diagnostics(&outcome.diagnostics, Format::Text);
+            if outcome.succeeded() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+        }
+        Command::Help { topic, man } => help(topic.as_deref(), man),
+        Command::Replay { trace } => {
+            let read = std::fs::read(&trace).map_err(|e| source::describe(&e)).and_then(|bytes| Trace::decode(&bytes));
+            let replayed = read.and_then(|read| Replay::new(read).run(std::io::stdin().lock(), std::io::stdout().lock()).map_err(|e| source::describe(&e)));
+            match replayed {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("{}: {}", source::display(&trace), e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::Serve { profile, module_path } => {
+            let profile = serve::profile(&profile).expect("clap only accepts profile names");
+            let mut server = serve::Server::new(profile).with_modules(Loader::new().with_search_path(module_path).with_env());
+            match server.serve(std::io::stdin().lock(), std::io::stdout().lock()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn should_give_results_in_the_order_of_the_items() {
+        let items: Vec<u64> = (0..40).collect();
+        // The first items take longest, so they finish last
+        let work = |&n: &u64| {
+            std::thread::sleep(Duration::from_millis(40 - n));
+            n * n
+        };
+        for jobs in [1, 4, 100] {
+            assert_eq!(in_parallel(&items, jobs, work), items.iter().map(|n| n * n).collect::<Vec<_>>());
         }
+        assert_eq!(in_parallel(&[] as &[u64], 4, work), Vec::<u64>::new());
     }
 }
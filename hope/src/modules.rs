@@ -0,0 +1,401 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::library;
+use crate::source::{self, canonical};
+use crate::syntax;
+use crate::syntax::ast::{DeclKind, Program};
+use crate::syntax::token::{Pos, TokenKind};
+
+// Directories to look for modules in, separated as PATH is
+pub const PATH_VAR: &str = "HOPE_PATH";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleError {
+    // No file for the module next to the one using it or along the search path
+    NotFound { path: PathBuf, name: String, pos: Pos, searched: Vec<PathBuf> },
+    // The modules from the one named first round to it again
+    Cycle { path: PathBuf, cycle: Vec<String>, pos: Pos },
+    // Two modules the file uses, neither using the other, both declare name. pos is the
+    // second in the file's `uses`, declared is each module with where it declares it
+    Ambiguous { path: PathBuf, name: String, declared: Box<[(String, PathBuf, Pos); 2]>, pos: Pos },
+    // The module is the file found, which the loader's Access doesn't let it read
+    Denied { path: PathBuf, name: String, found: PathBuf, pos: Pos },
+}
+
+impl ModuleError {
+    // The file with the `uses` at fault
+    pub fn path(&self) -> &Path {
+        match self {
+            ModuleError::NotFound { path, .. }
+            | ModuleError::Cycle { path, .. }
+            | ModuleError::Ambiguous { path, .. }
+            | ModuleError::Denied { path, .. } => path,
+        }
+    }
+
+    pub fn pos(&self) -> &Pos {
+        match self {
+            ModuleError::NotFound { pos, .. }
+            | ModuleError::Cycle { pos, .. }
+            | ModuleError::Ambiguous { pos, .. }
+            | ModuleError::Denied { pos, .. } => pos,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ModuleError::NotFound { .. } => "E0501",
+            ModuleError::Cycle { .. } => "E0502",
+            ModuleError::Ambiguous { .. } => "E0503",
+            ModuleError::Denied { .. } => "E0603",
+        }
+    }
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleError::NotFound { name, searched, .. } => {
+                let searched: Vec<_> = searched.iter().map(source::display).collect();
+                write!(f, "can't find module `{}`, looked in {}", name, searched.join(", "))
+            }
+            ModuleError::Cycle { cycle, .. } => write!(f, "modules use each other in a cycle: {}", cycle.join(" uses ")),
+            ModuleError::Ambiguous { name, declared, .. } =>
+                write!(f, "`{}` is declared by both `{}` and `{}`, which this file uses", name, declared[0].0, declared[1].0),
+            ModuleError::Denied { name, found, .. } => write!(f, "module `{}` is {}, which this sandbox can't read", name, source::display(found)),
+        }
+    }
+}
+
+// Finds the files that `uses Foo;` means, as Foo.hop or Foo.lhop next to the file
+// doing the using or else in the first directory of the search path that has one, or
+// else the library module Foo
+#[derive(Debug, Clone, Default)]
+pub struct Loader {
+    search_path: Vec<PathBuf>,
+    // Modules that are already loaded some other way, which `uses` needs no file for
+    provided: Vec<String>,
+    access: Access,
+}
+
+// The files `uses` may load modules from. The library's modules can always be used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Access {
+    // No files, only the library
+    Library,
+    // Files next to the one doing the using
+    Local,
+    #[default]
+    All,
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Loader::default()
+    }
+
+    pub fn with_search_path(mut self, dirs: impl IntoIterator<Item = PathBuf>) -> Self {
+        self.search_path.extend(dirs);
+        self
+    }
+
+    // Searches the directories in HOPE_PATH after any already given
+    pub fn with_env(self) -> Self {
+        match env::var_os(PATH_VAR) {
+            Some(dirs) => self.with_search_path(env::split_paths(&dirs).filter(|dir| !dir.as_os_str().is_empty())),
+            None => self,
+        }
+    }
+
+    pub fn with_access(mut self, access: Access) -> Self {
+        self.access = access;
+        self
+    }
+
+    pub fn with_provided(mut self, name: &str) -> Self {
+        self.provided.push(name.to_owned());
+        self
+    }
+
+    fn dirs(&self, from: &Path) -> Vec<PathBuf> {
+        let here = match from.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        std::iter::once(here).chain(self.search_path.iter().cloned()).collect()
+    }
+
+    pub fn resolve(&self, name: &str, from: &Path) -> Option<PathBuf> {
+        self.dirs(from).into_iter()
+            .flat_map(|dir| source::EXTENSIONS.map(|ext| dir.join(format!("{}.{}", name, ext))))
+            .find(|path| path.is_file())
+            .or_else(|| library::path(name))
+    }
+
+    // Whether the module found for a `uses` in the file is one its Access lets it read
+    fn permits(&self, found: &Path, name: &str, from: &Path) -> bool {
+        match self.access {
+            Access::All => true,
+            _ if library::path(name).as_deref() == Some(found) => true,
+            Access::Local => self.dirs(from).first().is_some_and(|here| found.parent() == Some(here.as_path())),
+            Access::Library => false,
+        }
+    }
+
+    // The files with every module they use, directly or not, before them. Each file is
+    // listed once however many others use it, so it is parsed and checked once
+    pub fn order(&self, roots: &[PathBuf]) -> Result<Vec<PathBuf>, ModuleError> {
+        Ok(self.graph(roots)?.into_iter().map(|module| module.path).collect())
+    }
+
+    // The same files in the same order, each with the modules it uses directly
+    pub fn graph(&self, roots: &[PathBuf]) -> Result<Vec<Module>, ModuleError> {
+        let mut visit = Visit { loader: self, done: HashSet::new(), using: Vec::new(), order: Vec::new() };
+        for root in roots {
+            visit.file(root)?;
+        }
+        Ok(visit.order)
+    }
+
+    // The modules named by the uses of an input that isn't a file, as if it were the
+    // file from, each after every module it uses. Those in loaded are taken as done
+    pub fn order_used(&self, from: &Path, used: Vec<(String, Pos)>, loaded: &[PathBuf]) -> Result<Vec<PathBuf>, ModuleError> {
+        let done = loaded.iter().map(|path| canonical(path)).collect();
+        let mut visit = Visit { loader: self, done, using: Vec::new(), order: Vec::new() };
+        visit.used(from, used)?;
+        Ok(visit.order.into_iter().map(|module| module.path).collect())
+    }
+}
+
+// A file of a program and the files of the modules its `uses` name, as they were found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    pub path: PathBuf,
+    pub uses: Vec<PathBuf>,
+}
+
+// What each file checked so far declares before any `private`, and the files it uses
+// directly or not, to tell when one uses two modules that declare the same name
+#[derive(Debug, Clone, Default)]
+pub struct Exports {
+    files: HashMap<PathBuf, File>,
+}
+
+#[derive(Debug, Clone)]
+struct File {
+    path: PathBuf,
+    names: HashMap<String, Pos>,
+    reaches: HashSet<PathBuf>,
+}
+
+impl Exports {
+    pub fn new() -> Self {
+        Exports::default()
+    }
+
+    // Files have to be added after the modules they use, as Loader::order lists them.
+    // What the file declares itself hides what its modules do, so isn't ambiguous
+    pub fn add(&mut self, loader: &Loader, path: &Path, program: &Program) -> Result<(), ModuleError> {
+        let mut names = HashMap::new();
+        let mut used = Vec::new();
+        for decl in &program.decls {
+            match &decl.kind {
+                DeclKind::Private => break,
+                DeclKind::Dec { names: declared, .. } => names.extend(declared.iter().map(|n| (n.name.clone(), n.pos.clone()))),
+                DeclKind::Uses(modules) => {
+                    for module in modules.iter().filter(|m| !loader.provided.contains(&m.name)) {
+                        if let Some(found) = loader.resolve(&module.name, path) {
+                            used.push((canonical(&found), module));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut reaches = HashSet::from([canonical(path)]);
+        for (i, (key, module)) in used.iter().enumerate() {
+            let Some(file) = self.files.get(key) else { continue };
+            reaches.extend(file.reaches.iter().cloned());
+            for (earlier_key, earlier) in &used[..i] {
+                let Some(other) = self.files.get(earlier_key) else { continue };
+                if file.reaches.contains(earlier_key) || other.reaches.contains(key) {
+                    continue;
+                }
+                let mut common: Vec<_> = file.names.keys().filter(|n| other.names.contains_key(*n) && !names.contains_key(*n)).collect();
+                common.sort();
+                if let Some(name) = common.first() {
+                    return Err(ModuleError::Ambiguous {
+                        path: path.to_path_buf(),
+                        name: name.to_string(),
+                        declared: Box::new([
+                            (earlier.name.clone(), other.path.clone(), other.names[*name].clone()),
+                            (module.name.clone(), file.path.clone(), file.names[*name].clone()),
+                        ]),
+                        pos: module.pos.clone(),
+                    });
+                }
+            }
+        }
+        self.files.insert(canonical(path), File { path: path.to_path_buf(), names, reaches });
+        Ok(())
+    }
+}
+
+struct Visit<'a> {
+    loader: &'a Loader,
+    done: HashSet<PathBuf>,
+    // The files whose modules are being found, outermost first, with their names
+    using: Vec<(PathBuf, String)>,
+    order: Vec<Module>,
+}
+
+impl Visit<'_> {
+    fn file(&mut self, path: &Path) -> Result<(), ModuleError> {
+        let key = canonical(path);
+        if self.done.contains(&key) {
+            return Ok(());
+        }
+        let name = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        self.using.push((key.clone(), name));
+        let uses = self.used(path, uses(path))?;
+        self.using.pop();
+        self.done.insert(key);
+        self.order.push(Module { path: path.to_path_buf(), uses });
+        Ok(())
+    }
+
+    // The files found for the modules
+    fn used(&mut self, path: &Path, used: Vec<(String, Pos)>) -> Result<Vec<PathBuf>, ModuleError> {
+        let mut files = Vec::new();
+        for (name, pos) in used {
+            if self.loader.provided.contains(&name) {
+                continue;
+            }
+            let Some(found) = self.loader.resolve(&name, path) else {
+                let searched = self.loader.dirs(path);
+                return Err(ModuleError::NotFound { path: path.to_path_buf(), name, pos, searched });
+            };
+            if !self.loader.permits(&found, &name, path) {
+                return Err(ModuleError::Denied { path: path.to_path_buf(), name, found, pos });
+            }
+            let found_key = canonical(&found);
+            if let Some(i) = self.using.iter().position(|(key, _)| *key == found_key) {
+                let cycle = self.using[i..].iter().map(|(_, name)| name.clone()).chain([name]).collect();
+                return Err(ModuleError::Cycle { path: path.to_path_buf(), cycle, pos });
+            }
+            self.file(&found)?;
+            if !files.contains(&found) {
+                files.push(found);
+            }
+        }
+        Ok(files)
+    }
+}
+
+// The modules each `uses` in the file names. This only lexes, since what the file
+// means can depend on the operators those modules declare. A file that can't be read
+// uses nothing here, and the error is left to whatever reads it next
+fn uses(path: &Path) -> Vec<(String, Pos)> {
+    match source::read(path) {
+        Ok(contents) => uses_in(&contents),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn uses_in(contents: &str) -> Vec<(String, Pos)> {
+    let (tokens, _) = syntax::lex(contents);
+    let mut names = Vec::new();
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        if token.kind != TokenKind::Uses {
+            continue;
+        }
+        for token in tokens.by_ref() {
+            match token.identifier() {
+                Some(name) => names.push((name.to_owned(), token.pos.clone())),
+                None if token.kind == TokenKind::Comma => {}
+                None => break,
+            }
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn with_dir<T>(name: &str, files: &[(&str, &str)], f: impl FnOnce(&Path) -> T) -> T {
+        let dir = env::temp_dir().join(format!("hope-modules-{}-{}", std::process::id(), name));
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let result = f(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    #[test]
+    fn should_order_modules_before_their_users_once_each() {
+        let files = [
+            ("main.hop", "uses Lists, Trees;\nuses Lists;"),
+            ("Lists.hop", "uses Base;"),
+            ("Trees.lhop", "> uses Base, Lists;"),
+            ("lib/Base.hop", ""),
+        ];
+        let (order, graph) = with_dir("order", &files, |dir| {
+            let loader = Loader::new().with_search_path([dir.join("lib")]);
+            let roots = [dir.join("main.hop"), dir.join("Lists.hop")];
+            let relative = |path: &PathBuf| path.strip_prefix(dir).unwrap().to_path_buf();
+            let graph = loader.graph(&roots).unwrap().iter()
+                .map(|module| (relative(&module.path), module.uses.iter().map(relative).collect::<Vec<_>>()))
+                .collect::<Vec<_>>();
+            (loader.order(&roots).unwrap().iter().map(relative).collect::<Vec<_>>(), graph)
+        });
+        assert_eq!(order, ["lib/Base.hop", "Lists.hop", "Trees.lhop", "main.hop"].map(PathBuf::from));
+        assert_eq!(graph.iter().map(|(path, _)| path).collect::<Vec<_>>(), order.iter().collect::<Vec<_>>());
+        assert_eq!(graph[2].1, ["lib/Base.hop", "Lists.hop"].map(PathBuf::from));
+        assert_eq!(graph[3].1, ["Lists.hop", "Trees.lhop"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn should_report_missing_modules_and_cycles() {
+        let files = [("main.hop", "uses A;"), ("A.hop", "dec x : num;\nuses B;"), ("B.hop", "uses A;"), ("C.hop", "uses Missing;")];
+        let (cycle, missing) = with_dir("errors", &files, |dir| {
+            let loader = Loader::new().with_search_path([dir.join("lib")]);
+            (loader.order(&[dir.join("main.hop")]).unwrap_err(), loader.order(&[dir.join("C.hop")]).unwrap_err())
+        });
+        assert_eq!(cycle.to_string(), "modules use each other in a cycle: A uses B uses A");
+        assert_eq!((cycle.path().file_name().unwrap(), cycle.pos().line, cycle.pos().column), ("B.hop".as_ref(), 1, 6));
+        assert_eq!(cycle.code(), "E0502");
+
+        assert!(missing.to_string().starts_with("can't find module `Missing`, looked in "), "{}", missing);
+        assert!(matches!(&missing, ModuleError::NotFound { searched, .. } if searched.len() == 2));
+        assert_eq!(missing.code(), "E0501");
+
+        let provided = with_dir("provided", &files, |dir| Loader::new().with_provided("Missing").order(&[dir.join("C.hop")]));
+        assert_eq!(provided.map(|order| order.len()), Ok(1));
+    }
+
+    #[test]
+    fn should_only_load_the_files_access_allows() {
+        let files = [("main.hop", "uses Char, Near;"), ("Near.hop", "uses Far;"), ("lib/Far.hop", "")];
+        let orders = with_dir("access", &files, |dir| {
+            [Access::All, Access::Local, Access::Library].map(|access| {
+                Loader::new().with_search_path([dir.join("lib")]).with_access(access).order(&[dir.join("main.hop")])
+            })
+        });
+        let [all, local, library] = orders;
+        assert_eq!(all.map(|order| order.len()), Ok(4));
+        let local = local.unwrap_err();
+        assert!(local.to_string().starts_with("module `Far` is ") && local.to_string().ends_with("Far.hop, which this sandbox can't read"));
+        assert_eq!((local.path().file_name().unwrap(), local.code()), ("Near.hop".as_ref(), "E0603"));
+        assert!(matches!(library.unwrap_err(), ModuleError::Denied { name, .. } if name == "Near"));
+    }
+}
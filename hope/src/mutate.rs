@@ -0,0 +1,181 @@
+use alloc::collections::BTreeSet;
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+use crate::syntax::token::Pos;
+
+// Small changes to a program that its tests ought to notice: an arithmetic operator
+// for its opposite, a comparison for its negation, or two equations of a function
+// that can match the same argument swapped, so the other one wins
+
+const OPPOSITES: [(&str, &str); 8] = [
+    ("+", "-"),
+    ("-", "+"),
+    ("<", ">="),
+    (">=", "<"),
+    (">", "=<"),
+    ("=<", ">"),
+    ("=", "/="),
+    ("/=", "="),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mutant {
+    pub pos: Pos,
+    pub description: String,
+    // The whole source with the change made
+    pub source: String,
+}
+
+// The mutants of the source, which the program was parsed from
+pub fn mutants(source: &str, program: &Program) -> Vec<Mutant> {
+    let mut constructors: BTreeSet<&str> = ["true", "false", "nil"].into_iter().collect();
+    for decl in &program.decls {
+        if let DeclKind::Data { constructors: declared, .. } = &decl.kind {
+            constructors.extend(declared.iter().map(|c| c.name.name.as_str()));
+        }
+    }
+
+    let mut mutants = Vec::new();
+    for decl in &program.decls {
+        let DeclKind::Equation(eq) = &decl.kind else { continue };
+        let mut ops = Vec::new();
+        eq.guard.iter().chain([&eq.body]).for_each(|expr| operators(expr, &mut ops));
+        // Expanded syntax can bring in operators written elsewhere
+        let inside = |pos: &Pos| decl.pos.range.start <= pos.range.start && pos.range.end <= decl.pos.range.end;
+        for op in ops.into_iter().filter(|op| inside(&op.pos)) {
+            let Some(&(_, opposite)) = OPPOSITES.iter().find(|(name, _)| *name == op.name) else { continue };
+            let range = op.pos.range.clone();
+            mutants.push(Mutant {
+                pos: op.pos.clone(),
+                description: format!("`{}` to `{}`", op.name, opposite),
+                source: format!("{}{}{}", &source[..range.start], opposite, &source[range.end..]),
+            });
+        }
+    }
+
+    let mut seen: Vec<&str> = Vec::new();
+    for decl in &program.decls {
+        let DeclKind::Equation(eq) = &decl.kind else { continue };
+        if seen.contains(&eq.name.name.as_str()) {
+            continue;
+        }
+        seen.push(&eq.name.name);
+        let equations: Vec<(&Decl, &Equation)> = program.decls.iter()
+            .filter_map(|decl| match &decl.kind {
+                DeclKind::Equation(other) if other.name.name == eq.name.name => Some((decl, other)),
+                _ => None,
+            })
+            .collect();
+        for (i, pair) in equations.windows(2).enumerate() {
+            let [(first, a), (second, b)] = pair else { unreachable!() };
+            if a.args.len() != b.args.len() || !a.args.iter().zip(&b.args).all(|(p, q)| overlap(p, q, &constructors)) {
+                continue;
+            }
+            let (x, y) = (first.pos.range.clone(), second.pos.range.clone());
+            mutants.push(Mutant {
+                pos: first.pos.clone(),
+                description: format!("equations {} and {} of `{}` swapped", i + 1, i + 2, eq.name.name),
+                source: format!("{}{}{}{}{}", &source[..x.start], &source[y.clone()], &source[x.end..y.start], &source[x.clone()], &source[y.end..]),
+            });
+        }
+    }
+    mutants
+}
+
+fn operators<'a>(expr: &'a Expr, ops: &mut Vec<&'a Ident>) {
+    match &expr.kind {
+        ExprKind::Var(_) | ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) => {}
+        ExprKind::Tuple(items) | ExprKind::List(items) => items.iter().for_each(|item| operators(item, ops)),
+        ExprKind::Apply(f, arg) => {
+            operators(f, ops);
+            operators(arg, ops);
+        }
+        ExprKind::BinOp(op, l, r) => {
+            ops.push(op);
+            operators(l, ops);
+            operators(r, ops);
+        }
+        ExprKind::If(c, t, e) => {
+            operators(c, ops);
+            operators(t, ops);
+            operators(e, ops);
+        }
+        ExprKind::Lambda(rules) => rules.iter().for_each(|rule| operators(&rule.body, ops)),
+        ExprKind::Let(binding) => {
+            operators(&binding.value, ops);
+            operators(&binding.body, ops);
+        }
+    }
+}
+
+// Whether some value matches both patterns. Swapping equations that can't both match
+// changes nothing, so such a mutant could never be caught
+fn overlap(p: &Pattern, q: &Pattern, constructors: &BTreeSet<&str>) -> bool {
+    match (&p.kind, &q.kind) {
+        (PatternKind::As(_, p), _) => overlap(p, q, constructors),
+        (_, PatternKind::As(_, q)) => overlap(p, q, constructors),
+        (PatternKind::Wildcard, _) | (_, PatternKind::Wildcard) => true,
+        (PatternKind::Var(name), _) if !constructors.contains(name.as_str()) => true,
+        (_, PatternKind::Var(name)) if !constructors.contains(name.as_str()) => true,
+        (PatternKind::Int(a), PatternKind::Int(b)) => a == b,
+        (PatternKind::Num(a), PatternKind::Num(b)) => a == b,
+        (PatternKind::Str(a), PatternKind::Str(b)) => a == b,
+        (PatternKind::Tuple(a), PatternKind::Tuple(b)) | (PatternKind::List(a), PatternKind::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(p, q)| overlap(p, q, constructors))
+        }
+        _ => match (constructor(p), constructor(q)) {
+            (Some((a, xs)), Some((b, ys))) => {
+                a == b && xs.len() == ys.len() && xs.iter().zip(&ys).all(|(p, q)| overlap(p, q, constructors))
+            }
+            // A list pattern against `nil` or `::`, an `n + k` against a number and so
+            // on, which may match the same value
+            _ => true,
+        },
+    }
+}
+
+// The constructor a pattern matches and the patterns of its arguments, a tuple of
+// them taken apart
+fn constructor(p: &Pattern) -> Option<(&str, Vec<&Pattern>)> {
+    match &p.kind {
+        // Only constructors are left as names by the time this is asked
+        PatternKind::Var(name) => Some((name, Vec::new())),
+        PatternKind::Construct(name, args) => match &args[..] {
+            [Pattern { kind: PatternKind::Tuple(items), .. }] => Some((&name.name, items.iter().collect())),
+            args => Some((&name.name, args.iter().collect())),
+        },
+        PatternKind::BinOp(op, l, r) if op.name != "+" => Some((&op.name, vec![&**l, &**r])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn descriptions(source: &str) -> Vec<String> {
+        let program = parser::parse_program(source).unwrap();
+        mutants(source, &program).into_iter().map(|m| m.description).collect()
+    }
+
+    #[test]
+    fn should_flip_operators_in_equations() {
+        let source = "dec f : num -> bool;\n--- f n <= n + 1 < 2;";
+        let program = parser::parse_program(source).unwrap();
+        let found = mutants(source, &program);
+        assert_eq!(found.iter().map(|m| m.description.as_str()).collect::<Vec<_>>(), ["`<` to `>=`", "`+` to `-`"]);
+        assert_eq!(found[1].source, "dec f : num -> bool;\n--- f n <= n - 1 < 2;");
+        assert_eq!(found[0].pos.column, 18);
+    }
+
+    #[test]
+    fn should_only_swap_equations_that_can_match_the_same_argument() {
+        let source = "dec fib : num -> num;\n--- fib 0 <= 0;\n--- fib n <= n;\n\
+            dec length : list num -> num;\n--- length nil <= 0;\n--- length (x :: l) <= 1;";
+        assert_eq!(descriptions(source), ["equations 1 and 2 of `fib` swapped"]);
+        let program = parser::parse_program(source).unwrap();
+        let swapped = &mutants(source, &program)[0].source;
+        assert!(swapped.starts_with("dec fib : num -> num;\n--- fib n <= n;\n--- fib 0 <= 0;\n"));
+    }
+}
@@ -0,0 +1,95 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+// How many columns the terminal has, from COLUMNS as shells set it
+pub fn columns() -> usize {
+    std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).filter(|&c| c > 0).unwrap_or(80)
+}
+
+// The text cut down to `rows` rows of a terminal `width` columns wide, where a long
+// line takes as many rows as it wraps to, or None if it already fits. A list prints
+// on one line however long it is, so counting only newlines wouldn't stop it
+pub fn truncate(text: &str, rows: usize, width: usize) -> Option<String> {
+    let width = width.max(1);
+    let height = |line: &str| line.chars().count().div_ceil(width).max(1);
+    if text.lines().map(height).sum::<usize>() <= rows {
+        return None;
+    }
+    let mut cut = String::new();
+    let mut used = 0;
+    let mut shown = 0;
+    for line in text.lines() {
+        if used + height(line) > rows {
+            let room = (rows - used) * width;
+            if room > 0 {
+                cut.extend(line.chars().take(room));
+                cut.push('\n');
+                shown += room;
+            }
+            break;
+        }
+        cut.push_str(line);
+        cut.push('\n');
+        used += height(line);
+        shown += line.chars().count();
+    }
+    let total: usize = text.lines().map(|line| line.chars().count()).sum();
+    let hidden = total - shown;
+    cut.push_str(&format!("... {} more character{} not shown\n", hidden, if hidden == 1 { "" } else { "s" }));
+    Some(cut)
+}
+
+// The number, from 1, of the first line where the texts differ, with what each has
+// there or None past its end
+pub fn first_different_line<'a>(expected: &'a str, actual: &'a str) -> Option<(usize, Option<&'a str>, Option<&'a str>)> {
+    let (mut expected, mut actual) = (expected.lines(), actual.lines());
+    for number in 1.. {
+        match (expected.next(), actual.next()) {
+            (None, None) => return None,
+            (l, r) if l != r => return Some((number, l, r)),
+            _ => {}
+        }
+    }
+    unreachable!()
+}
+
+// Shows the text through $PAGER, or `less`, until it quits
+pub fn page(text: &str) -> io::Result<()> {
+    let pager = std::env::var("PAGER").ok().filter(|p| !p.trim().is_empty()).unwrap_or_else(|| "less".to_owned());
+    let mut words = pager.split_whitespace();
+    let program = words.next().expect("the pager isn't blank");
+    let mut child = Command::new(program).args(words).stdin(Stdio::piped()).spawn()?;
+    let written = child.stdin.take().expect("stdin is piped").write_all(text.as_bytes());
+    child.wait()?;
+    // Quitting the pager before the end closes the pipe, which is fine
+    match written {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_leave_output_that_fits() {
+        assert_eq!(truncate("a\nb\n", 2, 80), None);
+        assert_eq!(truncate(&"x".repeat(160), 2, 80), None);
+    }
+
+    #[test]
+    fn should_find_the_first_line_that_differs() {
+        assert_eq!(first_different_line("a\nb\n", "a\nb"), None);
+        assert_eq!(first_different_line("a\nb\nc\n", "a\nx\nc\n"), Some((2, Some("b"), Some("x"))));
+        assert_eq!(first_different_line("a\n", "a\nb\n"), Some((2, None, Some("b"))));
+    }
+
+    #[test]
+    fn should_count_long_lines_as_the_rows_they_wrap_to() {
+        assert_eq!(truncate("a\nb\nc\n", 2, 80).unwrap(), "a\nb\n... 1 more character not shown\n");
+        let list = format!("[{}]", vec!["1"; 100].join(", "));
+        let cut = truncate(&list, 1, 20).unwrap();
+        assert_eq!(cut, format!("{}\n... {} more characters not shown\n", &list[..20], list.len() - 20));
+    }
+}
@@ -0,0 +1,86 @@
+use alloc::collections::BTreeSet;
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+use crate::syntax::token::Pos;
+
+// `pattern <- list` or a condition, after the `|` of a comprehension
+pub(super) enum Qualifier {
+    Generator(Pattern, Expr),
+    Guard(Expr),
+}
+
+// Translates `[e | qualifiers]` into recursion over the generators' lists, consing
+// each result onto the rest rather than appending, so the comprehension means the
+// same with or without the standard library loaded:
+//
+//     [e | p <- l, q] ++ rest  =  letrec h == lambda [] => rest
+//                                                   | p :: us => [e | q] ++ h us
+//                                                   | u :: us => h us
+//                                 in h l
+//     [e | b, q] ++ rest       =  if b then [e | q] ++ rest else rest
+//     [e | ] ++ rest           =  e :: rest
+//
+// What the user wrote keeps its position, everything made up here takes that of the
+// qualifier it stands for
+pub(super) fn comprehension(item: Expr, qualifiers: Vec<Qualifier>, pos: &Pos, constructors: &BTreeSet<String>, fresh: &mut usize) -> Expr {
+    let nil = Expr { kind: ExprKind::List(Vec::new()), pos: pos.clone() };
+    let mut expr = translate(item, qualifiers.into_iter().rev().collect(), nil, pos, constructors, fresh);
+    expr.pos = pos.clone();
+    expr
+}
+
+// The qualifiers are reversed, so the next one is at the end
+fn translate(item: Expr, mut qualifiers: Vec<Qualifier>, rest: Expr, pos: &Pos, constructors: &BTreeSet<String>, fresh: &mut usize) -> Expr {
+    let at = |kind, pos: &Pos| Expr { kind, pos: pos.clone() };
+    match qualifiers.pop() {
+        None => at(ExprKind::BinOp(cons(pos), Box::new(item), Box::new(rest)), pos),
+        Some(Qualifier::Guard(cond)) => {
+            let pos = cond.pos.clone();
+            let then = translate(item, qualifiers, rest.clone(), &pos, constructors, fresh);
+            at(ExprKind::If(Box::new(cond), Box::new(then), Box::new(rest)), &pos)
+        }
+        Some(Qualifier::Generator(pattern, list)) => {
+            let pos = pattern.pos.to(&list.pos);
+            let name = |base: &str, fresh: &mut usize| {
+                *fresh += 1;
+                format!("{}#{}", base, fresh)
+            };
+            let (h, us) = (name("h", fresh), name("us", fresh));
+            let var = |name: &str| at(ExprKind::Var(name.to_owned()), &pos);
+            let pat = |kind| Pattern { kind, pos: pos.clone() };
+            let cons_pattern = |head: Pattern| pat(PatternKind::BinOp(cons(&pos), Box::new(head), Box::new(pat(PatternKind::Var(us.clone())))));
+            let next = at(ExprKind::Apply(Box::new(var(&h)), Box::new(var(&us))), &pos);
+
+            // A generator whose pattern can't fail needs no rule to skip elements
+            let refutable = !irrefutable(&pattern, constructors);
+            let mut rules = vec![
+                Rule { pattern: pat(PatternKind::List(Vec::new())), body: rest, pos: pos.clone() },
+                Rule { pattern: cons_pattern(pattern), body: translate(item, qualifiers, next.clone(), &pos, constructors, fresh), pos: pos.clone() },
+            ];
+            if refutable {
+                let u = name("u", fresh);
+                rules.push(Rule { pattern: cons_pattern(pat(PatternKind::Var(u))), body: next, pos: pos.clone() });
+            }
+
+            let lambda = at(ExprKind::Lambda(rules), &pos);
+            let body = at(ExprKind::Apply(Box::new(var(&h)), Box::new(list)), &pos);
+            let pattern = pat(PatternKind::Var(h));
+            at(ExprKind::Let(Box::new(Let { kind: LetKind::LetRec, pattern, value: lambda, body })), &pos)
+        }
+    }
+}
+
+fn cons(pos: &Pos) -> Ident {
+    Ident { name: "::".to_owned(), pos: pos.clone() }
+}
+
+// A variable that names a constructor matches only that constructor
+fn irrefutable(pattern: &Pattern, constructors: &BTreeSet<String>) -> bool {
+    match &pattern.kind {
+        PatternKind::Var(name) => !constructors.contains(name),
+        PatternKind::Wildcard => true,
+        PatternKind::As(_, pattern) => irrefutable(pattern, constructors),
+        PatternKind::Tuple(items) => items.iter().all(|item| irrefutable(item, constructors)),
+        _ => false,
+    }
+}
@@ -0,0 +1,154 @@
+use core::fmt;
+use crate::alloc_prelude::*;
+use crate::syntax::token::{LexingError, Pos};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Lexing(LexingError, Pos),
+    UnexpectedToken { expected: &'static str, found: &'static str, pos: Pos },
+    UnexpectedEof { expected: &'static str, pos: Pos },
+    // A bracket or keyword that needs a partner never got one, `open` is where the
+    // construct started
+    Unclosed { expected: &'static str, found: &'static str, opener: &'static str, open: Pos, pos: Pos },
+    InvalidPattern(Pos),
+    InvalidPrecedence(Pos),
+    NestingTooDeep(Pos),
+    // A syntax definition applied to fewer arguments than it has parameters
+    SyntaxArity { name: String, expected: usize, found: usize, pos: Pos },
+    // A keyword where a name should be
+    ReservedWord { word: String, expected: &'static str, reserved: Reserved, pos: Pos },
+    // Given up at pos, see Cancel
+    Cancelled(Pos),
+    // Something the miranda-lite frontend reads no further than to say so
+    Unsupported(&'static str, Pos),
+}
+
+// When a word is a keyword rather than a name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reserved {
+    Always,
+    // The compat keywords, see Dialect
+    Classic,
+    // `syntax`, see Parser::with_macros
+    Macros,
+}
+
+impl ParseError {
+    pub fn pos(&self) -> &Pos {
+        match self {
+            ParseError::Lexing(_, pos)
+            | ParseError::UnexpectedToken { pos, .. }
+            | ParseError::UnexpectedEof { pos, .. }
+            | ParseError::Unclosed { pos, .. }
+            | ParseError::InvalidPattern(pos)
+            | ParseError::InvalidPrecedence(pos)
+            | ParseError::NestingTooDeep(pos)
+            | ParseError::SyntaxArity { pos, .. }
+            | ParseError::ReservedWord { pos, .. }
+            | ParseError::Cancelled(pos)
+            | ParseError::Unsupported(_, pos) => pos,
+        }
+    }
+
+    // Stable identifiers for tools, grouped by phase: E01xx lexing, E02xx parsing,
+    // E03xx type checking and E04xx evaluation
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::Lexing(e, _) => e.code(),
+            ParseError::UnexpectedToken { .. } => "E0201",
+            ParseError::UnexpectedEof { .. } => "E0202",
+            ParseError::Unclosed { .. } => "E0203",
+            ParseError::InvalidPattern(_) => "E0204",
+            ParseError::InvalidPrecedence(_) => "E0205",
+            ParseError::NestingTooDeep(_) => "E0206",
+            ParseError::SyntaxArity { .. } => "E0207",
+            ParseError::ReservedWord { .. } => "E0208",
+            ParseError::Cancelled(_) => "E0209",
+            ParseError::Unsupported(..) => "E0210",
+        }
+    }
+
+    // How to fix the source, where there is an obvious way
+    pub fn help(&self) -> Option<String> {
+        match self {
+            ParseError::ReservedWord { word, reserved: Reserved::Classic, .. } =>
+                Some(format!("rename it, for example to `{}'`, or use the modern dialect where it is a name", word)),
+            ParseError::ReservedWord { word, .. } => Some(format!("rename it, for example to `{}'`", word)),
+            _ => None,
+        }
+    }
+
+    pub fn opened_at(&self) -> Option<(&'static str, &Pos)> {
+        match self {
+            ParseError::Unclosed { opener, open, .. } => Some((opener, open)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Lexing(e, _) => write!(f, "{}", e),
+            ParseError::UnexpectedToken { expected, found, .. } =>
+                write!(f, "expected {}, found {}", expected, found),
+            ParseError::UnexpectedEof { expected, .. } =>
+                write!(f, "expected {}, found end of input", expected),
+            ParseError::Unclosed { expected, found, opener, .. } =>
+                write!(f, "expected {} to close {}, found {}", expected, opener, found),
+            ParseError::InvalidPattern(_) => write!(f, "expression is not a valid pattern"),
+            ParseError::InvalidPrecedence(_) => write!(f, "precedence must be a whole number"),
+            ParseError::NestingTooDeep(_) => write!(f, "expression is nested too deeply"),
+            ParseError::SyntaxArity { name, expected, found, .. } =>
+                write!(f, "syntax `{}` takes {} arguments, given {}", name, expected, found),
+            ParseError::ReservedWord { word, expected, reserved, .. } => {
+                write!(f, "expected {}, found `{}`, which is ", expected, word)?;
+                match reserved {
+                    Reserved::Always => write!(f, "a reserved word"),
+                    Reserved::Classic => write!(f, "reserved in the classic dialect"),
+                    Reserved::Macros => write!(f, "reserved when macros are allowed"),
+                }
+            }
+            ParseError::Cancelled(_) => write!(f, "parsing was cancelled"),
+            ParseError::Unsupported(what, _) => write!(f, "{} are not supported by miranda-lite", what),
+        }
+    }
+}
+
+// Problems the parser recovered from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseWarning {
+    // A declaration ended at a newline before the keyword starting the next one.
+    // `insert_at` is the empty position after the declaration's last token
+    MissingSemicolon { next: &'static str, insert_at: Pos },
+}
+
+impl ParseWarning {
+    pub fn pos(&self) -> &Pos {
+        match self {
+            ParseWarning::MissingSemicolon { insert_at, .. } => insert_at,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseWarning::MissingSemicolon { .. } => "W0201",
+        }
+    }
+
+    // The text to insert at pos() to fix the source
+    pub fn fix(&self) -> &'static str {
+        match self {
+            ParseWarning::MissingSemicolon { .. } => ";",
+        }
+    }
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseWarning::MissingSemicolon { next, .. } =>
+                write!(f, "missing `;` before {}, assumed at the end of the line", next),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+use crate::syntax::token::Pos;
+
+// What a name in a syntax body stands for
+#[derive(Clone)]
+enum Binding {
+    Arg(Expr),
+    Renamed(String),
+}
+
+// Replaces an application of a syntax definition with its body. The arguments keep
+// their own positions, everything that came from the body is placed at the
+// application. Names the body binds are renamed where they would capture a name used
+// in an argument, so `syntax twice e <= let x == e in x + x` can still be given `x`.
+// Names the body uses freely mean what they meant where it was defined, which is at
+// the top level. They are marked here and resolved once the declaration the
+// application is in has been parsed, see resolve
+pub(super) fn expand(def: &SyntaxDef, args: Vec<Expr>, pos: Pos, fresh: &mut usize) -> Expr {
+    let mut used = BTreeSet::new();
+    args.iter().for_each(|arg| names(arg, &mut used));
+    let env = def.params.iter().map(|param| param.name.clone()).zip(args.into_iter().map(Binding::Arg)).collect();
+    Expander { pos, used, fresh }.expr(&def.body, &env)
+}
+
+struct Expander<'a> {
+    pos: Pos,
+    used: BTreeSet<String>,
+    fresh: &'a mut usize,
+}
+
+impl Expander<'_> {
+    fn expr(&mut self, e: &Expr, env: &BTreeMap<String, Binding>) -> Expr {
+        let kind = match &e.kind {
+            ExprKind::Var(name) => match env.get(name) {
+                Some(Binding::Arg(arg)) => return arg.clone(),
+                Some(Binding::Renamed(name)) => ExprKind::Var(name.clone()),
+                None => ExprKind::Var(global(name)),
+            },
+            ExprKind::Int(n) => ExprKind::Int(*n),
+            ExprKind::BigInt(digits) => ExprKind::BigInt(digits.clone()),
+            ExprKind::Num(n) => ExprKind::Num(*n),
+            ExprKind::Str(s) => ExprKind::Str(s.clone()),
+            ExprKind::Tuple(items) => ExprKind::Tuple(items.iter().map(|item| self.expr(item, env)).collect()),
+            ExprKind::List(items) => ExprKind::List(items.iter().map(|item| self.expr(item, env)).collect()),
+            ExprKind::Apply(f, arg) => ExprKind::Apply(Box::new(self.expr(f, env)), Box::new(self.expr(arg, env))),
+            ExprKind::BinOp(op, l, r) => {
+                let name = match env.get(&op.name) {
+                    Some(Binding::Renamed(name)) => name.clone(),
+                    _ => global(&op.name),
+                };
+                let op = Ident { name, pos: self.pos.clone() };
+                ExprKind::BinOp(op, Box::new(self.expr(l, env)), Box::new(self.expr(r, env)))
+            }
+            ExprKind::If(c, t, f) => ExprKind::If(
+                Box::new(self.expr(c, env)),
+                Box::new(self.expr(t, env)),
+                Box::new(self.expr(f, env)),
+            ),
+            ExprKind::Lambda(rules) => ExprKind::Lambda(rules.iter()
+                .map(|rule| {
+                    let mut inner = env.clone();
+                    let pattern = self.pattern(&rule.pattern, &mut inner);
+                    Rule { pattern, body: self.expr(&rule.body, &inner), pos: self.pos.clone() }
+                })
+                .collect()),
+            ExprKind::Let(l) => {
+                let mut inner = env.clone();
+                let pattern = self.pattern(&l.pattern, &mut inner);
+                let value = self.expr(&l.value, if l.kind.is_rec() { &inner } else { env });
+                let body = self.expr(&l.body, &inner);
+                ExprKind::Let(Box::new(Let { kind: l.kind, pattern, value, body }))
+            }
+        };
+        Expr { kind, pos: self.pos.clone() }
+    }
+
+    // Binds the pattern's variables in env, shadowing any parameter of the same name
+    fn pattern(&mut self, p: &Pattern, env: &mut BTreeMap<String, Binding>) -> Pattern {
+        let kind = match &p.kind {
+            PatternKind::Var(name) => PatternKind::Var(self.bind(name, env)),
+            PatternKind::Wildcard => PatternKind::Wildcard,
+            PatternKind::As(name, pattern) => {
+                let name = Ident { name: self.bind(&name.name, env), pos: self.pos.clone() };
+                PatternKind::As(name, Box::new(self.pattern(pattern, env)))
+            }
+            PatternKind::Int(n) => PatternKind::Int(*n),
+            PatternKind::BigInt(digits) => PatternKind::BigInt(digits.clone()),
+            PatternKind::Num(n) => PatternKind::Num(*n),
+            PatternKind::Str(s) => PatternKind::Str(s.clone()),
+            PatternKind::Tuple(items) => PatternKind::Tuple(items.iter().map(|item| self.pattern(item, env)).collect()),
+            PatternKind::List(items) => PatternKind::List(items.iter().map(|item| self.pattern(item, env)).collect()),
+            PatternKind::Construct(name, args) => PatternKind::Construct(
+                Ident { name: name.name.clone(), pos: self.pos.clone() },
+                args.iter().map(|arg| self.pattern(arg, env)).collect(),
+            ),
+            PatternKind::BinOp(op, l, r) => PatternKind::BinOp(
+                Ident { name: op.name.clone(), pos: self.pos.clone() },
+                Box::new(self.pattern(l, env)),
+                Box::new(self.pattern(r, env)),
+            ),
+        };
+        Pattern { kind, pos: self.pos.clone() }
+    }
+
+    // The name a variable the body binds is given in the expansion
+    fn bind(&mut self, name: &str, env: &mut BTreeMap<String, Binding>) -> String {
+        let renamed = match self.used.contains(name) {
+            true => fresh_name(name, self.fresh),
+            false => name.to_owned(),
+        };
+        env.insert(name.to_owned(), Binding::Renamed(renamed.clone()));
+        renamed
+    }
+}
+
+fn fresh_name(name: &str, fresh: &mut usize) -> String {
+    *fresh += 1;
+    format!("{}#{}", name, fresh)
+}
+
+// A name the body of a syntax definition uses freely, until resolve sees whether the
+// application is somewhere that binds it
+fn global(name: &str) -> String {
+    format!("{}#", name)
+}
+
+// Points the names expansions marked at the top level. Anything in the declaration
+// that binds one of them, other than a constructor, is renamed along with its uses,
+// so `let x == 100 in twice 1` leaves the `x` that `twice` adds meaning the global one
+pub(super) fn resolve(decl: &mut Decl, constructors: &BTreeSet<String>, fresh: &mut usize) {
+    let mut globals = BTreeSet::new();
+    match &decl.kind {
+        DeclKind::Equation(eq) => eq.guard.iter().chain([&eq.body]).for_each(|e| marked(e, &mut globals)),
+        DeclKind::Syntax(def) => marked(&def.body, &mut globals),
+        DeclKind::Write(e) | DeclKind::Expr(e) => marked(e, &mut globals),
+        _ => {}
+    }
+    if globals.is_empty() {
+        return;
+    }
+
+    let mut resolver = Resolver { globals, constructors, fresh };
+    let mut env = BTreeMap::new();
+    match &mut decl.kind {
+        DeclKind::Equation(eq) => {
+            eq.args.iter_mut().for_each(|arg| resolver.pattern(arg, &mut env));
+            eq.guard.iter_mut().chain([&mut eq.body]).for_each(|e| resolver.expr(e, &env));
+        }
+        DeclKind::Syntax(def) => {
+            for param in &mut def.params {
+                param.name = resolver.bind(&param.name, &mut env);
+            }
+            resolver.expr(&mut def.body, &env);
+        }
+        DeclKind::Write(e) | DeclKind::Expr(e) => resolver.expr(e, &env),
+        _ => {}
+    }
+}
+
+struct Resolver<'a> {
+    globals: BTreeSet<String>,
+    constructors: &'a BTreeSet<String>,
+    fresh: &'a mut usize,
+}
+
+impl Resolver<'_> {
+    fn expr(&mut self, e: &mut Expr, env: &BTreeMap<String, String>) {
+        match &mut e.kind {
+            ExprKind::Var(name) => *name = self.name(name, env),
+            ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) => {}
+            ExprKind::Tuple(items) | ExprKind::List(items) => items.iter_mut().for_each(|item| self.expr(item, env)),
+            ExprKind::Apply(f, arg) => {
+                self.expr(f, env);
+                self.expr(arg, env);
+            }
+            ExprKind::BinOp(op, l, r) => {
+                op.name = self.name(&op.name, env);
+                self.expr(l, env);
+                self.expr(r, env);
+            }
+            ExprKind::If(c, t, f) => [c, t, f].into_iter().for_each(|e| self.expr(e, env)),
+            ExprKind::Lambda(rules) => rules.iter_mut().for_each(|rule| {
+                let mut inner = env.clone();
+                self.pattern(&mut rule.pattern, &mut inner);
+                self.expr(&mut rule.body, &inner);
+            }),
+            ExprKind::Let(l) => {
+                let mut inner = env.clone();
+                self.pattern(&mut l.pattern, &mut inner);
+                self.expr(&mut l.value, if l.kind.is_rec() { &inner } else { env });
+                self.expr(&mut l.body, &inner);
+            }
+        }
+    }
+
+    fn name(&self, name: &str, env: &BTreeMap<String, String>) -> String {
+        match name.strip_suffix('#') {
+            Some(global) => global.to_owned(),
+            None => env.get(name).cloned().unwrap_or_else(|| name.to_owned()),
+        }
+    }
+
+    fn pattern(&mut self, p: &mut Pattern, env: &mut BTreeMap<String, String>) {
+        match &mut p.kind {
+            PatternKind::Var(name) if !self.constructors.contains(name.as_str()) => *name = self.bind(name, env),
+            PatternKind::As(name, pattern) => {
+                name.name = self.bind(&name.name, env);
+                self.pattern(pattern, env);
+            }
+            PatternKind::Tuple(items) | PatternKind::List(items) | PatternKind::Construct(_, items) => {
+                items.iter_mut().for_each(|item| self.pattern(item, env));
+            }
+            PatternKind::BinOp(_, l, r) => {
+                self.pattern(l, env);
+                self.pattern(r, env);
+            }
+            _ => {}
+        }
+    }
+
+    fn bind(&mut self, name: &str, env: &mut BTreeMap<String, String>) -> String {
+        if !self.globals.contains(name) {
+            env.remove(name);
+            return name.to_owned();
+        }
+        let renamed = fresh_name(name, self.fresh);
+        env.insert(name.to_owned(), renamed.clone());
+        renamed
+    }
+}
+
+// The globals an expression's expansions refer to
+fn marked(e: &Expr, out: &mut BTreeSet<String>) {
+    let mut mark = |name: &str| {
+        if let Some(global) = name.strip_suffix('#') {
+            out.insert(global.to_owned());
+        }
+    };
+    match &e.kind {
+        ExprKind::Var(name) => mark(name),
+        ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) => {}
+        ExprKind::Tuple(items) | ExprKind::List(items) => items.iter().for_each(|item| marked(item, out)),
+        ExprKind::Apply(f, arg) => {
+            marked(f, out);
+            marked(arg, out);
+        }
+        ExprKind::BinOp(op, l, r) => {
+            mark(&op.name);
+            marked(l, out);
+            marked(r, out);
+        }
+        ExprKind::If(c, t, f) => [c, t, f].into_iter().for_each(|e| marked(e, out)),
+        ExprKind::Lambda(rules) => rules.iter().for_each(|rule| marked(&rule.body, out)),
+        ExprKind::Let(l) => {
+            marked(&l.value, out);
+            marked(&l.body, out);
+        }
+    }
+}
+
+// Every name an expression mentions, bound or not
+fn names(e: &Expr, out: &mut BTreeSet<String>) {
+    match &e.kind {
+        ExprKind::Var(name) => {
+            out.insert(name.clone());
+        }
+        ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) => {}
+        ExprKind::Tuple(items) | ExprKind::List(items) => items.iter().for_each(|item| names(item, out)),
+        ExprKind::Apply(f, arg) => {
+            names(f, out);
+            names(arg, out);
+        }
+        ExprKind::BinOp(op, l, r) => {
+            out.insert(op.name.clone());
+            names(l, out);
+            names(r, out);
+        }
+        ExprKind::If(c, t, f) => [c, t, f].into_iter().for_each(|e| names(e, out)),
+        ExprKind::Lambda(rules) => rules.iter().for_each(|rule| names(&rule.body, out)),
+        ExprKind::Let(l) => {
+            names(&l.value, out);
+            names(&l.body, out);
+        }
+    }
+}
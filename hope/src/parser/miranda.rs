@@ -0,0 +1,853 @@
+use alloc::collections::BTreeSet;
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+use crate::syntax::token::{LexingError, Pos};
+use super::{desugar, to_pattern, unwind_apply, PResult, ParseError};
+
+// `--from miranda-lite`, a subset of Miranda scripts read into the same syntax tree as
+// Hope, keeping the positions in the script, so they are checked and run like any
+// other program. What it reads:
+//
+//     f, g :: num -> [*] -> (*, bool)       signatures, with *, ** ... for alpha, beta ...
+//     tree * ::= Leaf | Node (tree *) * (tree *)
+//     string == [char]
+//     f 0 = 1
+//     f n = n * f (n - 1), if n > 0         guards, more of them on lines starting `=`
+//         = 0, otherwise
+//     g x = y + y where y = x * x           local values and functions, laid out by the
+//                                           offside rule
+//     main = f 5                            the script's value
+//
+// with `:`, `++`, `\/`, `&`, `~`, `#`, comparisons, `+ - * /`, `div` and `mod`,
+// tuples, lists, list comprehensions, strings, characters, True and False. Every
+// definition but `main` needs a signature, as Hope does. Ranges, sections, `!`, `.`,
+// `^` and `--` are reported as unsupported
+
+// Symbols as they are written and as errors show them, the longer of two that start
+// alike first
+const SYMBOLS: [(&str, &str); 33] = [
+    ("::=", "`::=`"), ("::", "`::`"), ("==", "`==`"), ("~=", "`~=`"), ("<=", "`<=`"), (">=", "`>=`"),
+    ("<-", "`<-`"), ("->", "`->`"), ("++", "`++`"), ("--", "`--`"), ("..", "`..`"), ("\\/", "`\\/`"),
+    ("=", "`=`"), (",", "`,`"), (";", "`;`"), ("(", "`(`"), (")", "`)`"), ("[", "`[`"), ("]", "`]`"),
+    (":", "`:`"), ("+", "`+`"), ("-", "`-`"), ("*", "`*`"), ("/", "`/`"), ("&", "`&`"), ("~", "`~`"),
+    ("<", "`<`"), (">", "`>`"), ("#", "`#`"), (".", "`.`"), ("|", "`|`"), ("!", "`!`"), ("^", "`^`"),
+];
+
+// The type variables `*`, `**` and so on stand for
+const TYPEVARS: [&str; 6] = ["alpha", "beta", "gamma", "delta", "epsilon", "zeta"];
+
+const KEYWORDS: [&str; 5] = ["where", "if", "otherwise", "div", "mod"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Name(String),
+    Int(i64),
+    BigInt(String),
+    Num(f64),
+    Str(String),
+    Char(char),
+    // An index into SYMBOLS
+    Sym(usize),
+    // A run of more than one `*`, a type variable
+    Stars(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    pos: Pos,
+    // Whether it is the first on its line, which the offside rule looks at
+    first: bool,
+}
+
+fn sym(s: &str) -> Tok {
+    Tok::Sym(SYMBOLS.iter().position(|(written, _)| *written == s).expect("a symbol"))
+}
+
+fn lex(source: &str) -> PResult<Vec<Token>> {
+    let bytes = source.as_bytes();
+    let (mut i, mut line, mut line_start, mut first) = (0, 1, 0, true);
+    let mut tokens = Vec::new();
+    while i < bytes.len() {
+        let c = bytes[i];
+        let pos = |end: usize| Pos { line, column: i - line_start + 1, range: i..end };
+        if c == b'\n' {
+            (i, line, line_start, first) = (i + 1, line + 1, i + 1, true);
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if source[i..].starts_with("||") {
+            i += source[i..].find('\n').unwrap_or(source.len() - i);
+            continue;
+        }
+        let (tok, end) = if c.is_ascii_alphabetic() || c == b'_' {
+            let end = i + source[i..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '\'')).unwrap_or(source.len() - i);
+            (Tok::Name(source[i..end].to_owned()), end)
+        } else if c.is_ascii_digit() {
+            let mut end = i + source[i..].find(|c: char| !c.is_ascii_digit()).unwrap_or(source.len() - i);
+            let fraction = bytes.get(end) == Some(&b'.') && bytes.get(end + 1).is_some_and(u8::is_ascii_digit);
+            if fraction {
+                end += 1 + source[end + 1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(source.len() - end - 1);
+            }
+            if bytes.get(end).is_some_and(|c| c.is_ascii_alphabetic()) {
+                return Err(ParseError::Lexing(LexingError::InvalidNumber(source[i..=end].to_owned(), i..end + 1), pos(end + 1)));
+            }
+            let text = &source[i..end];
+            let tok = match text.parse::<i64>() {
+                _ if fraction => Tok::Num(text.parse().expect("digits with a fraction")),
+                Ok(n) => Tok::Int(n),
+                Err(_) => Tok::BigInt(text.to_owned()),
+            };
+            (tok, end)
+        } else if c == b'"' || c == b'\'' {
+            let (text, end) = quoted(source, i).ok_or_else(|| ParseError::Lexing(LexingError::UnterminatedString, pos(i + 1)))?;
+            let tok = match (c, text.chars().next()) {
+                (b'"', _) => Tok::Str(text),
+                (_, Some(ch)) if text.chars().count() == 1 => Tok::Char(ch),
+                _ => return Err(ParseError::Lexing(LexingError::InvalidEscape(source[i..end].to_owned(), i..end), pos(end))),
+            };
+            (tok, end)
+        } else if c == b'*' && bytes.get(i + 1) == Some(&b'*') {
+            let stars = source[i..].find(|c: char| c != '*').unwrap_or(source.len() - i);
+            (Tok::Stars(stars), i + stars)
+        } else {
+            match SYMBOLS.iter().position(|(written, _)| source[i..].starts_with(written)) {
+                Some(index) => (Tok::Sym(index), i + SYMBOLS[index].0.len()),
+                None => {
+                    let end = i + source[i..].chars().next().map_or(1, char::len_utf8);
+                    return Err(ParseError::Lexing(LexingError::UnrecognisedCharacter, pos(end)));
+                }
+            }
+        };
+        tokens.push(Token { tok, pos: pos(end), first });
+        (i, first) = (end, false);
+    }
+    Ok(tokens)
+}
+
+// A string or character literal starting at i, with its escapes, and where it ends
+fn quoted(source: &str, i: usize) -> Option<(String, usize)> {
+    let quote = source[i..].chars().next()?;
+    let mut text = String::new();
+    let mut chars = source[i + 1..].char_indices();
+    while let Some((at, c)) = chars.next() {
+        match c {
+            '\n' => return None,
+            '\\' => text.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                other => other,
+            }),
+            c if c == quote => return Some((text, i + 1 + at + 1)),
+            c => text.push(c),
+        }
+    }
+    None
+}
+
+// A guard, or `otherwise`, which has none
+type Alternative = (Expr, Option<Expr>);
+// A local definition and whether it is of a function
+type Local = (Pattern, Expr, bool);
+// Those of a local function, with the arguments of each
+type Equations = Vec<(Vec<Pattern>, Expr)>;
+
+struct Reader {
+    tokens: Vec<Token>,
+    next: usize,
+    // The token starting the innermost definition being read and its column. A token
+    // first on its line that far left or further ends the definition
+    block: (usize, usize),
+    eof: Pos,
+    last: Pos,
+    // The type variables used, which are declared ahead of everything
+    typevars: BTreeSet<usize>,
+    // Those signed, of the functions
+    signed: BTreeSet<String>,
+    constructors: BTreeSet<String>,
+    fresh: usize,
+}
+
+pub fn parse_script(source: &str) -> PResult<Program> {
+    let eof = Pos { line: source.lines().count().max(1), column: 1, range: source.len()..source.len() };
+    let mut reader = Reader {
+        tokens: lex(source)?,
+        next: 0,
+        block: (0, 0),
+        last: eof.clone(),
+        eof,
+        typevars: BTreeSet::new(),
+        signed: BTreeSet::new(),
+        constructors: ["true", "false", "nil"].map(str::to_owned).into(),
+        fresh: 0,
+    };
+    let mut decls = Vec::new();
+    while reader.next < reader.tokens.len() {
+        let column = reader.tokens[reader.next].pos.column;
+        reader.block = (reader.next, column);
+        decls.extend(reader.definition()?);
+        if let Some(token) = reader.peek() {
+            return Err(ParseError::UnexpectedToken { expected: "a new line to end the definition", found: found(&token.tok), pos: token.pos.clone() });
+        }
+    }
+    if !reader.typevars.is_empty() {
+        let pos = Pos { line: 1, column: 1, range: 0..0 };
+        let names = reader.typevars.iter().map(|&n| Ident { name: typevar(n), pos: pos.clone() }).collect();
+        decls.insert(0, Decl { kind: DeclKind::TypeVar(names), pos });
+    }
+    Ok(Program { decls })
+}
+
+fn typevar(stars: usize) -> String {
+    TYPEVARS.get(stars - 1).map_or_else(|| format!("alpha{}", stars), |name| (*name).to_owned())
+}
+
+fn found(tok: &Tok) -> &'static str {
+    match tok {
+        Tok::Name(name) if KEYWORDS.contains(&name.as_str()) => "a keyword",
+        Tok::Name(_) => "a name",
+        Tok::Int(_) | Tok::BigInt(_) | Tok::Num(_) => "a number",
+        Tok::Str(_) => "a string",
+        Tok::Char(_) => "a character",
+        Tok::Sym(index) => SYMBOLS[*index].1,
+        Tok::Stars(_) => "a type variable",
+    }
+}
+
+fn is_constructor(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+fn ident(name: &str, pos: &Pos) -> Ident {
+    Ident { name: name.to_owned(), pos: pos.clone() }
+}
+
+fn at(kind: ExprKind, pos: &Pos) -> Expr {
+    Expr { kind, pos: pos.clone() }
+}
+
+fn binop(op: &str, op_pos: &Pos, l: Expr, r: Expr) -> Expr {
+    let pos = l.pos.to(&r.pos);
+    Expr { kind: ExprKind::BinOp(ident(op, op_pos), Box::new(l), Box::new(r)), pos }
+}
+
+fn apply(fun: Expr, arg: Expr) -> Expr {
+    let pos = fun.pos.to(&arg.pos);
+    Expr { kind: ExprKind::Apply(Box::new(fun), Box::new(arg)), pos }
+}
+
+fn unsupported(what: &'static str, pos: &Pos) -> ParseError {
+    ParseError::Unsupported(what, pos.clone())
+}
+
+impl Reader {
+    // The next token of the definition being read
+    fn peek(&self) -> Option<&Token> {
+        let token = self.tokens.get(self.next)?;
+        let offside = token.first && self.next != self.block.0 && token.pos.column <= self.block.1;
+        (!offside).then_some(token)
+    }
+
+    fn peek_tok(&self) -> Option<&Tok> {
+        self.peek().map(|token| &token.tok)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.peek()?.clone();
+        self.next += 1;
+        self.last = token.pos.clone();
+        Some(token)
+    }
+
+    fn at_sym(&self, s: &str) -> bool {
+        self.peek_tok() == Some(&sym(s))
+    }
+
+    fn at_name(&self, name: &str) -> bool {
+        matches!(self.peek_tok(), Some(Tok::Name(n)) if n == name)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let at = self.at_sym(s);
+        if at {
+            self.advance();
+        }
+        at
+    }
+
+    fn unexpected(&self, expected: &'static str) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::UnexpectedToken { expected, found: found(&token.tok), pos: token.pos.clone() },
+            None => ParseError::UnexpectedEof { expected, pos: self.tokens.get(self.next).map_or_else(|| self.eof.clone(), |t| t.pos.clone()) },
+        }
+    }
+
+    fn expect(&mut self, s: &'static str, expected: &'static str) -> PResult<Pos> {
+        if self.at_sym(s) {
+            Ok(self.advance().expect("the symbol").pos)
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    fn name(&mut self, expected: &'static str) -> PResult<Ident> {
+        match self.peek().cloned() {
+            Some(Token { tok: Tok::Name(name), pos, .. }) if !KEYWORDS.contains(&name.as_str()) => {
+                self.advance();
+                Ok(Ident { name, pos })
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    // What comes after the names at the start of the definition says what it is
+    fn definition(&mut self) -> PResult<Vec<Decl>> {
+        let start = self.peek().expect("a definition").pos.clone();
+        let mut ahead = self.next;
+        while let Some(Tok::Name(_) | Tok::Stars(_)) = self.tokens.get(ahead).map(|t| &t.tok) {
+            ahead += 1;
+            if self.tokens.get(ahead).map(|t| &t.tok) == Some(&sym(",")) || self.tokens.get(ahead).map(|t| &t.tok) == Some(&sym("*")) {
+                ahead += 1;
+            }
+        }
+        let kind = match self.tokens.get(ahead).map(|t| &t.tok) {
+            Some(tok) if *tok == sym("::") => self.signature()?,
+            Some(tok) if *tok == sym("::=") => self.data()?,
+            Some(tok) if *tok == sym("==") => self.synonym()?,
+            _ => return self.equations(&start),
+        };
+        Ok(vec![Decl { kind, pos: start.to(&self.last) }])
+    }
+
+    fn signature(&mut self) -> PResult<DeclKind> {
+        let mut names = vec![self.name("a name")?];
+        while self.eat(",") {
+            names.push(self.name("a name")?);
+        }
+        self.expect("::", "`::`")?;
+        self.signed.extend(names.iter().map(|name| name.name.clone()));
+        Ok(DeclKind::Dec { names, ty: self.ty()? })
+    }
+
+    fn head(&mut self) -> PResult<TypeHead> {
+        let name = self.name("the name of a type")?;
+        let mut params = Vec::new();
+        while let Some(n) = self.stars() {
+            let pos = self.advance().expect("the stars").pos;
+            self.typevars.insert(n);
+            params.push(Ident { name: typevar(n), pos });
+        }
+        Ok(TypeHead { name, params, infix: false })
+    }
+
+    fn stars(&self) -> Option<usize> {
+        match self.peek_tok() {
+            Some(Tok::Stars(n)) => Some(*n),
+            Some(tok) if *tok == sym("*") => Some(1),
+            _ => None,
+        }
+    }
+
+    fn data(&mut self) -> PResult<DeclKind> {
+        let head = self.head()?;
+        self.expect("::=", "`::=`")?;
+        let mut constructors = Vec::new();
+        loop {
+            let name = self.name("a constructor")?;
+            if !is_constructor(&name.name) {
+                return Err(ParseError::UnexpectedToken { expected: "a constructor, which starts with a capital", found: "a name", pos: name.pos });
+            }
+            let mut args = Vec::new();
+            while self.at_type_atom() {
+                args.push(self.type_atom()?);
+            }
+            self.constructors.insert(name.name.clone());
+            let pos = name.pos.to(&self.last);
+            constructors.push(Constructor { name, args, infix: false, pos });
+            if !self.eat("|") {
+                return Ok(DeclKind::Data { head, constructors });
+            }
+        }
+    }
+
+    fn synonym(&mut self) -> PResult<DeclKind> {
+        let head = self.head()?;
+        self.expect("==", "`==`")?;
+        Ok(DeclKind::Type { head, body: self.ty()? })
+    }
+
+    // Types
+
+    fn ty(&mut self) -> PResult<TypeExpr> {
+        let from = match self.peek_tok() {
+            Some(Tok::Name(_)) => {
+                let name = self.name("a type")?;
+                let mut args = Vec::new();
+                while self.at_type_atom() {
+                    args.push(self.type_atom()?);
+                }
+                let pos = name.pos.to(&self.last);
+                TypeExpr { name, args, pos }
+            }
+            _ => self.type_atom()?,
+        };
+        if !self.at_sym("->") {
+            return Ok(from);
+        }
+        let arrow = self.advance().expect("the arrow").pos;
+        let to = self.ty()?;
+        let pos = from.pos.to(&to.pos);
+        Ok(TypeExpr { name: ident("->", &arrow), args: vec![from, to], pos })
+    }
+
+    fn at_type_atom(&self) -> bool {
+        matches!(self.peek_tok(), Some(Tok::Name(_) | Tok::Stars(_))) || ["*", "(", "["].iter().any(|s| self.at_sym(s))
+    }
+
+    fn type_atom(&mut self) -> PResult<TypeExpr> {
+        if let Some(n) = self.stars() {
+            let pos = self.advance().expect("the stars").pos;
+            self.typevars.insert(n);
+            return Ok(TypeExpr { name: Ident { name: typevar(n), pos: pos.clone() }, args: Vec::new(), pos });
+        }
+        if self.at_sym("[") {
+            let start = self.advance().expect("the bracket").pos;
+            let elem = self.ty()?;
+            let end = self.expect("]", "`]`")?;
+            return Ok(TypeExpr { name: ident("list", &start), args: vec![elem], pos: start.to(&end) });
+        }
+        if self.at_sym("(") {
+            let start = self.advance().expect("the bracket").pos;
+            let mut items = vec![self.ty()?];
+            while self.eat(",") {
+                items.push(self.ty()?);
+            }
+            let end = self.expect(")", "`)`")?;
+            // A tuple is a pair of its first and the tuple of the rest
+            let last = items.pop().expect("a type");
+            let mut ty = items.into_iter().rev().fold(last, |rest, item| {
+                let pos = item.pos.to(&rest.pos);
+                TypeExpr { name: ident("#", &pos), args: vec![item, rest], pos }
+            });
+            ty.pos = start.to(&end);
+            return Ok(ty);
+        }
+        let name = self.name("a type")?;
+        let pos = name.pos.clone();
+        Ok(TypeExpr { name, args: Vec::new(), pos })
+    }
+
+    // Equations
+
+    fn equations(&mut self, start: &Pos) -> PResult<Vec<Decl>> {
+        let lhs = self.application()?;
+        self.expect("=", "`=`")?;
+        let (head, args) = unwind_apply(lhs);
+        let ExprKind::Var(name) = head.kind else { return Err(ParseError::InvalidPattern(head.pos)) };
+        let args: Vec<Pattern> = args.into_iter().map(to_pattern).collect::<PResult<_>>()?;
+        let (alternatives, locals) = self.rhs()?;
+        let pos = start.to(&self.last);
+        let name = Ident { name, pos: head.pos };
+
+        // Unsigned, `main` is the value of the script
+        if name.name == "main" && args.is_empty() && !self.signed.contains("main") {
+            let body = self.body(alternatives, locals, &pos)?;
+            return Ok(vec![Decl { kind: DeclKind::Expr(body), pos }]);
+        }
+        let mut decls = Vec::new();
+        if locals.is_empty() {
+            for (body, guard) in alternatives {
+                let eq = Equation { name: name.clone(), args: args.clone(), guard, body, infix: false };
+                decls.push(Decl { kind: DeclKind::Equation(eq), pos: pos.clone() });
+            }
+        } else {
+            // The guards can use the local definitions, so they become conditionals
+            let body = self.body(alternatives, locals, &pos)?;
+            decls.push(Decl { kind: DeclKind::Equation(Equation { name: name.clone(), args, guard: None, body, infix: false }), pos: pos.clone() });
+        }
+        if name.name == "main" && decls.iter().all(|decl| matches!(&decl.kind, DeclKind::Equation(eq) if eq.args.is_empty())) {
+            decls.push(Decl { kind: DeclKind::Expr(at(ExprKind::Var(name.name), &name.pos)), pos });
+        }
+        Ok(decls)
+    }
+
+    // The alternatives of a right hand side and the definitions after its `where`
+    fn rhs(&mut self) -> PResult<(Vec<Alternative>, Vec<Local>)> {
+        let mut alternatives = Vec::new();
+        loop {
+            let body = self.expr()?;
+            let mut guard = None;
+            if self.eat(",") {
+                if self.at_name("otherwise") {
+                    self.advance();
+                } else {
+                    if self.at_name("if") {
+                        self.advance();
+                    }
+                    guard = Some(self.expr()?);
+                }
+            }
+            alternatives.push((body, guard));
+            if !self.at_sym("=") {
+                break;
+            }
+            self.advance();
+        }
+        let mut locals = Vec::new();
+        if self.at_name("where") {
+            self.advance();
+            let outer = self.block;
+            let column = self.peek().ok_or_else(|| self.unexpected("a definition"))?.pos.column;
+            let mut equations: Vec<(Pattern, Vec<Pattern>, Expr)> = Vec::new();
+            loop {
+                self.block = (self.next, column);
+                equations.push(self.local()?);
+                self.block = outer;
+                match self.peek() {
+                    Some(token) if token.first && token.pos.column == column => {}
+                    _ => break,
+                }
+            }
+            // The equations of a function one after another make one definition of it
+            let mut functions: Vec<(Pattern, Equations)> = Vec::new();
+            for (head, args, body) in equations {
+                match functions.last_mut() {
+                    Some((last, rules)) if !args.is_empty() && last.kind == head.kind && rules[0].0.len() == args.len() => rules.push((args, body)),
+                    _ => functions.push((head, vec![(args, body)])),
+                }
+            }
+            for (head, mut rules) in functions {
+                if rules[0].0.is_empty() {
+                    locals.push((head, rules.pop().expect("a definition").1, false));
+                } else {
+                    let lambda = self.function(rules);
+                    locals.push((head, lambda, true));
+                }
+            }
+        }
+        Ok((alternatives, locals))
+    }
+
+    // The name and arguments of a local function, or the pattern a value is bound to
+    // with none, and what it is defined as
+    fn local(&mut self) -> PResult<(Pattern, Vec<Pattern>, Expr)> {
+        let start = self.peek().ok_or_else(|| self.unexpected("a definition"))?.pos.clone();
+        let lhs = self.application()?;
+        self.expect("=", "`=`")?;
+        let (alternatives, locals) = self.rhs()?;
+        let pos = start.to(&self.last);
+        let body = self.body(alternatives, locals, &pos)?;
+        let (head, args) = match lhs.kind {
+            ExprKind::Apply(..) => unwind_apply(lhs),
+            _ => return Ok((to_pattern(lhs)?, Vec::new(), body)),
+        };
+        let ExprKind::Var(name) = head.kind.clone() else { return Err(ParseError::InvalidPattern(head.pos)) };
+        if is_constructor(&name) {
+            return Ok((to_pattern(apply_all(head, args))?, Vec::new(), body));
+        }
+        let args = args.into_iter().map(to_pattern).collect::<PResult<_>>()?;
+        Ok((Pattern { kind: PatternKind::Var(name), pos: head.pos }, args, body))
+    }
+
+    // A lambda for each argument. With more than one equation, those of a function of
+    // one argument are the rules of its lambda, and those of more match a tuple of them
+    fn function(&mut self, mut rules: Equations) -> Expr {
+        let lambda = |pattern: Pattern, body: Expr| {
+            let pos = pattern.pos.to(&body.pos);
+            Expr { kind: ExprKind::Lambda(vec![Rule { pattern, body, pos: pos.clone() }]), pos }
+        };
+        if rules.len() == 1 {
+            let (args, body) = rules.pop().expect("an equation");
+            return args.into_iter().rev().fold(body, |body, arg| lambda(arg, body));
+        }
+        let pos = rules[0].0[0].pos.to(&rules[rules.len() - 1].1.pos);
+        let arity = rules[0].0.len();
+        let rules: Vec<Rule> = rules.into_iter().map(|(mut args, body)| {
+            let pattern = match arity {
+                1 => args.pop().expect("an argument"),
+                _ => Pattern { pos: args[0].pos.to(&args[arity - 1].pos), kind: PatternKind::Tuple(args) },
+            };
+            Rule { pos: pattern.pos.to(&body.pos), pattern, body }
+        }).collect();
+        let matching = Expr { kind: ExprKind::Lambda(rules), pos: pos.clone() };
+        if arity == 1 {
+            return matching;
+        }
+        let names: Vec<String> = (0..arity).map(|_| {
+            self.fresh += 1;
+            format!("a#{}", self.fresh)
+        }).collect();
+        let var = |name: &String| at(ExprKind::Var(name.clone()), &pos);
+        let tuple = at(ExprKind::Tuple(names.iter().map(var).collect()), &pos);
+        names.iter().rev().fold(apply(matching, tuple), |body, name| lambda(Pattern { kind: PatternKind::Var(name.clone()), pos: pos.clone() }, body))
+    }
+
+    // The alternatives as conditionals in the local definitions, the last one unguarded
+    fn body(&mut self, mut alternatives: Vec<Alternative>, locals: Vec<Local>, pos: &Pos) -> PResult<Expr> {
+        let (mut body, guard) = alternatives.pop().expect("an alternative");
+        if let Some(guard) = guard {
+            return Err(unsupported("guards without `otherwise` in definitions with `where`", &guard.pos));
+        }
+        while let Some((then, guard)) = alternatives.pop() {
+            let cond = guard.ok_or_else(|| unsupported("alternatives after `otherwise`", &then.pos))?;
+            let pos = cond.pos.to(&body.pos);
+            body = Expr { kind: ExprKind::If(Box::new(cond), Box::new(then), Box::new(body)), pos };
+        }
+        for (pattern, value, function) in locals.into_iter().rev() {
+            let kind = if function { LetKind::WhereRec } else { LetKind::Where };
+            body = Expr { kind: ExprKind::Let(Box::new(Let { kind, pattern, value, body })), pos: pos.clone() };
+        }
+        Ok(body)
+    }
+
+    // Expressions, from the loosest operators to the tightest
+
+    fn expr(&mut self) -> PResult<Expr> {
+        let l = self.disjunction()?;
+        for (written, op) in [(":", "::"), ("++", "<>")] {
+            if self.at_sym(written) {
+                let pos = self.advance().expect("the operator").pos;
+                return Ok(binop(op, &pos, l, self.expr()?));
+            }
+        }
+        if self.at_sym("--") {
+            return Err(unsupported("`--`", &self.peek().expect("the operator").pos));
+        }
+        Ok(l)
+    }
+
+    fn right(&mut self, written: &str, op: &str, next: fn(&mut Self) -> PResult<Expr>, this: fn(&mut Self) -> PResult<Expr>) -> PResult<Expr> {
+        let l = next(self)?;
+        if !self.at_sym(written) {
+            return Ok(l);
+        }
+        let pos = self.advance().expect("the operator").pos;
+        Ok(binop(op, &pos, l, this(self)?))
+    }
+
+    fn disjunction(&mut self) -> PResult<Expr> {
+        self.right("\\/", "or", Self::conjunction, Self::disjunction)
+    }
+
+    fn conjunction(&mut self) -> PResult<Expr> {
+        self.right("&", "and", Self::negation, Self::conjunction)
+    }
+
+    fn negation(&mut self) -> PResult<Expr> {
+        if !self.at_sym("~") {
+            return self.comparison();
+        }
+        let pos = self.advance().expect("the operator").pos;
+        Ok(apply(at(ExprKind::Var("not".to_owned()), &pos), self.negation()?))
+    }
+
+    fn comparison(&mut self) -> PResult<Expr> {
+        let l = self.sum()?;
+        // An `=` at the start of a line starts the next alternative
+        let at_alternative = self.peek().is_some_and(|token| token.first && token.tok == sym("="));
+        for (written, op) in [("=", "="), ("~=", "/="), ("<", "<"), ("<=", "=<"), (">", ">"), (">=", ">=")] {
+            if self.at_sym(written) && !at_alternative {
+                let pos = self.advance().expect("the operator").pos;
+                return Ok(binop(op, &pos, l, self.sum()?));
+            }
+        }
+        Ok(l)
+    }
+
+    fn sum(&mut self) -> PResult<Expr> {
+        let mut l = if self.at_sym("-") {
+            let pos = self.advance().expect("the operator").pos;
+            binop("-", &pos, at(ExprKind::Int(0), &pos), self.product()?)
+        } else {
+            self.product()?
+        };
+        while let Some(op) = ["+", "-"].into_iter().find(|op| self.at_sym(op)) {
+            let pos = self.advance().expect("the operator").pos;
+            l = binop(op, &pos, l, self.product()?);
+        }
+        Ok(l)
+    }
+
+    fn product(&mut self) -> PResult<Expr> {
+        let mut l = self.application()?;
+        loop {
+            let op = match self.peek_tok() {
+                Some(Tok::Name(name)) if name == "div" || name == "mod" => name.clone(),
+                Some(tok) if *tok == sym("*") || *tok == sym("/") => SYMBOLS[match tok { Tok::Sym(i) => *i, _ => unreachable!() }].0.to_owned(),
+                Some(tok) if [".", "!", "^"].iter().any(|s| *tok == sym(s)) => {
+                    return Err(unsupported("`.`, `!` and `^`", &self.peek().expect("the operator").pos));
+                }
+                _ => return Ok(l),
+            };
+            let pos = self.advance().expect("the operator").pos;
+            l = binop(&op, &pos, l, self.application()?);
+        }
+    }
+
+    fn application(&mut self) -> PResult<Expr> {
+        if self.at_sym("#") {
+            let pos = self.advance().expect("the operator").pos;
+            return Ok(apply(at(ExprKind::Var("length".to_owned()), &pos), self.application()?));
+        }
+        let mut fun = self.atom()?;
+        while self.at_atom() {
+            fun = apply(fun, self.atom()?);
+        }
+        Ok(fun)
+    }
+
+    fn at_atom(&self) -> bool {
+        match self.peek_tok() {
+            Some(Tok::Name(name)) => !KEYWORDS.contains(&name.as_str()),
+            Some(Tok::Sym(_)) => self.at_sym("(") || self.at_sym("["),
+            Some(Tok::Stars(_)) => false,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    fn atom(&mut self) -> PResult<Expr> {
+        if !self.at_atom() {
+            return Err(self.unexpected("an expression"));
+        }
+        let Token { tok, pos, .. } = self.advance().expect("an atom");
+        let kind = match tok {
+            Tok::Name(name) if name == "True" || name == "False" => ExprKind::Var(name.to_lowercase()),
+            Tok::Name(name) => ExprKind::Var(name),
+            Tok::Int(n) => ExprKind::Int(n),
+            Tok::BigInt(digits) => ExprKind::BigInt(digits),
+            Tok::Num(n) => ExprKind::Num(n),
+            Tok::Str(s) => ExprKind::Str(s),
+            // Hope has no character literals, only the characters strings are made of
+            Tok::Char(c) => return Ok(apply(at(ExprKind::Var("chr".to_owned()), &pos), at(ExprKind::Int(i64::from(u32::from(c))), &pos))),
+            tok if tok == sym("(") => return self.parenthesised(pos),
+            _ => return self.bracketed(pos),
+        };
+        Ok(Expr { kind, pos })
+    }
+
+    fn parenthesised(&mut self, start: Pos) -> PResult<Expr> {
+        if matches!(self.peek_tok(), Some(Tok::Sym(_))) && !["(", "[", "-", "~", "#"].iter().any(|s| self.at_sym(s)) {
+            return Err(unsupported("sections", &self.peek().expect("the operator").pos));
+        }
+        let mut items = vec![self.expr()?];
+        while self.eat(",") {
+            items.push(self.expr()?);
+        }
+        let end = self.expect(")", "`)`")?;
+        let pos = start.to(&end);
+        Ok(match items.len() {
+            1 => Expr { pos, ..items.pop().expect("an item") },
+            _ => Expr { kind: ExprKind::Tuple(items), pos },
+        })
+    }
+
+    fn bracketed(&mut self, start: Pos) -> PResult<Expr> {
+        let mut items = Vec::new();
+        if !self.at_sym("]") {
+            items.push(self.expr()?);
+            if self.at_sym("..") {
+                return Err(unsupported("ranges", &self.peek().expect("the dots").pos));
+            }
+            if self.eat("|") {
+                return self.comprehension(items.pop().expect("the item"), start);
+            }
+            while self.eat(",") {
+                items.push(self.expr()?);
+            }
+        }
+        let end = self.expect("]", "`]`")?;
+        Ok(Expr { kind: ExprKind::List(items), pos: start.to(&end) })
+    }
+
+    fn comprehension(&mut self, item: Expr, start: Pos) -> PResult<Expr> {
+        let mut qualifiers = Vec::new();
+        loop {
+            let expr = self.expr()?;
+            if self.eat("<-") {
+                qualifiers.push(desugar::Qualifier::Generator(to_pattern(expr)?, self.expr()?));
+            } else {
+                qualifiers.push(desugar::Qualifier::Guard(expr));
+            }
+            if !self.eat(";") {
+                break;
+            }
+        }
+        let end = self.expect("]", "`]`")?;
+        Ok(desugar::comprehension(item, qualifiers, &start.to(&end), &self.constructors, &mut self.fresh))
+    }
+}
+
+fn apply_all(head: Expr, args: impl IntoIterator<Item = Expr>) -> Expr {
+    args.into_iter().fold(head, apply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::build::{self, *};
+
+    #[test]
+    fn should_read_definitions_into_hope_declarations() {
+        let script = "|| A course's first script\n\
+                      tree * ::= Leaf | Node (tree *) * (tree *)\n\
+                      size :: tree * -> num\n\
+                      size Leaf = 0\n\
+                      size (Node l x r) = size l + 1 + size r\n\
+                      \n\
+                      sign :: num -> num\n\
+                      sign n = 1, if n > 0\n\
+                      \x20      = 0, if n = 0\n\
+                      \x20      = -1, otherwise\n\
+                      main = (size (Node Leaf 'a' Leaf), sign 3)\n";
+        let program = parse_script(script).unwrap();
+        let kinds: Vec<_> = program.decls.iter().map(|decl| match &decl.kind {
+            DeclKind::TypeVar(names) => format!("typevar {}", names[0].name),
+            DeclKind::Data { head, constructors } => format!("data {} {}", head.name.name, constructors.len()),
+            DeclKind::Dec { names, .. } => format!("dec {}", names[0].name),
+            DeclKind::Equation(eq) => format!("--- {} {}", eq.name.name, eq.guard.is_some()),
+            DeclKind::Expr(_) => "expr".to_owned(),
+            _ => "other".to_owned(),
+        }).collect();
+        assert_eq!(kinds, ["typevar alpha", "data tree 2", "dec size", "--- size false", "--- size false", "dec sign",
+            "--- sign true", "--- sign true", "--- sign false", "expr"]);
+        // Positions are in the script
+        let DeclKind::Equation(eq) = &program.decls[6].kind else { unreachable!() };
+        assert_eq!((eq.guard.as_ref().unwrap().pos.line, eq.guard.as_ref().unwrap().pos.column), (8, 16));
+    }
+
+    #[test]
+    fn should_lay_out_local_definitions_by_the_offside_rule() {
+        let script = "f :: num -> num\n\
+                      f x = a + b\n\
+                      \x20     where\n\
+                      \x20     a = x * x\n\
+                      \x20     b = g a\n\
+                      \x20         where g y = y + 1\n\
+                      z :: num\n\
+                      z = f 2\n";
+        let local = bind(LetKind::WhereRec, pvar("g"), lambda([(pvar("y"), op("+", var("y"), int(1)))]), build::apply(var("g"), [var("a")]));
+        let body = bind(LetKind::Where, pvar("a"), op("*", var("x"), var("x")), bind(LetKind::Where, pvar("b"), local, op("+", var("a"), var("b"))));
+        assert_eq!(parse_script(script).unwrap().unplaced(), build::program([
+            dec(&["f"], arrow(ty("num", []), ty("num", []))),
+            equation("f", [pvar("x")], body),
+            dec(&["z"], ty("num", [])),
+            equation("z", [], build::apply(var("f"), [int(2)])),
+        ]));
+    }
+
+    #[test]
+    fn should_say_what_it_doesnt_read() {
+        let unsupported = |script: &str| match parse_script(script) {
+            Err(ParseError::Unsupported(what, pos)) => (what, pos.column),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(unsupported("xs = [1..10]\n"), ("ranges", 8));
+        assert_eq!(unsupported("f = map (+ 1)\n"), ("sections", 10));
+        assert_eq!(unsupported("h = f . g\n"), ("`.`, `!` and `^`", 7));
+        assert!(matches!(parse_script("f x = \"open\n"), Err(ParseError::Lexing(LexingError::UnterminatedString, _))));
+    }
+}
@@ -0,0 +1,1172 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use logos::Logos;
+use smallvec::SmallVec;
+use crate::alloc_prelude::*;
+use crate::cancel::{self, Cancel};
+use crate::syntax::ast::*;
+use crate::syntax::token::{Dialect, Literal, Pos, SpannedToken, Token, TokenKind};
+
+mod desugar;
+mod error;
+mod expand;
+pub mod miranda;
+
+pub use error::{ParseError, ParseWarning, Reserved};
+
+type PResult<T> = Result<T, ParseError>;
+
+// Fixities of the standard operators, which `infix` and `infixr` declarations add to
+// or override from the declaration on
+const STANDARD_FIXITIES: &[(&str, u32, Assoc)] = &[
+    // Only means anything in patterns, where it names the whole of what it matches
+    ("&", 0, Assoc::Right),
+    ("or", 1, Assoc::Left),
+    ("and", 2, Assoc::Left),
+    ("->", 2, Assoc::Right),
+    ("=", 3, Assoc::Left),
+    ("/=", 3, Assoc::Left),
+    ("<", 4, Assoc::Left),
+    ("=<", 4, Assoc::Left),
+    (">", 4, Assoc::Left),
+    (">=", 4, Assoc::Left),
+    ("#", 4, Assoc::Right),
+    ("::", 5, Assoc::Right),
+    ("<>", 5, Assoc::Right),
+    ("+", 6, Assoc::Left),
+    ("-", 6, Assoc::Left),
+    ("*", 7, Assoc::Left),
+    ("/", 7, Assoc::Left),
+    ("div", 7, Assoc::Left),
+    ("mod", 7, Assoc::Left),
+];
+
+// Brackets, conditionals and the like nested beyond this are reported rather than
+// parsed. Chains of operators don't count, however long
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+// Debug builds use kilobytes of stack per nesting level, so the descent grows the
+// stack on demand rather than relying on the caller's thread size. Without std it
+// can't, and max_depth alone bounds the descent
+#[cfg(feature = "std")]
+const STACK_RED_ZONE: usize = 64 * 1024;
+#[cfg(feature = "std")]
+const STACK_GROWTH: usize = 1024 * 1024;
+
+pub fn standard_fixity(op: &str) -> Option<(u32, Assoc)> {
+    STANDARD_FIXITIES.iter().find(|(name, ..)| *name == op).map(|&(_, prec, assoc)| (prec, assoc))
+}
+
+// The declarations that change how later source parses, see Parser::with_notation
+pub fn notation(decls: &[Decl]) -> impl Iterator<Item = Decl> + '_ {
+    decls.iter().filter(|decl| matches!(decl.kind, DeclKind::Infix { .. } | DeclKind::Syntax(_) | DeclKind::Data { .. })).cloned()
+}
+
+pub fn parse_program(source: &str) -> PResult<Program> {
+    Parser::new(source)?.parse_program()
+}
+
+pub fn parse_expr(source: &str) -> PResult<Expr> {
+    let mut parser = Parser::new(source)?;
+    let expr = parser.parse_expr()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(_) => Err(parser.unexpected("end of expression")),
+    }
+}
+
+pub fn parse_type(source: &str) -> PResult<TypeExpr> {
+    let mut parser = Parser::new(source)?;
+    let ty = parser.parse_type(0)?;
+    match parser.peek() {
+        None => Ok(ty),
+        Some(_) => Err(parser.unexpected("end of type")),
+    }
+}
+
+pub struct Parser<'src> {
+    source: &'src str,
+    // Reversed, so the next token is at the end
+    tokens: Vec<SpannedToken<'src>>,
+    last: Pos,
+    eof: Pos,
+    fixities: BTreeMap<String, (u32, Assoc)>,
+    depth: usize,
+    max_depth: usize,
+    lenient_semicolons: bool,
+    comprehensions: bool,
+    warnings: Vec<ParseWarning>,
+    // `syntax` definitions seen so far, by name
+    syntax: BTreeMap<String, SyntaxDef>,
+    // Counts the names expansion has made up
+    fresh: usize,
+    // Constructors declared so far, which a comprehension's pattern can fail to match
+    constructors: BTreeSet<String>,
+    // Where the `module` being parsed started, until its `end`
+    module: Option<Pos>,
+    // The left operands of right associative operators still waiting for their right
+    // one, see parse_operators. One stack for every call, each using what is above
+    // where it was when the call began, so it is only allocated once
+    operands: Vec<(Expr, Ident, u32)>,
+    cancel: Option<Cancel>,
+}
+
+impl<'src> Parser<'src> {
+    pub fn new(source: &'src str) -> PResult<Self> {
+        Parser::lex(source, None)
+    }
+
+    // Gives up, lexing or before a declaration, once cancel is
+    pub fn cancellable(source: &'src str, cancel: &Cancel) -> PResult<Self> {
+        Parser::lex(source, Some(cancel))
+    }
+
+    fn lex(source: &'src str, cancel: Option<&Cancel>) -> PResult<Self> {
+        let mut lex = Token::lexer(source);
+        let mut tokens = Vec::new();
+        while let Some(tok) = lex.next() {
+            if tokens.len() % cancel::EVERY == 0 && cancel.is_some_and(Cancel::is_cancelled) {
+                return Err(ParseError::Cancelled(lex.extras.pos(lex.span())));
+            }
+            match tok {
+                Ok(token) => tokens.push(SpannedToken::from(token).with_macros(false)),
+                Err(e) => return Err(ParseError::Lexing(e, lex.extras.pos(lex.span()))),
+            }
+        }
+        tokens.reverse();
+
+        let eof = lex.extras.pos(source.len()..source.len());
+        let fixities = STANDARD_FIXITIES.iter()
+            .map(|&(op, prec, assoc)| (op.to_owned(), (prec, assoc)))
+            .collect();
+
+        Ok(Parser {
+            source,
+            tokens,
+            last: eof.clone(),
+            eof,
+            fixities,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            lenient_semicolons: false,
+            comprehensions: false,
+            warnings: Vec::new(),
+            syntax: BTreeMap::new(),
+            fresh: 0,
+            constructors: ["true", "false", "nil"].map(str::to_owned).into(),
+            module: None,
+            operands: Vec::new(),
+            cancel: cancel.cloned(),
+        })
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    // Lets a newline stand in for the `;` that ends a declaration, when the next line
+    // starts with a declaration keyword. Each one is reported as a warning
+    pub fn with_lenient_semicolons(mut self, lenient: bool) -> Self {
+        self.lenient_semicolons = lenient;
+        self
+    }
+
+    // Accepts `[e | p <- list, cond, ...]`, rewritten as recursion over the lists
+    // while parsing. `<-` is then no longer a name
+    pub fn with_comprehensions(mut self, comprehensions: bool) -> Self {
+        self.comprehensions = comprehensions;
+        self
+    }
+
+    // Accepts `syntax` definitions, which are expanded where they are applied while
+    // parsing. `syntax` is then no longer a name
+    pub fn with_macros(mut self, macros: bool) -> Self {
+        self.tokens = core::mem::take(&mut self.tokens).into_iter().map(|t| t.with_macros(macros)).collect();
+        self
+    }
+
+    // Reads `module`, `end`, `nonop` and the `pub` keywords as names unless the dialect
+    // is classic
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.tokens = core::mem::take(&mut self.tokens).into_iter().map(|t| t.in_dialect(dialect)).collect();
+        self
+    }
+
+    // Parses the source as if it came after these declarations, so it can use the
+    // operators and syntax they define. Other declarations make no difference
+    pub fn with_notation<'a>(mut self, decls: impl IntoIterator<Item = &'a Decl>) -> Self {
+        decls.into_iter().for_each(|decl| self.declare(decl));
+        self
+    }
+
+    fn declare(&mut self, decl: &Decl) {
+        match &decl.kind {
+            DeclKind::Infix { assoc, ops, prec } => {
+                self.fixities.extend(ops.iter().map(|op| (op.name.clone(), (*prec, *assoc))));
+            }
+            DeclKind::Syntax(def) => {
+                self.syntax.insert(def.name.name.clone(), def.clone());
+            }
+            DeclKind::Data { constructors, .. } => {
+                self.constructors.extend(constructors.iter().map(|c| c.name.name.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.warnings
+    }
+
+    pub fn parse_program(&mut self) -> PResult<Program> {
+        let mut decls = Vec::new();
+        while self.peek().is_some() {
+            if self.cancel.as_ref().is_some_and(Cancel::is_cancelled) {
+                return Err(ParseError::Cancelled(self.peek_pos()));
+            }
+            decls.push(self.parse_decl()?);
+            if let Some(next) = self.next_decl().filter(|_| self.lenient_semicolons) {
+                let end = self.last.range.end;
+                let insert_at = Pos {
+                    line: self.last.line,
+                    column: self.last.column + self.last.range.len(),
+                    range: end..end,
+                };
+                self.warnings.push(ParseWarning::MissingSemicolon { next, insert_at });
+                continue;
+            }
+            self.expect("`;`", TokenKind::SemiColon)?;
+        }
+        if let Some(open) = self.module.take() {
+            let pos = self.eof.clone();
+            return Err(ParseError::Unclosed { expected: "`end`", found: "end of input", opener: "`module`", open, pos });
+        }
+        Ok(Program { decls })
+    }
+
+    // A keyword that can only start a declaration on a later line than the last token,
+    // or the end of input
+    fn next_decl(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(t) if t.pos.line > self.last.line => match t.kind {
+                TokenKind::TypeVar => Some("`typevar`"),
+                TokenKind::Infix => Some("`infix`"),
+                TokenKind::InfixR => Some("`infixr`"),
+                TokenKind::AbsType => Some("`abstype`"),
+                TokenKind::Data => Some("`data`"),
+                TokenKind::Type => Some("`type`"),
+                TokenKind::Dec => Some("`dec`"),
+                TokenKind::TripleDash => Some("`---`"),
+                TokenKind::Uses => Some("`uses`"),
+                TokenKind::Private => Some("`private`"),
+                TokenKind::Write => Some("`write`"),
+                TokenKind::Syntax => Some("`syntax`"),
+                TokenKind::Module => Some("`module`"),
+                TokenKind::End => Some("`end`"),
+                TokenKind::PubConst => Some("`pubconst`"),
+                TokenKind::PubFun => Some("`pubfun`"),
+                TokenKind::PubType => Some("`pubtype`"),
+                _ => None,
+            },
+            Some(_) => None,
+            None => Some("end of input"),
+        }
+    }
+
+    // Token helpers
+
+    fn peek(&self) -> Option<&SpannedToken<'src>> {
+        self.tokens.last()
+    }
+
+    fn peek_kind(&self) -> Option<TokenKind> {
+        self.peek().map(|t| t.kind)
+    }
+
+    fn peek_second(&self) -> Option<&SpannedToken<'src>> {
+        self.tokens.len().checked_sub(2).map(|i| &self.tokens[i])
+    }
+
+    fn peek_identifier(&self) -> Option<&'src str> {
+        self.peek().and_then(SpannedToken::identifier)
+    }
+
+    fn peek_pos(&self) -> Pos {
+        self.peek().map_or_else(|| self.eof.clone(), |t| t.pos.clone())
+    }
+
+    fn advance(&mut self) -> Option<SpannedToken<'src>> {
+        let token = self.tokens.pop();
+        if let Some(t) = &token {
+            self.last = t.pos.clone();
+        }
+        token
+    }
+
+    fn unexpected(&self, expected: &'static str) -> ParseError {
+        match self.peek() {
+            Some(t) => ParseError::UnexpectedToken { expected, found: t.kind.name(), pos: t.pos.clone() },
+            None => ParseError::UnexpectedEof { expected, pos: self.eof.clone() },
+        }
+    }
+
+    fn expect(&mut self, expected: &'static str, kind: TokenKind) -> PResult<Pos> {
+        match self.peek_kind() {
+            Some(k) if k == kind => Ok(self.advance().unwrap().pos),
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    // expect the token that finishes the construct `opener` started at `open`
+    fn close(&mut self, expected: &'static str, opener: &'static str, open: &Pos, kind: TokenKind) -> PResult<Pos> {
+        match self.peek() {
+            Some(t) if t.kind == kind => Ok(self.advance().unwrap().pos),
+            found => {
+                let (found, pos) = match found {
+                    Some(t) => (t.kind.name(), t.pos.clone()),
+                    None => ("end of input", self.eof.clone()),
+                };
+                Err(ParseError::Unclosed { expected, found, opener, open: open.clone(), pos })
+            }
+        }
+    }
+
+    fn eat(&mut self, kind: TokenKind) -> bool {
+        let found = self.peek_kind() == Some(kind);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn expect_ident(&mut self, expected: &'static str) -> PResult<Ident> {
+        match self.peek_identifier() {
+            Some(name) => Ok(Ident { name: name.to_owned(), pos: self.advance().unwrap().pos }),
+            None => Err(self.reserved(expected).unwrap_or_else(|| self.unexpected(expected))),
+        }
+    }
+
+    // A keyword spelled like a name, found where a name was expected
+    fn reserved(&self, expected: &'static str) -> Option<ParseError> {
+        let token = self.peek().filter(|t| t.kind != TokenKind::Identifier)?;
+        let word = self.source.get(token.pos.range.clone())?;
+        if !word.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let reserved = match token.kind {
+            TokenKind::Syntax => Reserved::Macros,
+            kind if kind.compat_word().is_some() => Reserved::Classic,
+            _ => Reserved::Always,
+        };
+        Some(ParseError::ReservedWord { word: word.to_owned(), expected, reserved, pos: token.pos.clone() })
+    }
+
+    fn operator(&self, token: Option<&SpannedToken>) -> Option<(u32, Assoc)> {
+        token.and_then(SpannedToken::identifier).and_then(|name| self.fixities.get(name).copied())
+    }
+
+    fn is_operator(&self, name: &str) -> bool {
+        self.fixities.contains_key(name)
+    }
+
+    // Runs a recursive step one nesting level deeper, failing instead of recursing
+    // past max_depth
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> PResult<T>) -> PResult<T> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::NestingTooDeep(self.peek_pos()));
+        }
+        self.depth += 1;
+        #[cfg(feature = "std")]
+        let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || parse(self));
+        #[cfg(not(feature = "std"))]
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn ident_list(&mut self, expected: &'static str) -> PResult<Vec<Ident>> {
+        let mut idents = vec![self.expect_ident(expected)?];
+        while self.eat(TokenKind::Comma) {
+            idents.push(self.expect_ident(expected)?);
+        }
+        Ok(idents)
+    }
+
+    // Declarations
+
+    fn parse_decl(&mut self) -> PResult<Decl> {
+        let start = self.peek_pos();
+        let kind = match self.peek_kind() {
+            Some(TokenKind::TypeVar) => {
+                self.advance();
+                DeclKind::TypeVar(self.ident_list("type variable")?)
+            }
+            Some(TokenKind::Infix | TokenKind::InfixR) => {
+                let assoc = match self.advance().map(|t| t.kind) {
+                    Some(TokenKind::InfixR) => Assoc::Right,
+                    _ => Assoc::Left,
+                };
+                let ops = self.ident_list("operator")?;
+                self.expect("`:`", TokenKind::Colon)?;
+                let prec = self.parse_precedence()?;
+                DeclKind::Infix { assoc, ops, prec }
+            }
+            Some(TokenKind::AbsType) => {
+                self.advance();
+                DeclKind::AbsType(self.parse_type_head()?)
+            }
+            Some(TokenKind::Data) => {
+                self.advance();
+                let head = self.parse_type_head()?;
+                self.expect("`==`", TokenKind::EqEq)?;
+                let mut constructors = vec![self.parse_constructor()?];
+                while self.eat(TokenKind::PlusPlus) {
+                    constructors.push(self.parse_constructor()?);
+                }
+                DeclKind::Data { head, constructors }
+            }
+            Some(TokenKind::Type) => {
+                self.advance();
+                let head = self.parse_type_head()?;
+                self.expect("`==`", TokenKind::EqEq)?;
+                DeclKind::Type { head, body: self.parse_type(0)? }
+            }
+            Some(TokenKind::Dec) => {
+                self.advance();
+                let names = self.ident_list("name")?;
+                self.expect("`:`", TokenKind::Colon)?;
+                DeclKind::Dec { names, ty: self.parse_type(0)? }
+            }
+            Some(TokenKind::TripleDash) => {
+                self.advance();
+                DeclKind::Equation(self.parse_equation()?)
+            }
+            Some(TokenKind::Syntax) => {
+                self.advance();
+                let name = self.expect_ident("syntax name")?;
+                let mut params = Vec::new();
+                while self.at_atom() {
+                    params.push(self.expect_ident("parameter")?);
+                }
+                self.expect("`<=`", TokenKind::LeftArrowFat)?;
+                DeclKind::Syntax(SyntaxDef { name, params, body: self.parse_expr()? })
+            }
+            Some(TokenKind::Uses) => {
+                self.advance();
+                DeclKind::Uses(self.ident_list("module name")?)
+            }
+            Some(TokenKind::Private) => {
+                self.advance();
+                DeclKind::Private
+            }
+            // Modules don't nest, and `end` and the `pub` keywords mean nothing outside one
+            Some(TokenKind::Module) => {
+                if let Some(open) = self.module.clone() {
+                    return Err(ParseError::Unclosed { expected: "`end`", found: "Module", opener: "`module`", open, pos: start });
+                }
+                self.advance();
+                self.module = Some(start.clone());
+                DeclKind::Module(self.expect_ident("module name")?)
+            }
+            Some(TokenKind::End) if self.module.is_some() => {
+                self.advance();
+                self.module = None;
+                DeclKind::End
+            }
+            Some(TokenKind::PubConst | TokenKind::PubFun | TokenKind::PubType) if self.module.is_some() => {
+                let kind = match self.advance().map(|t| t.kind) {
+                    Some(TokenKind::PubConst) => PublicKind::Const,
+                    Some(TokenKind::PubFun) => PublicKind::Fun,
+                    _ => PublicKind::Type,
+                };
+                DeclKind::Public(kind, self.ident_list("name")?)
+            }
+            Some(TokenKind::Write) => {
+                self.advance();
+                DeclKind::Write(self.parse_expr()?)
+            }
+            _ => DeclKind::Expr(self.parse_expr()?),
+        };
+
+        let mut decl = Decl { kind, pos: start.to(&self.last) };
+        expand::resolve(&mut decl, &self.constructors, &mut self.fresh);
+        self.declare(&decl);
+        Ok(decl)
+    }
+
+    fn parse_precedence(&mut self) -> PResult<u32> {
+        match self.peek() {
+            Some(SpannedToken { literal: Some(Literal::Int(n)), pos, .. }) => {
+                let prec = u32::try_from(*n).map_err(|_| ParseError::InvalidPrecedence(pos.clone()))?;
+                self.advance();
+                Ok(prec)
+            }
+            Some(SpannedToken { kind: TokenKind::Num, pos, .. }) => Err(ParseError::InvalidPrecedence(pos.clone())),
+            _ => Err(self.unexpected("precedence")),
+        }
+    }
+
+    // `tree alpha`, or an infix head such as `neg -> pos`
+    fn parse_type_head(&mut self) -> PResult<TypeHead> {
+        let first = self.expect_ident("type name")?;
+        if self.operator(self.peek()).is_some() {
+            let name = self.expect_ident("operator")?;
+            let second = self.expect_ident("type parameter")?;
+            return Ok(TypeHead { name, params: vec![first, second], infix: true });
+        }
+
+        let mut params = Vec::new();
+        while self.peek_kind() == Some(TokenKind::Identifier) {
+            params.push(self.expect_ident("type parameter")?);
+        }
+        Ok(TypeHead { name: first, params, infix: false })
+    }
+
+    fn parse_constructor(&mut self) -> PResult<Constructor> {
+        let ty = self.parse_type(0)?;
+
+        // Operators never head a type application, so a binary operator here was
+        // written infix, as in `alpha :: list alpha`
+        if self.is_operator(&ty.name.name) && ty.args.len() == 2 {
+            let pair = TypeExpr {
+                name: Ident { name: "#".to_owned(), pos: ty.name.pos.clone() },
+                args: ty.args,
+                pos: ty.pos.clone(),
+            };
+            return Ok(Constructor { name: ty.name, args: vec![pair], infix: true, pos: ty.pos });
+        }
+
+        Ok(Constructor { name: ty.name, args: ty.args, infix: false, pos: ty.pos })
+    }
+
+    fn parse_equation(&mut self) -> PResult<Equation> {
+        let lhs = self.parse_binary(0)?;
+        let guard = if self.eat(TokenKind::If) { Some(self.parse_binary(0)?) } else { None };
+        self.expect("`<=`", TokenKind::LeftArrowFat)?;
+        let body = self.parse_expr()?;
+
+        let pos = lhs.pos;
+        match lhs.kind {
+            ExprKind::BinOp(op, l, r) => {
+                let pair = Pattern {
+                    kind: PatternKind::Tuple(vec![to_pattern(*l)?, to_pattern(*r)?]),
+                    pos
+                };
+                Ok(Equation { name: op, args: vec![pair], guard, body, infix: true })
+            }
+            kind => {
+                let (head, args) = unwind_apply(Expr { kind, pos });
+                match head.kind {
+                    ExprKind::Var(name) => {
+                        let args = args.into_iter().map(to_pattern).collect::<PResult<_>>()?;
+                        Ok(Equation { name: Ident { name, pos: head.pos }, args, guard, body, infix: false })
+                    }
+                    _ => Err(ParseError::InvalidPattern(head.pos)),
+                }
+            }
+        }
+    }
+
+    // Types
+
+    fn at_type_atom(&self) -> bool {
+        match self.peek_identifier() {
+            Some(name) => !self.is_operator(name),
+            None => self.peek_kind() == Some(TokenKind::LParen),
+        }
+    }
+
+    pub fn parse_type(&mut self, min_prec: u32) -> PResult<TypeExpr> {
+        self.nested(|p| p.parse_type_operators(min_prec))
+    }
+
+    fn parse_type_operators(&mut self, min_prec: u32) -> PResult<TypeExpr> {
+        let mut lhs = self.parse_type_application()?;
+        while let Some((prec, assoc)) = self.operator(self.peek()) {
+            if prec < min_prec {
+                break;
+            }
+            let op = self.expect_ident("operator")?;
+            let rhs = self.parse_type(if assoc == Assoc::Right { prec } else { prec + 1 })?;
+            let pos = lhs.pos.to(&rhs.pos);
+            lhs = TypeExpr { name: op, args: vec![lhs, rhs], pos };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_type_application(&mut self) -> PResult<TypeExpr> {
+        if self.peek_kind() == Some(TokenKind::LParen) {
+            return self.parse_type_atom();
+        }
+        if !self.at_type_atom() {
+            return Err(self.unexpected("type"));
+        }
+
+        let name = self.expect_ident("type")?;
+        let mut args = Vec::new();
+        while self.at_type_atom() {
+            args.push(self.parse_type_atom()?);
+        }
+        let pos = name.pos.to(&self.last);
+        Ok(TypeExpr { name, args, pos })
+    }
+
+    fn parse_type_atom(&mut self) -> PResult<TypeExpr> {
+        match self.peek_kind() {
+            Some(TokenKind::LParen) => {
+                let start = self.advance().unwrap().pos;
+                let mut ty = self.parse_type(0)?;
+                let end = self.close("`)`", "`(`", &start, TokenKind::RParen)?;
+                ty.pos = start.to(&end);
+                Ok(ty)
+            }
+            _ if self.at_type_atom() => {
+                let name = self.expect_ident("type")?;
+                let pos = name.pos.clone();
+                Ok(TypeExpr { name, args: Vec::new(), pos })
+            }
+            _ => Err(self.unexpected("type")),
+        }
+    }
+
+    // Expressions
+
+    pub fn parse_expr(&mut self) -> PResult<Expr> {
+        let mut expr = self.parse_binary(0)?;
+        loop {
+            let kind = match self.peek_kind() {
+                Some(TokenKind::Where) => LetKind::Where,
+                Some(TokenKind::WhereRec) => LetKind::WhereRec,
+                _ => break,
+            };
+            self.advance();
+            let pattern = self.parse_pattern()?;
+            self.expect("`==`", TokenKind::EqEq)?;
+            let value = self.parse_binary(0)?;
+            let pos = expr.pos.to(&value.pos);
+            expr = Expr { kind: ExprKind::Let(Box::new(Let { kind, pattern, value, body: expr })), pos };
+        }
+        Ok(expr)
+    }
+
+    fn parse_binary(&mut self, min_prec: u32) -> PResult<Expr> {
+        self.nested(|p| p.parse_operators(min_prec))
+    }
+
+    // The right operand of a right associative operator is parsed in the same loop,
+    // keeping what it is the operand of on a stack, so a long `1 :: 2 :: ... :: nil`
+    // isn't counted as nesting. Only a left associative operator's operand recurses,
+    // and that only as deep as there are precedences above it
+    fn parse_operators(&mut self, min_prec: u32) -> PResult<Expr> {
+        let base = self.operands.len();
+        let result = self.parse_operands(min_prec, base);
+        self.operands.truncate(base);
+        result
+    }
+
+    fn parse_operands(&mut self, min_prec: u32, base: usize) -> PResult<Expr> {
+        let mut min_prec = min_prec;
+        let mut lhs = self.parse_application()?;
+        loop {
+            match self.operator(self.peek()) {
+                Some((prec, assoc)) if prec >= min_prec => {
+                    let op = self.expect_ident("operator")?;
+                    if assoc == Assoc::Right {
+                        self.operands.push((lhs, op, min_prec));
+                        min_prec = prec;
+                        lhs = self.parse_application()?;
+                        continue;
+                    }
+                    let rhs = self.parse_binary(prec + 1)?;
+                    let pos = lhs.pos.to(&rhs.pos);
+                    lhs = Expr { kind: ExprKind::BinOp(op, Box::new(lhs), Box::new(rhs)), pos };
+                }
+                _ if self.operands.len() == base => return Ok(lhs),
+                _ => {
+                    let (left, op, outer) = self.operands.pop().expect("there are operands above base");
+                    let pos = left.pos.to(&lhs.pos);
+                    lhs = Expr { kind: ExprKind::BinOp(op, Box::new(left), Box::new(lhs)), pos };
+                    min_prec = outer;
+                }
+            }
+        }
+    }
+
+    fn at_atom(&self) -> bool {
+        match self.peek_identifier() {
+            Some("<-") if self.comprehensions => false,
+            Some(name) => !self.is_operator(name),
+            None => matches!(
+                self.peek_kind(),
+                Some(TokenKind::Int | TokenKind::Num | TokenKind::String | TokenKind::LParen | TokenKind::LSquare | TokenKind::NonOp)
+            ),
+        }
+    }
+
+    fn parse_application(&mut self) -> PResult<Expr> {
+        match self.peek_kind() {
+            Some(TokenKind::If) => return self.parse_if(),
+            Some(TokenKind::Let | TokenKind::LetRec) => return self.parse_let(),
+            Some(TokenKind::Lambda) => return self.parse_lambda(),
+            _ => {}
+        }
+
+        let mut expr = self.parse_atom()?;
+        let def = match &expr.kind {
+            ExprKind::Var(name) => self.syntax.get(name).cloned(),
+            _ => None,
+        };
+        if let Some(def) = def {
+            expr = self.parse_expansion(&def, expr.pos)?;
+        }
+        while self.at_atom() {
+            let arg = self.parse_atom()?;
+            let pos = expr.pos.to(&arg.pos);
+            expr = Expr { kind: ExprKind::Apply(Box::new(expr), Box::new(arg)), pos };
+        }
+        Ok(expr)
+    }
+
+    // The arguments of a syntax definition applied at pos, expanded
+    fn parse_expansion(&mut self, def: &SyntaxDef, pos: Pos) -> PResult<Expr> {
+        let mut args = Vec::new();
+        while args.len() < def.params.len() {
+            if !self.at_atom() {
+                let (name, expected, found) = (def.name.name.clone(), def.params.len(), args.len());
+                return Err(ParseError::SyntaxArity { name, expected, found, pos: pos.to(&self.last) });
+            }
+            args.push(self.parse_atom()?);
+        }
+        let pos = pos.to(&self.last);
+        Ok(expand::expand(def, args, pos, &mut self.fresh))
+    }
+
+    fn parse_atom(&mut self) -> PResult<Expr> {
+        if !self.at_atom() {
+            return Err(self.unexpected("expression"));
+        }
+
+        let SpannedToken { kind, literal, pos } = self.advance().unwrap();
+        match (kind, literal) {
+            (_, Some(Literal::Identifier(name))) => Ok(Expr { kind: ExprKind::Var(name.to_owned()), pos }),
+            (_, Some(Literal::Int(n))) => Ok(Expr { kind: ExprKind::Int(n), pos }),
+            (_, Some(Literal::BigInt(digits))) => Ok(Expr { kind: ExprKind::BigInt(digits.to_owned()), pos }),
+            (_, Some(Literal::Num(n))) => Ok(Expr { kind: ExprKind::Num(n), pos }),
+            (_, Some(Literal::String(s))) => Ok(Expr { kind: ExprKind::Str(s.into_owned()), pos }),
+            // `nonop op` is the classic spelling of `(op)`
+            (TokenKind::NonOp, None) => {
+                if self.operator(self.peek()).is_none() {
+                    return Err(self.unexpected("operator"));
+                }
+                let op = self.expect_ident("operator")?;
+                let pos = pos.to(&op.pos);
+                Ok(Expr { kind: ExprKind::Var(op.name), pos })
+            }
+            (TokenKind::LParen, None) => {
+                let start = pos;
+                // `(op)` refers to an operator as an ordinary function
+                if self.operator(self.peek()).is_some()
+                    && self.peek_second().is_some_and(|t| t.kind == TokenKind::RParen) {
+                    let op = self.expect_ident("operator")?;
+                    let end = self.expect("`)`", TokenKind::RParen)?;
+                    return Ok(Expr { kind: ExprKind::Var(op.name), pos: start.to(&end) });
+                }
+
+                let mut items = vec![self.parse_expr()?];
+                while self.eat(TokenKind::Comma) {
+                    items.push(self.parse_expr()?);
+                }
+                let end = self.close("`)`", "`(`", &start, TokenKind::RParen)?;
+                let pos = start.to(&end);
+                if items.len() == 1 {
+                    let mut expr = items.pop().unwrap();
+                    expr.pos = pos;
+                    Ok(expr)
+                } else {
+                    Ok(Expr { kind: ExprKind::Tuple(items), pos })
+                }
+            }
+            (TokenKind::LSquare, None) => {
+                let start = pos;
+                let mut items = Vec::new();
+                if self.peek_kind() != Some(TokenKind::RSquare) {
+                    items.push(self.parse_expr()?);
+                    if self.comprehensions && self.eat(TokenKind::Pipe) {
+                        return self.parse_comprehension(items.pop().unwrap(), start);
+                    }
+                    while self.eat(TokenKind::Comma) {
+                        items.push(self.parse_expr()?);
+                    }
+                }
+                let end = self.close("`]`", "`[`", &start, TokenKind::RSquare)?;
+                Ok(Expr { kind: ExprKind::List(items), pos: start.to(&end) })
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // The qualifiers of `[item | ...]`, after the `|`
+    fn parse_comprehension(&mut self, item: Expr, start: Pos) -> PResult<Expr> {
+        let mut qualifiers = Vec::new();
+        loop {
+            let expr = self.parse_expr()?;
+            if self.peek_identifier() == Some("<-") {
+                self.advance();
+                let pattern = to_pattern(expr)?;
+                qualifiers.push(desugar::Qualifier::Generator(pattern, self.parse_expr()?));
+            } else {
+                qualifiers.push(desugar::Qualifier::Guard(expr));
+            }
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+        let end = self.close("`]`", "`[`", &start, TokenKind::RSquare)?;
+        Ok(desugar::comprehension(item, qualifiers, &start.to(&end), &self.constructors, &mut self.fresh))
+    }
+
+    fn parse_if(&mut self) -> PResult<Expr> {
+        let start = self.advance().unwrap().pos;
+        let cond = self.parse_expr()?;
+        self.close("`then`", "`if`", &start, TokenKind::Then)?;
+        let then = self.parse_expr()?;
+        self.close("`else`", "`if`", &start, TokenKind::Else)?;
+        // `where` after the else branch scopes over the whole conditional
+        let other = self.parse_binary(0)?;
+        let pos = start.to(&other.pos);
+        Ok(Expr { kind: ExprKind::If(Box::new(cond), Box::new(then), Box::new(other)), pos })
+    }
+
+    fn parse_let(&mut self) -> PResult<Expr> {
+        let token = self.advance().unwrap();
+        let kind = if token.kind == TokenKind::LetRec { LetKind::LetRec } else { LetKind::Let };
+        let start = token.pos;
+        let pattern = self.parse_pattern()?;
+        self.expect("`==`", TokenKind::EqEq)?;
+        let value = self.parse_expr()?;
+        let opener = if kind.is_rec() { "`letrec`" } else { "`let`" };
+        self.close("`in`", opener, &start, TokenKind::In)?;
+        let body = self.parse_binary(0)?;
+        let pos = start.to(&body.pos);
+        Ok(Expr { kind: ExprKind::Let(Box::new(Let { kind, pattern, value, body })), pos })
+    }
+
+    fn parse_lambda(&mut self) -> PResult<Expr> {
+        let start = self.advance().unwrap().pos;
+        let mut rules = vec![self.parse_rule()?];
+        while self.eat(TokenKind::Pipe) {
+            rules.push(self.parse_rule()?);
+        }
+        Ok(Expr { kind: ExprKind::Lambda(rules), pos: start.to(&self.last) })
+    }
+
+    fn parse_rule(&mut self) -> PResult<Rule> {
+        let pattern = self.parse_pattern()?;
+        self.expect("`=>`", TokenKind::RightArrowFat)?;
+        let body = self.parse_binary(0)?;
+        let pos = pattern.pos.to(&body.pos);
+        Ok(Rule { pattern, body, pos })
+    }
+
+    // Patterns share the expression grammar and are checked afterwards
+
+    fn parse_pattern(&mut self) -> PResult<Pattern> {
+        let expr = self.parse_binary(0)?;
+        to_pattern(expr)
+    }
+}
+
+// The arguments only last until they are made patterns, and there are rarely more than
+// a few
+fn unwind_apply(expr: Expr) -> (Expr, SmallVec<[Expr; 4]>) {
+    let mut head = expr;
+    let mut args = SmallVec::new();
+    loop {
+        match head.kind {
+            ExprKind::Apply(f, arg) => {
+                args.push(*arg);
+                head = *f;
+            }
+            kind => {
+                args.reverse();
+                return (Expr { kind, pos: head.pos }, args);
+            }
+        }
+    }
+}
+
+fn to_pattern(expr: Expr) -> PResult<Pattern> {
+    let pos = expr.pos;
+    let kind = match expr.kind {
+        ExprKind::Var(name) if name == "_" => PatternKind::Wildcard,
+        ExprKind::Var(name) => PatternKind::Var(name),
+        ExprKind::Int(n) => PatternKind::Int(n),
+        ExprKind::BigInt(digits) => PatternKind::BigInt(digits),
+        ExprKind::Num(n) => PatternKind::Num(n),
+        ExprKind::Str(s) => PatternKind::Str(s),
+        ExprKind::Tuple(items) => PatternKind::Tuple(items.into_iter().map(to_pattern).collect::<PResult<_>>()?),
+        ExprKind::List(items) => PatternKind::List(items.into_iter().map(to_pattern).collect::<PResult<_>>()?),
+        ExprKind::BinOp(op, l, r) if op.name == "&" => match l.kind {
+            ExprKind::Var(name) => PatternKind::As(Ident { name, pos: l.pos }, Box::new(to_pattern(*r)?)),
+            _ => return Err(ParseError::InvalidPattern(l.pos)),
+        },
+        ExprKind::BinOp(op, l, r) => PatternKind::BinOp(op, Box::new(to_pattern(*l)?), Box::new(to_pattern(*r)?)),
+        kind @ ExprKind::Apply(..) => {
+            let (head, args) = unwind_apply(Expr { kind, pos: pos.clone() });
+            match head.kind {
+                ExprKind::Var(name) => {
+                    let args = args.into_iter().map(to_pattern).collect::<PResult<_>>()?;
+                    PatternKind::Construct(Ident { name, pos: head.pos }, args)
+                }
+                _ => return Err(ParseError::InvalidPattern(head.pos)),
+            }
+        }
+        _ => return Err(ParseError::InvalidPattern(pos)),
+    };
+    Ok(Pattern { kind, pos })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::build::{self, *};
+
+    fn show(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Var(name) => name.clone(),
+            ExprKind::BigInt(digits) => digits.clone(),
+            ExprKind::Int(n) => n.to_string(),
+            ExprKind::Num(n) => n.to_string(),
+            ExprKind::Str(s) => format!("{:?}", s),
+            ExprKind::Tuple(items) => format!("({})", items.iter().map(show).collect::<Vec<_>>().join(", ")),
+            ExprKind::List(items) => format!("[{}]", items.iter().map(show).collect::<Vec<_>>().join(", ")),
+            ExprKind::Apply(f, arg) => format!("({} {})", show(f), show(arg)),
+            ExprKind::BinOp(op, l, r) => format!("({} {} {})", show(l), op.name, show(r)),
+            ExprKind::If(c, t, e) => format!("(if {} then {} else {})", show(c), show(t), show(e)),
+            ExprKind::Lambda(rules) => format!("(lambda {})", rules.len()),
+            ExprKind::Let(binding) => format!("({:?} {})", binding.kind, show(&binding.body)),
+        }
+    }
+
+    #[test]
+    fn should_parse_standard_prelude() {
+        let program = parse_program(include_str!("../../../lib/Standard.hop")).unwrap();
+        assert!(program.decls.iter().any(|d| matches!(&d.kind, DeclKind::Equation(eq) if eq.name.name == "map")));
+    }
+
+    #[test]
+    fn should_respect_precedence_and_application() {
+        let expr = parse_expr("f x :: g y + 1 * 2 :: l").unwrap();
+        assert_eq!(show(&expr), "((f x) :: (((g y) + (1 * 2)) :: l))");
+        let expr = parse_expr("a :: b <> c = d - e :: l or x :: y").unwrap();
+        assert_eq!(show(&expr), "(((a :: (b <> c)) = ((d - e) :: l)) or (x :: y))");
+
+        let expr = parse_expr("if a then b else c where a == 1").unwrap();
+        assert_eq!(show(&expr), "(Where (if a then b else c))");
+    }
+
+    #[test]
+    fn should_parse_equations_and_data() {
+        let program = parse_program("
+            data tree alpha == empty ++ node (tree alpha # alpha # tree alpha);
+            --- (x :: l) <> m <= x :: (l <> m);
+            --- insert (x, node (l, y, r)) <= l;
+            --- x <> y if x =< y <= x;
+            --- f (l & (x :: _), _) <= l;
+        ").unwrap();
+        let tree = ty("tree", [ty("alpha", [])]);
+        assert_eq!(program.unplaced(), build::program([
+            data("tree", &["alpha"], [("empty", vec![]), ("node", vec![pair(tree.clone(), pair(ty("alpha", []), tree))])]),
+            infix_equation("<>", pop("::", pvar("x"), pvar("l")), pvar("m"), op("::", var("x"), op("<>", var("l"), var("m")))),
+            equation("insert", [ptuple([pvar("x"), construct("node", [ptuple([pvar("l"), pvar("y"), pvar("r")])])])], var("l")),
+            infix_guarded("<>", pvar("x"), pvar("y"), op("=<", var("x"), var("y")), var("x")),
+            equation("f", [ptuple([pas("l", pop("::", pvar("x"), wildcard())), wildcard()])], var("l")),
+        ]));
+        assert!(parse_program("--- f ((a, b) & c) <= c;").is_err());
+    }
+
+    #[test]
+    fn should_carry_spans() {
+        let program = parse_program("dec f : num -> num;\n--- f x <= x + 1;").unwrap();
+        let DeclKind::Equation(eq) = &program.decls[1].kind else { panic!() };
+        assert_eq!(eq.body.pos.range, 31..36);
+        assert_eq!(eq.body.pos.line, 2);
+        assert_eq!(program.decls[1].pos.range, 20..36);
+    }
+
+    #[test]
+    fn should_limit_nesting_depth() {
+        // Without std the parser can't grow the stack, so it is up to the caller
+        let thread = std::thread::Builder::new().stack_size(64 << 20).spawn(limit_nesting_depth);
+        thread.unwrap().join().unwrap();
+    }
+
+    fn limit_nesting_depth() {
+        let deep = format!("{}x{}", "(".repeat(10_000), ")".repeat(10_000));
+        assert!(matches!(parse_expr(&deep), Err(ParseError::NestingTooDeep(_))));
+
+        // A chain of right associative operators is as flat as one of left associative ones
+        let chain = format!("{}[]", "x :: ".repeat(10_000));
+        assert!(parse_expr(&chain).is_ok());
+        let nested = format!("{}[]{}", "(x :: ".repeat(10_000), ")".repeat(10_000));
+        assert!(matches!(parse_expr(&nested), Err(ParseError::NestingTooDeep(_))));
+
+        let deep_type = format!("dec f : {}num{};", "(".repeat(10_000), ")".repeat(10_000));
+        assert!(matches!(parse_program(&deep_type), Err(ParseError::NestingTooDeep(_))));
+
+        let fits = format!("{}x{}", "(".repeat(DEFAULT_MAX_DEPTH - 1), ")".repeat(DEFAULT_MAX_DEPTH - 1));
+        assert!(parse_expr(&fits).is_ok());
+
+        // Only std can grow the stack that far
+        if cfg!(feature = "std") {
+            let configured = Parser::new(&deep).unwrap().with_max_depth(20_000).parse_expr();
+            assert!(configured.is_ok());
+        }
+
+        let shallow = Parser::new("((x))").unwrap().with_max_depth(2).parse_expr();
+        assert!(matches!(shallow, Err(ParseError::NestingTooDeep(_))));
+    }
+
+    #[test]
+    fn should_report_unexpected_tokens() {
+        let err = parse_program("dec f num;").unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedToken { expected: "`:`", .. }));
+
+        let err = parse_program("--- f (lambda x => x) <= 1;").unwrap_err();
+        assert!(matches!(err, ParseError::InvalidPattern(_)));
+    }
+
+    #[test]
+    fn should_say_when_a_name_is_a_reserved_word() {
+        let err = parse_program("dec type : num;").unwrap_err();
+        assert_eq!(err.to_string(), "expected name, found `type`, which is a reserved word");
+        assert_eq!((err.code(), err.pos().column), ("E0208", 5));
+        assert_eq!(err.help().as_deref(), Some("rename it, for example to `type'`"));
+
+        let err = parse_program("uses Lists, end;").unwrap_err();
+        assert_eq!(err.to_string(), "expected module name, found `end`, which is reserved in the classic dialect");
+        assert!(Parser::new("uses Lists, end;").unwrap().with_dialect(Dialect::Modern).parse_program().is_ok());
+        let err = Parser::new("dec syntax : num;").unwrap().with_macros(true).parse_program().unwrap_err();
+        assert!(matches!(err, ParseError::ReservedWord { reserved: Reserved::Macros, .. }));
+
+        // Anything else is just unexpected
+        assert!(matches!(parse_program("dec ( : num;"), Err(ParseError::UnexpectedToken { .. })));
+        assert!(matches!(parse_program("dec \\ : num;"), Err(ParseError::UnexpectedToken { .. })));
+    }
+
+    #[test]
+    fn should_assume_semicolons_before_declarations_when_lenient() {
+        let source = "dec f : num -> num\n--- f x <= x + 1\nwrite f 2\n\ndec g : num;";
+        assert!(parse_program(source).is_err());
+
+        let mut parser = Parser::new(source).unwrap().with_lenient_semicolons(true);
+        assert_eq!(parser.parse_program().unwrap().decls.len(), 4);
+
+        let positions: Vec<_> = parser.warnings().iter().map(|w| (w.pos().line, w.pos().range.start)).collect();
+        assert_eq!(positions, [(1, 18), (2, 35), (3, 45)]);
+        assert_eq!(parser.warnings()[0].to_string(), "missing `;` before `---`, assumed at the end of the line");
+    }
+
+    #[test]
+    fn should_use_declared_fixities() {
+        let program = parse_program("infixr ^^ : 8;\ninfix + : 9;\n1 + 2 ^^ 3 ^^ 4 * 5;").unwrap();
+        let DeclKind::Expr(expr) = &program.decls[2].kind else { panic!() };
+        assert_eq!(show(expr), "(((1 + 2) ^^ (3 ^^ 4)) * 5)");
+
+        // Until it is declared `^^` is an ordinary name
+        assert_eq!(show(&parse_expr("1 ^^ 2").unwrap()), "((1 ^^) 2)");
+    }
+
+    #[test]
+    fn should_expand_syntax_definitions() {
+        let parse_program = |source| Parser::new(source).unwrap().with_macros(true).parse_program();
+        let source = "syntax unless c a b <= if c then b else a;\nsyntax twice e <= let x == e in x + x;\n\
+                      unless p (twice x) y z;";
+        let program = parse_program(source).unwrap();
+        let DeclKind::Expr(expr) = &program.decls[2].kind else { panic!() };
+        assert_eq!(show(expr), "((if p then y else (Let (x#1 + x#1))) z)");
+
+        // The body is placed at the application, the arguments where they were written
+        let ExprKind::Apply(f, _) = &expr.kind else { panic!() };
+        let ExprKind::If(c, _, _) = &f.kind else { panic!() };
+        assert_eq!((f.pos.range.clone(), c.pos.range.clone()), (82..102, 89..90));
+
+        // Only what shadows a name the body uses freely is renamed
+        let program = parse_program("syntax more e <= e + x;\nlet x == 1 in lambda (y, x) => more x + y;").unwrap();
+        let DeclKind::Expr(expr) = &program.decls[1].kind else { panic!() };
+        let ExprKind::Let(l) = &expr.kind else { panic!() };
+        let ExprKind::Lambda(rules) = &l.body.kind else { panic!() };
+        assert!(matches!(&l.pattern.kind, PatternKind::Var(name) if name == "x#1"));
+        assert!(matches!(&rules[0].pattern.kind, PatternKind::Tuple(items) if items[0].kind == PatternKind::Var("y".to_owned())));
+        assert_eq!(show(&rules[0].body), "((x#2 + x) + y)");
+
+        let err = parse_program("syntax unless c a b <= if c then b else a;\nunless p;").unwrap_err();
+        assert_eq!(err.to_string(), "syntax `unless` takes 3 arguments, given 1");
+
+        // Unless macros are allowed `syntax` is an ordinary name
+        assert_eq!(show(&super::parse_expr("syntax x").unwrap()), "(syntax x)");
+    }
+
+    #[test]
+    fn should_desugar_list_comprehensions() {
+        let parse = |source| Parser::new(source).unwrap().with_comprehensions(true).parse_expr();
+        let expr = parse("[x * 2 | x <- xs, x > 1]").unwrap();
+        assert_eq!(show(&expr), "(LetRec (h#1 xs))");
+        let ExprKind::Let(l) = &expr.kind else { panic!() };
+        let ExprKind::Lambda(rules) = &l.value.kind else { panic!() };
+        assert_eq!(rules.len(), 2);
+        assert_eq!(show(&rules[1].body), "(if (x > 1) then ((x * 2) :: (h#1 us#2)) else (h#1 us#2))");
+
+        // The guard keeps its position, and the whole expression spans the brackets
+        let ExprKind::If(c, _, _) = &rules[1].body.kind else { panic!() };
+        assert_eq!((expr.pos.range.clone(), c.pos.range.clone()), (0..24, 18..23));
+
+        // A pattern that can fail skips the elements it doesn't match
+        let rules = |source| {
+            let expr = parse(source).unwrap();
+            let ExprKind::Let(l) = &expr.kind else { panic!() };
+            let ExprKind::Lambda(rules) = &l.value.kind else { panic!() };
+            rules.len()
+        };
+        assert_eq!(rules("[a | (a, 0) <- ps]"), 3);
+        assert_eq!(rules("[1 | true <- bs]"), 3);
+        assert_eq!(rules("[1 | (_, nil) <- ps]"), 3);
+        assert_eq!(rules("[a | (a, b) <- ps]"), 2);
+
+        assert!(parse_expr("[x | x <- xs]").is_err());
+    }
+
+    #[test]
+    fn should_parse_modules_only_in_the_classic_dialect() {
+        let source = "module stacks;\npubtype stack;\npubfun push, pop;\ndec s : num;\nend;\nnonop + (1, 2);";
+        let program = parse_program(source).unwrap();
+        let kinds: Vec<_> = program.decls.iter().map(|d| &d.kind).collect();
+        assert!(matches!(kinds[0], DeclKind::Module(name) if name.name == "stacks"));
+        assert!(matches!(kinds[2], DeclKind::Public(PublicKind::Fun, names) if names.len() == 2));
+        assert!(matches!(kinds[4], DeclKind::End));
+        let DeclKind::Expr(expr) = kinds[5] else { panic!() };
+        assert_eq!(show(expr), "(+ (1, 2))");
+
+        let modern = |source| Parser::new(source).unwrap().with_dialect(Dialect::Modern).parse_program();
+        let program = modern("dec module, end : num;\n--- module <= 1;\nnonop;").unwrap();
+        assert!(matches!(&program.decls[0].kind, DeclKind::Dec { names, .. } if names[1].name == "end"));
+        assert!(modern(source).is_err());
+
+        let err = parse_program("module a;\ndec x : num;").unwrap_err();
+        assert_eq!(err.to_string(), "expected `end` to close `module`, found end of input");
+        assert!(parse_program("module a;\nmodule b;\nend;").is_err());
+        assert!(parse_program("pubfun f;").is_err());
+        assert!(parse_expr("nonop f").is_err());
+    }
+
+    #[test]
+    fn should_point_unclosed_constructs_at_their_opening() {
+        let err = parse_expr("f (x, [y, z) + 1").unwrap_err();
+        assert_eq!(err.to_string(), "expected `]` to close `[`, found RParen");
+        assert_eq!(err.opened_at().map(|(_, pos)| pos.range.clone()), Some(6..7));
+
+        let err = parse_expr("if a then\n  b").unwrap_err();
+        assert!(matches!(&err, ParseError::Unclosed { expected: "`else`", found: "end of input", .. }));
+        assert_eq!(err.opened_at().map(|(opener, pos)| (opener, pos.line)), Some(("`if`", 1)));
+    }
+}
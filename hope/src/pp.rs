@@ -0,0 +1,186 @@
+use core::fmt;
+use alloc::rc::Rc;
+use crate::alloc_prelude::*;
+
+// Wadler-style pretty printing documents. A `group` is laid out flat when it fits in
+// the remaining width, otherwise its `line`s become newlines at the current nesting.
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Nil,
+    Text(Rc<str>),
+    // `flat` is what the break renders as when its group fits on one line
+    Line { flat: &'static str },
+    HardLine,
+    Cat(Rc<Doc>, Rc<Doc>),
+    Nest(usize, Rc<Doc>),
+    Group(Rc<Doc>),
+}
+
+pub fn nil() -> Doc {
+    Doc::Nil
+}
+
+pub fn text(s: impl AsRef<str>) -> Doc {
+    Doc::Text(Rc::from(s.as_ref()))
+}
+
+// Space when flat, newline when broken
+pub fn line() -> Doc {
+    Doc::Line { flat: " " }
+}
+
+// Nothing when flat, newline when broken
+pub fn softline() -> Doc {
+    Doc::Line { flat: "" }
+}
+
+pub fn hardline() -> Doc {
+    Doc::HardLine
+}
+
+pub fn concat(docs: impl IntoIterator<Item = Doc>) -> Doc {
+    docs.into_iter().fold(Doc::Nil, Doc::append)
+}
+
+pub fn join(docs: impl IntoIterator<Item = Doc>, sep: Doc) -> Doc {
+    let mut out = Doc::Nil;
+    for (i, doc) in docs.into_iter().enumerate() {
+        if i > 0 {
+            out = out.append(sep.clone());
+        }
+        out = out.append(doc);
+    }
+    out
+}
+
+impl Doc {
+    pub fn append(self, other: Doc) -> Doc {
+        match (self, other) {
+            (Doc::Nil, d) | (d, Doc::Nil) => d,
+            (a, b) => Doc::Cat(Rc::new(a), Rc::new(b)),
+        }
+    }
+
+    pub fn nest(self, indent: usize) -> Doc {
+        Doc::Nest(indent, Rc::new(self))
+    }
+
+    pub fn group(self) -> Doc {
+        Doc::Group(Rc::new(self))
+    }
+
+    pub fn render(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut col = 0;
+        let mut stack = vec![(0, Mode::Break, self)];
+
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Nil => {}
+                Doc::Text(s) => {
+                    out.push_str(s);
+                    col += s.chars().count();
+                }
+                Doc::Line { flat } if mode == Mode::Flat => {
+                    out.push_str(flat);
+                    col += flat.len();
+                }
+                Doc::Line { .. } | Doc::HardLine => {
+                    newline(&mut out, indent);
+                    col = indent;
+                }
+                Doc::Cat(a, b) => {
+                    stack.push((indent, mode, b));
+                    stack.push((indent, mode, a));
+                }
+                Doc::Nest(i, d) => stack.push((indent + i, mode, d)),
+                Doc::Group(d) => {
+                    let flat = mode == Mode::Flat
+                        || fits(width.saturating_sub(col) as isize, d, &stack);
+                    stack.push((indent, if flat { Mode::Flat } else { Mode::Break }, d));
+                }
+            }
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Doc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(f.width().unwrap_or(80)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+fn newline(out: &mut String, indent: usize) {
+    // Drop trailing spaces left by a flat separator before the break
+    let trimmed = out.trim_end_matches(' ').len();
+    out.truncate(trimmed);
+    out.push('\n');
+    out.extend(core::iter::repeat_n(' ', indent));
+}
+
+// Does `doc` rendered flat, followed by the rest of the line, fit in `remaining` columns
+fn fits(mut remaining: isize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut pending = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+
+    while remaining >= 0 {
+        let (mode, doc) = match pending.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some(&(_, mode, doc)) => (mode, doc),
+                None => return true,
+            },
+        };
+
+        match doc {
+            Doc::Nil => {}
+            Doc::Text(s) => remaining -= s.chars().count() as isize,
+            Doc::Line { flat } if mode == Mode::Flat => remaining -= flat.len() as isize,
+            Doc::Line { .. } | Doc::HardLine => return true,
+            Doc::Cat(a, b) => {
+                pending.push((mode, b));
+                pending.push((mode, a));
+            }
+            Doc::Nest(_, d) => pending.push((mode, d)),
+            Doc::Group(d) => pending.push((mode, d)),
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: &[&str]) -> Doc {
+        text("[")
+            .append(join(items.iter().map(text), text(",").append(line())).nest(1))
+            .append(text("]"))
+            .group()
+    }
+
+    #[test]
+    fn should_stay_flat_when_it_fits() {
+        assert_eq!(list(&["1", "2", "3"]).render(80), "[1, 2, 3]");
+    }
+
+    #[test]
+    fn should_break_and_nest_when_too_wide() {
+        assert_eq!(list(&["one", "two", "three"]).render(10), "[one,\n two,\n three]");
+    }
+
+    #[test]
+    fn should_account_for_trailing_text_after_group() {
+        let doc = list(&["a", "b"]).append(text(" ++ something long"));
+        assert_eq!(doc.render(12), "[a,\n b] ++ something long");
+    }
+}
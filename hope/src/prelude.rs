@@ -0,0 +1,15 @@
+use crate::parser;
+use crate::syntax::ast::Program;
+
+// lib/Standard.hop, built in so that programs can use it from anywhere
+pub const SOURCE: &str = include_str!("../../lib/Standard.hop");
+
+// What errors in its definitions are reported against
+pub const FILE: &str = "Standard.hop";
+
+// The module name the prelude answers to, so `uses Standard;` needs no file
+pub const MODULE: &str = "Standard";
+
+pub fn program() -> Program {
+    parser::parse_program(SOURCE).expect("the prelude parses")
+}
@@ -0,0 +1,195 @@
+use core::fmt;
+use core::iter::Peekable;
+use core::str::Chars;
+use crate::alloc_prelude::*;
+
+// Format strings of the `format` builtin, which the checker looks at when they are
+// written out and the interpreter when they are run. A `%` starts a directive: `-` to
+// line up on the left rather than the right, a width to pad to, `.` and a precision,
+// then `s` for text, cut to the precision if there is one, `d` for a whole number or
+// `f` for any number with the precision's digits after the point, 6 if not given.
+// `%%` is a `%`
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conversion {
+    Text,
+    Whole,
+    Fixed,
+}
+
+impl Conversion {
+    // The formatarg constructor of the arguments it takes
+    pub fn constructor(self) -> &'static str {
+        match self {
+            Conversion::Text => "fstr",
+            Conversion::Whole | Conversion::Fixed => "fnum",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directive {
+    // As written, `%-8.2f` and so on
+    pub text: String,
+    pub left: bool,
+    pub width: usize,
+    pub precision: Option<usize>,
+    pub conversion: Conversion,
+}
+
+impl Directive {
+    // The argument written out, padded to the width
+    pub fn pad(&self, written: &str) -> String {
+        let written: String = match (self.conversion, self.precision) {
+            (Conversion::Text, Some(precision)) => written.chars().take(precision).collect(),
+            _ => written.to_owned(),
+        };
+        let padding = " ".repeat(self.width.saturating_sub(written.chars().count()));
+        if self.left { written + &padding } else { padding + &written }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece {
+    Text(String),
+    Directive(Directive),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    // What follows a `%` up to where it stopped being a directive
+    BadDirective(String),
+    Count { directives: usize, arguments: usize },
+    // The argument, from 0, isn't what its directive takes, which needs what is said
+    Argument { index: usize, directive: String, needs: &'static str },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::BadDirective(text) => write!(f, "`{}` isn't a directive, which ends in `s`, `d` or `f`", text),
+            FormatError::Count { directives, arguments } => write!(
+                f,
+                "the format has {} directive{} but is given {} argument{}",
+                directives,
+                if *directives == 1 { "" } else { "s" },
+                arguments,
+                if *arguments == 1 { "" } else { "s" },
+            ),
+            FormatError::Argument { index, directive, needs } =>
+                write!(f, "argument {} is for `{}`, which needs {}", index + 1, directive, needs),
+        }
+    }
+}
+
+pub fn parse(format: &str) -> Result<Vec<Piece>, FormatError> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            text.push('%');
+            continue;
+        }
+        let mut written = String::from("%");
+        let left = chars.next_if_eq(&'-').is_some();
+        if left {
+            written.push('-');
+        }
+        let width = number(&mut chars, &mut written).unwrap_or(0);
+        let precision = match chars.next_if_eq(&'.') {
+            Some(_) => {
+                written.push('.');
+                Some(number(&mut chars, &mut written).unwrap_or(0))
+            }
+            None => None,
+        };
+        let conversion = match chars.next() {
+            Some('s') => Conversion::Text,
+            Some('d') => Conversion::Whole,
+            Some('f') => Conversion::Fixed,
+            other => {
+                written.extend(other);
+                return Err(FormatError::BadDirective(written));
+            }
+        };
+        written.push(match conversion {
+            Conversion::Text => 's',
+            Conversion::Whole => 'd',
+            Conversion::Fixed => 'f',
+        });
+        if !text.is_empty() {
+            pieces.push(Piece::Text(core::mem::take(&mut text)));
+        }
+        pieces.push(Piece::Directive(Directive { text: written, left, width, precision, conversion }));
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+fn number(chars: &mut Peekable<Chars<'_>>, written: &mut String) -> Option<usize> {
+    let mut n = None;
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        written.push(digit);
+        n = Some(n.unwrap_or(0usize).saturating_mul(10).saturating_add(digit as usize - '0' as usize));
+    }
+    n
+}
+
+// Whether there is an argument of the right constructor for each directive, as far as
+// the constructor of each is known
+pub fn check(pieces: &[Piece], constructors: &[Option<&str>]) -> Result<(), FormatError> {
+    let directives: Vec<&Directive> = pieces.iter()
+        .filter_map(|piece| match piece {
+            Piece::Directive(directive) => Some(directive),
+            Piece::Text(_) => None,
+        })
+        .collect();
+    if directives.len() != constructors.len() {
+        return Err(FormatError::Count { directives: directives.len(), arguments: constructors.len() });
+    }
+    for (index, (directive, constructor)) in directives.iter().zip(constructors).enumerate() {
+        let expected = directive.conversion.constructor();
+        if constructor.is_some_and(|constructor| constructor != expected) {
+            let needs = if expected == "fstr" { "`fstr` of some text" } else { "`fnum` of a number" };
+            return Err(FormatError::Argument { index, directive: directive.text.clone(), needs });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_directives_between_text() {
+        let pieces = parse("%-6s|%5.2f%% %d").unwrap();
+        let directives: Vec<_> = pieces.iter().filter_map(|piece| match piece {
+            Piece::Directive(d) => Some((d.text.as_str(), d.left, d.width, d.precision)),
+            Piece::Text(_) => None,
+        }).collect();
+        assert_eq!(directives, [("%-6s", true, 6, None), ("%5.2f", false, 5, Some(2)), ("%d", false, 0, None)]);
+        assert_eq!(pieces[1], Piece::Text("|".to_owned()));
+        assert_eq!(pieces[3], Piece::Text("% ".to_owned()));
+        assert_eq!(parse("50%").unwrap_err().to_string(), "`%` isn't a directive, which ends in `s`, `d` or `f`");
+        assert_eq!(parse("%5x").unwrap_err(), FormatError::BadDirective("%5x".to_owned()));
+    }
+
+    #[test]
+    fn should_match_arguments_to_directives() {
+        let pieces = parse("%s: %d").unwrap();
+        assert_eq!(check(&pieces, &[Some("fstr"), None]), Ok(()));
+        assert_eq!(check(&pieces, &[Some("fstr")]).unwrap_err().to_string(), "the format has 2 directives but is given 1 argument");
+        assert_eq!(
+            check(&pieces, &[Some("fstr"), Some("fstr")]).unwrap_err().to_string(),
+            "argument 2 is for `%d`, which needs `fnum` of a number",
+        );
+    }
+}
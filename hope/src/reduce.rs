@@ -0,0 +1,157 @@
+use core::ops::Range;
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+
+// Cutting a program down to what still makes something happen, for a bug report. Whole
+// declarations go first, as many at a time as can be, then expressions are replaced
+// by their parts, and both again until neither makes the program any smaller. A
+// change is kept when `holds` says the program still does what it did, which it is
+// asked of the whole new source. While the source parses, only changes that leave it
+// parsing are tried, so the positions of the next round can be found
+pub fn reduce(source: &str, parse: impl Fn(&str) -> Option<Program>, mut holds: impl FnMut(&str) -> bool) -> String {
+    let mut best = source.to_owned();
+    loop {
+        let before = best.len();
+        best = remove_decls(best, &parse, &mut holds);
+        best = simplify(best, &parse, &mut holds);
+        if best.len() >= before {
+            return best;
+        }
+    }
+}
+
+fn remove_decls(mut best: String, parse: &impl Fn(&str) -> Option<Program>, holds: &mut impl FnMut(&str) -> bool) -> String {
+    let parses = parse(&best).is_some();
+    let mut units = units(&best, parse);
+    let mut size = units.len().div_ceil(2).max(1);
+    loop {
+        let mut i = 0;
+        while i < units.len() {
+            let end = (i + size).min(units.len());
+            let candidate = format!("{}{}", &best[..units[i].start], &best[units[end - 1].end..]);
+            if (!parses || parse(&candidate).is_some()) && holds(&candidate) {
+                best = candidate;
+                units = self::units(&best, parse);
+            } else {
+                i += size;
+            }
+        }
+        if size == 1 {
+            return best;
+        }
+        size = size.div_ceil(2);
+    }
+}
+
+// Each declaration with the `;` and the rest of the line after it, or each line of a
+// source that doesn't parse
+fn units(source: &str, parse: &impl Fn(&str) -> Option<Program>) -> Vec<Range<usize>> {
+    let Some(program) = parse(source) else {
+        let mut start = 0;
+        return source.split_inclusive('\n').map(|line| {
+            start += line.len();
+            start - line.len()..start
+        }).collect();
+    };
+    program.decls.iter().map(|decl| {
+        let end = decl.pos.range.end;
+        let rest = &source[end..];
+        let semicolon = rest.find(|c: char| !c.is_whitespace()).filter(|&i| rest[i..].starts_with(';'));
+        let end = semicolon.map_or(end, |i| end + i + 1);
+        let line = source[end..].find(|c: char| c == '\n' || !c.is_whitespace()).filter(|&i| source[end + i..].starts_with('\n'));
+        decl.pos.range.start..line.map_or(end, |i| end + i + 1)
+    }).collect()
+}
+
+fn simplify(mut best: String, parse: &impl Fn(&str) -> Option<Program>, holds: &mut impl FnMut(&str) -> bool) -> String {
+    'again: loop {
+        let Some(program) = parse(&best) else { return best };
+        let mut replacements = Vec::new();
+        for decl in &program.decls {
+            match &decl.kind {
+                DeclKind::Equation(eq) => {
+                    eq.guard.iter().chain([&eq.body]).for_each(|expr| parts(expr, &best, &mut replacements));
+                }
+                DeclKind::Expr(expr) | DeclKind::Write(expr) => parts(expr, &best, &mut replacements),
+                _ => {}
+            }
+        }
+        for (range, text) in replacements {
+            let candidate = format!("{}{}{}", &best[..range.start], text, &best[range.end..]);
+            if candidate.len() < best.len() && parse(&candidate).is_some() && holds(&candidate) {
+                best = candidate;
+                continue 'again;
+            }
+        }
+        return best;
+    }
+}
+
+// What each expression could be replaced with, outermost first: the parts it is made
+// of, in brackets unless they need none
+fn parts(expr: &Expr, source: &str, replacements: &mut Vec<(Range<usize>, String)>) {
+    let children: Vec<&Expr> = match &expr.kind {
+        ExprKind::Var(_) | ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) => Vec::new(),
+        ExprKind::Tuple(items) | ExprKind::List(items) => items.iter().collect(),
+        ExprKind::Apply(f, arg) => vec![f, arg],
+        ExprKind::BinOp(_, l, r) => vec![l, r],
+        ExprKind::If(_, t, e) => vec![t, e],
+        ExprKind::Lambda(rules) => rules.iter().map(|rule| &rule.body).collect(),
+        ExprKind::Let(binding) => vec![&binding.body],
+    };
+    if matches!(expr.kind, ExprKind::List(_)) {
+        replacements.push((expr.pos.range.clone(), "[]".to_owned()));
+    }
+    for child in &children {
+        let text = &source[child.pos.range.clone()];
+        let atomic = matches!(child.kind, ExprKind::Var(_) | ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) | ExprKind::Tuple(_) | ExprKind::List(_));
+        let text = if atomic || bracketed(text) { text.to_owned() } else { format!("({})", text) };
+        replacements.push((expr.pos.range.clone(), text));
+    }
+    children.into_iter().for_each(|child| parts(child, source, replacements));
+}
+
+// Whether the text is all inside one pair of brackets, as `(a + b)` is and `(a) + (b)`
+// isn't
+fn bracketed(text: &str) -> bool {
+    if !text.starts_with('(') {
+        return false;
+    }
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return i == text.len() - 1;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn parse(source: &str) -> Option<Program> {
+        parser::parse_program(source).ok()
+    }
+
+    #[test]
+    fn should_keep_only_what_the_check_needs() {
+        let source = "dec a : num;\n--- a <= 1;\ndec b : num;\n--- b <= 2;\ndec c : num;\n--- c <= if a > 0 then (b + 3) * 4 else 0;\n";
+        let reduced = reduce(source, parse, |candidate| candidate.contains("b + 3"));
+        assert_eq!(reduced, "--- c <= (b + 3);\n");
+        let reduced = reduce(source, parse, |candidate| candidate.contains("dec c") && candidate.contains("--- c"));
+        assert_eq!(reduced, "dec c : num;\n--- c <= b;\n");
+    }
+
+    #[test]
+    fn should_reduce_by_lines_what_doesnt_parse() {
+        let source = "dec a : num;\n--- a <= (;\n1;\n";
+        assert_eq!(reduce(source, parse, |candidate| candidate.contains("(;")), "--- a <= (;\n");
+    }
+}
@@ -0,0 +1,972 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+#[cfg(feature = "repl")]
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+#[cfg(feature = "repl")]
+use rustyline::error::ReadlineError;
+#[cfg(feature = "repl")]
+use rustyline::DefaultEditor;
+use logos::Logos;
+use crate::eval::{Builtins, EvalError, Interpreter, Limits, Value};
+#[cfg(feature = "repl")]
+use crate::help;
+use crate::modules::{self, Exports, Loader, ModuleError};
+#[cfg(feature = "repl")]
+use crate::output;
+use crate::parser::{self, ParseError};
+use crate::prelude;
+use crate::sandbox::{Sandbox, Violation};
+use crate::source;
+use crate::syntax::ast::{Assoc, Decl, DeclKind, Program};
+use crate::syntax::token::{Token, TokenKind};
+use crate::types::{Checker, Scheme, TypeError};
+
+#[cfg(feature = "repl")]
+const PROMPT: &str = ">: ";
+#[cfg(feature = "repl")]
+const CONTINUATION: &str = "   ";
+const MAIN_WORKSPACE: &str = "main";
+// Runs of each expression `:bench` times, after as many again that it doesn't, so that
+// constants and memo tables are already worked out
+#[cfg(feature = "repl")]
+const BENCH_RUNS: usize = 10;
+// Rows of results shown at the prompt unless set otherwise
+#[cfg(feature = "repl")]
+pub const DEFAULT_MAX_LINES: usize = 100;
+
+#[derive(Debug, Clone)]
+pub enum Output {
+    Written(Value),
+    Value(Value, Scheme),
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Written(value) => write!(f, "{}", value),
+            Output::Value(value, ty) => write!(f, "{} : {}", value, ty),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionError {
+    // A script that couldn't be read, with the reason
+    Read(PathBuf, String),
+    Parse(ParseError),
+    Type(Vec<TypeError>),
+    Eval(EvalError),
+    // A module the input uses that couldn't be found, or can't be used with the others
+    Uses(ModuleError),
+    // What went wrong in a module the input uses
+    InModule(PathBuf, Box<SessionError>),
+    // What the input tried that the session's sandbox doesn't allow
+    Sandbox(Vec<Violation>),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Read(path, e) => write!(f, "{}: {}", source::display(path), e),
+            SessionError::Parse(e) => {
+                write!(f, "{}:{}: {}", e.pos().line, e.pos().column, e)?;
+                match e.opened_at() {
+                    Some((opener, open)) => write!(f, "\n{}:{}: note: {} opened here", open.line, open.column, opener),
+                    None => Ok(()),
+                }
+            }
+            SessionError::Type(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}:{}: {}", e.pos().line, e.pos().column, e)?;
+                }
+                Ok(())
+            }
+            SessionError::Eval(e) => match e.pos() {
+                Some(pos) => write!(f, "{}:{}: {}", pos.line, pos.column, e),
+                None => write!(f, "{}", e),
+            },
+            SessionError::Uses(e) => write!(f, "{}:{}: {}", e.pos().line, e.pos().column, e),
+            SessionError::Sandbox(violations) => {
+                for (i, violation) in violations.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}:{}: {}", violation.pos().line, violation.pos().column, violation)?;
+                }
+                Ok(())
+            }
+            SessionError::InModule(path, e) => {
+                for (i, line) in e.to_string().lines().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}:{}", source::display(path), line)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// How long each timed run of an expression took, and the evaluation steps one takes
+#[derive(Debug, Clone)]
+pub struct Bench {
+    pub source: String,
+    pub times: Vec<Duration>,
+    pub steps: u64,
+}
+
+impl Bench {
+    pub fn mean(&self) -> Duration {
+        self.times.iter().sum::<Duration>() / self.times.len().max(1) as u32
+    }
+
+    pub fn median(&self) -> Duration {
+        let mut times = self.times.clone();
+        times.sort();
+        times.get(times.len() / 2).copied().unwrap_or_default()
+    }
+}
+
+// The environment after one input that defined something, with the names it defined
+// and the input itself
+#[derive(Debug, Clone)]
+struct Frame {
+    checker: Checker,
+    interp: Interpreter,
+    defined: Vec<String>,
+    source: String,
+    // Every declaration so far that later input is parsed with, see Parser::with_notation
+    notation: Vec<Decl>,
+    // The files of the modules loaded for `uses`, each once
+    loaded: Vec<PathBuf>,
+    exports: Exports,
+}
+
+// Everything defined so far at the prompt, as a stack of frames. Undoing an input
+// pops its frame, which brings back whatever its definitions shadowed
+#[derive(Debug, Clone)]
+pub struct Session {
+    // Never empty, the first frame is the initial environment
+    frames: Vec<Frame>,
+    modules: Loader,
+    prelude: bool,
+    sandbox: Option<Sandbox>,
+    // Host functions the sandbox left out, whose use is a violation rather than an
+    // unknown name
+    withheld: Vec<String>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let base = Frame {
+            checker: Checker::new(),
+            interp: Interpreter::new(),
+            defined: Vec::new(),
+            source: String::new(),
+            notation: Vec::new(),
+            loaded: Vec::new(),
+            exports: Exports::new(),
+        };
+        Session { frames: vec![base], modules: Loader::new(), prelude: false, sandbox: None, withheld: Vec::new() }
+    }
+
+    // Where to find the modules input uses, which are looked for next to a script or
+    // in the current directory first
+    pub fn with_modules(mut self, modules: Loader) -> Self {
+        self.modules = modules;
+        self
+    }
+
+    // For a session with nothing defined yet. Every later input draws on the same
+    // budget of steps, and each has the time limit to itself
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        assert_eq!(self.frames.len(), 1, "limits are set before anything is defined");
+        self.frames[0].interp = self.frames[0].interp.clone().with_limits(limits);
+        self
+    }
+
+    // For a session with nothing defined yet, as with_limits is. A sandbox without host
+    // functions keeps them out
+    pub fn with_builtins(mut self, builtins: &Builtins) -> Self {
+        assert_eq!(self.frames.len(), 1, "host functions are added before anything is defined");
+        if self.sandbox.is_some_and(|sandbox| !sandbox.host) {
+            self.withheld = builtins.iter().map(|function| function.name.clone()).collect();
+            return self;
+        }
+        let base = &mut self.frames[0];
+        base.checker = base.checker.clone().with_builtins(builtins);
+        base.interp = base.interp.clone().with_builtins(builtins);
+        self
+    }
+
+    // For a session with nothing defined yet, before with_builtins, as Driver::with_sandbox
+    // is for files. Input, or a module it uses, with a violation is refused whole
+    pub fn with_sandbox(self, sandbox: &Sandbox) -> Self {
+        let mut session = self.with_limits(sandbox.limits);
+        session.sandbox = Some(*sandbox);
+        session
+    }
+
+    // Starts the session from the standard prelude, which undoing never takes away
+    pub fn with_prelude(mut self) -> Self {
+        assert_eq!(self.frames.len(), 1, "the prelude is loaded before anything is defined");
+        let program = prelude::program();
+        let base = &mut self.frames[0];
+        base.checker.check(program.clone()).expect("the prelude checks");
+        base.interp.load(&program);
+        base.notation.extend(parser::notation(&program.decls));
+        self.prelude = true;
+        self
+    }
+
+    fn top(&self) -> &Frame {
+        self.frames.last().expect("the initial frame is never undone")
+    }
+
+    // Declarations extend the session only if the whole input checks. Expressions are
+    // evaluated in order, an error stops the rest
+    pub fn submit(&mut self, input: &str) -> Result<Vec<Output>, SessionError> {
+        self.submit_from(input, Path::new("<input>"))
+    }
+
+    // The modules the input uses are looked for as if it were the file from
+    fn submit_from(&mut self, input: &str, from: &Path) -> Result<Vec<Output>, SessionError> {
+        let mut frame = self.top().clone();
+        frame.interp.start_clock();
+        let used = self.load_used(&mut frame, input, from)?;
+        let program = parser::Parser::new(input)
+            .and_then(|parser| parser.with_notation(&frame.notation).parse_program())
+            .map_err(SessionError::Parse)?;
+        self.allowed(from, &program)?;
+        let mut defined = defined_names(&program.decls);
+        defined.extend(used);
+        frame.exports.add(&self.loader(), from, &program).map_err(SessionError::Uses)?;
+
+        let typed = frame.checker.check(program.clone()).map_err(|errors| self.refused(from, errors))?;
+        frame.interp.load(&program);
+        frame.defined = defined;
+        frame.source = input.trim().to_owned();
+        frame.notation.extend(parser::notation(&program.decls));
+
+        let mut outputs = Vec::new();
+        let mut result = Ok(());
+        for decl in typed.decls {
+            let value = match &decl.decl.kind {
+                DeclKind::Write(expr) | DeclKind::Expr(expr) => frame.interp.eval(expr),
+                _ => continue,
+            };
+            match (value, decl.decl.kind, decl.ty) {
+                (Ok(value), DeclKind::Write(_), _) => outputs.push(Output::Written(value)),
+                (Ok(value), _, ty) => outputs.push(Output::Value(value, ty.expect("expressions are given a type"))),
+                (Err(e), ..) => {
+                    result = Err(SessionError::Eval(e));
+                    break;
+                }
+            }
+        }
+
+        // The definitions stand even if evaluating an expression after them failed
+        if !frame.defined.is_empty() {
+            self.frames.push(frame);
+        }
+        result.map(|()| outputs)
+    }
+
+    fn loader(&self) -> Loader {
+        let mut loader = if self.prelude { self.modules.clone().with_provided(prelude::MODULE) } else { self.modules.clone() };
+        if let Some(sandbox) = self.sandbox {
+            loader = loader.with_access(sandbox.modules);
+        }
+        loader
+    }
+
+    // Each `write`, if the sandbox doesn't allow them
+    fn allowed(&self, path: &Path, program: &Program) -> Result<(), SessionError> {
+        if self.sandbox.is_none_or(|sandbox| sandbox.write) {
+            return Ok(());
+        }
+        let writes: Vec<_> = program.decls.iter()
+            .filter(|decl| matches!(decl.kind, DeclKind::Write(_)))
+            .map(|decl| Violation::Write { path: path.to_path_buf(), pos: decl.pos.clone() })
+            .collect();
+        if writes.is_empty() { Ok(()) } else { Err(SessionError::Sandbox(writes)) }
+    }
+
+    // Names that are only unknown because the sandbox left out the host's functions are
+    // violations instead
+    fn refused(&self, path: &Path, errors: Vec<TypeError>) -> SessionError {
+        let host: Vec<_> = errors.iter().filter_map(|e| match e {
+            TypeError::UnknownVariable(name, pos) if self.withheld.contains(name) =>
+                Some(Violation::Host { path: path.to_path_buf(), name: name.clone(), pos: pos.clone() }),
+            _ => None,
+        }).collect();
+        if host.is_empty() { SessionError::Type(errors) } else { SessionError::Sandbox(host) }
+    }
+
+    // Loads the modules the input uses that aren't already, and those they use, into
+    // the frame as Driver does for a file, returning their names
+    fn load_used(&self, frame: &mut Frame, input: &str, from: &Path) -> Result<Vec<String>, SessionError> {
+        let loader = self.loader();
+        let paths = loader.order_used(from, modules::uses_in(input), &frame.loaded)
+            .map_err(|e| Violation::from_module(&e).map_or(SessionError::Uses(e), |violation| SessionError::Sandbox(vec![violation])))?;
+        let mut names = Vec::new();
+        for path in paths {
+            let in_module = |e| SessionError::InModule(path.clone(), Box::new(e));
+            let contents = source::read(&path)
+                .map_err(|e| SessionError::Read(path.clone(), source::describe(&e)))?;
+            let program = parser::Parser::new(&contents)
+                .and_then(|parser| parser.with_notation(&frame.notation).parse_program())
+                .map_err(|e| in_module(SessionError::Parse(e)))?;
+            self.allowed(&path, &program).map_err(in_module)?;
+            frame.exports.add(&loader, &path, &program).map_err(|e| in_module(SessionError::Uses(e)))?;
+            frame.checker.check(program.clone()).map_err(|e| in_module(self.refused(&path, e)))?;
+            frame.interp.load(&program);
+            frame.notation.extend(parser::notation(&program.decls));
+            frame.loaded.push(path.clone());
+            names.push(path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()));
+        }
+        Ok(names)
+    }
+
+    // Evaluates each expression in the input, which has nothing else in it, warmup times
+    // and then runs times more timing each. Nothing is defined by it, and the last `;`
+    // can be left off
+    pub fn bench(&self, input: &str, warmup: usize, runs: usize) -> Result<Vec<Bench>, SessionError> {
+        let input = if is_complete(input) { input.to_owned() } else { format!("{};", input) };
+        let program = parser::Parser::new(&input)
+            .and_then(|parser| parser.with_notation(&self.top().notation).parse_program())
+            .map_err(SessionError::Parse)?;
+        let mut checker = self.top().checker.clone();
+        let typed = checker.check(program).map_err(SessionError::Type)?;
+
+        let interp = &self.top().interp;
+        interp.start_clock();
+        let mut benches = Vec::new();
+        for decl in &typed.decls {
+            let DeclKind::Expr(expr) = &decl.decl.kind else {
+                let pos = decl.decl.pos.clone();
+                return Err(SessionError::Parse(ParseError::UnexpectedToken { expected: "an expression", found: "a declaration", pos }));
+            };
+            for _ in 0..warmup {
+                interp.eval(expr).map_err(SessionError::Eval)?;
+            }
+            let mut bench = Bench { source: source_of(&input, &decl.decl), times: Vec::new(), steps: 0 };
+            for _ in 0..runs {
+                let (steps, start) = (interp.steps(), Instant::now());
+                interp.eval(expr).map_err(SessionError::Eval)?;
+                bench.times.push(start.elapsed());
+                bench.steps = interp.steps() - steps;
+            }
+            benches.push(bench);
+        }
+        Ok(benches)
+    }
+
+    // Removes the most recent inputs' definitions, returning the names they defined
+    pub fn undo(&mut self, count: usize) -> Vec<String> {
+        let keep = self.frames.len().saturating_sub(count).max(1);
+        self.frames.drain(keep..).rev().flat_map(|frame| frame.defined).collect()
+    }
+
+    // `name : type` for every value whose name starts with prefix, sorted by name
+    pub fn browse(&self, prefix: &str) -> Vec<String> {
+        let mut values: Vec<_> = self.top().checker.values().filter(|(name, _)| name.starts_with(prefix)).collect();
+        values.sort_by_key(|(name, _)| *name);
+        values.iter().map(|(name, scheme)| format!("{} : {}", name, scheme)).collect()
+    }
+
+    pub fn type_of(&self, name: &str) -> Option<&Scheme> {
+        self.top().checker.lookup(name)
+    }
+
+    pub fn kind(&self, name: &str) -> Option<String> {
+        let arity = self.top().checker.type_arity(name)?;
+        Some(format!("{} : {}", name, vec!["type"; arity + 1].join(" -> ")))
+    }
+
+    // In the syntax of the declaration that would give the operator its fixity
+    pub fn fixity(&self, op: &str) -> Option<String> {
+        let declared = self.top().notation.iter().rev().find_map(|decl| match &decl.kind {
+            DeclKind::Infix { assoc, ops, prec } if ops.iter().any(|o| o.name == op) => Some((*prec, *assoc)),
+            _ => None,
+        });
+        let (prec, assoc) = declared.or_else(|| parser::standard_fixity(op))?;
+        let keyword = match assoc {
+            Assoc::Left => "infix",
+            Assoc::Right => "infixr",
+        };
+        Some(format!("{} {} : {};", keyword, op, prec))
+    }
+
+    // Runs a file's declarations as if they had been typed at the prompt in one go
+    pub fn script(&mut self, path: &Path) -> Result<Vec<Output>, SessionError> {
+        let contents = source::read(path)
+            .map_err(|e| SessionError::Read(path.to_path_buf(), source::describe(&e)))?;
+        self.submit_from(&contents, path)
+    }
+
+    // The inputs that make up the session, in order, as a script that rebuilds it
+    pub fn source(&self) -> String {
+        self.frames[1..].iter().map(|frame| format!("{}\n", frame.source)).collect()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.source())
+    }
+}
+
+// Named sessions, one of which is in use at the prompt
+#[derive(Debug, Clone)]
+pub struct Workspaces {
+    current: String,
+    sessions: BTreeMap<String, Session>,
+    // What main and every loaded workspace start from
+    base: Session,
+}
+
+impl Default for Workspaces {
+    fn default() -> Self {
+        Workspaces::new()
+    }
+}
+
+impl Workspaces {
+    pub fn new() -> Self {
+        Workspaces::with_base(Session::new())
+    }
+
+    pub fn with_base(base: Session) -> Self {
+        let sessions = BTreeMap::from([(MAIN_WORKSPACE.to_owned(), base.clone())]);
+        Workspaces { current: MAIN_WORKSPACE.to_owned(), sessions, base }
+    }
+
+    // A new session with nothing in it but what every workspace starts from
+    pub fn fresh(&self) -> Session {
+        self.base.clone()
+    }
+
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    pub fn session(&mut self) -> &mut Session {
+        self.sessions.get_mut(&self.current).expect("the current workspace exists")
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.sessions.keys().map(String::as_str)
+    }
+
+    // Adds a workspace and switches to it, returning false if the name is taken
+    pub fn create(&mut self, name: &str, session: Session) -> bool {
+        if self.sessions.contains_key(name) {
+            return false;
+        }
+        self.sessions.insert(name.to_owned(), session);
+        self.current = name.to_owned();
+        true
+    }
+
+    pub fn switch(&mut self, name: &str) -> bool {
+        let exists = self.sessions.contains_key(name);
+        if exists {
+            self.current = name.to_owned();
+        }
+        exists
+    }
+}
+
+fn source_of(input: &str, decl: &Decl) -> String {
+    input.get(decl.pos.range.clone()).unwrap_or_default().trim().to_owned()
+}
+
+fn defined_names(decls: &[Decl]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut add = |name: &str| {
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_owned());
+        }
+    };
+    for decl in decls {
+        match &decl.kind {
+            DeclKind::TypeVar(idents) | DeclKind::Dec { names: idents, .. } | DeclKind::Infix { ops: idents, .. } => {
+                idents.iter().for_each(|ident| add(&ident.name));
+            }
+            DeclKind::AbsType(head) | DeclKind::Type { head, .. } => add(&head.name.name),
+            DeclKind::Data { head, constructors } => {
+                add(&head.name.name);
+                constructors.iter().for_each(|c| add(&c.name.name));
+            }
+            DeclKind::Equation(eq) => add(&eq.name.name),
+            DeclKind::Syntax(def) => add(&def.name.name),
+            _ => {}
+        }
+    }
+    names
+}
+
+// Input is only submitted once it ends a declaration, so it can span several lines.
+// That is when its last token is `;`, so one in a comment or a string doesn't count
+// and a comment after it doesn't hide it. Input that doesn't lex is submitted too,
+// more lines wouldn't make it lex
+pub fn is_complete(input: &str) -> bool {
+    let mut last = None;
+    for token in Token::lexer(input) {
+        match token {
+            Ok(token) => last = Some(token.kind()),
+            Err(_) => return true,
+        }
+    }
+    last == Some(TokenKind::SemiColon)
+}
+
+#[cfg(feature = "repl")]
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".hope_history"))
+}
+
+// How results are shown at the prompt. Those longer than `max_lines` rows of the
+// terminal are cut short, or with `page` shown whole through $PAGER
+#[cfg(feature = "repl")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Paging {
+    pub max_lines: Option<usize>,
+    pub page: bool,
+}
+
+#[cfg(feature = "repl")]
+impl Default for Paging {
+    fn default() -> Self {
+        Paging { max_lines: Some(DEFAULT_MAX_LINES), page: false }
+    }
+}
+
+#[cfg(feature = "repl")]
+impl fmt::Display for Paging {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let page = if self.page { "on" } else { "off" };
+        match self.max_lines {
+            Some(lines) => write!(f, "page {}, lines {}", page, lines),
+            None => write!(f, "page {}, lines off", page),
+        }
+    }
+}
+
+#[cfg(feature = "repl")]
+fn report(result: Result<Vec<Output>, SessionError>, paging: &Paging) {
+    match result {
+        Ok(outputs) => show(&outputs.iter().map(|output| format!("{}\n", output)).collect::<String>(), paging),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+#[cfg(feature = "repl")]
+fn show(text: &str, paging: &Paging) {
+    let cut = paging.max_lines.and_then(|rows| output::truncate(text, rows, output::columns()));
+    let Some(cut) = cut else { return print!("{}", text) };
+    if paging.page && io::stdout().is_terminal() {
+        match output::page(text) {
+            Ok(()) => return,
+            Err(e) => eprintln!("can't start the pager: {}", e),
+        }
+    }
+    print!("{}", cut);
+}
+
+// `:set page on|off` and `:set lines <count>|off`
+#[cfg(feature = "repl")]
+fn set(paging: &mut Paging, arg: &str) {
+    let args: Vec<_> = arg.split_whitespace().collect();
+    match args[..] {
+        [] => println!("{}", paging),
+        ["page", "on"] => paging.page = true,
+        ["page", "off"] => paging.page = false,
+        ["lines", "off"] => paging.max_lines = None,
+        ["lines", count] if count.parse::<usize>().is_ok() => paging.max_lines = count.parse().ok(),
+        _ => eprintln!("usage: :set [page on|off | lines <count>|off]"),
+    }
+}
+
+// One line per expression, with how many times slower than the first each one is
+#[cfg(feature = "repl")]
+fn report_bench(result: Result<Vec<Bench>, SessionError>) {
+    let benches = match result {
+        Ok(benches) => benches,
+        Err(e) => return eprintln!("{}", e),
+    };
+    let width = benches.iter().map(|b| b.source.len()).max().unwrap_or(0);
+    let first = benches.first().map(|b| b.median().as_secs_f64());
+    for bench in &benches {
+        print!("{:width$}  mean {:>10.3?}  median {:>10.3?}  {} steps", bench.source, bench.mean(), bench.median(), bench.steps);
+        match first {
+            Some(first) if benches.len() > 1 && first > 0.0 => println!("  {:.2}x", bench.median().as_secs_f64() / first),
+            _ => println!(),
+        }
+    }
+}
+
+#[cfg(feature = "repl")]
+fn report_undone(names: Vec<String>) {
+    if names.is_empty() {
+        println!("nothing to undo");
+    } else {
+        println!("undid {}", names.join(", "));
+    }
+}
+
+// `:workspace` and its subcommands. A new workspace starts as a copy of the current
+// one, a loaded one from nothing but the script
+#[cfg(feature = "repl")]
+fn workspace(workspaces: &mut Workspaces, arg: &str, paging: &Paging) {
+    let args: Vec<_> = arg.split_whitespace().collect();
+    match args[..] {
+        [] => {
+            for name in workspaces.names() {
+                let marker = if name == workspaces.current() { "*" } else { " " };
+                println!("{} {}", marker, name);
+            }
+        }
+        ["new", name] => {
+            let session = workspaces.session().clone();
+            if !workspaces.create(name, session) {
+                eprintln!("workspace {} already exists", name);
+            }
+        }
+        ["switch", name] => {
+            if !workspaces.switch(name) {
+                eprintln!("no workspace named {}", name);
+            }
+        }
+        ["load", name, path] => {
+            let mut session = workspaces.fresh();
+            let result = session.script(Path::new(path));
+            let failed = result.is_err();
+            report(result, paging);
+            if !failed && !workspaces.create(name, session) {
+                eprintln!("workspace {} already exists", name);
+            }
+        }
+        _ => eprintln!("usage: :workspace [new <name> | switch <name> | load <name> <file>]"),
+    }
+}
+
+// How lines typed at the prompt are being collected
+#[cfg(feature = "repl")]
+enum Mode {
+    // Until a line ends a declaration
+    Line,
+    // Until `:end` or end of file, then submitted as one unit, so declarations can
+    // refer to each other
+    Paste,
+}
+
+// The session starts with the given files loaded, in order, after the prelude if
+// there is to be one
+#[cfg(feature = "repl")]
+pub fn run(files: &[impl AsRef<Path>], prelude: bool, modules: Loader, mut paging: Paging) -> rustyline::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_file();
+    if let Some(path) = &history {
+        // There is no history the first time round
+        let _ = editor.load_history(path);
+    }
+
+    let base = Session::new().with_modules(modules);
+    let base = if prelude { base.with_prelude() } else { base };
+    let mut workspaces = Workspaces::with_base(base);
+    for file in files {
+        report(workspaces.session().script(file.as_ref()), &paging);
+    }
+
+    let mut input = String::new();
+    let mut mode = Mode::Line;
+    loop {
+        // Only workspaces other than main are named in the prompt
+        let prompt = match mode {
+            Mode::Line if input.is_empty() && workspaces.current() == MAIN_WORKSPACE => PROMPT.to_owned(),
+            Mode::Line if input.is_empty() => format!("{} {}", workspaces.current(), PROMPT),
+            _ => CONTINUATION.to_owned(),
+        };
+        match (editor.readline(&prompt), &mode) {
+            (Ok(line), Mode::Paste) => {
+                if line.trim() == ":end" {
+                    editor.add_history_entry(input.trim_end())?;
+                    report(workspaces.session().submit(&input), &paging);
+                    input.clear();
+                    mode = Mode::Line;
+                } else {
+                    input.push_str(&line);
+                    input.push('\n');
+                }
+            }
+            (Ok(line), Mode::Line) if input.is_empty() && line.trim_start().starts_with(':') => {
+                editor.add_history_entry(line.trim())?;
+                let (command, arg) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+                match command {
+                    ":quit" | ":q" => break,
+                    ":help" if arg.trim().is_empty() => print!("{}", help::index()),
+                    ":help" => match help::topic(arg.trim()) {
+                        Some(topic) => println!("{}", topic),
+                        None => eprintln!("no help topic {}, see :help", arg.trim()),
+                    },
+                    ":paste" => {
+                        println!("(pasting, finish with :end or ^D)");
+                        mode = Mode::Paste;
+                    }
+                    ":script" if !arg.trim().is_empty() => report(workspaces.session().script(Path::new(arg.trim())), &paging),
+                    ":script" => eprintln!("usage: :script <file>"),
+                    ":bench" if arg.trim().is_empty() => eprintln!("usage: :bench <expr>[; <expr>...]"),
+                    ":bench" => report_bench(workspaces.session().bench(arg, BENCH_RUNS, BENCH_RUNS)),
+                    ":undo" => match arg.trim().parse::<usize>() {
+                        Ok(count) => report_undone(workspaces.session().undo(count)),
+                        Err(_) if arg.trim().is_empty() => report_undone(workspaces.session().undo(1)),
+                        Err(_) => eprintln!("usage: :undo [count]"),
+                    },
+                    ":workspace" => workspace(&mut workspaces, arg, &paging),
+                    ":set" => set(&mut paging, arg),
+                    ":save" if !arg.trim().is_empty() => {
+                        if let Err(e) = workspaces.session().save(Path::new(arg.trim())) {
+                            eprintln!("{}: {}", arg.trim(), e);
+                        }
+                    }
+                    ":save" => eprintln!("usage: :save <file>"),
+                    ":browse" => workspaces.session().browse(arg.trim()).iter().for_each(|line| println!("{}", line)),
+                    ":kind" | ":fixity" if arg.trim().is_empty() => eprintln!("usage: {} <name>", command),
+                    ":kind" => match workspaces.session().kind(arg.trim()) {
+                        Some(kind) => println!("{}", kind),
+                        None => eprintln!("unknown type {}", arg.trim()),
+                    },
+                    ":fixity" => match workspaces.session().fixity(arg.trim()) {
+                        Some(fixity) => println!("{}", fixity),
+                        None => eprintln!("{} is not an operator", arg.trim()),
+                    },
+                    _ => eprintln!("unknown command {}", command),
+                }
+            }
+            (Ok(line), Mode::Line) => {
+                if input.is_empty() && matches!(line.trim(), "exit" | "exit;") {
+                    break;
+                }
+                input.push_str(&line);
+                input.push('\n');
+                if !is_complete(&input) {
+                    continue;
+                }
+
+                editor.add_history_entry(input.trim_end())?;
+                report(workspaces.session().submit(&input), &paging);
+                input.clear();
+            }
+            (Err(ReadlineError::Eof), Mode::Paste) => {
+                report(workspaces.session().submit(&input), &paging);
+                input.clear();
+                mode = Mode::Line;
+            }
+            // ^C abandons the current input, ^D leaves
+            (Err(ReadlineError::Interrupted), _) => {
+                input.clear();
+                mode = Mode::Line;
+            }
+            (Err(ReadlineError::Eof), Mode::Line) => break,
+            (Err(e), _) => return Err(e),
+        }
+    }
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_extend_the_session_with_definitions() {
+        let mut session = Session::new();
+        session.submit("typevar alpha;\ndec twice : (alpha -> alpha) -> alpha -> alpha;").unwrap();
+        session.submit("--- twice f x <= f (f x);").unwrap();
+
+        let outputs = session.submit("twice (lambda n => n * 3) 2;").unwrap();
+        assert_eq!(outputs.iter().map(|o| o.to_string()).collect::<Vec<_>>(), ["18 : num"]);
+
+        // A failing input leaves the session as it was
+        assert!(matches!(session.submit("dec bad : num;\n--- bad <= true;"), Err(SessionError::Type(_))));
+        assert!(matches!(session.submit("bad;"), Err(SessionError::Type(_))));
+    }
+
+    #[test]
+    fn should_wait_for_the_end_of_a_declaration() {
+        assert!(!is_complete("dec f : num\n"));
+        assert!(is_complete("dec f : num\n  -> num;\n"));
+        assert!(is_complete("dec f : num -> num; ! the type\n"));
+        assert!(!is_complete("dec f : num ! ends here;\n"));
+        assert!(!is_complete("write \"a;\" <> \";\""));
+        assert!(is_complete("write \"unterminated;"));
+    }
+
+    #[test]
+    fn should_bench_expressions_without_defining_anything() {
+        let mut session = Session::new();
+        session.submit("dec count : num -> num;\n--- count 0 <= 0;\n--- count n <= 1 + count (n - 1);").unwrap();
+        let benches = session.bench("count 10; count 100", 1, 3).unwrap();
+        let found: Vec<_> = benches.iter().map(|b| (b.source.as_str(), b.times.len())).collect();
+        assert_eq!(found, [("count 10", 3), ("count 100", 3)]);
+        assert!(benches[0].steps > 0 && benches[1].steps > 5 * benches[0].steps);
+        assert!(benches[0].median() <= *benches[0].times.iter().max().unwrap());
+
+        assert!(matches!(session.bench("dec x : num;", 1, 3), Err(SessionError::Parse(_))));
+        assert!(matches!(session.bench("count true", 1, 3), Err(SessionError::Type(_))));
+        assert!(matches!(session.submit("x;"), Err(SessionError::Type(_))));
+    }
+
+    #[test]
+    fn should_submit_mutually_recursive_definitions_together() {
+        let mut session = Session::new();
+        let block = "dec even, odd : num -> bool;\n\
+            --- even 0 <= true;\n\
+            --- even n <= odd (n - 1);\n\
+            --- odd 0 <= false;\n\
+            --- odd n <= even (n - 1);\n\
+            even 10;";
+        let outputs = session.submit(block).unwrap();
+        assert_eq!(outputs.iter().map(|o| o.to_string()).collect::<Vec<_>>(), ["true : bool"]);
+    }
+
+    #[test]
+    fn should_run_scripts_in_the_session() {
+        let path = std::env::temp_dir().join(format!("hope-script-{}.hop", std::process::id()));
+        std::fs::write(&path, "dec x : num;\n--- x <= 41;\nwrite x + 1;").unwrap();
+        let mut session = Session::new();
+        let outputs = session.script(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(outputs.unwrap().iter().map(|o| o.to_string()).collect::<Vec<_>>(), ["42"]);
+        assert_eq!(session.submit("x;").unwrap()[0].to_string(), "41 : num");
+        assert!(matches!(session.script(&path), Err(SessionError::Read(..))));
+    }
+
+    #[test]
+    fn should_describe_the_environment() {
+        let mut session = Session::new();
+        session.submit("typevar alpha;\ndata tree alpha == leaf ++ node (tree alpha # alpha # tree alpha);").unwrap();
+
+        assert_eq!(session.browse("n"), [
+            "nil : list alpha",
+            "node : tree alpha # alpha # tree alpha -> tree alpha",
+            "not : bool -> bool",
+        ]);
+        assert_eq!(session.kind("tree").unwrap(), "tree : type -> type");
+        assert_eq!(session.kind("num").unwrap(), "num : type");
+        assert_eq!(session.fixity("::").unwrap(), "infixr :: : 5;");
+        assert_eq!(session.fixity("nope"), None);
+    }
+
+    #[test]
+    fn should_parse_later_input_with_declared_operators() {
+        let mut session = Session::new();
+        session.submit("infix plus : 6;\ndec plus : num # num -> num;\n--- x plus y <= x + y;").unwrap();
+        let outputs = session.submit("1 plus 2 * 3;").unwrap();
+        assert_eq!(outputs.iter().map(|o| o.to_string()).collect::<Vec<_>>(), ["7 : num"]);
+        assert_eq!(session.fixity("plus").unwrap(), "infix plus : 6;");
+    }
+
+    #[test]
+    fn should_undo_to_the_shadowed_definition() {
+        let mut session = Session::new();
+        session.submit("dec x : num;\n--- x <= 1;").unwrap();
+        session.submit("dec x : bool;\n--- x <= true;").unwrap();
+        session.submit("x;").unwrap();
+        assert_eq!(session.submit("x;").unwrap()[0].to_string(), "true : bool");
+
+        assert_eq!(session.undo(1), ["x"]);
+        assert_eq!(session.submit("x;").unwrap()[0].to_string(), "1 : num");
+
+        assert_eq!(session.undo(5), ["x"]);
+        assert!(session.undo(1).is_empty());
+        assert!(matches!(session.submit("x;"), Err(SessionError::Type(_))));
+    }
+
+    #[test]
+    fn should_keep_workspaces_apart() {
+        let mut workspaces = Workspaces::new();
+        workspaces.session().submit("dec x : num;\n--- x <= 1;").unwrap();
+
+        let fork = workspaces.session().clone();
+        assert!(workspaces.create("experiments", fork));
+        assert!(!workspaces.create("main", Session::new()));
+        workspaces.session().submit("dec x : num;\n--- x <= 2;").unwrap();
+        assert_eq!(workspaces.session().submit("x;").unwrap()[0].to_string(), "2 : num");
+
+        assert!(workspaces.switch("main"));
+        assert!(!workspaces.switch("nope"));
+        assert_eq!(workspaces.session().submit("x;").unwrap()[0].to_string(), "1 : num");
+        assert_eq!(workspaces.names().collect::<Vec<_>>(), ["experiments", "main"]);
+    }
+
+    #[test]
+    fn should_save_sessions_as_scripts() {
+        let mut session = Session::new();
+        session.submit("dec x : num;\n--- x <= 1;\n").unwrap();
+        session.submit("x + 1;").unwrap();
+        session.submit("dec y : num;\n--- y <= x + 1;").unwrap();
+        assert_eq!(session.source(), "dec x : num;\n--- x <= 1;\ndec y : num;\n--- y <= x + 1;\n");
+
+        let path = std::env::temp_dir().join(format!("hope-session-{}.hop", std::process::id()));
+        session.save(&path).unwrap();
+        let mut loaded = Session::new();
+        let outputs = loaded.script(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(outputs.unwrap().is_empty());
+        assert_eq!(loaded.submit("y;").unwrap()[0].to_string(), "2 : num");
+    }
+
+    #[test]
+    fn should_keep_the_prelude_past_undoing_everything() {
+        let mut workspaces = Workspaces::with_base(Session::new().with_prelude());
+        let session = workspaces.session();
+        session.submit("dec xs : list num;\n--- xs <= [1, 2] <> [3];").unwrap();
+        assert_eq!(session.submit("length xs;").unwrap()[0].to_string(), "3 : num");
+        assert_eq!(session.source(), "dec xs : list num;\n--- xs <= [1, 2] <> [3];\n");
+
+        session.undo(10);
+        assert_eq!(session.submit("map (\\x => x + 1) [1];").unwrap()[0].to_string(), "[2] : list num");
+        assert!(workspaces.fresh().submit("length [1];").is_ok());
+    }
+
+    #[test]
+    fn should_load_the_modules_input_uses() {
+        let mut session = Session::new().with_prelude();
+        assert!(matches!(session.submit("uses Nope;"), Err(SessionError::Uses(ModuleError::NotFound { .. }))));
+        session.submit("uses Char;").unwrap();
+        assert_eq!(session.submit("isdigit (chr 55);").unwrap()[0].to_string(), "true : bool");
+        assert_eq!(session.undo(1), ["Char"]);
+        assert!(matches!(session.submit("isdigit (chr 55);"), Err(SessionError::Type(_))));
+
+        let dir = std::env::temp_dir().join(format!("hope-session-{}-uses", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Leak.hop"), "dec leak : num;\n--- leak <= 42;\n").unwrap();
+        std::fs::write(dir.join("u.hop"), "uses Leak;\nwrite leak;\n").unwrap();
+        std::fs::write(dir.join("Broken.hop"), "dec broken : num;\n--- broken <= true;\n").unwrap();
+        let written = session.script(&dir.join("u.hop"));
+        let broken = Session::new().with_modules(Loader::new().with_search_path([dir.clone()])).submit("uses Broken;");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(written.unwrap()[0].to_string(), "42");
+        let Err(SessionError::InModule(path, e)) = broken else { panic!("{:?}", broken) };
+        assert!(path.ends_with("Broken.hop") && matches!(*e, SessionError::Type(_)));
+    }
+}
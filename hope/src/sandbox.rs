@@ -0,0 +1,92 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::eval::Limits;
+use crate::modules::{Access, ModuleError};
+use crate::source;
+use crate::syntax::token::Pos;
+
+// What a program run for someone else may do, see Driver::with_sandbox. Anything else
+// it tries is a Violation, found before it runs. Memory is bounded by the depth limit,
+// which is how deep evaluation can nest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sandbox {
+    pub name: &'static str,
+    pub limits: Limits,
+    // Whether `write` is allowed
+    pub write: bool,
+    // Whether the host functions the driver was given are registered
+    pub host: bool,
+    pub modules: Access,
+}
+
+pub const SANDBOXES: [Sandbox; 3] = [
+    Sandbox {
+        name: "pure",
+        limits: Limits { steps: Some(10_000_000), depth: Some(10_000), time: Some(Duration::from_secs(5)) },
+        write: false,
+        host: false,
+        modules: Access::Library,
+    },
+    Sandbox {
+        name: "io",
+        limits: Limits { steps: Some(100_000_000), depth: Some(100_000), time: Some(Duration::from_secs(30)) },
+        write: true,
+        host: true,
+        modules: Access::Local,
+    },
+    Sandbox { name: "full", limits: Limits { steps: None, depth: None, time: None }, write: true, host: true, modules: Access::All },
+];
+
+pub fn sandbox(name: &str) -> Option<&'static Sandbox> {
+    SANDBOXES.iter().find(|sandbox| sandbox.name == name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    Write { path: PathBuf, pos: Pos },
+    // A use of a host function, which isn't registered
+    Host { path: PathBuf, name: String, pos: Pos },
+    // A `uses` of a module in a file outside what the sandbox can read
+    Module { path: PathBuf, name: String, found: PathBuf, pos: Pos },
+}
+
+impl Violation {
+    pub fn path(&self) -> &Path {
+        match self {
+            Violation::Write { path, .. } | Violation::Host { path, .. } | Violation::Module { path, .. } => path,
+        }
+    }
+
+    pub fn pos(&self) -> &Pos {
+        match self {
+            Violation::Write { pos, .. } | Violation::Host { pos, .. } | Violation::Module { pos, .. } => pos,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Violation::Write { .. } => "E0601",
+            Violation::Host { .. } => "E0602",
+            Violation::Module { .. } => "E0603",
+        }
+    }
+
+    pub fn from_module(e: &ModuleError) -> Option<Self> {
+        match e {
+            ModuleError::Denied { path, name, found, pos } =>
+                Some(Violation::Module { path: path.clone(), name: name.clone(), found: found.clone(), pos: pos.clone() }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Write { .. } => write!(f, "this sandbox doesn't allow `write`"),
+            Violation::Host { name, .. } => write!(f, "`{}` is a host function, which this sandbox doesn't allow", name),
+            Violation::Module { name, found, .. } => write!(f, "module `{}` is {}, which this sandbox can't read", name, source::display(found)),
+        }
+    }
+}
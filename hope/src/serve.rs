@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::any::Any;
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+use serde_json::{json, Value as Json};
+use crate::eval::{Builtins, Limits};
+use crate::modules::{Access, Loader};
+use crate::repl::{Output, Session, SessionError};
+use crate::sandbox::Sandbox;
+use crate::source;
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+// Open sessions are kept until closed, so their number is capped
+pub const MAX_SESSIONS: usize = 64;
+
+// What a session may do, the sandbox it runs in and how much source it can be given.
+// Steps are shared by everything submitted to the session, the time limit applies to
+// each submission and the input limit to each submission's source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Profile {
+    pub sandbox: Sandbox,
+    // Bytes of source
+    pub input: Option<usize>,
+}
+
+impl Profile {
+    pub const fn name(&self) -> &'static str {
+        self.sandbox.name
+    }
+}
+
+pub const PROFILES: [Profile; 3] = [
+    Profile {
+        sandbox: Sandbox { name: "unlimited", limits: Limits { steps: None, depth: None, time: None }, write: true, host: true, modules: Access::All },
+        input: None,
+    },
+    Profile {
+        sandbox: Sandbox {
+            name: "playground",
+            limits: Limits { steps: Some(10_000_000), depth: Some(100_000), time: Some(Duration::from_secs(5)) },
+            write: true,
+            host: false,
+            modules: Access::Library,
+        },
+        input: Some(1 << 20),
+    },
+    // Graded programs are judged on the values of their expressions alone
+    Profile {
+        sandbox: Sandbox {
+            name: "grading",
+            limits: Limits { steps: Some(1_000_000), depth: Some(10_000), time: Some(Duration::from_secs(2)) },
+            write: false,
+            host: false,
+            modules: Access::Library,
+        },
+        input: Some(64 << 10),
+    },
+];
+
+pub fn profile(name: &str) -> Option<&'static Profile> {
+    PROFILES.iter().find(|profile| profile.name() == name)
+}
+
+#[derive(Debug)]
+struct RpcError(i64, String);
+
+// A client's session, isolated from every other one
+#[derive(Debug)]
+struct Tenant {
+    session: Session,
+    profile: &'static Profile,
+}
+
+// Answers one request per line of input with one response per line of output,
+// requests without an id are notifications and get no response. `run` and `eval`
+// are answered in a session of their own, `open` starts a session that `submit`
+// adds to until `close`
+#[derive(Debug)]
+pub struct Server {
+    default: &'static Profile,
+    // Every session starts as a copy of this one, so the initial environment is only
+    // built once
+    template: Session,
+    // Only for sessions whose profile allows host functions
+    builtins: Builtins,
+    tenants: HashMap<u64, Tenant>,
+    next_id: u64,
+}
+
+impl Server {
+    pub fn new(default: &'static Profile) -> Self {
+        Server { default, template: Session::new(), builtins: Builtins::new(), tenants: HashMap::new(), next_id: 1 }
+    }
+
+    // Where sessions look for the modules they use, as far as their profile lets them
+    pub fn with_modules(mut self, modules: Loader) -> Self {
+        self.template = self.template.with_modules(modules);
+        self
+    }
+
+    pub fn with_builtins(mut self, builtins: Builtins) -> Self {
+        self.builtins = builtins;
+        self
+    }
+
+    pub fn serve(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle(&line) {
+                writeln!(output, "{}", response)?;
+                output.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle(&mut self, line: &str) -> Option<Json> {
+        let request: Json = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return Some(response(Json::Null, Err(RpcError(PARSE_ERROR, e.to_string())))),
+        };
+        let id = request.get("id").cloned();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.dispatch(&request)))
+            .unwrap_or_else(|payload| Err(self.failed(&request, payload)));
+        id.map(|id| response(id, result))
+    }
+
+    // A panic is a bug in hope, which fails the request alone. The session it was
+    // submitted to could be left half changed, so it is closed
+    fn failed(&mut self, request: &Json, payload: Box<dyn Any + Send>) -> RpcError {
+        let reason = payload.downcast_ref::<&str>().copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("no reason given");
+        let mut message = format!("hope failed on this request: {}", reason);
+        let session = request.get("params").filter(|_| request["method"] == "submit").and_then(|params| session_param(params).ok());
+        if let Some(id) = session.filter(|id| self.tenants.remove(id).is_some()) {
+            message.push_str(&format!(", so session {} is closed", id));
+        }
+        RpcError(INTERNAL_ERROR, message)
+    }
+
+    fn dispatch(&mut self, request: &Json) -> Result<Json, RpcError> {
+        let Some(method) = request.get("method").and_then(Json::as_str) else {
+            return Err(RpcError(INVALID_REQUEST, "missing method".to_owned()));
+        };
+        let params = request.get("params").cloned().unwrap_or(json!({}));
+        match method {
+            "run" => {
+                let mut tenant = self.tenant(&params)?;
+                Ok(tenant.submit(string_param(&params, "source")?))
+            }
+            "eval" => {
+                let mut tenant = self.tenant(&params)?;
+                let expr = string_param(&params, "expr")?.trim_end().trim_end_matches(';');
+                Ok(tenant.submit(&format!("{};", expr)))
+            }
+            "open" => {
+                if self.tenants.len() >= MAX_SESSIONS {
+                    return Err(RpcError(INVALID_REQUEST, format!("at most {} sessions can be open", MAX_SESSIONS)));
+                }
+                let tenant = self.tenant(&params)?;
+                let id = self.next_id;
+                self.next_id += 1;
+                self.tenants.insert(id, tenant);
+                Ok(json!({ "session": id }))
+            }
+            "submit" => {
+                let source = string_param(&params, "source")?;
+                let id = session_param(&params)?;
+                let tenant = self.tenants.get_mut(&id).ok_or_else(|| unknown_session(id))?;
+                Ok(tenant.submit(source))
+            }
+            "close" => {
+                let id = session_param(&params)?;
+                self.tenants.remove(&id).ok_or_else(|| unknown_session(id))?;
+                Ok(Json::Null)
+            }
+            "profiles" => Ok(PROFILES.iter().map(|profile| {
+                let Sandbox { name, limits, write, host, modules } = profile.sandbox;
+                let modules = match modules {
+                    Access::Library => "library",
+                    Access::Local => "local",
+                    Access::All => "all",
+                };
+                json!({
+                    "name": name,
+                    "steps": limits.steps,
+                    "depth": limits.depth,
+                    "time_ms": limits.time.map(|time| time.as_millis() as u64),
+                    "input": profile.input,
+                    "write": write,
+                    "host": host,
+                    "modules": modules,
+                })
+            }).collect()),
+            _ => Err(RpcError(METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    // A new session under the requested profile
+    fn tenant(&self, params: &Json) -> Result<Tenant, RpcError> {
+        let profile = match params.get("profile") {
+            None => self.default,
+            Some(name) => name.as_str().and_then(profile)
+                .ok_or_else(|| RpcError(INVALID_PARAMS, format!("unknown profile {}", name)))?,
+        };
+        let session = self.template.clone().with_sandbox(&profile.sandbox).with_builtins(&self.builtins);
+        Ok(Tenant { session, profile })
+    }
+}
+
+impl Tenant {
+    fn submit(&mut self, source: &str) -> Json {
+        if self.profile.input.is_some_and(|limit| source.len() > limit) {
+            let message = format!("the program is larger than the {} profile allows", self.profile.name());
+            return json!({ "outputs": [], "diagnostics": [{ "message": message }] });
+        }
+
+        match self.session.submit(source) {
+            Ok(outputs) => json!({ "outputs": outputs.iter().map(output).collect::<Vec<_>>(), "diagnostics": [] }),
+            Err(e) => json!({ "outputs": [], "diagnostics": diagnostics(&e) }),
+        }
+    }
+}
+
+fn session_param(params: &Json) -> Result<u64, RpcError> {
+    params.get("session").and_then(Json::as_u64)
+        .ok_or_else(|| RpcError(INVALID_PARAMS, "missing session id".to_owned()))
+}
+
+fn unknown_session(id: u64) -> RpcError {
+    RpcError(INVALID_PARAMS, format!("no open session {}", id))
+}
+
+fn string_param<'a>(params: &'a Json, name: &str) -> Result<&'a str, RpcError> {
+    params.get(name).and_then(Json::as_str)
+        .ok_or_else(|| RpcError(INVALID_PARAMS, format!("missing string parameter {}", name)))
+}
+
+fn response(id: Json, result: Result<Json, RpcError>) -> Json {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(RpcError(code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    }
+}
+
+fn output(output: &Output) -> Json {
+    match output {
+        Output::Written(value) => json!({ "kind": "write", "value": value.to_string() }),
+        Output::Value(value, ty) => json!({ "kind": "value", "value": value.to_string(), "type": ty.to_string() }),
+    }
+}
+
+fn diagnostic(pos: Option<(usize, usize)>, message: String) -> Json {
+    match pos {
+        Some((line, column)) => json!({ "line": line, "column": column, "message": message }),
+        None => json!({ "message": message }),
+    }
+}
+
+fn diagnostics(e: &SessionError) -> Vec<Json> {
+    match e {
+        SessionError::Read(..) => vec![diagnostic(None, e.to_string())],
+        SessionError::Parse(e) => vec![diagnostic(Some((e.pos().line, e.pos().column)), e.to_string())],
+        SessionError::Type(errors) => errors.iter()
+            .map(|e| diagnostic(Some((e.pos().line, e.pos().column)), e.to_string()))
+            .collect(),
+        SessionError::Eval(e) => vec![diagnostic(e.pos().map(|pos| (pos.line, pos.column)), e.to_string())],
+        SessionError::Uses(e) => vec![diagnostic(Some((e.pos().line, e.pos().column)), e.to_string())],
+        SessionError::Sandbox(violations) => violations.iter()
+            .map(|violation| diagnostic(Some((violation.pos().line, violation.pos().column)), violation.to_string()))
+            .collect(),
+        // Their positions are in the module's file
+        SessionError::InModule(path, e) => diagnostics(e).into_iter()
+            .map(|mut d| {
+                d["path"] = json!(source::display(path));
+                d
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The whole response to a request with id 1
+    fn call(server: &mut Server, method: &str, params: Json) -> Json {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        server.handle(&request.to_string()).unwrap()
+    }
+
+    #[test]
+    fn should_run_programs_and_report_diagnostics() {
+        let mut server = Server::new(profile("unlimited").unwrap());
+        let source = "dec x : num;\n--- x <= 41;\nwrite x + 1;\nx;";
+        let reply = call(&mut server, "run", json!({ "source": source }));
+        assert_eq!(reply["id"], 1);
+        assert_eq!(reply["result"]["outputs"], json!([
+            { "kind": "write", "value": "42" },
+            { "kind": "value", "value": "41", "type": "num" },
+        ]));
+
+        let reply = call(&mut server, "eval", json!({ "expr": "1 + true" }));
+        let diagnostic = &reply["result"]["diagnostics"][0];
+        assert_eq!((diagnostic["line"].as_u64(), diagnostic["column"].as_u64()), (Some(1), Some(5)));
+    }
+
+    #[test]
+    fn should_apply_the_requested_profile() {
+        let mut server = Server::new(profile("unlimited").unwrap());
+        let source = "dec loop : num -> num;\n--- loop n <= loop (n + 1);\nloop 0;";
+        let reply = call(&mut server, "run", json!({ "source": source, "profile": "grading" }));
+        assert_eq!(reply["result"]["diagnostics"][0]["message"], "evaluation nested too deeply");
+
+        let reply = call(&mut server, "run", json!({ "source": source, "profile": "nope" }));
+        assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn should_allow_only_what_the_profile_does() {
+        let mut builtins = Builtins::new();
+        builtins.register("double", 1, "num -> num", |args| Ok(args[0].clone())).unwrap();
+        let mut server = Server::new(profile("grading").unwrap()).with_builtins(builtins);
+        let mut run = |source: &str, profile: &str| call(&mut server, "run", json!({ "source": source, "profile": profile }))["result"].clone();
+
+        let message = |result: Json| result["diagnostics"][0]["message"].clone();
+        assert_eq!(message(run("write 1;", "grading")), "this sandbox doesn't allow `write`");
+        assert_eq!(run("write 1;", "playground")["outputs"][0]["value"], "1");
+        assert_eq!(message(run("double 2;", "playground")), "`double` is a host function, which this sandbox doesn't allow");
+        assert_eq!(run("double 2;", "unlimited")["outputs"][0]["value"], "2");
+
+        let dir = std::env::temp_dir().join(format!("hope-serve-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Near.hop"), "dec near : num;\n--- near <= 1;").unwrap();
+        let mut server = Server::new(profile("grading").unwrap()).with_modules(Loader::new().with_search_path([dir.clone()]));
+        let mut run = |source: &str, profile: &str| call(&mut server, "run", json!({ "source": source, "profile": profile }))["result"].clone();
+        let (denied, allowed) = (run("uses Near;\nnear;", "playground"), run("uses Near;\nnear;", "unlimited"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(message(denied).as_str().unwrap().starts_with("module `Near` is "));
+        assert_eq!(allowed["outputs"][0]["value"], "1");
+    }
+
+    #[test]
+    fn should_follow_json_rpc() {
+        let mut server = Server::new(&PROFILES[0]);
+        assert_eq!(server.handle("{").unwrap()["error"]["code"], PARSE_ERROR);
+        assert_eq!(call(&mut server, "nope", json!({}))["error"]["code"], METHOD_NOT_FOUND);
+        assert!(server.handle(r#"{"jsonrpc": "2.0", "method": "profiles"}"#).is_none());
+    }
+
+    #[test]
+    fn should_keep_sessions_apart() {
+        let mut server = Server::new(profile("grading").unwrap());
+        let a = call(&mut server, "open", json!({}))["result"]["session"].clone();
+        let b = call(&mut server, "open", json!({}))["result"]["session"].clone();
+        let mut submit = |session: &Json, source: &str| {
+            call(&mut server, "submit", json!({ "session": session, "source": source }))["result"].clone()
+        };
+
+        submit(&a, "dec x : num;\n--- x <= 1;");
+        assert_eq!(submit(&a, "x;")["outputs"][0]["value"], "1");
+        assert!(submit(&b, "x;")["outputs"].as_array().unwrap().is_empty());
+
+        // The steps one session spends don't come out of another's budget
+        let count = "dec count : num -> num;\n--- count 0 <= 0;\n--- count n <= 1 + count (n - 1);";
+        submit(&a, count);
+        for _ in 0..200 {
+            submit(&a, "count 1000;");
+        }
+        assert_eq!(submit(&a, "count 1000;")["diagnostics"][0]["message"], "evaluation took too many steps");
+        submit(&b, count);
+        assert_eq!(submit(&b, "count 1000;")["outputs"][0]["value"], "1000");
+
+        assert_eq!(call(&mut server, "close", json!({ "session": a }))["result"], Json::Null);
+        assert_eq!(call(&mut server, "close", json!({ "session": a }))["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn should_fail_only_the_request_that_panicked() {
+        let mut builtins = Builtins::new();
+        builtins.register("boom", 1, "num -> num", |_| panic!("boom")).unwrap();
+        let mut server = Server::new(profile("unlimited").unwrap()).with_builtins(builtins);
+        let reply = call(&mut server, "eval", json!({ "expr": "boom 1" }));
+        assert_eq!(reply["error"], json!({ "code": INTERNAL_ERROR, "message": "hope failed on this request: boom" }));
+
+        let session = call(&mut server, "open", json!({}))["result"]["session"].clone();
+        let mut submit = |source: &str| call(&mut server, "submit", json!({ "session": session, "source": source }));
+        assert_eq!(submit("dec x : num;\n--- x <= 1;")["result"]["diagnostics"], json!([]));
+        let message = format!("hope failed on this request: boom, so session {} is closed", session);
+        assert_eq!(submit("boom x;")["error"]["message"], message);
+        assert_eq!(submit("x;")["error"]["code"], INVALID_PARAMS);
+        assert_eq!(call(&mut server, "eval", json!({ "expr": "1 + 1" }))["result"]["outputs"][0]["value"], "2");
+    }
+
+    // Dropping the list used to overflow the stack, taking every session down with it
+    #[test]
+    fn should_survive_large_values() {
+        let mut server = Server::new(profile("grading").unwrap());
+        let items = vec!["0"; 30_000].join(",");
+        let reply = call(&mut server, "eval", json!({ "expr": format!("[{}] = []", items) }));
+        assert_eq!(reply["result"]["outputs"][0]["value"], "false");
+        let reply = call(&mut server, "eval", json!({ "expr": "1 + 1" }));
+        assert_eq!(reply["result"]["outputs"][0]["value"], "2");
+    }
+}
@@ -0,0 +1,159 @@
+use std::borrow::Cow;
+#[cfg(feature = "cli")]
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::library;
+
+pub const EXTENSIONS: [&str; 2] = ["hop", "lhop"];
+
+#[cfg(feature = "cli")]
+fn is_source(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| EXTENSIONS.iter().any(|e| ext == *e))
+}
+
+#[cfg(feature = "cli")]
+fn walk(path: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() || is_source(&entry) {
+                walk(&entry, found)?;
+            }
+        }
+    } else {
+        found.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+// Expands each argument (file, directory or glob pattern) into source files, in
+// argument order, with files reached more than once only listed the first time
+#[cfg(feature = "cli")]
+pub fn discover<P: AsRef<str>>(args: &[P]) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for arg in args {
+        let arg = arg.as_ref();
+        if arg.contains(['*', '?', '[']) {
+            let pattern = glob::glob(arg)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            for entry in pattern {
+                walk(&entry.map_err(io::Error::from)?, &mut found)?;
+            }
+        } else {
+            walk(Path::new(arg), &mut found)?;
+        }
+    }
+
+    let mut seen = HashSet::new();
+    found.retain(|path| seen.insert(canonical(path)));
+    Ok(found)
+}
+
+// Literate sources keep only their Bird-tracked (`>`) lines; everything else is
+// blanked out so line numbers still match the file
+pub fn unlit(contents: &str) -> String {
+    contents
+        .lines()
+        .map(|line| line.strip_prefix('>').map(|code| format!(" {}", code)).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// The path with `/` between its parts whatever the system, for output that should
+// read the same on every one
+pub fn display(path: impl AsRef<Path>) -> String {
+    let text = path.as_ref().to_string_lossy();
+    let text = unverbatim(&text);
+    if std::path::MAIN_SEPARATOR == '/' { text.into_owned() } else { text.replace(std::path::MAIN_SEPARATOR, "/") }
+}
+
+// The file the path names, the same however it was reached, or the path itself when
+// there is no such file
+pub fn canonical(path: &Path) -> PathBuf {
+    match fs::canonicalize(path) {
+        Ok(found) => match unverbatim(&found.to_string_lossy()) {
+            Cow::Owned(plain) => PathBuf::from(plain),
+            Cow::Borrowed(_) => found,
+        },
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+// Windows canonicalizes to verbatim paths, `\\?\C:\dir` and `\\?\UNC\server\share`,
+// which most programs can't open and nobody wants to read. Drives and shares go back
+// to the usual `C:\dir` and `\\server\share`, other verbatim paths have no usual form
+fn unverbatim(text: &str) -> Cow<'_, str> {
+    if let Some(share) = text.strip_prefix(r"\\?\UNC\") {
+        return Cow::Owned(format!(r"\\{}", share));
+    }
+    match text.strip_prefix(r"\\?\") {
+        Some(rest) if rest.as_bytes().get(1) == Some(&b':') && rest.as_bytes()[0].is_ascii_alphabetic() => Cow::Owned(rest.to_owned()),
+        _ => Cow::Borrowed(text),
+    }
+}
+
+// Why a file couldn't be read or written, said the same way on every system rather
+// than in the system's own words
+pub fn describe(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::NotFound => "no such file or directory".to_owned(),
+        io::ErrorKind::PermissionDenied => "permission denied".to_owned(),
+        io::ErrorKind::InvalidData => "not UTF-8 text".to_owned(),
+        io::ErrorKind::IsADirectory => "is a directory".to_owned(),
+        kind => kind.to_string(),
+    }
+}
+
+pub fn read(path: &Path) -> io::Result<String> {
+    if let Some(contents) = library::source(path) {
+        return Ok(contents.to_owned());
+    }
+    let contents = fs::read_to_string(path)?;
+    if path.extension().is_some_and(|ext| ext == "lhop") {
+        Ok(unlit(&contents))
+    } else {
+        Ok(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_unlit_bird_tracks() {
+        let source = "Some prose\n> dec x : num;\n\n> --- x <= 1;";
+        assert_eq!(unlit(source), "\n  dec x : num;\n\n  --- x <= 1;");
+    }
+
+    #[test]
+    fn should_give_windows_paths_in_their_usual_form() {
+        assert_eq!(unverbatim(r"\\?\C:\hope\main.hop"), r"C:\hope\main.hop");
+        assert_eq!(unverbatim(r"\\?\UNC\server\share\main.hop"), r"\\server\share\main.hop");
+        assert_eq!(unverbatim(r"\\?\Volume{1}\main.hop"), r"\\?\Volume{1}\main.hop");
+        assert_eq!(unverbatim(r"\\server\share\main.hop"), r"\\server\share\main.hop");
+        assert_eq!(unverbatim("/home/hope/main.hop"), "/home/hope/main.hop");
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn should_discover_each_file_once() {
+        let dir = std::env::temp_dir().join(format!("hope-discover-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("a.hop"), "").unwrap();
+        fs::write(dir.join("nested/b.lhop"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let dir_arg = dir.to_string_lossy().into_owned();
+        let file_arg = dir.join("a.hop").to_string_lossy().into_owned();
+        let found = discover(&[file_arg, dir_arg]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found, vec![dir.join("a.hop"), dir.join("nested/b.lhop")]);
+    }
+}
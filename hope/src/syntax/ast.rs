@@ -0,0 +1,177 @@
+use crate::alloc_prelude::*;
+use crate::syntax::token::Pos;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub decls: Vec<Decl>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decl {
+    pub kind: DeclKind,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeclKind {
+    TypeVar(Vec<Ident>),
+    Infix { assoc: Assoc, ops: Vec<Ident>, prec: u32 },
+    AbsType(TypeHead),
+    Data { head: TypeHead, constructors: Vec<Constructor> },
+    Type { head: TypeHead, body: TypeExpr },
+    Dec { names: Vec<Ident>, ty: TypeExpr },
+    Equation(Equation),
+    Syntax(SyntaxDef),
+    Uses(Vec<Ident>),
+    Private,
+    // `module name` up to its `end`, in the classic dialect. Outside it only what the
+    // `pubconst`, `pubfun` and `pubtype` declarations inside it name can be seen
+    Module(Ident),
+    End,
+    Public(PublicKind, Vec<Ident>),
+    Write(Expr),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PublicKind {
+    Const,
+    Fun,
+    Type,
+}
+
+// The left hand side of a type definition, `tree alpha` or `neg -> pos`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeHead {
+    pub name: Ident,
+    pub params: Vec<Ident>,
+    pub infix: bool,
+}
+
+// An infix constructor such as `alpha :: list alpha` takes a single pair argument,
+// `infix` only records how it was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constructor {
+    pub name: Ident,
+    pub args: Vec<TypeExpr>,
+    pub infix: bool,
+    pub pos: Pos,
+}
+
+// Type variables and type constructors look the same until `typevar`
+// declarations are taken into account, so both are a name with arguments
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeExpr {
+    pub name: Ident,
+    pub args: Vec<TypeExpr>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equation {
+    pub name: Ident,
+    pub args: Vec<Pattern>,
+    // `--- f n if n > 0 <= ...`, an equation that only applies when this holds
+    pub guard: Option<Expr>,
+    pub body: Expr,
+    pub infix: bool,
+}
+
+// `syntax unless c a b <= if c then b else a`. The parser replaces each application
+// of the name to enough arguments with the body, so this is kept only for tools
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxDef {
+    pub name: Ident,
+    pub params: Vec<Ident>,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Var(String),
+    Int(i64),
+    // A whole number past the range of i64, as its digits
+    BigInt(String),
+    Num(f64),
+    Str(String),
+    Tuple(Vec<Expr>),
+    List(Vec<Expr>),
+    Apply(Box<Expr>, Box<Expr>),
+    // `l op r`, which means `op (l, r)`
+    BinOp(Ident, Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Lambda(Vec<Rule>),
+    Let(Box<Let>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LetKind {
+    Let,
+    LetRec,
+    Where,
+    WhereRec,
+}
+
+impl LetKind {
+    pub fn is_rec(self) -> bool {
+        matches!(self, LetKind::LetRec | LetKind::WhereRec)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Let {
+    pub kind: LetKind,
+    pub pattern: Pattern,
+    pub value: Expr,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub pattern: Pattern,
+    pub body: Expr,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub pos: Pos,
+}
+
+// A bare name is a variable unless it names a nullary constructor, which is only
+// known once declarations are resolved
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternKind {
+    Var(String),
+    // `_`, which matches anything and binds nothing
+    Wildcard,
+    // `l & (x :: _)` binds l to the whole value as well as matching it
+    As(Ident, Box<Pattern>),
+    Int(i64),
+    BigInt(String),
+    Num(f64),
+    Str(String),
+    Tuple(Vec<Pattern>),
+    List(Vec<Pattern>),
+    Construct(Ident, Vec<Pattern>),
+    BinOp(Ident, Box<Pattern>, Box<Pattern>),
+}
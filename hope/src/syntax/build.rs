@@ -0,0 +1,322 @@
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+use crate::syntax::token::Pos;
+
+// Syntax trees written out in Rust, for tests and for tools that make programs rather
+// than parse them. What is built here is nowhere in any source, and `unplaced` moves
+// a parsed tree there too, so the two compare equal when they mean the same:
+//
+//     assert_eq!(parse_expr("f x + 1")?.unplaced(), op("+", apply(var("f"), [var("x")]), int(1)));
+//
+// Names of operators are given as written, constructors of patterns by name, and the
+// types `->` and `#` with arrow and pair
+
+pub fn nowhere() -> Pos {
+    Pos { line: 0, column: 0, range: 0..0 }
+}
+
+pub fn ident(name: &str) -> Ident {
+    Ident { name: name.to_owned(), pos: nowhere() }
+}
+
+fn expr(kind: ExprKind) -> Expr {
+    Expr { kind, pos: nowhere() }
+}
+
+pub fn var(name: &str) -> Expr {
+    expr(ExprKind::Var(name.to_owned()))
+}
+
+pub fn int(n: i64) -> Expr {
+    expr(ExprKind::Int(n))
+}
+
+pub fn num(n: f64) -> Expr {
+    expr(ExprKind::Num(n))
+}
+
+pub fn string(s: &str) -> Expr {
+    expr(ExprKind::Str(s.to_owned()))
+}
+
+pub fn tuple(items: impl IntoIterator<Item = Expr>) -> Expr {
+    expr(ExprKind::Tuple(items.into_iter().collect()))
+}
+
+pub fn list(items: impl IntoIterator<Item = Expr>) -> Expr {
+    expr(ExprKind::List(items.into_iter().collect()))
+}
+
+// The function applied to each argument in turn
+pub fn apply(fun: Expr, args: impl IntoIterator<Item = Expr>) -> Expr {
+    args.into_iter().fold(fun, |fun, arg| expr(ExprKind::Apply(Box::new(fun), Box::new(arg))))
+}
+
+pub fn op(name: &str, l: Expr, r: Expr) -> Expr {
+    expr(ExprKind::BinOp(ident(name), Box::new(l), Box::new(r)))
+}
+
+pub fn cond(c: Expr, then: Expr, other: Expr) -> Expr {
+    expr(ExprKind::If(Box::new(c), Box::new(then), Box::new(other)))
+}
+
+pub fn lambda(rules: impl IntoIterator<Item = (Pattern, Expr)>) -> Expr {
+    let rules = rules.into_iter().map(|(pattern, body)| Rule { pattern, body, pos: nowhere() });
+    expr(ExprKind::Lambda(rules.collect()))
+}
+
+pub fn bind(kind: LetKind, pattern: Pattern, value: Expr, body: Expr) -> Expr {
+    expr(ExprKind::Let(Box::new(Let { kind, pattern, value, body })))
+}
+
+fn pattern(kind: PatternKind) -> Pattern {
+    Pattern { kind, pos: nowhere() }
+}
+
+pub fn pvar(name: &str) -> Pattern {
+    pattern(PatternKind::Var(name.to_owned()))
+}
+
+pub fn wildcard() -> Pattern {
+    pattern(PatternKind::Wildcard)
+}
+
+pub fn pas(name: &str, inner: Pattern) -> Pattern {
+    pattern(PatternKind::As(ident(name), Box::new(inner)))
+}
+
+pub fn pint(n: i64) -> Pattern {
+    pattern(PatternKind::Int(n))
+}
+
+pub fn ptuple(items: impl IntoIterator<Item = Pattern>) -> Pattern {
+    pattern(PatternKind::Tuple(items.into_iter().collect()))
+}
+
+pub fn plist(items: impl IntoIterator<Item = Pattern>) -> Pattern {
+    pattern(PatternKind::List(items.into_iter().collect()))
+}
+
+pub fn construct(name: &str, args: impl IntoIterator<Item = Pattern>) -> Pattern {
+    pattern(PatternKind::Construct(ident(name), args.into_iter().collect()))
+}
+
+pub fn pop(name: &str, l: Pattern, r: Pattern) -> Pattern {
+    pattern(PatternKind::BinOp(ident(name), Box::new(l), Box::new(r)))
+}
+
+pub fn ty(name: &str, args: impl IntoIterator<Item = TypeExpr>) -> TypeExpr {
+    TypeExpr { name: ident(name), args: args.into_iter().collect(), pos: nowhere() }
+}
+
+pub fn arrow(from: TypeExpr, to: TypeExpr) -> TypeExpr {
+    ty("->", [from, to])
+}
+
+pub fn pair(l: TypeExpr, r: TypeExpr) -> TypeExpr {
+    ty("#", [l, r])
+}
+
+fn decl(kind: DeclKind) -> Decl {
+    Decl { kind, pos: nowhere() }
+}
+
+pub fn program(decls: impl IntoIterator<Item = Decl>) -> Program {
+    Program { decls: decls.into_iter().collect() }
+}
+
+pub fn typevar(names: &[&str]) -> Decl {
+    decl(DeclKind::TypeVar(names.iter().map(|name| ident(name)).collect()))
+}
+
+fn head(name: &str, params: &[&str]) -> TypeHead {
+    TypeHead { name: ident(name), params: params.iter().map(|param| ident(param)).collect(), infix: false }
+}
+
+// Each constructor with the types of its arguments
+pub fn data(name: &str, params: &[&str], constructors: impl IntoIterator<Item = (&'static str, Vec<TypeExpr>)>) -> Decl {
+    let constructors = constructors.into_iter().map(|(name, args)| Constructor { name: ident(name), args, infix: false, pos: nowhere() });
+    decl(DeclKind::Data { head: head(name, params), constructors: constructors.collect() })
+}
+
+pub fn synonym(name: &str, params: &[&str], body: TypeExpr) -> Decl {
+    decl(DeclKind::Type { head: head(name, params), body })
+}
+
+pub fn dec(names: &[&str], ty: TypeExpr) -> Decl {
+    decl(DeclKind::Dec { names: names.iter().map(|name| ident(name)).collect(), ty })
+}
+
+fn eq(name: &str, args: Vec<Pattern>, guard: Option<Expr>, body: Expr, infix: bool) -> Decl {
+    decl(DeclKind::Equation(Equation { name: ident(name), args, guard, body, infix }))
+}
+
+pub fn equation(name: &str, args: impl IntoIterator<Item = Pattern>, body: Expr) -> Decl {
+    eq(name, args.into_iter().collect(), None, body, false)
+}
+
+pub fn guarded(name: &str, args: impl IntoIterator<Item = Pattern>, guard: Expr, body: Expr) -> Decl {
+    eq(name, args.into_iter().collect(), Some(guard), body, false)
+}
+
+// `--- l op r <= body`, which takes the pair
+pub fn infix_equation(name: &str, l: Pattern, r: Pattern, body: Expr) -> Decl {
+    eq(name, vec![ptuple([l, r])], None, body, true)
+}
+
+pub fn infix_guarded(name: &str, l: Pattern, r: Pattern, guard: Expr, body: Expr) -> Decl {
+    eq(name, vec![ptuple([l, r])], Some(guard), body, true)
+}
+
+pub fn write(e: Expr) -> Decl {
+    decl(DeclKind::Write(e))
+}
+
+pub fn top(e: Expr) -> Decl {
+    decl(DeclKind::Expr(e))
+}
+
+// The same tree with every position nowhere
+pub trait Unplaced {
+    fn unplaced(self) -> Self;
+}
+
+impl Unplaced for Program {
+    fn unplaced(mut self) -> Self {
+        self.decls.iter_mut().for_each(unplace_decl);
+        self
+    }
+}
+
+impl Unplaced for Decl {
+    fn unplaced(mut self) -> Self {
+        unplace_decl(&mut self);
+        self
+    }
+}
+
+impl Unplaced for Expr {
+    fn unplaced(mut self) -> Self {
+        unplace_expr(&mut self);
+        self
+    }
+}
+
+impl Unplaced for Pattern {
+    fn unplaced(mut self) -> Self {
+        unplace_pattern(&mut self);
+        self
+    }
+}
+
+impl Unplaced for TypeExpr {
+    fn unplaced(mut self) -> Self {
+        unplace_type(&mut self);
+        self
+    }
+}
+
+fn unplace_ident(ident: &mut Ident) {
+    ident.pos = nowhere();
+}
+
+fn unplace_head(head: &mut TypeHead) {
+    unplace_ident(&mut head.name);
+    head.params.iter_mut().for_each(unplace_ident);
+}
+
+fn unplace_decl(decl: &mut Decl) {
+    decl.pos = nowhere();
+    match &mut decl.kind {
+        DeclKind::TypeVar(names) | DeclKind::Uses(names) | DeclKind::Public(_, names) | DeclKind::Infix { ops: names, .. } =>
+            names.iter_mut().for_each(unplace_ident),
+        DeclKind::AbsType(head) => unplace_head(head),
+        DeclKind::Data { head, constructors } => {
+            unplace_head(head);
+            for constructor in constructors {
+                constructor.pos = nowhere();
+                unplace_ident(&mut constructor.name);
+                constructor.args.iter_mut().for_each(unplace_type);
+            }
+        }
+        DeclKind::Type { head, body } => {
+            unplace_head(head);
+            unplace_type(body);
+        }
+        DeclKind::Dec { names, ty } => {
+            names.iter_mut().for_each(unplace_ident);
+            unplace_type(ty);
+        }
+        DeclKind::Equation(eq) => {
+            unplace_ident(&mut eq.name);
+            eq.args.iter_mut().for_each(unplace_pattern);
+            eq.guard.iter_mut().for_each(unplace_expr);
+            unplace_expr(&mut eq.body);
+        }
+        DeclKind::Syntax(def) => {
+            unplace_ident(&mut def.name);
+            def.params.iter_mut().for_each(unplace_ident);
+            unplace_expr(&mut def.body);
+        }
+        DeclKind::Module(name) => unplace_ident(name),
+        DeclKind::Write(e) | DeclKind::Expr(e) => unplace_expr(e),
+        DeclKind::Private | DeclKind::End => {}
+    }
+}
+
+fn unplace_type(ty: &mut TypeExpr) {
+    ty.pos = nowhere();
+    unplace_ident(&mut ty.name);
+    ty.args.iter_mut().for_each(unplace_type);
+}
+
+fn unplace_expr(e: &mut Expr) {
+    e.pos = nowhere();
+    match &mut e.kind {
+        ExprKind::Var(_) | ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) => {}
+        ExprKind::Tuple(items) | ExprKind::List(items) => items.iter_mut().for_each(unplace_expr),
+        ExprKind::Apply(fun, arg) => {
+            unplace_expr(fun);
+            unplace_expr(arg);
+        }
+        ExprKind::BinOp(op, l, r) => {
+            unplace_ident(op);
+            unplace_expr(l);
+            unplace_expr(r);
+        }
+        ExprKind::If(c, then, other) => [c, then, other].into_iter().for_each(|e| unplace_expr(e)),
+        ExprKind::Lambda(rules) => {
+            for rule in rules {
+                rule.pos = nowhere();
+                unplace_pattern(&mut rule.pattern);
+                unplace_expr(&mut rule.body);
+            }
+        }
+        ExprKind::Let(binding) => {
+            unplace_pattern(&mut binding.pattern);
+            unplace_expr(&mut binding.value);
+            unplace_expr(&mut binding.body);
+        }
+    }
+}
+
+fn unplace_pattern(p: &mut Pattern) {
+    p.pos = nowhere();
+    match &mut p.kind {
+        PatternKind::Var(_) | PatternKind::Wildcard | PatternKind::Int(_) | PatternKind::BigInt(_) | PatternKind::Num(_) | PatternKind::Str(_) => {}
+        PatternKind::As(name, inner) => {
+            unplace_ident(name);
+            unplace_pattern(inner);
+        }
+        PatternKind::Tuple(items) | PatternKind::List(items) => items.iter_mut().for_each(unplace_pattern),
+        PatternKind::Construct(name, args) => {
+            unplace_ident(name);
+            args.iter_mut().for_each(unplace_pattern);
+        }
+        PatternKind::BinOp(op, l, r) => {
+            unplace_ident(op);
+            unplace_pattern(l);
+            unplace_pattern(r);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use core::fmt;
+use logos::Logos;
+use crate::alloc_prelude::*;
+use crate::syntax::token::{Token, TokenKind};
+
+// Every byte of the source belongs to exactly one token of the tree, whitespace,
+// comments and text that didn't lex included, so printing the tree gives back the
+// source unchanged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    Token(TokenKind),
+    Whitespace,
+    Comment,
+    // Text the lexer rejected
+    Error,
+}
+
+impl SyntaxKind {
+    pub fn is_trivia(self) -> bool {
+        matches!(self, SyntaxKind::Whitespace | SyntaxKind::Comment)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstToken<'src> {
+    pub kind: SyntaxKind,
+    pub text: &'src str,
+    // Byte offset of the token in the source
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    Program,
+    // Everything up to and including a `;`
+    Decl,
+    // A bracket and what it encloses, up to its partner if there is one
+    Group,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element<'src> {
+    Node(Node<'src>),
+    Token(CstToken<'src>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node<'src> {
+    pub kind: NodeKind,
+    pub children: Vec<Element<'src>>,
+}
+
+impl<'src> Node<'src> {
+    fn new(kind: NodeKind) -> Self {
+        Node { kind, children: Vec::new() }
+    }
+
+    // The node's tokens in source order
+    pub fn tokens(&self) -> impl Iterator<Item = &CstToken<'src>> {
+        let mut stack = vec![self.children.iter()];
+        core::iter::from_fn(move || loop {
+            match stack.last_mut()?.next() {
+                Some(Element::Token(token)) => return Some(token),
+                Some(Element::Node(node)) => stack.push(node.children.iter()),
+                None => {
+                    stack.pop();
+                }
+            }
+        })
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node<'src>> {
+        self.children.iter().filter_map(|child| match child {
+            Element::Node(node) => Some(node),
+            Element::Token(_) => None,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens().map(|token| token.text.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens().next().is_none()
+    }
+}
+
+impl fmt::Display for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tokens().try_for_each(|token| f.write_str(token.text))
+    }
+}
+
+// The source as tokens, with the gaps the lexer skips filled in as trivia
+pub fn tokens(source: &str) -> Vec<CstToken<'_>> {
+    let mut tokens = Vec::new();
+    let mut end = 0;
+    let mut lex = Token::lexer(source);
+    while let Some(tok) = lex.next() {
+        let span = lex.span();
+        trivia(source, end, span.start, &mut tokens);
+        let kind = match tok {
+            Ok(token) => SyntaxKind::Token(token.kind()),
+            Err(_) => SyntaxKind::Error,
+        };
+        tokens.push(CstToken { kind, text: &source[span.clone()], offset: span.start });
+        end = span.end;
+    }
+    trivia(source, end, source.len(), &mut tokens);
+    tokens
+}
+
+// Splits skipped text into comments, which run from `!` to the end of the line, and
+// the whitespace around them
+fn trivia<'src>(source: &'src str, mut start: usize, end: usize, tokens: &mut Vec<CstToken<'src>>) {
+    while start < end {
+        let rest = &source[start..end];
+        let (kind, len) = match rest.strip_prefix('!') {
+            Some(comment) => (SyntaxKind::Comment, 1 + comment.find('\n').unwrap_or(comment.len())),
+            None => (SyntaxKind::Whitespace, rest.find('!').unwrap_or(rest.len())),
+        };
+        tokens.push(CstToken { kind, text: &rest[..len], offset: start });
+        start += len;
+    }
+}
+
+// Groups the tokens into declarations and brackets. Trivia between declarations
+// belongs to the program, a `;` closes any brackets left open before it so one
+// missing bracket doesn't swallow the rest of the file
+pub fn parse(source: &str) -> Node<'_> {
+    let mut stack = vec![Node::new(NodeKind::Program)];
+    for token in tokens(source) {
+        if stack.len() == 1 && !token.kind.is_trivia() {
+            stack.push(Node::new(NodeKind::Decl));
+        }
+        match token.kind {
+            SyntaxKind::Token(TokenKind::LParen | TokenKind::LSquare) => {
+                let mut group = Node::new(NodeKind::Group);
+                group.children.push(Element::Token(token));
+                stack.push(group);
+            }
+            SyntaxKind::Token(TokenKind::RParen | TokenKind::RSquare)
+                if stack.last().is_some_and(|node| node.kind == NodeKind::Group) => {
+                stack.last_mut().unwrap().children.push(Element::Token(token));
+                finish(&mut stack);
+            }
+            SyntaxKind::Token(TokenKind::SemiColon) => {
+                while stack.len() > 2 {
+                    finish(&mut stack);
+                }
+                stack.last_mut().unwrap().children.push(Element::Token(token));
+                finish(&mut stack);
+            }
+            _ => stack.last_mut().unwrap().children.push(Element::Token(token)),
+        }
+    }
+    while stack.len() > 1 {
+        finish(&mut stack);
+    }
+    stack.pop().unwrap()
+}
+
+fn finish(stack: &mut Vec<Node>) {
+    let node = stack.pop().unwrap();
+    stack.last_mut().unwrap().children.push(Element::Node(node));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_reproduce_the_source() {
+        for source in [
+            include_str!("../../../lib/Standard.hop"),
+            "dec f : num -> num; ! doubles\n--- f x <= (x +\t x) ;\n\n",
+            "f (1 + [2; g $ \"open\n",
+            "",
+        ] {
+            assert_eq!(parse(source).to_string(), source);
+        }
+    }
+
+    #[test]
+    fn should_group_declarations_and_brackets() {
+        let program = parse("! header\ndec x : num;\n--- x <= (1 + (2));\nf [x");
+        let decls: Vec<_> = program.nodes().map(|decl| decl.to_string()).collect();
+        assert_eq!(decls, ["dec x : num;", "--- x <= (1 + (2));", "f [x"]);
+        assert!(matches!(&program.children[0], Element::Token(t) if t.kind == SyntaxKind::Comment));
+
+        let groups: Vec<_> = program.nodes().nth(1).unwrap().nodes().map(|group| group.to_string()).collect();
+        assert_eq!(groups, ["(1 + (2))"]);
+        assert_eq!(program.nodes().nth(2).unwrap().nodes().next().map(Node::len), Some(2));
+    }
+}
@@ -1 +1,54 @@
-pub mod token;
\ No newline at end of file
+pub mod ast;
+pub mod build;
+pub mod cst;
+#[cfg(feature = "std")]
+pub mod stats;
+pub mod token;
+
+use logos::Logos;
+use token::{Extras, IdentifierPolicy, LexingError, Pos, SpannedToken, Token};
+use crate::alloc_prelude::*;
+
+// Every token in the source, carrying on past anything that doesn't lex, with each
+// error and where it was
+pub fn lex(source: &str) -> (Vec<SpannedToken<'_>>, Vec<(LexingError, Pos)>) {
+    lex_with_policy(source, IdentifierPolicy::default())
+}
+
+pub fn lex_with_policy(source: &str, policy: IdentifierPolicy) -> (Vec<SpannedToken<'_>>, Vec<(LexingError, Pos)>) {
+    lex_with_extras(source, Extras::with_policy(policy))
+}
+
+pub fn lex_with_extras(source: &str, extras: Extras) -> (Vec<SpannedToken<'_>>, Vec<(LexingError, Pos)>) {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut lex = Token::lexer_with_extras(source, extras);
+    while let Some(tok) = lex.next() {
+        match tok {
+            Ok(token) => tokens.push(token.in_dialect(lex.extras.dialect).into()),
+            Err(e) => errors.push((e, lex.extras.pos(lex.span()))),
+        }
+    }
+    (tokens, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use token::TokenKind;
+
+    #[test]
+    fn should_lex_past_errors() {
+        let (tokens, errors) = lex_with_policy("f ` 4.a\n\"open\ng 1e999;", IdentifierPolicy::Strict);
+        let kinds: Vec<_> = tokens.iter().map(|t| t.kind).collect();
+        assert_eq!(kinds, [TokenKind::Identifier, TokenKind::Identifier, TokenKind::SemiColon]);
+        assert_eq!(tokens[1].identifier(), Some("g"));
+        let errors: Vec<_> = errors.iter().map(|(e, pos)| (e.to_string(), pos.line, pos.column)).collect();
+        assert_eq!(errors, [
+            ("``` is not allowed in operators under the strict policy".to_owned(), 1, 3),
+            ("invalid number `4.a`".to_owned(), 1, 5),
+            ("unterminated string literal starting here".to_owned(), 2, 1),
+            ("number is too large".to_owned(), 3, 3),
+        ]);
+    }
+}
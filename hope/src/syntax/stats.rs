@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use logos::Logos;
+use crate::source;
+use crate::syntax::token::{Extras, IdentifierPolicy, Token, TokenKind};
+
+// PubType is the last kind
+const KINDS: usize = TokenKind::PubType as usize + 1;
+
+#[derive(Debug, Default)]
+pub struct FileStats {
+    pub path: PathBuf,
+    pub bytes: usize,
+    pub tokens: usize,
+    pub errors: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct CorpusStats {
+    pub files: Vec<FileStats>,
+    pub frequency: HashMap<&'static str, usize>,
+    pub identifier_lengths: BTreeMap<usize, usize>,
+    pub elapsed: Duration,
+    pub policy: IdentifierPolicy,
+}
+
+impl CorpusStats {
+    pub fn add_source(&mut self, path: &Path, source: &str) {
+        let mut file = FileStats {
+            path: path.to_path_buf(),
+            bytes: source.len(),
+            ..FileStats::default()
+        };
+
+        // Counted in arrays while lexing and added to the maps once per file, looking a
+        // map up for every token took several times as long as the lexing
+        let mut kinds = [(0, ""); KINDS];
+        let mut lengths = Vec::new();
+        for tok in Token::lexer_with_extras(source, Extras::with_policy(self.policy)) {
+            match tok {
+                Ok(token) => {
+                    file.tokens += 1;
+                    if let Token::Identifier((name, _)) = &token {
+                        let len = name.chars().count();
+                        if lengths.len() <= len {
+                            lengths.resize(len + 1, 0);
+                        }
+                        lengths[len] += 1;
+                    }
+                    let kind = &mut kinds[token.kind() as usize];
+                    *kind = (kind.0 + 1, token.name());
+                }
+                Err(_) => file.errors += 1
+            }
+        }
+
+        for (count, name) in kinds.into_iter().filter(|(count, _)| *count > 0) {
+            *self.frequency.entry(name).or_default() += count;
+        }
+        for (len, count) in lengths.into_iter().enumerate().filter(|(_, count)| *count > 0) {
+            *self.identifier_lengths.entry(len).or_default() += count;
+        }
+        self.files.push(file);
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.files.iter().map(|f| f.bytes).sum()
+    }
+
+    pub fn total_tokens(&self) -> usize {
+        self.files.iter().map(|f| f.tokens).sum()
+    }
+
+    pub fn total_errors(&self) -> usize {
+        self.files.iter().map(|f| f.errors).sum()
+    }
+
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.total_bytes() as f64 / (1024.0 * 1024.0) / secs
+    }
+}
+
+impl fmt::Display for CorpusStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "files: {}", self.files.len())?;
+        for file in &self.files {
+            writeln!(f, "  {}: {} bytes, {} tokens, {} errors",
+                     source::display(&file.path), file.bytes, file.tokens, file.errors)?;
+        }
+
+        // Most frequent first, ties broken by name so the output is stable
+        let mut frequency: Vec<_> = self.frequency.iter().collect();
+        frequency.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(f, "token frequency:")?;
+        for (name, count) in frequency {
+            writeln!(f, "  {:<14} {}", name, count)?;
+        }
+
+        writeln!(f, "identifier lengths:")?;
+        for (len, count) in &self.identifier_lengths {
+            writeln!(f, "  {:>3} {}", len, count)?;
+        }
+
+        writeln!(f, "total: {} bytes, {} tokens, {} errors",
+                 self.total_bytes(), self.total_tokens(), self.total_errors())?;
+        write!(f, "throughput: {:.2} MB/s", self.throughput())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_count_tokens_and_identifiers() {
+        let mut stats = CorpusStats::default();
+        stats.add_source(Path::new("a.hop"), "dec foo : num;\nfoo ' ab");
+
+        assert_eq!(stats.total_tokens(), 7);
+        assert_eq!(stats.total_errors(), 1);
+        assert_eq!(stats.frequency.get("Identifier"), Some(&4));
+        assert_eq!(stats.identifier_lengths.get(&3), Some(&3));
+        assert_eq!(stats.identifier_lengths.get(&2), Some(&1));
+    }
+}
@@ -1,85 +1,294 @@
-use std::num::ParseFloatError;
-use logos::{Lexer, Logos, Skip, Span};
+use alloc::borrow::Cow;
+use core::fmt;
+use logos::{Lexer, Logos, Span};
+use crate::alloc_prelude::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Pos {
     pub line: usize,
     pub column: usize,
     pub range: Span,
 }
 
+impl Pos {
+    // The position running from the start of self to the end of other
+    pub fn to(&self, other: &Pos) -> Pos {
+        Pos {
+            line: self.line,
+            column: self.column,
+            range: self.range.start..other.range.end
+        }
+    }
+}
+
+// Permissive accepts any run of symbol characters as an operator, which is how old
+// sources were written; Strict limits operators to STRICT_OPERATOR_CHARS
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum IdentifierPolicy {
+    #[default]
+    Permissive,
+    Strict,
+}
+
+// Classic sources have modules, `module name; ... end` with `pubconst`, `pubfun` and
+// `pubtype` naming what they export, and `nonop` to use an operator as a name. In the
+// modern dialect those words are ordinary names
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    #[default]
+    Classic,
+    Modern,
+}
+
+pub const STRICT_OPERATOR_CHARS: &str = "#$%&*+-./<=>?@^~";
+
+// Longest identifier or string literal accepted, anything larger is almost certainly
+// not source code
+pub const MAX_TOKEN_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extras {
+    pub line: usize,
+    // Byte offset of the start of the current line
+    pub line_start: usize,
+    pub policy: IdentifierPolicy,
+    pub dialect: Dialect,
+}
+
+impl Default for Extras {
+    fn default() -> Self {
+        Extras { line: 1, line_start: 0, policy: IdentifierPolicy::default(), dialect: Dialect::default() }
+    }
+}
+
+impl Extras {
+    pub fn with_policy(policy: IdentifierPolicy) -> Self {
+        Extras { policy, ..Extras::default() }
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    // The position of a span on the current line. Columns count bytes from 1
+    pub fn pos(&self, range: Span) -> Pos {
+        Pos { line: self.line, column: range.start - self.line_start + 1, range }
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq)]
 pub enum LexingError {
-    InvalidNumber(String),
+    // A number run straight into letters or a dangling `.`, as in `4.a`
+    InvalidNumber(String, Span),
+
+    // The literal's magnitude is too large to be represented, it would read as infinity
+    NumberOutOfRange,
+
+    // An operator character only accepted by the permissive policy, with its byte offset
+    PermissiveOperatorChar(char, usize),
+
+    // A string literal with no closing quote before the end of its line
+    UnterminatedString,
+
+    // An escape sequence in a string literal that doesn't stand for a character, with
+    // the sequence and its span
+    InvalidEscape(String, Span),
+
+    TokenTooLong(usize),
 
     #[default]
     UnrecognisedCharacter
 }
 
-impl From<ParseFloatError> for LexingError {
-    fn from(_: ParseFloatError) -> Self {
-        LexingError::InvalidNumber("Invalid number: {}".to_owned())
+impl LexingError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            LexingError::InvalidNumber(..) => "E0101",
+            LexingError::NumberOutOfRange => "E0102",
+            LexingError::PermissiveOperatorChar(..) => "E0103",
+            LexingError::UnterminatedString => "E0104",
+            LexingError::InvalidEscape(..) => "E0105",
+            LexingError::TokenTooLong(_) => "E0106",
+            LexingError::UnrecognisedCharacter => "E0107",
+        }
+    }
+}
+
+impl fmt::Display for LexingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexingError::InvalidNumber(slice, _) => write!(f, "invalid number `{}`", slice),
+            LexingError::NumberOutOfRange => write!(f, "number is too large"),
+            LexingError::PermissiveOperatorChar(c, _) =>
+                write!(f, "`{}` is not allowed in operators under the strict policy", c),
+            LexingError::UnterminatedString => write!(f, "unterminated string literal starting here"),
+            LexingError::InvalidEscape(escape, _) => write!(f, "invalid escape `{}` in string literal", escape),
+            LexingError::TokenTooLong(len) =>
+                write!(f, "token is {} bytes long, the limit is {}", len, MAX_TOKEN_LEN),
+            LexingError::UnrecognisedCharacter => write!(f, "unrecognised character"),
+        }
     }
 }
 
-fn newline_callback(lex: &mut Lexer<Token>) -> Skip {
-    lex.extras += 1;
-    Skip
+// A newline swallows the indentation and blank lines after it, so this runs once per
+// run of them. Runs are a few bytes, which one loop gets through faster than memchr
+// finding the newlines and then the last of them
+fn newline_callback<'src>(lex: &mut Lexer<'src, Token<'src>>) {
+    let start = lex.span().start;
+    for (i, &b) in lex.slice().as_bytes().iter().enumerate() {
+        if b == b'\n' {
+            lex.extras.line += 1;
+            lex.extras.line_start = start + i + 1;
+        }
+    }
+}
+
+fn slice_callback<'src>(lex: &mut Lexer<'src, Token<'src>>) -> Result<(&'src str, Pos), LexingError> {
+    if lex.slice().len() > MAX_TOKEN_LEN {
+        return Err(LexingError::TokenTooLong(lex.slice().len()));
+    }
+
+    Ok((lex.slice(), lex.extras.pos(lex.span())))
 }
 
-fn string_callback(lex: &mut Lexer<Token>) -> (String, Pos) {
-    let body = lex.slice().to_owned();
-    let pos = Pos {
-        line: lex.extras,
-        column: lex.span().start + 1,
-        range: lex.span()
+// The value of a string literal, without its quotes and with its escapes decoded. The
+// token's position still covers the literal as written. Only literals with escapes in
+// them need a string of their own
+fn string_literal_callback<'src>(lex: &mut Lexer<'src, Token<'src>>) -> Result<(Cow<'src, str>, Pos), LexingError> {
+    if lex.slice().len() > MAX_TOKEN_LEN {
+        return Err(LexingError::TokenTooLong(lex.slice().len()));
+    }
+
+    let slice = lex.slice();
+    let body = &slice[1..slice.len() - 1];
+    let offset = lex.span().start + 1;
+    if !body.contains('\\') {
+        return Ok((Cow::Borrowed(body), lex.extras.pos(lex.span())));
+    }
+    let invalid = |from: usize, len: usize| {
+        LexingError::InvalidEscape(body[from..from + len].to_owned(), offset + from..offset + from + len)
     };
 
-    (body, pos)
+    let mut value = String::with_capacity(body.len());
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        let decoded = match chars.next() {
+            Some((_, '"')) => '"',
+            Some((_, '\\')) => '\\',
+            Some((_, '/')) => '/',
+            Some((_, 'b')) => '\u{8}',
+            Some((_, 'f')) => '\u{c}',
+            Some((_, 'n')) => '\n',
+            Some((_, 'r')) => '\r',
+            Some((_, 't')) => '\t',
+            Some((_, 'u')) => {
+                let digits = body[i + 2..].bytes().take(4).take_while(u8::is_ascii_hexdigit).count();
+                let code = match digits {
+                    4 => u32::from_str_radix(&body[i + 2..i + 6], 16).ok().and_then(char::from_u32),
+                    _ => None,
+                };
+                let code = code.ok_or_else(|| invalid(i, 2 + digits))?;
+                chars.nth(3);
+                code
+            }
+            Some((_, other)) => return Err(invalid(i, 1 + other.len_utf8())),
+            None => return Err(invalid(i, 1)),
+        };
+        value.push(decoded);
+    }
+
+    Ok((Cow::Owned(value), lex.extras.pos(lex.span())))
 }
 
-fn loc_callback(lex: &mut Lexer<Token>) -> Pos {
-    Pos {
-        line: lex.extras,
-        column: lex.span().start + 1,
-        range: lex.span()
+fn symbol_callback<'src>(lex: &mut Lexer<'src, Token<'src>>) -> Result<(&'src str, Pos), LexingError> {
+    if lex.extras.policy == IdentifierPolicy::Strict {
+        let start = lex.span().start;
+        if let Some((i, c)) = lex.slice().char_indices().find(|(_, c)| !STRICT_OPERATOR_CHARS.contains(*c)) {
+            return Err(LexingError::PermissiveOperatorChar(c, start + i));
+        }
     }
+
+    slice_callback(lex)
 }
 
-fn num_callback(lex: &mut Lexer<Token>) -> Result<(f64, Pos), LexingError> {
+// Matches the same prefix as a string literal without its closing quote, so only wins
+// when the quote is missing. The error's span starts at the opening quote
+fn unterminated_callback<'src>(_: &mut Lexer<'src, Token<'src>>) -> Result<(Cow<'src, str>, Pos), LexingError> {
+    Err(LexingError::UnterminatedString)
+}
+
+fn loc_callback<'src>(lex: &mut Lexer<'src, Token<'src>>) -> Pos {
+    lex.extras.pos(lex.span())
+}
+
+fn num_callback<'src>(lex: &mut Lexer<'src, Token<'src>>) -> Result<(f64, Pos), LexingError> {
     let body = lex.slice().parse::<f64>();
     match body {
-        Err(e) => Err(<Token as Logos>::Error::from(e)),
-        Ok(n) => {
-            let pos = Pos {
-                line: lex.extras,
-                column: lex.span().start + 1,
-                range: lex.span()
-            };
-            Ok((n, pos))
-        }
+        Err(_) => Err(malformed_number(lex)),
+        Ok(n) if n.is_infinite() => Err(LexingError::NumberOutOfRange),
+        Ok(n) => Ok((n, lex.extras.pos(lex.span()))),
     }
 }
 
-// TODO: I think comment lexing might be broken, once again I should write better tests
-// TODO: Number parsing is slightly broken, 4.a parses as "4.0", ".", and "a" which is wrong
-//       It should be an error
-#[derive(Logos, Debug, PartialEq)]
-#[logos(skip r"[ \t\f]+", error = LexingError, extras = usize)]
-pub enum Token {
-    // Newline handling for positions
-    #[regex(r"\n", newline_callback)]
-    Newline,
+// Whether whole numbers past the range of i64 are kept, as evaluation promotes results
+// that overflow to big integers, see eval::Overflow
+const BIG_LITERALS: bool = !cfg!(any(feature = "overflow-checked", feature = "overflow-wrapping"));
+
+fn int_callback<'src>(lex: &mut Lexer<'src, Token<'src>>) -> Result<(Whole<'src>, Pos), LexingError> {
+    match lex.slice().parse::<i64>() {
+        Ok(n) => Ok((Whole::Int(n), lex.extras.pos(lex.span()))),
+        // Logos can hand back a malformed literal like `1e+` here when it backtracks
+        Err(_) if lex.slice().bytes().all(|b| b.is_ascii_digit()) && BIG_LITERALS => {
+            Ok((Whole::Big(lex.slice()), lex.extras.pos(lex.span())))
+        }
+        Err(_) if lex.slice().bytes().all(|b| b.is_ascii_digit()) => Err(LexingError::NumberOutOfRange),
+        Err(_) => Err(malformed_number(lex)),
+    }
+}
 
-    // Literals
-    #[regex(r"([[:alpha:]]|_)[[:word:]]*'*", string_callback)]
-    #[regex(r#"[^[[:digit:]][[:alpha:]][ \t\n\f]!'"_\(\)\[\],;:|\\]+"#, string_callback)]
-    Identifier((String, Pos)),
+// A whole number literal, as its digits when it is past the range of i64
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Whole<'src> {
+    Int(i64),
+    Big(&'src str),
+}
 
-    #[regex(r#""([^"\\\x00-\x1F]|\\(["\\bnfrt/]|u[a-fA-F0-9]{4}))*""#, string_callback)]
-    String((String, Pos)),
+// Anything starting with a digit that the number rules doesn't match in full, which
+// would otherwise split into a number and whatever follows
+fn malformed_number<'src>(lex: &mut Lexer<'src, Token<'src>>) -> LexingError {
+    LexingError::InvalidNumber(lex.slice().to_owned(), lex.span())
+}
 
-    #[regex(r"[[:digit:]]+(\.[[:digit:]]+)?([eE][-+]?[[:digit:]]+)?", num_callback)]
+#[derive(Logos, Debug, PartialEq)]
+#[logos(skip r"[ \t\f\r]+")]
+#[logos(skip(r"\n[ \t\f\r\n]*", newline_callback))]
+// `!` comments run to the end of the line, leaving the newline to count it
+#[logos(skip r"![^\n]*")]
+#[logos(error = LexingError, extras = Extras)]
+pub enum Token<'src> {
+    // Literals, borrowed from the source where they can be
+    #[regex(r"([[:alpha:]]|_)[[:word:]]*'*", slice_callback)]
+    #[token("::", slice_callback)]
+    #[regex(r#"[^[[:digit:]][[:alpha:]][ \t\r\n\f]!'"_\(\)\[\],;:|\\]+"#, symbol_callback)]
+    Identifier((&'src str, Pos)),
+
+    // Any character can follow a backslash here, so a bad escape is reported as one
+    // rather than as an unterminated string
+    #[regex(r#""([^"\\\x00-\x1F]|\\[^\x00-\x1F])*""#, string_literal_callback)]
+    #[regex(r#""([^"\\\x00-\x1F]|\\[^\x00-\x1F])*"#, unterminated_callback)]
+    String((Cow<'src, str>, Pos)),
+
+    // Literals with neither a fraction nor an exponent
+    #[regex(r"[[:digit:]]+", int_callback, priority = 4)]
+    Int((Whole<'src>, Pos)),
+
+    #[regex(r"[[:digit:]]+(\.[[:digit:]]+([eE][-+]?[[:digit:]]+)?|[eE][-+]?[[:digit:]]+)", num_callback, priority = 4)]
+    #[regex(r"[[:digit:]][[:word:].]*", |lex| Err(malformed_number(lex)))]
     Num((f64, Pos)),
 
     // Punctuation
@@ -102,9 +311,6 @@ pub enum Token {
     SemiColon(Pos),
 
     // Reserved
-    #[token("!", loc_callback)]
-    Bang(Pos),
-
     #[token("++", loc_callback)]
     PlusPlus(Pos),
 
@@ -176,6 +382,9 @@ pub enum Token {
     #[token("save", loc_callback)]
     Save(Pos),
 
+    #[token("syntax", loc_callback)]
+    Syntax(Pos),
+
     #[token("then", loc_callback)]
     Then(Pos),
 
@@ -218,6 +427,308 @@ pub enum Token {
     PubType(Pos),
 }
 
+impl Token<'_> {
+    pub fn name(&self) -> &'static str {
+        self.kind().name()
+    }
+
+    // The lexer always reads the compat keywords as keywords, this reads them as
+    // identifiers where the dialect doesn't have them
+    pub fn in_dialect(self, dialect: Dialect) -> Self {
+        match self.kind().compat_word() {
+            Some(word) if dialect == Dialect::Modern => Token::Identifier((word, self.pos().clone())),
+            _ => self,
+        }
+    }
+
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Token::Identifier(_) => TokenKind::Identifier,
+            Token::String(_) => TokenKind::String,
+            Token::Int(_) => TokenKind::Int,
+            Token::Num(_) => TokenKind::Num,
+            Token::LParen(_) => TokenKind::LParen,
+            Token::RParen(_) => TokenKind::RParen,
+            Token::LSquare(_) => TokenKind::LSquare,
+            Token::RSquare(_) => TokenKind::RSquare,
+            Token::Comma(_) => TokenKind::Comma,
+            Token::SemiColon(_) => TokenKind::SemiColon,
+            Token::PlusPlus(_) => TokenKind::PlusPlus,
+            Token::TripleDash(_) => TokenKind::TripleDash,
+            Token::Colon(_) => TokenKind::Colon,
+            Token::LeftArrowFat(_) => TokenKind::LeftArrowFat,
+            Token::EqEq(_) => TokenKind::EqEq,
+            Token::RightArrowFat(_) => TokenKind::RightArrowFat,
+            Token::Pipe(_) => TokenKind::Pipe,
+            Token::AbsType(_) => TokenKind::AbsType,
+            Token::Data(_) => TokenKind::Data,
+            Token::Dec(_) => TokenKind::Dec,
+            Token::Display(_) => TokenKind::Display,
+            Token::Else(_) => TokenKind::Else,
+            Token::Edit(_) => TokenKind::Edit,
+            Token::Exit(_) => TokenKind::Exit,
+            Token::If(_) => TokenKind::If,
+            Token::In(_) => TokenKind::In,
+            Token::Infix(_) => TokenKind::Infix,
+            Token::InfixR(_) => TokenKind::InfixR,
+            Token::Lambda(_) => TokenKind::Lambda,
+            Token::Let(_) => TokenKind::Let,
+            Token::LetRec(_) => TokenKind::LetRec,
+            Token::Private(_) => TokenKind::Private,
+            Token::Save(_) => TokenKind::Save,
+            Token::Syntax(_) => TokenKind::Syntax,
+            Token::Then(_) => TokenKind::Then,
+            Token::Type(_) => TokenKind::Type,
+            Token::TypeVar(_) => TokenKind::TypeVar,
+            Token::Uses(_) => TokenKind::Uses,
+            Token::Where(_) => TokenKind::Where,
+            Token::WhereRec(_) => TokenKind::WhereRec,
+            Token::Write(_) => TokenKind::Write,
+            Token::End(_) => TokenKind::End,
+            Token::Module(_) => TokenKind::Module,
+            Token::NonOp(_) => TokenKind::NonOp,
+            Token::PubConst(_) => TokenKind::PubConst,
+            Token::PubFun(_) => TokenKind::PubFun,
+            Token::PubType(_) => TokenKind::PubType,
+        }
+    }
+
+    pub fn pos(&self) -> &Pos {
+        match self {
+            Token::Identifier((_, pos)) | Token::String((_, pos)) => pos,
+            Token::Int((_, pos)) => pos,
+            Token::Num((_, pos)) => pos,
+            Token::LParen(pos) |
+            Token::RParen(pos) |
+            Token::LSquare(pos) |
+            Token::RSquare(pos) |
+            Token::Comma(pos) |
+            Token::SemiColon(pos) |
+            Token::PlusPlus(pos) |
+            Token::TripleDash(pos) |
+            Token::Colon(pos) |
+            Token::LeftArrowFat(pos) |
+            Token::EqEq(pos) |
+            Token::RightArrowFat(pos) |
+            Token::Pipe(pos) |
+            Token::AbsType(pos) |
+            Token::Data(pos) |
+            Token::Dec(pos) |
+            Token::Display(pos) |
+            Token::Else(pos) |
+            Token::Edit(pos) |
+            Token::Exit(pos) |
+            Token::If(pos) |
+            Token::In(pos) |
+            Token::Infix(pos) |
+            Token::InfixR(pos) |
+            Token::Lambda(pos) |
+            Token::Let(pos) |
+            Token::LetRec(pos) |
+            Token::Private(pos) |
+            Token::Save(pos) |
+            Token::Syntax(pos) |
+            Token::Then(pos) |
+            Token::Type(pos) |
+            Token::TypeVar(pos) |
+            Token::Uses(pos) |
+            Token::Where(pos) |
+            Token::WhereRec(pos) |
+            Token::Write(pos) |
+            Token::End(pos) |
+            Token::Module(pos) |
+            Token::NonOp(pos) |
+            Token::PubConst(pos) |
+            Token::PubFun(pos) |
+            Token::PubType(pos) => pos,
+        }
+    }
+}
+
+// Which token it is, without its payload or position, for matching tokens cheaply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Identifier,
+    String,
+    Int,
+    Num,
+    LParen,
+    RParen,
+    LSquare,
+    RSquare,
+    Comma,
+    SemiColon,
+    PlusPlus,
+    TripleDash,
+    Colon,
+    LeftArrowFat,
+    EqEq,
+    RightArrowFat,
+    Pipe,
+    AbsType,
+    Data,
+    Dec,
+    Display,
+    Else,
+    Edit,
+    Exit,
+    If,
+    In,
+    Infix,
+    InfixR,
+    Lambda,
+    Let,
+    LetRec,
+    Private,
+    Save,
+    Syntax,
+    Then,
+    Type,
+    TypeVar,
+    Uses,
+    Where,
+    WhereRec,
+    Write,
+    End,
+    Module,
+    NonOp,
+    PubConst,
+    PubFun,
+    PubType,
+}
+
+impl TokenKind {
+    // How the keywords only the classic dialect has are spelled
+    pub fn compat_word(self) -> Option<&'static str> {
+        match self {
+            TokenKind::End => Some("end"),
+            TokenKind::Module => Some("module"),
+            TokenKind::NonOp => Some("nonop"),
+            TokenKind::PubConst => Some("pubconst"),
+            TokenKind::PubFun => Some("pubfun"),
+            TokenKind::PubType => Some("pubtype"),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenKind::Identifier => "Identifier",
+            TokenKind::String => "String",
+            TokenKind::Int => "Int",
+            TokenKind::Num => "Num",
+            TokenKind::LParen => "LParen",
+            TokenKind::RParen => "RParen",
+            TokenKind::LSquare => "LSquare",
+            TokenKind::RSquare => "RSquare",
+            TokenKind::Comma => "Comma",
+            TokenKind::SemiColon => "SemiColon",
+            TokenKind::PlusPlus => "PlusPlus",
+            TokenKind::TripleDash => "TripleDash",
+            TokenKind::Colon => "Colon",
+            TokenKind::LeftArrowFat => "LeftArrowFat",
+            TokenKind::EqEq => "EqEq",
+            TokenKind::RightArrowFat => "RightArrowFat",
+            TokenKind::Pipe => "Pipe",
+            TokenKind::AbsType => "AbsType",
+            TokenKind::Data => "Data",
+            TokenKind::Dec => "Dec",
+            TokenKind::Display => "Display",
+            TokenKind::Else => "Else",
+            TokenKind::Edit => "Edit",
+            TokenKind::Exit => "Exit",
+            TokenKind::If => "If",
+            TokenKind::In => "In",
+            TokenKind::Infix => "Infix",
+            TokenKind::InfixR => "InfixR",
+            TokenKind::Lambda => "Lambda",
+            TokenKind::Let => "Let",
+            TokenKind::LetRec => "LetRec",
+            TokenKind::Private => "Private",
+            TokenKind::Save => "Save",
+            TokenKind::Syntax => "Syntax",
+            TokenKind::Then => "Then",
+            TokenKind::Type => "Type",
+            TokenKind::TypeVar => "TypeVar",
+            TokenKind::Uses => "Uses",
+            TokenKind::Where => "Where",
+            TokenKind::WhereRec => "WhereRec",
+            TokenKind::Write => "Write",
+            TokenKind::End => "End",
+            TokenKind::Module => "Module",
+            TokenKind::NonOp => "NonOp",
+            TokenKind::PubConst => "PubConst",
+            TokenKind::PubFun => "PubFun",
+            TokenKind::PubType => "PubType",
+        }
+    }
+}
+
+// What a literal or identifier token carries besides its position
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal<'src> {
+    Identifier(&'src str),
+    String(Cow<'src, str>),
+    Int(i64),
+    BigInt(&'src str),
+    Num(f64),
+}
+
+// A token as its kind and position, with the payload if it has one
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken<'src> {
+    pub kind: TokenKind,
+    pub literal: Option<Literal<'src>>,
+    pub pos: Pos,
+}
+
+impl<'src> SpannedToken<'src> {
+    // See Token::in_dialect
+    pub fn in_dialect(self, dialect: Dialect) -> Self {
+        match self.kind.compat_word() {
+            Some(word) if dialect == Dialect::Modern => {
+                SpannedToken { kind: TokenKind::Identifier, literal: Some(Literal::Identifier(word)), pos: self.pos }
+            }
+            _ => self,
+        }
+    }
+
+    // `syntax` is only a keyword where macros are allowed, elsewhere it is a name
+    pub fn with_macros(self, macros: bool) -> Self {
+        match self.kind {
+            TokenKind::Syntax if !macros => {
+                SpannedToken { kind: TokenKind::Identifier, literal: Some(Literal::Identifier("syntax")), pos: self.pos }
+            }
+            TokenKind::Identifier if macros && self.identifier() == Some("syntax") => {
+                SpannedToken { kind: TokenKind::Syntax, literal: None, pos: self.pos }
+            }
+            _ => self,
+        }
+    }
+
+    pub fn identifier(&self) -> Option<&'src str> {
+        match self.literal {
+            Some(Literal::Identifier(name)) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl<'src> From<Token<'src>> for SpannedToken<'src> {
+    fn from(token: Token<'src>) -> Self {
+        let kind = token.kind();
+        let (literal, pos) = match token {
+            Token::Identifier((name, pos)) => (Some(Literal::Identifier(name)), pos),
+            Token::String((s, pos)) => (Some(Literal::String(s)), pos),
+            Token::Int((Whole::Int(n), pos)) => (Some(Literal::Int(n)), pos),
+            Token::Int((Whole::Big(digits), pos)) => (Some(Literal::BigInt(digits)), pos),
+            Token::Num((n, pos)) => (Some(Literal::Num(n)), pos),
+            other => (None, other.pos().clone()),
+        };
+        SpannedToken { kind, literal, pos }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // TODO: Update tests and create proper testing method
@@ -235,14 +746,217 @@ mod tests {
             }),
         ]);
 
-        let mut lex = Token::lexer_with_extras("_lift0'", 1);
+        let mut lex = Token::lexer("_lift0'");
 
         while let Some(tok) = lex.next() {
             if let Ok(Token::Identifier((name, pos))) = tok {
                 assert_eq!(lex.slice(), name);
-                let det_pos = identifiers.get(&name.as_str());
+                let det_pos = identifiers.get(&name);
                 assert_eq!(det_pos, Some(&pos))
             }
         }
     }
+
+    #[test]
+    fn should_count_lines_across_blank_runs() {
+        let mut lex = Token::lexer("a\n\n  \n\tb\nc");
+
+        let lines: Vec<usize> = lex.by_ref()
+            .filter_map(|tok| match tok {
+                Ok(Token::Identifier((_, pos))) => Some(pos.line),
+                _ => None
+            })
+            .collect();
+
+        assert_eq!(lines, vec![1, 4, 5]);
+        assert_eq!(lex.extras.line, 5);
+    }
+
+    #[test]
+    fn should_lex_standard_prelude() {
+        let lex = Token::lexer(include_str!("../../../lib/Standard.hop"));
+        assert!(lex.into_iter().all(|tok| tok.is_ok()));
+    }
+
+    #[test]
+    fn should_reject_permissive_operators_when_strict() {
+        let strict = |src| Token::lexer_with_extras(src, Extras::with_policy(IdentifierPolicy::Strict))
+            .collect::<Vec<_>>();
+
+        assert!(matches!(strict("a <=> b")[1], Ok(Token::Identifier(_))));
+        assert_eq!(strict("a +{ b")[1], Err(LexingError::PermissiveOperatorChar('{', 3)));
+        assert!(matches!(Token::lexer("a +{ b").nth(1), Some(Ok(Token::Identifier(_)))));
+    }
+
+    #[test]
+    fn should_only_read_compat_keywords_in_the_classic_dialect() {
+        let kinds = |dialect| Token::lexer_with_extras("module m; nonop +; end", Extras::default().with_dialect(dialect))
+            .map(|tok| tok.unwrap().in_dialect(dialect).name())
+            .collect::<Vec<_>>();
+
+        assert_eq!(kinds(Dialect::Classic), ["Module", "Identifier", "SemiColon", "NonOp", "Identifier", "SemiColon", "End"]);
+        assert_eq!(kinds(Dialect::Modern)[..4], ["Identifier", "Identifier", "SemiColon", "Identifier"]);
+        let token: SpannedToken = Token::lexer("pubfun").next().unwrap().unwrap().into();
+        assert_eq!(token.in_dialect(Dialect::Modern).identifier(), Some("pubfun"));
+    }
+
+    #[test]
+    fn should_parse_floats_exactly() {
+        let nums = |src| Token::lexer(src)
+            .map(|tok| match tok {
+                Ok(Token::Num((n, _))) => Ok(n.to_bits()),
+                Ok(other) => panic!("expected a number, found {:?}", other),
+                Err(e) => Err(e)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(nums("0.1 2.5E-3 1e308 0.30000000000000004"), vec![
+            Ok(0.1f64.to_bits()),
+            Ok(2.5e-3f64.to_bits()),
+            Ok(1e308f64.to_bits()),
+            Ok((0.1f64 + 0.2).to_bits()),
+        ]);
+        assert_eq!(nums("1e309"), vec![Err(LexingError::NumberOutOfRange)]);
+        assert_eq!(nums("1e-400"), vec![Ok(0f64.to_bits())]);
+    }
+
+    #[test]
+    fn should_keep_integers_exact() {
+        let mut lex = Token::lexer("9007199254740993 9223372036854775807 9223372036854775808 1.0");
+        assert!(matches!(lex.next(), Some(Ok(Token::Int((Whole::Int(9007199254740993), _))))));
+        assert!(matches!(lex.next(), Some(Ok(Token::Int((Whole::Int(i64::MAX), _))))));
+        if BIG_LITERALS {
+            assert!(matches!(lex.next(), Some(Ok(Token::Int((Whole::Big("9223372036854775808"), _))))));
+        } else {
+            assert_eq!(lex.next(), Some(Err(LexingError::NumberOutOfRange)));
+        }
+        assert!(matches!(lex.next(), Some(Ok(Token::Num((1.0, _))))));
+    }
+
+    #[test]
+    fn should_split_tokens_into_kind_and_literal() {
+        let tokens: Vec<SpannedToken> = Token::lexer("f 12 \"s\" ;").map(|t| t.unwrap().into()).collect();
+        let kinds: Vec<_> = tokens.iter().map(|t| t.kind).collect();
+        assert_eq!(kinds, [TokenKind::Identifier, TokenKind::Int, TokenKind::String, TokenKind::SemiColon]);
+        assert_eq!(tokens[0].identifier(), Some("f"));
+        assert_eq!(tokens[1].literal, Some(Literal::Int(12)));
+        assert_eq!(tokens[2].literal, Some(Literal::String(Cow::Borrowed("s"))));
+        assert_eq!((tokens[3].literal.as_ref(), tokens[3].pos.range.clone()), (None, 9..10));
+    }
+
+    #[test]
+    fn should_treat_crlf_as_newline() {
+        let lines: Vec<_> = Token::lexer("a\r\nb \r\n\r\n c")
+            .map(|tok| match tok {
+                Ok(Token::Identifier((name, pos))) => (name, pos.line),
+                other => panic!("unexpected {:?}", other)
+            })
+            .collect();
+
+        assert_eq!(lines, vec![("a", 1), ("b", 2), ("c", 4)]);
+    }
+
+    #[test]
+    fn should_decode_string_escapes() {
+        let mut lex = Token::lexer(r#"x "a\"b\\c\n\u00e9\u2603" "\q" "ab\u12g4" "\ud800""#);
+        lex.next();
+        let Some(Ok(Token::String((value, pos)))) = lex.next() else { panic!() };
+        assert_eq!(value, "a\"b\\c\né☃");
+        assert_eq!(pos.range, 2..25);
+        assert_eq!(lex.next(), Some(Err(LexingError::InvalidEscape(r"\q".to_owned(), 27..29))));
+        assert_eq!(lex.next(), Some(Err(LexingError::InvalidEscape(r"\u12".to_owned(), 34..38))));
+        assert_eq!(lex.next(), Some(Err(LexingError::InvalidEscape(r"\ud800".to_owned(), 43..49))));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn should_report_unterminated_strings_at_the_quote() {
+        let mut lex = Token::lexer("x = \"abc def;\ny");
+        assert!(matches!(lex.next(), Some(Ok(Token::Identifier(_)))));
+        assert!(matches!(lex.next(), Some(Ok(Token::Identifier(_)))));
+        assert_eq!(lex.next(), Some(Err(LexingError::UnterminatedString)));
+        assert_eq!(lex.span(), 4..13);
+        assert!(matches!(lex.next(), Some(Ok(Token::Identifier((name, _)))) if name == "y"));
+        assert_eq!(lex.next(), None);
+
+        let long = "a".repeat(MAX_TOKEN_LEN + 1);
+        assert_eq!(Token::lexer(&long).next(), Some(Err(LexingError::TokenTooLong(MAX_TOKEN_LEN + 1))));
+    }
+
+    #[test]
+    fn should_count_columns_from_the_start_of_each_line() {
+        let source = "dec f : num;\n\n  --- f <= \r\n\t1;";
+        let positions: Vec<_> = Token::lexer(source)
+            .map(|tok| {
+                let pos = tok.unwrap().pos().clone();
+                (pos.line, pos.column)
+            })
+            .collect();
+        assert_eq!(positions, [(1, 1), (1, 5), (1, 7), (1, 9), (1, 12), (3, 3), (3, 7), (3, 9), (4, 2), (4, 3)]);
+    }
+
+    #[test]
+    fn should_reject_malformed_numbers() {
+        for (source, slice) in [("4.a", "4.a"), ("12abc", "12abc"), ("1e", "1e"), ("4.", "4."), ("1.5.3", "1.5.3")] {
+            let mut lex = Token::lexer(source);
+            assert_eq!(lex.next(), Some(Err(LexingError::InvalidNumber(slice.to_owned(), 0..slice.len()))));
+            assert_eq!(lex.next(), None, "{}", source);
+        }
+
+        let nums: Vec<_> = Token::lexer("2.5 1e+5 [1,2]")
+            .filter_map(|tok| match tok.unwrap() {
+                Token::Num((n, _)) => Some(n),
+                Token::Int((Whole::Int(n), _)) => Some(n as f64),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(nums, [2.5, 1e5, 1.0, 2.0]);
+
+        let mut lex = Token::lexer("1e+");
+        assert_eq!(lex.next(), Some(Err(LexingError::InvalidNumber("1e".to_owned(), 0..2))));
+    }
+
+    mod comments {
+        use super::*;
+
+        fn lex(source: &str) -> Vec<(String, usize)> {
+            Token::lexer(source)
+                .map(|tok| {
+                    let tok = tok.unwrap();
+                    let text = match &tok {
+                        Token::Identifier((s, _)) => s.to_string(),
+                        Token::String((s, _)) => s.to_string(),
+                        other => other.name().to_owned(),
+                    };
+                    (text, tok.pos().line)
+                })
+                .collect()
+        }
+
+        fn names(source: &str) -> Vec<String> {
+            lex(source).into_iter().map(|(text, _)| text).collect()
+        }
+
+        #[test]
+        fn should_end_identifiers() {
+            assert_eq!(names("foo! comment\nbar"), ["foo", "bar"]);
+            assert_eq!(names("<>! comment"), ["<>"]);
+        }
+
+        #[test]
+        fn should_not_start_inside_strings() {
+            assert_eq!(names("\"hi! there\" ! comment \"quoted\""), ["hi! there"]);
+        }
+
+        #[test]
+        fn should_keep_line_counts() {
+            let source = "a ! one\n! two\r\n\n  ! three\nb !\nc";
+            assert_eq!(lex(source), [("a".to_owned(), 1), ("b".to_owned(), 5), ("c".to_owned(), 6)]);
+        }
+
+        #[test]
+        fn should_run_to_end_of_input() {
+            assert_eq!(names("dec x : num; ! no newline"), ["Dec", "x", "Colon", "num", "SemiColon"]);
+        }
+    }
 }
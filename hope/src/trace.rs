@@ -0,0 +1,216 @@
+use core::fmt;
+use core::time::Duration;
+use log::kv::Value;
+use log::{Level, Record};
+use crate::alloc_prelude::*;
+
+// Spans of the work of each phase, logged through `log` when they end, for `hope --log`
+// and any logger a host installs. Each is logged at debug under the module doing the
+// work, with the phase, what it worked on as a field named by key and, with std, the
+// microseconds it took as `elapsed_us`. Nothing is formatted unless the target's debug
+// messages are wanted
+pub struct Span {
+    target: &'static str,
+    phase: &'static str,
+    key: &'static str,
+    subject: String,
+    #[cfg(feature = "std")]
+    start: std::time::Instant,
+}
+
+pub fn span(target: &'static str, phase: &'static str, key: &'static str, subject: impl fmt::Display) -> Option<Span> {
+    if !log::log_enabled!(target: target, Level::Debug) {
+        return None;
+    }
+    let subject = subject.to_string();
+    log::trace!(target: target, "{} {} {}", phase, key, subject);
+    #[cfg(feature = "std")]
+    let start = std::time::Instant::now();
+    Some(Span {
+        target,
+        phase,
+        key,
+        subject,
+        #[cfg(feature = "std")]
+        start,
+    })
+}
+
+impl Span {
+    #[cfg(feature = "std")]
+    fn elapsed(&self) -> Option<Duration> {
+        Some(self.start.elapsed())
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn elapsed(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = self.elapsed();
+        let message = match elapsed {
+            Some(elapsed) => format!("{} {} {} in {:?}", self.phase, self.key, self.subject, elapsed),
+            None => format!("{} {} {}", self.phase, self.key, self.subject),
+        };
+        let fields = [
+            Some(("phase", Value::from(self.phase))),
+            Some((self.key, Value::from(self.subject.as_str()))),
+            elapsed.map(|elapsed| ("elapsed_us", Value::from(elapsed.as_micros() as u64))),
+        ];
+        log::logger().log(&Record::builder()
+            .level(Level::Debug)
+            .target(self.target)
+            .args(format_args!("{}", message))
+            .key_values(&fields)
+            .build());
+    }
+}
+
+#[cfg(feature = "std")]
+pub use logger::{init, Filter, Logger};
+
+#[cfg(feature = "std")]
+mod logger {
+    use std::io::Write;
+    use std::str::FromStr;
+    use log::kv::{self, Key, Value, VisitSource};
+    use log::{LevelFilter, Log, Metadata, Record};
+
+    // Which messages are wanted, as `--log` is written: a level for every target, one
+    // for a target and those under it as `hope::types=debug`, or several of those split
+    // by commas. The most specific target given decides
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Filter {
+        default: LevelFilter,
+        targets: Vec<(String, LevelFilter)>,
+    }
+
+    impl FromStr for Filter {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Filter, String> {
+            let mut filter = Filter { default: LevelFilter::Off, targets: Vec::new() };
+            let level = |level: &str| LevelFilter::from_str(level).map_err(|_| format!("`{}` is not a level, which are off, error, warn, info, debug and trace", level));
+            for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+                match part.split_once('=') {
+                    Some((target, wanted)) => filter.targets.push((target.to_owned(), level(wanted)?)),
+                    None if LevelFilter::from_str(part).is_ok() => filter.default = level(part)?,
+                    // A target alone wants everything of it
+                    None => filter.targets.push((part.to_owned(), LevelFilter::Trace)),
+                }
+            }
+            Ok(filter)
+        }
+    }
+
+    impl Filter {
+        pub fn level(&self, target: &str) -> LevelFilter {
+            let under = |name: &str| target == name || target.strip_prefix(name).is_some_and(|rest| rest.starts_with("::"));
+            let found = self.targets.iter().filter(|(name, _)| under(name)).max_by_key(|(name, _)| name.len());
+            found.map_or(self.default, |(_, level)| *level)
+        }
+
+        fn max(&self) -> LevelFilter {
+            self.targets.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+        }
+    }
+
+    // Writes each message wanted as a line to stderr, or as a JSON object of its level,
+    // target, message and fields with json, which needs serde
+    pub struct Logger {
+        filter: Filter,
+        #[cfg_attr(not(feature = "serde"), allow(dead_code))]
+        json: bool,
+    }
+
+    impl Logger {
+        pub fn new(filter: Filter, json: bool) -> Self {
+            Logger { filter, json }
+        }
+
+        pub fn line(&self, record: &Record) -> String {
+            let mut fields = Fields(Vec::new());
+            let _ = record.key_values().visit(&mut fields);
+            #[cfg(feature = "serde")]
+            if self.json {
+                let mut object = serde_json::Map::new();
+                object.insert("level".to_owned(), record.level().as_str().to_lowercase().into());
+                object.insert("target".to_owned(), record.target().into());
+                object.insert("message".to_owned(), record.args().to_string().into());
+                for (key, value) in fields.0 {
+                    let value = value.parse::<u64>().map_or_else(|_| value.into(), Into::into);
+                    object.insert(key, value);
+                }
+                return serde_json::Value::Object(object).to_string();
+            }
+            let fields: String = fields.0.iter().map(|(key, value)| format!(" {}={}", key, value)).collect();
+            format!("{:>5} {}: {}{}", record.level(), record.target(), record.args(), fields)
+        }
+    }
+
+    struct Fields(Vec<(String, String)>);
+
+    impl<'kvs> VisitSource<'kvs> for Fields {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            self.0.push((key.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    impl Log for Logger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.filter.level(metadata.target())
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                let _ = writeln!(std::io::stderr().lock(), "{}", self.line(record));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    // Logs what the filter wants for the rest of the process
+    pub fn init(filter: Filter, json: bool) -> Result<(), log::SetLoggerError> {
+        log::set_max_level(filter.max());
+        log::set_boxed_logger(Box::new(Logger::new(filter, json)))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use log::{Level, Record};
+    use super::*;
+
+    #[test]
+    fn should_want_what_the_most_specific_target_says() {
+        let filter: Filter = "warn,hope::types=debug,hope::types::check=off".parse().unwrap();
+        assert_eq!(filter.level("hope::types").to_string(), "DEBUG");
+        assert_eq!(filter.level("hope::types::coverage").to_string(), "DEBUG");
+        assert_eq!(filter.level("hope::types::check").to_string(), "OFF");
+        assert_eq!(filter.level("hope::typesetting").to_string(), "WARN");
+        assert!("hope=loud".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn should_write_fields_after_the_message() {
+        let logger = Logger::new("debug".parse().unwrap(), false);
+        let fields = [("phase", "check"), ("decl", "f")];
+        let line = |logger: &Logger| logger.line(&Record::builder()
+            .level(Level::Debug)
+            .target("hope::types")
+            .args(format_args!("check f"))
+            .key_values(&fields)
+            .build());
+        assert_eq!(line(&logger), "DEBUG hope::types: check f phase=check decl=f");
+        #[cfg(feature = "serde")]
+        {
+            let json: serde_json::Value = serde_json::from_str(&line(&Logger::new("debug".parse().unwrap(), true))).unwrap();
+            assert_eq!((&json["level"], &json["decl"]), (&serde_json::json!("debug"), &serde_json::json!("f")));
+        }
+    }
+}
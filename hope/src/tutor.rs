@@ -0,0 +1,329 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+#[cfg(feature = "repl")]
+use rustyline::error::ReadlineError;
+#[cfg(feature = "repl")]
+use rustyline::DefaultEditor;
+use crate::eval::{EvalError, Limits};
+use crate::repl::{Output, Session, SessionError};
+#[cfg(feature = "repl")]
+use crate::help;
+#[cfg(feature = "repl")]
+use crate::repl::is_complete;
+#[cfg(feature = "repl")]
+use crate::source;
+
+// The exercises of `hope tutor`, each asking for a definition that is held to a type
+// and to tests the learner doesn't see until one fails
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exercise {
+    pub name: &'static str,
+    pub task: &'static str,
+    pub hint: &'static str,
+    // Declarations the session is given before the exercise starts
+    pub setup: &'static str,
+    pub ty: &'static str,
+    // Expressions with what each should evaluate to
+    pub tests: &'static [(&'static str, &'static str)],
+}
+
+pub const EXERCISES: [Exercise; 6] = [
+    Exercise {
+        name: "double",
+        task: "Functions are declared with `dec` and defined by equations, `--- name args <= result;`, \
+               and declaring one again starts it over. Define `double`, giving twice the number it is given.",
+        hint: "dec double : num -> num;\n--- double n <= ...;",
+        setup: "",
+        ty: "num -> num",
+        tests: &[("double 4", "8"), ("double 0", "0"), ("double (0 - 3)", "0 - 6")],
+    },
+    Exercise {
+        name: "fact",
+        task: "Equations are tried in order, so one for 0 can come before the one for any other number. \
+               Define `fact`, the product of the numbers from 1 up to the one given, 1 for 0.",
+        hint: "dec fact : num -> num;\n--- fact 0 <= 1;\n--- fact n <= n * fact (n - 1);",
+        setup: "",
+        ty: "num -> num",
+        tests: &[("fact 0", "1"), ("fact 1", "1"), ("fact 5", "120")],
+    },
+    Exercise {
+        name: "len",
+        task: "A list is [] or an element on the front of a list, `x :: l`, and equations can take it apart \
+               the same way. Define `len`, how many elements a list has, for lists of anything.",
+        hint: "dec len : list alpha -> num;\n--- len [] <= 0;\n--- len (x :: l) <= ...;",
+        setup: "",
+        ty: "list alpha -> num",
+        tests: &[("len []", "0"), ("len [7, 8, 9]", "3"), ("len \"hope\"", "4")],
+    },
+    Exercise {
+        name: "twice",
+        task: "Functions can be given functions. Define `twice`, which applies the function it is given \
+               to a value and then to the result.",
+        hint: "dec twice : (alpha -> alpha) -> alpha -> alpha;\n--- twice f x <= ...;",
+        setup: "",
+        ty: "(alpha -> alpha) -> alpha -> alpha",
+        tests: &[("twice (lambda n => n * 3) 2", "18"), ("twice reverse \"ab\"", "\"ab\""), ("twice (lambda l => 0 :: l) [1]", "[0, 0, 1]")],
+    },
+    Exercise {
+        name: "sum",
+        task: "Define `sum` of a list of numbers, either by its own equations or with foldr, which \
+               combines the elements from the right, `foldr (nonop +) 0 l`.",
+        hint: "dec sum : list num -> num;\n--- sum [] <= 0;\n--- sum (x :: l) <= x + sum l;",
+        setup: "",
+        ty: "list num -> num",
+        tests: &[("sum []", "0"), ("sum [1, 2, 3, 4]", "10")],
+    },
+    Exercise {
+        name: "insert",
+        task: "The session now has `data tree == leaf ++ node (tree # num # tree);`. Define `insert` of \
+               a number into a tree, keeping smaller numbers to the left of each node and larger ones \
+               to the right.",
+        hint: "dec insert : num # tree -> tree;\n--- insert (n, leaf) <= node (leaf, n, leaf);\n--- insert (n, node (l, m, r)) <= if n < m then ... else ...;",
+        setup: "data tree == leaf ++ node (tree # num # tree);",
+        ty: "num # tree -> tree",
+        tests: &[
+            ("insert (1, leaf)", "node (leaf, 1, leaf)"),
+            ("insert (2, insert (3, insert (1, leaf)))", "node (leaf, 1, node (node (leaf, 2, leaf), 3, leaf))"),
+        ],
+    },
+];
+
+pub fn exercise(name: &str) -> Option<&'static Exercise> {
+    EXERCISES.iter().find(|exercise| exercise.name == name)
+}
+
+// Why a session doesn't yet do what an exercise asks
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    Missing(&'static str),
+    Type { name: &'static str, expected: &'static str, found: String },
+    Test { test: &'static str, expected: String, found: String },
+    // What went wrong evaluating the test
+    Error { test: &'static str, message: String },
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Missing(name) => write!(f, "`{}` isn't defined yet", name),
+            Failure::Type { name, expected, found } => write!(f, "`{}` should have type {}, but it has type {}", name, expected, found),
+            Failure::Test { test, expected, found } => write!(f, "`{}` should be {}, but it is {}", test, expected, found),
+            Failure::Error { test, message } => write!(f, "`{}` failed: {}", test, message),
+        }
+    }
+}
+
+impl Exercise {
+    // Tries the definition in a copy of the session, which is left as it was
+    pub fn check(&self, session: &Session) -> Result<(), Failure> {
+        let Some(found) = session.type_of(self.name) else { return Err(Failure::Missing(self.name)) };
+        let mut trial = session.clone();
+        // Declaring a value of the expected type to be it only checks if its type is at
+        // least as general
+        let probe = format!("dec tutor_expected : {};\n--- tutor_expected <= {};", self.ty, self.name);
+        if trial.submit(&probe).is_err() {
+            return Err(Failure::Type { name: self.name, expected: self.ty, found: found.to_string() });
+        }
+        for (test, expected) in self.tests {
+            let found = value(&mut trial, test).map_err(|error| match error {
+                // Declared but with no equations yet
+                SessionError::Eval(EvalError::UnboundVariable(name, _)) if name == self.name => Failure::Missing(self.name),
+                SessionError::Eval(e) => Failure::Error { test, message: e.to_string() },
+                error => Failure::Error { test, message: error.to_string() },
+            })?;
+            let expected = value(&mut trial, expected).expect("the tests' expected values evaluate");
+            if found != expected {
+                return Err(Failure::Test { test, expected, found });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn value(session: &mut Session, expr: &str) -> Result<String, SessionError> {
+    let outputs = session.submit(&format!("{};", expr))?;
+    match outputs.last() {
+        Some(Output::Value(value, _)) => Ok(value.to_string()),
+        _ => unreachable!("an expression has a value"),
+    }
+}
+
+// The exercises done so far, kept in a file with the name of each on a line of its own
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Progress {
+    pub done: BTreeSet<String>,
+}
+
+impl Progress {
+    // Nothing is done yet if there is no file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(Progress { done: contents.lines().map(str::trim).filter(|line| !line.is_empty()).map(str::to_owned).collect() }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Progress::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.done.iter().map(|name| format!("{}\n", name)).collect::<String>())
+    }
+
+    pub fn next(&self) -> Option<&'static Exercise> {
+        EXERCISES.iter().find(|exercise| !self.done.contains(exercise.name))
+    }
+}
+
+pub fn progress_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".hope_tutor"))
+}
+
+// Definitions are evaluated as at the prompt, which each has the time to itself
+pub fn session() -> Session {
+    let limits = Limits { steps: None, depth: Some(100_000), time: Some(Duration::from_secs(5)) };
+    Session::new().with_limits(limits).with_prelude()
+}
+
+#[cfg(feature = "repl")]
+fn introduce(exercise: &Exercise, first: bool) {
+    let number = EXERCISES.iter().position(|e| e.name == exercise.name).expect("the exercise is one of them") + 1;
+    let mut task = String::new();
+    help::wrap(exercise.task, "", &mut task);
+    print!("\n[{}/{}] {} : {}\n{}", number, EXERCISES.len(), exercise.name, exercise.ty, task);
+    if first {
+        println!("(:hint for a start, :skip to move on, :list for every exercise, :quit to stop)");
+    }
+}
+
+// Takes declarations and expressions as the REPL does, checking the exercise after
+// each input and moving on once it is done. Progress is saved to the file after each
+#[cfg(feature = "repl")]
+pub fn run(path: Option<&Path>) -> rustyline::Result<()> {
+    let mut progress = match path {
+        Some(path) => Progress::load(path)?,
+        None => Progress::default(),
+    };
+    let mut editor = DefaultEditor::new()?;
+    let mut session = session();
+    let save = |progress: &Progress| {
+        if let Some((path, Err(e))) = path.map(|path| (path, progress.save(path))) {
+            eprintln!("can't save progress to {}: {}", source::display(path), source::describe(&e));
+        }
+    };
+    let mut current = progress.next();
+    let mut introduced = None;
+    let mut input = String::new();
+    while let Some(exercise) = current {
+        if introduced != Some(exercise.name) {
+            if session.type_of(exercise.name).is_none() && !exercise.setup.is_empty() {
+                session.submit(exercise.setup).expect("the setup of each exercise checks");
+            }
+            introduce(exercise, introduced.is_none());
+            introduced = Some(exercise.name);
+        }
+        let line = match editor.readline(if input.is_empty() { "tutor> " } else { "       " }) {
+            Ok(line) => line,
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => break,
+            Err(e) => return Err(e),
+        };
+        if input.is_empty() && line.trim_start().starts_with(':') {
+            editor.add_history_entry(line.trim())?;
+            match line.trim() {
+                ":quit" | ":q" => break,
+                ":hint" => println!("{}", exercise.hint),
+                ":skip" => current = EXERCISES.iter().skip_while(|e| e.name != exercise.name).nth(1),
+                ":list" => {
+                    for e in &EXERCISES {
+                        println!("{} {} : {}", if progress.done.contains(e.name) { "*" } else { " " }, e.name, e.ty);
+                    }
+                }
+                command => eprintln!("unknown command {}, the tutor takes :hint, :skip, :list and :quit", command),
+            }
+            continue;
+        }
+        input.push_str(&line);
+        input.push('\n');
+        if !is_complete(&input) {
+            continue;
+        }
+        editor.add_history_entry(input.trim_end())?;
+        match session.submit(&input) {
+            Ok(outputs) => outputs.iter().for_each(|output| println!("{}", output)),
+            Err(e) => eprintln!("{}", e),
+        }
+        input.clear();
+        match exercise.check(&session) {
+            Ok(()) => {
+                println!("That's it, `{}` passes every test.", exercise.name);
+                progress.done.insert(exercise.name.to_owned());
+                save(&progress);
+                current = progress.next();
+            }
+            Err(Failure::Missing(_)) => {}
+            Err(failure) => println!("Not yet: {}", failure),
+        }
+    }
+    if progress.next().is_none() {
+        println!("Every exercise is done.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_hold_definitions_to_the_type_and_the_tests() {
+        let fact = exercise("fact").unwrap();
+        let mut session = session();
+        assert_eq!(fact.check(&session), Err(Failure::Missing("fact")));
+
+        session.submit("dec fact : num -> bool;\n--- fact n <= n > 1;").unwrap();
+        assert_eq!(fact.check(&session).unwrap_err().to_string(), "`fact` should have type num -> num, but it has type num -> bool");
+
+        session.submit("dec fact : num -> num;\n--- fact n <= n;").unwrap();
+        assert_eq!(fact.check(&session).unwrap_err().to_string(), "`fact 0` should be 1, but it is 0");
+
+        session.submit("dec fact : num -> num;\n--- fact n <= n * fact (n - 1);").unwrap();
+        assert!(matches!(fact.check(&session), Err(Failure::Error { test: "fact 0", .. })));
+
+        session.submit("dec fact : num -> num;\n--- fact 0 <= 1;\n--- fact n <= n * fact (n - 1);").unwrap();
+        assert_eq!(fact.check(&session), Ok(()));
+        assert!(session.type_of("tutor_expected").is_none());
+    }
+
+    #[test]
+    fn should_answer_every_exercise_with_its_hint_and_keep_progress() {
+        let answers = [
+            "dec double : num -> num;\n--- double n <= n + n;",
+            "dec fact : num -> num;\n--- fact 0 <= 1;\n--- fact n <= n * fact (n - 1);",
+            "dec len : list alpha -> num;\n--- len [] <= 0;\n--- len (x :: l) <= 1 + len l;",
+            "dec twice : (alpha -> alpha) -> alpha -> alpha;\n--- twice f x <= f (f x);",
+            "dec sum : list num -> num;\n--- sum l <= foldr (nonop +) 0 l;",
+            "dec insert : num # tree -> tree;\n--- insert (n, leaf) <= node (leaf, n, leaf);\n\
+             --- insert (n, node (l, m, r)) <= if n < m then node (insert (n, l), m, r) else node (l, m, insert (n, r));",
+        ];
+        let mut session = session();
+        let mut progress = Progress::default();
+        for (exercise, answer) in EXERCISES.iter().zip(answers) {
+            assert_eq!(progress.next(), Some(exercise));
+            if !exercise.setup.is_empty() {
+                session.submit(exercise.setup).unwrap();
+            }
+            session.submit(answer).unwrap();
+            assert_eq!(exercise.check(&session), Ok(()), "{}", exercise.name);
+            progress.done.insert(exercise.name.to_owned());
+        }
+        assert_eq!(progress.next(), None);
+
+        let path = std::env::temp_dir().join(format!("hope-tutor-{}", std::process::id()));
+        progress.save(&path).unwrap();
+        assert_eq!(Progress::load(&path).unwrap(), progress);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Progress::load(&path).unwrap(), Progress::default());
+    }
+}
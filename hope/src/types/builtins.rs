@@ -0,0 +1,53 @@
+// Types and values every program starts with. Signatures are Hope source, in which
+// `alpha` is the only type variable of a constructor and functions can have `beta` too
+
+pub const TYPES: &[(&str, usize)] = &[
+    ("num", 0),
+    ("char", 0),
+    ("bool", 0),
+    ("list", 1),
+    // The arguments of `format`
+    ("formatarg", 0),
+    ("->", 2),
+    ("#", 2),
+];
+
+// (name, parameters, signature)
+pub const CONSTRUCTORS: &[(&str, &[&str], &str)] = &[
+    ("false", &[], "bool"),
+    ("true", &[], "bool"),
+    ("nil", &["alpha"], "list alpha"),
+    ("::", &["alpha"], "alpha # list alpha -> list alpha"),
+    ("fnum", &[], "num -> formatarg"),
+    ("fstr", &[], "list char -> formatarg"),
+];
+
+pub const FUNCTIONS: &[(&str, &str)] = &[
+    ("+", "num # num -> num"),
+    ("-", "num # num -> num"),
+    ("*", "num # num -> num"),
+    ("/", "num # num -> num"),
+    ("div", "num # num -> num"),
+    ("mod", "num # num -> num"),
+    ("<", "num # num -> bool"),
+    ("=<", "num # num -> bool"),
+    (">", "num # num -> bool"),
+    (">=", "num # num -> bool"),
+    ("=", "alpha # alpha -> bool"),
+    ("/=", "alpha # alpha -> bool"),
+    ("and", "bool # bool -> bool"),
+    ("or", "bool # bool -> bool"),
+    ("not", "bool -> bool"),
+    // Characters by their Unicode code points
+    ("ord", "char -> num"),
+    ("chr", "num -> char"),
+    // Numbers from and to text, the way they are written, see lib/Text.hop
+    ("read_num", "list char -> num"),
+    ("show_num", "num -> list char"),
+    // The text with each directive replaced by its argument, see printf
+    ("format", "list char # list formatarg -> list char"),
+    // The function, remembering what it returns for each argument
+    ("memo", "(alpha -> beta) -> alpha -> beta"),
+    // True, or fails showing where the actual value, second, differs from the expected
+    ("assert_eq", "alpha # alpha -> bool"),
+];
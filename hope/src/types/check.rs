@@ -0,0 +1,835 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use crate::alloc_prelude::*;
+#[cfg(feature = "std")]
+use crate::eval::Builtins;
+use crate::parser;
+use crate::cancel::Cancel;
+use crate::printf;
+use crate::syntax::ast::*;
+use crate::syntax::token::Pos;
+use crate::trace;
+use crate::types::{builtins, coverage, var_name, Scheme, Type, TypeError, TypeWarning};
+
+#[cfg(feature = "std")]
+const STACK_RED_ZONE: usize = 64 * 1024;
+#[cfg(feature = "std")]
+const STACK_GROWTH: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+struct TypeInfo {
+    arity: usize,
+    // Body of a `type` synonym, in terms of Gen(i) for its parameters
+    synonym: Option<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstructorInfo {
+    pub scheme: Scheme,
+    pub arity: usize,
+}
+
+// How unannotated names in a type expression are treated: parameters of a type
+// definition are fixed up front, while a `dec` quantifies every typevar it mentions
+struct TypeScope {
+    names: Vec<String>,
+    open: bool,
+}
+
+enum UnifyError {
+    Mismatch,
+    Occurs(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedDecl {
+    pub decl: Decl,
+    // The declared or inferred type, for declarations that have one
+    pub ty: Option<Scheme>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedProgram {
+    pub decls: Vec<TypedDecl>,
+    pub warnings: Vec<TypeWarning>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Checker {
+    types: BTreeMap<String, TypeInfo>,
+    typevars: BTreeSet<String>,
+    constructors: BTreeMap<String, ConstructorInfo>,
+    globals: BTreeMap<String, Scheme>,
+    // The builtin `format`'s type, to tell it from anything declared in its place
+    format: Option<Scheme>,
+
+    // Inference state, reset after every program
+    bindings: Vec<Option<Type>>,
+    levels: Vec<u32>,
+    level: u32,
+    scopes: Vec<BTreeMap<String, Scheme>>,
+    errors: Vec<TypeError>,
+    cancel: Option<Cancel>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Checker::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        let mut checker = Checker {
+            types: BTreeMap::new(),
+            typevars: BTreeSet::new(),
+            constructors: BTreeMap::new(),
+            globals: BTreeMap::new(),
+            format: None,
+            bindings: Vec::new(),
+            levels: Vec::new(),
+            level: 0,
+            scopes: Vec::new(),
+            errors: Vec::new(),
+            cancel: None,
+        };
+
+        for &(name, arity) in builtins::TYPES {
+            checker.types.insert(name.to_owned(), TypeInfo { arity, synonym: None });
+        }
+
+        for &(name, params, signature) in builtins::CONSTRUCTORS {
+            let mut scope = TypeScope { names: params.iter().map(|p| p.to_string()).collect(), open: false };
+            let ty = checker.builtin_type(signature, &mut scope);
+            let arity = usize::from(matches!(&ty, Type::Con(n, _) if n == "->"));
+            let scheme = Scheme { params: scope.names, ty };
+            checker.constructors.insert(name.to_owned(), ConstructorInfo { scheme, arity });
+        }
+
+        checker.typevars.extend(["alpha", "beta"].map(str::to_owned));
+        for &(name, signature) in builtins::FUNCTIONS {
+            let mut scope = TypeScope { names: Vec::new(), open: true };
+            let ty = checker.builtin_type(signature, &mut scope);
+            checker.globals.insert(name.to_owned(), Scheme { params: scope.names, ty });
+        }
+        checker.typevars.clear();
+        checker.format = checker.globals.get("format").cloned();
+
+        checker
+    }
+
+    // Gives up before the next declaration once cancel is, with only that error
+    pub fn with_cancel(mut self, cancel: Cancel) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    // Adds the host functions, as if they were declared before any program
+    #[cfg(feature = "std")]
+    pub fn with_builtins(mut self, builtins: &Builtins) -> Self {
+        for native in builtins.iter() {
+            self.globals.insert(native.name.clone(), native.scheme.clone());
+        }
+        self
+    }
+
+    // A host function's type, see Builtins::register
+    #[cfg(feature = "std")]
+    pub(crate) fn host_scheme(name: &str, signature: &str) -> Result<Scheme, TypeError> {
+        let mut checker = Checker::new();
+        checker.typevars.extend(["alpha", "beta", "gamma"].map(str::to_owned));
+        let mut scope = TypeScope { names: Vec::new(), open: true };
+        let expr = parser::parse_type(signature).map_err(|e| {
+            TypeError::HostSignature { name: name.to_owned(), message: e.to_string(), pos: e.pos().clone() }
+        })?;
+        let ty = checker.convert(&expr, &mut scope)?;
+        Ok(Scheme { params: scope.names, ty })
+    }
+
+    fn builtin_type(&self, signature: &str, scope: &mut TypeScope) -> Type {
+        let expr = parser::parse_type(signature).expect("builtin signatures parse");
+        self.convert(&expr, scope).expect("builtin signatures are well formed")
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Scheme> {
+        self.constructors.get(name).map(|c| &c.scheme).or_else(|| self.globals.get(name))
+    }
+
+    pub fn constructor(&self, name: &str) -> Option<&ConstructorInfo> {
+        self.constructors.get(name)
+    }
+
+    // Every named value, constructors included, in no particular order
+    pub fn values(&self) -> impl Iterator<Item = (&str, &Scheme)> {
+        let constructors = self.constructors.iter().map(|(name, c)| (name.as_str(), &c.scheme));
+        constructors.chain(self.globals.iter().map(|(name, scheme)| (name.as_str(), scheme)))
+    }
+
+    // The number of parameters a type constructor takes
+    pub fn type_arity(&self, name: &str) -> Option<usize> {
+        self.types.get(name).map(|info| info.arity)
+    }
+
+    // Checks a program against everything declared so far. Declarations only take
+    // effect if the whole program checks, and then only its interface: what it declares
+    // after `private` and the constructors of its `abstype`s stay inside it. What a
+    // `module` doesn't make public is gone from the `end;` on, as if what follows were
+    // another program
+    pub fn check(&mut self, program: Program) -> Result<TypedProgram, Vec<TypeError>> {
+        let hidden = Hidden::of(&program);
+        let mut next = self.clone();
+        let mut typed = TypedProgram { decls: Vec::new(), warnings: Vec::new() };
+        let mut errors = Vec::new();
+        let mut decls = program.decls.into_iter().peekable();
+        while decls.peek().is_some() {
+            let mut part = Vec::new();
+            for decl in decls.by_ref() {
+                let end = matches!(decl.kind, DeclKind::End);
+                part.push(decl);
+                if end {
+                    break;
+                }
+            }
+            let unexported = Hidden::unexported(&part);
+            let before = next.clone();
+            match next.check_decls(Program { decls: part }) {
+                Ok(part) => {
+                    typed.decls.extend(part.decls);
+                    typed.warnings.extend(part.warnings);
+                }
+                Err(e) => errors.extend(e),
+            }
+            next.bindings.clear();
+            next.levels.clear();
+            next.hide(unexported, &before);
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        next.hide(hidden, self);
+        *self = next;
+        Ok(typed)
+    }
+
+    // Hiding a name uncovers whatever it shadowed in before
+    fn hide(&mut self, hidden: Hidden, before: &Checker) {
+        for name in hidden.values {
+            restore(&mut self.globals, &before.globals, name);
+        }
+        for name in hidden.constructors {
+            restore(&mut self.constructors, &before.constructors, name);
+        }
+        for name in hidden.types {
+            restore(&mut self.types, &before.types, name);
+        }
+    }
+
+    fn check_decls(&mut self, program: Program) -> Result<TypedProgram, Vec<TypeError>> {
+        // Type-level declarations and signatures come first, so that equations and
+        // types can refer to anything declared in the program
+        for decl in &program.decls {
+            if let DeclKind::TypeVar(names) = &decl.kind {
+                self.typevars.extend(names.iter().map(|n| n.name.clone()));
+            }
+        }
+        for decl in &program.decls {
+            if let DeclKind::AbsType(head) | DeclKind::Data { head, .. } = &decl.kind {
+                let info = TypeInfo { arity: head.params.len(), synonym: None };
+                self.types.insert(head.name.name.clone(), info);
+            }
+        }
+        self.errors.extend(duplicates(&program.decls));
+        for decl in &program.decls {
+            if let Err(e) = self.declare(decl) {
+                self.errors.push(e);
+            }
+        }
+
+        // Only worth doing once the patterns are known to be well typed
+        let warnings = if self.errors.is_empty() { coverage::check(&program.decls, &self.constructors) } else { Vec::new() };
+        let mut decls = Vec::new();
+        for decl in program.decls {
+            if self.cancel.as_ref().is_some_and(Cancel::is_cancelled) {
+                return Err(vec![TypeError::Cancelled(decl.pos.clone())]);
+            }
+            let ty = match &decl.kind {
+                DeclKind::Dec { names, .. } => self.globals.get(&names[0].name).cloned(),
+                DeclKind::Equation(eq) => {
+                    let _span = trace::span(module_path!(), "check", "decl", &eq.name.name);
+                    self.check_equation(eq);
+                    self.globals.get(&eq.name.name).cloned()
+                }
+                DeclKind::Expr(expr) | DeclKind::Write(expr) => {
+                    let _span = trace::span(module_path!(), "check", "line", expr.pos.line);
+                    self.level += 1;
+                    let ty = self.infer_expr(expr);
+                    self.level -= 1;
+                    Some(self.generalize(&ty))
+                }
+                _ => None,
+            };
+            decls.push(TypedDecl { decl, ty });
+        }
+
+        if self.errors.is_empty() {
+            Ok(TypedProgram { decls, warnings })
+        } else {
+            Err(core::mem::take(&mut self.errors))
+        }
+    }
+
+    fn declare(&mut self, decl: &Decl) -> Result<(), TypeError> {
+        match &decl.kind {
+            DeclKind::Type { head, body } => {
+                let mut scope = TypeScope { names: param_names(head), open: false };
+                let body = self.convert(body, &mut scope)?;
+                let info = TypeInfo { arity: head.params.len(), synonym: Some(body) };
+                self.types.insert(head.name.name.clone(), info);
+            }
+            DeclKind::Data { head, constructors } => {
+                let params = param_names(head);
+                let result = Type::Con(
+                    head.name.name.clone(),
+                    (0..params.len() as u32).map(Type::Gen).collect(),
+                );
+                for constructor in constructors {
+                    let mut scope = TypeScope { names: params.clone(), open: false };
+                    let args = constructor.args.iter()
+                        .map(|arg| self.convert(arg, &mut scope))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let arity = args.len();
+                    let ty = args.into_iter().rev().fold(result.clone(), |r, arg| Type::arrow(arg, r));
+                    let scheme = Scheme { params: params.clone(), ty };
+                    self.constructors.insert(constructor.name.name.clone(), ConstructorInfo { scheme, arity });
+                }
+            }
+            DeclKind::Dec { names, ty } => {
+                let mut scope = TypeScope { names: Vec::new(), open: true };
+                let ty = self.convert(ty, &mut scope)?;
+                let scheme = Scheme { params: scope.names, ty };
+                for name in names {
+                    self.globals.insert(name.name.clone(), scheme.clone());
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn convert(&self, ty: &TypeExpr, scope: &mut TypeScope) -> Result<Type, TypeError> {
+        let name = &ty.name.name;
+        if ty.args.is_empty() {
+            if let Some(i) = scope.names.iter().position(|n| n == name) {
+                return Ok(Type::Gen(i as u32));
+            }
+            if self.typevars.contains(name) {
+                if !scope.open {
+                    return Err(TypeError::UnboundTypeVariable(name.clone(), ty.pos.clone()));
+                }
+                scope.names.push(name.clone());
+                return Ok(Type::Gen(scope.names.len() as u32 - 1));
+            }
+        }
+
+        let info = self.types.get(name)
+            .ok_or_else(|| TypeError::UnknownType(name.clone(), ty.name.pos.clone()))?;
+        if info.arity != ty.args.len() {
+            return Err(TypeError::TypeArity {
+                name: name.clone(),
+                expected: info.arity,
+                found: ty.args.len(),
+                pos: ty.pos.clone(),
+            });
+        }
+
+        let args = ty.args.iter()
+            .map(|arg| self.convert(arg, scope))
+            .collect::<Result<Vec<_>, _>>()?;
+        match &info.synonym {
+            Some(body) => Ok(substitute(body, &args)),
+            None => Ok(Type::Con(name.clone(), args)),
+        }
+    }
+
+    // Unification
+
+    fn fresh(&mut self) -> Type {
+        self.bindings.push(None);
+        self.levels.push(self.level);
+        Type::Var(self.bindings.len() as u32 - 1)
+    }
+
+    fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(v) => match &self.bindings[*v as usize] {
+                Some(bound) => self.resolve(bound),
+                None => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    fn zonk(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Con(name, args) => Type::Con(name, args.iter().map(|a| self.zonk(a)).collect()),
+            other => other,
+        }
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> Result<(), UnifyError> {
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::Var(x), Type::Var(y)) if x == y => Ok(()),
+            (Type::Var(v), ty) | (ty, Type::Var(v)) => {
+                self.adjust(v, &ty)?;
+                self.bindings[v as usize] = Some(ty);
+                Ok(())
+            }
+            (Type::Con(n1, a1), Type::Con(n2, a2)) if n1 == n2 && a1.len() == a2.len() => {
+                a1.iter().zip(&a2).try_for_each(|(x, y)| self.unify(x, y))
+            }
+            _ => Err(UnifyError::Mismatch),
+        }
+    }
+
+    // Occurs check, and pull the level of every variable in `ty` down to v's so
+    // that binding v can't let them be generalised too early
+    fn adjust(&mut self, v: u32, ty: &Type) -> Result<(), UnifyError> {
+        match self.resolve(ty) {
+            Type::Var(w) if w == v => Err(UnifyError::Occurs(v)),
+            Type::Var(w) => {
+                let level = self.levels[v as usize].min(self.levels[w as usize]);
+                self.levels[w as usize] = level;
+                Ok(())
+            }
+            Type::Con(_, args) => args.iter().try_for_each(|arg| self.adjust(v, arg)),
+            Type::Gen(_) => Ok(()),
+        }
+    }
+
+    fn expect(&mut self, expected: &Type, found: &Type, pos: &Pos) {
+        match self.unify(expected, found) {
+            Ok(()) => {}
+            Err(UnifyError::Mismatch) => self.errors.push(TypeError::Mismatch {
+                expected: Box::new(self.zonk(expected)),
+                found: Box::new(self.zonk(found)),
+                pos: pos.clone(),
+            }),
+            Err(UnifyError::Occurs(v)) => self.errors.push(TypeError::InfiniteType {
+                var: Box::new(Type::Var(v)),
+                ty: Box::new(self.zonk(found)),
+                pos: pos.clone(),
+            }),
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let vars: Vec<Type> = scheme.params.iter().map(|_| self.fresh()).collect();
+        substitute(&scheme.ty, &vars)
+    }
+
+    // Declared type variables stand for any type, so inside the definition they are
+    // treated as unknown constants that only unify with themselves
+    fn skolemize(&self, scheme: &Scheme) -> Type {
+        let rigid: Vec<Type> = scheme.params.iter().map(|p| Type::con(p)).collect();
+        substitute(&scheme.ty, &rigid)
+    }
+
+    fn generalize(&self, ty: &Type) -> Scheme {
+        fn walk(checker: &Checker, ty: &Type, vars: &mut Vec<u32>) -> Type {
+            match ty {
+                Type::Var(v) if checker.levels[*v as usize] > checker.level => {
+                    let i = vars.iter().position(|w| w == v).unwrap_or_else(|| {
+                        vars.push(*v);
+                        vars.len() - 1
+                    });
+                    Type::Gen(i as u32)
+                }
+                Type::Con(name, args) => Type::Con(name.clone(), args.iter().map(|a| walk(checker, a, vars)).collect()),
+                other => other.clone(),
+            }
+        }
+
+        let mut vars = Vec::new();
+        let ty = walk(self, &self.zonk(ty), &mut vars);
+        Scheme { params: (0..vars.len() as u32).map(var_name).collect(), ty }
+    }
+
+    // Inference
+
+    fn lookup_var(&mut self, name: &str, pos: &Pos) -> Type {
+        let scheme = self.scopes.iter().rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.constructors.get(name).map(|c| &c.scheme))
+            .or_else(|| self.globals.get(name))
+            .cloned();
+
+        match scheme {
+            Some(scheme) => self.instantiate(&scheme),
+            None => {
+                self.errors.push(TypeError::UnknownVariable(name.to_owned(), pos.clone()));
+                self.fresh()
+            }
+        }
+    }
+
+    // The result type of applying `fun` to `arg`
+    fn apply(&mut self, fun: Type, fun_pos: &Pos, arg: Type, arg_pos: &Pos) -> Type {
+        let param = self.fresh();
+        let result = self.fresh();
+        self.expect(&Type::arrow(param.clone(), result.clone()), &fun, fun_pos);
+        self.expect(&param, &arg, arg_pos);
+        result
+    }
+
+    // `l op r` is `op (l, r)`, checking the halves separately gives better positions
+    fn apply_pair(&mut self, fun: Type, fun_pos: &Pos, (l, l_pos): (Type, &Pos), (r, r_pos): (Type, &Pos)) -> Type {
+        let (first, second, result) = (self.fresh(), self.fresh(), self.fresh());
+        let param = Type::pair(first.clone(), second.clone());
+        self.expect(&Type::arrow(param, result.clone()), &fun, fun_pos);
+        self.expect(&first, &l, l_pos);
+        self.expect(&second, &r, r_pos);
+        result
+    }
+
+    // Long chains of operators make deep trees even where nothing is nested in the
+    // source, so the stack grows on demand here as in the parser
+    fn infer_expr(&mut self, expr: &Expr) -> Type {
+        #[cfg(feature = "std")]
+        return stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || self.infer_kind(expr));
+        #[cfg(not(feature = "std"))]
+        return self.infer_kind(expr);
+    }
+
+    fn infer_kind(&mut self, expr: &Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Var(name) => self.lookup_var(name, &expr.pos),
+            ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) => Type::num(),
+            ExprKind::Str(_) => Type::list(Type::char()),
+            ExprKind::Tuple(items) => {
+                let items = items.iter().map(|item| self.infer_expr(item)).collect();
+                Type::tuple(items)
+            }
+            ExprKind::List(items) => {
+                let elem = self.fresh();
+                for item in items {
+                    let ty = self.infer_expr(item);
+                    self.expect(&elem, &ty, &item.pos);
+                }
+                Type::list(elem)
+            }
+            ExprKind::Apply(fun, arg) => {
+                if matches!(&fun.kind, ExprKind::Var(name) if name == "format") && self.is_builtin_format() {
+                    self.check_format(arg);
+                }
+                let fun_ty = self.infer_expr(fun);
+                let arg_ty = self.infer_expr(arg);
+                self.apply(fun_ty, &fun.pos, arg_ty, &arg.pos)
+            }
+            ExprKind::BinOp(op, l, r) => {
+                let op_ty = self.lookup_var(&op.name, &op.pos);
+                let l_ty = self.infer_expr(l);
+                let r_ty = self.infer_expr(r);
+                self.apply_pair(op_ty, &op.pos, (l_ty, &l.pos), (r_ty, &r.pos))
+            }
+            ExprKind::If(cond, then, other) => {
+                let cond_ty = self.infer_expr(cond);
+                self.expect(&Type::bool(), &cond_ty, &cond.pos);
+                let then_ty = self.infer_expr(then);
+                let other_ty = self.infer_expr(other);
+                self.expect(&then_ty, &other_ty, &other.pos);
+                then_ty
+            }
+            ExprKind::Lambda(rules) => {
+                let param = self.fresh();
+                let result = self.fresh();
+                for rule in rules {
+                    let mut vars = BTreeMap::new();
+                    let pattern_ty = self.infer_pattern(&rule.pattern, &mut vars);
+                    self.expect(&param, &pattern_ty, &rule.pattern.pos);
+                    self.scopes.push(monomorphic(vars));
+                    let body_ty = self.infer_expr(&rule.body);
+                    self.scopes.pop();
+                    self.expect(&result, &body_ty, &rule.body.pos);
+                }
+                Type::arrow(param, result)
+            }
+            ExprKind::Let(binding) => {
+                let mut vars = BTreeMap::new();
+                self.level += 1;
+                if binding.kind.is_rec() {
+                    let pattern_ty = self.infer_pattern(&binding.pattern, &mut vars);
+                    self.scopes.push(monomorphic(vars.clone()));
+                    let value_ty = self.infer_expr(&binding.value);
+                    self.scopes.pop();
+                    self.expect(&pattern_ty, &value_ty, &binding.value.pos);
+                } else {
+                    let value_ty = self.infer_expr(&binding.value);
+                    let pattern_ty = self.infer_pattern(&binding.pattern, &mut vars);
+                    self.expect(&pattern_ty, &value_ty, &binding.value.pos);
+                }
+                self.level -= 1;
+
+                let scope = vars.into_iter().map(|(name, ty)| (name, self.generalize(&ty))).collect();
+                self.scopes.push(scope);
+                let body_ty = self.infer_expr(&binding.body);
+                self.scopes.pop();
+                body_ty
+            }
+        }
+    }
+
+    // Whether `format` still means the builtin where it is used
+    fn is_builtin_format(&self) -> bool {
+        !self.scopes.iter().any(|scope| scope.contains_key("format")) && self.globals.get("format") == self.format.as_ref()
+    }
+
+    // A format string written out is checked against the arguments written out with
+    // it, as far as what they are can be seen from how they are written
+    fn check_format(&mut self, arg: &Expr) {
+        let ExprKind::Tuple(items) = &arg.kind else { return };
+        let [Expr { kind: ExprKind::Str(format), pos }, args] = &items[..] else { return };
+        let ExprKind::List(args) = &args.kind else { return };
+        let result = printf::parse(format).and_then(|pieces| {
+            let constructors: Vec<_> = args.iter()
+                .map(|arg| match &arg.kind {
+                    ExprKind::Apply(f, _) => match &f.kind {
+                        ExprKind::Var(name) if ["fnum", "fstr"].contains(&name.as_str()) => Some(name.as_str()),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect();
+            printf::check(&pieces, &constructors)
+        });
+        match result {
+            Ok(()) => {}
+            Err(e @ printf::FormatError::Argument { index, .. }) => self.errors.push(TypeError::Format(e, args[index].pos.clone())),
+            Err(e) => self.errors.push(TypeError::Format(e, pos.clone())),
+        }
+    }
+
+    fn constructor_type(&mut self, name: &Ident, arity: usize) -> Type {
+        match self.constructors.get(&name.name).cloned() {
+            Some(info) => {
+                if info.arity != arity {
+                    self.errors.push(TypeError::ConstructorArity {
+                        name: name.name.clone(),
+                        expected: info.arity,
+                        found: arity,
+                        pos: name.pos.clone(),
+                    });
+                }
+                self.instantiate(&info.scheme)
+            }
+            None => {
+                self.errors.push(TypeError::UnknownConstructor(name.name.clone(), name.pos.clone()));
+                self.fresh()
+            }
+        }
+    }
+
+    fn infer_pattern(&mut self, pattern: &Pattern, vars: &mut BTreeMap<String, Type>) -> Type {
+        match &pattern.kind {
+            PatternKind::Var(name) if self.constructors.contains_key(name) => {
+                let ident = Ident { name: name.clone(), pos: pattern.pos.clone() };
+                self.constructor_type(&ident, 0)
+            }
+            PatternKind::Var(name) => {
+                let ty = self.fresh();
+                vars.insert(name.clone(), ty.clone());
+                ty
+            }
+            PatternKind::Wildcard => self.fresh(),
+            PatternKind::As(name, pattern) => {
+                let ty = self.infer_pattern(pattern, vars);
+                vars.insert(name.name.clone(), ty.clone());
+                ty
+            }
+            PatternKind::Int(_) | PatternKind::BigInt(_) | PatternKind::Num(_) => Type::num(),
+            PatternKind::Str(_) => Type::list(Type::char()),
+            PatternKind::Tuple(items) => {
+                let items = items.iter().map(|item| self.infer_pattern(item, vars)).collect();
+                Type::tuple(items)
+            }
+            PatternKind::List(items) => {
+                let elem = self.fresh();
+                for item in items {
+                    let ty = self.infer_pattern(item, vars);
+                    self.expect(&elem, &ty, &item.pos);
+                }
+                Type::list(elem)
+            }
+            PatternKind::Construct(name, args) => {
+                let mut ty = self.constructor_type(name, args.len());
+                for arg in args {
+                    let arg_ty = self.infer_pattern(arg, vars);
+                    ty = self.apply(ty, &name.pos, arg_ty, &arg.pos);
+                }
+                ty
+            }
+            PatternKind::BinOp(op, l, r) => {
+                let op_ty = self.constructor_type(op, 1);
+                let l_ty = self.infer_pattern(l, vars);
+                let r_ty = self.infer_pattern(r, vars);
+                self.apply_pair(op_ty, &op.pos, (l_ty, &l.pos), (r_ty, &r.pos))
+            }
+        }
+    }
+
+    fn check_equation(&mut self, eq: &Equation) {
+        let Some(scheme) = self.globals.get(&eq.name.name).cloned() else {
+            self.errors.push(TypeError::MissingDec(eq.name.name.clone(), eq.name.pos.clone()));
+            return;
+        };
+
+        let mut expected = self.skolemize(&scheme);
+        let mut vars = BTreeMap::new();
+        for arg in &eq.args {
+            let arg_ty = self.infer_pattern(arg, &mut vars);
+            expected = self.apply(expected, &eq.name.pos, arg_ty, &arg.pos);
+        }
+
+        self.scopes.push(monomorphic(vars));
+        if let Some(guard) = &eq.guard {
+            let guard_ty = self.infer_expr(guard);
+            self.expect(&Type::bool(), &guard_ty, &guard.pos);
+        }
+        let body_ty = self.infer_expr(&eq.body);
+        self.scopes.pop();
+        self.expect(&expected, &body_ty, &eq.body.pos);
+    }
+}
+
+// The names a program declares that only it can use
+#[derive(Default)]
+struct Hidden {
+    values: Vec<String>,
+    constructors: Vec<String>,
+    types: Vec<String>,
+}
+
+impl Hidden {
+    // What the module that decls end with declares and doesn't make public
+    fn unexported(decls: &[Decl]) -> Self {
+        let mut hidden = Hidden::default();
+        if !decls.last().is_some_and(|decl| matches!(decl.kind, DeclKind::End)) {
+            return hidden;
+        }
+        let mut public: Vec<(PublicKind, &str)> = Vec::new();
+        for decl in decls {
+            match &decl.kind {
+                DeclKind::Module(_) => (hidden, public) = (Hidden::default(), Vec::new()),
+                DeclKind::Public(kind, names) => public.extend(names.iter().map(|n| (*kind, n.name.as_str()))),
+                DeclKind::Dec { names, .. } => hidden.values.extend(names.iter().map(|n| n.name.clone())),
+                DeclKind::Data { head, constructors } => {
+                    hidden.constructors.extend(constructors.iter().map(|c| c.name.name.clone()));
+                    hidden.types.push(head.name.name.clone());
+                }
+                DeclKind::AbsType(head) | DeclKind::Type { head, .. } => hidden.types.push(head.name.name.clone()),
+                _ => {}
+            }
+        }
+        // Constructors and other values share names, so either `pub` keyword exports both
+        let value = |name: &String| !public.iter().any(|(kind, n)| *kind != PublicKind::Type && n == name);
+        hidden.values.retain(value);
+        hidden.constructors.retain(value);
+        hidden.types.retain(|name| !public.contains(&(PublicKind::Type, name)));
+        hidden
+    }
+
+    // What the program keeps to itself past any module: what it declares after
+    // `private` and the constructors of its abstract types
+    fn of(program: &Program) -> Self {
+        let abstract_types: Vec<_> = program.decls.iter()
+            .filter_map(|decl| match &decl.kind {
+                DeclKind::AbsType(head) => Some(&head.name.name),
+                _ => None,
+            })
+            .collect();
+
+        let mut hidden = Hidden::default();
+        let mut private = false;
+        for decl in &program.decls {
+            match &decl.kind {
+                DeclKind::Private => private = true,
+                DeclKind::Dec { names, .. } if private => hidden.values.extend(names.iter().map(|n| n.name.clone())),
+                DeclKind::Data { head, constructors } => {
+                    if private || abstract_types.contains(&&head.name.name) {
+                        hidden.constructors.extend(constructors.iter().map(|c| c.name.name.clone()));
+                    }
+                    if private {
+                        hidden.types.push(head.name.name.clone());
+                    }
+                }
+                DeclKind::AbsType(head) | DeclKind::Type { head, .. } if private => hidden.types.push(head.name.name.clone()),
+                _ => {}
+            }
+        }
+        hidden
+    }
+}
+
+// Values and constructors share one set of names in a module, and types another. An
+// `abstype` is defined again by the `data` that gives its representation
+fn duplicates(decls: &[Decl]) -> Vec<TypeError> {
+    let mut values: BTreeMap<&str, &Pos> = BTreeMap::new();
+    let mut types: BTreeMap<&str, &Pos> = BTreeMap::new();
+    let mut abstract_types = BTreeSet::new();
+    let mut errors = Vec::new();
+    for decl in decls {
+        let (names, types_named): (Vec<&Ident>, Vec<&Ident>) = match &decl.kind {
+            DeclKind::Module(_) | DeclKind::End => {
+                (values, types) = (BTreeMap::new(), BTreeMap::new());
+                abstract_types.clear();
+                continue;
+            }
+            DeclKind::Dec { names, .. } => (names.iter().collect(), Vec::new()),
+            DeclKind::Data { head, constructors } => {
+                let representation = abstract_types.remove(head.name.name.as_str());
+                let types_named = if representation { Vec::new() } else { vec![&head.name] };
+                (constructors.iter().map(|c| &c.name).collect(), types_named)
+            }
+            DeclKind::AbsType(head) => {
+                abstract_types.insert(head.name.name.as_str());
+                (Vec::new(), vec![&head.name])
+            }
+            DeclKind::Type { head, .. } => (Vec::new(), vec![&head.name]),
+            _ => continue,
+        };
+        for name in names {
+            if let Some(first) = values.insert(&name.name, &name.pos) {
+                errors.push(TypeError::Duplicate { name: name.name.clone(), first: first.clone(), pos: name.pos.clone() });
+                values.insert(&name.name, first);
+            }
+        }
+        for name in types_named {
+            if let Some(first) = types.insert(&name.name, &name.pos) {
+                errors.push(TypeError::DuplicateType { name: name.name.clone(), first: first.clone(), pos: name.pos.clone() });
+                types.insert(&name.name, first);
+            }
+        }
+    }
+    errors
+}
+
+fn restore<T: Clone>(next: &mut BTreeMap<String, T>, before: &BTreeMap<String, T>, name: String) {
+    match before.get(&name) {
+        Some(old) => next.insert(name, old.clone()),
+        None => next.remove(&name),
+    };
+}
+
+fn param_names(head: &TypeHead) -> Vec<String> {
+    head.params.iter().map(|p| p.name.clone()).collect()
+}
+
+fn monomorphic(vars: BTreeMap<String, Type>) -> BTreeMap<String, Scheme> {
+    vars.into_iter().map(|(name, ty)| (name, Scheme::mono(ty))).collect()
+}
+
+fn substitute(ty: &Type, args: &[Type]) -> Type {
+    match ty {
+        Type::Gen(i) => args[*i as usize].clone(),
+        Type::Con(name, items) => Type::Con(name.clone(), items.iter().map(|t| substitute(t, args)).collect()),
+        Type::Var(_) => ty.clone(),
+    }
+}
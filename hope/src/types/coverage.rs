@@ -0,0 +1,292 @@
+use alloc::collections::BTreeMap;
+use crate::alloc_prelude::*;
+use crate::syntax::ast::*;
+use crate::syntax::token::Pos;
+use crate::types::{ConstructorInfo, Type, TypeWarning};
+
+// What a pattern tests, with lists, strings and tuples spelled out in terms of `nil`,
+// `::` and pairs so that `[]`, `nil` and `""` are the same test
+#[derive(Debug, Clone)]
+enum Pat {
+    Any,
+    Con(Head, Vec<Pat>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Head {
+    Constructor(String),
+    Pair,
+    // A number or character, of which there are too many to list
+    Literal(String),
+}
+
+// A clause's patterns, its position and whether it has a guard
+type Clause = (Vec<Pat>, Pos, bool);
+
+// The constructors of each data type, by the name of any one of them
+struct Constructors {
+    siblings: BTreeMap<String, Vec<(String, usize)>>,
+}
+
+impl Constructors {
+    fn new(constructors: &BTreeMap<String, ConstructorInfo>) -> Self {
+        let mut by_type: BTreeMap<&str, Vec<(String, usize)>> = BTreeMap::new();
+        for (name, info) in constructors {
+            // Constructors are curried, the type they build is past one arrow per argument
+            let mut result = &info.scheme.ty;
+            for _ in 0..info.arity {
+                match result {
+                    Type::Con(arrow, items) if arrow == "->" => result = &items[1],
+                    _ => break,
+                }
+            }
+            if let Type::Con(ty, _) = result {
+                by_type.entry(ty).or_default().push((name.clone(), info.arity));
+            }
+        }
+        let mut siblings = BTreeMap::new();
+        for mut all in by_type.into_values() {
+            all.sort();
+            for (name, _) in &all {
+                siblings.insert(name.clone(), all.clone());
+            }
+        }
+        Constructors { siblings }
+    }
+
+    // Every way a value of the type could start, if there are few enough to list
+    fn signature(&self, head: &Head) -> Option<Vec<(Head, usize)>> {
+        match head {
+            Head::Pair => Some(vec![(Head::Pair, 2)]),
+            Head::Constructor(name) => Some(self.siblings.get(name)?.iter()
+                .map(|(name, arity)| (Head::Constructor(name.clone()), *arity))
+                .collect()),
+            Head::Literal(_) => None,
+        }
+    }
+
+    fn pattern(&self, pattern: &Pattern) -> Pat {
+        match &pattern.kind {
+            PatternKind::Var(name) if self.siblings.contains_key(name) => Pat::Con(Head::Constructor(name.clone()), Vec::new()),
+            PatternKind::Var(_) | PatternKind::Wildcard => Pat::Any,
+            PatternKind::As(_, pattern) => self.pattern(pattern),
+            PatternKind::Int(n) => Pat::Con(Head::Literal(n.to_string()), Vec::new()),
+            PatternKind::BigInt(digits) => Pat::Con(Head::Literal(digits.clone()), Vec::new()),
+            PatternKind::Num(n) => Pat::Con(Head::Literal(n.to_string()), Vec::new()),
+            PatternKind::Str(s) => s.chars().rev().fold(nil(), |tail, c| {
+                cons(Pat::Con(Head::Literal(format!("{:?}", c)), Vec::new()), tail)
+            }),
+            PatternKind::Tuple(items) => {
+                let mut items = items.iter().rev().map(|item| self.pattern(item));
+                let last = items.next().expect("tuples have at least two items");
+                items.fold(last, |rest, item| Pat::Con(Head::Pair, vec![item, rest]))
+            }
+            PatternKind::List(items) => items.iter().rev().fold(nil(), |tail, item| cons(self.pattern(item), tail)),
+            PatternKind::Construct(name, args) => {
+                Pat::Con(Head::Constructor(name.name.clone()), args.iter().map(|arg| self.pattern(arg)).collect())
+            }
+            PatternKind::BinOp(op, l, r) => {
+                let pair = Pat::Con(Head::Pair, vec![self.pattern(l), self.pattern(r)]);
+                Pat::Con(Head::Constructor(op.name.clone()), vec![pair])
+            }
+        }
+    }
+
+    // An argument list that none of the rows match, or None if they cover every one
+    fn missing(&self, rows: &[Vec<Pat>], width: usize) -> Option<Vec<Pat>> {
+        if width == 0 {
+            return if rows.is_empty() { Some(Vec::new()) } else { None };
+        }
+
+        let heads = heads(rows);
+        let signature = heads.first().and_then(|head| self.signature(head));
+        match signature {
+            Some(signature) if signature.iter().all(|(head, _)| heads.contains(head)) => {
+                signature.into_iter().find_map(|(head, arity)| {
+                    let mut found = self.missing(&specialize(rows, &head, arity), arity + width - 1)?;
+                    let rest = found.split_off(arity);
+                    Some(core::iter::once(Pat::Con(head, found)).chain(rest).collect())
+                })
+            }
+            signature => {
+                let rest = self.missing(&default(rows), width - 1)?;
+                let first = signature
+                    .and_then(|signature| signature.into_iter().find(|(head, _)| !heads.contains(head)))
+                    .map_or(Pat::Any, |(head, arity)| Pat::Con(head, vec![Pat::Any; arity]));
+                Some(core::iter::once(first).chain(rest).collect())
+            }
+        }
+    }
+
+    // Whether some argument list matches row but none of the rows
+    fn useful(&self, rows: &[Vec<Pat>], row: &[Pat]) -> bool {
+        let Some((first, rest)) = row.split_first() else { return rows.is_empty() };
+        match first {
+            Pat::Con(head, args) => {
+                let row: Vec<_> = args.iter().chain(rest).cloned().collect();
+                self.useful(&specialize(rows, head, args.len()), &row)
+            }
+            Pat::Any => {
+                let heads = heads(rows);
+                match heads.first().and_then(|head| self.signature(head)) {
+                    Some(signature) if signature.iter().all(|(head, _)| heads.contains(head)) => {
+                        signature.into_iter().any(|(head, arity)| {
+                            let row: Vec<_> = core::iter::repeat_n(Pat::Any, arity).chain(rest.iter().cloned()).collect();
+                            self.useful(&specialize(rows, &head, arity), &row)
+                        })
+                    }
+                    _ => self.useful(&default(rows), rest),
+                }
+            }
+        }
+    }
+
+    // Warns about a function or lambda given by these clauses. Guarded clauses can
+    // always fall through, so they cover nothing, but can still be shadowed
+    fn clauses(&self, name: &str, clauses: &[Clause], warnings: &mut Vec<TypeWarning>) {
+        let Some((first, ..)) = clauses.first() else { return };
+        let width = first.len();
+        if clauses.iter().any(|(pats, ..)| pats.len() != width) {
+            return;
+        }
+
+        let mut rows = Vec::new();
+        for (pats, pos, guarded) in clauses {
+            if !self.useful(&rows, pats) {
+                warnings.push(TypeWarning::Redundant { name: name.to_owned(), pos: pos.clone() });
+            }
+            if !guarded {
+                rows.push(pats.clone());
+            }
+        }
+        if let Some(example) = self.missing(&rows, width) {
+            let example = example.iter().map(|pat| show(pat, true)).collect::<Vec<_>>().join(" ");
+            warnings.push(TypeWarning::NonExhaustive { name: name.to_owned(), example, pos: clauses[0].1.clone() });
+        }
+    }
+
+    fn expr(&self, expr: &Expr, warnings: &mut Vec<TypeWarning>) {
+        match &expr.kind {
+            ExprKind::Var(_) | ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Num(_) | ExprKind::Str(_) => {}
+            ExprKind::Tuple(items) | ExprKind::List(items) => items.iter().for_each(|item| self.expr(item, warnings)),
+            ExprKind::Apply(f, arg) => {
+                self.expr(f, warnings);
+                self.expr(arg, warnings);
+            }
+            ExprKind::BinOp(_, l, r) => {
+                self.expr(l, warnings);
+                self.expr(r, warnings);
+            }
+            ExprKind::If(c, t, f) => [c, t, f].into_iter().for_each(|e| self.expr(e, warnings)),
+            ExprKind::Lambda(rules) => {
+                let clauses: Vec<_> = rules.iter()
+                    .map(|rule| (vec![self.pattern(&rule.pattern)], rule.pos.clone(), false))
+                    .collect();
+                self.clauses("lambda", &clauses, warnings);
+                rules.iter().for_each(|rule| self.expr(&rule.body, warnings));
+            }
+            ExprKind::Let(l) => {
+                self.expr(&l.value, warnings);
+                self.expr(&l.body, warnings);
+            }
+        }
+    }
+}
+
+// Checks the equations of each function in the program, and every lambda
+pub(super) fn check(decls: &[Decl], constructors: &BTreeMap<String, ConstructorInfo>) -> Vec<TypeWarning> {
+    let constructors = Constructors::new(constructors);
+    let mut warnings = Vec::new();
+    let mut functions: Vec<(&str, Vec<Clause>)> = Vec::new();
+    for decl in decls {
+        match &decl.kind {
+            DeclKind::Equation(eq) => {
+                let clause = (eq.args.iter().map(|arg| constructors.pattern(arg)).collect(), decl.pos.clone(), eq.guard.is_some());
+                match functions.iter_mut().find(|(name, _)| *name == eq.name.name) {
+                    Some((_, clauses)) => clauses.push(clause),
+                    None => functions.push((&eq.name.name, vec![clause])),
+                }
+                eq.guard.iter().chain([&eq.body]).for_each(|e| constructors.expr(e, &mut warnings));
+            }
+            DeclKind::Expr(expr) | DeclKind::Write(expr) => constructors.expr(expr, &mut warnings),
+            _ => {}
+        }
+    }
+    for (name, clauses) in &functions {
+        constructors.clauses(name, clauses, &mut warnings);
+    }
+    warnings.sort_by_key(|warning| warning.pos().range.start);
+    warnings
+}
+
+fn nil() -> Pat {
+    Pat::Con(Head::Constructor("nil".to_owned()), Vec::new())
+}
+
+fn cons(head: Pat, tail: Pat) -> Pat {
+    Pat::Con(Head::Constructor("::".to_owned()), vec![Pat::Con(Head::Pair, vec![head, tail])])
+}
+
+// The constructors the first column tests for, in the order they appear
+fn heads(rows: &[Vec<Pat>]) -> Vec<Head> {
+    let mut heads = Vec::new();
+    for row in rows {
+        match &row[0] {
+            Pat::Con(head, _) if !heads.contains(head) => heads.push(head.clone()),
+            _ => {}
+        }
+    }
+    heads
+}
+
+// The rows that match a value starting with head, with its arguments in place of the
+// first column
+fn specialize(rows: &[Vec<Pat>], head: &Head, arity: usize) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| {
+            let args = match &row[0] {
+                Pat::Con(h, args) if h == head => args.clone(),
+                Pat::Con(..) => return None,
+                Pat::Any => vec![Pat::Any; arity],
+            };
+            Some(args.into_iter().chain(row[1..].iter().cloned()).collect())
+        })
+        .collect()
+}
+
+// The rows that match whatever is in the first column, without it
+fn default(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter().filter(|row| matches!(row[0], Pat::Any)).map(|row| row[1..].to_vec()).collect()
+}
+
+fn show(pat: &Pat, atom: bool) -> String {
+    let text = match pat {
+        Pat::Any => return "_".to_owned(),
+        Pat::Con(Head::Pair, args) => {
+            let mut items = vec![show(&args[0], false)];
+            let mut rest = &args[1];
+            while let Pat::Con(Head::Pair, args) = rest {
+                items.push(show(&args[0], false));
+                rest = &args[1];
+            }
+            items.push(show(rest, false));
+            return format!("({})", items.join(", "));
+        }
+        Pat::Con(Head::Literal(text), _) => return text.clone(),
+        Pat::Con(Head::Constructor(name), args) if name == "nil" && args.is_empty() => return "[]".to_owned(),
+        Pat::Con(Head::Constructor(name), args) => match &args[..] {
+            [] => return name.clone(),
+            // Operators are written between the two halves of their argument
+            [Pat::Con(Head::Pair, pair)] if is_operator(name) => {
+                format!("{} {} {}", show(&pair[0], true), name, show(&pair[1], false))
+            }
+            [Pat::Any] if is_operator(name) => format!("_ {} _", name),
+            args => format!("{} {}", name, args.iter().map(|arg| show(arg, true)).collect::<Vec<_>>().join(" ")),
+        },
+    };
+    if atom { format!("({})", text) } else { text }
+}
+
+fn is_operator(name: &str) -> bool {
+    !name.starts_with(|c: char| c.is_alphanumeric())
+}
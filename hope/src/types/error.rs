@@ -0,0 +1,139 @@
+use core::fmt;
+use crate::alloc_prelude::*;
+use crate::printf::FormatError;
+use crate::syntax::token::Pos;
+use crate::types::Type;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    Mismatch { expected: Box<Type>, found: Box<Type>, pos: Pos },
+    InfiniteType { var: Box<Type>, ty: Box<Type>, pos: Pos },
+    UnknownVariable(String, Pos),
+    UnknownConstructor(String, Pos),
+    UnknownType(String, Pos),
+    UnboundTypeVariable(String, Pos),
+    TypeArity { name: String, expected: usize, found: usize, pos: Pos },
+    ConstructorArity { name: String, expected: usize, found: usize, pos: Pos },
+    MissingDec(String, Pos),
+    // A second `dec` or constructor of a name in one module, first is where the other is
+    Duplicate { name: String, first: Pos, pos: Pos },
+    DuplicateType { name: String, first: Pos, pos: Pos },
+    // A format string written out in a call of `format` that its arguments don't suit
+    Format(FormatError, Pos),
+    // A signature given to Builtins::register that isn't a type, or takes fewer
+    // arguments than the function, pos is within the signature
+    HostSignature { name: String, message: String, pos: Pos },
+    // Given up before the declaration at pos, see Cancel
+    Cancelled(Pos),
+}
+
+impl TypeError {
+    pub fn pos(&self) -> &Pos {
+        match self {
+            TypeError::Mismatch { pos, .. }
+            | TypeError::InfiniteType { pos, .. }
+            | TypeError::UnknownVariable(_, pos)
+            | TypeError::UnknownConstructor(_, pos)
+            | TypeError::UnknownType(_, pos)
+            | TypeError::UnboundTypeVariable(_, pos)
+            | TypeError::TypeArity { pos, .. }
+            | TypeError::ConstructorArity { pos, .. }
+            | TypeError::MissingDec(_, pos)
+            | TypeError::Duplicate { pos, .. }
+            | TypeError::DuplicateType { pos, .. }
+            | TypeError::Format(_, pos)
+            | TypeError::HostSignature { pos, .. }
+            | TypeError::Cancelled(pos) => pos,
+        }
+    }
+
+    pub fn first_declared(&self) -> Option<&Pos> {
+        match self {
+            TypeError::Duplicate { first, .. } | TypeError::DuplicateType { first, .. } => Some(first),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypeError::Mismatch { .. } => "E0301",
+            TypeError::InfiniteType { .. } => "E0302",
+            TypeError::UnknownVariable(..) => "E0303",
+            TypeError::UnknownConstructor(..) => "E0304",
+            TypeError::UnknownType(..) => "E0305",
+            TypeError::UnboundTypeVariable(..) => "E0306",
+            TypeError::TypeArity { .. } => "E0307",
+            TypeError::ConstructorArity { .. } => "E0308",
+            TypeError::MissingDec(..) => "E0309",
+            TypeError::Duplicate { .. } => "E0310",
+            TypeError::DuplicateType { .. } => "E0311",
+            TypeError::Format(..) => "E0312",
+            TypeError::HostSignature { .. } => "E0313",
+            TypeError::Cancelled(_) => "E0314",
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::Mismatch { expected, found, .. } =>
+                write!(f, "type mismatch: expected {}, found {}", expected, found),
+            TypeError::InfiniteType { var, ty, .. } =>
+                write!(f, "infinite type: {} occurs in {}", var, ty),
+            TypeError::UnknownVariable(name, _) => write!(f, "unknown identifier `{}`", name),
+            TypeError::UnknownConstructor(name, _) => write!(f, "unknown constructor `{}`", name),
+            TypeError::UnknownType(name, _) => write!(f, "unknown type `{}`", name),
+            TypeError::UnboundTypeVariable(name, _) =>
+                write!(f, "type variable `{}` is not a parameter of this type", name),
+            TypeError::TypeArity { name, expected, found, .. } =>
+                write!(f, "type `{}` takes {} arguments, given {}", name, expected, found),
+            TypeError::ConstructorArity { name, expected, found, .. } =>
+                write!(f, "constructor `{}` takes {} arguments, given {}", name, expected, found),
+            TypeError::MissingDec(name, _) =>
+                write!(f, "`{}` has equations but no `dec` declaration", name),
+            TypeError::Duplicate { name, .. } => write!(f, "`{}` is already declared in this module", name),
+            TypeError::DuplicateType { name, .. } => write!(f, "type `{}` is already defined in this module", name),
+            TypeError::Format(e, _) => write!(f, "bad format: {}", e),
+            TypeError::HostSignature { name, message, .. } => write!(f, "host function `{}`: {}", name, message),
+            TypeError::Cancelled(_) => write!(f, "checking was cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeWarning {
+    // The equations or rules of name don't match every argument, example is one they miss
+    NonExhaustive { name: String, example: String, pos: Pos },
+    // A clause that only matches what the ones before it already do
+    Redundant { name: String, pos: Pos },
+}
+
+impl TypeWarning {
+    pub fn pos(&self) -> &Pos {
+        match self {
+            TypeWarning::NonExhaustive { pos, .. } | TypeWarning::Redundant { pos, .. } => pos,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypeWarning::NonExhaustive { .. } => "W0301",
+            TypeWarning::Redundant { .. } => "W0302",
+        }
+    }
+}
+
+impl fmt::Display for TypeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeWarning::NonExhaustive { name, example, .. } if name == "lambda" =>
+                write!(f, "lambda doesn't match `{}`", example),
+            TypeWarning::NonExhaustive { name, example, .. } =>
+                write!(f, "equations for `{}` don't match `{} {}`", name, name, example),
+            TypeWarning::Redundant { name, .. } if name == "lambda" => write!(f, "this rule can never match"),
+            TypeWarning::Redundant { name, .. } =>
+                write!(f, "this equation for `{}` can never match", name),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+mod builtins;
+mod check;
+mod coverage;
+mod error;
+mod ty;
+
+pub use check::{Checker, ConstructorInfo, TypedDecl, TypedProgram};
+pub use error::{TypeError, TypeWarning};
+pub use ty::{var_name, Scheme, Type};
+
+use crate::alloc_prelude::*;
+use crate::syntax::ast::Program;
+
+pub fn check_program(program: Program) -> Result<TypedProgram, Vec<TypeError>> {
+    Checker::new().check(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn check(source: &str) -> Result<TypedProgram, Vec<TypeError>> {
+        check_program(parser::parse_program(source).unwrap())
+    }
+
+    fn types(source: &str) -> Vec<String> {
+        check(source).unwrap().decls.iter()
+            .filter_map(|d| d.ty.as_ref().map(|t| t.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn should_stop_before_the_next_declaration_once_cancelled() {
+        let program = parser::parse_program("dec x : num;\n--- x <= 1;\nx + 1;").unwrap();
+        let cancel = crate::cancel::Cancel::new();
+        let mut checker = Checker::new().with_cancel(cancel.clone());
+        assert!(checker.check(program.clone()).is_ok());
+
+        let raised = cancel.clone();
+        std::thread::spawn(move || raised.cancel()).join().unwrap();
+        let errors = checker.check(program).unwrap_err();
+        assert_eq!(errors.iter().map(|e| (e.code(), e.pos().line)).collect::<Vec<_>>(), [("E0314", 1)]);
+    }
+
+    #[test]
+    fn should_check_standard_prelude() {
+        let source = std::fs::read_to_string("../lib/Standard.hop").unwrap();
+        if let Err(errors) = check(&source) {
+            panic!("{:?}", errors);
+        }
+    }
+
+    #[test]
+    fn should_generalise_let_bindings() {
+        let source = "let id == lambda x => x in (id 1, id \"a\");";
+        assert_eq!(types(source), ["num # list char"]);
+        assert_eq!(types("lambda (x, y) => y;"), ["alpha # beta -> beta"]);
+    }
+
+    #[test]
+    fn should_type_data_constructors() {
+        let source = "typevar alpha;\n\
+            data tree alpha == leaf ++ node (tree alpha # alpha # tree alpha);\n\
+            dec size : tree alpha -> num;\n\
+            --- size leaf <= 0;\n\
+            --- size (node (l, _x, r)) <= size l + 1 + size r;\n\
+            node (leaf, 1, leaf);";
+        assert_eq!(types(source).last().unwrap(), "tree num");
+    }
+
+    #[test]
+    fn should_check_format_strings_that_are_written_out() {
+        let errors = check("format (\"%s: %d\", [fstr \"a\", fstr \"b\"]);\nformat (\"%s\", []);").unwrap_err();
+        let found: Vec<_> = errors.iter().map(|e| (e.to_string(), e.pos().column)).collect();
+        assert_eq!(found, [
+            ("bad format: argument 2 is for `%d`, which needs `fnum` of a number".to_owned(), 30),
+            ("bad format: the format has 1 directive but is given 0 arguments".to_owned(), 9),
+        ]);
+        // Only what is written out can be checked, and only while `format` is the builtin
+        assert!(check("dec args : list formatarg;\n--- args <= [];\nformat (\"%d\", args);").is_ok());
+        assert!(check("let format == lambda (s, l) => s in format (\"%d\", []);").is_ok());
+    }
+
+    #[test]
+    fn should_report_mismatches_with_positions() {
+        let errors = check("dec f : num -> num;\n--- f x <= x + true;").unwrap_err();
+        match &errors[..] {
+            [TypeError::Mismatch { expected, found, pos }] => {
+                assert_eq!((expected.to_string(), found.to_string()), ("num".to_owned(), "bool".to_owned()));
+                assert_eq!((pos.line, pos.column), (2, 16));
+            }
+            _ => panic!("{:?}", errors),
+        }
+    }
+
+    #[test]
+    fn should_check_guards_as_truth_values() {
+        let errors = check("dec f : num -> num;\n--- f x if x + 1 <= x;").unwrap_err();
+        assert!(matches!(&errors[..], [TypeError::Mismatch { pos, .. }] if pos.column == 12));
+    }
+
+    #[test]
+    fn should_warn_about_missing_and_redundant_clauses() {
+        let warnings = |source| check(source).unwrap().warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        let source = "data tree alpha == leaf ++ node (tree alpha # alpha # tree alpha);\n\
+            dec f : tree num # list num -> num;\n\
+            --- f (leaf, []) <= 0;\n\
+            --- f (node (l, x, r), y :: ys) <= x;\n\
+            --- f (leaf, nil) <= 1;";
+        assert_eq!(warnings(source), [
+            "equations for `f` don't match `f (leaf, _ :: _)`",
+            "this equation for `f` can never match",
+        ]);
+
+        // A guard can fail, so the clause after it is still needed
+        let source = "dec g : num -> num;\n--- g n if n > 0 <= n;\n--- g n <= 0;\n(lambda true => 1) false;";
+        assert_eq!(warnings(source), ["lambda doesn't match `false`"]);
+
+        let source = "dec h : list bool -> bool;\n--- h (l & (true :: _)) <= true;\n--- h _ <= false;\n--- h [] <= false;";
+        assert_eq!(warnings(source), ["this equation for `h` can never match"]);
+
+        // Constructors with the same number of arguments aren't confused across types
+        let source = "typevar alpha;\ndata box alpha == box alpha num;\ndata pt == pt num num;\n\
+            dec unbox : box alpha -> alpha;\n--- unbox (box x n) <= x;";
+        assert_eq!(warnings(source), Vec::<String>::new());
+
+        let prelude = std::fs::read_to_string("../lib/Standard.hop").unwrap();
+        assert_eq!(warnings(&prelude), Vec::<String>::new());
+    }
+
+    #[test]
+    fn should_hide_representations_and_private_names() {
+        let module = "typevar alpha;\nabstype stack alpha;\n\
+            data stack alpha == empty ++ push (alpha # stack alpha);\n\
+            dec new : stack alpha;\n--- new <= empty;\n\
+            dec top : stack alpha -> alpha;\n--- top (push (x, s)) <= x;\n\
+            private;\ndec helper : num -> num;\n--- helper n <= n;";
+        let mut checker = Checker::new();
+        checker.check(parser::parse_program(module).unwrap()).unwrap();
+
+        let mut use_module = |source| checker.check(parser::parse_program(source).unwrap());
+        assert!(use_module("top new;").is_ok());
+        assert!(matches!(&use_module("empty;").unwrap_err()[..], [TypeError::UnknownVariable(name, _)] if name == "empty"));
+        assert!(matches!(&use_module("helper 1;").unwrap_err()[..], [TypeError::UnknownVariable(name, _)] if name == "helper"));
+        let errors = use_module("dec peek : stack num -> num;\n--- peek (push (x, s)) <= x;").unwrap_err();
+        assert!(matches!(&errors[..], [TypeError::UnknownConstructor(name, _)] if name == "push"));
+    }
+
+    #[test]
+    fn should_only_export_what_modules_make_public() {
+        let module = "module stacks;\npubtype stack;\npubfun new, size;\n\
+            data stack == empty ++ push (num # stack);\n\
+            dec new : stack;\n--- new <= empty;\n\
+            dec size : stack -> num;\n--- size empty <= 0;\n--- size (push (_, s)) <= 1 + size s;\n\
+            dec helper : num;\n--- helper <= 1;\nend;";
+        let mut checker = Checker::new();
+        checker.check(parser::parse_program(module).unwrap()).unwrap();
+
+        let mut use_module = |source| checker.check(parser::parse_program(source).unwrap());
+        assert!(use_module("dec s : stack;\n--- s <= new;\nsize s;").is_ok());
+        assert!(matches!(&use_module("helper;").unwrap_err()[..], [TypeError::UnknownVariable(name, _)] if name == "helper"));
+        assert!(matches!(&use_module("push (1, new);").unwrap_err()[..], [TypeError::UnknownVariable(name, _)] if name == "push"));
+
+        // Nor can what comes after `end;` in the same program
+        let unknown = |source: &str| match &check(&format!("{}\n{}", module, source)).unwrap_err()[..] {
+            [TypeError::UnknownVariable(name, _)] => name.clone(),
+            errors => panic!("{:?}", errors),
+        };
+        assert_eq!(unknown("helper;"), "helper");
+        assert_eq!(unknown("empty;"), "empty");
+        assert!(check(&format!("{}\nsize new;\ndec helper : list char;\n--- helper <= \"own\";\nhelper;", module)).is_ok());
+    }
+
+    #[test]
+    fn should_require_declarations_for_equations() {
+        let errors = check("--- f x <= x;").unwrap_err();
+        assert!(matches!(&errors[..], [TypeError::MissingDec(name, _)] if name == "f"));
+    }
+
+    #[test]
+    fn should_report_names_declared_twice_in_a_module() {
+        let errors = check("dec x : num;\ndata t == a ++ x;\ntype t == num;\ndec x : bool;").unwrap_err();
+        let found: Vec<_> = errors.iter().map(|e| (e.to_string(), e.pos().line, e.first_declared().map(|p| p.line))).collect();
+        assert_eq!(found, [
+            ("`x` is already declared in this module".to_owned(), 2, Some(1)),
+            ("type `t` is already defined in this module".to_owned(), 3, Some(2)),
+            ("`x` is already declared in this module".to_owned(), 4, Some(1)),
+        ]);
+        assert_eq!(errors[1].code(), "E0311");
+
+        // An abstype's data, another module and a later program can all declare it again
+        assert!(check("abstype t;\ndata t == a;\nmodule m;\ndec x : t;\nend;\nmodule n;\ndec x : t;\nend;").is_ok());
+        let mut checker = Checker::new();
+        checker.check(parser::parse_program("dec x : num;").unwrap()).unwrap();
+        assert!(checker.check(parser::parse_program("dec x : bool;").unwrap()).is_ok());
+    }
+}
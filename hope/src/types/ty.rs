@@ -0,0 +1,148 @@
+use core::fmt;
+use crate::alloc_prelude::*;
+use crate::pp::{self, Doc};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    // Unification variable, bound in the checker's substitution
+    Var(u32),
+    // Quantified variable of a Scheme
+    Gen(u32),
+    Con(String, Vec<Type>),
+}
+
+impl Type {
+    pub fn con(name: &str) -> Type {
+        Type::Con(name.to_owned(), Vec::new())
+    }
+
+    pub fn num() -> Type {
+        Type::con("num")
+    }
+
+    pub fn bool() -> Type {
+        Type::con("bool")
+    }
+
+    pub fn char() -> Type {
+        Type::con("char")
+    }
+
+    pub fn list(elem: Type) -> Type {
+        Type::Con("list".to_owned(), vec![elem])
+    }
+
+    pub fn arrow(from: Type, to: Type) -> Type {
+        Type::Con("->".to_owned(), vec![from, to])
+    }
+
+    pub fn pair(l: Type, r: Type) -> Type {
+        Type::Con("#".to_owned(), vec![l, r])
+    }
+
+    // Tuples are right-nested pairs, `(a, b, c)` is `a # (b # c)`
+    pub fn tuple(mut items: Vec<Type>) -> Type {
+        let last = items.pop().expect("tuples have at least two items");
+        items.into_iter().rev().fold(last, |r, l| Type::pair(l, r))
+    }
+
+    pub fn to_doc(&self, names: &[String]) -> Doc {
+        self.doc_at(0, names)
+    }
+
+    fn doc_at(&self, prec: u32, names: &[String]) -> Doc {
+        match self {
+            Type::Var(v) => pp::text(format!("?{}", v)),
+            Type::Gen(i) => pp::text(names.get(*i as usize).cloned().unwrap_or_else(|| var_name(*i))),
+            Type::Con(name, args) if args.len() == 2 && is_symbolic(name) => {
+                let own = infix_prec(name);
+                let doc = args[0].doc_at(own + 1, names)
+                    .append(pp::text(format!(" {}", name)))
+                    .append(pp::line())
+                    .append(args[1].doc_at(own, names))
+                    .group();
+                parenthesise(doc, own < prec)
+            }
+            Type::Con(name, args) if args.is_empty() => pp::text(name),
+            Type::Con(name, args) => {
+                let doc = pp::text(name).append(pp::concat(args.iter().map(|arg| {
+                    pp::line().append(arg.doc_at(APPLY_PREC + 1, names))
+                })).nest(2)).group();
+                parenthesise(doc, APPLY_PREC < prec)
+            }
+        }
+    }
+}
+
+const APPLY_PREC: u32 = 10;
+
+fn infix_prec(name: &str) -> u32 {
+    match name {
+        "->" => 2,
+        "#" => 4,
+        _ => 5,
+    }
+}
+
+fn is_symbolic(name: &str) -> bool {
+    !name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+}
+
+fn parenthesise(doc: Doc, parens: bool) -> Doc {
+    if parens {
+        pp::text("(").append(doc).append(pp::text(")"))
+    } else {
+        doc
+    }
+}
+
+// alpha, beta, ... for generated type variables, then alpha1, beta1, ...
+pub fn var_name(i: u32) -> String {
+    const NAMES: [&str; 6] = ["alpha", "beta", "gamma", "delta", "epsilon", "zeta"];
+    let base = NAMES[i as usize % NAMES.len()];
+    match i as usize / NAMES.len() {
+        0 => base.to_owned(),
+        n => format!("{}{}", base, n),
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_doc(&[]).render(f.width().unwrap_or(80)))
+    }
+}
+
+// A type with `params.len()` quantified variables, `Gen(i)` being named `params[i]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheme {
+    pub params: Vec<String>,
+    pub ty: Type,
+}
+
+impl Scheme {
+    pub fn mono(ty: Type) -> Scheme {
+        Scheme { params: Vec::new(), ty }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ty.to_doc(&self.params).render(f.width().unwrap_or(80)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_print_with_minimal_parentheses() {
+        let a = Type::Gen(0);
+        let ty = Type::arrow(
+            Type::arrow(a.clone(), Type::Gen(1)),
+            Type::arrow(Type::list(Type::pair(a, Type::num())), Type::list(Type::list(Type::Gen(1)))),
+        );
+        let scheme = Scheme { params: vec!["alpha".to_owned(), "beta".to_owned()], ty };
+        assert_eq!(scheme.to_string(), "(alpha -> beta) -> list (alpha # num) -> list (list beta)");
+    }
+}
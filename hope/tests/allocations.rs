@@ -0,0 +1,56 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use logos::Logos;
+use hope::parser::Parser;
+use hope::syntax::token::Token;
+
+// Counts the allocations each thread makes, so a test can see how many parsing takes.
+// Not something a unit test can do, as the library has no unsafe code
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+#[test]
+fn should_allocate_little_more_than_the_syntax_tree() {
+    let source = include_str!("../../lib/Standard.hop");
+    let tokens = Token::lexer(source).count();
+    let mut parser = Parser::new(source).unwrap();
+    let (program, parsing) = allocations(|| parser.parse_program().unwrap());
+    assert!(!program.decls.is_empty());
+    assert!(parsing <= tokens * 3 / 2, "{} allocations for {} tokens", parsing, tokens);
+
+    // Each `1 :: nil` is the names of `::` and `nil` and the boxes of the operands.
+    // Operands waiting for their right one go on one stack for the whole parse, not
+    // on one of their own for each `::`
+    let list = |n: usize| format!("[{}]", vec!["1 :: nil"; n].join(", "));
+    let parse = |source: String| allocations(|| Parser::new(&source).unwrap().parse_expr().unwrap()).1;
+    let more = parse(list(2000)) - parse(list(1000));
+    assert!(more <= 4 * 1000 + 16, "{} more allocations for 1000 more lists", more);
+}